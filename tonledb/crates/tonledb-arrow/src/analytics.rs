@@ -0,0 +1,224 @@
+//! Analytic SQL through DataFusion.
+//!
+//! `execute_analytic_sql` keeps simple queries (a `SELECT` from one table without
+//! joins, grouping, aggregates or `DISTINCT`) on the native engine, and plans
+//! anything else with DataFusion. The tables and collections such a query names are
+//! registered with it as streaming providers, read from storage a record batch at a
+//! time as the query pulls them: tables from their rows, or from their `olap`
+//! column segments when they have them, reading only the columns the query names;
+//! collections with a schema inferred over all their documents (see `collection`).
+//! Results come back as JSON rows either way.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use arrow::datatypes::SchemaRef;
+use arrow::record_batch::RecordBatch;
+use datafusion::datasource::streaming::StreamingTable;
+use datafusion::error::DataFusionError;
+use datafusion::execution::{SendableRecordBatchStream, TaskContext};
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::streaming::PartitionStream;
+use datafusion::prelude::SessionContext;
+use serde_json::Value as Json;
+use sqlparser::ast::{Expr, GroupByExpr, SelectItem, SetExpr, Statement};
+use sqlparser::keywords::Keyword;
+use sqlparser::tokenizer::{Token, Tokenizer};
+use sqlparser::{dialect::GenericDialect, parser::Parser};
+use tonledb_core::columnar;
+use tonledb_core::{Column, Db, DbError, Result, Space, Storage, TableSchema};
+use tonledb_nosql_doc::Paging;
+use crate::collection::{collection_schema, docs_to_record_batch};
+
+/// Rows per record batch handed to DataFusion
+pub const ANALYTIC_BATCH_ROWS: usize = 8192;
+
+fn df_err(e: datafusion::error::DataFusionError) -> DbError {
+    DbError::Invalid(format!("analytic query: {}", e))
+}
+
+/// Run `sql` against `db`: on the native engine if it is simple, on DataFusion
+/// otherwise (see the module docs). Returns the rows as a JSON array.
+pub async fn execute_analytic_sql(db: &Db, sql: &str) -> Result<Json> {
+    if is_simple(sql) {
+        return tonledb_sql::execute_sql(db, sql);
+    }
+    let ctx = SessionContext::new();
+    let state = ctx.state();
+    let statement = state.sql_to_statement(sql, "generic").map_err(df_err)?;
+    for reference in state.resolve_table_references(&statement).map_err(df_err)? {
+        let name = reference.table();
+        if let Some(provider) = provider(db, name, sql)? {
+            ctx.register_table(name, Arc::new(provider)).map_err(df_err)?;
+        }
+    }
+    let batches = ctx.sql(sql).await.map_err(df_err)?.collect().await.map_err(df_err)?;
+    batches_to_json(&batches)
+}
+
+/// Whether the native engine runs `sql` (see the module docs). Anything that fails
+/// to parse is left to DataFusion, which reports the error.
+pub fn is_simple(sql: &str) -> bool {
+    let Ok(statements) = Parser::parse_sql(&GenericDialect, sql) else { return false };
+    let [Statement::Query(query)] = statements.as_slice() else { return false };
+    let SetExpr::Select(select) = &*query.body else { return false };
+    let [from] = select.from.as_slice() else { return false };
+    let grouped = !matches!(&select.group_by, GroupByExpr::Expressions(exprs) if exprs.is_empty());
+    let aggregates = select.projection.iter().any(|item| match item {
+        SelectItem::UnnamedExpr(e) | SelectItem::ExprWithAlias { expr: e, .. } => has_function(e),
+        _ => false,
+    });
+    query.with.is_none() && from.joins.is_empty() && !grouped && select.having.is_none() && select.distinct.is_none() && !aggregates
+}
+
+/// Whether a projected expression calls a function, aggregate or not: the native
+/// engine evaluates neither
+fn has_function(e: &Expr) -> bool {
+    match e {
+        Expr::Function(_) => true,
+        Expr::BinaryOp { left, right, .. } => has_function(left) || has_function(right),
+        Expr::UnaryOp { expr, .. } | Expr::Nested(expr) | Expr::Cast { expr, .. } => has_function(expr),
+        _ => false,
+    }
+}
+
+/// A provider for the table or collection `name`, or `None` if there is neither.
+/// Tables with complete column segments (see `tonledb_core::columnar`) are read
+/// from them, and only for the columns `sql` mentions.
+fn provider(db: &Db, name: &str, sql: &str) -> Result<Option<StreamingTable>> {
+    let table = db.catalog.read().tables.get(name).cloned();
+    let (schema, source) = match table {
+        Some(table) if columnar::is_ready(&*db.storage, name)? => {
+            let table = TableSchema { columns: referenced_columns(sql, &table), ..table };
+            (Arc::new(crate::table_schema(&table)), Source::Segments(table))
+        }
+        Some(table) => (Arc::new(crate::table_schema(&table)), Source::Rows(table)),
+        None => {
+            let registered = db.storage.get(&Space("catalog".into()), format!("col/{}", name).as_bytes())?.is_some();
+            let first = tonledb_nosql_doc::list_page(&*db.storage, name, true, &Paging { limit: Some(1), ..Default::default() })?;
+            if !registered && first.docs.is_empty() {
+                return Ok(None);
+            }
+            (Arc::new(collection_schema(&*db.storage, name)?), Source::Collection(name.to_string()))
+        }
+    };
+    let partition = Partition { schema: schema.clone(), storage: db.storage.clone(), source };
+    StreamingTable::try_new(schema, vec![Arc::new(partition)]).map(Some).map_err(df_err)
+}
+
+/// Where a `Partition` reads its rows
+enum Source {
+    /// The stored rows of a table
+    Rows(TableSchema),
+    /// The column segments of a table, for the columns it lists
+    Segments(TableSchema),
+    Collection(String),
+}
+
+/// A table or collection as DataFusion scans it: record batches of
+/// `ANALYTIC_BATCH_ROWS` rows, each read from storage when the query pulls it, so
+/// no scan holds more than a batch of its rows at once
+struct Partition {
+    schema: SchemaRef,
+    storage: Arc<dyn Storage>,
+    source: Source,
+}
+
+type Batches = Box<dyn Iterator<Item = Result<RecordBatch>> + Send>;
+
+impl Partition {
+    fn batches(&self) -> Result<Batches> {
+        match &self.source {
+            Source::Rows(table) => {
+                let name = table.name.clone();
+                let rows = self.storage.snapshot()?.scan_prefix(&Space("data".into()), format!("tbl/{}/", name).as_bytes())?
+                    .map(move |(_, v)| serde_json::from_slice(&v).map_err(|e| DbError::Corruption(format!("row of table {}: {}", name, e))));
+                Ok(row_batches(table.clone(), rows))
+            }
+            Source::Segments(table) => {
+                let names: Vec<&str> = table.columns.iter().map(|c| c.name.as_str()).collect();
+                Ok(row_batches(table.clone(), columnar::column_rows(&*self.storage, &table.name, &names)?))
+            }
+            Source::Collection(name) => {
+                let (storage, name, schema) = (self.storage.clone(), name.clone(), self.schema.clone());
+                let mut paging = Some(Paging { limit: Some(ANALYTIC_BATCH_ROWS), ..Default::default() });
+                Ok(Box::new(std::iter::from_fn(move || {
+                    let current = paging.take()?;
+                    Some(tonledb_nosql_doc::list_page(&*storage, &name, true, &current).and_then(|page| {
+                        paging = page.next.map(|next| Paging { after: Some(next), ..current });
+                        docs_to_record_batch(&schema, &page.docs)
+                    }))
+                })))
+            }
+        }
+    }
+}
+
+impl PartitionStream for Partition {
+    fn schema(&self) -> &SchemaRef {
+        &self.schema
+    }
+
+    fn execute(&self, _ctx: Arc<TaskContext>) -> SendableRecordBatchStream {
+        let batches = self.batches().unwrap_or_else(|e| Box::new(std::iter::once(Err(e))));
+        let batches = batches.map(|b| b.map_err(|e| DataFusionError::External(Box::new(e))));
+        Box::pin(RecordBatchStreamAdapter::new(self.schema.clone(), futures::stream::iter(batches)))
+    }
+}
+
+/// `rows` of `table`, `ANALYTIC_BATCH_ROWS` to a record batch
+fn row_batches(table: TableSchema, mut rows: impl Iterator<Item = Result<Json>> + Send + 'static) -> Batches {
+    Box::new(std::iter::from_fn(move || {
+        let mut chunk = Vec::with_capacity(ANALYTIC_BATCH_ROWS);
+        for row in rows.by_ref() {
+            match row {
+                Ok(row) => chunk.push(row),
+                Err(e) => return Some(Err(e)),
+            }
+            if chunk.len() == ANALYTIC_BATCH_ROWS {
+                break;
+            }
+        }
+        (!chunk.is_empty()).then(|| crate::rows_to_record_batch(&table, &chunk))
+    }))
+}
+
+/// Columns of `table` that `sql` may use: those it names anywhere, or all of them
+/// if it selects `*`. A query naming none of them (`COUNT(*)`) still gets the
+/// first, as record batches need a column to count rows by.
+fn referenced_columns(sql: &str, table: &TableSchema) -> Vec<Column> {
+    let Ok(tokens) = Tokenizer::new(&GenericDialect, sql).tokenize() else { return table.columns.clone() };
+    let tokens: Vec<Token> = tokens.into_iter().filter(|t| !matches!(t, Token::Whitespace(_))).collect();
+    let wildcard = tokens.windows(2).any(|pair| match pair {
+        [Token::Word(w), Token::Mul] => w.keyword == Keyword::SELECT,
+        [Token::Comma | Token::Period, Token::Mul] => true,
+        _ => false,
+    });
+    if wildcard {
+        return table.columns.clone();
+    }
+    let words: HashSet<String> = tokens
+        .iter()
+        .filter_map(|t| match t {
+            Token::Word(w) => Some(w.value.to_lowercase()),
+            _ => None,
+        })
+        .collect();
+    let columns: Vec<Column> = table.columns.iter().filter(|c| words.contains(&c.name.to_lowercase())).cloned().collect();
+    if columns.is_empty() {
+        return table.columns.iter().take(1).cloned().collect();
+    }
+    columns
+}
+
+/// Record batches as a JSON array of row objects; nulls are left out of the rows
+fn batches_to_json(batches: &[RecordBatch]) -> Result<Json> {
+    let json_err = |e: arrow::error::ArrowError| DbError::Storage(format!("Failed to encode rows: {}", e));
+    let mut writer = arrow::json::ArrayWriter::new(Vec::new());
+    writer.write_batches(&batches.iter().collect::<Vec<_>>()).map_err(json_err)?;
+    writer.finish().map_err(json_err)?;
+    let bytes = writer.into_inner();
+    if bytes.is_empty() {
+        return Ok(Json::Array(Vec::new()));
+    }
+    serde_json::from_slice(&bytes).map_err(|e| DbError::Storage(format!("Failed to encode rows: {}", e)))
+}
@@ -0,0 +1,198 @@
+//! Document collections as Parquet, keeping their nesting.
+//!
+//! `infer_schema` walks documents and takes the union of their fields, widening
+//! types where documents disagree: integers mixed with floats become `Float64`,
+//! objects become struct columns whose fields are inferred the same way, arrays
+//! become lists of their widened element type, and any other mix (or an object
+//! with no fields) becomes `Utf8` holding JSON text. Every field is nullable.
+//! `export_collection_parquet` infers the schema over the whole collection in a
+//! first pass and streams the documents out in a second, `EXPORT_BATCH_DOCS` at a
+//! time, in row groups of `stream::DEFAULT_ROW_GROUP_ROWS`.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+use arrow::array::{ArrayRef, BooleanArray, Float64Array, Int64Array, ListArray, StringArray, StructArray};
+use arrow::buffer::{NullBuffer, OffsetBuffer};
+use arrow::datatypes::{DataType, Field, Fields, Schema};
+use arrow::record_batch::RecordBatch;
+use serde_json::Value as Json;
+use tonledb_core::{DbError, Result, Storage};
+use tonledb_nosql_doc::Paging;
+use crate::stream;
+
+/// Documents converted and written at a time by `export_collection_parquet`
+pub const EXPORT_BATCH_DOCS: usize = 8192;
+
+/// Type inferred for a field so far
+#[derive(Debug, Clone, PartialEq)]
+enum Kind {
+    /// Only nulls seen
+    Null,
+    Bool,
+    Int,
+    Float,
+    Str,
+    List(Box<Kind>),
+    Struct(BTreeMap<String, Kind>),
+    /// Kinds that don't widen into one another: JSON text
+    Mixed,
+}
+
+impl Kind {
+    fn of(v: &Json) -> Kind {
+        match v {
+            Json::Null => Kind::Null,
+            Json::Bool(_) => Kind::Bool,
+            Json::Number(n) if n.is_i64() || n.is_u64() => Kind::Int,
+            Json::Number(_) => Kind::Float,
+            Json::String(_) => Kind::Str,
+            Json::Array(items) => Kind::List(Box::new(items.iter().fold(Kind::Null, |k, v| k.widen(Kind::of(v))))),
+            Json::Object(fields) => Kind::Struct(fields.iter().map(|(k, v)| (k.clone(), Kind::of(v))).collect()),
+        }
+    }
+
+    fn widen(self, other: Kind) -> Kind {
+        match (self, other) {
+            (Kind::Null, k) | (k, Kind::Null) => k,
+            (Kind::Int, Kind::Float) | (Kind::Float, Kind::Int) => Kind::Float,
+            (Kind::List(a), Kind::List(b)) => Kind::List(Box::new(a.widen(*b))),
+            (Kind::Struct(mut a), Kind::Struct(b)) => {
+                for (name, kind) in b {
+                    let merged = match a.remove(&name) {
+                        Some(existing) => existing.widen(kind),
+                        None => kind,
+                    };
+                    a.insert(name, merged);
+                }
+                Kind::Struct(a)
+            }
+            (a, b) if a == b => a,
+            _ => Kind::Mixed,
+        }
+    }
+
+    fn arrow_type(&self) -> DataType {
+        match self {
+            Kind::Bool => DataType::Boolean,
+            Kind::Int => DataType::Int64,
+            Kind::Float => DataType::Float64,
+            Kind::Null | Kind::Str | Kind::Mixed => DataType::Utf8,
+            Kind::List(item) => DataType::List(Arc::new(Field::new("item", item.arrow_type(), true))),
+            Kind::Struct(fields) if fields.is_empty() => DataType::Utf8,
+            Kind::Struct(fields) => DataType::Struct(struct_fields(fields)),
+        }
+    }
+}
+
+fn struct_fields(fields: &BTreeMap<String, Kind>) -> Fields {
+    fields.iter().map(|(name, kind)| Field::new(name.as_str(), kind.arrow_type(), true)).collect()
+}
+
+/// Widen `fields` with the fields of `doc`; anything but an object is skipped
+fn merge(fields: &mut BTreeMap<String, Kind>, doc: &Json) {
+    let Json::Object(doc) = doc else { return };
+    for (name, v) in doc {
+        let kind = match fields.remove(name) {
+            Some(existing) => existing.widen(Kind::of(v)),
+            None => Kind::of(v),
+        };
+        fields.insert(name.clone(), kind);
+    }
+}
+
+/// Schema for `docs` (see the module docs); columns sorted by name
+pub fn infer_schema<'a>(docs: impl IntoIterator<Item = &'a Json>) -> Schema {
+    let mut fields = BTreeMap::new();
+    for doc in docs {
+        merge(&mut fields, doc);
+    }
+    Schema::new(struct_fields(&fields))
+}
+
+/// `docs` as a record batch of `schema`, as inferred by `infer_schema`. Values that
+/// don't fit their column's type become null; `Utf8` columns take strings as they are
+/// and any other value as JSON text.
+pub fn docs_to_record_batch(schema: &Schema, docs: &[Json]) -> Result<RecordBatch> {
+    let columns = schema
+        .fields()
+        .iter()
+        .map(|f| build(f.data_type(), &docs.iter().map(|d| d.get(f.name()).filter(|v| !v.is_null())).collect::<Vec<_>>()))
+        .collect::<Result<Vec<_>>>()?;
+    RecordBatch::try_new(Arc::new(schema.clone()), columns)
+        .map_err(|e| DbError::Storage(format!("Failed to build record batch: {}", e)))
+}
+
+fn build(data_type: &DataType, cells: &[Option<&Json>]) -> Result<ArrayRef> {
+    let arrow_err = |e: arrow::error::ArrowError| DbError::Storage(format!("Failed to build array: {}", e));
+    Ok(match data_type {
+        DataType::Boolean => Arc::new(cells.iter().map(|v| v.and_then(|v| v.as_bool())).collect::<BooleanArray>()),
+        DataType::Int64 => Arc::new(cells.iter().map(|v| v.and_then(|v| v.as_i64())).collect::<Int64Array>()),
+        DataType::Float64 => Arc::new(cells.iter().map(|v| v.and_then(|v| v.as_f64())).collect::<Float64Array>()),
+        DataType::Utf8 => Arc::new(
+            cells
+                .iter()
+                .map(|v| v.map(|v| v.as_str().map(str::to_string).unwrap_or_else(|| v.to_string())))
+                .collect::<StringArray>(),
+        ),
+        DataType::List(item) => {
+            let lists: Vec<Option<&Vec<Json>>> = cells.iter().map(|v| v.and_then(|v| v.as_array())).collect();
+            let values: Vec<Option<&Json>> = lists.iter().flatten().flat_map(|l| l.iter().map(|v| Some(v).filter(|v| !v.is_null()))).collect();
+            let offsets = OffsetBuffer::from_lengths(lists.iter().map(|l| l.map_or(0, |l| l.len())));
+            let nulls = NullBuffer::from(lists.iter().map(Option::is_some).collect::<Vec<_>>());
+            Arc::new(ListArray::try_new(item.clone(), offsets, build(item.data_type(), &values)?, Some(nulls)).map_err(arrow_err)?)
+        }
+        DataType::Struct(fields) => {
+            let objects: Vec<Option<&serde_json::Map<String, Json>>> = cells.iter().map(|v| v.and_then(|v| v.as_object())).collect();
+            let children = fields
+                .iter()
+                .map(|f| build(f.data_type(), &objects.iter().map(|o| o.and_then(|o| o.get(f.name())).filter(|v| !v.is_null())).collect::<Vec<_>>()))
+                .collect::<Result<Vec<_>>>()?;
+            let nulls = NullBuffer::from(objects.iter().map(Option::is_some).collect::<Vec<_>>());
+            Arc::new(StructArray::try_new(fields.clone(), children, Some(nulls)).map_err(arrow_err)?)
+        }
+        other => return Err(DbError::Invalid(format!("unsupported column type {}", other))),
+    })
+}
+
+/// Write the live documents of `collection` to a Parquet file at `path` with a
+/// schema inferred from all of them; returns how many were written. Documents
+/// written between the two passes may lose values that don't fit the schema.
+pub fn export_collection_parquet<S: Storage + ?Sized>(storage: &S, collection: &str, path: &Path) -> Result<u64> {
+    let schema = collection_schema(storage, collection)?;
+    if schema.fields().is_empty() {
+        return Err(DbError::Invalid(format!("collection {} has no fields to export", collection)));
+    }
+    let mut writer = stream::create_parquet_file(path, Arc::new(schema.clone()), stream::DEFAULT_ROW_GROUP_ROWS)?;
+    for_each_page(storage, collection, |docs| writer.write(&docs_to_record_batch(&schema, docs)?))?;
+    stream::finish_parquet_file(writer)
+}
+
+/// `infer_schema` over the live documents of `collection`, read a page at a time
+pub(crate) fn collection_schema<S: Storage + ?Sized>(storage: &S, collection: &str) -> Result<Schema> {
+    let mut fields = BTreeMap::new();
+    for_each_page(storage, collection, |docs| {
+        docs.iter().for_each(|doc| merge(&mut fields, doc));
+        Ok(())
+    })?;
+    Ok(Schema::new(struct_fields(&fields)))
+}
+
+/// Call `f` with each page of up to `EXPORT_BATCH_DOCS` live documents of `collection`
+fn for_each_page<S, F>(storage: &S, collection: &str, mut f: F) -> Result<()>
+where
+    S: Storage + ?Sized,
+    F: FnMut(&[Json]) -> Result<()>,
+{
+    let mut paging = Paging { limit: Some(EXPORT_BATCH_DOCS), ..Default::default() };
+    loop {
+        let page = tonledb_nosql_doc::list_page(storage, collection, true, &paging)?;
+        if !page.docs.is_empty() {
+            f(&page.docs)?;
+        }
+        match page.next {
+            Some(next) => paging.after = Some(next),
+            None => return Ok(()),
+        }
+    }
+}
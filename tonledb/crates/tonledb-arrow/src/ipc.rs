@@ -0,0 +1,50 @@
+//! Query results as Arrow IPC streams (`application/vnd.apache.arrow.stream`), so
+//! clients reading large result sets skip JSON encoding and decoding.
+
+use std::io::Write;
+use arrow::ipc::writer::StreamWriter;
+use serde_json::Value as Json;
+use tonledb_core::{Column, DbError, Result, TableSchema};
+use crate::collection::{docs_to_record_batch, infer_schema};
+use crate::{rows_to_record_batch, table_schema};
+
+/// Media type of an Arrow IPC stream
+pub const ARROW_STREAM_MEDIA_TYPE: &str = "application/vnd.apache.arrow.stream";
+/// Rows per record batch in the stream
+pub const IPC_BATCH_ROWS: usize = 8192;
+
+/// JSON result rows (objects) as an Arrow IPC stream. Columns are inferred from the
+/// rows as `collection::infer_schema` does, so nested objects become struct columns;
+/// rows that aren't objects are skipped.
+pub fn rows_to_ipc_stream(rows: &[Json]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    write_ipc_stream(None, rows.to_vec(), &mut out)?;
+    Ok(out)
+}
+
+/// Write result rows (objects) to `out` as an Arrow IPC stream, `IPC_BATCH_ROWS` rows
+/// to a record batch, each batch written as soon as it is built. Given `columns` (as
+/// `tonledb_sql::result_columns` works them out) the stream has those columns, all
+/// nullable, and values that don't fit a column's type become null; otherwise columns
+/// are inferred from the rows as `rows_to_ipc_stream` does.
+pub fn write_ipc_stream<W: Write>(columns: Option<&[Column]>, mut rows: Vec<Json>, out: W) -> Result<()> {
+    rows.retain(Json::is_object);
+    let Some(columns) = columns else {
+        let schema = infer_schema(&rows);
+        let mut writer = StreamWriter::try_new(out, &schema).map_err(ipc_err)?;
+        for chunk in rows.chunks(IPC_BATCH_ROWS) {
+            writer.write(&docs_to_record_batch(&schema, chunk)?).map_err(ipc_err)?;
+        }
+        return writer.finish().map_err(ipc_err);
+    };
+    let table = TableSchema { name: "result".into(), columns: columns.to_vec(), pk: None, constraints: vec![] };
+    let mut writer = StreamWriter::try_new(out, &table_schema(&table)).map_err(ipc_err)?;
+    for chunk in rows.chunks(IPC_BATCH_ROWS) {
+        writer.write(&rows_to_record_batch(&table, chunk)?).map_err(ipc_err)?;
+    }
+    writer.finish().map_err(ipc_err)
+}
+
+fn ipc_err(e: arrow::error::ArrowError) -> DbError {
+    DbError::Storage(format!("Arrow IPC: {}", e))
+}
@@ -1,13 +1,23 @@
 //! Arrow and Parquet support for TonleDB
 
-use arrow::array::{ArrayRef, Int64Array, Float64Array, StringArray, BooleanArray};
+use arrow::array::{Array, ArrayRef, AsArray, Int64Array, Float64Array, StringArray, BooleanArray};
+use arrow::datatypes::{Float64Type, Int64Type};
 use arrow::datatypes::{DataType, Field, Schema};
 use arrow::record_batch::RecordBatch;
-use parquet::arrow::ArrowWriter;
-use parquet::file::properties::WriterProperties;
-use parquet::file::writer::InMemoryWriteableCursor;
+use std::collections::HashMap;
 use std::sync::Arc;
-use tonledb_core::{Db, DbError, Result, Space, Storage, Value};
+use serde_json::Value as Json;
+use tonledb_core::numbers::{self, NumberMode};
+use tonledb_core::{Column, ColumnConstraint, Db, DbError, Result, Space, Storage, TableSchema, Value};
+use tonledb_core::DataType as ColumnType;
+use tonledb_core::schema_inference;
+
+#[cfg(feature = "datafusion")]
+pub mod analytics;
+pub mod collection;
+pub mod ipc;
+pub mod stream;
+pub mod tiering;
 
 /// Convert TonleDB values to Arrow arrays
 pub fn values_to_arrow_arrays(values: &[Value]) -> Result<Vec<ArrayRef>> {
@@ -77,35 +87,17 @@ pub fn values_to_arrow_arrays(values: &[Value]) -> Result<Vec<ArrayRef>> {
     Ok(vec![array])
 }
 
-/// Convert a record batch to Parquet format and write to storage
+/// Convert a record batch to Parquet format and write to storage. The value is the
+/// whole file, so this suits small batches; see `stream` for large ones.
 pub fn write_record_batch_to_parquet<S: Storage + ?Sized>(
     storage: &S,
     space: &Space,
     key: Vec<u8>,
     batch: &RecordBatch,
 ) -> Result<()> {
-    // Create an in-memory cursor for writing the Parquet file
-    let cursor = InMemoryWriteableCursor::default();
-    
-    // Create a Parquet writer
-    let schema = batch.schema();
-    let props = WriterProperties::builder().build();
-    let mut writer = ArrowWriter::try_new(cursor.clone(), schema, Some(props))
-        .map_err(|e| DbError::Storage(format!("Failed to create Parquet writer: {}", e)))?;
-    
-    // Write the record batch
-    writer.write(batch)
-        .map_err(|e| DbError::Storage(format!("Failed to write record batch: {}", e)))?;
-    
-    // Close the writer to finalize the Parquet file
-    writer.close()
-        .map_err(|e| DbError::Storage(format!("Failed to close Parquet writer: {}", e)))?;
-    
-    // Get the Parquet data
-    let parquet_data = cursor.data();
-    
-    // Store the Parquet data in the storage
-    storage.put(space, key, parquet_data)
+    let mut writer = stream::ParquetStreamWriter::try_new(Vec::new(), batch.schema(), stream::DEFAULT_ROW_GROUP_ROWS)?;
+    writer.write(batch)?;
+    storage.put(space, key, writer.finish()?)
 }
 
 /// Read a Parquet file from storage and convert to a record batch
@@ -120,11 +112,8 @@ pub fn read_parquet_from_storage<S: Storage + ?Sized>(
         None => return Ok(None),
     };
     
-    // Create a cursor from the Parquet data
-    let cursor = std::io::Cursor::new(parquet_data);
-    
     // Create a Parquet reader
-    let mut reader = parquet::arrow::ParquetRecordBatchReaderBuilder::try_new(cursor)
+    let mut reader = parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder::try_new(bytes::Bytes::from(parquet_data))
         .map_err(|e| DbError::Storage(format!("Failed to create Parquet reader: {}", e)))?
         .build()
         .map_err(|e| DbError::Storage(format!("Failed to build Parquet reader: {}", e)))?;
@@ -137,6 +126,231 @@ pub fn read_parquet_from_storage<S: Storage + ?Sized>(
     Ok(Some(batch))
 }
 
+/// Field metadata naming the catalog type of a `Utf8` field of `table_schema` that
+/// holds JSON or decimal text, so `table_from_schema` can tell it from plain text
+const COLUMN_TYPE_METADATA: &str = "tonledb.type";
+
+/// Arrow type used for a catalog column type; `Json` columns carry serialized JSON text
+pub fn arrow_type(column_type: &ColumnType) -> DataType {
+    match column_type {
+        ColumnType::Integer => DataType::Int64,
+        ColumnType::Float => DataType::Float64,
+        ColumnType::Boolean => DataType::Boolean,
+        // Decimals travel as their exact text
+        ColumnType::Text | ColumnType::Json | ColumnType::Decimal => DataType::Utf8,
+    }
+}
+
+/// Arrow schema of a table: one field per catalog column, typed by `arrow_type` and
+/// nullable unless the column is `NOT NULL` or the primary key. The table's name and
+/// primary key are kept in the schema metadata as `tonledb.table` and
+/// `tonledb.primary_key`, and `Json` and `Decimal` fields are marked with a
+/// `tonledb.type` of `json` or `decimal`.
+pub fn table_schema(table: &TableSchema) -> Schema {
+    let fields: Vec<Field> = table.columns.iter().map(|c| {
+        let field = Field::new(c.name.as_str(), arrow_type(&c.data_type), nullable(c));
+        let marked = match c.data_type {
+            ColumnType::Json => "json",
+            ColumnType::Decimal => "decimal",
+            _ => return field,
+        };
+        field.with_metadata(HashMap::from([(COLUMN_TYPE_METADATA.to_string(), marked.to_string())]))
+    }).collect();
+    let mut metadata = HashMap::from([("tonledb.table".to_string(), table.name.clone())]);
+    if let Some(pk) = &table.pk {
+        metadata.insert("tonledb.primary_key".to_string(), pk.clone());
+    }
+    Schema::new_with_metadata(fields, metadata)
+}
+
+/// Catalog column type for an Arrow type, the inverse of `arrow_type` where there is
+/// one: integers of any width are `Integer`, floats `Float`, decimals `Decimal`,
+/// strings, dates and timestamps `Text`, and structs, lists and maps `Json`. `None`
+/// for types with no catalog counterpart, like binary data.
+pub fn column_type(data_type: &DataType) -> Option<ColumnType> {
+    Some(match data_type {
+        DataType::Int8 | DataType::Int16 | DataType::Int32 | DataType::Int64 => ColumnType::Integer,
+        DataType::UInt8 | DataType::UInt16 | DataType::UInt32 | DataType::UInt64 => ColumnType::Integer,
+        DataType::Float16 | DataType::Float32 | DataType::Float64 => ColumnType::Float,
+        DataType::Decimal128(..) | DataType::Decimal256(..) => ColumnType::Decimal,
+        DataType::Boolean => ColumnType::Boolean,
+        DataType::Utf8 | DataType::LargeUtf8 => ColumnType::Text,
+        DataType::Date32 | DataType::Date64 | DataType::Timestamp(..) => ColumnType::Text,
+        DataType::Struct(_) | DataType::List(_) | DataType::LargeList(_) | DataType::Map(..) => ColumnType::Json,
+        _ => return None,
+    })
+}
+
+/// Catalog table `name` for an Arrow schema, typed by `column_type`, except that the
+/// text fields `table_schema` marks as `json` or `decimal` are `Json` and `Decimal`
+/// again. Fields that can't be null become `NOT NULL` columns; the primary key is
+/// taken from the `tonledb.primary_key` metadata `table_schema` writes, if it names
+/// a field. Fails with `DbError::Invalid` on a field `column_type` can't map.
+pub fn table_from_schema(name: &str, schema: &Schema) -> Result<TableSchema> {
+    let pk = schema.metadata().get("tonledb.primary_key").filter(|pk| schema.field_with_name(pk).is_ok()).cloned();
+    let columns = schema
+        .fields()
+        .iter()
+        .map(|f| {
+            let marked = f.metadata().get(COLUMN_TYPE_METADATA).map(String::as_str);
+            let data_type = match (column_type(f.data_type()), marked) {
+                (Some(ColumnType::Text), Some("json")) => ColumnType::Json,
+                (Some(ColumnType::Text), Some("decimal")) => ColumnType::Decimal,
+                (Some(t), _) => t,
+                (None, _) => return Err(DbError::Invalid(format!("column {} has type {}, which tables can't hold", f.name(), f.data_type()))),
+            };
+            let constraints = if pk.as_ref() == Some(f.name()) {
+                vec![ColumnConstraint::PrimaryKey]
+            } else if !f.is_nullable() {
+                vec![ColumnConstraint::NotNull]
+            } else {
+                vec![]
+            };
+            Ok(Column { name: f.name().clone(), data_type, constraints })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(TableSchema { name: name.to_string(), columns, pk, constraints: vec![] })
+}
+
+fn nullable(column: &Column) -> bool {
+    !column.constraints.iter().any(|c| matches!(c, ColumnConstraint::NotNull | ColumnConstraint::PrimaryKey))
+}
+
+/// Stored rows of `table` as a record batch of `table_schema(table)`. Missing
+/// values, and values that don't fit a column's type, become null; in a column that
+/// can't be null that fails with `DbError::Invalid`.
+pub fn rows_to_record_batch(table: &TableSchema, rows: &[Json]) -> Result<RecordBatch> {
+    let schema = table_schema(table);
+    let mut columns: Vec<ArrayRef> = Vec::with_capacity(table.columns.len());
+    for (column, field) in table.columns.iter().zip(schema.fields()) {
+        let array = json_column(rows, &column.name, &column.data_type);
+        if !field.is_nullable() && array.null_count() > 0 {
+            return Err(DbError::Invalid(format!("column {} of table {} is missing or not {:?} in a stored row", column.name, table.name, column.data_type)));
+        }
+        columns.push(array);
+    }
+    RecordBatch::try_new(Arc::new(schema), columns)
+        .map_err(|e| DbError::Storage(format!("Failed to build record batch: {}", e)))
+}
+
+/// The rows of `table`, read from one snapshot, as record batches of
+/// `table_schema` with up to `batch_size` rows each (see `rows_to_record_batch`)
+pub fn table_to_record_batches(db: &Db, table: &str, batch_size: usize) -> Result<Vec<RecordBatch>> {
+    if batch_size == 0 {
+        return Err(DbError::Invalid("batch size must be at least 1".into()));
+    }
+    let schema = db.catalog.read().tables.get(table).cloned().ok_or_else(|| DbError::NotFound(format!("table {}", table)))?;
+    let view = db.storage.snapshot()?;
+    let mut batches = Vec::new();
+    let mut rows = Vec::with_capacity(batch_size);
+    for (_, v) in view.scan_prefix(&Space("data".into()), format!("tbl/{}/", table).as_bytes())? {
+        rows.push(serde_json::from_slice(&v).map_err(|e| DbError::Corruption(format!("row of table {}: {}", table, e)))?);
+        if rows.len() == batch_size {
+            batches.push(rows_to_record_batch(&schema, &rows)?);
+            rows.clear();
+        }
+    }
+    if !rows.is_empty() {
+        batches.push(rows_to_record_batch(&schema, &rows)?);
+    }
+    Ok(batches)
+}
+
+/// Rows of `batch` as they are stored in `table`. Columns are matched by name and cast
+/// to the column's Arrow type, so e.g. `Int32` or `Decimal128` data fits `Integer` and
+/// `Decimal` columns; `Json` columns are parsed from their text, or converted from
+/// struct, list and map data. Nulls and columns the batch lacks are left out of the
+/// rows. Fails with `DbError::Invalid` if the batch has a column the table doesn't or
+/// one that can't be cast.
+pub fn record_batch_to_rows(table: &TableSchema, batch: &RecordBatch) -> Result<Vec<Json>> {
+    if let Some(extra) = batch.schema().fields().iter().find(|f| !table.columns.iter().any(|c| c.name == *f.name())) {
+        return Err(DbError::Invalid(format!("table {} has no column {}", table.name, extra.name())));
+    }
+    let mut rows = vec![serde_json::Map::new(); batch.num_rows()];
+    for column in &table.columns {
+        let Some(array) = batch.column_by_name(&column.name) else { continue };
+        if column.data_type == ColumnType::Json && array.data_type().is_nested() {
+            for (row, v) in rows.iter_mut().zip(nested_to_json(&column.name, array)?) {
+                if !v.is_null() {
+                    row.insert(column.name.clone(), v);
+                }
+            }
+            continue;
+        }
+        let array = arrow::compute::cast(array, &arrow_type(&column.data_type))
+            .map_err(|e| DbError::Invalid(format!("column {} of table {}: {}", column.name, table.name, e)))?;
+        for (i, row) in rows.iter_mut().enumerate() {
+            if array.is_null(i) {
+                continue;
+            }
+            let v = match column.data_type {
+                ColumnType::Integer => Json::from(array.as_primitive::<Int64Type>().value(i)),
+                ColumnType::Float => match serde_json::Number::from_f64(array.as_primitive::<Float64Type>().value(i)) {
+                    Some(n) => Json::Number(n),
+                    None => continue,
+                },
+                ColumnType::Boolean => Json::Bool(array.as_boolean().value(i)),
+                ColumnType::Text => Json::String(array.as_string::<i32>().value(i).to_string()),
+                ColumnType::Json => {
+                    let text = array.as_string::<i32>().value(i);
+                    serde_json::from_str(text).map_err(|e| DbError::Invalid(format!("column {} of table {}: bad JSON: {}", column.name, table.name, e)))?
+                }
+                ColumnType::Decimal => {
+                    let text = array.as_string::<i32>().value(i);
+                    numbers::number_literal(text, NumberMode::Decimal)
+                        .ok_or_else(|| DbError::Invalid(format!("column {} of table {}: bad decimal {}", column.name, table.name, text)))?
+                }
+            };
+            row.insert(column.name.clone(), v);
+        }
+    }
+    Ok(rows.into_iter().map(Json::Object).collect())
+}
+
+/// Values of a struct, list or map array as JSON, with nulls as `Json::Null`
+fn nested_to_json(name: &str, array: &ArrayRef) -> Result<Vec<Json>> {
+    let json_err = |e: arrow::error::ArrowError| DbError::Invalid(format!("column {}: {}", name, e));
+    let schema = Arc::new(Schema::new(vec![Field::new(name, array.data_type().clone(), true)]));
+    let batch = RecordBatch::try_new(schema, vec![array.clone()]).map_err(json_err)?;
+    let mut writer = arrow::json::ArrayWriter::new(Vec::new());
+    writer.write(&batch).map_err(json_err)?;
+    writer.finish().map_err(json_err)?;
+    let rows: Vec<serde_json::Map<String, Json>> =
+        serde_json::from_slice(&writer.into_inner()).map_err(|e| DbError::Invalid(format!("column {}: {}", name, e)))?;
+    Ok(rows.into_iter().map(|mut row| row.remove(name).unwrap_or(Json::Null)).collect())
+}
+
+/// Export a document collection as a record batch with one nullable column per field
+/// of its inferred schema. Values that don't fit a column's type become null.
+pub fn collection_to_record_batch<S: Storage + ?Sized>(storage: &S, collection: &str) -> Result<RecordBatch> {
+    let inferred = schema_inference::load_or_refresh(storage, collection)?;
+    if inferred.fields.is_empty() {
+        return Err(DbError::Invalid(format!("collection {} has no inferred fields", collection)));
+    }
+    let docs = tonledb_nosql_doc::list_all(storage, collection, true)?;
+
+    let fields: Vec<Field> = inferred.fields.iter()
+        .map(|f| Field::new(f.name.as_str(), arrow_type(&f.data_type()), true))
+        .collect();
+    let columns: Vec<ArrayRef> = inferred.fields.iter()
+        .map(|f| json_column(&docs, &f.name, &f.data_type()))
+        .collect();
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)
+        .map_err(|e| DbError::Storage(format!("Failed to build record batch: {}", e)))
+}
+
+fn json_column(docs: &[serde_json::Value], name: &str, column_type: &ColumnType) -> ArrayRef {
+    let cells = docs.iter().map(|d| d.get(name).filter(|v| !v.is_null()));
+    match column_type {
+        ColumnType::Integer => Arc::new(Int64Array::from(cells.map(|v| v.and_then(|v| v.as_i64())).collect::<Vec<_>>())),
+        ColumnType::Float => Arc::new(Float64Array::from(cells.map(|v| v.and_then(|v| v.as_f64())).collect::<Vec<_>>())),
+        ColumnType::Boolean => Arc::new(BooleanArray::from(cells.map(|v| v.and_then(|v| v.as_bool())).collect::<Vec<_>>())),
+        ColumnType::Text => Arc::new(StringArray::from(cells.map(|v| v.and_then(|v| v.as_str().map(str::to_string))).collect::<Vec<_>>())),
+        ColumnType::Json => Arc::new(StringArray::from(cells.map(|v| v.map(|v| v.to_string())).collect::<Vec<_>>())),
+        ColumnType::Decimal => Arc::new(StringArray::from(cells.map(|v| v.and_then(|v| v.as_number()).map(|n| n.to_string())).collect::<Vec<_>>())),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
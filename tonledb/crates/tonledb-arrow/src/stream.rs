@@ -0,0 +1,150 @@
+//! Streaming Parquet output, for tables and collections too large to hold in memory.
+//!
+//! `ParquetStreamWriter` appends record batches to any `Write` and cuts a row group
+//! every `row_group_rows` rows, so at most one row group is buffered at a time.
+//! `create_parquet_file` opens one on a local file. `ObjectStoreParquetWriter`
+//! writes to an `ObjectStore`, which takes whole objects only, as a dataset of part
+//! files (`<prefix>/part-00000.parquet`, ...) of up to `part_rows` rows each, the
+//! layout query engines read as one table.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+use arrow::datatypes::SchemaRef;
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use parquet::file::properties::WriterProperties;
+use tonledb_core::object_store::ObjectStore;
+use tonledb_core::{DbError, Result};
+
+/// Rows per row group unless asked otherwise
+pub const DEFAULT_ROW_GROUP_ROWS: usize = 64 * 1024;
+/// Rows per part file of an `ObjectStoreParquetWriter` unless asked otherwise
+pub const DEFAULT_PART_ROWS: usize = 1 << 20;
+
+fn parquet_err(e: parquet::errors::ParquetError) -> DbError {
+    DbError::Storage(format!("Parquet: {}", e))
+}
+
+/// One Parquet file written batch by batch (see the module docs)
+pub struct ParquetStreamWriter<W: Write + Send> {
+    inner: ArrowWriter<W>,
+    rows: u64,
+}
+
+impl<W: Write + Send> ParquetStreamWriter<W> {
+    pub fn try_new(out: W, schema: SchemaRef, row_group_rows: usize) -> Result<Self> {
+        if row_group_rows == 0 {
+            return Err(DbError::Invalid("row groups must hold at least 1 row".into()));
+        }
+        let props = WriterProperties::builder().set_max_row_group_size(row_group_rows).build();
+        let inner = ArrowWriter::try_new(out, schema, Some(props)).map_err(parquet_err)?;
+        Ok(Self { inner, rows: 0 })
+    }
+
+    /// Append `batch`, writing out each row group as it fills
+    pub fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        self.inner.write(batch).map_err(parquet_err)?;
+        self.rows += batch.num_rows() as u64;
+        Ok(())
+    }
+
+    /// Rows written so far
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    /// Write the last row group and the footer; returns the output
+    pub fn finish(self) -> Result<W> {
+        self.inner.into_inner().map_err(parquet_err)
+    }
+}
+
+/// A `ParquetStreamWriter` on a new file at `path`
+pub fn create_parquet_file(path: &Path, schema: SchemaRef, row_group_rows: usize) -> Result<ParquetStreamWriter<BufWriter<File>>> {
+    let file = File::create(path).map_err(|e| DbError::Storage(format!("Parquet file {}: {}", path.display(), e)))?;
+    ParquetStreamWriter::try_new(BufWriter::new(file), schema, row_group_rows)
+}
+
+/// Finish a writer from `create_parquet_file` and sync the file to disk
+pub fn finish_parquet_file(writer: ParquetStreamWriter<BufWriter<File>>) -> Result<u64> {
+    let rows = writer.rows();
+    let file = writer.finish()?.into_inner().map_err(|e| DbError::Storage(format!("Parquet file: {}", e.error())))?;
+    file.sync_all().map_err(|e| DbError::Storage(format!("Parquet file: {}", e)))?;
+    Ok(rows)
+}
+
+/// A Parquet dataset in an object store, one part file per `part_rows` rows (see
+/// the module docs). Only the part being written is held in memory.
+pub struct ObjectStoreParquetWriter {
+    store: Arc<dyn ObjectStore>,
+    prefix: String,
+    schema: SchemaRef,
+    row_group_rows: usize,
+    part_rows: usize,
+    current: Option<ParquetStreamWriter<Vec<u8>>>,
+    parts: Vec<String>,
+}
+
+impl ObjectStoreParquetWriter {
+    pub fn new(store: Arc<dyn ObjectStore>, prefix: &str, schema: SchemaRef) -> Self {
+        Self {
+            store,
+            prefix: prefix.trim_end_matches('/').to_string(),
+            schema,
+            row_group_rows: DEFAULT_ROW_GROUP_ROWS,
+            part_rows: DEFAULT_PART_ROWS,
+            current: None,
+            parts: Vec::new(),
+        }
+    }
+
+    pub fn with_row_group_rows(mut self, rows: usize) -> Self {
+        self.row_group_rows = rows;
+        self
+    }
+
+    /// Rows per part file; the most rows held in memory
+    pub fn with_part_rows(mut self, rows: usize) -> Self {
+        self.part_rows = rows.max(1);
+        self
+    }
+
+    /// Append `batch`, uploading each part file as it fills
+    pub fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        let mut offset = 0;
+        while offset < batch.num_rows() {
+            if self.current.is_none() {
+                self.current = Some(ParquetStreamWriter::try_new(Vec::new(), self.schema.clone(), self.row_group_rows)?);
+            }
+            let writer = self.current.as_mut().expect("started above");
+            let room = self.part_rows - writer.rows() as usize;
+            let len = room.min(batch.num_rows() - offset);
+            writer.write(&batch.slice(offset, len))?;
+            offset += len;
+            if writer.rows() as usize >= self.part_rows {
+                self.upload()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn upload(&mut self) -> Result<()> {
+        let Some(writer) = self.current.take() else { return Ok(()) };
+        let path = format!("{}/part-{:05}.parquet", self.prefix, self.parts.len());
+        self.store.put(&path, writer.finish()?)?;
+        self.parts.push(path);
+        Ok(())
+    }
+
+    /// Upload the last part; returns the paths of every part, in order. A dataset
+    /// without rows still gets one (empty) part, so it keeps its schema.
+    pub fn finish(mut self) -> Result<Vec<String>> {
+        if self.parts.is_empty() && self.current.is_none() {
+            self.current = Some(ParquetStreamWriter::try_new(Vec::new(), self.schema.clone(), self.row_group_rows)?);
+        }
+        self.upload()?;
+        Ok(self.parts)
+    }
+}
@@ -0,0 +1,203 @@
+//! Data lifecycle tiering: move cold documents to Parquet files in object storage.
+//!
+//! Archived files are registered in the catalog as an external table for their
+//! collection (`ext/<collection>/<object path>`), and every archived document gets a
+//! locator (`tier/<collection>/<id>` -> object path) so reads can recall it
+//! transparently into the hot tier.
+
+use std::sync::Arc;
+use arrow::array::{Array, StringArray};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as Json;
+use tonledb_core::object_store::ObjectStore;
+use tonledb_core::{DbError, Result, Space, Storage};
+
+const CATALOG_SPACE: &str = "catalog";
+
+/// Move documents of `collection` whose numeric `age_field` (epoch ms) is older
+/// than `max_age_ms` to the cold tier
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TieringPolicy {
+    pub collection: String,
+    pub age_field: String,
+    pub max_age_ms: u64,
+}
+
+/// Result of applying a tiering policy
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TierReport {
+    pub archived_docs: usize,
+    pub object_path: Option<String>,
+    pub hot_bytes: u64,
+    pub cold_bytes: u64,
+}
+
+/// External-table entry describing one archived Parquet object
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ColdFile {
+    pub path: String,
+    pub rows: usize,
+    pub bytes: u64,
+}
+
+/// Archive cold documents of `policy.collection` into a new Parquet object.
+pub fn apply_policy<S: Storage + ?Sized>(storage: &S, store: &dyn ObjectStore, policy: &TieringPolicy) -> Result<TierReport> {
+    let now = now_ms();
+    let cutoff = now.saturating_sub(policy.max_age_ms);
+    let col = &policy.collection;
+
+    let mut cold: Vec<(String, Json)> = Vec::new();
+    for doc in tonledb_nosql_doc::list_all(storage, col, false)? {
+        let is_cold = doc.get(&policy.age_field).and_then(|v| v.as_u64()).is_some_and(|ts| ts < cutoff);
+        if let (true, Some(id)) = (is_cold, doc.get("_id").and_then(|v| v.as_str())) {
+            cold.push((id.to_string(), doc.clone()));
+        }
+    }
+
+    let mut report = TierReport::default();
+    if !cold.is_empty() {
+        let path = format!("cold/{}/{}.parquet", col, now);
+        let bytes = docs_to_parquet(&cold)?;
+        let file = ColdFile { path: path.clone(), rows: cold.len(), bytes: bytes.len() as u64 };
+        store.put(&path, bytes)?;
+
+        // Register the file before dropping hot copies so nothing is ever unreachable
+        let catalog = Space(CATALOG_SPACE.into());
+        storage.put(&catalog, ext_key(col, &path), serde_json::to_vec(&file).map_err(|e| DbError::Storage(e.to_string()))?)?;
+        for (id, _) in &cold {
+            storage.put(&catalog, locator_key(col, id), path.clone().into_bytes())?;
+            tonledb_nosql_doc::delete(storage, col, id)?;
+        }
+        report.archived_docs = cold.len();
+        report.object_path = Some(path);
+    }
+
+    let (hot, cold_bytes) = tier_sizes(storage, col)?;
+    report.hot_bytes = hot;
+    report.cold_bytes = cold_bytes;
+    Ok(report)
+}
+
+/// Get a document from the hot tier, recalling it from the cold tier if it was archived.
+pub fn get_or_recall<S: Storage + ?Sized>(storage: &S, store: &dyn ObjectStore, collection: &str, id: &str) -> Result<Option<Json>> {
+    if let Some(doc) = tonledb_nosql_doc::get(storage, collection, id, true)? {
+        return Ok(Some(doc));
+    }
+    let catalog = Space(CATALOG_SPACE.into());
+    let path = match storage.get(&catalog, &locator_key(collection, id))? {
+        Some(p) => String::from_utf8_lossy(&p).to_string(),
+        None => return Ok(None),
+    };
+    let found = read_cold_file(store, &path)?.into_iter().find(|(doc_id, _)| doc_id == id);
+    match found {
+        Some((_, doc)) => {
+            tonledb_nosql_doc::update_merge(storage, collection, id, doc.clone(), true)?;
+            storage.del(&catalog, &locator_key(collection, id))?;
+            let (hot, cold) = tier_sizes(storage, collection)?;
+            tonledb_metrics::set_tier_bytes("hot", collection, hot);
+            tonledb_metrics::set_tier_bytes("cold", collection, cold);
+            Ok(Some(doc))
+        }
+        None => Err(DbError::Storage(format!("cold object {} has no document {}", path, id))),
+    }
+}
+
+/// Read every still-archived document of `collection` through its external table.
+pub fn scan_cold<S: Storage + ?Sized>(storage: &S, store: &dyn ObjectStore, collection: &str) -> Result<Vec<Json>> {
+    let catalog = Space(CATALOG_SPACE.into());
+    let mut out = Vec::new();
+    for file in cold_files(storage, collection)? {
+        for (id, doc) in read_cold_file(store, &file.path)? {
+            // Recalled documents live in the hot tier now
+            if storage.get(&catalog, &locator_key(collection, &id))?.is_some() {
+                out.push(doc);
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// External-table entries registered for `collection`
+pub fn cold_files<S: Storage + ?Sized>(storage: &S, collection: &str) -> Result<Vec<ColdFile>> {
+    let prefix = format!("ext/{}/", collection).into_bytes();
+    storage
+        .scan_prefix(&Space(CATALOG_SPACE.into()), &prefix)?
+        .map(|(_, v)| serde_json::from_slice(&v).map_err(|e| DbError::Storage(e.to_string())))
+        .collect()
+}
+
+/// Hot and cold bytes for `collection`, also published as `tonledb_tier_bytes`
+pub fn tier_sizes<S: Storage + ?Sized>(storage: &S, collection: &str) -> Result<(u64, u64)> {
+    let prefix = format!("doc/{}/", collection).into_bytes();
+    let hot: u64 = storage.scan_prefix(&Space("data".into()), &prefix)?.map(|(_, v)| v.len() as u64).sum();
+    let cold: u64 = cold_files(storage, collection)?.iter().map(|f| f.bytes).sum();
+    tonledb_metrics::set_tier_bytes("hot", collection, hot);
+    tonledb_metrics::set_tier_bytes("cold", collection, cold);
+    Ok((hot, cold))
+}
+
+// ---------- helpers ----------
+
+fn docs_to_parquet(docs: &[(String, Json)]) -> Result<Vec<u8>> {
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("_id", DataType::Utf8, false),
+        Field::new("doc", DataType::Utf8, false),
+    ]));
+    let ids = StringArray::from(docs.iter().map(|(id, _)| id.as_str()).collect::<Vec<_>>());
+    let bodies = StringArray::from(docs.iter().map(|(_, d)| d.to_string()).collect::<Vec<_>>());
+    let batch = RecordBatch::try_new(schema.clone(), vec![Arc::new(ids), Arc::new(bodies)])
+        .map_err(|e| DbError::Storage(format!("Failed to build record batch: {}", e)))?;
+
+    let mut buf = Vec::new();
+    let mut writer = ArrowWriter::try_new(&mut buf, schema, None)
+        .map_err(|e| DbError::Storage(format!("Failed to create Parquet writer: {}", e)))?;
+    writer.write(&batch)
+        .map_err(|e| DbError::Storage(format!("Failed to write record batch: {}", e)))?;
+    writer.close()
+        .map_err(|e| DbError::Storage(format!("Failed to close Parquet writer: {}", e)))?;
+    Ok(buf)
+}
+
+fn read_cold_file(store: &dyn ObjectStore, path: &str) -> Result<Vec<(String, Json)>> {
+    let data = store.get(path)?.ok_or_else(|| DbError::NotFound(format!("cold object {}", path)))?;
+    let reader = ParquetRecordBatchReaderBuilder::try_new(bytes::Bytes::from(data))
+        .map_err(|e| DbError::Storage(format!("Failed to create Parquet reader: {}", e)))?
+        .build()
+        .map_err(|e| DbError::Storage(format!("Failed to build Parquet reader: {}", e)))?;
+
+    let mut out = Vec::new();
+    for batch in reader {
+        let batch = batch.map_err(|e| DbError::Storage(format!("Failed to read record batch: {}", e)))?;
+        let ids = string_column(&batch, 0)?;
+        let bodies = string_column(&batch, 1)?;
+        for i in 0..batch.num_rows() {
+            let doc = serde_json::from_str(bodies.value(i)).map_err(|e| DbError::Storage(e.to_string()))?;
+            out.push((ids.value(i).to_string(), doc));
+        }
+    }
+    Ok(out)
+}
+
+fn string_column(batch: &RecordBatch, idx: usize) -> Result<&StringArray> {
+    batch.column(idx).as_any().downcast_ref::<StringArray>()
+        .ok_or_else(|| DbError::Storage("cold file column is not Utf8".into()))
+}
+
+fn ext_key(collection: &str, path: &str) -> Vec<u8> {
+    format!("ext/{}/{}", collection, path).into_bytes()
+}
+
+fn locator_key(collection: &str, id: &str) -> Vec<u8> {
+    format!("tier/{}/{}", collection, id).into_bytes()
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
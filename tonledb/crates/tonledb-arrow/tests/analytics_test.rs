@@ -0,0 +1,87 @@
+//! Tests for routing analytic SQL through DataFusion
+#![cfg(feature = "datafusion")]
+
+use std::sync::Arc;
+use serde_json::json;
+use tonledb_arrow::analytics::{execute_analytic_sql, is_simple, ANALYTIC_BATCH_ROWS};
+use tonledb_core::{Column, ColumnConstraint, DataType, Db, DbError, Space, Storage, TableSchema};
+use tonledb_storage::{arc_inmem_with_wal, ColumnarStorage};
+
+fn shop_db() -> Db {
+    shop_db_on(arc_inmem_with_wal(None, 1000))
+}
+
+fn shop_db_on(storage: Arc<dyn Storage>) -> Db {
+    let db = Db::new(storage);
+    let column = |name: &str, data_type| Column { name: name.into(), data_type, constraints: vec![] };
+    let mut id = column("id", DataType::Integer);
+    id.constraints.push(ColumnConstraint::PrimaryKey);
+    let columns = vec![id, column("customer", DataType::Text), column("total", DataType::Integer)];
+    db.catalog.write().tables.insert("orders".into(), TableSchema { name: "orders".into(), columns, pk: Some("id".into()), constraints: vec![] });
+    for (id, customer, total) in [(1, "ann", 10), (2, "bob", 5), (3, "ann", 7)] {
+        let row = json!({"id": id, "customer": customer, "total": total});
+        db.storage.put(&Space("data".into()), format!("tbl/orders/{}", id).into_bytes(), serde_json::to_vec(&row).unwrap()).unwrap();
+    }
+    for (name, city) in [("ann", "Oslo"), ("bob", "Lima")] {
+        tonledb_nosql_doc::insert(&*db.storage, "customers", json!({"name": name, "city": city})).unwrap();
+    }
+    db
+}
+
+#[test]
+fn test_is_simple() {
+    assert!(is_simple("SELECT * FROM orders WHERE id = 1"));
+    assert!(!is_simple("SELECT customer, SUM(total) FROM orders GROUP BY customer"));
+    assert!(!is_simple("SELECT COUNT(*) FROM orders"));
+    assert!(!is_simple("SELECT DISTINCT customer FROM orders"));
+    assert!(!is_simple("SELECT * FROM orders o JOIN customers c ON o.customer = c.name"));
+    assert!(!is_simple("not sql"));
+}
+
+#[tokio::test]
+async fn test_aggregation_and_join_run_on_datafusion() {
+    let db = shop_db();
+    let totals = execute_analytic_sql(&db, "SELECT customer, SUM(total) AS spent FROM orders GROUP BY customer ORDER BY customer").await.unwrap();
+    assert_eq!(totals, json!([{"customer": "ann", "spent": 17}, {"customer": "bob", "spent": 5}]));
+
+    // Tables join with collections
+    let joined = execute_analytic_sql(&db, "SELECT o.id, c.city FROM orders o JOIN customers c ON o.customer = c.name ORDER BY o.id").await.unwrap();
+    assert_eq!(joined, json!([{"id": 1, "city": "Oslo"}, {"id": 2, "city": "Lima"}, {"id": 3, "city": "Oslo"}]));
+
+    let missing = execute_analytic_sql(&db, "SELECT COUNT(*) FROM nowhere").await;
+    assert!(matches!(missing, Err(DbError::Invalid(_))));
+}
+
+#[tokio::test]
+async fn test_simple_query_stays_native() {
+    let db = shop_db();
+    let native = tonledb_sql::execute_sql(&db, "SELECT * FROM orders WHERE id = 2").unwrap();
+    assert_eq!(execute_analytic_sql(&db, "SELECT * FROM orders WHERE id = 2").await.unwrap(), native);
+}
+
+#[tokio::test]
+async fn test_tables_with_column_segments_read_them() {
+    let storage: Arc<dyn Storage> = Arc::new(ColumnarStorage::new(arc_inmem_with_wal(None, 1000), ["orders".to_string()]).unwrap());
+    let db = shop_db_on(storage);
+    assert!(tonledb_core::columnar::is_ready(&*db.storage, "orders").unwrap());
+    let totals = execute_analytic_sql(&db, "SELECT customer, SUM(total) AS spent FROM orders GROUP BY customer ORDER BY customer").await.unwrap();
+    assert_eq!(totals, json!([{"customer": "ann", "spent": 17}, {"customer": "bob", "spent": 5}]));
+    let count = execute_analytic_sql(&db, "SELECT COUNT(*) AS n FROM orders").await.unwrap();
+    assert_eq!(count, json!([{"n": 3}]));
+}
+
+#[tokio::test]
+async fn test_scans_span_several_record_batches() {
+    let db = shop_db();
+    let rows = ANALYTIC_BATCH_ROWS * 2 + 5;
+    for id in 4..4 + rows {
+        let row = json!({"id": id, "customer": "cy", "total": 1});
+        db.storage.put(&Space("data".into()), format!("tbl/orders/{}", id).into_bytes(), serde_json::to_vec(&row).unwrap()).unwrap();
+        tonledb_nosql_doc::insert(&*db.storage, "visits", json!({"page": id % 3})).unwrap();
+    }
+    let spent = execute_analytic_sql(&db, "SELECT SUM(total) AS spent FROM orders WHERE customer = 'cy'").await.unwrap();
+    assert_eq!(spent, json!([{"spent": rows}]));
+    let pages = execute_analytic_sql(&db, "SELECT page, COUNT(*) AS n FROM visits GROUP BY page ORDER BY page").await.unwrap();
+    let counts: Vec<u64> = pages.as_array().unwrap().iter().map(|p| p["n"].as_u64().unwrap()).collect();
+    assert_eq!(counts.iter().sum::<u64>(), rows as u64);
+}
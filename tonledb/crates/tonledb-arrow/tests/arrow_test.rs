@@ -3,7 +3,7 @@
 use tonledb_arrow::{values_to_arrow_arrays, write_record_batch_to_parquet, read_parquet_from_storage};
 use tonledb_core::{Value, Space, Storage};
 use tonledb_storage::arc_inmem_with_wal;
-use arrow::array::{Int64Array, RecordBatch};
+use arrow::array::{Array, Int64Array, RecordBatch};
 use arrow::datatypes::{DataType, Field, Schema};
 use std::sync::Arc;
 
@@ -57,4 +57,50 @@ fn test_parquet_write_read() {
     let read_batch = read_batch.unwrap();
     assert_eq!(read_batch.num_rows(), 4);
     assert_eq!(read_batch.num_columns(), 2);
-}
\ No newline at end of file
+}
+
+fn accounts_db() -> tonledb_core::Db {
+    use tonledb_core::{Column, ColumnConstraint, DataType as ColumnType, TableSchema};
+    let db = tonledb_core::Db::new(arc_inmem_with_wal(None, 1000));
+    let columns = vec![
+        Column { name: "id".into(), data_type: ColumnType::Integer, constraints: vec![ColumnConstraint::PrimaryKey] },
+        Column { name: "owner".into(), data_type: ColumnType::Text, constraints: vec![ColumnConstraint::NotNull] },
+        Column { name: "balance".into(), data_type: ColumnType::Float, constraints: vec![] },
+    ];
+    db.catalog.write().tables.insert("accounts".into(), TableSchema { name: "accounts".into(), columns, pk: Some("id".into()), constraints: vec![] });
+    db
+}
+
+#[test]
+fn test_table_to_record_batches() {
+    use arrow::array::{Float64Array, StringArray};
+    let db = accounts_db();
+    for i in 0..5i64 {
+        let row = serde_json::json!({"id": i, "owner": format!("o{}", i), "balance": if i == 2 { serde_json::Value::Null } else { serde_json::json!(i as f64 * 1.5) }});
+        db.storage.put(&Space("data".into()), format!("tbl/accounts/{}", i).into_bytes(), serde_json::to_vec(&row).unwrap()).unwrap();
+    }
+
+    let batches = tonledb_arrow::table_to_record_batches(&db, "accounts", 2).unwrap();
+    assert_eq!(batches.iter().map(|b| b.num_rows()).collect::<Vec<_>>(), vec![2, 2, 1]);
+    let schema = batches[0].schema();
+    assert_eq!(schema.fields().iter().map(|f| (f.name().as_str(), f.data_type().clone(), f.is_nullable())).collect::<Vec<_>>(), vec![
+        ("id", DataType::Int64, false),
+        ("owner", DataType::Utf8, false),
+        ("balance", DataType::Float64, true),
+    ]);
+    assert_eq!(schema.metadata()["tonledb.primary_key"], "id");
+    let owners = batches[1].column(1).as_any().downcast_ref::<StringArray>().unwrap();
+    assert_eq!(owners.value(0), "o2");
+    let balances = batches[1].column(2).as_any().downcast_ref::<Float64Array>().unwrap();
+    assert!(balances.is_null(0));
+    assert_eq!(balances.value(1), 4.5);
+
+    assert!(tonledb_arrow::table_to_record_batches(&db, "missing", 2).is_err());
+}
+
+#[test]
+fn test_table_to_record_batches_rejects_nulls_in_not_null_columns() {
+    let db = accounts_db();
+    db.storage.put(&Space("data".into()), b"tbl/accounts/1".to_vec(), serde_json::to_vec(&serde_json::json!({"id": 1})).unwrap()).unwrap();
+    assert!(matches!(tonledb_arrow::table_to_record_batches(&db, "accounts", 10), Err(tonledb_core::DbError::Invalid(_))));
+}
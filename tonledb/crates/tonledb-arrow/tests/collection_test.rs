@@ -0,0 +1,71 @@
+//! Tests for exporting document collections to Parquet with inferred, nested schemas
+
+use arrow::array::{Array, AsArray, Float64Array, Int64Array, StringArray, StructArray};
+use arrow::datatypes::{DataType, Field, Fields};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use serde_json::json;
+use std::sync::Arc;
+use tonledb_arrow::collection::{docs_to_record_batch, export_collection_parquet, infer_schema};
+use tonledb_storage::arc_inmem_with_wal;
+
+#[test]
+fn test_infer_schema_widens_and_nests() {
+    let docs = [
+        json!({"n": 1, "tag": "a", "user": {"name": "ann", "age": 30}, "scores": [1, 2]}),
+        json!({"n": 2.5, "tag": 7, "user": {"name": "bob", "city": "Oslo"}, "scores": [3.5]}),
+        json!({"extra": true, "scores": null}),
+    ];
+    let schema = infer_schema(&docs);
+    let field = |name: &str| schema.field_with_name(name).unwrap().data_type().clone();
+    assert_eq!(schema.fields().iter().map(|f| f.name().as_str()).collect::<Vec<_>>(), vec!["extra", "n", "scores", "tag", "user"]);
+    assert_eq!(field("n"), DataType::Float64);
+    assert_eq!(field("tag"), DataType::Utf8);
+    assert_eq!(field("extra"), DataType::Boolean);
+    assert_eq!(field("scores"), DataType::List(Arc::new(Field::new("item", DataType::Float64, true))));
+    let user = Fields::from(vec![
+        Field::new("age", DataType::Int64, true),
+        Field::new("city", DataType::Utf8, true),
+        Field::new("name", DataType::Utf8, true),
+    ]);
+    assert_eq!(field("user"), DataType::Struct(user));
+
+    let batch = docs_to_record_batch(&schema, &docs).unwrap();
+    assert_eq!(batch.num_rows(), 3);
+    // Mixed kinds are JSON text, strings as they are
+    let tags = batch.column_by_name("tag").unwrap().as_any().downcast_ref::<StringArray>().unwrap();
+    assert_eq!((tags.value(0), tags.value(1)), ("a", "7"));
+    let users = batch.column_by_name("user").unwrap().as_any().downcast_ref::<StructArray>().unwrap();
+    assert!(users.is_null(2));
+    let ages = users.column_by_name("age").unwrap().as_any().downcast_ref::<Int64Array>().unwrap();
+    assert_eq!(ages.value(0), 30);
+    assert!(ages.is_null(1));
+    let scores = batch.column_by_name("scores").unwrap().as_list::<i32>();
+    assert!(scores.is_null(2));
+    let second = scores.value(1);
+    assert_eq!(second.as_any().downcast_ref::<Float64Array>().unwrap().value(0), 3.5);
+}
+
+#[test]
+fn test_export_collection_parquet() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    for i in 0..3 {
+        tonledb_nosql_doc::insert(&*storage, "orders", json!({"n": i, "customer": {"name": format!("c{}", i)}, "items": [{"sku": "x", "qty": i}]})).unwrap();
+    }
+    let path = std::env::temp_dir().join(format!("tonledb-orders-docs-{}.parquet", std::process::id()));
+    assert_eq!(export_collection_parquet(&*storage, "orders", &path).unwrap(), 3);
+
+    let batches: Vec<_> = ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&path).unwrap())
+        .unwrap()
+        .build()
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 3);
+    let schema = batches[0].schema();
+    assert!(matches!(schema.field_with_name("customer").unwrap().data_type(), DataType::Struct(_)));
+    assert!(matches!(schema.field_with_name("items").unwrap().data_type(), DataType::List(_)));
+    assert_eq!(schema.field_with_name("_id").unwrap().data_type(), &DataType::Utf8);
+
+    assert!(export_collection_parquet(&*storage, "missing", &path).is_err());
+    let _ = std::fs::remove_file(path);
+}
@@ -0,0 +1,27 @@
+//! Tests for Arrow IPC result streams
+
+use arrow::array::{Array, Int64Array, StringArray};
+use arrow::ipc::reader::StreamReader;
+use serde_json::json;
+use tonledb_arrow::ipc::{rows_to_ipc_stream, IPC_BATCH_ROWS};
+
+#[test]
+fn test_rows_to_ipc_stream_round_trip() {
+    let rows: Vec<_> = (0..IPC_BATCH_ROWS as i64 + 1).map(|i| json!({"id": i, "name": if i == 1 { json!(null) } else { json!(format!("n{}", i)) }})).collect();
+    let bytes = rows_to_ipc_stream(&rows).unwrap();
+    let batches: Vec<_> = StreamReader::try_new(std::io::Cursor::new(bytes), None).unwrap().collect::<Result<_, _>>().unwrap();
+    assert_eq!(batches.iter().map(|b| b.num_rows()).collect::<Vec<_>>(), vec![IPC_BATCH_ROWS, 1]);
+    let ids = batches[0].column_by_name("id").unwrap().as_any().downcast_ref::<Int64Array>().unwrap();
+    assert_eq!(ids.value(2), 2);
+    let names = batches[0].column_by_name("name").unwrap().as_any().downcast_ref::<StringArray>().unwrap();
+    assert_eq!(names.value(0), "n0");
+    assert!(names.is_null(1));
+}
+
+#[test]
+fn test_empty_result_has_a_schema_only_stream() {
+    let bytes = rows_to_ipc_stream(&[]).unwrap();
+    let reader = StreamReader::try_new(std::io::Cursor::new(bytes), None).unwrap();
+    assert_eq!(reader.schema().fields().len(), 0);
+    assert_eq!(reader.count(), 0);
+}
@@ -1,11 +1,42 @@
 //! Backup and Point-In-Time Recovery (PITR) functionality for TonleDB
+//!
+//! `PITRManager::snapshot` streams a consistent snapshot of chosen spaces to a
+//! `target::BackupTarget` (a local directory or an S3-compatible bucket) and
+//! `PITRManager::restore` streams it back, so neither needs the backup on local disk.
+//! Each snapshot gets a manifest that `manifest::verify` checks it against;
+//! `PITRManager::restore_filtered` restores only chosen spaces, tables or collections.
+//! `pitr::restore_to` rolls a snapshot forward with the WAL to a point in time.
+//! `write_snapshot` and `read_snapshot` do the same over any stream, e.g. an HTTP
+//! download or upload. `sql_dump::dump_sql` writes a logical backup of the SQL tables and
+//! `sql_dump::restore_sql` reads it back; `table_parquet` moves one table in and out
+//! as Parquet, loading it with `tonledb_sql::bulk::BulkInsert`.
+//! `schedule::ScheduledBackups` takes snapshots on a timer and rotates
+//! old ones out.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Read, Write};
 use std::time::{SystemTime, UNIX_EPOCH};
 use serde::{Deserialize, Serialize};
-use tonledb_core::{DbError, Result, Storage};
+use sha2::{Digest, Sha256};
+use tonledb_core::{DbError, Result, Space, Storage};
+use tonledb_storage::InMemoryStore;
 use tonledb_wal::Wal;
 
+pub mod manifest;
+pub mod pitr;
+pub mod replicate;
+pub mod restore;
+pub mod schedule;
+mod snapshot;
+pub mod sql_dump;
+pub mod table_parquet;
+pub mod target;
+
+use manifest::{HashingReader, SnapshotManifest, WalPosition};
+use replicate::{BackupReplicator, DestinationStatus, ReplicationJob};
+use restore::RestoreFilter;
+use target::{BackupTarget, BackupWriter};
+
 /// Backup metadata
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BackupMetadata {
@@ -20,6 +51,7 @@ pub struct BackupMetadata {
 pub struct PITRManager {
     backups: HashMap<String, BackupMetadata>,
     wal: Wal,
+    replicator: Option<BackupReplicator>,
 }
 
 impl PITRManager {
@@ -30,9 +62,101 @@ impl PITRManager {
         Ok(Self {
             backups: HashMap::new(),
             wal,
+            replicator: None,
         })
     }
+
+    /// Send every snapshot this manager takes to the replicator's destinations
+    pub fn with_replicator(mut self, replicator: BackupReplicator) -> Self {
+        self.replicator = Some(replicator);
+        self
+    }
+
+    /// Record the snapshot bytes of `backup_id` (size and SHA-256) and, with a
+    /// replicator configured, start fanning them out to every destination.
+    pub fn complete_backup(&mut self, backup_id: &str, data: Vec<u8>) -> Result<Option<ReplicationJob>> {
+        let meta = self
+            .backups
+            .get_mut(backup_id)
+            .ok_or_else(|| DbError::NotFound(format!("Backup {} not found", backup_id)))?;
+        meta.size = data.len() as u64;
+        meta.checksum = replicate::sha256_hex(&data);
+        self.replicator.as_ref().map(|r| r.replicate(backup_id, data)).transpose()
+    }
+
+    /// Per-destination replication status of `backup_id`
+    pub fn replication_status(&self, backup_id: &str) -> Vec<DestinationStatus> {
+        self.replicator.as_ref().map(|r| r.status(backup_id)).unwrap_or_default()
+    }
     
+    /// Stream a consistent snapshot of every key in `spaces` to `target` as backup
+    /// `backup_id`, then store its manifest (see `manifest`) beside it
+    pub fn snapshot<S: Storage + ?Sized>(&mut self, storage: &S, spaces: &[Space], backup_id: &str, target: &dyn BackupTarget) -> Result<SnapshotManifest> {
+        self.take_snapshot(storage, spaces, backup_id, target, None)
+    }
+
+    /// `snapshot` of a store with a WAL, noting where in the log it was taken, so
+    /// `pitr::restore_to` can roll it forward to a later point in time
+    pub fn snapshot_with_wal(&mut self, store: &InMemoryStore, spaces: &[Space], backup_id: &str, target: &dyn BackupTarget) -> Result<SnapshotManifest> {
+        // Taken before the snapshot: replaying records it already holds changes nothing
+        let lsn = store.wal_end_lsn().ok_or_else(|| DbError::Invalid("store has no WAL to restore forward from".into()))?;
+        let wal = WalPosition { lsn, at_ms: now_ms() };
+        self.take_snapshot(store, spaces, backup_id, target, Some(wal))
+    }
+
+    /// Take the snapshot and, with a replicator configured, start fanning it out;
+    /// the uploads go on in the background (see `replication_status`)
+    fn take_snapshot<S: Storage + ?Sized>(&mut self, storage: &S, spaces: &[Space], backup_id: &str, target: &dyn BackupTarget, wal: Option<WalPosition>) -> Result<SnapshotManifest> {
+        let manifest = snapshot_to(storage, spaces, backup_id, target, wal)?;
+        self.backups.insert(backup_id.to_string(), BackupMetadata {
+            id: backup_id.to_string(),
+            timestamp: manifest.created_at,
+            wal_position: 0,
+            size: manifest.size,
+            checksum: manifest.sha256.clone(),
+        });
+        if self.replicator.is_some() {
+            let mut data = Vec::with_capacity(manifest.size as usize);
+            let mut stream = target.reader(backup_id)?.ok_or_else(|| DbError::NotFound(format!("Backup {} not found", backup_id)))?;
+            stream.read_to_end(&mut data).map_err(|e| DbError::Storage(e.to_string()))?;
+            self.complete_backup(backup_id, data)?;
+        }
+        Ok(manifest)
+    }
+
+    /// Stream backup `backup_id` from `target` into `storage`; returns the number of
+    /// keys written. Keys are written as they are read, over whatever `storage`
+    /// holds, and without their TTLs. When the SHA-256 of the stream is known (from
+    /// the manifest, or because this manager took the backup), the stream is first
+    /// read once to check it, so a damaged backup fails with `DbError::Corruption`
+    /// before anything is written.
+    pub fn restore<S: Storage + ?Sized>(&self, storage: &S, backup_id: &str, target: &dyn BackupTarget) -> Result<u64> {
+        self.restore_where(storage, backup_id, target, None, |_, _| true)
+    }
+
+    /// `restore` of only the keys `filter` matches; returns how many were written.
+    /// Those keys are replaced: any of them in `storage` are deleted first, once the
+    /// stream has been read through and found whole. Other keys of `storage` are
+    /// left as they are.
+    pub fn restore_filtered<S: Storage + ?Sized>(&self, storage: &S, backup_id: &str, target: &dyn BackupTarget, filter: &RestoreFilter) -> Result<u64> {
+        if filter.is_empty() {
+            return Err(DbError::Invalid("restore filter selects nothing".into()));
+        }
+        self.restore_where(storage, backup_id, target, Some(filter), |space, key| filter.matches(space, key))
+    }
+
+    fn restore_where<S, F>(&self, storage: &S, backup_id: &str, target: &dyn BackupTarget, replace: Option<&RestoreFilter>, keep: F) -> Result<u64>
+    where
+        S: Storage + ?Sized,
+        F: Fn(&Space, &[u8]) -> bool,
+    {
+        let checksum = match self.backups.get(backup_id) {
+            Some(meta) => Some(meta.checksum.clone()),
+            None => manifest::read_manifest(target, backup_id)?.map(|m| m.sha256),
+        };
+        restore_stream(storage, backup_id, target, checksum.as_deref(), replace, keep)
+    }
+
     /// Create a new backup
     pub fn create_backup<S: Storage + ?Sized>(&mut self, _storage: &S, backup_id: &str) -> Result<()> {
         // In a real implementation, this would:
@@ -103,6 +227,109 @@ impl PITRManager {
     }
 }
 
+/// Write the keys `keep` selects of backup `backup_id` to `storage`, after checking
+/// the stream against `checksum` when given; returns how many were written. The
+/// keys `replace` matches are deleted from `storage` first, and then the stream is
+/// checked even without a checksum.
+pub(crate) fn restore_stream<S, F>(storage: &S, backup_id: &str, target: &dyn BackupTarget, checksum: Option<&str>, replace: Option<&RestoreFilter>, keep: F) -> Result<u64>
+where
+    S: Storage + ?Sized,
+    F: Fn(&Space, &[u8]) -> bool,
+{
+    let open = || target.reader(backup_id)?.ok_or_else(|| DbError::NotFound(format!("Backup {} not found", backup_id)));
+    if let Some(checksum) = checksum {
+        let mut stream = HashingReader::new(open()?);
+        io::copy(&mut stream, &mut io::sink()).map_err(|e| DbError::Storage(e.to_string()))?;
+        if stream.hex() != checksum {
+            return Err(DbError::Corruption(format!("backup {} failed verification", backup_id)));
+        }
+    } else if replace.is_some() {
+        check_snapshot(open()?)?;
+    }
+    if let Some(filter) = replace {
+        filter.clear(storage)?;
+    }
+    read_snapshot(storage, open()?, keep)
+}
+
+/// Stream a consistent snapshot of `spaces` to `target` as `backup_id` and store its
+/// manifest beside it
+pub(crate) fn snapshot_to<S: Storage + ?Sized>(storage: &S, spaces: &[Space], backup_id: &str, target: &dyn BackupTarget, wal: Option<WalPosition>) -> Result<SnapshotManifest> {
+    let view = storage.snapshot()?;
+    let mut out = HashingWriter { inner: target.writer(backup_id)?, sha: Sha256::new(), size: 0 };
+    let counts = snapshot::write(&*view, spaces, &mut out)?;
+    let HashingWriter { inner, sha, size } = out;
+    let files = inner.finish()?;
+    let manifest = SnapshotManifest {
+        backup_id: backup_id.to_string(),
+        created_at: now_ms() / 1000,
+        engine_version: manifest::ENGINE_VERSION.to_string(),
+        format_version: snapshot::SNAPSHOT_VERSION,
+        size,
+        sha256: hex::encode(sha.finalize()),
+        spaces: counts,
+        files,
+        wal,
+    };
+    target.put_manifest(backup_id, manifest.to_json())?;
+    Ok(manifest)
+}
+
+/// Write a snapshot of every key in `spaces` to `out`, in the format
+/// `PITRManager::snapshot` stores; returns the keys per space
+pub fn write_snapshot<S: Storage + ?Sized, W: Write>(storage: &S, spaces: &[Space], out: W) -> Result<BTreeMap<String, u64>> {
+    snapshot::write(&*storage.snapshot()?, spaces, out)
+}
+
+/// Decode the snapshot read from `input` without writing anything, so a damaged
+/// or truncated stream fails before a restore starts; returns the keys per space
+pub fn check_snapshot<R: Read>(input: R) -> Result<BTreeMap<String, u64>> {
+    snapshot::read(input, |_, _, _| Ok(()))
+}
+
+/// Write the keys `keep` selects of the snapshot read from `input` to `storage`, as
+/// they are read; returns how many were written. Nothing checks the stream first,
+/// so pass it to `check_snapshot` beforehand if it may be damaged.
+pub fn read_snapshot<S, R, F>(storage: &S, input: R, keep: F) -> Result<u64>
+where
+    S: Storage + ?Sized,
+    R: Read,
+    F: Fn(&Space, &[u8]) -> bool,
+{
+    let mut written = 0;
+    snapshot::read(input, |space, key, val| {
+        if !keep(&space, &key) {
+            return Ok(());
+        }
+        written += 1;
+        storage.put(&space, key, val)
+    })?;
+    Ok(written)
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+/// Passes writes on while hashing and counting them
+struct HashingWriter {
+    inner: Box<dyn BackupWriter>,
+    sha: Sha256,
+    size: u64,
+}
+
+impl Write for HashingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.sha.update(&buf[..n]);
+        self.size += n as u64;
+        Ok(n)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,221 @@
+//! Snapshot manifests and verification.
+//!
+//! `PITRManager::snapshot` stores a manifest beside each snapshot: the keys per
+//! space, the size and SHA-256 of the stream, every file the target stored with its
+//! own SHA-256, the engine and format versions that wrote it and, for snapshots
+//! taken with `PITRManager::snapshot_with_wal`, where in the WAL the snapshot
+//! stands (see `pitr`). `verify` (or
+//! `verify_snapshot` for a snapshot file on local disk) checks a snapshot against
+//! its manifest without restoring it: every file is re-hashed and the stream is
+//! decoded end to end, so a damaged or incomplete snapshot is caught before an
+//! operator relies on it.
+
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+use std::path::Path;
+use serde_json::{json, Value as Json};
+use sha2::{Digest, Sha256};
+use tonledb_core::{DbError, Result};
+use tonledb_wal::Lsn;
+use crate::snapshot::{self, SNAPSHOT_VERSION};
+use crate::target::{BackupTarget, LocalTarget, StoredFile};
+
+/// Version of the engine writing snapshots
+pub const ENGINE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Where in the WAL a snapshot was taken
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalPosition {
+    /// End of the log just before the snapshot; everything logged before it is in
+    /// the snapshot
+    pub lsn: Lsn,
+    /// When the snapshot was taken (epoch ms)
+    pub at_ms: u64,
+}
+
+/// What a snapshot holds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotManifest {
+    pub backup_id: String,
+    /// Seconds since the epoch
+    pub created_at: u64,
+    pub engine_version: String,
+    pub format_version: u16,
+    /// Size of the snapshot stream in bytes
+    pub size: u64,
+    /// Hex SHA-256 of the snapshot stream
+    pub sha256: String,
+    /// Keys per space
+    pub spaces: BTreeMap<String, u64>,
+    pub files: Vec<StoredFile>,
+    pub wal: Option<WalPosition>,
+}
+
+impl SnapshotManifest {
+    /// Keys in the snapshot
+    pub fn records(&self) -> u64 {
+        self.spaces.values().sum()
+    }
+
+    pub(crate) fn to_json(&self) -> Vec<u8> {
+        let files: Vec<Json> = self.files.iter()
+            .map(|f| json!({ "path": f.path, "size": f.size, "sha256": f.sha256 }))
+            .collect();
+        let spaces: serde_json::Map<String, Json> = self.spaces.iter().map(|(s, n)| (s.clone(), Json::from(*n))).collect();
+        let wal = match self.wal {
+            Some(w) => json!({ "segment": w.lsn.segment, "offset": w.lsn.offset, "at_ms": w.at_ms }),
+            None => Json::Null,
+        };
+        let v = json!({
+            "backup_id": self.backup_id,
+            "created_at": self.created_at,
+            "engine_version": self.engine_version,
+            "format_version": self.format_version,
+            "size": self.size,
+            "sha256": self.sha256,
+            "spaces": spaces,
+            "files": files,
+            "wal": wal,
+        });
+        serde_json::to_vec_pretty(&v).unwrap()
+    }
+
+    pub(crate) fn parse(name: &str, bytes: &[u8]) -> Result<Self> {
+        let bad = || DbError::Corruption(format!("manifest of backup {}", name));
+        let v: Json = serde_json::from_slice(bytes).map_err(|_| bad())?;
+        let str_of = |v: &Json, k: &str| v.get(k).and_then(Json::as_str).map(str::to_string).ok_or_else(bad);
+        let u64_of = |v: &Json, k: &str| v.get(k).and_then(Json::as_u64).ok_or_else(bad);
+        let spaces = v.get("spaces").and_then(Json::as_object).ok_or_else(bad)?
+            .iter()
+            .map(|(s, n)| n.as_u64().map(|n| (s.clone(), n)).ok_or_else(bad))
+            .collect::<Result<_>>()?;
+        let files = v.get("files").and_then(Json::as_array).ok_or_else(bad)?
+            .iter()
+            .map(|f| Ok(StoredFile { path: str_of(f, "path")?, size: u64_of(f, "size")?, sha256: str_of(f, "sha256")? }))
+            .collect::<Result<_>>()?;
+        let wal = match v.get("wal").filter(|w| !w.is_null()) {
+            Some(w) => Some(WalPosition {
+                lsn: Lsn { segment: u64_of(w, "segment")?, offset: u64_of(w, "offset")? },
+                at_ms: u64_of(w, "at_ms")?,
+            }),
+            None => None,
+        };
+        Ok(Self {
+            backup_id: str_of(&v, "backup_id")?,
+            created_at: u64_of(&v, "created_at")?,
+            engine_version: str_of(&v, "engine_version")?,
+            format_version: u16::try_from(u64_of(&v, "format_version")?).map_err(|_| bad())?,
+            size: u64_of(&v, "size")?,
+            sha256: str_of(&v, "sha256")?,
+            spaces,
+            files,
+            wal,
+        })
+    }
+}
+
+/// Outcome of `verify`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyReport {
+    pub manifest: SnapshotManifest,
+    /// What doesn't match the manifest; empty for an intact snapshot
+    pub problems: Vec<String>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Manifest of backup `name`; `None` if it has none
+pub fn read_manifest(target: &dyn BackupTarget, name: &str) -> Result<Option<SnapshotManifest>> {
+    target.manifest(name)?.map(|b| SnapshotManifest::parse(name, &b)).transpose()
+}
+
+/// Check backup `name` on `target` against its manifest. Fails with
+/// `DbError::NotFound` when there is no manifest; anything else wrong is listed in
+/// the report.
+pub fn verify(target: &dyn BackupTarget, name: &str) -> Result<VerifyReport> {
+    let manifest = read_manifest(target, name)?.ok_or_else(|| DbError::NotFound(format!("manifest of backup {}", name)))?;
+    let mut problems = Vec::new();
+    if manifest.format_version != SNAPSHOT_VERSION {
+        problems.push(format!("snapshot format {} is not supported (engine {} reads format {})", manifest.format_version, ENGINE_VERSION, SNAPSHOT_VERSION));
+    }
+
+    for file in &manifest.files {
+        let Some(reader) = target.file_reader(name, &file.path)? else {
+            problems.push(format!("file {} is missing", file.path));
+            continue;
+        };
+        let mut hashed = HashingReader::new(reader);
+        if let Err(e) = io::copy(&mut hashed, &mut io::sink()) {
+            problems.push(format!("file {}: {}", file.path, e));
+            continue;
+        }
+        if hashed.size != file.size || hashed.hex() != file.sha256 {
+            problems.push(format!("file {} does not match its checksum", file.path));
+        }
+    }
+
+    let Some(reader) = target.reader(name)? else {
+        problems.push("snapshot is missing".into());
+        return Ok(VerifyReport { manifest, problems });
+    };
+    // Spaces without keys leave nothing in the stream
+    let expected: BTreeMap<String, u64> = manifest.spaces.iter().filter(|(_, n)| **n > 0).map(|(s, n)| (s.clone(), *n)).collect();
+    let mut stream = HashingReader::new(reader);
+    match snapshot::read(&mut stream, |_, _, _| Ok(())) {
+        Ok(spaces) if spaces != expected => {
+            problems.push(format!("snapshot holds {} keys, the manifest lists {}", spaces.values().sum::<u64>(), manifest.records()));
+        }
+        Ok(_) => {}
+        Err(e) => problems.push(format!("snapshot does not decode: {}", e)),
+    }
+    // Hash whatever the decoder left unread too
+    let _ = io::copy(&mut stream, &mut io::sink());
+    if stream.size != manifest.size || stream.hex() != manifest.sha256 {
+        problems.push("snapshot does not match its checksum".into());
+    }
+    Ok(VerifyReport { manifest, problems })
+}
+
+/// `verify` for the snapshot file at `path`, written to a `LocalTarget` over its directory
+pub fn verify_snapshot(path: &Path) -> Result<VerifyReport> {
+    let (target, name) = local_snapshot(path)?;
+    verify(&target, &name)
+}
+
+/// The `LocalTarget` holding the snapshot file at `path`, and the snapshot's name
+pub(crate) fn local_snapshot(path: &Path) -> Result<(LocalTarget, String)> {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err(DbError::Invalid(format!("not a snapshot file: {}", path.display())));
+    };
+    Ok((LocalTarget::new(dir)?, name.to_string_lossy().into_owned()))
+}
+
+/// Passes reads on while hashing and counting them
+pub(crate) struct HashingReader<R> {
+    inner: R,
+    sha: Sha256,
+    pub(crate) size: u64,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        Self { inner, sha: Sha256::new(), size: 0 }
+    }
+
+    pub(crate) fn hex(&self) -> String {
+        hex::encode(self.sha.clone().finalize())
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.sha.update(&buf[..n]);
+        self.size += n as u64;
+        Ok(n)
+    }
+}
@@ -0,0 +1,211 @@
+//! Fan-out of completed backups to several destinations.
+//!
+//! `PITRManager::with_replicator` sends every snapshot the manager takes through a
+//! `BackupReplicator` once it is written. Each destination is an `ObjectStore`: a
+//! local or mounted directory (`LocalObjectStore`) or an S3-compatible bucket
+//! (`S3ObjectStore`). A backup is uploaded to every destination in parallel, on its
+//! own thread, as `backups/<id>.bin` plus `backups/<id>.sha256`; the upload is read
+//! back and its SHA-256 compared before the copy counts as verified. A failing
+//! destination is retried with backoff and then marked failed without holding up
+//! the others.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tonledb_core::object_store::ObjectStore;
+use tonledb_core::{DbError, Result};
+
+const BACKUP_PREFIX: &str = "backups/";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplicaState {
+    Pending,
+    Uploading,
+    Verified,
+    Failed,
+}
+
+/// Where one backup stands on one destination
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DestinationStatus {
+    pub destination: String,
+    pub backup_id: String,
+    pub state: ReplicaState,
+    pub attempts: u32,
+    /// Hex SHA-256 of the backup, as verified on the destination
+    pub sha256: Option<String>,
+    pub error: Option<String>,
+    pub updated_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    /// Delay before the second attempt; doubled for each further one
+    pub backoff_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self { max_attempts: 3, backoff_ms: 500 }
+    }
+}
+
+type StatusMap = BTreeMap<(String, String), DestinationStatus>;
+
+/// Uploads backups to every registered destination and tracks their status
+pub struct BackupReplicator {
+    destinations: Vec<(String, Arc<dyn ObjectStore>)>,
+    retry: RetryPolicy,
+    status: Arc<Mutex<StatusMap>>,
+}
+
+/// Uploads of one backup in flight
+pub struct ReplicationJob {
+    backup_id: String,
+    handles: Vec<JoinHandle<()>>,
+    status: Arc<Mutex<StatusMap>>,
+}
+
+impl ReplicationJob {
+    /// Block until every destination is verified or failed, and return their status
+    pub fn wait(self) -> Vec<DestinationStatus> {
+        for h in self.handles {
+            let _ = h.join();
+        }
+        statuses(&self.status, &self.backup_id)
+    }
+}
+
+impl BackupReplicator {
+    pub fn new(retry: RetryPolicy) -> Self {
+        Self { destinations: Vec::new(), retry, status: Arc::new(Mutex::new(BTreeMap::new())) }
+    }
+
+    /// Add a destination under a unique name
+    pub fn with_destination(mut self, name: &str, store: Arc<dyn ObjectStore>) -> Self {
+        self.destinations.push((name.to_string(), store));
+        self
+    }
+
+    pub fn destinations(&self) -> Vec<&str> {
+        self.destinations.iter().map(|(n, _)| n.as_str()).collect()
+    }
+
+    /// Start uploading `data` as backup `backup_id` to every destination.
+    /// Returns immediately; follow progress with `status` or block on the job.
+    pub fn replicate(&self, backup_id: &str, data: Vec<u8>) -> Result<ReplicationJob> {
+        if backup_id.is_empty() || backup_id.contains('/') {
+            return Err(DbError::Invalid(format!("invalid backup id: {:?}", backup_id)));
+        }
+        if self.destinations.is_empty() {
+            return Err(DbError::Invalid("no backup destinations configured".into()));
+        }
+        let data = Arc::new(data);
+        let sha = sha256_hex(&data);
+        let mut handles = Vec::new();
+        for (name, store) in &self.destinations {
+            set_status(&self.status, name, backup_id, ReplicaState::Pending, 0, None, None);
+            let (name, store, data, sha, status, retry) =
+                (name.clone(), store.clone(), data.clone(), sha.clone(), self.status.clone(), self.retry);
+            let id = backup_id.to_string();
+            handles.push(std::thread::spawn(move || upload(&name, &*store, &id, &data, &sha, &status, retry)));
+        }
+        Ok(ReplicationJob { backup_id: backup_id.to_string(), handles, status: self.status.clone() })
+    }
+
+    /// Status of `backup_id` on every destination it was sent to
+    pub fn status(&self, backup_id: &str) -> Vec<DestinationStatus> {
+        statuses(&self.status, backup_id)
+    }
+
+    /// Re-check the copy of `backup_id` on destination `name` against its stored hash
+    pub fn verify(&self, name: &str, backup_id: &str) -> Result<bool> {
+        let (_, store) = self
+            .destinations
+            .iter()
+            .find(|(n, _)| n == name)
+            .ok_or_else(|| DbError::NotFound(format!("backup destination {}", name)))?;
+        let (Some(data), Some(sha)) = (store.get(&data_path(backup_id))?, store.get(&hash_path(backup_id))?) else {
+            return Ok(false);
+        };
+        Ok(sha256_hex(&data).as_bytes() == sha.as_slice())
+    }
+}
+
+/// Hex SHA-256 of `data`
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+// ---------- helpers ----------
+
+fn data_path(backup_id: &str) -> String {
+    format!("{}{}.bin", BACKUP_PREFIX, backup_id)
+}
+
+fn hash_path(backup_id: &str) -> String {
+    format!("{}{}.sha256", BACKUP_PREFIX, backup_id)
+}
+
+fn upload(name: &str, store: &dyn ObjectStore, id: &str, data: &[u8], sha: &str, status: &Mutex<StatusMap>, retry: RetryPolicy) {
+    let mut error = None;
+    for attempt in 1..=retry.max_attempts.max(1) {
+        if attempt > 1 {
+            std::thread::sleep(Duration::from_millis(retry.backoff_ms.saturating_mul(1 << (attempt - 2).min(16))));
+        }
+        set_status(status, name, id, ReplicaState::Uploading, attempt, None, error.clone());
+        match upload_once(store, id, data, sha) {
+            Ok(()) => {
+                set_status(status, name, id, ReplicaState::Verified, attempt, Some(sha.to_string()), None);
+                return;
+            }
+            Err(e) => error = Some(e.to_string()),
+        }
+    }
+    set_status(status, name, id, ReplicaState::Failed, retry.max_attempts.max(1), None, error);
+}
+
+fn upload_once(store: &dyn ObjectStore, id: &str, data: &[u8], sha: &str) -> Result<()> {
+    store.put(&data_path(id), data.to_vec())?;
+    store.put(&hash_path(id), sha.as_bytes().to_vec())?;
+    let back = store.get(&data_path(id))?.ok_or_else(|| DbError::Storage("uploaded backup is missing".into()))?;
+    if sha256_hex(&back) != sha {
+        return Err(DbError::Corruption(format!("backup {} failed verification", id)));
+    }
+    Ok(())
+}
+
+fn set_status(status: &Mutex<StatusMap>, name: &str, id: &str, state: ReplicaState, attempts: u32, sha256: Option<String>, error: Option<String>) {
+    let st = DestinationStatus {
+        destination: name.to_string(),
+        backup_id: id.to_string(),
+        state,
+        attempts,
+        sha256,
+        error,
+        updated_ms: now_ms(),
+    };
+    status.lock().unwrap_or_else(|e| e.into_inner()).insert((id.to_string(), name.to_string()), st);
+}
+
+fn statuses(status: &Mutex<StatusMap>, backup_id: &str) -> Vec<DestinationStatus> {
+    status
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .range((backup_id.to_string(), String::new())..)
+        .take_while(|((id, _), _)| id == backup_id)
+        .map(|(_, s)| s.clone())
+        .collect()
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,121 @@
+//! Selective restore: which keys of a snapshot `PITRManager::restore_filtered`
+//! writes back.
+//!
+//! A `RestoreFilter` is a list of rules, and a key is restored when any rule
+//! matches it: a whole space, a key prefix within a space, or one of the shorthands
+//! for a SQL table or a document collection, which cover the keys those keep in the
+//! `data`, `catalog` and `doc_index` spaces. Keys outside the filter are left alone
+//! in the target storage, so restoring one collection doesn't touch the others.
+//! Keys inside it are replaced: those the backup doesn't have (documents written
+//! since, say, and their index entries) are deleted before the backup's are written.
+
+use tonledb_core::{Result, Space, Storage};
+
+/// Keys deleted per step of `RestoreFilter::clear`
+const CLEAR_BATCH: usize = 256;
+
+const DATA_SPACE: &str = "data";
+const CATALOG_SPACE: &str = "catalog";
+const DOC_INDEX_SPACE: &str = "doc_index";
+/// Catalog entries keyed `<kind>/<collection>`
+const COLLECTION_SETTINGS: [&str; 6] = ["col", "schema", "numbers/col", "capped", "timeseries", "timestamps"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Rule {
+    Space,
+    Prefix(Vec<u8>),
+    Key(Vec<u8>),
+}
+
+/// Keys to restore; see the module docs
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RestoreFilter {
+    rules: Vec<(String, Rule)>,
+}
+
+impl RestoreFilter {
+    /// Filter restoring nothing, to add rules to
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every key of `space`
+    pub fn space(mut self, space: &str) -> Self {
+        self.rules.push((space.to_string(), Rule::Space));
+        self
+    }
+
+    /// Keys of `space` starting with `prefix`
+    pub fn prefix(mut self, space: &str, prefix: &[u8]) -> Self {
+        self.rules.push((space.to_string(), Rule::Prefix(prefix.to_vec())));
+        self
+    }
+
+    /// The rows of SQL table `name` (`tbl/<name>/`) and its number modes
+    pub fn table(self, name: &str) -> Self {
+        let mut f = self.prefix(DATA_SPACE, format!("tbl/{}/", name).as_bytes());
+        f.rules.push((CATALOG_SPACE.into(), Rule::Key(format!("numbers/tbl/{}", name).into_bytes())));
+        f
+    }
+
+    /// The documents of collection `name`, its catalog entry and settings, and its
+    /// index definitions and entries
+    pub fn collection(self, name: &str) -> Self {
+        let mut f = self
+            .prefix(DATA_SPACE, format!("doc/{}/", name).as_bytes())
+            .prefix(CATALOG_SPACE, format!("docidx/{}/", name).as_bytes())
+            .prefix(DOC_INDEX_SPACE, format!("{}/", name).as_bytes());
+        for kind in COLLECTION_SETTINGS {
+            f.rules.push((CATALOG_SPACE.into(), Rule::Key(format!("{}/{}", kind, name).into_bytes())));
+        }
+        f
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Delete every key of `storage` the filter matches; returns how many
+    pub(crate) fn clear<S: Storage + ?Sized>(&self, storage: &S) -> Result<u64> {
+        let mut deleted = 0;
+        for (space, rule) in &self.rules {
+            let space = Space(space.clone());
+            let prefix = match rule {
+                Rule::Space => &[][..],
+                Rule::Prefix(p) => p.as_slice(),
+                Rule::Key(k) => {
+                    if storage.get(&space, k)?.is_some() {
+                        storage.del(&space, k)?;
+                        deleted += 1;
+                    }
+                    continue;
+                }
+            };
+            let mut after: Option<Vec<u8>> = None;
+            loop {
+                let batch = storage.scan_prefix_page(&space, prefix, after.as_deref(), CLEAR_BATCH)?;
+                for (k, _) in &batch {
+                    storage.del(&space, k)?;
+                    deleted += 1;
+                }
+                match batch.into_iter().last() {
+                    Some((k, _)) => after = Some(k),
+                    None => break,
+                }
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// Whether `key` of `space` is restored
+    pub fn matches(&self, space: &Space, key: &[u8]) -> bool {
+        self.rules.iter().any(|(s, rule)| {
+            *s == space.0
+                && match rule {
+                    Rule::Space => true,
+                    Rule::Prefix(p) => key.starts_with(p),
+                    Rule::Key(k) => key == k.as_slice(),
+                }
+        })
+    }
+}
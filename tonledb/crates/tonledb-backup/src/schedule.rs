@@ -0,0 +1,183 @@
+//! Scheduled backups: snapshots taken on a timer and old ones rotated out.
+//!
+//! `ScheduledBackups::spawn` runs `run_backup` on a dedicated thread, either every
+//! fixed interval or once a day at a set time (`Schedule::parse`). Each run takes a
+//! snapshot named `<prefix><epoch ms>` to the target, then deletes the scheduled
+//! backups `Retention` no longer keeps. Backups not named with the prefix, e.g.
+//! ones taken by hand, are never rotated. Outcomes are reported through the
+//! `tonledb_backup_*` and `tonledb_scheduled_backups_total` metrics.
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tonledb_core::{DbError, Result, Space, Storage};
+use crate::manifest::{self, SnapshotManifest};
+use crate::target::BackupTarget;
+
+const DAY_MS: u64 = 86_400_000;
+
+/// When scheduled backups run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Schedule {
+    /// Every interval, the first one interval after start
+    Every(Duration),
+    /// Once a day at `hour:minute` UTC
+    DailyAt { hour: u8, minute: u8 },
+}
+
+impl Schedule {
+    /// An interval (`30m`, `6h`, `1d`; units s, m, h, d, w) or `daily HH:MM` (UTC)
+    pub fn parse(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let invalid = || DbError::Invalid(format!("invalid backup schedule: {} (use e.g. 6h or daily 02:30)", s));
+        if let Some(at) = s.strip_prefix("daily ") {
+            let (h, m) = at.trim().split_once(':').ok_or_else(invalid)?;
+            let hour: u8 = h.parse().map_err(|_| invalid())?;
+            let minute: u8 = m.parse().map_err(|_| invalid())?;
+            if hour > 23 || minute > 59 {
+                return Err(invalid());
+            }
+            return Ok(Schedule::DailyAt { hour, minute });
+        }
+        let (n, unit) = s.split_at(s.len() - s.trim_start_matches(|c: char| c.is_ascii_digit()).len());
+        let n: u64 = n.parse().map_err(|_| invalid())?;
+        let unit_ms = match unit {
+            "s" => 1_000,
+            "m" => 60_000,
+            "h" => 3_600_000,
+            "d" => DAY_MS,
+            "w" => 7 * DAY_MS,
+            _ => return Err(invalid()),
+        };
+        if n == 0 {
+            return Err(invalid());
+        }
+        Ok(Schedule::Every(Duration::from_millis(n * unit_ms)))
+    }
+
+    /// Epoch ms of the next run after `now_ms`
+    pub fn next_after(&self, now_ms: u64) -> u64 {
+        match *self {
+            Schedule::Every(every) => now_ms + every.as_millis() as u64,
+            Schedule::DailyAt { hour, minute } => {
+                let today = now_ms - now_ms % DAY_MS + hour as u64 * 3_600_000 + minute as u64 * 60_000;
+                if today > now_ms { today } else { today + DAY_MS }
+            }
+        }
+    }
+}
+
+/// Which scheduled backups are kept. The newest one always is; of the others, a
+/// backup is deleted once it is past `max_age` or `keep` newer ones exist.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Retention {
+    pub keep: Option<usize>,
+    pub max_age: Option<Duration>,
+}
+
+/// What a scheduled backup covers and how it is named and rotated
+#[derive(Debug, Clone)]
+pub struct BackupJob {
+    pub spaces: Vec<Space>,
+    /// Start of every scheduled backup's name; only backups named with it are rotated
+    pub prefix: String,
+    pub retention: Retention,
+}
+
+/// Outcome of `run_backup`
+#[derive(Debug, Clone)]
+pub struct BackupRun {
+    pub manifest: SnapshotManifest,
+    /// Scheduled backups deleted by rotation, oldest first
+    pub rotated: Vec<String>,
+    /// Scheduled backups left in the target
+    pub retained: usize,
+}
+
+/// Take one scheduled backup of `storage` to `target` at `now_ms`, then rotate
+pub fn run_backup<S: Storage + ?Sized>(storage: &S, target: &dyn BackupTarget, job: &BackupJob, now_ms: u64) -> Result<BackupRun> {
+    let name = format!("{}{:013}", job.prefix, now_ms);
+    let manifest = crate::snapshot_to(storage, &job.spaces, &name, target, None)?;
+    let (rotated, retained) = rotate(target, &job.prefix, job.retention, now_ms / 1000)?;
+    Ok(BackupRun { manifest, rotated, retained })
+}
+
+/// Delete the backups in `target` named with `prefix` that `retention` no longer
+/// keeps at `now` (epoch seconds); returns those deleted, oldest first, and how many
+/// are left. A backup without a readable manifest is kept and counted.
+pub fn rotate(target: &dyn BackupTarget, prefix: &str, retention: Retention, now: u64) -> Result<(Vec<String>, usize)> {
+    let mut backups = Vec::new();
+    for name in target.list()?.into_iter().filter(|n| n.starts_with(prefix)) {
+        let created_at = manifest::read_manifest(target, &name).ok().flatten().map(|m| m.created_at);
+        backups.push((created_at, name));
+    }
+    // Newest first; unreadable manifests sort last and are never candidates
+    backups.sort_by(|a, b| b.cmp(a));
+    let mut rotated = Vec::new();
+    for (i, (created_at, name)) in backups.iter().enumerate().skip(1) {
+        let Some(created_at) = created_at else { continue };
+        let too_many = retention.keep.is_some_and(|keep| i >= keep);
+        let too_old = retention.max_age.is_some_and(|age| now.saturating_sub(*created_at) > age.as_secs());
+        if too_many || too_old {
+            target.delete(name)?;
+            rotated.push(name.clone());
+        }
+    }
+    rotated.reverse();
+    let kept = backups.len() - rotated.len();
+    Ok((rotated, kept))
+}
+
+/// Handle to a backup scheduler thread; the thread stops when the handle is dropped
+pub struct ScheduledBackups {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ScheduledBackups {
+    /// Run `job` against `storage` on `schedule`, on a dedicated thread. A failed
+    /// backup is counted, passed to `on_error` and retried at the next scheduled time.
+    pub fn spawn<F>(storage: Arc<dyn Storage>, target: Arc<dyn BackupTarget>, schedule: Schedule, job: BackupJob, on_error: F) -> Self
+    where
+        F: Fn(&DbError) + Send + 'static,
+    {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let stop2 = stop.clone();
+        let handle = std::thread::Builder::new()
+            .name("tonledb-backup-scheduler".into())
+            .spawn(move || loop {
+                let now = crate::now_ms();
+                let wait = Duration::from_millis(schedule.next_after(now) - now);
+                let (lock, cv) = &*stop2;
+                let stopped = cv.wait_timeout_while(lock.lock().unwrap(), wait, |s| !*s).unwrap().0;
+                if *stopped {
+                    return;
+                }
+                drop(stopped);
+                let started = Instant::now();
+                match run_backup(&*storage, &*target, &job, crate::now_ms()) {
+                    Ok(run) => {
+                        tonledb_metrics::observe_backup_ok(run.manifest.size, started.elapsed(), run.manifest.created_at);
+                        tonledb_metrics::set_backups_retained(run.retained as u64);
+                    }
+                    Err(e) => {
+                        tonledb_metrics::observe_backup_err();
+                        on_error(&e);
+                    }
+                }
+            })
+            .expect("spawn backup scheduler");
+        Self { stop, handle: Some(handle) }
+    }
+}
+
+impl Drop for ScheduledBackups {
+    fn drop(&mut self) {
+        let (lock, cv) = &*self.stop;
+        *lock.lock().unwrap() = true;
+        cv.notify_all();
+        if let Some(h) = self.handle.take() {
+            let _ = h.join();
+        }
+    }
+}
@@ -0,0 +1,96 @@
+//! Snapshot stream format.
+//!
+//! A snapshot is a zstd stream (with its content checksum) of `SNAPSHOT_MAGIC`, a
+//! big-endian `u16` version, then one frame per key: tag `1`, and the space, key
+//! and value, each as a `u32` BE length followed by the bytes. Tag `0` and the
+//! number of keys as a `u64` BE end the stream, so a truncated snapshot is told
+//! apart from a short one.
+
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use tonledb_core::{DbError, ReadView, Result, Space};
+
+const SNAPSHOT_MAGIC: &[u8; 8] = b"TLDBSNP\0";
+pub(crate) const SNAPSHOT_VERSION: u16 = 1;
+const ZSTD_LEVEL: i32 = 3;
+
+fn io_err(e: io::Error) -> DbError {
+    DbError::Storage(format!("snapshot stream: {}", e))
+}
+
+/// Keys per space
+pub(crate) type Counts = BTreeMap<String, u64>;
+
+/// Write every key of `spaces` in `view` to `out`; returns the keys per space
+pub(crate) fn write<W: Write>(view: &dyn ReadView, spaces: &[Space], out: W) -> Result<Counts> {
+    let mut enc = zstd::stream::write::Encoder::new(out, ZSTD_LEVEL).map_err(io_err)?;
+    enc.include_checksum(true).map_err(io_err)?;
+    enc.write_all(SNAPSHOT_MAGIC).map_err(io_err)?;
+    enc.write_all(&SNAPSHOT_VERSION.to_be_bytes()).map_err(io_err)?;
+    let mut counts = Counts::new();
+    for space in spaces {
+        let count = counts.entry(space.0.clone()).or_insert(0);
+        for (key, val) in view.scan_prefix(space, b"")? {
+            enc.write_all(&[1]).map_err(io_err)?;
+            for field in [space.0.as_bytes(), &key, &val] {
+                let len = u32::try_from(field.len()).map_err(|_| DbError::Invalid("snapshot entry over 4 GiB".into()))?;
+                enc.write_all(&len.to_be_bytes()).map_err(io_err)?;
+                enc.write_all(field).map_err(io_err)?;
+            }
+            *count += 1;
+        }
+    }
+    enc.write_all(&[0]).map_err(io_err)?;
+    enc.write_all(&counts.values().sum::<u64>().to_be_bytes()).map_err(io_err)?;
+    enc.finish().map_err(io_err)?;
+    Ok(counts)
+}
+
+/// Read a snapshot from `input`, calling `apply` for each key; returns the keys per space
+pub(crate) fn read<R, F>(input: R, mut apply: F) -> Result<Counts>
+where
+    R: Read,
+    F: FnMut(Space, Vec<u8>, Vec<u8>) -> Result<()>,
+{
+    let mut dec = zstd::stream::read::Decoder::new(input).map_err(io_err)?;
+    let mut header = [0u8; SNAPSHOT_MAGIC.len() + 2];
+    dec.read_exact(&mut header).map_err(io_err)?;
+    if !header.starts_with(SNAPSHOT_MAGIC) {
+        return Err(DbError::Corruption("not a TonleDB snapshot".into()));
+    }
+    let version = u16::from_be_bytes([header[SNAPSHOT_MAGIC.len()], header[SNAPSHOT_MAGIC.len() + 1]]);
+    if version != SNAPSHOT_VERSION {
+        return Err(DbError::Invalid(format!("unsupported snapshot version {}", version)));
+    }
+    let mut counts = Counts::new();
+    loop {
+        let mut tag = [0u8];
+        dec.read_exact(&mut tag).map_err(io_err)?;
+        match tag[0] {
+            0 => break,
+            1 => {
+                let space = String::from_utf8(field(&mut dec)?).map_err(|_| DbError::Corruption("snapshot space name".into()))?;
+                let key = field(&mut dec)?;
+                let val = field(&mut dec)?;
+                *counts.entry(space.clone()).or_insert(0) += 1;
+                apply(Space(space), key, val)?;
+            }
+            t => return Err(DbError::Corruption(format!("snapshot frame tag {}", t))),
+        }
+    }
+    let mut end = [0u8; 8];
+    dec.read_exact(&mut end).map_err(io_err)?;
+    let count: u64 = counts.values().sum();
+    if u64::from_be_bytes(end) != count {
+        return Err(DbError::Corruption(format!("snapshot holds {} keys, its trailer says {}", count, u64::from_be_bytes(end))));
+    }
+    Ok(counts)
+}
+
+fn field<R: Read>(input: &mut R) -> Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    input.read_exact(&mut len).map_err(io_err)?;
+    let mut buf = vec![0u8; u32::from_be_bytes(len) as usize];
+    input.read_exact(&mut buf).map_err(io_err)?;
+    Ok(buf)
+}
@@ -0,0 +1,369 @@
+//! Logical backups as SQL.
+//!
+//! `dump_sql` writes every table in the catalog as a script that recreates it: a
+//! `CREATE TABLE` with the column types and the `PRIMARY KEY`, `NOT NULL`,
+//! `UNIQUE`, `REFERENCES` and `CHECK` constraints, one `CREATE INDEX` per secondary
+//! index, and the rows as `INSERT` statements of up to `INSERT_BATCH_ROWS` rows
+//! each. Rows are read from one snapshot, so the dump is consistent. Identifiers are
+//! double-quoted; JSON columns are written as JSON text in string literals and
+//! numbers exactly as stored.
+//!
+//! `restore_sql` reads such a dump back, one statement at a time: tables are added
+//! to the catalog (failing if one already exists), indexes created and filled, and
+//! rows written under their primary key, or under a generated key for tables
+//! without one. It runs `CREATE TABLE`, `CREATE INDEX` and `INSERT ... VALUES`
+//! only, so it is not a general SQL script runner.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use serde_json::Value as Json;
+use sqlparser::ast::{self, Expr, Statement};
+use sqlparser::{dialect::GenericDialect, parser::Parser};
+use tonledb_core::numbers::{self, NumberMode};
+use tonledb_core::{Column, ColumnConstraint, DataType, Db, DbError, IndexType, Result, TableConstraint, TableSchema};
+use tonledb_sql::rows::{self, data_space, RowWriter};
+
+/// Rows per `INSERT` statement
+pub const INSERT_BATCH_ROWS: usize = 100;
+
+/// What `dump_sql` wrote
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DumpReport {
+    pub tables: usize,
+    pub indexes: usize,
+    pub rows: u64,
+}
+
+fn io_err(e: std::io::Error) -> DbError {
+    DbError::Storage(format!("SQL dump: {}", e))
+}
+
+/// What `restore_sql` created
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RestoreSqlReport {
+    pub tables: usize,
+    pub indexes: usize,
+    pub rows: u64,
+}
+
+/// Write every table of `db` to `path` as SQL (see the module docs)
+pub fn dump_sql(db: &Db, path: &Path) -> Result<DumpReport> {
+    let (tables, mut indexes) = {
+        let catalog = db.catalog.read();
+        (catalog.tables.values().cloned().collect::<Vec<_>>(), catalog.indexes.values().cloned().collect::<Vec<_>>())
+    };
+    indexes.sort_by(|a, b| (&a.table, &a.column).cmp(&(&b.table, &b.column)));
+    let view = db.storage.snapshot()?;
+    let mut out = BufWriter::new(File::create(path).map_err(io_err)?);
+    let mut report = DumpReport::default();
+    writeln!(out, "-- TonleDB SQL dump").map_err(io_err)?;
+    for table in &tables {
+        writeln!(out, "\n{};", create_table(table)).map_err(io_err)?;
+        for index in indexes.iter().filter(|i| i.table == table.name) {
+            let name = quote_ident(&format!("{}_{}_idx", index.table, index.column));
+            let unique = if index.is_unique { "UNIQUE " } else { "" };
+            let using = if index.index_type == IndexType::Hash { " USING HASH" } else { "" };
+            writeln!(out, "CREATE {}INDEX {} ON {}{} ({});", unique, name, quote_ident(&index.table), using, quote_ident(&index.column)).map_err(io_err)?;
+            report.indexes += 1;
+        }
+        let columns: Vec<String> = table.columns.iter().map(|c| quote_ident(&c.name)).collect();
+        let insert = format!("INSERT INTO {} ({}) VALUES", quote_ident(&table.name), columns.join(", "));
+        let mut batch = Vec::with_capacity(INSERT_BATCH_ROWS);
+        for (_, v) in view.scan_prefix(&data_space(), rows::table_prefix(&table.name).as_bytes())? {
+            let row: Json = serde_json::from_slice(&v).map_err(|e| DbError::Corruption(format!("row of table {}: {}", table.name, e)))?;
+            let values: Vec<String> = table.columns.iter().map(|c| literal(row.get(&c.name).unwrap_or(&Json::Null), &c.data_type)).collect();
+            batch.push(format!("({})", values.join(", ")));
+            report.rows += 1;
+            if batch.len() == INSERT_BATCH_ROWS {
+                writeln!(out, "{}\n  {};", insert, batch.join(",\n  ")).map_err(io_err)?;
+                batch.clear();
+            }
+        }
+        if !batch.is_empty() {
+            writeln!(out, "{}\n  {};", insert, batch.join(",\n  ")).map_err(io_err)?;
+        }
+        report.tables += 1;
+    }
+    out.into_inner().map_err(|e| io_err(e.into_error()))?.sync_all().map_err(io_err)?;
+    Ok(report)
+}
+
+fn create_table(table: &TableSchema) -> String {
+    let mut defs: Vec<String> = table
+        .columns
+        .iter()
+        .map(|c| {
+            let mut def = format!("{} {}", quote_ident(&c.name), type_name(&c.data_type));
+            // The table's `pk` counts even if the column does not carry the constraint
+            if table.pk.as_ref() == Some(&c.name) || c.constraints.contains(&ColumnConstraint::PrimaryKey) {
+                def.push_str(" PRIMARY KEY");
+            }
+            for constraint in &c.constraints {
+                match constraint {
+                    ColumnConstraint::NotNull => def.push_str(" NOT NULL"),
+                    ColumnConstraint::Unique => def.push_str(" UNIQUE"),
+                    ColumnConstraint::PrimaryKey => {}
+                    ColumnConstraint::ForeignKey { table, column } => def.push_str(&format!(" REFERENCES {} ({})", quote_ident(table), quote_ident(column))),
+                    ColumnConstraint::Check(expr) => def.push_str(&format!(" CHECK ({})", expr)),
+                }
+            }
+            def
+        })
+        .collect();
+    for constraint in &table.constraints {
+        defs.push(match constraint {
+            TableConstraint::Unique { columns } => format!("UNIQUE ({})", quote_list(columns)),
+            TableConstraint::ForeignKey { columns, ref_table, ref_columns } => {
+                format!("FOREIGN KEY ({}) REFERENCES {} ({})", quote_list(columns), quote_ident(ref_table), quote_list(ref_columns))
+            }
+            TableConstraint::Check(expr) => format!("CHECK ({})", expr),
+        });
+    }
+    format!("CREATE TABLE {} (\n  {}\n)", quote_ident(&table.name), defs.join(",\n  "))
+}
+
+fn type_name(t: &DataType) -> &'static str {
+    match t {
+        DataType::Integer => "BIGINT",
+        DataType::Float => "DOUBLE PRECISION",
+        DataType::Text => "TEXT",
+        DataType::Boolean => "BOOLEAN",
+        DataType::Json => "JSON",
+        DataType::Decimal => "DECIMAL",
+    }
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn quote_list(names: &[String]) -> String {
+    names.iter().map(|n| quote_ident(n)).collect::<Vec<_>>().join(", ")
+}
+
+fn quote_str(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+/// `v` as a literal for a column of type `t`
+fn literal(v: &Json, t: &DataType) -> String {
+    match v {
+        Json::Null => "NULL".into(),
+        // Kept as JSON text, so a string value reads back as a string
+        _ if *t == DataType::Json => quote_str(&v.to_string()),
+        Json::Bool(b) => if *b { "TRUE" } else { "FALSE" }.into(),
+        Json::Number(n) => n.to_string(),
+        Json::String(s) => quote_str(s),
+        Json::Array(_) | Json::Object(_) => quote_str(&v.to_string()),
+    }
+}
+
+/// Run the dump at `path`, as written by `dump_sql`, against `db` (see the module docs)
+pub fn restore_sql(db: &Db, path: &Path) -> Result<RestoreSqlReport> {
+    let mut statements = Statements { input: BufReader::new(File::open(path).map_err(io_err)?), line: String::new(), pos: 0 };
+    let mut restore = SqlRestore { db, report: RestoreSqlReport::default(), rows: RowWriter::new(db) };
+    while let Some(sql) = statements.next()? {
+        for stmt in Parser::parse_sql(&GenericDialect, &sql).map_err(|e| DbError::Invalid(format!("SQL dump: {}", e)))? {
+            restore.run(&stmt)?;
+        }
+    }
+    Ok(restore.report)
+}
+
+/// Statements of a dump, each up to its `;`, with `--` comment lines skipped
+struct Statements<R> {
+    input: R,
+    line: String,
+    /// How much of `line` has been read
+    pos: usize,
+}
+
+impl<R: BufRead> Statements<R> {
+    /// The next statement; `None` at the end of the dump
+    fn next(&mut self) -> Result<Option<String>> {
+        let mut sql = String::new();
+        let mut quote = None;
+        loop {
+            if self.pos >= self.line.len() {
+                self.line.clear();
+                self.pos = 0;
+                if self.input.read_line(&mut self.line).map_err(io_err)? == 0 {
+                    return if sql.trim().is_empty() { Ok(None) } else { Err(DbError::Invalid("SQL dump ends inside a statement".into())) };
+                }
+                if quote.is_none() && self.line.trim_start().starts_with("--") {
+                    self.pos = self.line.len();
+                    continue;
+                }
+            }
+            for (i, c) in self.line[self.pos..].char_indices() {
+                match (quote, c) {
+                    (None, '\'' | '"') => quote = Some(c),
+                    // A doubled quote closes and reopens, which reads the same
+                    (Some(q), c) if c == q => quote = None,
+                    (None, ';') => {
+                        self.pos += i + 1;
+                        return Ok(Some(sql));
+                    }
+                    _ => {}
+                }
+                sql.push(c);
+            }
+            self.pos = self.line.len();
+        }
+    }
+}
+
+struct SqlRestore<'a> {
+    db: &'a Db,
+    report: RestoreSqlReport,
+    rows: RowWriter<'a>,
+}
+
+impl SqlRestore<'_> {
+    fn run(&mut self, stmt: &Statement) -> Result<()> {
+        match stmt {
+            Statement::CreateTable { name, columns, constraints, .. } => self.create_table(&ident_name(name), columns, constraints),
+            Statement::CreateIndex { table_name, columns, unique, using, .. } => {
+                let [column] = columns.as_slice() else { return Err(DbError::Invalid("SQL dump: indexes cover one column".into())) };
+                let Expr::Identifier(column) = &column.expr else { return Err(DbError::Invalid("SQL dump: indexes cover one column".into())) };
+                let index_type = match using.as_ref().map(|u| u.value.to_ascii_lowercase()).as_deref() {
+                    None | Some("btree") => IndexType::BTree,
+                    Some("hash") => IndexType::Hash,
+                    Some(other) => return Err(DbError::Invalid(format!("SQL dump: unknown index type {}", other))),
+                };
+                let table = ident_name(table_name);
+                self.db.create_index(&table, &column.value, index_type, *unique)?;
+                // Rows inserted before the index was created
+                for (key, row) in self.db.storage.scan_prefix(&data_space(), rows::table_prefix(&table).as_bytes())? {
+                    let row: Json = serde_json::from_slice(&row).map_err(|e| DbError::Corruption(format!("row of table {}: {}", table, e)))?;
+                    self.rows.index_row(&table, &column.value, &key, &row)?;
+                }
+                self.report.indexes += 1;
+                Ok(())
+            }
+            Statement::Insert(insert) => self.insert(insert),
+            other => Err(DbError::Invalid(format!("SQL dump: unsupported statement {}", other))),
+        }
+    }
+
+    fn create_table(&mut self, name: &str, defs: &[ast::ColumnDef], constraints: &[ast::TableConstraint]) -> Result<()> {
+        if self.db.catalog.read().tables.contains_key(name) {
+            return Err(DbError::Conflict(format!("table {} already exists", name)));
+        }
+        let mut schema = TableSchema { name: name.to_string(), columns: Vec::with_capacity(defs.len()), pk: None, constraints: Vec::new() };
+        for def in defs {
+            let mut column = Column { name: def.name.value.clone(), data_type: data_type(&def.data_type)?, constraints: Vec::new() };
+            for opt in &def.options {
+                match &opt.option {
+                    ast::ColumnOption::NotNull => column.constraints.push(ColumnConstraint::NotNull),
+                    ast::ColumnOption::Unique { is_primary: true, .. } => {
+                        schema.pk = Some(column.name.clone());
+                        column.constraints.push(ColumnConstraint::PrimaryKey);
+                    }
+                    ast::ColumnOption::Unique { is_primary: false, .. } => column.constraints.push(ColumnConstraint::Unique),
+                    ast::ColumnOption::ForeignKey { foreign_table, referred_columns, .. } => {
+                        let Some(col) = referred_columns.first() else { return Err(DbError::Invalid(format!("SQL dump: reference of {}.{} names no column", name, column.name))) };
+                        column.constraints.push(ColumnConstraint::ForeignKey { table: ident_name(foreign_table), column: col.value.clone() });
+                    }
+                    ast::ColumnOption::Check(expr) => column.constraints.push(ColumnConstraint::Check(expr.to_string())),
+                    ast::ColumnOption::Null => {}
+                    other => return Err(DbError::Invalid(format!("SQL dump: unsupported column option {}", other))),
+                }
+            }
+            schema.columns.push(column);
+        }
+        let names = |idents: &[ast::Ident]| idents.iter().map(|i| i.value.clone()).collect::<Vec<_>>();
+        for constraint in constraints {
+            match constraint {
+                ast::TableConstraint::Unique { columns, .. } => schema.constraints.push(TableConstraint::Unique { columns: names(columns) }),
+                ast::TableConstraint::PrimaryKey { columns, .. } => match columns.as_slice() {
+                    [pk] => schema.pk = Some(pk.value.clone()),
+                    _ => return Err(DbError::Invalid(format!("SQL dump: table {} has a multi-column primary key", name))),
+                },
+                ast::TableConstraint::ForeignKey { columns, foreign_table, referred_columns, .. } => schema.constraints.push(TableConstraint::ForeignKey {
+                    columns: names(columns),
+                    ref_table: ident_name(foreign_table),
+                    ref_columns: names(referred_columns),
+                }),
+                ast::TableConstraint::Check { expr, .. } => schema.constraints.push(TableConstraint::Check(expr.to_string())),
+                other => return Err(DbError::Invalid(format!("SQL dump: unsupported table constraint {}", other))),
+            }
+        }
+        if let Some(pk) = schema.pk.as_ref().filter(|pk| !schema.columns.iter().any(|c| c.name == **pk)) {
+            return Err(DbError::Invalid(format!("SQL dump: primary key {} of table {} is not a column", pk, name)));
+        }
+        self.db.catalog.write().tables.insert(name.to_string(), schema);
+        self.report.tables += 1;
+        Ok(())
+    }
+
+    fn insert(&mut self, insert: &ast::Insert) -> Result<()> {
+        let table = ident_name(&insert.table_name);
+        let Some(schema) = self.db.catalog.read().tables.get(&table).cloned() else {
+            return Err(DbError::NotFound(format!("table {}", table)));
+        };
+        let Some(ast::SetExpr::Values(values)) = insert.source.as_ref().map(|q| &*q.body) else {
+            return Err(DbError::Invalid(format!("SQL dump: INSERT into {} without VALUES", table)));
+        };
+        let columns: Vec<&Column> = if insert.columns.is_empty() {
+            schema.columns.iter().collect()
+        } else {
+            insert
+                .columns
+                .iter()
+                .map(|i| schema.columns.iter().find(|c| c.name == i.value).ok_or_else(|| DbError::NotFound(format!("column {} of table {}", i.value, table))))
+                .collect::<Result<_>>()?
+        };
+        let indexed = rows::indexed_columns(self.db, &table);
+        for exprs in &values.rows {
+            if exprs.len() != columns.len() {
+                return Err(DbError::Invalid(format!("SQL dump: row of {} has {} values for {} columns", table, exprs.len(), columns.len())));
+            }
+            let mut row = serde_json::Map::new();
+            for (column, expr) in columns.iter().zip(exprs) {
+                row.insert(column.name.clone(), value(expr, &column.data_type)?);
+            }
+            self.rows.insert(&schema, &indexed, &Json::Object(row))?;
+            self.report.rows += 1;
+        }
+        Ok(())
+    }
+}
+
+fn ident_name(name: &ast::ObjectName) -> String {
+    name.0.iter().map(|i| i.value.as_str()).collect::<Vec<_>>().join(".")
+}
+
+fn data_type(t: &ast::DataType) -> Result<DataType> {
+    use ast::DataType as T;
+    Ok(match t {
+        T::Int(_) | T::Integer(_) | T::BigInt(_) | T::Int64 => DataType::Integer,
+        T::Float(_) | T::Real | T::Double | T::DoublePrecision | T::Float64 => DataType::Float,
+        T::Text | T::Varchar(_) | T::String(_) | T::Char(_) => DataType::Text,
+        T::Boolean | T::Bool => DataType::Boolean,
+        T::JSON | T::JSONB => DataType::Json,
+        T::Decimal(_) | T::Numeric(_) => DataType::Decimal,
+        other => return Err(DbError::Invalid(format!("SQL dump: unsupported column type {}", other))),
+    })
+}
+
+/// The literal `expr` as stored in a column of type `t`
+fn value(expr: &Expr, t: &DataType) -> Result<Json> {
+    let mode = if *t == DataType::Decimal { NumberMode::Decimal } else { numbers::number_mode() };
+    let number = |n: &str| numbers::number_literal(n, mode).ok_or_else(|| DbError::Invalid(format!("SQL dump: bad number {}", n)));
+    match expr {
+        Expr::Value(ast::Value::Null) => Ok(Json::Null),
+        Expr::Value(ast::Value::Boolean(b)) => Ok(Json::Bool(*b)),
+        Expr::Value(ast::Value::Number(n, _)) => number(n),
+        Expr::UnaryOp { op: ast::UnaryOperator::Minus, expr } => match &**expr {
+            Expr::Value(ast::Value::Number(n, _)) => number(&format!("-{}", n)),
+            _ => Err(DbError::Invalid(format!("SQL dump: unsupported value {}", expr))),
+        },
+        Expr::Value(ast::Value::SingleQuotedString(s)) if *t == DataType::Json => {
+            serde_json::from_str(s).map_err(|e| DbError::Invalid(format!("SQL dump: bad JSON value: {}", e)))
+        }
+        Expr::Value(ast::Value::SingleQuotedString(s)) => Ok(Json::String(s.clone())),
+        other => Err(DbError::Invalid(format!("SQL dump: unsupported value {}", other))),
+    }
+}
@@ -0,0 +1,100 @@
+//! Tables as Parquet files, for analytics tooling to read directly.
+//!
+//! `export_table_parquet` writes the rows of a table from one snapshot, one column
+//! per catalog column typed as `tonledb_arrow::arrow_type` gives it, encoding
+//! `EXPORT_BATCH_ROWS` rows at a time and streaming them out in row groups of
+//! `tonledb_arrow::stream::DEFAULT_ROW_GROUP_ROWS`. `import_table_parquet` reads
+//! such a file, or one written by other tools, into an existing table: columns are
+//! matched by name and rows are written under their primary key (or a generated
+//! key) with the table's indexes kept up to date, a batch of rows at a time in one
+//! `Storage::commit_writes`. A row whose primary key is taken fails the import. `import_parquet` does the same but creates the table
+//! from the file's schema if it doesn't exist yet, and drops it again if the import
+//! fails.
+
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use serde_json::Value as Json;
+use tonledb_arrow::stream;
+use tonledb_core::{Db, DbError, Result, TableSchema};
+use tonledb_sql::bulk::BulkInsert;
+use tonledb_sql::rows::{data_space, table_prefix};
+
+/// Rows held in memory and converted at a time by an export or import
+pub const EXPORT_BATCH_ROWS: usize = 8192;
+
+fn parquet_err(e: impl std::fmt::Display) -> DbError {
+    DbError::Storage(format!("Parquet: {}", e))
+}
+
+fn table_schema(db: &Db, table: &str) -> Result<TableSchema> {
+    db.catalog.read().tables.get(table).cloned().ok_or_else(|| DbError::NotFound(format!("table {}", table)))
+}
+
+/// Write the rows of `table` to a Parquet file at `path`; returns how many were written
+pub fn export_table_parquet(db: &Db, table: &str, path: &Path) -> Result<u64> {
+    let schema = table_schema(db, table)?;
+    let view = db.storage.snapshot()?;
+    let mut writer = stream::create_parquet_file(path, Arc::new(tonledb_arrow::table_schema(&schema)), stream::DEFAULT_ROW_GROUP_ROWS)?;
+    let mut batch = Vec::with_capacity(EXPORT_BATCH_ROWS);
+    for (_, v) in view.scan_prefix(&data_space(), table_prefix(table).as_bytes())? {
+        batch.push(serde_json::from_slice::<Json>(&v).map_err(|e| DbError::Corruption(format!("row of table {}: {}", table, e)))?);
+        if batch.len() == EXPORT_BATCH_ROWS {
+            writer.write(&tonledb_arrow::rows_to_record_batch(&schema, &batch)?)?;
+            batch.clear();
+        }
+    }
+    if !batch.is_empty() {
+        writer.write(&tonledb_arrow::rows_to_record_batch(&schema, &batch)?)?;
+    }
+    stream::finish_parquet_file(writer)
+}
+
+/// Write the rows of the Parquet file at `path` into `table`, which must exist;
+/// returns how many were written. Rows with the primary key of a stored row replace
+/// it. Fails with `DbError::Invalid` if the file has a column the table doesn't.
+pub fn import_table_parquet(db: &Db, table: &str, path: &Path) -> Result<u64> {
+    let schema = table_schema(db, table)?;
+    write_rows(db, &schema, open(path)?)
+}
+
+/// Like `import_table_parquet`, but if `table` doesn't exist it is created first
+/// from the file's schema (see `tonledb_arrow::table_from_schema`), and dropped with
+/// the rows already written if the import fails. Returns how many rows were written.
+pub fn import_parquet(db: &Db, table: &str, path: &Path) -> Result<u64> {
+    let reader = open(path)?;
+    let mut catalog = db.catalog.write();
+    let (schema, created) = match catalog.tables.get(table) {
+        Some(schema) => (schema.clone(), false),
+        None => {
+            let schema = tonledb_arrow::table_from_schema(table, reader.schema())?;
+            catalog.tables.insert(table.to_string(), schema.clone());
+            (schema, true)
+        }
+    };
+    drop(catalog);
+    let written = write_rows(db, &schema, reader);
+    if created && written.is_err() {
+        let _ = db.drop_table(table);
+    }
+    written
+}
+
+fn open(path: &Path) -> Result<ParquetRecordBatchReaderBuilder<File>> {
+    let file = File::open(path).map_err(parquet_err)?;
+    ParquetRecordBatchReaderBuilder::try_new(file).map_err(parquet_err)
+}
+
+/// Write every row `reader` reads into `table`, each batch of `EXPORT_BATCH_ROWS`
+/// rows (and their index entries) committed together
+fn write_rows(db: &Db, table: &TableSchema, reader: ParquetRecordBatchReaderBuilder<File>) -> Result<u64> {
+    let reader = reader.with_batch_size(EXPORT_BATCH_ROWS).build().map_err(parquet_err)?;
+    let mut bulk = BulkInsert::new(db, table.clone());
+    for batch in reader {
+        for row in tonledb_arrow::record_batch_to_rows(table, &batch.map_err(parquet_err)?)? {
+            bulk.push(&row)?;
+        }
+    }
+    bulk.finish()
+}
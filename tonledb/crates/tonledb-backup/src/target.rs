@@ -0,0 +1,395 @@
+//! Where snapshots are written to and read back from.
+//!
+//! A `BackupTarget` stores named backups as byte streams, so `PITRManager::snapshot`
+//! and `PITRManager::restore` never need the whole backup in memory or on local
+//! disk. `LocalTarget` keeps each backup as one file in a directory.
+//! `ObjectStoreTarget` puts it in any `ObjectStore`, S3-compatible buckets included
+//! (`ObjectStoreTarget::s3`, with the `s3` feature): the stream is cut into parts of
+//! `part_size` bytes, uploaded as they fill up, and an index of the parts is
+//! written last, so a backup whose upload was cut short is never listed or read.
+//! The parts of each upload are named apart, so until the new index is in place a
+//! rewrite leaves the old backup whole and readable.
+//!
+//! Writers report the files they stored with their SHA-256, and targets keep a
+//! small manifest beside each backup (see `crate::manifest`).
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use sha2::{Digest, Sha256};
+use tonledb_core::object_store::ObjectStore;
+use tonledb_core::{DbError, Result};
+
+/// Prefix of snapshot objects in an object store
+const SNAPSHOT_PREFIX: &str = "snapshots/";
+const PARTS: &str = "parts";
+const MANIFEST: &str = "manifest.json";
+/// Default part size of `ObjectStoreTarget`
+pub const DEFAULT_PART_SIZE: usize = 8 << 20;
+
+/// One file of a stored backup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredFile {
+    /// Path within the backup, for `BackupTarget::file_reader`
+    pub path: String,
+    pub size: u64,
+    /// Hex SHA-256 of the file
+    pub sha256: String,
+}
+
+/// Stream being written to a target. Nothing is visible under its name until
+/// `finish` returns the files it stored; a writer dropped without finishing leaves
+/// no backup behind.
+pub trait BackupWriter: Write + Send {
+    fn finish(self: Box<Self>) -> Result<Vec<StoredFile>>;
+}
+
+/// Named backup streams
+pub trait BackupTarget: Send + Sync {
+    /// Start writing backup `name`, replacing any backup of that name once finished
+    fn writer(&self, name: &str) -> Result<Box<dyn BackupWriter>>;
+    /// Read backup `name` back; `None` if there is no such backup
+    fn reader(&self, name: &str) -> Result<Option<Box<dyn Read + Send>>>;
+    /// Names of the finished backups, sorted
+    fn list(&self) -> Result<Vec<String>>;
+    /// Delete backup `name` and its manifest
+    fn delete(&self, name: &str) -> Result<()>;
+    /// Store the manifest of backup `name`, replacing any earlier one
+    fn put_manifest(&self, name: &str, data: Vec<u8>) -> Result<()>;
+    /// Manifest of backup `name`; `None` if it has none
+    fn manifest(&self, name: &str) -> Result<Option<Vec<u8>>>;
+    /// Read one file of backup `name`, as listed by its writer's `finish`
+    fn file_reader(&self, name: &str, path: &str) -> Result<Option<Box<dyn Read + Send>>>;
+}
+
+fn check_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.contains('/') || name.contains('\\') || name.starts_with('.') {
+        return Err(DbError::Invalid(format!("invalid backup name: {:?}", name)));
+    }
+    Ok(())
+}
+
+fn io_err(e: io::Error) -> DbError {
+    DbError::Storage(e.to_string())
+}
+
+// ---------- local directory ----------
+
+/// Backups as files in a local directory
+pub struct LocalTarget {
+    dir: PathBuf,
+}
+
+impl LocalTarget {
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).map_err(|e| DbError::Storage(format!("backup directory: {}", e)))?;
+        Ok(Self { dir })
+    }
+}
+
+struct LocalWriter {
+    file: BufWriter<File>,
+    tmp: PathBuf,
+    name: String,
+    path: PathBuf,
+    sha: Sha256,
+    size: u64,
+}
+
+impl Write for LocalWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.file.write(buf)?;
+        self.sha.update(&buf[..n]);
+        self.size += n as u64;
+        Ok(n)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl BackupWriter for LocalWriter {
+    fn finish(self: Box<Self>) -> Result<Vec<StoredFile>> {
+        let file = self.file.into_inner().map_err(|e| io_err(e.into_error()))?;
+        file.sync_all().map_err(io_err)?;
+        fs::rename(&self.tmp, &self.path).map_err(io_err)?;
+        Ok(vec![StoredFile { path: self.name, size: self.size, sha256: hex::encode(self.sha.finalize()) }])
+    }
+}
+
+impl BackupTarget for LocalTarget {
+    fn writer(&self, name: &str) -> Result<Box<dyn BackupWriter>> {
+        check_name(name)?;
+        // Written beside the backup and renamed into place
+        let tmp = self.dir.join(format!(".{}.part", name));
+        let file = File::create(&tmp).map_err(io_err)?;
+        Ok(Box::new(LocalWriter {
+            file: BufWriter::new(file),
+            tmp,
+            name: name.to_string(),
+            path: self.dir.join(name),
+            sha: Sha256::new(),
+            size: 0,
+        }))
+    }
+
+    fn reader(&self, name: &str) -> Result<Option<Box<dyn Read + Send>>> {
+        check_name(name)?;
+        match File::open(self.dir.join(name)) {
+            Ok(f) => Ok(Some(Box::new(io::BufReader::new(f)))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(io_err(e)),
+        }
+    }
+
+    fn list(&self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&self.dir).map_err(io_err)? {
+            let entry = entry.map_err(io_err)?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.starts_with('.') && entry.path().is_file() {
+                names.push(name);
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn delete(&self, name: &str) -> Result<()> {
+        check_name(name)?;
+        for path in [self.dir.join(name), self.manifest_path(name)] {
+            match fs::remove_file(path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(io_err(e)),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn put_manifest(&self, name: &str, data: Vec<u8>) -> Result<()> {
+        check_name(name)?;
+        let tmp = self.dir.join(format!(".{}.manifest.part", name));
+        fs::write(&tmp, data).map_err(io_err)?;
+        fs::rename(&tmp, self.manifest_path(name)).map_err(io_err)
+    }
+
+    fn manifest(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        check_name(name)?;
+        match fs::read(self.manifest_path(name)) {
+            Ok(b) => Ok(Some(b)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(io_err(e)),
+        }
+    }
+
+    fn file_reader(&self, name: &str, path: &str) -> Result<Option<Box<dyn Read + Send>>> {
+        // A local backup is the one file
+        if path != name {
+            return Ok(None);
+        }
+        self.reader(name)
+    }
+}
+
+impl LocalTarget {
+    /// Hidden, so `list` skips it
+    fn manifest_path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!(".{}.manifest.json", name))
+    }
+}
+
+// ---------- object store ----------
+
+/// Backups in an object store, as `snapshots/<name>/part-<n>.<upload>`,
+/// `snapshots/<name>/parts` (the number of parts and the upload they belong to)
+/// and `snapshots/<name>/manifest.json`
+pub struct ObjectStoreTarget {
+    store: Arc<dyn ObjectStore>,
+    part_size: usize,
+}
+
+impl ObjectStoreTarget {
+    pub fn new(store: Arc<dyn ObjectStore>) -> Self {
+        Self { store, part_size: DEFAULT_PART_SIZE }
+    }
+
+    /// Target in an S3 or S3-compatible bucket
+    #[cfg(feature = "s3")]
+    pub fn s3(cfg: tonledb_core::object_store::S3Config) -> Result<Self> {
+        Ok(Self::new(Arc::new(tonledb_core::object_store::S3ObjectStore::new(cfg)?)))
+    }
+
+    /// Bytes per uploaded part; the most a writer holds in memory
+    pub fn with_part_size(mut self, bytes: usize) -> Self {
+        self.part_size = bytes.max(1);
+        self
+    }
+
+    /// Number of parts of backup `name` and the upload they belong to
+    fn parts(&self, name: &str) -> Result<Option<(u64, String)>> {
+        let Some(index) = self.store.get(&object(name, PARTS))? else { return Ok(None) };
+        let parts = std::str::from_utf8(&index).ok()
+            .and_then(|s| s.trim().split_once(' '))
+            .and_then(|(n, upload)| Some((n.parse().ok()?, upload.to_string())))
+            .ok_or_else(|| DbError::Corruption(format!("part index of backup {}", name)))?;
+        Ok(Some(parts))
+    }
+}
+
+fn object(name: &str, file: &str) -> String {
+    format!("{}{}/{}", SNAPSHOT_PREFIX, name, file)
+}
+
+fn part(name: &str, upload: &str, n: u64) -> String {
+    object(name, &part_file(upload, n))
+}
+
+fn part_file(upload: &str, n: u64) -> String {
+    format!("part-{:06}.{}", n, upload)
+}
+
+/// Name of a new upload: the time it started, in hex nanoseconds
+fn new_upload() -> String {
+    let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    format!("{:016x}", nanos as u64)
+}
+
+struct ObjectWriter {
+    store: Arc<dyn ObjectStore>,
+    name: String,
+    upload: String,
+    buf: Vec<u8>,
+    part_size: usize,
+    stored: Vec<StoredFile>,
+}
+
+impl ObjectWriter {
+    fn upload(&mut self) -> Result<()> {
+        let data = std::mem::replace(&mut self.buf, Vec::with_capacity(self.part_size));
+        let n = self.stored.len() as u64;
+        let file = StoredFile { path: part_file(&self.upload, n), size: data.len() as u64, sha256: hex::encode(Sha256::digest(&data)) };
+        self.store.put(&part(&self.name, &self.upload, n), data)?;
+        self.stored.push(file);
+        Ok(())
+    }
+}
+
+impl Write for ObjectWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.part_size - self.buf.len());
+        self.buf.extend_from_slice(&buf[..n]);
+        if self.buf.len() == self.part_size {
+            self.upload().map_err(io::Error::other)?;
+        }
+        Ok(n)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl BackupWriter for ObjectWriter {
+    fn finish(mut self: Box<Self>) -> Result<Vec<StoredFile>> {
+        if !self.buf.is_empty() {
+            self.upload()?;
+        }
+        self.store.put(&object(&self.name, PARTS), format!("{} {}", self.stored.len(), self.upload).into_bytes())?;
+        // Parts of the backup this one replaced, and of uploads cut short; what
+        // can't be deleted now goes with the next rewrite or `delete`
+        let ours = format!(".{}", self.upload);
+        if let Ok(paths) = self.store.list(&object(&self.name, "part-")) {
+            for path in paths.iter().filter(|p| !p.ends_with(&ours)) {
+                let _ = self.store.delete(path);
+            }
+        }
+        Ok(self.stored)
+    }
+}
+
+struct ObjectReader {
+    store: Arc<dyn ObjectStore>,
+    name: String,
+    upload: String,
+    parts: u64,
+    next: u64,
+    cur: io::Cursor<Vec<u8>>,
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.cur.read(buf)?;
+            if n > 0 || buf.is_empty() || self.next == self.parts {
+                return Ok(n);
+            }
+            let data = self.store.get(&part(&self.name, &self.upload, self.next)).map_err(io::Error::other)?
+                .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, format!("part {} of backup {} is missing", self.next, self.name)))?;
+            self.cur = io::Cursor::new(data);
+            self.next += 1;
+        }
+    }
+}
+
+impl BackupTarget for ObjectStoreTarget {
+    fn writer(&self, name: &str) -> Result<Box<dyn BackupWriter>> {
+        check_name(name)?;
+        Ok(Box::new(ObjectWriter {
+            store: self.store.clone(),
+            name: name.to_string(),
+            upload: new_upload(),
+            buf: Vec::with_capacity(self.part_size),
+            part_size: self.part_size,
+            stored: Vec::new(),
+        }))
+    }
+
+    fn reader(&self, name: &str) -> Result<Option<Box<dyn Read + Send>>> {
+        check_name(name)?;
+        let Some((parts, upload)) = self.parts(name)? else { return Ok(None) };
+        Ok(Some(Box::new(ObjectReader {
+            store: self.store.clone(),
+            name: name.to_string(),
+            upload,
+            parts,
+            next: 0,
+            cur: io::Cursor::new(Vec::new()),
+        })))
+    }
+
+    fn list(&self) -> Result<Vec<String>> {
+        let suffix = format!("/{}", PARTS);
+        Ok(self.store.list(SNAPSHOT_PREFIX)?
+            .into_iter()
+            .filter_map(|p| p.strip_prefix(SNAPSHOT_PREFIX)?.strip_suffix(&suffix).map(str::to_string))
+            .collect())
+    }
+
+    fn delete(&self, name: &str) -> Result<()> {
+        check_name(name)?;
+        self.store.delete(&object(name, PARTS))?;
+        for path in self.store.list(&format!("{}{}/", SNAPSHOT_PREFIX, name))? {
+            self.store.delete(&path)?;
+        }
+        Ok(())
+    }
+
+    fn put_manifest(&self, name: &str, data: Vec<u8>) -> Result<()> {
+        check_name(name)?;
+        self.store.put(&object(name, MANIFEST), data)
+    }
+
+    fn manifest(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        check_name(name)?;
+        self.store.get(&object(name, MANIFEST))
+    }
+
+    fn file_reader(&self, name: &str, path: &str) -> Result<Option<Box<dyn Read + Send>>> {
+        check_name(name)?;
+        if !path.starts_with("part-") || path.contains('/') {
+            return Ok(None);
+        }
+        Ok(self.store.get(&object(name, path))?.map(|b| Box::new(io::Cursor::new(b)) as Box<dyn Read + Send>))
+    }
+}
@@ -0,0 +1,89 @@
+//! Tests for fanning backups out to several destinations
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tonledb_backup::replicate::{self, BackupReplicator, ReplicaState, RetryPolicy};
+use tonledb_backup::target::LocalTarget;
+use tonledb_core::object_store::{LocalObjectStore, ObjectStore};
+use tonledb_core::{DbError, Result, Space, Storage};
+
+fn dir(name: &str) -> std::path::PathBuf {
+    let p = std::env::temp_dir().join(format!("tonledb-bkrep-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&p);
+    p
+}
+
+/// Destination that is down
+struct Offline;
+
+impl ObjectStore for Offline {
+    fn put(&self, _path: &str, _data: Vec<u8>) -> Result<()> {
+        Err(DbError::Storage("connection refused".into()))
+    }
+    fn get(&self, _path: &str) -> Result<Option<Vec<u8>>> {
+        Err(DbError::Storage("connection refused".into()))
+    }
+    fn delete(&self, _path: &str) -> Result<()> {
+        Ok(())
+    }
+    fn list(&self, _prefix: &str) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+}
+
+#[test]
+fn test_backup_reaches_every_healthy_destination() {
+    let (a, b) = (dir("a"), dir("b"));
+    let rep = BackupReplicator::new(RetryPolicy { max_attempts: 2, backoff_ms: 1 })
+        .with_destination("local", Arc::new(LocalObjectStore::new(&a).unwrap()))
+        .with_destination("offsite", Arc::new(LocalObjectStore::new(&b).unwrap()))
+        .with_destination("s3", Arc::new(Offline));
+
+    let statuses = rep.replicate("b1", b"snapshot".to_vec()).unwrap().wait();
+    let state = |name: &str| statuses.iter().find(|s| s.destination == name).unwrap().clone();
+    assert_eq!(state("local").state, ReplicaState::Verified);
+    assert_eq!(state("offsite").sha256, Some(replicate::sha256_hex(b"snapshot")));
+    assert_eq!(state("s3").state, ReplicaState::Failed);
+    assert_eq!(state("s3").attempts, 2);
+    assert!(state("s3").error.unwrap().contains("connection refused"));
+
+    assert!(rep.verify("offsite", "b1").unwrap());
+    std::fs::write(b.join("backups/b1.bin"), b"tampered").unwrap();
+    assert!(!rep.verify("offsite", "b1").unwrap());
+}
+
+#[test]
+fn test_pitr_manager_records_checksum_and_fans_out() {
+    let wal = dir("wal").with_extension("wal");
+    let rep = BackupReplicator::new(RetryPolicy::default()).with_destination("local", Arc::new(LocalObjectStore::new(dir("m")).unwrap()));
+    let mut manager = tonledb_backup::PITRManager::new(wal.to_str().unwrap()).unwrap().with_replicator(rep);
+    let storage = tonledb_storage::arc_inmem_with_wal(None, 10);
+    manager.create_backup(&*storage, "b2").unwrap();
+
+    let job = manager.complete_backup("b2", b"data".to_vec()).unwrap().unwrap();
+    assert_eq!(job.wait()[0].state, ReplicaState::Verified);
+    let meta = manager.list_backups()[0].clone();
+    assert_eq!((meta.size, meta.checksum), (4, replicate::sha256_hex(b"data")));
+    assert_eq!(manager.replication_status("b2").len(), 1);
+    let _ = std::fs::remove_file(wal);
+}
+
+#[test]
+fn test_snapshots_are_replicated_once_written() {
+    let wal = dir("snap-wal").with_extension("wal");
+    let (snapshots, copies) = (dir("snapshots"), dir("copies"));
+    let rep = BackupReplicator::new(RetryPolicy::default()).with_destination("copies", Arc::new(LocalObjectStore::new(&copies).unwrap()));
+    let mut manager = tonledb_backup::PITRManager::new(wal.to_str().unwrap()).unwrap().with_replicator(rep);
+    let storage = tonledb_storage::arc_inmem_with_wal(None, 10);
+    storage.put(&Space("data".into()), b"k".to_vec(), b"v".to_vec()).unwrap();
+    let target = LocalTarget::new(&snapshots).unwrap();
+    let manifest = manager.snapshot(&*storage, &[Space("data".into())], "b3", &target).unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while manager.replication_status("b3").first().map(|s| s.state) != Some(ReplicaState::Verified) {
+        assert!(Instant::now() < deadline, "{:?}", manager.replication_status("b3"));
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(manager.replication_status("b3")[0].sha256, Some(manifest.sha256));
+    let _ = std::fs::remove_file(wal);
+}
@@ -0,0 +1,64 @@
+//! Tests for restoring part of a snapshot
+
+use tonledb_backup::restore::RestoreFilter;
+use tonledb_backup::target::LocalTarget;
+use tonledb_backup::PITRManager;
+use tonledb_core::{DbError, Space, Storage};
+use tonledb_storage::arc_inmem_with_wal;
+
+fn put(storage: &dyn Storage, space: &str, key: &str, val: &str) {
+    storage.put(&Space(space.into()), key.as_bytes().to_vec(), val.as_bytes().to_vec()).unwrap();
+}
+
+fn get(storage: &dyn Storage, space: &str, key: &str) -> Option<String> {
+    storage.get(&Space(space.into()), key.as_bytes()).unwrap().map(|v| String::from_utf8(v).unwrap())
+}
+
+#[test]
+fn test_restore_only_selected_keys() {
+    let root = std::env::temp_dir().join(format!("tonledb-restore-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    let target = LocalTarget::new(root.join("backups")).unwrap();
+    let source = arc_inmem_with_wal(None, 1000);
+    put(&*source, "data", "doc/users/1", "alice");
+    put(&*source, "data", "doc/users2/1", "other");
+    put(&*source, "data", "doc/orders/1", "order");
+    put(&*source, "data", "tbl/items/1", "row");
+    put(&*source, "catalog", "col/users", "{}");
+    put(&*source, "catalog", "col/users2", "{}");
+    put(&*source, "catalog", "docidx/users/email", "unique");
+    put(&*source, "doc_index", "users/email\x00a\x001", "");
+    put(&*source, "logs", "l1", "x");
+    let spaces: Vec<Space> = ["data", "catalog", "doc_index", "logs"].iter().map(|s| Space(s.to_string())).collect();
+    let mut manager = PITRManager::new(root.join("test.wal").to_str().unwrap()).unwrap();
+    manager.snapshot(&*source, &spaces, "b1", &target).unwrap();
+
+    // The live database moved on; only the users collection goes back
+    let live = arc_inmem_with_wal(None, 1000);
+    put(&*live, "data", "doc/users/1", "changed");
+    put(&*live, "data", "doc/orders/1", "newer order");
+    // Written after the backup, so the restore removes them
+    put(&*live, "data", "doc/users/2", "bob");
+    put(&*live, "doc_index", "users/email\x00b\x002", "");
+    put(&*live, "catalog", "docidx/users/name", "plain");
+    let filter = RestoreFilter::new().collection("users");
+    assert_eq!(manager.restore_filtered(&*live, "b1", &target, &filter).unwrap(), 4);
+    assert_eq!(get(&*live, "data", "doc/users/1").as_deref(), Some("alice"));
+    assert_eq!(get(&*live, "catalog", "docidx/users/email").as_deref(), Some("unique"));
+    assert_eq!(get(&*live, "doc_index", "users/email\x00a\x001").as_deref(), Some(""));
+    assert_eq!(get(&*live, "data", "doc/users/2"), None);
+    assert_eq!(get(&*live, "doc_index", "users/email\x00b\x002"), None);
+    assert_eq!(get(&*live, "catalog", "docidx/users/name"), None);
+    assert_eq!(get(&*live, "data", "doc/orders/1").as_deref(), Some("newer order"));
+    assert_eq!(get(&*live, "data", "doc/users2/1"), None);
+    assert_eq!(get(&*live, "catalog", "col/users2"), None);
+
+    let filter = RestoreFilter::new().table("items").space("logs");
+    assert_eq!(manager.restore_filtered(&*live, "b1", &target, &filter).unwrap(), 2);
+    assert_eq!(get(&*live, "data", "tbl/items/1").as_deref(), Some("row"));
+    assert_eq!(get(&*live, "logs", "l1").as_deref(), Some("x"));
+
+    let res = manager.restore_filtered(&*live, "b1", &target, &RestoreFilter::new());
+    assert!(matches!(res, Err(DbError::Invalid(_))));
+    let _ = std::fs::remove_dir_all(root);
+}
@@ -0,0 +1,59 @@
+//! Tests for scheduled backups and their rotation
+
+use std::time::Duration;
+use tonledb_backup::schedule::{rotate, run_backup, BackupJob, Retention, Schedule};
+use tonledb_backup::target::{BackupTarget, LocalTarget};
+use tonledb_core::{DbError, Space, Storage};
+use tonledb_storage::InMemoryStore;
+
+#[test]
+fn test_schedule_parse_and_next_run() {
+    assert_eq!(Schedule::parse("6h").unwrap(), Schedule::Every(Duration::from_secs(6 * 3600)));
+    assert_eq!(Schedule::parse("daily 02:30").unwrap(), Schedule::DailyAt { hour: 2, minute: 30 });
+    for bad in ["", "6", "0h", "6y", "daily 24:00", "daily 2"] {
+        assert!(matches!(Schedule::parse(bad), Err(DbError::Invalid(_))), "{}", bad);
+    }
+    assert_eq!(Schedule::Every(Duration::from_secs(60)).next_after(1_000), 61_000);
+    let daily = Schedule::DailyAt { hour: 2, minute: 30 };
+    let day = 86_400_000;
+    let at = 2 * 3_600_000 + 30 * 60_000;
+    assert_eq!(daily.next_after(10 * day), 10 * day + at);
+    // At or past today's time: tomorrow
+    assert_eq!(daily.next_after(10 * day + at), 11 * day + at);
+}
+
+#[test]
+fn test_run_backup_rotates_by_count_and_age() {
+    let dir = std::env::temp_dir().join(format!("tonledb-schedule-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let target = LocalTarget::new(&dir).unwrap();
+    let store = InMemoryStore::new(1000);
+    store.put(&Space("data".into()), b"k".to_vec(), b"v".to_vec()).unwrap();
+    // Taken by hand: never rotated
+    target.writer("manual").unwrap().finish().unwrap();
+
+    let job = BackupJob {
+        spaces: vec![Space("data".into())],
+        prefix: "scheduled-".into(),
+        retention: Retention { keep: Some(2), max_age: None },
+    };
+    let start = 1_700_000_000_000;
+    let mut names = Vec::new();
+    for i in 0..3 {
+        let run = run_backup(&store, &target, &job, start + i).unwrap();
+        assert_eq!(run.manifest.spaces["data"], 1);
+        names.push(run.manifest.backup_id);
+    }
+    // The third run rotated out the first
+    let listed = target.list().unwrap();
+    assert_eq!(listed, vec!["manual".to_string(), names[1].clone(), names[2].clone()]);
+
+    // By age: everything but the newest is past it
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+    let by_age = Retention { keep: None, max_age: Some(Duration::from_secs(60)) };
+    let (rotated, retained) = rotate(&target, "scheduled-", by_age, now + 3600).unwrap();
+    assert_eq!(rotated, vec![names[1].clone()]);
+    assert_eq!(retained, 1);
+    assert_eq!(target.list().unwrap(), vec!["manual".to_string(), names[2].clone()]);
+    let _ = std::fs::remove_dir_all(dir);
+}
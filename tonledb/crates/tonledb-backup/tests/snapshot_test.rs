@@ -0,0 +1,118 @@
+//! Tests for streaming snapshots to backup targets
+
+use std::sync::Arc;
+use tonledb_backup::target::{BackupTarget, LocalTarget, ObjectStoreTarget};
+use tonledb_backup::PITRManager;
+use tonledb_core::object_store::{LocalObjectStore, ObjectStore};
+use tonledb_core::{DbError, Space, Storage};
+use tonledb_storage::arc_inmem_with_wal;
+
+fn dir(name: &str) -> std::path::PathBuf {
+    let p = std::env::temp_dir().join(format!("tonledb-snap-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&p);
+    p
+}
+
+fn seeded() -> Arc<dyn Storage> {
+    let storage = arc_inmem_with_wal(None, 1000);
+    for i in 0..500u32 {
+        storage.put(&Space("data".into()), format!("k{:04}", i).into_bytes(), vec![i as u8; 64]).unwrap();
+    }
+    storage.put(&Space("catalog".into()), b"col/a".to_vec(), b"{}".to_vec()).unwrap();
+    storage.put(&Space("other".into()), b"skip".to_vec(), b"me".to_vec()).unwrap();
+    storage
+}
+
+fn round_trip(target: &dyn BackupTarget, wal: &std::path::Path) {
+    let spaces = [Space("data".into()), Space("catalog".into())];
+    let source = seeded();
+    let mut manager = PITRManager::new(wal.to_str().unwrap()).unwrap();
+    let meta = manager.snapshot(&*source, &spaces, "b1", target).unwrap();
+    assert!(meta.size > 0);
+    assert_eq!(target.list().unwrap(), vec!["b1".to_string()]);
+
+    let restored = arc_inmem_with_wal(None, 1000);
+    assert_eq!(manager.restore(&*restored, "b1", target).unwrap(), 501);
+    assert_eq!(restored.get(&Space("data".into()), b"k0499").unwrap(), Some(vec![243u8; 64]));
+    assert_eq!(restored.get(&Space("catalog".into()), b"col/a").unwrap(), Some(b"{}".to_vec()));
+    assert_eq!(restored.get(&Space("other".into()), b"skip").unwrap(), None);
+
+    // A manager that didn't take the backup restores it too
+    let other = PITRManager::new(wal.to_str().unwrap()).unwrap();
+    assert_eq!(other.restore(&*arc_inmem_with_wal(None, 1000), "b1", target).unwrap(), 501);
+    assert!(matches!(other.restore(&*restored, "nope", target), Err(DbError::NotFound(_))));
+
+    target.delete("b1").unwrap();
+    assert!(target.list().unwrap().is_empty());
+}
+
+#[test]
+fn test_snapshot_round_trip_local_directory() {
+    let root = dir("local");
+    round_trip(&LocalTarget::new(root.join("backups")).unwrap(), &root.join("test.wal"));
+    let _ = std::fs::remove_dir_all(root);
+}
+
+#[test]
+fn test_snapshot_round_trip_object_store_in_parts() {
+    let root = dir("objects");
+    let store = Arc::new(LocalObjectStore::new(root.join("bucket")).unwrap());
+    let target = ObjectStoreTarget::new(store.clone()).with_part_size(100);
+    round_trip(&target, &root.join("test.wal"));
+    let _ = std::fs::remove_dir_all(root);
+}
+
+#[test]
+fn test_rewrite_keeps_the_old_backup_until_finished() {
+    let root = dir("rewrite");
+    let store = Arc::new(LocalObjectStore::new(root.join("bucket")).unwrap());
+    let target = ObjectStoreTarget::new(store.clone()).with_part_size(64);
+    let read = |target: &ObjectStoreTarget| {
+        let mut out = Vec::new();
+        std::io::Read::read_to_end(&mut target.reader("b1").unwrap().unwrap(), &mut out).unwrap();
+        out
+    };
+    let mut w = target.writer("b1").unwrap();
+    std::io::Write::write_all(&mut w, &[1u8; 200]).unwrap();
+    w.finish().unwrap();
+
+    // A rewrite cut short leaves the old backup as it was
+    let mut w = target.writer("b1").unwrap();
+    std::io::Write::write_all(&mut w, &[2u8; 300]).unwrap();
+    drop(w);
+    assert_eq!(read(&target), vec![1u8; 200]);
+
+    // A finished one replaces it and clears out the parts of both
+    let mut w = target.writer("b1").unwrap();
+    std::io::Write::write_all(&mut w, &[3u8; 100]).unwrap();
+    w.finish().unwrap();
+    assert_eq!(read(&target), vec![3u8; 100]);
+    assert_eq!(store.list("snapshots/b1/part-").unwrap().len(), 2);
+    let _ = std::fs::remove_dir_all(root);
+}
+
+#[test]
+fn test_unfinished_and_damaged_backups() {
+    let root = dir("damaged");
+    let store = Arc::new(LocalObjectStore::new(root.join("bucket")).unwrap());
+    let target = ObjectStoreTarget::new(store.clone()).with_part_size(64);
+
+    // Parts without a manifest are not a backup
+    let mut w = target.writer("partial").unwrap();
+    std::io::Write::write_all(&mut w, &[7u8; 200]).unwrap();
+    drop(w);
+    assert!(target.list().unwrap().is_empty());
+    assert!(target.reader("partial").unwrap().is_none());
+
+    let mut manager = PITRManager::new(root.join("test.wal").to_str().unwrap()).unwrap();
+    manager.snapshot(&*seeded(), &[Space("data".into())], "b2", &target).unwrap();
+    let first = store.list("snapshots/b2/part-").unwrap()[0].clone();
+    let mut bytes = store.get(&first).unwrap().unwrap();
+    bytes[10] ^= 0xff;
+    store.put(&first, bytes).unwrap();
+    let restored = arc_inmem_with_wal(None, 1000);
+    assert!(matches!(manager.restore(&*restored, "b2", &target), Err(DbError::Corruption(_))));
+    assert!(restored.scan_prefix(&Space("data".into()), b"").unwrap().next().is_none());
+    assert!(target.writer("../escape").is_err());
+    let _ = std::fs::remove_dir_all(root);
+}
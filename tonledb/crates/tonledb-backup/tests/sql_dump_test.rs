@@ -0,0 +1,126 @@
+//! Tests for SQL dumps of tables and restoring them
+
+use std::sync::Arc;
+use serde_json::json;
+use tonledb_backup::sql_dump::{dump_sql, restore_sql, DumpReport, RestoreSqlReport, INSERT_BATCH_ROWS};
+use tonledb_core::{Column, ColumnConstraint, DataType, Db, IndexType, Space, Storage, TableConstraint, TableSchema};
+use tonledb_storage::InMemoryStore;
+
+fn column(name: &str, data_type: DataType, constraints: Vec<ColumnConstraint>) -> Column {
+    Column { name: name.into(), data_type, constraints }
+}
+
+fn users_db() -> Db {
+    let db = Db::new(Arc::new(InMemoryStore::new(1000)));
+    let schema = TableSchema {
+        name: "users".into(),
+        columns: vec![
+            column("id", DataType::Integer, vec![ColumnConstraint::PrimaryKey]),
+            column("email", DataType::Text, vec![ColumnConstraint::NotNull, ColumnConstraint::Unique]),
+            column("balance", DataType::Decimal, vec![]),
+            column("active", DataType::Boolean, vec![]),
+            column("prefs", DataType::Json, vec![]),
+        ],
+        pk: Some("id".into()),
+        constraints: vec![TableConstraint::Unique { columns: vec!["email".into(), "active".into()] }],
+    };
+    db.catalog.write().tables.insert("users".into(), schema);
+    db.create_index("users", "email", IndexType::BTree, true).unwrap();
+    db.create_index("users", "active", IndexType::Hash, false).unwrap();
+    db
+}
+
+fn put_row(db: &Db, id: u64, row: serde_json::Value) {
+    db.storage.put(&Space("data".into()), format!("tbl/users/{:05}", id).into_bytes(), serde_json::to_vec(&row).unwrap()).unwrap();
+}
+
+#[test]
+fn test_dump_keeps_types_constraints_and_indexes() {
+    let db = users_db();
+    put_row(&db, 1, json!({"id": 1, "email": "o'neil@example.com", "balance": 10.25, "active": true, "prefs": {"theme": "dark"}}));
+    put_row(&db, 2, json!({"id": 2, "email": "b@example.com", "active": false, "prefs": "plain"}));
+    let path = std::env::temp_dir().join(format!("tonledb-dump-{}.sql", std::process::id()));
+
+    let report = dump_sql(&db, &path).unwrap();
+    assert_eq!(report, DumpReport { tables: 1, indexes: 2, rows: 2 });
+    let sql = std::fs::read_to_string(&path).unwrap();
+    assert!(sql.contains("\"id\" BIGINT PRIMARY KEY"));
+    assert!(sql.contains("\"email\" TEXT NOT NULL UNIQUE"));
+    assert!(sql.contains("\"balance\" DECIMAL"));
+    assert!(sql.contains("\"prefs\" JSON"));
+    assert!(sql.contains("UNIQUE (\"email\", \"active\")"));
+    assert!(sql.contains("CREATE UNIQUE INDEX \"users_email_idx\" ON \"users\" (\"email\");"));
+    assert!(sql.contains("CREATE INDEX \"users_active_idx\" ON \"users\" USING HASH (\"active\");"));
+    assert!(sql.contains("(1, 'o''neil@example.com', 10.25, TRUE, '{\"theme\":\"dark\"}')"));
+    // Missing columns are NULL; JSON strings stay JSON text
+    assert!(sql.contains("(2, 'b@example.com', NULL, FALSE, '\"plain\"')"));
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn test_dump_batches_inserts() {
+    let db = users_db();
+    let rows = INSERT_BATCH_ROWS as u64 * 2 + 1;
+    for id in 0..rows {
+        put_row(&db, id, json!({"id": id, "email": format!("u{}@example.com", id)}));
+    }
+    let path = std::env::temp_dir().join(format!("tonledb-dump-batches-{}.sql", std::process::id()));
+    assert_eq!(dump_sql(&db, &path).unwrap().rows, rows);
+    let sql = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(sql.matches("INSERT INTO \"users\"").count(), 3);
+    let _ = std::fs::remove_file(path);
+}
+
+fn rows(db: &Db, table: &str) -> Vec<serde_json::Value> {
+    db.storage
+        .scan_prefix(&Space("data".into()), format!("tbl/{}/", table).as_bytes())
+        .unwrap()
+        .map(|(_, v)| serde_json::from_slice(&v).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_restore_sql_round_trips_a_dump() {
+    let db = users_db();
+    put_row(&db, 1, json!({"id": 1, "email": "o'neil@example.com", "balance": 10.25, "active": true, "prefs": {"theme": "dark"}}));
+    put_row(&db, 2, json!({"id": -2, "email": "semi;colon@example.com", "balance": null, "active": false, "prefs": "plain"}));
+    let log = TableSchema {
+        name: "log".into(),
+        columns: vec![column("msg", DataType::Text, vec![])],
+        pk: None,
+        constraints: vec![],
+    };
+    db.catalog.write().tables.insert("log".into(), log);
+    for (i, msg) in ["a", "b", "a"].iter().enumerate() {
+        db.storage.put(&Space("data".into()), format!("tbl/log/{}", i).into_bytes(), serde_json::to_vec(&json!({"msg": msg})).unwrap()).unwrap();
+    }
+    let path = std::env::temp_dir().join(format!("tonledb-dump-restore-{}.sql", std::process::id()));
+    dump_sql(&db, &path).unwrap();
+
+    let restored = Db::new(Arc::new(InMemoryStore::new(1000)));
+    let report = restore_sql(&restored, &path).unwrap();
+    assert_eq!(report, RestoreSqlReport { tables: 2, indexes: 2, rows: 5 });
+    {
+        let catalog = restored.catalog.read();
+        let users = &catalog.tables["users"];
+        assert_eq!(users.pk.as_deref(), Some("id"));
+        assert_eq!(users.columns[2].data_type, DataType::Decimal);
+        assert!(users.columns[1].constraints.contains(&ColumnConstraint::Unique));
+        assert!(matches!(&users.constraints[..], [TableConstraint::Unique { columns }] if columns.len() == 2));
+    }
+    assert!(restored.get_index("users", "email").unwrap().unwrap().is_unique);
+    assert_eq!(restored.get_index("users", "active").unwrap().unwrap().index_type, IndexType::Hash);
+    let mut original = rows(&db, "users");
+    let mut back = rows(&restored, "users");
+    original.sort_by_key(|r| r["id"].to_string());
+    back.sort_by_key(|r| r["id"].to_string());
+    assert_eq!(back, original);
+    assert_eq!(rows(&restored, "log").len(), 3);
+    // Index entries are rebuilt, keyed by value then row key
+    let entries: Vec<_> = restored.storage.scan_prefix(&Space("index_users.email".into()), b"semi;colon@example.com#").unwrap().collect();
+    assert_eq!(entries.len(), 1);
+
+    // Tables are never overwritten
+    assert!(matches!(restore_sql(&restored, &path), Err(tonledb_core::DbError::Conflict(_))));
+    let _ = std::fs::remove_file(path);
+}
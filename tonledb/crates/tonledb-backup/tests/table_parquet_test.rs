@@ -0,0 +1,125 @@
+//! Tests for exporting tables to Parquet and importing them back
+
+use std::sync::Arc;
+use arrow::array::{ArrayRef, Int32Array, Int64Array, StringArray, StructArray};
+use arrow::datatypes::{DataType as ArrowType, Field, Fields, Schema};
+use arrow::record_batch::RecordBatch;
+use serde_json::json;
+use tonledb_backup::table_parquet::{export_table_parquet, import_parquet, import_table_parquet};
+use tonledb_core::{ColumnConstraint, DataType, Db, DbError, IndexType, Space, Storage};
+use tonledb_sql::fixture;
+use tonledb_storage::InMemoryStore;
+
+fn orders_db() -> Db {
+    let db = Db::new(Arc::new(InMemoryStore::new(1000)));
+    let columns = [("id", DataType::Integer), ("customer", DataType::Text), ("total", DataType::Decimal), ("weight", DataType::Float), ("paid", DataType::Boolean), ("meta", DataType::Json)];
+    fixture::create_table(&db, "orders", &columns, Some("id"));
+    db.create_index("orders", "customer", IndexType::BTree, false).unwrap();
+    db
+}
+
+fn rows(db: &Db) -> Vec<serde_json::Value> {
+    db.storage.scan_prefix(&Space("data".into()), b"tbl/orders/").unwrap().map(|(_, v)| serde_json::from_slice(&v).unwrap()).collect()
+}
+
+#[test]
+fn test_parquet_round_trip() {
+    let db = orders_db();
+    let stored = [
+        json!({"id": 1, "customer": "ann", "total": 10.25, "weight": 1.5, "paid": true, "meta": {"tags": ["a"]}}),
+        json!({"id": 2, "customer": "bob", "paid": false}),
+    ];
+    fixture::insert_rows(&db, "orders", &stored).unwrap();
+    let path = std::env::temp_dir().join(format!("tonledb-orders-{}.parquet", std::process::id()));
+    assert_eq!(export_table_parquet(&db, "orders", &path).unwrap(), 2);
+
+    let imported = orders_db();
+    assert_eq!(import_table_parquet(&imported, "orders", &path).unwrap(), 2);
+    assert_eq!(rows(&imported), stored.to_vec());
+    // Rows are indexed as they are written
+    let entries: Vec<_> = imported.storage.scan_prefix(&Space("index_orders.customer".into()), b"bob#").unwrap().collect();
+    assert_eq!(entries.len(), 1);
+
+    assert!(matches!(import_table_parquet(&imported, "missing", &path), Err(DbError::NotFound(_))));
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn test_import_rejects_unknown_columns() {
+    let db = orders_db();
+    fixture::insert_rows(&db, "orders", &[json!({"id": 1})]).unwrap();
+    let path = std::env::temp_dir().join(format!("tonledb-orders-extra-{}.parquet", std::process::id()));
+    export_table_parquet(&db, "orders", &path).unwrap();
+
+    let narrow = Db::new(Arc::new(InMemoryStore::new(1000)));
+    fixture::create_table(&narrow, "orders", &[("id", DataType::Integer)], Some("id"));
+    assert!(matches!(import_table_parquet(&narrow, "orders", &path), Err(DbError::Invalid(_))));
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn test_import_parquet_creates_table() {
+    // A file from our own export keeps its primary key
+    let source = orders_db();
+    fixture::insert_rows(&source, "orders", &[json!({"id": 7, "customer": "cy"})]).unwrap();
+    let path = std::env::temp_dir().join(format!("tonledb-orders-create-{}.parquet", std::process::id()));
+    export_table_parquet(&source, "orders", &path).unwrap();
+    let db = Db::new(Arc::new(InMemoryStore::new(1000)));
+    assert_eq!(import_parquet(&db, "orders", &path).unwrap(), 1);
+    let created = db.catalog.read().tables["orders"].clone();
+    assert_eq!(created.pk.as_deref(), Some("id"));
+    assert_eq!(created.columns.iter().map(|c| c.data_type.clone()).collect::<Vec<_>>(), vec![DataType::Integer, DataType::Text, DataType::Decimal, DataType::Float, DataType::Boolean, DataType::Json]);
+    assert!(db.storage.get(&Space("data".into()), b"tbl/orders/7").unwrap().is_some());
+    // An existing table is written into as it is, without replacing its rows
+    assert_eq!(import_parquet(&orders_db(), "orders", &path).unwrap(), 1);
+    assert!(matches!(import_parquet(&source, "orders", &path), Err(DbError::UniqueViolation(_))));
+    let _ = std::fs::remove_file(path);
+
+    // A file from elsewhere: narrow integers, nested data and no primary key
+    let point = Fields::from(vec![Field::new("x", ArrowType::Int64, true)]);
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("n", ArrowType::Int32, false),
+        Field::new("label", ArrowType::Utf8, true),
+        Field::new("at", ArrowType::Struct(point.clone()), true),
+    ]));
+    let at = StructArray::new(point, vec![Arc::new(Int64Array::from(vec![1, 2])) as ArrayRef], None);
+    let batch = RecordBatch::try_new(schema.clone(), vec![
+        Arc::new(Int32Array::from(vec![1, 2])),
+        Arc::new(StringArray::from(vec![Some("a"), None])),
+        Arc::new(at),
+    ]).unwrap();
+    let path = std::env::temp_dir().join(format!("tonledb-points-{}.parquet", std::process::id()));
+    let mut writer = parquet::arrow::ArrowWriter::try_new(std::fs::File::create(&path).unwrap(), schema, None).unwrap();
+    writer.write(&batch).unwrap();
+    writer.close().unwrap();
+
+    assert_eq!(import_parquet(&db, "points", &path).unwrap(), 2);
+    let points = db.catalog.read().tables["points"].clone();
+    assert_eq!(points.pk, None);
+    assert_eq!(points.columns[0].constraints, vec![ColumnConstraint::NotNull]);
+    assert_eq!(points.columns.iter().map(|c| c.data_type.clone()).collect::<Vec<_>>(), vec![DataType::Integer, DataType::Text, DataType::Json]);
+    let rows: Vec<serde_json::Value> = db.storage.scan_prefix(&Space("data".into()), b"tbl/points/").unwrap().map(|(_, v)| serde_json::from_slice(&v).unwrap()).collect();
+    assert_eq!(rows, vec![json!({"n": 1, "label": "a", "at": {"x": 1}}), json!({"n": 2, "at": {"x": 2}})]);
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn test_failed_import_drops_the_table_it_created() {
+    // The last row, in the second read batch, isn't a decimal
+    let mut totals: Vec<String> = (0..9000).map(|n| n.to_string()).collect();
+    totals[8999] = "lots".into();
+    let field = Field::new("total", ArrowType::Utf8, true)
+        .with_metadata(std::collections::HashMap::from([("tonledb.type".to_string(), "decimal".to_string())]));
+    let schema = Arc::new(Schema::new(vec![field]));
+    let batch = RecordBatch::try_new(schema.clone(), vec![Arc::new(StringArray::from(totals)) as ArrayRef]).unwrap();
+    let path = std::env::temp_dir().join(format!("tonledb-bad-totals-{}.parquet", std::process::id()));
+    let mut writer = parquet::arrow::ArrowWriter::try_new(std::fs::File::create(&path).unwrap(), schema, None).unwrap();
+    writer.write(&batch).unwrap();
+    writer.close().unwrap();
+
+    let db = Db::new(Arc::new(InMemoryStore::new(1000)));
+    assert!(matches!(import_parquet(&db, "totals", &path), Err(DbError::Invalid(_))));
+    assert!(!db.catalog.read().tables.contains_key("totals"));
+    assert!(db.storage.scan_prefix(&Space("data".into()), b"tbl/totals/").unwrap().next().is_none());
+    let _ = std::fs::remove_file(path);
+}
@@ -7,12 +7,23 @@ use chrono::Local;
 #[command(name="tonledb", version, about="TonleDB CLI")]
 struct Args {
 #[arg(long, default_value = "http://127.0.0.1:8383")] endpoint: String,
+/// Replica consistency for reads/writes: one | quorum | all
+#[arg(long)] consistency: Option<String>,
 #[command(subcommand)] cmd: Cmd,
 }
 
 
 #[derive(Subcommand, Debug)]
-enum Cmd { Sql { query: String }, Init { #[arg(long, default_value = "./tonledb.wal")] wal: String }, Snapshot { #[arg(long, default_value_t = String::new())] out: String } }
+enum Cmd { Sql { query: String }, KvGet { key: String }, KvPut { key: String, value: String }, Init { #[arg(long, default_value = "./tonledb.wal")] wal: String }, Snapshot { #[arg(long, default_value_t = String::new())] out: String },
+/// Statements you ran (admins: any user's), e.g. `history --since 1d`
+History { #[arg(long)] user: Option<String>, /// Look back this far: 30m, 12h, 1d, 2w
+#[arg(long)] since: Option<String>, #[arg(long)] fingerprint: Option<String>, #[arg(long)] status: Option<String>, #[arg(long, default_value_t = 100)] limit: usize },
+/// Compact a space online (admin): drops expired keys and tombstones, shrinks its WAL records
+Compact { space: String },
+/// Roll a WAL back to a point in time, e.g. to before an accidental delete (server stopped)
+Restore { #[arg(long, default_value = "./tonledb.wal")] wal: String, /// RFC 3339 time or epoch ms
+#[arg(long)] to_time: Option<String>, /// `<segment>:<offset>` position in the WAL
+#[arg(long)] to_lsn: Option<String> } }
 
 
 #[derive(Serialize)]
@@ -24,7 +35,12 @@ async fn main() -> anyhow::Result<()> {
 let args = Args::parse();
 match args.cmd {
 Cmd::Sql { query } => do_sql(&args.endpoint, &query).await?,
+Cmd::KvGet { key } => do_kv(&args.endpoint, &key, None, args.consistency.as_deref()).await?,
+Cmd::KvPut { key, value } => do_kv(&args.endpoint, &key, Some(value), args.consistency.as_deref()).await?,
 Cmd::Init { wal } => { std::fs::File::create(&wal)?; println!("Initialized WAL at {}", wal); },
+Cmd::History { user, since, fingerprint, status, limit } => do_history(&args.endpoint, user, since.as_deref(), fingerprint, status, limit).await?,
+Cmd::Compact { space } => do_compact(&args.endpoint, &space).await?,
+Cmd::Restore { wal, to_time, to_lsn } => do_restore(&wal, to_time.as_deref(), to_lsn.as_deref())?,
 Cmd::Snapshot { out } => { let path = if out.is_empty() { format!("snap-{}.snap", Local::now().format("%Y%m%d-%H%M%S")) } else { out }; std::fs::write(&path, b"demo snapshot\n")?; println!("Wrote {}", path); },
 }
 Ok(())
@@ -37,4 +53,63 @@ let body = SqlBody { sql: sql.to_string() };
 let res: serde_json::Value = reqwest::Client::new().post(url).json(&body).send().await?.json().await?;
 println!("{}", serde_json::to_string_pretty(&res)?);
 Ok(())
-}
\ No newline at end of file
+}
+
+
+async fn do_kv(ep: &str, key: &str, value: Option<String>, consistency: Option<&str>) -> anyhow::Result<()> {
+let url = format!("{}/kv/{}", ep, key);
+let client = reqwest::Client::new();
+let mut req = match value { Some(v) => client.post(url).body(v), None => client.get(url) };
+if let Some(c) = consistency { req = req.header("x-tonledb-consistency", c); }
+let res: serde_json::Value = req.send().await?.json().await?;
+println!("{}", serde_json::to_string_pretty(&res)?);
+Ok(())
+}
+
+
+async fn do_history(ep: &str, user: Option<String>, since: Option<&str>, fingerprint: Option<String>, status: Option<String>, limit: usize) -> anyhow::Result<()> {
+let mut query = vec![("limit", limit.to_string())];
+if let Some(since) = since { query.push(("since_ms", (chrono::Utc::now().timestamp_millis() as u64).saturating_sub(parse_duration_ms(since)?).to_string())); }
+if let Some(u) = user { query.push(("user", u)); }
+if let Some(f) = fingerprint { query.push(("fingerprint", f)); }
+if let Some(s) = status { query.push(("status", s)); }
+let res: serde_json::Value = reqwest::Client::new().get(format!("{}/admin/history", ep)).query(&query).send().await?.json().await?;
+println!("{}", serde_json::to_string_pretty(&res)?);
+Ok(())
+}
+
+
+async fn do_compact(ep: &str, space: &str) -> anyhow::Result<()> {
+let res: serde_json::Value = reqwest::Client::new().post(format!("{}/admin/compact/{}", ep, space)).send().await?.json().await?;
+println!("{}", serde_json::to_string_pretty(&res)?);
+Ok(())
+}
+
+
+fn do_restore(wal: &str, to_time: Option<&str>, to_lsn: Option<&str>) -> anyhow::Result<()> {
+let target = match (to_time, to_lsn) {
+    (Some(t), None) => {
+        let ms = match t.parse::<u64>() { Ok(ms) => ms, Err(_) => chrono::DateTime::parse_from_rfc3339(t)?.timestamp_millis() as u64 };
+        tonledb_wal::RecoveryTarget::Time(ms)
+    }
+    (None, Some(l)) => {
+        let (segment, offset) = l.split_once(':').ok_or_else(|| anyhow::anyhow!("invalid LSN: {} (use <segment>:<offset>)", l))?;
+        tonledb_wal::RecoveryTarget::Lsn(tonledb_wal::Lsn { segment: segment.parse()?, offset: offset.parse()? })
+    }
+    _ => anyhow::bail!("pass one of --to-time or --to-lsn"),
+};
+// Keep the segments rolled back; the server prunes them per its `gc.wal_retention`
+let opts = tonledb_wal::WalOptions { retention: std::time::Duration::MAX, ..Default::default() };
+let store = tonledb_storage::InMemoryStore::restore(wal, 100_000, opts, target)?;
+println!("Restored {} to {:?} ({} bytes live)", wal, target, store.memory_usage());
+Ok(())
+}
+
+
+/// `30s`, `15m`, `12h`, `3d`, `2w` in milliseconds
+fn parse_duration_ms(s: &str) -> anyhow::Result<u64> {
+let (n, unit) = s.split_at(s.len() - s.trim_start_matches(|c: char| c.is_ascii_digit()).len());
+let n: u64 = n.parse().map_err(|_| anyhow::anyhow!("invalid duration: {}", s))?;
+let unit_ms = match unit { "s" => 1_000, "m" => 60_000, "h" => 3_600_000, "d" => 86_400_000, "w" => 604_800_000, _ => anyhow::bail!("invalid duration unit in {} (use s, m, h, d or w)", s) };
+Ok(n * unit_ms)
+}
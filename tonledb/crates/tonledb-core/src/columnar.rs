@@ -0,0 +1,165 @@
+//! Column-oriented copy of table rows, for scan-heavy analytics.
+//!
+//! Tables that opt in (see `tonledb_storage::ColumnarStorage`) get every row written
+//! to `data` mirrored into the `olap` space as one segment per column: the value
+//! of `column` in the row at `tbl/<table>/<rowkey>` is stored, as JSON, under
+//! `<table>/c/<column>\0<rowkey>`, and each row also leaves a presence entry at
+//! `<table>/r/<rowkey>`. Scanning one column's segment reads that column alone, so
+//! an aggregate over two columns of a wide table reads two of its columns.
+//!
+//! A table's segments are complete once `rebuild` has run for it, which records
+//! `olap/<table>` in the catalog; `is_ready` checks for that, and analytic queries
+//! read the segments only then, going to the rows otherwise. From then on every
+//! write to the table has to go through the wrapper. The wrapper drops the mark
+//! (`invalidate`) when a segment write fails after its row was written, and when
+//! it opens without the table; writes made around it some other way need
+//! `rebuild` to run again.
+
+use std::collections::HashSet;
+use serde_json::Value as Json;
+use crate::transaction::{next_timestamp, WriteSet};
+use crate::{DbError, Result, Space, Storage};
+
+pub const OLAP_SPACE: &str = "olap";
+const CATALOG_SPACE: &str = "catalog";
+const DATA_SPACE: &str = "data";
+const ROW_PREFIX: &[u8] = b"tbl/";
+
+pub fn olap_space() -> Space {
+    Space(OLAP_SPACE.into())
+}
+
+/// Table and row key of a `data` key, if it holds a table row
+pub fn split_row_key(key: &[u8]) -> Option<(&str, &[u8])> {
+    let rest = key.strip_prefix(ROW_PREFIX)?;
+    let slash = rest.iter().position(|b| *b == b'/')?;
+    let table = std::str::from_utf8(&rest[..slash]).ok()?;
+    Some((table, &rest[slash + 1..]))
+}
+
+/// Prefix of every `olap` key of `table`
+pub fn table_prefix(table: &str) -> Vec<u8> {
+    format!("{}/", table).into_bytes()
+}
+
+/// Prefix of the presence entries of `table`'s rows
+pub fn rows_prefix(table: &str) -> Vec<u8> {
+    format!("{}/r/", table).into_bytes()
+}
+
+/// Prefix of the segment holding `column` of `table`
+pub fn column_prefix(table: &str, column: &str) -> Vec<u8> {
+    format!("{}/c/{}\0", table, column).into_bytes()
+}
+
+fn ready_key(table: &str) -> Vec<u8> {
+    format!("olap/{}", table).into_bytes()
+}
+
+/// Add to `writes` what takes `table`'s segments from the row `old` at `rowkey` to
+/// `new`, either being absent. Rows that aren't JSON objects have no columns.
+pub fn row_writes(table: &str, rowkey: &[u8], old: Option<&[u8]>, new: Option<&[u8]>, writes: &mut WriteSet) {
+    let fields = |row: Option<&[u8]>| match row.map(serde_json::from_slice::<Json>) {
+        Some(Ok(Json::Object(fields))) => fields,
+        _ => serde_json::Map::new(),
+    };
+    let (old_fields, new_fields) = (fields(old), fields(new));
+    let olap = olap_space();
+    let key = |prefix: Vec<u8>| [prefix.as_slice(), rowkey].concat();
+    for (column, v) in &new_fields {
+        writes.insert((olap.clone(), key(column_prefix(table, column))), Some(v.to_string().into_bytes()));
+    }
+    for column in old_fields.keys().filter(|c| !new_fields.contains_key(*c)) {
+        writes.insert((olap.clone(), key(column_prefix(table, column))), None);
+    }
+    writes.insert((olap, key(rows_prefix(table))), new.map(|_| Vec::new()));
+}
+
+/// Whether `table`'s segments are complete (see the module docs)
+pub fn is_ready<S: Storage + ?Sized>(storage: &S, table: &str) -> Result<bool> {
+    Ok(storage.get(&Space(CATALOG_SPACE.into()), &ready_key(table))?.is_some())
+}
+
+/// Tables whose segments are marked complete
+pub fn ready_tables<S: Storage + ?Sized>(storage: &S) -> Result<Vec<String>> {
+    let prefix = ready_key("");
+    Ok(storage
+        .scan_prefix(&Space(CATALOG_SPACE.into()), &prefix)?
+        .filter_map(|(k, _)| String::from_utf8(k[prefix.len()..].to_vec()).ok())
+        .collect())
+}
+
+/// Mark `table`'s segments incomplete, so they aren't read until `rebuild`
+pub fn invalidate<S: Storage + ?Sized>(storage: &S, table: &str) -> Result<()> {
+    storage.del(&Space(CATALOG_SPACE.into()), &ready_key(table))
+}
+
+/// Rewrite `table`'s segments from its rows and mark them complete; returns the
+/// number of rows
+pub fn rebuild<S: Storage + ?Sized>(storage: &S, table: &str) -> Result<u64> {
+    storage.delete_prefix(&olap_space(), &table_prefix(table))?;
+    let prefix = format!("tbl/{}/", table).into_bytes();
+    let mut rows = 0;
+    let mut writes = WriteSet::new();
+    for (key, row) in storage.scan_prefix(&Space(DATA_SPACE.into()), &prefix)? {
+        row_writes(table, &key[prefix.len()..], None, Some(&row), &mut writes);
+        rows += 1;
+        if writes.len() >= 4096 {
+            storage.commit_writes(&std::mem::take(&mut writes), next_timestamp())?;
+        }
+    }
+    storage.commit_writes(&writes, next_timestamp())?;
+    storage.put(&Space(CATALOG_SPACE.into()), ready_key(table), Vec::new())?;
+    Ok(rows)
+}
+
+/// Row keys of `table`, in order
+pub fn row_keys<S: Storage + ?Sized>(storage: &S, table: &str) -> Result<Vec<Vec<u8>>> {
+    let prefix = rows_prefix(table);
+    Ok(storage.scan_prefix(&olap_space(), &prefix)?.map(|(k, _)| k[prefix.len()..].to_vec()).collect())
+}
+
+/// The segment of `column` in `table`: row keys and values, in row key order
+pub fn scan_column<S: Storage + ?Sized>(storage: &S, table: &str, column: &str) -> Result<Vec<(Vec<u8>, Json)>> {
+    let prefix = column_prefix(table, column);
+    storage
+        .scan_prefix(&olap_space(), &prefix)?
+        .map(|(k, v)| {
+            let v = serde_json::from_slice(&v).map_err(|e| DbError::Corruption(format!("olap value of {}.{}: {}", table, column, e)))?;
+            Ok((k[prefix.len()..].to_vec(), v))
+        })
+        .collect()
+}
+
+/// Rows of `table` holding only `columns`, read from their segments. Row keys
+/// order the rows, as they do in `data`.
+pub fn read_columns<S: Storage + ?Sized>(storage: &S, table: &str, columns: &[&str]) -> Result<Vec<Json>> {
+    column_rows(storage, table, columns)?.collect()
+}
+
+/// `read_columns` a row at a time: each row is read from the segments as the
+/// iterator reaches it
+pub fn column_rows<S: Storage + ?Sized>(storage: &S, table: &str, columns: &[&str]) -> Result<Box<dyn Iterator<Item = Result<Json>> + Send>> {
+    let keys_prefix = rows_prefix(table);
+    let keys = storage.scan_prefix(&olap_space(), &keys_prefix)?;
+    let wanted: HashSet<&str> = columns.iter().copied().collect();
+    let mut segments = Vec::with_capacity(wanted.len());
+    for column in wanted {
+        let prefix = column_prefix(table, column);
+        segments.push((column.to_string(), prefix.len(), storage.scan_prefix(&olap_space(), &prefix)?.peekable()));
+    }
+    let table = table.to_string();
+    Ok(Box::new(keys.map(move |(key, _)| {
+        let key = &key[keys_prefix.len()..];
+        let mut row = serde_json::Map::new();
+        for (column, skip, segment) in &mut segments {
+            // Both are sorted by row key, so one pass matches them up
+            while segment.next_if(|(k, _)| &k[*skip..] < key).is_some() {}
+            if let Some((_, v)) = segment.next_if(|(k, _)| &k[*skip..] == key) {
+                let v = serde_json::from_slice(&v).map_err(|e| DbError::Corruption(format!("olap value of {}.{}: {}", table, column, e)))?;
+                row.insert(column.clone(), v);
+            }
+        }
+        Ok(Json::Object(row))
+    })))
+}
@@ -0,0 +1,244 @@
+//! Per-request deadlines
+//!
+//! A request's deadline is installed on the thread serving it with `within`.
+//! `DeadlineStorage`, query execution and replica fan-out call `check` as they go
+//! and give up with `DbError::DeadlineExceeded` once it has passed, so a request
+//! the client stopped waiting for does not keep the server busy. Outside `within`
+//! there is no deadline and `check` always passes.
+//!
+//! A request can also be cancelled before its deadline: run it under
+//! `cancellable` with a `Canceller`, and once another thread calls
+//! `Canceller::cancel` the checks fail as if the deadline had passed.
+
+use std::cell::{Cell, RefCell};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use crate::transaction::WriteSet;
+use crate::{CacheStats, CompactionReport, Consistency, DbError, ReadView, Result, Space, Storage};
+
+thread_local! {
+    static CURRENT: Cell<Option<Instant>> = const { Cell::new(None) };
+    static CANCELLERS: RefCell<Vec<Canceller>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Restores the enclosing deadline when a `within` scope ends, panics included
+struct Restore(Option<Instant>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        CURRENT.with(|c| c.set(self.0));
+    }
+}
+
+/// Run `f` with `deadline` in force on this thread. A nested scope can only
+/// shorten the deadline of the one around it.
+pub fn within<T>(deadline: Option<Instant>, f: impl FnOnce() -> T) -> T {
+    let prev = CURRENT.with(|c| c.get());
+    let effective = match (prev, deadline) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    };
+    let _restore = Restore(prev);
+    CURRENT.with(|c| c.set(effective));
+    f()
+}
+
+/// Cancels the requests run under it with `cancellable`, from any thread
+#[derive(Debug, Clone, Default)]
+pub struct Canceller(Arc<AtomicBool>);
+
+impl Canceller {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Pops the canceller a `cancellable` scope pushed when it ends, panics included
+struct PopCanceller;
+
+impl Drop for PopCanceller {
+    fn drop(&mut self) {
+        CANCELLERS.with(|c| c.borrow_mut().pop());
+    }
+}
+
+/// Run `f` on this thread so that cancelling `canceller` stops it at its next
+/// check. Nested scopes add to the cancellers of the ones around them.
+pub fn cancellable<T>(canceller: &Canceller, f: impl FnOnce() -> T) -> T {
+    CANCELLERS.with(|c| c.borrow_mut().push(canceller.clone()));
+    let _pop = PopCanceller;
+    f()
+}
+
+/// Whether a canceller in force on this thread has been cancelled
+pub fn cancelled() -> bool {
+    CANCELLERS.with(|c| c.borrow().iter().any(Canceller::is_cancelled))
+}
+
+/// Deadline in force on this thread
+pub fn current() -> Option<Instant> {
+    CURRENT.with(|c| c.get())
+}
+
+/// Time left before the deadline; `None` without one
+pub fn remaining() -> Option<Duration> {
+    current().map(|d| d.saturating_duration_since(Instant::now()))
+}
+
+/// Whether the deadline has passed or the request was cancelled
+pub fn expired() -> bool {
+    current().is_some_and(|d| Instant::now() >= d) || cancelled()
+}
+
+/// Fail with `DbError::DeadlineExceeded` once the deadline has passed or the
+/// request was cancelled
+pub fn check() -> Result<()> {
+    if cancelled() {
+        return Err(DbError::DeadlineExceeded("request cancelled".into()));
+    }
+    match current() {
+        Some(d) if Instant::now() >= d => Err(DbError::DeadlineExceeded(format!(
+            "request deadline passed {} ms ago",
+            Instant::now().duration_since(d).as_millis()
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Storage wrapper refusing calls made after the deadline of the request they serve
+pub struct DeadlineStorage {
+    inner: Arc<dyn Storage>,
+}
+
+impl DeadlineStorage {
+    pub fn new(inner: Arc<dyn Storage>) -> Self {
+        Self { inner }
+    }
+}
+
+impl Storage for DeadlineStorage {
+    fn get(&self, space: &Space, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        check()?;
+        self.inner.get(space, key)
+    }
+
+    fn put(&self, space: &Space, key: Vec<u8>, val: Vec<u8>) -> Result<()> {
+        check()?;
+        self.inner.put(space, key, val)
+    }
+
+    fn del(&self, space: &Space, key: &[u8]) -> Result<()> {
+        check()?;
+        self.inner.del(space, key)
+    }
+
+    /// Checked when the scan starts; callers iterating long scans check as they go
+    fn scan_prefix(&self, space: &Space, prefix: &[u8]) -> Result<Box<dyn Iterator<Item=(Vec<u8>, Vec<u8>)> + Send>> {
+        check()?;
+        self.inner.scan_prefix(space, prefix)
+    }
+
+    fn scan_prefix_page(&self, space: &Space, prefix: &[u8], after: Option<&[u8]>, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        check()?;
+        self.inner.scan_prefix_page(space, prefix, after, limit)
+    }
+
+    fn scan_range(&self, space: &Space, start: &[u8], end: Option<&[u8]>, reverse: bool, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        check()?;
+        self.inner.scan_range(space, start, end, reverse, limit)
+    }
+
+    fn merge(&self, space: &Space, key: Vec<u8>, operand: Vec<u8>) -> Result<()> {
+        check()?;
+        self.inner.merge(space, key, operand)
+    }
+
+    fn delete_prefix(&self, space: &Space, prefix: &[u8]) -> Result<usize> {
+        check()?;
+        self.inner.delete_prefix(space, prefix)
+    }
+
+    fn get_versioned(&self, space: &Space, key: &[u8], version: u64) -> Result<Option<Vec<u8>>> {
+        check()?;
+        self.inner.get_versioned(space, key, version)
+    }
+
+    fn put_versioned(&self, space: &Space, key: Vec<u8>, val: Vec<u8>, version: u64) -> Result<()> {
+        check()?;
+        self.inner.put_versioned(space, key, val, version)
+    }
+
+    fn del_versioned(&self, space: &Space, key: &[u8], version: u64) -> Result<()> {
+        check()?;
+        self.inner.del_versioned(space, key, version)
+    }
+
+    fn commit_writes(&self, writes: &WriteSet, version: u64) -> Result<()> {
+        check()?;
+        self.inner.commit_writes(writes, version)
+    }
+
+    fn latest_version(&self, space: &Space, key: &[u8]) -> Result<Option<u64>> {
+        check()?;
+        self.inner.latest_version(space, key)
+    }
+
+    fn gc_versions(&self, oldest_active: u64) -> Result<usize> {
+        self.inner.gc_versions(oldest_active)
+    }
+
+    fn get_with(&self, space: &Space, key: &[u8], consistency: Consistency) -> Result<Option<Vec<u8>>> {
+        check()?;
+        self.inner.get_with(space, key, consistency)
+    }
+
+    fn put_with(&self, space: &Space, key: Vec<u8>, val: Vec<u8>, consistency: Consistency) -> Result<()> {
+        check()?;
+        self.inner.put_with(space, key, val, consistency)
+    }
+
+    fn del_with(&self, space: &Space, key: &[u8], consistency: Consistency) -> Result<()> {
+        check()?;
+        self.inner.del_with(space, key, consistency)
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn ReadView>> {
+        check()?;
+        self.inner.snapshot()
+    }
+
+    fn put_with_ttl(&self, space: &Space, key: Vec<u8>, val: Vec<u8>, ttl_ms: u64) -> Result<()> {
+        check()?;
+        self.inner.put_with_ttl(space, key, val, ttl_ms)
+    }
+
+    fn expires_at(&self, space: &Space, key: &[u8]) -> Result<Option<u64>> {
+        check()?;
+        self.inner.expires_at(space, key)
+    }
+
+    fn sweep_expired(&self) -> Result<usize> {
+        self.inner.sweep_expired()
+    }
+
+    fn compact_space(&self, space: &Space) -> Result<CompactionReport> {
+        self.inner.compact_space(space)
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.inner.cache_stats()
+    }
+
+    fn is_cached(&self, space: &Space, key: &[u8]) -> bool {
+        self.inner.is_cached(space, key)
+    }
+}
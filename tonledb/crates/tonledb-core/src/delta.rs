@@ -0,0 +1,138 @@
+//! Delta encoding of JSON values for replication/CDC streams
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value as Json;
+use crate::{DbError, Result};
+
+/// Values smaller than this are always shipped whole; diffing them is not worth it.
+pub const DEFAULT_DELTA_MIN_BYTES: usize = 512;
+
+/// A single field-level change inside a JSON document
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum DeltaOp {
+    /// Set (add or replace) the value at `path`
+    Set { path: Vec<String>, value: Json },
+    /// Remove the field at `path`
+    Remove { path: Vec<String> },
+}
+
+/// Payload shipped for a change: either the full value or a delta against the previous one
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum ChangePayload {
+    /// Full new value (`None` for deletes)
+    Full(Option<Vec<u8>>),
+    /// Field-level delta to apply to the previous JSON value, whose `checksum` is `base`
+    Delta { base: u32, ops: Vec<DeltaOp> },
+}
+
+/// Encode `new` relative to `old`.
+///
+/// Falls back to `ChangePayload::Full` when there is no previous value, either
+/// side is not a JSON object, the value is below `min_bytes`, or the delta
+/// would not be smaller than the full value.
+pub fn encode(old: Option<&[u8]>, new: Option<&[u8]>, min_bytes: usize) -> ChangePayload {
+    let (old, new) = match (old, new) {
+        (Some(o), Some(n)) if n.len() >= min_bytes => (o, n),
+        (_, n) => return ChangePayload::Full(n.map(|b| b.to_vec())),
+    };
+    let (old_doc, new_doc) = match (serde_json::from_slice::<Json>(old), serde_json::from_slice::<Json>(new)) {
+        (Ok(o @ Json::Object(_)), Ok(n @ Json::Object(_))) => (o, n),
+        _ => return ChangePayload::Full(Some(new.to_vec())),
+    };
+
+    let mut ops = Vec::new();
+    diff(&mut Vec::new(), &old_doc, &new_doc, &mut ops);
+    let delta_len = serde_json::to_vec(&ops).map(|b| b.len()).unwrap_or(usize::MAX);
+    if delta_len >= new.len() {
+        return ChangePayload::Full(Some(new.to_vec()));
+    }
+    ChangePayload::Delta { base: checksum(&old_doc), ops }
+}
+
+/// Checksum of a JSON document, taken over its serde_json encoding so that copies
+/// stored with other whitespace or key order still match
+pub fn checksum(doc: &Json) -> u32 {
+    crc32fast::hash(&serde_json::to_vec(doc).unwrap_or_default())
+}
+
+/// Apply a payload to the current value held by a replica/consumer.
+///
+/// Returns the resulting value (`None` for deletes). A delta without a base
+/// value, or whose base checksum doesn't match the consumer's value, is an error:
+/// the consumer is out of sync and needs a full copy.
+pub fn apply(base: Option<&[u8]>, payload: &ChangePayload) -> Result<Option<Vec<u8>>> {
+    match payload {
+        ChangePayload::Full(v) => Ok(v.clone()),
+        ChangePayload::Delta { base: expected, ops } => {
+            let base = base.ok_or_else(|| DbError::Invalid("delta received without base value".into()))?;
+            let mut doc: Json = serde_json::from_slice(base)
+                .map_err(|e| DbError::Invalid(format!("delta base is not JSON: {}", e)))?;
+            if checksum(&doc) != *expected {
+                return Err(DbError::Conflict("delta base does not match the value it was taken against".into()));
+            }
+            for op in ops {
+                apply_op(&mut doc, op)?;
+            }
+            serde_json::to_vec(&doc).map(Some).map_err(|e| DbError::Storage(e.to_string()))
+        }
+    }
+}
+
+// ---------- helpers ----------
+
+fn diff(path: &mut Vec<String>, old: &Json, new: &Json, ops: &mut Vec<DeltaOp>) {
+    match (old, new) {
+        (Json::Object(a), Json::Object(b)) => {
+            for (k, old_v) in a {
+                path.push(k.clone());
+                match b.get(k) {
+                    Some(new_v) => diff(path, old_v, new_v, ops),
+                    None => ops.push(DeltaOp::Remove { path: path.clone() }),
+                }
+                path.pop();
+            }
+            for (k, new_v) in b {
+                if !a.contains_key(k) {
+                    path.push(k.clone());
+                    ops.push(DeltaOp::Set { path: path.clone(), value: new_v.clone() });
+                    path.pop();
+                }
+            }
+        }
+        (a, b) if a == b => {}
+        (_, b) => ops.push(DeltaOp::Set { path: path.clone(), value: b.clone() }),
+    }
+}
+
+fn apply_op(doc: &mut Json, op: &DeltaOp) -> Result<()> {
+    let path = match op {
+        DeltaOp::Set { path, .. } | DeltaOp::Remove { path } => path,
+    };
+    let (last, parents) = match path.split_last() {
+        Some(x) => x,
+        None => {
+            // Empty path addresses the whole document
+            if let DeltaOp::Set { value, .. } = op {
+                *doc = value.clone();
+            }
+            return Ok(());
+        }
+    };
+
+    let mut cur = doc;
+    for seg in parents {
+        cur = cur
+            .as_object_mut()
+            .ok_or_else(|| DbError::Invalid(format!("delta path {:?} crosses a non-object", path)))?
+            .entry(seg.clone())
+            .or_insert_with(|| Json::Object(Default::default()));
+    }
+    let obj = cur
+        .as_object_mut()
+        .ok_or_else(|| DbError::Invalid(format!("delta path {:?} crosses a non-object", path)))?;
+    match op {
+        DeltaOp::Set { value, .. } => { obj.insert(last.clone(), value.clone()); }
+        DeltaOp::Remove { .. } => { obj.remove(last); }
+    }
+    Ok(())
+}
@@ -3,6 +3,7 @@
 use std::sync::{Arc, RwLock};
 use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
+use crate::delta::{self, ChangePayload};
 
 /// Represents a change event in the database
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -16,6 +17,43 @@ pub struct ChangeEvent {
     pub new_value: Option<Vec<u8>>,
 }
 
+impl ChangeEvent {
+    /// Encode this event for the replication stream. Updates of large JSON
+    /// documents ship a field-level delta; everything else ships the full value.
+    pub fn to_replication_record(&self, min_delta_bytes: usize) -> ReplicationRecord {
+        let payload = match self.operation {
+            Operation::Update => delta::encode(self.old_value.as_deref(), self.new_value.as_deref(), min_delta_bytes),
+            _ => ChangePayload::Full(self.new_value.clone()),
+        };
+        ReplicationRecord {
+            id: self.id.clone(),
+            timestamp: self.timestamp,
+            operation: self.operation.clone(),
+            table: self.table.clone(),
+            key: self.key.clone(),
+            payload,
+        }
+    }
+}
+
+/// A change event as shipped to replicas and CDC consumers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplicationRecord {
+    pub id: String,
+    pub timestamp: u64,
+    pub operation: Operation,
+    pub table: String,
+    pub key: Option<Vec<u8>>,
+    pub payload: ChangePayload,
+}
+
+impl ReplicationRecord {
+    /// Apply this record on top of the consumer's current value for `key`
+    pub fn apply_to(&self, current: Option<&[u8]>) -> crate::Result<Option<Vec<u8>>> {
+        delta::apply(current, &self.payload)
+    }
+}
+
 /// Type of operation that caused the change
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum Operation {
@@ -29,9 +67,22 @@ pub struct ChangeFeed {
     pub id: String,
     pub table_filter: Option<String>,
     pub operation_filter: Option<Vec<Operation>>,
+    /// Only events on keys starting with this are delivered
+    pub key_prefix: Option<Vec<u8>>,
+    /// Server-side predicate; events it rejects are never delivered
+    pub predicate: Option<Box<dyn Fn(&ChangeEvent) -> bool + Send + Sync>>,
     pub callback: Box<dyn Fn(ChangeEvent) + Send + Sync>,
 }
 
+impl ChangeFeed {
+    /// Whether the table, operation and key filters let an event through
+    fn accepts(&self, table: &str, operation: &Operation, key: Option<&[u8]>) -> bool {
+        self.table_filter.as_ref().is_none_or(|t| t == table)
+            && self.operation_filter.as_ref().is_none_or(|ops| ops.contains(operation))
+            && self.key_prefix.as_ref().is_none_or(|p| key.is_some_and(|k| k.starts_with(p)))
+    }
+}
+
 /// Event sourcing manager
 pub struct EventSourcingManager {
     feeds: RwLock<HashMap<String, ChangeFeed>>,
@@ -53,6 +104,27 @@ impl EventSourcingManager {
             id: id.clone(),
             table_filter,
             operation_filter,
+            key_prefix: None,
+            predicate: None,
+            callback: Box::new(callback),
+        };
+        
+        self.feeds.write().unwrap().insert(id, feed);
+        Ok(())
+    }
+    
+    /// Register a changefeed that only receives events accepted by `predicate`
+    pub fn register_filtered_feed<P, F>(&self, id: String, table_filter: Option<String>, operation_filter: Option<Vec<Operation>>, predicate: P, callback: F) -> Result<(), String>
+    where
+        P: Fn(&ChangeEvent) -> bool + Send + Sync + 'static,
+        F: Fn(ChangeEvent) + Send + Sync + 'static,
+    {
+        let feed = ChangeFeed {
+            id: id.clone(),
+            table_filter,
+            operation_filter,
+            key_prefix: None,
+            predicate: Some(Box::new(predicate)),
             callback: Box::new(callback),
         };
         
@@ -60,25 +132,58 @@ impl EventSourcingManager {
         Ok(())
     }
     
+    /// Register a changefeed that only receives events on keys starting with `key_prefix`
+    pub fn register_prefix_feed<F>(&self, id: String, table_filter: Option<String>, key_prefix: Vec<u8>, callback: F) -> Result<(), String>
+    where
+        F: Fn(ChangeEvent) + Send + Sync + 'static,
+    {
+        let feed = ChangeFeed {
+            id: id.clone(),
+            table_filter,
+            operation_filter: None,
+            key_prefix: Some(key_prefix),
+            predicate: None,
+            callback: Box::new(callback),
+        };
+        
+        self.feeds.write().unwrap().insert(id, feed);
+        Ok(())
+    }
+    
+    /// Register a changefeed that receives delta-encoded replication records
+    pub fn register_replication_feed<F>(&self, id: String, table_filter: Option<String>, min_delta_bytes: usize, callback: F) -> Result<(), String>
+    where
+        F: Fn(ReplicationRecord) + Send + Sync + 'static,
+    {
+        self.register_feed(id, table_filter, None, move |event| {
+            callback(event.to_replication_record(min_delta_bytes))
+        })
+    }
+    
     /// Unregister a changefeed
     pub fn unregister_feed(&self, id: &str) -> bool {
         self.feeds.write().unwrap().remove(id).is_some()
     }
     
+    /// Whether any feed would take an event of `operation` on `key` in `table`, so
+    /// writers can skip building events nobody receives. Feeds with a predicate
+    /// count as taking it, since the predicate needs the event.
+    pub fn wants(&self, table: &str, operation: &Operation, key: &[u8]) -> bool {
+        self.feeds.read().unwrap().values().any(|feed| feed.accepts(table, operation, Some(key)))
+    }
+    
     /// Publish a change event to all interested feeds
     pub fn publish_event(&self, event: ChangeEvent) {
         let feeds = self.feeds.read().unwrap();
         for feed in feeds.values() {
-            // Check table filter
-            if let Some(ref table_filter) = feed.table_filter {
-                if *table_filter != event.table {
-                    continue;
-                }
+            // Check table, operation and key filters
+            if !feed.accepts(&event.table, &event.operation, event.key.as_deref()) {
+                continue;
             }
             
-            // Check operation filter
-            if let Some(ref operation_filter) = feed.operation_filter {
-                if !operation_filter.contains(&event.operation) {
+            // Check predicate
+            if let Some(ref predicate) = feed.predicate {
+                if !predicate(&event) {
                     continue;
                 }
             }
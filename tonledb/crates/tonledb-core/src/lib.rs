@@ -1,13 +1,25 @@
 use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
-use parking_lot::RwLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use std::hash::Hash;
 
+pub mod columnar;
+pub mod deadline;
+pub mod delta;
 pub mod event_sourcing;
+pub mod merge;
+pub mod numbers;
+pub mod object_store;
+pub mod op_trace;
+pub mod schema_inference;
 pub mod transaction;
 pub mod security;
+pub mod statement_history;
+pub mod stats;
+pub mod system_events;
 
 // ---------- Errors ----------
 #[derive(Debug, Error)]
@@ -15,6 +27,14 @@ pub enum DbError {
 #[error("not found: {0}")] NotFound(String),
 #[error("invalid: {0}")] Invalid(String),
 #[error("storage: {0}")] Storage(String),
+#[error("conflict: {0}")] Conflict(String),
+#[error("resource exhausted: {0}")] ResourceExhausted(String),
+#[error("corruption: {0}")] Corruption(String),
+#[error("deadline exceeded: {0}")] DeadlineExceeded(String),
+/// A row whose key is already taken
+#[error("unique violation: {0}")] UniqueViolation(String),
+/// A row without a value in a NOT NULL column
+#[error("not-null violation: {0}")] NotNullViolation(String),
 }
 
 
@@ -33,12 +53,105 @@ pub type Row = BTreeMap<String, Value>;
 pub struct Space(pub String);
 
 
+/// Replica consistency requested for a single read or write in replicated deployments.
+/// Single-node engines satisfy every level trivially.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum Consistency { #[default] One, Quorum, All }
+
+impl Consistency {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "one" => Some(Self::One),
+            "quorum" => Some(Self::Quorum),
+            "all" => Some(Self::All),
+            _ => None,
+        }
+    }
+
+    /// Number of replica acknowledgements needed out of `replicas`.
+    pub fn required(self, replicas: usize) -> usize {
+        match self {
+            Self::One => 1.min(replicas),
+            Self::Quorum => replicas / 2 + 1,
+            Self::All => replicas,
+        }
+    }
+}
+
+
+/// Read-cache counters reported by engines that keep an LRU in front of their data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct CacheStats { pub capacity: usize, pub entries: usize, pub hits: u64, pub misses: u64 }
+
+impl CacheStats {
+    /// Fraction of point reads served from the cache so far (0 before any read).
+    pub fn hit_ratio(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 { 0.0 } else { self.hits as f64 / total as f64 }
+    }
+}
+
+/// What `Storage::compact_space` removed and rewrote in one space.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct CompactionReport {
+    pub space: String,
+    /// Keys left in the space afterwards
+    pub live_keys: usize,
+    /// Expired keys physically removed
+    pub expired_removed: usize,
+    /// Delete markers dropped from version chains
+    pub tombstones_removed: usize,
+    /// Values re-encoded with the space's current compression settings
+    pub recompressed: usize,
+    pub wal_records_before: usize,
+    pub wal_records_after: usize,
+}
+
+
 pub trait Storage: Send + Sync {
 fn get(&self, space: &Space, key: &[u8]) -> Result<Option<Vec<u8>>>;
 fn put(&self, space: &Space, key: Vec<u8>, val: Vec<u8>) -> Result<()>;
 fn del(&self, space: &Space, key: &[u8]) -> Result<()>;
 fn scan_prefix(&self, space: &Space, prefix: &[u8]) -> Result<Box<dyn Iterator<Item=(Vec<u8>, Vec<u8>)> + Send>>;
 
+/// Fold `operand` into the value of `key` with the space's merge operator, atomically
+/// with respect to other writes to the key (see `merge::MergeOperator`).
+fn merge(&self, _space: &Space, _key: Vec<u8>, _operand: Vec<u8>) -> Result<()> {
+    Err(DbError::Invalid("merge not supported by this storage engine".into()))
+}
+
+/// Up to `limit` pairs of `scan_prefix`, in key order, with keys after `after`: one
+/// page of a scan, resumed from the last key of the page before. The default pages
+/// through `scan_prefix`; engines with ordered maps should start at `after` instead.
+fn scan_prefix_page(&self, space: &Space, prefix: &[u8], after: Option<&[u8]>, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    Ok(self.scan_prefix(space, prefix)?.filter(|(k, _)| after.is_none_or(|a| k.as_slice() > a)).take(limit).collect())
+}
+
+/// Up to `limit` pairs with keys in `start..end` (to the end of the space if `end` is
+/// `None`), in key order or, with `reverse`, from the last key down, so "the newest N"
+/// of time-ordered keys is one call. The default filters a scan of the whole space;
+/// engines with ordered maps should walk the range instead.
+fn scan_range(&self, space: &Space, start: &[u8], end: Option<&[u8]>, reverse: bool, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let rows = self.scan_prefix(space, b"")?.filter(|(k, _)| k.as_slice() >= start && end.is_none_or(|e| k.as_slice() < e));
+    if !reverse {
+        return Ok(rows.take(limit).collect());
+    }
+    let mut rows: Vec<_> = rows.collect();
+    rows.reverse();
+    rows.truncate(limit);
+    Ok(rows)
+}
+
+/// Delete every key in `space` starting with `prefix`; returns how many were removed.
+/// Engines with a WAL should log this as a single record. The default deletes key by key.
+fn delete_prefix(&self, space: &Space, prefix: &[u8]) -> Result<usize> {
+    let keys: Vec<Vec<u8>> = self.scan_prefix(space, prefix)?.map(|(k, _)| k).collect();
+    for k in &keys {
+        self.del(space, k)?;
+    }
+    Ok(keys.len())
+}
+
 // MVCC extensions
 fn get_versioned(&self, space: &Space, key: &[u8], _version: u64) -> Result<Option<Vec<u8>>> {
     // Default implementation falls back to regular get
@@ -49,6 +162,121 @@ fn put_versioned(&self, space: &Space, key: Vec<u8>, val: Vec<u8>, _version: u64
     // Default implementation falls back to regular put
     self.put(space, key, val)
 }
+
+fn del_versioned(&self, space: &Space, key: &[u8], _version: u64) -> Result<()> {
+    // Default implementation falls back to regular del
+    self.del(space, key)
+}
+
+/// Apply a committed transaction's writes at `version`. Engines with a WAL should log
+/// them as one transaction, so replay restores all of them or none. The default
+/// writes key by key.
+fn commit_writes(&self, writes: &transaction::WriteSet, version: u64) -> Result<()> {
+    for ((space, key), val) in writes {
+        match val {
+            Some(val) => self.put_versioned(space, key.clone(), val.clone(), version)?,
+            None => self.del_versioned(space, key, version)?,
+        }
+    }
+    Ok(())
+}
+
+/// Newest committed version of a key, if the engine tracks versions.
+fn latest_version(&self, _space: &Space, _key: &[u8]) -> Result<Option<u64>> {
+    Ok(None)
+}
+
+/// Drop versions no reader at or after `oldest_active` can see. Returns the number removed.
+fn gc_versions(&self, _oldest_active: u64) -> Result<usize> {
+    Ok(0)
+}
+
+// Replication extensions: per-request consistency levels
+fn get_with(&self, space: &Space, key: &[u8], _consistency: Consistency) -> Result<Option<Vec<u8>>> {
+    self.get(space, key)
+}
+
+fn put_with(&self, space: &Space, key: Vec<u8>, val: Vec<u8>, _consistency: Consistency) -> Result<()> {
+    self.put(space, key, val)
+}
+
+fn del_with(&self, space: &Space, key: &[u8], _consistency: Consistency) -> Result<()> {
+    self.del(space, key)
+}
+
+/// Take a consistent point-in-time read view. Writes made after the call are not visible through it.
+fn snapshot(&self) -> Result<Box<dyn ReadView>> {
+    Err(DbError::Invalid("snapshots not supported by this storage engine".into()))
+}
+
+/// Write a value that expires `ttl_ms` milliseconds from now. Expired keys read as
+/// absent and are physically removed by `sweep_expired`.
+fn put_with_ttl(&self, _space: &Space, _key: Vec<u8>, _val: Vec<u8>, _ttl_ms: u64) -> Result<()> {
+    Err(DbError::Invalid("TTL not supported by this storage engine".into()))
+}
+
+/// When `key` expires (epoch milliseconds); `None` if it has no TTL, is absent or
+/// has already expired.
+fn expires_at(&self, _space: &Space, _key: &[u8]) -> Result<Option<u64>> {
+    Ok(None)
+}
+
+/// Physically remove expired keys. Returns the number removed.
+fn sweep_expired(&self) -> Result<usize> {
+    Ok(0)
+}
+
+/// Rewrite `space` in place while it stays readable and writable: drop expired keys and
+/// delete markers, re-encode values and shrink the engine's log to the live entries.
+fn compact_space(&self, _space: &Space) -> Result<CompactionReport> {
+    Err(DbError::Invalid("compaction not supported by this storage engine".into()))
+}
+
+// Cache introspection, used by EXPLAIN to estimate warm vs cold reads
+fn cache_stats(&self) -> Option<CacheStats> {
+    None
+}
+
+/// Would `get(space, key)` be served from the cache right now? Does not touch LRU order.
+fn is_cached(&self, _space: &Space, _key: &[u8]) -> bool {
+    false
+}
+}
+
+
+/// Shared handles are storage too, so wrappers can be layered over an `Arc<dyn Storage>`.
+impl<S: Storage + ?Sized> Storage for Arc<S> {
+fn get(&self, space: &Space, key: &[u8]) -> Result<Option<Vec<u8>>> { (**self).get(space, key) }
+fn put(&self, space: &Space, key: Vec<u8>, val: Vec<u8>) -> Result<()> { (**self).put(space, key, val) }
+fn del(&self, space: &Space, key: &[u8]) -> Result<()> { (**self).del(space, key) }
+fn scan_prefix(&self, space: &Space, prefix: &[u8]) -> Result<Box<dyn Iterator<Item=(Vec<u8>, Vec<u8>)> + Send>> { (**self).scan_prefix(space, prefix) }
+fn scan_prefix_page(&self, space: &Space, prefix: &[u8], after: Option<&[u8]>, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> { (**self).scan_prefix_page(space, prefix, after, limit) }
+fn scan_range(&self, space: &Space, start: &[u8], end: Option<&[u8]>, reverse: bool, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> { (**self).scan_range(space, start, end, reverse, limit) }
+fn merge(&self, space: &Space, key: Vec<u8>, operand: Vec<u8>) -> Result<()> { (**self).merge(space, key, operand) }
+fn delete_prefix(&self, space: &Space, prefix: &[u8]) -> Result<usize> { (**self).delete_prefix(space, prefix) }
+fn get_versioned(&self, space: &Space, key: &[u8], version: u64) -> Result<Option<Vec<u8>>> { (**self).get_versioned(space, key, version) }
+fn put_versioned(&self, space: &Space, key: Vec<u8>, val: Vec<u8>, version: u64) -> Result<()> { (**self).put_versioned(space, key, val, version) }
+fn del_versioned(&self, space: &Space, key: &[u8], version: u64) -> Result<()> { (**self).del_versioned(space, key, version) }
+fn commit_writes(&self, writes: &transaction::WriteSet, version: u64) -> Result<()> { (**self).commit_writes(writes, version) }
+fn latest_version(&self, space: &Space, key: &[u8]) -> Result<Option<u64>> { (**self).latest_version(space, key) }
+fn gc_versions(&self, oldest_active: u64) -> Result<usize> { (**self).gc_versions(oldest_active) }
+fn get_with(&self, space: &Space, key: &[u8], consistency: Consistency) -> Result<Option<Vec<u8>>> { (**self).get_with(space, key, consistency) }
+fn put_with(&self, space: &Space, key: Vec<u8>, val: Vec<u8>, consistency: Consistency) -> Result<()> { (**self).put_with(space, key, val, consistency) }
+fn del_with(&self, space: &Space, key: &[u8], consistency: Consistency) -> Result<()> { (**self).del_with(space, key, consistency) }
+fn snapshot(&self) -> Result<Box<dyn ReadView>> { (**self).snapshot() }
+fn put_with_ttl(&self, space: &Space, key: Vec<u8>, val: Vec<u8>, ttl_ms: u64) -> Result<()> { (**self).put_with_ttl(space, key, val, ttl_ms) }
+fn expires_at(&self, space: &Space, key: &[u8]) -> Result<Option<u64>> { (**self).expires_at(space, key) }
+fn sweep_expired(&self) -> Result<usize> { (**self).sweep_expired() }
+fn compact_space(&self, space: &Space) -> Result<CompactionReport> { (**self).compact_space(space) }
+fn cache_stats(&self) -> Option<CacheStats> { (**self).cache_stats() }
+fn is_cached(&self, space: &Space, key: &[u8]) -> bool { (**self).is_cached(space, key) }
+}
+
+
+/// Read-only view of storage as of the moment `Storage::snapshot` was called.
+pub trait ReadView: Send + Sync {
+fn get(&self, space: &Space, key: &[u8]) -> Result<Option<Vec<u8>>>;
+fn scan_prefix(&self, space: &Space, prefix: &[u8]) -> Result<Box<dyn Iterator<Item=(Vec<u8>, Vec<u8>)> + Send>>;
 }
 
 
@@ -67,6 +295,8 @@ pub enum DataType {
     Text,
     Boolean,
     Json,
+    /// Exact decimal number; never rounded through f64 (see `numbers`)
+    Decimal,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -122,16 +352,67 @@ impl Default for IndexType {
     }
 }
 
+/// The catalog behind a lock that counts its writes, so that what is worked out
+/// from it can be kept until it changes (see `Db::catalog_version`)
+#[derive(Default)]
+pub struct CatalogLock {
+    catalog: RwLock<Catalog>,
+    version: AtomicU64,
+}
+
+impl CatalogLock {
+    pub fn read(&self) -> RwLockReadGuard<'_, Catalog> {
+        self.catalog.read()
+    }
+
+    pub fn write(&self) -> CatalogWriteGuard<'_> {
+        CatalogWriteGuard { guard: self.catalog.write(), version: &self.version }
+    }
+}
+
+/// Write access to the catalog; the catalog version moves on when it ends
+pub struct CatalogWriteGuard<'a> {
+    guard: RwLockWriteGuard<'a, Catalog>,
+    version: &'a AtomicU64,
+}
+
+impl std::ops::Deref for CatalogWriteGuard<'_> {
+    type Target = Catalog;
+
+    fn deref(&self) -> &Catalog {
+        &self.guard
+    }
+}
+
+impl std::ops::DerefMut for CatalogWriteGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Catalog {
+        &mut self.guard
+    }
+}
+
+impl Drop for CatalogWriteGuard<'_> {
+    fn drop(&mut self) {
+        // Still under the lock, so no reader sees the new catalog at the old version
+        self.version.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
 // ---------- Database handle ----------
 pub struct Db {
 pub storage: Arc<dyn Storage>,
-pub catalog: RwLock<Catalog>,
+pub catalog: CatalogLock,
 }
 
 
 impl Db { 
     pub fn new(storage: Arc<dyn Storage>) -> Self { 
-        Self { storage, catalog: RwLock::new(Catalog::default()) } 
+        Self { storage, catalog: CatalogLock::default() } 
+    }
+
+    /// How many times the catalog has been written. Read it before reading the
+    /// catalog: what was worked out from the catalog holds while this hasn't moved.
+    pub fn catalog_version(&self) -> u64 {
+        self.catalog.version.load(Ordering::SeqCst)
     }
     
     /// Create a secondary index on a table column
@@ -173,6 +454,20 @@ impl Db {
         Ok(())
     }
     
+    /// Drop a table together with its rows and secondary indexes
+    pub fn drop_table(&self, table_name: &str) -> Result<usize> {
+        let mut catalog = self.catalog.write();
+        if catalog.tables.remove(table_name).is_none() {
+            return Err(DbError::NotFound(format!("Table {} not found", table_name)));
+        }
+        let indexes: Vec<String> = catalog.indexes.iter().filter(|(_, i)| i.table == table_name).map(|(k, _)| k.clone()).collect();
+        for key in indexes {
+            catalog.indexes.remove(&key);
+            self.storage.delete_prefix(&Space(format!("index_{}", key)), b"")?;
+        }
+        self.storage.delete_prefix(&Space("data".into()), format!("tbl/{}/", table_name).as_bytes())
+    }
+    
     /// Get index information
     pub fn get_index(&self, table_name: &str, column_name: &str) -> Result<Option<IndexDef>> {
         let catalog = self.catalog.read();
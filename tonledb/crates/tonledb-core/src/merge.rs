@@ -0,0 +1,70 @@
+//! Merge operators for `Storage::merge`.
+//!
+//! A merge folds an operand into the key's current value inside the engine, so
+//! concurrent updates to counters, sets and logs need no read-modify-write in the
+//! caller. Engines pick the operator per space (see `StorageOptions::merge_operator`
+//! in `tonledb-storage`).
+
+use serde::{Deserialize, Serialize};
+use crate::{DbError, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "kind")]
+pub enum MergeOperator {
+    /// Signed 64-bit counter stored as decimal text; the operand is the delta
+    Counter,
+    /// JSON array of distinct values; the operand is a JSON array or a single value
+    /// to add. Insertion order is kept.
+    SetUnion,
+    /// Operand appended to the value, with `separator` between entries
+    Append {
+        #[serde(default)]
+        separator: String,
+    },
+}
+
+impl MergeOperator {
+    /// New value of a key holding `existing` (`None` if absent) after merging `operand`
+    pub fn apply(&self, existing: Option<&[u8]>, operand: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            MergeOperator::Counter => {
+                let cur = existing.map(parse_counter).transpose()?.unwrap_or(0);
+                let next = cur
+                    .checked_add(parse_counter(operand)?)
+                    .ok_or_else(|| DbError::Invalid("counter overflow".into()))?;
+                Ok(next.to_string().into_bytes())
+            }
+            MergeOperator::SetUnion => {
+                let mut set = match existing {
+                    Some(b) => match serde_json::from_slice(b) {
+                        Ok(serde_json::Value::Array(items)) => items,
+                        _ => return Err(DbError::Invalid("set value is not a JSON array".into())),
+                    },
+                    None => Vec::new(),
+                };
+                let add = match serde_json::from_slice(operand) {
+                    Ok(serde_json::Value::Array(items)) => items,
+                    Ok(item) => vec![item],
+                    Err(e) => return Err(DbError::Invalid(format!("set operand is not JSON: {}", e))),
+                };
+                for item in add {
+                    if !set.contains(&item) {
+                        set.push(item);
+                    }
+                }
+                serde_json::to_vec(&set).map_err(|e| DbError::Storage(e.to_string()))
+            }
+            MergeOperator::Append { separator } => Ok(match existing {
+                Some(cur) if !cur.is_empty() => [cur, separator.as_bytes(), operand].concat(),
+                _ => operand.to_vec(),
+            }),
+        }
+    }
+}
+
+fn parse_counter(b: &[u8]) -> Result<i64> {
+    std::str::from_utf8(b)
+        .ok()
+        .and_then(|s| s.trim().parse().ok())
+        .ok_or_else(|| DbError::Invalid(format!("not a counter value: {:?}", String::from_utf8_lossy(b))))
+}
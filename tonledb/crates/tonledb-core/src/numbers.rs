@@ -0,0 +1,214 @@
+//! JSON number handling: binary floats or exact decimals.
+//!
+//! With the `decimal` feature (on in the server), serde_json is built with
+//! `arbitrary_precision`, so a number keeps the digits it was written with from
+//! parsing through storage to output; without it, numbers beyond `f64` are rounded
+//! when parsed, whatever the mode. Under `NumberMode::Float` (the default, and the
+//! historical behavior) numbers written by the doc layer are still normalized
+//! through `f64`; under `NumberMode::Decimal` they are kept verbatim, so monetary
+//! values are never silently rounded.
+//!
+//! The mode is server-wide (`set_number_mode`) and can be overridden per column of
+//! a table or field of a collection (`set_column_mode`, or a `Decimal` column type).
+//! Overrides are kept in the catalog under `numbers/tbl/<table>` or
+//! `numbers/col/<collection>`, so a table and a collection of the same name each
+//! have their own.
+//! Comparisons go through `cmp_numbers`, which is exact in either mode.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use serde::{Deserialize, Serialize};
+use serde_json::{Number, Value as Json};
+use crate::{DbError, Result, Space, Storage};
+
+const CATALOG_SPACE: &str = "catalog";
+
+static DECIMAL: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NumberMode {
+    #[default]
+    Float,
+    Decimal,
+}
+
+/// Per-column overrides of one table or collection
+pub type ColumnModes = HashMap<String, NumberMode>;
+
+/// What column overrides belong to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source<'a> {
+    Table(&'a str),
+    Collection(&'a str),
+}
+
+/// Set the server-wide mode for columns without an override
+pub fn set_number_mode(mode: NumberMode) {
+    DECIMAL.store(mode == NumberMode::Decimal, AtomicOrdering::Relaxed);
+}
+
+pub fn number_mode() -> NumberMode {
+    if DECIMAL.load(AtomicOrdering::Relaxed) { NumberMode::Decimal } else { NumberMode::Float }
+}
+
+/// Override the mode of `column` in table or collection `source`; `None` removes the override
+pub fn set_column_mode<S: Storage + ?Sized>(storage: &S, source: Source, column: &str, mode: Option<NumberMode>) -> Result<()> {
+    let mut modes = column_modes(storage, source)?;
+    match mode {
+        Some(m) => { modes.insert(column.to_string(), m); }
+        None => { modes.remove(column); }
+    }
+    let bytes = serde_json::to_vec(&modes).map_err(|e| DbError::Storage(e.to_string()))?;
+    storage.put(&Space(CATALOG_SPACE.into()), modes_key(source), bytes)
+}
+
+/// Overrides registered for `source`
+pub fn column_modes<S: Storage + ?Sized>(storage: &S, source: Source) -> Result<ColumnModes> {
+    match storage.get(&Space(CATALOG_SPACE.into()), &modes_key(source))? {
+        Some(b) => serde_json::from_slice(&b).map_err(|e| DbError::Storage(format!("corrupt number modes: {}", e))),
+        None => Ok(ColumnModes::new()),
+    }
+}
+
+/// Mode in effect for `column`
+pub fn mode_for(modes: &ColumnModes, column: &str) -> NumberMode {
+    modes.get(column).copied().unwrap_or_else(number_mode)
+}
+
+/// Apply number modes to a document about to be stored: numbers under float-mode
+/// top-level fields (nested values included) are rounded through `f64`.
+pub fn normalize_doc(doc: &mut Json, modes: &ColumnModes) {
+    match doc.as_object_mut() {
+        Some(obj) => {
+            for (field, v) in obj.iter_mut() {
+                if mode_for(modes, field) == NumberMode::Float {
+                    round_numbers(v);
+                }
+            }
+        }
+        None if number_mode() == NumberMode::Float => round_numbers(doc),
+        None => {}
+    }
+}
+
+/// Number for literal text such as `12.50`, exact in decimal mode
+pub fn number_literal(text: &str, mode: NumberMode) -> Option<Json> {
+    let exact: Number = serde_json::from_str(text).ok()?;
+    Some(Json::Number(match mode {
+        NumberMode::Decimal => exact,
+        NumberMode::Float => to_float(&exact)?,
+    }))
+}
+
+/// Exact numeric order of two JSON numbers; `1`, `1.0` and `1e0` are equal
+pub fn cmp_numbers(a: &Number, b: &Number) -> Option<Ordering> {
+    Some(Decimal::parse(&a.to_string())?.cmp(&Decimal::parse(&b.to_string())?))
+}
+
+/// JSON equality that compares numbers by value rather than by representation
+pub fn values_equal(a: &Json, b: &Json) -> bool {
+    match (a, b) {
+        (Json::Number(x), Json::Number(y)) => cmp_numbers(x, y) == Some(Ordering::Equal),
+        _ => a == b,
+    }
+}
+
+/// Canonical text of a number's value, equal for numbers that compare equal
+pub fn canonical(n: &Number) -> Option<String> {
+    let d = Decimal::parse(&n.to_string())?;
+    Some(format!("{}0.{}e{}", if d.neg { "-" } else { "" }, String::from_utf8_lossy(&d.digits), d.exp))
+}
+
+// ---------- helpers ----------
+
+fn modes_key(source: Source) -> Vec<u8> {
+    match source {
+        Source::Table(name) => format!("numbers/tbl/{}", name).into_bytes(),
+        Source::Collection(name) => format!("numbers/col/{}", name).into_bytes(),
+    }
+}
+
+fn round_numbers(v: &mut Json) {
+    match v {
+        Json::Number(n) => {
+            if let Some(f) = to_float(n) {
+                *n = f;
+            }
+        }
+        Json::Array(items) => items.iter_mut().for_each(round_numbers),
+        Json::Object(obj) => obj.values_mut().for_each(round_numbers),
+        _ => {}
+    }
+}
+
+/// Integers that fit 64 bits are exact either way and stay integers
+fn to_float(n: &Number) -> Option<Number> {
+    if n.is_i64() || n.is_u64() {
+        return Some(n.clone());
+    }
+    Number::from_f64(n.as_f64()?)
+}
+
+/// Sign, significant digits and exponent: the value is `0.<digits> * 10^exp`.
+/// Zero has no digits and is never negative.
+#[derive(PartialEq, Eq)]
+struct Decimal {
+    neg: bool,
+    digits: Vec<u8>,
+    exp: i64,
+}
+
+impl Decimal {
+    fn parse(s: &str) -> Option<Self> {
+        let (neg, s) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let (mantissa, exp) = match s.find(['e', 'E']) {
+            Some(i) => (&s[..i], s[i + 1..].trim_start_matches('+').parse::<i64>().ok()?),
+            None => (s, 0),
+        };
+        let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        if int.is_empty() || !int.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let all: Vec<u8> = int.bytes().chain(frac.bytes()).collect();
+        let lead = all.iter().take_while(|b| **b == b'0').count();
+        let mut digits = all[lead..].to_vec();
+        while digits.last() == Some(&b'0') {
+            digits.pop();
+        }
+        if digits.is_empty() {
+            return Some(Self { neg: false, digits, exp: 0 });
+        }
+        Some(Self { neg, digits, exp: exp + int.len() as i64 - lead as i64 })
+    }
+
+    fn signum(&self) -> i8 {
+        match (self.digits.is_empty(), self.neg) {
+            (true, _) => 0,
+            (false, true) => -1,
+            (false, false) => 1,
+        }
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let by_sign = self.signum().cmp(&other.signum());
+        if by_sign != Ordering::Equal || self.digits.is_empty() {
+            return by_sign;
+        }
+        // Same sign: larger exponent is larger magnitude; equal exponents compare digit by digit
+        let magnitude = self.exp.cmp(&other.exp).then_with(|| self.digits.cmp(&other.digits));
+        if self.neg { magnitude.reverse() } else { magnitude }
+    }
+}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
@@ -0,0 +1,88 @@
+//! Object storage abstraction used for cold tiers, archives and backups
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::{DbError, Result};
+
+#[cfg(feature = "s3")]
+mod s3;
+#[cfg(feature = "s3")]
+pub use s3::{sigv4_authorization, S3Config, S3ObjectStore};
+
+/// Minimal blob store: flat `/`-separated object paths mapped to byte blobs
+pub trait ObjectStore: Send + Sync {
+    fn put(&self, path: &str, data: Vec<u8>) -> Result<()>;
+    fn get(&self, path: &str) -> Result<Option<Vec<u8>>>;
+    fn delete(&self, path: &str) -> Result<()>;
+    /// Paths starting with `prefix`, sorted
+    fn list(&self, prefix: &str) -> Result<Vec<String>>;
+}
+
+/// Object store backed by a local directory (or a mounted bucket)
+pub struct LocalObjectStore {
+    root: PathBuf,
+}
+
+impl LocalObjectStore {
+    pub fn new(root: impl Into<PathBuf>) -> Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(&root).map_err(|e| DbError::Storage(format!("object store root: {}", e)))?;
+        Ok(Self { root })
+    }
+
+    fn resolve(&self, path: &str) -> Result<PathBuf> {
+        if path.split('/').any(|seg| seg == ".." || seg.is_empty()) {
+            return Err(DbError::Invalid(format!("invalid object path: {}", path)));
+        }
+        Ok(self.root.join(path))
+    }
+
+    fn walk(&self, dir: &Path, out: &mut Vec<String>) -> std::io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let p = entry?.path();
+            if p.is_dir() {
+                self.walk(&p, out)?;
+            } else if let Ok(rel) = p.strip_prefix(&self.root) {
+                out.push(rel.to_string_lossy().replace('\\', "/"));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ObjectStore for LocalObjectStore {
+    fn put(&self, path: &str, data: Vec<u8>) -> Result<()> {
+        let p = self.resolve(path)?;
+        if let Some(parent) = p.parent() {
+            fs::create_dir_all(parent).map_err(|e| DbError::Storage(e.to_string()))?;
+        }
+        // Write-then-rename so readers never see a partial object
+        let tmp = p.with_extension("tmp");
+        fs::write(&tmp, data).map_err(|e| DbError::Storage(e.to_string()))?;
+        fs::rename(&tmp, &p).map_err(|e| DbError::Storage(e.to_string()))
+    }
+
+    fn get(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        match fs::read(self.resolve(path)?) {
+            Ok(b) => Ok(Some(b)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(DbError::Storage(e.to_string())),
+        }
+    }
+
+    fn delete(&self, path: &str) -> Result<()> {
+        match fs::remove_file(self.resolve(path)?) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(DbError::Storage(e.to_string())),
+        }
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut out = Vec::new();
+        self.walk(&self.root, &mut out).map_err(|e| DbError::Storage(e.to_string()))?;
+        out.retain(|p| p.starts_with(prefix));
+        out.sort();
+        Ok(out)
+    }
+}
@@ -0,0 +1,208 @@
+//! S3 and S3-compatible object storage (MinIO, R2, ...), signed with AWS
+//! Signature Version 4. Objects are addressed path-style
+//! (`<endpoint>/<bucket>/<prefix><path>`), which every S3-compatible service
+//! accepts. Requests are blocking, so the store can be used from any thread
+//! without an async runtime.
+
+use std::io::Read;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use super::ObjectStore;
+use crate::{DbError, Result};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Where an `S3ObjectStore` keeps its objects, and the credentials to reach them
+#[derive(Debug, Clone)]
+pub struct S3Config {
+    /// e.g. `https://s3.eu-west-1.amazonaws.com` or `http://minio:9000`
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: String,
+    /// Prepended to every object path, e.g. `tonledb/prod/`
+    pub prefix: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+impl S3Config {
+    /// Config with credentials from `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and
+    /// (optionally) `AWS_SESSION_TOKEN`
+    pub fn from_env(endpoint: &str, region: &str, bucket: &str, prefix: &str) -> Result<Self> {
+        let var = |name: &str| std::env::var(name).map_err(|_| DbError::Invalid(format!("{} is not set", name)));
+        Ok(Self {
+            endpoint: endpoint.to_string(),
+            region: region.to_string(),
+            bucket: bucket.to_string(),
+            prefix: prefix.to_string(),
+            access_key_id: var("AWS_ACCESS_KEY_ID")?,
+            secret_access_key: var("AWS_SECRET_ACCESS_KEY")?,
+            session_token: std::env::var("AWS_SESSION_TOKEN").ok(),
+        })
+    }
+}
+
+/// Object store in an S3 bucket
+pub struct S3ObjectStore {
+    cfg: S3Config,
+    /// Host (and port) of the endpoint, as signed
+    host: String,
+    agent: ureq::Agent,
+}
+
+impl S3ObjectStore {
+    pub fn new(cfg: S3Config) -> Result<Self> {
+        let host = cfg.endpoint.split_once("://").map_or(cfg.endpoint.as_str(), |(_, rest)| rest).trim_end_matches('/').to_string();
+        if host.is_empty() || host.contains('/') || cfg.bucket.is_empty() {
+            return Err(DbError::Invalid(format!("invalid S3 endpoint or bucket: {} {}", cfg.endpoint, cfg.bucket)));
+        }
+        let agent = ureq::AgentBuilder::new().timeout(REQUEST_TIMEOUT).build();
+        Ok(Self { cfg, host, agent })
+    }
+
+    /// Send a signed request for the object at `path` (under the prefix) or, with an
+    /// empty path, the bucket. `None` if it was not found.
+    fn send(&self, method: &str, path: &str, query: &[(&str, &str)], body: &[u8]) -> Result<Option<ureq::Response>> {
+        let key = if path.is_empty() { String::new() } else { format!("{}{}", self.cfg.prefix, path) };
+        let uri = format!("/{}/{}", uri_encode(&self.cfg.bucket, true), uri_encode(&key, false));
+        let mut params: Vec<(String, String)> = query.iter().map(|(k, v)| (uri_encode(k, true), uri_encode(v, true))).collect();
+        params.sort();
+        let query = params.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join("&");
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let amz_date = amz_date(now);
+        let payload_hash = hex::encode(Sha256::digest(body));
+        let mut headers = vec![("host", self.host.clone()), ("x-amz-content-sha256", payload_hash.clone()), ("x-amz-date", amz_date.clone())];
+        if let Some(token) = &self.cfg.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+        let authorization = sigv4_authorization(&self.cfg, method, &uri, &query, &headers, &payload_hash);
+
+        let url = if query.is_empty() { format!("{}{}", self.cfg.endpoint.trim_end_matches('/'), uri) } else { format!("{}{}?{}", self.cfg.endpoint.trim_end_matches('/'), uri, query) };
+        let mut req = self.agent.request(method, &url).set("Authorization", &authorization);
+        // ureq sets Host itself, from the same endpoint
+        for (k, v) in headers.iter().filter(|(k, _)| *k != "host") {
+            req = req.set(k, v);
+        }
+        match req.send_bytes(body) {
+            Ok(resp) => Ok(Some(resp)),
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(ureq::Error::Status(code, resp)) => Err(DbError::Storage(format!("S3 {} {}: HTTP {}: {}", method, key, code, resp.into_string().unwrap_or_default()))),
+            Err(e) => Err(DbError::Storage(format!("S3 {} {}: {}", method, key, e))),
+        }
+    }
+}
+
+impl ObjectStore for S3ObjectStore {
+    fn put(&self, path: &str, data: Vec<u8>) -> Result<()> {
+        self.send("PUT", path, &[], &data)?.ok_or_else(|| DbError::Storage(format!("S3 bucket {} not found", self.cfg.bucket)))?;
+        Ok(())
+    }
+
+    fn get(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        let Some(resp) = self.send("GET", path, &[], &[])? else { return Ok(None) };
+        let mut data = Vec::new();
+        resp.into_reader().read_to_end(&mut data).map_err(|e| DbError::Storage(format!("S3 GET {}: {}", path, e)))?;
+        Ok(Some(data))
+    }
+
+    fn delete(&self, path: &str) -> Result<()> {
+        self.send("DELETE", path, &[], &[])?;
+        Ok(())
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        let full = format!("{}{}", self.cfg.prefix, prefix);
+        let mut out = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", full.as_str())];
+            if let Some(t) = &token {
+                query.push(("continuation-token", t.as_str()));
+            }
+            let resp = self.send("GET", "", &query, &[])?.ok_or_else(|| DbError::Storage(format!("S3 bucket {} not found", self.cfg.bucket)))?;
+            let xml = resp.into_string().map_err(|e| DbError::Storage(format!("S3 list {}: {}", prefix, e)))?;
+            out.extend(xml_values(&xml, "Key").into_iter().filter_map(|k| k.strip_prefix(&self.cfg.prefix).map(str::to_string)));
+            token = xml_values(&xml, "NextContinuationToken").into_iter().next();
+            if token.is_none() || !xml.contains("<IsTruncated>true</IsTruncated>") {
+                break;
+            }
+        }
+        out.sort();
+        Ok(out)
+    }
+}
+
+/// `Authorization` header of an S3 request signed with AWS Signature Version 4. `uri`
+/// and `query` are canonical already (encoded, parameters sorted); `headers` are the
+/// lowercase names and trimmed values to sign, sorted by name, `x-amz-date` among them.
+pub fn sigv4_authorization(cfg: &S3Config, method: &str, uri: &str, query: &str, headers: &[(&str, String)], payload_hash: &str) -> String {
+    let amz_date = headers.iter().find(|(k, _)| *k == "x-amz-date").map_or("", |(_, v)| v.as_str());
+    let signed_headers = headers.iter().map(|(k, _)| *k).collect::<Vec<_>>().join(";");
+    let canonical_headers: String = headers.iter().map(|(k, v)| format!("{}:{}\n", k, v.trim())).collect();
+    let canonical_request = format!("{}\n{}\n{}\n{}\n{}\n{}", method, uri, query, canonical_headers, signed_headers, payload_hash);
+
+    let day = amz_date.get(..8).unwrap_or_default();
+    let scope = format!("{}/{}/s3/aws4_request", day, cfg.region);
+    let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", amz_date, scope, hex::encode(Sha256::digest(canonical_request.as_bytes())));
+    let mut signing_key = hmac(format!("AWS4{}", cfg.secret_access_key).as_bytes(), day);
+    for part in [cfg.region.as_str(), "s3", "aws4_request"] {
+        signing_key = hmac(&signing_key, part);
+    }
+    let signature = hex::encode(hmac(&signing_key, &string_to_sign));
+    format!("AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}", cfg.access_key_id, scope, signed_headers, signature)
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encoding as SigV4 canonicalises it: everything but unreserved characters,
+/// and `/` too unless `slash` is false
+fn uri_encode(s: &str, slash: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => out.push(b as char),
+            b'/' if !slash => out.push('/'),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+/// `YYYYMMDDTHHMMSSZ` of `secs` since the epoch
+fn amz_date(secs: u64) -> String {
+    let (days, rem) = ((secs / 86_400) as i64, secs % 86_400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}{:02}{:02}T{:02}{:02}{:02}Z", year, month, day, rem / 3_600, rem % 3_600 / 60, rem % 60)
+}
+
+/// Text of every `<tag>` element of a response, unescaped
+fn xml_values(xml: &str, tag: &str) -> Vec<String> {
+    let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+    let mut out = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        let after = &rest[start + open.len()..];
+        let Some(end) = after.find(&close) else { break };
+        out.push(after[..end].replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&apos;", "'").replace("&amp;", "&"));
+        rest = &after[end + close.len()..];
+    }
+    out
+}
@@ -0,0 +1,176 @@
+//! Per-request tracing of storage operations (debug mode)
+//!
+//! Wrap the storage in `TracedStorage` once at startup; operations are only
+//! recorded while a `collect` call is active on the current thread, so the
+//! wrapper costs one thread-local check per call otherwise.
+
+use std::cell::RefCell;
+use std::sync::Arc;
+use std::time::Instant;
+use serde::Serialize;
+use crate::transaction::WriteSet;
+use crate::{CacheStats, CompactionReport, Consistency, ReadView, Result, Space, Storage};
+
+/// One storage call made while serving a traced request
+#[derive(Debug, Clone, Serialize)]
+pub struct StorageOp {
+    pub op: &'static str,
+    pub space: String,
+    pub key: String,
+    pub micros: u64,
+}
+
+thread_local! {
+    static ACTIVE: RefCell<Option<Vec<StorageOp>>> = const { RefCell::new(None) };
+}
+
+/// Run `f`, returning its result and every storage operation it performed on this thread.
+pub fn collect<T>(f: impl FnOnce() -> T) -> (T, Vec<StorageOp>) {
+    let prev = ACTIVE.with(|a| a.borrow_mut().replace(Vec::new()));
+    let out = f();
+    let ops = ACTIVE.with(|a| std::mem::replace(&mut *a.borrow_mut(), prev)).unwrap_or_default();
+    (out, ops)
+}
+
+fn is_active() -> bool {
+    ACTIVE.with(|a| a.borrow().is_some())
+}
+
+fn record(op: &'static str, space: &Space, key: &[u8], start: Instant) {
+    let entry = StorageOp {
+        op,
+        space: space.0.clone(),
+        key: String::from_utf8_lossy(key).into_owned(),
+        micros: start.elapsed().as_micros() as u64,
+    };
+    ACTIVE.with(|a| {
+        if let Some(ops) = a.borrow_mut().as_mut() {
+            ops.push(entry);
+        }
+    });
+}
+
+/// Times a storage call when tracing is active
+fn traced<T>(op: &'static str, space: &Space, key: &[u8], f: impl FnOnce() -> T) -> T {
+    if !is_active() {
+        return f();
+    }
+    let start = Instant::now();
+    let out = f();
+    record(op, space, key, start);
+    out
+}
+
+/// Storage wrapper that reports calls to the active request trace
+pub struct TracedStorage {
+    inner: Arc<dyn Storage>,
+}
+
+impl TracedStorage {
+    pub fn new(inner: Arc<dyn Storage>) -> Self {
+        Self { inner }
+    }
+}
+
+impl Storage for TracedStorage {
+    fn get(&self, space: &Space, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        traced("get", space, key, || self.inner.get(space, key))
+    }
+
+    fn put(&self, space: &Space, key: Vec<u8>, val: Vec<u8>) -> Result<()> {
+        let k = key.clone();
+        traced("put", space, &k, || self.inner.put(space, key, val))
+    }
+
+    fn del(&self, space: &Space, key: &[u8]) -> Result<()> {
+        traced("del", space, key, || self.inner.del(space, key))
+    }
+
+    fn scan_prefix(&self, space: &Space, prefix: &[u8]) -> Result<Box<dyn Iterator<Item=(Vec<u8>, Vec<u8>)> + Send>> {
+        traced("scan", space, prefix, || self.inner.scan_prefix(space, prefix))
+    }
+
+    fn scan_prefix_page(&self, space: &Space, prefix: &[u8], after: Option<&[u8]>, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        traced("scan", space, prefix, || self.inner.scan_prefix_page(space, prefix, after, limit))
+    }
+
+    fn scan_range(&self, space: &Space, start: &[u8], end: Option<&[u8]>, reverse: bool, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        traced("scan", space, start, || self.inner.scan_range(space, start, end, reverse, limit))
+    }
+
+    fn merge(&self, space: &Space, key: Vec<u8>, operand: Vec<u8>) -> Result<()> {
+        let k = key.clone();
+        traced("merge", space, &k, || self.inner.merge(space, key, operand))
+    }
+
+    fn delete_prefix(&self, space: &Space, prefix: &[u8]) -> Result<usize> {
+        traced("delete_prefix", space, prefix, || self.inner.delete_prefix(space, prefix))
+    }
+
+    fn get_versioned(&self, space: &Space, key: &[u8], version: u64) -> Result<Option<Vec<u8>>> {
+        traced("get_versioned", space, key, || self.inner.get_versioned(space, key, version))
+    }
+
+    fn put_versioned(&self, space: &Space, key: Vec<u8>, val: Vec<u8>, version: u64) -> Result<()> {
+        let k = key.clone();
+        traced("put_versioned", space, &k, || self.inner.put_versioned(space, key, val, version))
+    }
+
+    fn del_versioned(&self, space: &Space, key: &[u8], version: u64) -> Result<()> {
+        traced("del_versioned", space, key, || self.inner.del_versioned(space, key, version))
+    }
+
+    fn commit_writes(&self, writes: &WriteSet, version: u64) -> Result<()> {
+        self.inner.commit_writes(writes, version)
+    }
+
+    fn latest_version(&self, space: &Space, key: &[u8]) -> Result<Option<u64>> {
+        self.inner.latest_version(space, key)
+    }
+
+    fn gc_versions(&self, oldest_active: u64) -> Result<usize> {
+        self.inner.gc_versions(oldest_active)
+    }
+
+    fn get_with(&self, space: &Space, key: &[u8], consistency: Consistency) -> Result<Option<Vec<u8>>> {
+        traced("get", space, key, || self.inner.get_with(space, key, consistency))
+    }
+
+    fn put_with(&self, space: &Space, key: Vec<u8>, val: Vec<u8>, consistency: Consistency) -> Result<()> {
+        let k = key.clone();
+        traced("put", space, &k, || self.inner.put_with(space, key, val, consistency))
+    }
+
+    fn del_with(&self, space: &Space, key: &[u8], consistency: Consistency) -> Result<()> {
+        traced("del", space, key, || self.inner.del_with(space, key, consistency))
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn ReadView>> {
+        self.inner.snapshot()
+    }
+
+    fn put_with_ttl(&self, space: &Space, key: Vec<u8>, val: Vec<u8>, ttl_ms: u64) -> Result<()> {
+        let k = key.clone();
+        traced("put", space, &k, || self.inner.put_with_ttl(space, key, val, ttl_ms))
+    }
+
+    fn expires_at(&self, space: &Space, key: &[u8]) -> Result<Option<u64>> {
+        self.inner.expires_at(space, key)
+    }
+
+    fn sweep_expired(&self) -> Result<usize> {
+        self.inner.sweep_expired()
+    }
+
+    fn compact_space(&self, space: &Space) -> Result<CompactionReport> {
+        self.inner.compact_space(space)
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.inner.cache_stats()
+    }
+
+    fn is_cached(&self, space: &Space, key: &[u8]) -> bool {
+        self.inner.is_cached(space, key)
+    }
+}
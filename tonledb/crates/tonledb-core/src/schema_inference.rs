@@ -0,0 +1,170 @@
+//! Optimistic schema-on-read for document collections.
+//!
+//! A sample of a collection's documents is scanned to infer which top-level
+//! fields exist, what JSON types they hold and how often they are null or missing.
+//! The result is kept in the catalog under `schema/<collection>` and is used to
+//! give SQL, the LSP and the Arrow/Parquet exporters a column layout.
+
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as Json;
+use crate::{DataType, DbError, Result, Space, Storage};
+
+const CATALOG_SPACE: &str = "catalog";
+const DATA_SPACE: &str = "data";
+
+/// Documents sampled per refresh unless the caller asks otherwise
+pub const DEFAULT_SAMPLE_SIZE: usize = 1000;
+
+/// JSON value kinds seen for a field
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JsonType {
+    Bool,
+    Integer,
+    Float,
+    String,
+    Array,
+    Object,
+}
+
+impl JsonType {
+    /// Kind of `v`, or `None` for JSON null
+    pub fn of(v: &Json) -> Option<Self> {
+        match v {
+            Json::Null => None,
+            Json::Bool(_) => Some(JsonType::Bool),
+            Json::Number(n) if n.is_i64() || n.is_u64() => Some(JsonType::Integer),
+            Json::Number(_) => Some(JsonType::Float),
+            Json::String(_) => Some(JsonType::String),
+            Json::Array(_) => Some(JsonType::Array),
+            Json::Object(_) => Some(JsonType::Object),
+        }
+    }
+}
+
+/// What the sample says about one field
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InferredField {
+    pub name: String,
+    /// Non-null kinds observed, sorted
+    pub types: Vec<JsonType>,
+    /// Fraction of sampled documents where the field was missing or null
+    pub null_rate: f64,
+}
+
+impl InferredField {
+    /// Column type for SQL and Arrow. Integers mixed with floats widen to `Float`;
+    /// any other mix, and nested values, are exposed as `Json`.
+    pub fn data_type(&self) -> DataType {
+        match self.types.as_slice() {
+            [] | [JsonType::String] => DataType::Text,
+            [JsonType::Bool] => DataType::Boolean,
+            [JsonType::Integer] => DataType::Integer,
+            [JsonType::Float] | [JsonType::Integer, JsonType::Float] => DataType::Float,
+            _ => DataType::Json,
+        }
+    }
+}
+
+/// Inferred schema of a collection
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InferredSchema {
+    pub collection: String,
+    /// Number of documents the schema was inferred from
+    pub sampled: usize,
+    /// Fields sorted by name
+    pub fields: Vec<InferredField>,
+    pub updated_ms: u64,
+}
+
+impl InferredSchema {
+    pub fn field(&self, name: &str) -> Option<&InferredField> {
+        self.fields.iter().find(|f| f.name == name)
+    }
+}
+
+/// Infer a schema from `docs`. Non-object documents count towards `sampled` only.
+pub fn infer<'a>(collection: &str, docs: impl IntoIterator<Item = &'a Json>) -> InferredSchema {
+    // field -> (non-null count, kinds seen)
+    let mut seen: BTreeMap<String, (usize, Vec<JsonType>)> = BTreeMap::new();
+    let mut sampled = 0;
+    for doc in docs {
+        sampled += 1;
+        let Some(obj) = doc.as_object() else { continue };
+        for (name, v) in obj {
+            let entry = seen.entry(name.clone()).or_default();
+            if let Some(t) = JsonType::of(v) {
+                entry.0 += 1;
+                if !entry.1.contains(&t) {
+                    entry.1.push(t);
+                }
+            }
+        }
+    }
+    let fields = seen
+        .into_iter()
+        .map(|(name, (present, mut types))| {
+            types.sort();
+            let null_rate = if sampled == 0 { 0.0 } else { 1.0 - present as f64 / sampled as f64 };
+            InferredField { name, types, null_rate }
+        })
+        .collect();
+    InferredSchema { collection: collection.to_string(), sampled, fields, updated_ms: now_ms() }
+}
+
+/// Sample up to `sample_size` documents spread evenly across `collection` and infer its schema.
+pub fn infer_collection<S: Storage + ?Sized>(storage: &S, collection: &str, sample_size: usize) -> Result<InferredSchema> {
+    let prefix = format!("doc/{}/", collection).into_bytes();
+    let values: Vec<Vec<u8>> = storage.scan_prefix(&Space(DATA_SPACE.into()), &prefix)?.map(|(_, v)| v).collect();
+    let step = values.len().div_ceil(sample_size.max(1)).max(1);
+    let docs: Vec<Json> = values
+        .iter()
+        .step_by(step)
+        .filter_map(|v| serde_json::from_slice(v).ok())
+        .collect();
+    Ok(infer(collection, &docs))
+}
+
+/// Re-infer the schema of `collection` and store it in the catalog.
+pub fn refresh<S: Storage + ?Sized>(storage: &S, collection: &str, sample_size: usize) -> Result<InferredSchema> {
+    let schema = infer_collection(storage, collection, sample_size)?;
+    let bytes = serde_json::to_vec(&schema).map_err(|e| DbError::Storage(e.to_string()))?;
+    storage.put(&Space(CATALOG_SPACE.into()), schema_key(collection), bytes)?;
+    Ok(schema)
+}
+
+/// The stored schema of `collection`, if it was ever inferred
+pub fn load<S: Storage + ?Sized>(storage: &S, collection: &str) -> Result<Option<InferredSchema>> {
+    match storage.get(&Space(CATALOG_SPACE.into()), &schema_key(collection))? {
+        Some(b) => serde_json::from_slice(&b).map(Some).map_err(|e| DbError::Storage(format!("corrupt inferred schema: {}", e))),
+        None => Ok(None),
+    }
+}
+
+/// The stored schema, inferring and storing it first if there is none
+pub fn load_or_refresh<S: Storage + ?Sized>(storage: &S, collection: &str) -> Result<InferredSchema> {
+    match load(storage, collection)? {
+        Some(s) => Ok(s),
+        None => refresh(storage, collection, DEFAULT_SAMPLE_SIZE),
+    }
+}
+
+/// Every stored schema
+pub fn list<S: Storage + ?Sized>(storage: &S) -> Result<Vec<InferredSchema>> {
+    storage
+        .scan_prefix(&Space(CATALOG_SPACE.into()), b"schema/")?
+        .map(|(_, v)| serde_json::from_slice(&v).map_err(|e| DbError::Storage(format!("corrupt inferred schema: {}", e))))
+        .collect()
+}
+
+fn schema_key(collection: &str) -> Vec<u8> {
+    format!("schema/{}", collection).into_bytes()
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
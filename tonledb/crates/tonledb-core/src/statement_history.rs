@@ -0,0 +1,232 @@
+//! Per-user history of executed statements, persisted in `Space("sys_history")`
+//! for compliance reviews and "what did I run yesterday".
+//!
+//! Every statement is stored with a fingerprint (hash of the text with literals
+//! replaced by `?`, so runs of the same query with different values group
+//! together), its timing and outcome, and optionally the full text. Retention is
+//! applied every `PRUNE_EVERY` statements, or on demand with `prune`.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use serde::{Deserialize, Serialize};
+use crate::{DbError, Result, Space, Storage};
+
+const HISTORY_SPACE: &str = "sys_history";
+/// Statements recorded between automatic retention passes
+pub const PRUNE_EVERY: u64 = 64;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StatementStatus {
+    Ok,
+    Error,
+}
+
+/// One executed statement
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatementRecord {
+    pub seq: u64,
+    pub user: String,
+    pub fingerprint: String,
+    /// Full statement text, kept only when `HistoryRetention::store_text` is set
+    pub text: Option<String>,
+    pub started_ms: u64,
+    pub duration_ms: u64,
+    pub status: StatementStatus,
+    pub error: Option<String>,
+}
+
+/// How much history is kept
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryRetention {
+    /// Drop statements older than this
+    #[serde(default)]
+    pub max_age_ms: Option<u64>,
+    /// Keep at most this many statements per user, newest first
+    #[serde(default)]
+    pub max_per_user: Option<usize>,
+    /// Store the full text next to the fingerprint. Off keeps literals (which may be
+    /// sensitive) out of the history.
+    #[serde(default)]
+    pub store_text: bool,
+}
+
+impl Default for HistoryRetention {
+    fn default() -> Self {
+        Self { max_age_ms: Some(30 * 24 * 3600 * 1000), max_per_user: Some(10_000), store_text: false }
+    }
+}
+
+/// Filters for querying the history
+#[derive(Debug, Clone, Default)]
+pub struct HistoryQuery {
+    pub user: Option<String>,
+    pub since_ms: Option<u64>,
+    pub until_ms: Option<u64>,
+    pub fingerprint: Option<String>,
+    pub status: Option<StatementStatus>,
+    pub limit: Option<usize>,
+}
+
+pub struct StatementHistory {
+    storage: Arc<dyn Storage>,
+    retention: HistoryRetention,
+    seq: AtomicU64,
+}
+
+impl StatementHistory {
+    pub fn new(storage: Arc<dyn Storage>, retention: HistoryRetention) -> Result<Self> {
+        // Continue numbering after the newest persisted statement
+        let last_seq = storage
+            .scan_prefix(&Space(HISTORY_SPACE.into()), b"")?
+            .filter_map(|(k, _)| k.len().checked_sub(8).map(|at| u64::from_be_bytes(k[at..].try_into().unwrap())))
+            .max()
+            .unwrap_or(0);
+        Ok(Self { storage, retention, seq: AtomicU64::new(last_seq) })
+    }
+
+    pub fn retention(&self) -> &HistoryRetention {
+        &self.retention
+    }
+
+    /// Persist one executed statement; `outcome` carries the error message of a failed one
+    pub fn record(&self, user: &str, sql: &str, started_ms: u64, duration_ms: u64, outcome: std::result::Result<(), String>) -> Result<StatementRecord> {
+        if user.as_bytes().contains(&0) {
+            return Err(DbError::Invalid("user name may not contain NUL".into()));
+        }
+        let rec = StatementRecord {
+            seq: self.seq.fetch_add(1, Ordering::SeqCst) + 1,
+            user: user.to_string(),
+            fingerprint: fingerprint(sql),
+            text: self.retention.store_text.then(|| sql.to_string()),
+            started_ms,
+            duration_ms,
+            status: if outcome.is_ok() { StatementStatus::Ok } else { StatementStatus::Error },
+            error: outcome.err(),
+        };
+        let val = serde_json::to_vec(&rec).map_err(|e| DbError::Storage(e.to_string()))?;
+        self.storage.put(&space(), record_key(user, rec.started_ms, rec.seq), val)?;
+        if rec.seq.is_multiple_of(PRUNE_EVERY) {
+            self.prune()?;
+        }
+        Ok(rec)
+    }
+
+    /// Statements matching `q`, oldest first
+    pub fn query(&self, q: &HistoryQuery) -> Result<Vec<StatementRecord>> {
+        let prefix = q.user.as_deref().map(user_prefix).unwrap_or_default();
+        let mut out = Vec::new();
+        for (_, v) in self.storage.scan_prefix(&space(), &prefix)? {
+            let rec: StatementRecord = serde_json::from_slice(&v)
+                .map_err(|e| DbError::Storage(format!("corrupt statement history: {}", e)))?;
+            if q.since_ms.is_some_and(|t| rec.started_ms < t)
+                || q.until_ms.is_some_and(|t| rec.started_ms >= t)
+                || q.fingerprint.as_ref().is_some_and(|f| *f != rec.fingerprint)
+                || q.status.is_some_and(|s| s != rec.status)
+            {
+                continue;
+            }
+            out.push(rec);
+        }
+        // Keys sort by user first; order across users by time
+        out.sort_by_key(|r| (r.started_ms, r.seq));
+        if let Some(limit) = q.limit {
+            // Keep the most recent `limit` statements
+            let skip = out.len().saturating_sub(limit);
+            out.drain(..skip);
+        }
+        Ok(out)
+    }
+
+    /// Apply retention to every user's history. Returns the number of statements removed.
+    pub fn prune(&self) -> Result<usize> {
+        let now = now_ms();
+        let mut users: Vec<String> = Vec::new();
+        for (k, _) in self.storage.scan_prefix(&space(), b"")? {
+            let Some(end) = k.iter().position(|b| *b == 0) else { continue };
+            let user = String::from_utf8_lossy(&k[..end]).into_owned();
+            if users.last() != Some(&user) {
+                users.push(user);
+            }
+        }
+        let mut removed = 0;
+        for user in users {
+            removed += self.prune_user(&user, now)?;
+        }
+        Ok(removed)
+    }
+
+    fn prune_user(&self, user: &str, now: u64) -> Result<usize> {
+        let keys: Vec<Vec<u8>> = self.storage.scan_prefix(&space(), &user_prefix(user))?.map(|(k, _)| k).collect();
+        let over_cap = self.retention.max_per_user.map_or(0, |cap| keys.len().saturating_sub(cap));
+        let cutoff = self.retention.max_age_ms.map(|age| now.saturating_sub(age));
+        let mut removed = 0;
+        // Oldest first, so the cap drops the oldest statements
+        for (i, k) in keys.iter().enumerate() {
+            let ts = u64::from_be_bytes(k[user.len() + 1..user.len() + 9].try_into().unwrap());
+            if i < over_cap || cutoff.is_some_and(|c| ts < c) {
+                self.storage.del(&space(), k)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// Hash of `sql` with string and numeric literals replaced by `?` and whitespace
+/// collapsed, as 16 hex digits (FNV-1a, stable across builds)
+pub fn fingerprint(sql: &str) -> String {
+    format!("{:016x}", normalize(sql).bytes().fold(0xcbf29ce484222325u64, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3)))
+}
+
+/// Statement text as fingerprinted: literals replaced by `?`, keywords and
+/// identifiers lowercased, whitespace collapsed
+pub fn normalize(sql: &str) -> String {
+    let mut out = String::with_capacity(sql.len());
+    let mut chars = sql.trim().trim_end_matches(';').chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                // '' escapes a quote inside a literal
+                while let Some(d) = chars.next() {
+                    if d == '\'' && chars.next_if_eq(&'\'').is_none() {
+                        break;
+                    }
+                }
+                out.push('?');
+            }
+            c if c.is_ascii_digit() && !out.ends_with(|p: char| p.is_alphanumeric() || p == '_') => {
+                while chars.next_if(|d| d.is_ascii_alphanumeric() || *d == '.').is_some() {}
+                out.push('?');
+            }
+            c if c.is_whitespace() => {
+                while chars.next_if(|d| d.is_whitespace()).is_some() {}
+                out.push(' ');
+            }
+            c => out.extend(c.to_lowercase()),
+        }
+    }
+    out
+}
+
+// ---------- helpers ----------
+
+fn space() -> Space {
+    Space(HISTORY_SPACE.into())
+}
+
+fn user_prefix(user: &str) -> Vec<u8> {
+    [user.as_bytes(), &[0]].concat()
+}
+
+/// User, NUL, then big-endian timestamp and sequence, so each user's statements sort chronologically
+fn record_key(user: &str, ts: u64, seq: u64) -> Vec<u8> {
+    [user_prefix(user), ts.to_be_bytes().to_vec(), seq.to_be_bytes().to_vec()].concat()
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,118 @@
+//! Column statistics for the SQL planner.
+//!
+//! Stored in the catalog under `stats/<table>.<column>`. `analyze` computes them
+//! from the table's rows; adaptive execution then corrects the equality estimate
+//! with what queries actually observe, so a plan that went wrong once is costed
+//! better the next time.
+
+use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+use crate::{DbError, Result, Space, Storage};
+
+const CATALOG_SPACE: &str = "catalog";
+const DATA_SPACE: &str = "data";
+
+/// A query whose match count is off from the estimate by more than this factor
+/// (either way) counts as a misestimate
+pub const MISESTIMATE_FACTOR: f64 = 4.0;
+
+/// `record_actual` leaves the statistics as they are while the estimate would move
+/// by no more than this share
+pub const REWRITE_DRIFT: f64 = 0.1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct ColumnStats {
+    pub table: String,
+    pub column: String,
+    pub rows: u64,
+    pub distinct: u64,
+    /// Rows per equality match learned from executed queries; overrides `rows / distinct`
+    pub observed_eq_rows: Option<f64>,
+    pub misestimates: u64,
+    pub updated_ms: u64,
+}
+
+impl ColumnStats {
+    /// Expected rows matching `column = <value>`
+    pub fn estimate_eq(&self) -> f64 {
+        match self.observed_eq_rows {
+            Some(n) => n,
+            None if self.distinct == 0 => 0.0,
+            None => self.rows as f64 / self.distinct as f64,
+        }
+    }
+}
+
+/// Scan `table` and compute statistics for `column`
+pub fn analyze<S: Storage + ?Sized>(storage: &S, table: &str, column: &str) -> Result<ColumnStats> {
+    let prefix = format!("tbl/{}/", table).into_bytes();
+    let mut rows = 0;
+    let mut values = HashSet::new();
+    for (_, v) in storage.scan_prefix(&Space(DATA_SPACE.into()), &prefix)? {
+        rows += 1;
+        let row: serde_json::Value = serde_json::from_slice(&v).unwrap_or(serde_json::Value::Null);
+        if let Some(val) = row.get(column).filter(|v| !v.is_null()) {
+            values.insert(val.to_string());
+        }
+    }
+    let stats = ColumnStats {
+        table: table.to_string(),
+        column: column.to_string(),
+        rows,
+        distinct: values.len() as u64,
+        observed_eq_rows: None,
+        misestimates: 0,
+        updated_ms: now_ms(),
+    };
+    store(storage, &stats)?;
+    Ok(stats)
+}
+
+pub fn load<S: Storage + ?Sized>(storage: &S, table: &str, column: &str) -> Result<Option<ColumnStats>> {
+    match storage.get(&Space(CATALOG_SPACE.into()), &stats_key(table, column))? {
+        Some(b) => serde_json::from_slice(&b).map(Some).map_err(|e| DbError::Storage(format!("corrupt column stats: {}", e))),
+        None => Ok(None),
+    }
+}
+
+pub fn store<S: Storage + ?Sized>(storage: &S, stats: &ColumnStats) -> Result<()> {
+    let bytes = serde_json::to_vec(stats).map_err(|e| DbError::Storage(e.to_string()))?;
+    storage.put(&Space(CATALOG_SPACE.into()), stats_key(&stats.table, &stats.column), bytes)
+}
+
+/// Feed back what an equality query on `column` actually matched. Returns `true` if
+/// it was a misestimate. Tables never analyzed get no statistics from this.
+///
+/// Most queries land close to the estimate, so statistics are only written back
+/// for a misestimate or when the estimate moves by more than `REWRITE_DRIFT`; a
+/// steady workload reads its statistics without writing them on every query.
+pub fn record_actual<S: Storage + ?Sized>(storage: &S, table: &str, column: &str, actual: u64) -> Result<bool> {
+    let Some(mut stats) = load(storage, table, column)? else { return Ok(false) };
+    let estimated = stats.estimate_eq();
+    // +1 keeps "expected 0, got 1" from counting as an infinite miss
+    let ratio = (actual as f64 + 1.0) / (estimated + 1.0);
+    let missed = !(1.0 / MISESTIMATE_FACTOR..=MISESTIMATE_FACTOR).contains(&ratio);
+    // Moving average, so one unusual value doesn't swing the estimate all the way
+    let observed = (estimated + actual as f64) / 2.0;
+    if !missed && (observed - estimated).abs() <= (estimated + 1.0) * REWRITE_DRIFT {
+        return Ok(false);
+    }
+    if missed {
+        stats.misestimates += 1;
+    }
+    stats.observed_eq_rows = Some(observed);
+    stats.updated_ms = now_ms();
+    store(storage, &stats)?;
+    Ok(missed)
+}
+
+fn stats_key(table: &str, column: &str) -> Vec<u8> {
+    format!("stats/{}.{}", table, column).into_bytes()
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
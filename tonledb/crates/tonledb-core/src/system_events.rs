@@ -0,0 +1,175 @@
+//! Structured system events (node lifecycle, WAL, compaction, backups, replication)
+//! persisted in a dedicated space, with alert rules evaluated on every event.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use crate::{DbError, Result, Space, Storage};
+
+const EVENTS_SPACE: &str = "sys_events";
+
+/// What happened
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SystemEventKind {
+    NodeStarted,
+    WalRotated,
+    CompactionFinished,
+    BackupFailed,
+    ReplicaLagBreach,
+    Other(String),
+}
+
+/// How bad it is
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+/// A recorded system event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SystemEvent {
+    pub seq: u64,
+    pub timestamp: u64, // epoch millis
+    pub kind: SystemEventKind,
+    pub severity: Severity,
+    pub message: String,
+    pub attrs: BTreeMap<String, String>,
+}
+
+/// Fire an alert when an event of one of `kinds` (all kinds if empty) at or
+/// above `min_severity` is recorded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertRule {
+    pub name: String,
+    #[serde(default)]
+    pub kinds: Vec<SystemEventKind>,
+    pub min_severity: Severity,
+    pub webhook: String,
+}
+
+impl AlertRule {
+    pub fn matches(&self, event: &SystemEvent) -> bool {
+        event.severity >= self.min_severity
+            && (self.kinds.is_empty() || self.kinds.contains(&event.kind))
+    }
+}
+
+/// Delivers fired alerts (e.g. as webhook POSTs). Must not block.
+pub trait AlertSink: Send + Sync {
+    fn fire(&self, rule: &AlertRule, event: &SystemEvent);
+}
+
+/// Filters for querying the event log
+#[derive(Debug, Clone, Default)]
+pub struct EventQuery {
+    pub since_ms: Option<u64>,
+    pub kind: Option<SystemEventKind>,
+    pub min_severity: Option<Severity>,
+    pub limit: Option<usize>,
+}
+
+/// System event log backed by `Space("sys_events")`
+pub struct SystemEventLog {
+    storage: Arc<dyn Storage>,
+    seq: AtomicU64,
+    rules: RwLock<Vec<AlertRule>>,
+    sink: RwLock<Option<Arc<dyn AlertSink>>>,
+}
+
+impl SystemEventLog {
+    pub fn new(storage: Arc<dyn Storage>) -> Result<Self> {
+        // Continue numbering after the newest persisted event
+        let last_seq = storage
+            .scan_prefix(&Space(EVENTS_SPACE.into()), b"")?
+            .filter_map(|(k, _)| k.get(8..16).map(|b| u64::from_be_bytes(b.try_into().unwrap())))
+            .max()
+            .unwrap_or(0);
+        Ok(Self {
+            storage,
+            seq: AtomicU64::new(last_seq),
+            rules: RwLock::new(Vec::new()),
+            sink: RwLock::new(None),
+        })
+    }
+
+    /// Replace the active alert rules
+    pub fn set_rules(&self, rules: Vec<AlertRule>) {
+        *self.rules.write() = rules;
+    }
+
+    pub fn rules(&self) -> Vec<AlertRule> {
+        self.rules.read().clone()
+    }
+
+    /// Set where fired alerts are delivered
+    pub fn set_sink(&self, sink: Arc<dyn AlertSink>) {
+        *self.sink.write() = Some(sink);
+    }
+
+    /// Persist an event and fire any matching alert rules
+    pub fn record(&self, kind: SystemEventKind, severity: Severity, message: impl Into<String>, attrs: BTreeMap<String, String>) -> Result<SystemEvent> {
+        let event = SystemEvent {
+            seq: self.seq.fetch_add(1, Ordering::SeqCst) + 1,
+            timestamp: now_ms(),
+            kind,
+            severity,
+            message: message.into(),
+            attrs,
+        };
+        let val = serde_json::to_vec(&event).map_err(|e| DbError::Storage(e.to_string()))?;
+        self.storage.put(&Space(EVENTS_SPACE.into()), event_key(event.timestamp, event.seq), val)?;
+
+        if let Some(sink) = self.sink.read().clone() {
+            for rule in self.rules.read().iter().filter(|r| r.matches(&event)) {
+                sink.fire(rule, &event);
+            }
+        }
+        Ok(event)
+    }
+
+    /// Events matching `q`, oldest first
+    pub fn query(&self, q: &EventQuery) -> Result<Vec<SystemEvent>> {
+        let mut out = Vec::new();
+        for (k, v) in self.storage.scan_prefix(&Space(EVENTS_SPACE.into()), b"")? {
+            let ts = k.get(..8).map(|b| u64::from_be_bytes(b.try_into().unwrap())).unwrap_or(0);
+            if q.since_ms.is_some_and(|since| ts < since) {
+                continue;
+            }
+            let event: SystemEvent = serde_json::from_slice(&v)
+                .map_err(|e| DbError::Storage(format!("corrupt system event: {}", e)))?;
+            if q.kind.as_ref().is_some_and(|k| *k != event.kind) {
+                continue;
+            }
+            if q.min_severity.is_some_and(|s| event.severity < s) {
+                continue;
+            }
+            out.push(event);
+        }
+        if let Some(limit) = q.limit {
+            // Keep the most recent `limit` events
+            let skip = out.len().saturating_sub(limit);
+            out.drain(..skip);
+        }
+        Ok(out)
+    }
+}
+
+// ---------- helpers ----------
+
+/// Big-endian timestamp then sequence, so keys sort chronologically
+fn event_key(ts: u64, seq: u64) -> Vec<u8> {
+    [ts.to_be_bytes(), seq.to_be_bytes()].concat()
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
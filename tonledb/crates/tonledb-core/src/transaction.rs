@@ -2,6 +2,7 @@
 
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use parking_lot::RwLock;
 use crate::{DbError, Result, Space, Storage};
 
@@ -13,6 +14,9 @@ pub enum TransactionState {
     Aborted,
 }
 
+/// Buffered writes of a transaction; `None` deletes the key
+pub type WriteSet = HashMap<(Space, Vec<u8>), Option<Vec<u8>>>;
+
 /// A database transaction
 #[derive(Clone)]
 pub struct Transaction {
@@ -20,11 +24,29 @@ pub struct Transaction {
     pub state: TransactionState,
     // Track read and write operations
     pub read_set: HashSet<(Space, Vec<u8>)>,
-    pub write_set: HashMap<(Space, Vec<u8>), Option<Vec<u8>>>,
-    // Timestamp for MVCC
+    pub write_set: WriteSet,
+    // Snapshot timestamp for MVCC: the transaction reads versions committed at or before it
     pub timestamp: u64,
 }
 
+static CLOCK: AtomicU64 = AtomicU64::new(0);
+
+/// Next MVCC timestamp: wall-clock microseconds, strictly increasing across the process.
+pub fn next_timestamp() -> u64 {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or(0);
+    let mut cur = CLOCK.load(Ordering::Relaxed);
+    loop {
+        let next = now.max(cur + 1);
+        match CLOCK.compare_exchange_weak(cur, next, Ordering::SeqCst, Ordering::Relaxed) {
+            Ok(_) => return next,
+            Err(actual) => cur = actual,
+        }
+    }
+}
+
 impl Transaction {
     pub fn new(id: u64) -> Self {
         Self {
@@ -32,18 +54,10 @@ impl Transaction {
             state: TransactionState::Active,
             read_set: HashSet::new(),
             write_set: HashMap::new(),
-            timestamp: Self::current_timestamp(),
+            timestamp: next_timestamp(),
         }
     }
     
-    /// Get current timestamp in milliseconds
-    fn current_timestamp() -> u64 {
-        std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .map(|d| d.as_millis() as u64)
-            .unwrap_or(0)
-    }
-    
     /// Read a value within the transaction
     pub fn get<S: Storage + ?Sized>(&mut self, storage: &S, space: &Space, key: &[u8]) -> Result<Option<Vec<u8>>> {
         // Check if we have a pending write
@@ -54,8 +68,8 @@ impl Transaction {
         // Add to read set for conflict detection
         self.read_set.insert((space.clone(), key.to_vec()));
         
-        // Read from storage
-        storage.get(space, key)
+        // Read the version visible at our snapshot timestamp
+        storage.get_versioned(space, key, self.timestamp)
     }
     
     /// Write a value within the transaction
@@ -105,6 +119,30 @@ impl TransactionManager {
         Ok(txn_id)
     }
     
+    /// Read a key through an active transaction (snapshot read, sees its own writes)
+    pub fn get<S: Storage + ?Sized>(&self, storage: &S, txn_id: u64, space: &Space, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let mut transactions = self.transactions.write();
+        let txn = transactions.get_mut(&txn_id)
+            .ok_or_else(|| DbError::NotFound(format!("Transaction {} not found", txn_id)))?;
+        txn.get(storage, space, key)
+    }
+    
+    /// Buffer a write in an active transaction
+    pub fn put(&self, txn_id: u64, space: Space, key: Vec<u8>, val: Vec<u8>) -> Result<()> {
+        let mut transactions = self.transactions.write();
+        let txn = transactions.get_mut(&txn_id)
+            .ok_or_else(|| DbError::NotFound(format!("Transaction {} not found", txn_id)))?;
+        txn.put(space, key, val)
+    }
+    
+    /// Buffer a delete in an active transaction
+    pub fn delete(&self, txn_id: u64, space: Space, key: Vec<u8>) -> Result<()> {
+        let mut transactions = self.transactions.write();
+        let txn = transactions.get_mut(&txn_id)
+            .ok_or_else(|| DbError::NotFound(format!("Transaction {} not found", txn_id)))?;
+        txn.delete(space, key)
+    }
+    
     /// Commit a transaction
     pub fn commit<S: Storage + ?Sized>(&self, storage: &S, txn_id: u64) -> Result<()> {
         // First, get the transaction and validate it
@@ -119,15 +157,24 @@ impl TransactionManager {
                 return Err(DbError::Invalid("Transaction is not active".into()));
             }
             
-            // Apply all writes
-            for ((space, key), value) in &txn.write_set {
-                match value {
-                    Some(val) => {
-                        storage.put(space, key.clone(), val.clone())?;
+            // Snapshot isolation: first committer wins on write-write conflicts
+            for (space, key) in txn.write_set.keys() {
+                if let Some(v) = storage.latest_version(space, key)? {
+                    if v > txn.timestamp {
+                        let conflict = DbError::Conflict(format!(
+                            "transaction {} lost write conflict on key {:?}",
+                            txn_id,
+                            String::from_utf8_lossy(key)
+                        ));
+                        drop(transactions);
+                        self.abort(txn_id)?;
+                        return Err(conflict);
                     }
-                    None => storage.del(space, key)?,
                 }
             }
+            
+            // Apply all writes at a single commit timestamp
+            storage.commit_writes(&txn.write_set, next_timestamp())?;
         }
         
         // Update transaction state
@@ -153,6 +200,17 @@ impl TransactionManager {
         }
     }
     
+    /// Garbage-collect versions that no active transaction can still read
+    pub fn gc<S: Storage + ?Sized>(&self, storage: &S) -> Result<usize> {
+        let oldest_active = self.transactions.read()
+            .values()
+            .filter(|t| t.state == TransactionState::Active)
+            .map(|t| t.timestamp)
+            .min()
+            .unwrap_or_else(next_timestamp);
+        storage.gc_versions(oldest_active)
+    }
+    
     /// Get a transaction
     pub fn get_transaction(&self, txn_id: u64) -> Option<Transaction> {
         self.transactions.read().get(&txn_id).cloned()
@@ -0,0 +1,66 @@
+//! Tests for per-request deadlines
+
+use std::time::{Duration, Instant};
+use tonledb_core::deadline::{self, DeadlineStorage};
+use tonledb_core::{DbError, Space, Storage};
+use tonledb_storage::arc_inmem_with_wal;
+
+#[test]
+fn test_check_passes_without_deadline_and_fails_after_it() {
+    assert!(deadline::check().is_ok());
+    assert_eq!(deadline::remaining(), None);
+
+    let past = Instant::now() - Duration::from_millis(5);
+    let err = deadline::within(Some(past), deadline::check).unwrap_err();
+    assert!(matches!(err, DbError::DeadlineExceeded(_)));
+
+    let future = Instant::now() + Duration::from_secs(60);
+    deadline::within(Some(future), || {
+        assert!(deadline::check().is_ok());
+        assert!(deadline::remaining().unwrap() > Duration::from_secs(50));
+    });
+    // The scope's deadline does not outlive it
+    assert_eq!(deadline::current(), None);
+}
+
+#[test]
+fn test_nested_scope_only_shortens_the_deadline() {
+    let outer = Instant::now() + Duration::from_secs(1);
+    let inner = Instant::now() + Duration::from_secs(60);
+    deadline::within(Some(outer), || {
+        deadline::within(Some(inner), || assert_eq!(deadline::current(), Some(outer)));
+        deadline::within(None, || assert_eq!(deadline::current(), Some(outer)));
+        assert_eq!(deadline::current(), Some(outer));
+    });
+}
+
+#[test]
+fn test_storage_calls_are_refused_after_the_deadline() {
+    let storage = DeadlineStorage::new(arc_inmem_with_wal(None, 100));
+    let space = Space("kv".to_string());
+    storage.put(&space, b"a".to_vec(), b"1".to_vec()).unwrap();
+
+    let past = Instant::now() - Duration::from_millis(1);
+    deadline::within(Some(past), || {
+        assert!(matches!(storage.get(&space, b"a"), Err(DbError::DeadlineExceeded(_))));
+        assert!(matches!(storage.put(&space, b"b".to_vec(), b"2".to_vec()), Err(DbError::DeadlineExceeded(_))));
+        assert!(storage.scan_prefix(&space, b"").is_err());
+    });
+    assert_eq!(storage.get(&space, b"a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(storage.get(&space, b"b").unwrap(), None);
+}
+
+#[test]
+fn test_cancelled_requests_fail_their_checks() {
+    let canceller = deadline::Canceller::new();
+    deadline::cancellable(&canceller, || {
+        assert!(deadline::check().is_ok());
+        let other = canceller.clone();
+        std::thread::spawn(move || other.cancel()).join().unwrap();
+        assert!(deadline::expired());
+        assert!(matches!(deadline::check(), Err(DbError::DeadlineExceeded(_))));
+    });
+    // Only requests run under the canceller are affected
+    assert!(deadline::check().is_ok());
+    deadline::cancellable(&deadline::Canceller::new(), || assert!(deadline::check().is_ok()));
+}
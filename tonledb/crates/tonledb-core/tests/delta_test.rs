@@ -0,0 +1,85 @@
+//! Tests for delta-encoded replication payloads
+
+use tonledb_core::delta::{self, ChangePayload};
+use tonledb_core::event_sourcing::{ChangeEvent, Operation};
+use tonledb_core::DbError;
+use serde_json::json;
+
+fn large_doc(status: &str) -> Vec<u8> {
+    let doc = json!({
+        "_id": "u1",
+        "status": status,
+        "bio": "x".repeat(2048),
+        "address": { "city": "Phnom Penh", "zip": "12000" },
+    });
+    serde_json::to_vec(&doc).unwrap()
+}
+
+#[test]
+fn test_delta_for_small_field_change() {
+    let old = large_doc("active");
+    let new = large_doc("disabled");
+
+    let payload = delta::encode(Some(&old), Some(&new), 64);
+    assert!(matches!(payload, ChangePayload::Delta { ref ops, .. } if ops.len() == 1));
+
+    let applied = delta::apply(Some(&old), &payload).unwrap().unwrap();
+    let applied: serde_json::Value = serde_json::from_slice(&applied).unwrap();
+    let expected: serde_json::Value = serde_json::from_slice(&new).unwrap();
+    assert_eq!(applied, expected);
+}
+
+#[test]
+fn test_full_value_fallbacks() {
+    let new = large_doc("active");
+
+    // Insert: no previous value
+    assert_eq!(delta::encode(None, Some(&new), 64), ChangePayload::Full(Some(new.clone())));
+    // Non-JSON values
+    assert_eq!(delta::encode(Some(b"raw"), Some(b"bytes!"), 0), ChangePayload::Full(Some(b"bytes!".to_vec())));
+    // Below the size threshold
+    let old = large_doc("disabled");
+    assert_eq!(delta::encode(Some(&old), Some(&new), usize::MAX), ChangePayload::Full(Some(new.clone())));
+}
+
+#[test]
+fn test_delta_without_base_is_rejected() {
+    let old = large_doc("active");
+    let new = large_doc("disabled");
+    let payload = delta::encode(Some(&old), Some(&new), 64);
+    assert!(delta::apply(None, &payload).is_err());
+}
+
+#[test]
+fn test_delta_against_drifted_base_is_rejected() {
+    let old = large_doc("active");
+    let new = large_doc("disabled");
+    let payload = delta::encode(Some(&old), Some(&new), 64);
+
+    // The consumer missed a change, so its copy is not the delta's base
+    let drifted = large_doc("pending");
+    assert!(matches!(delta::apply(Some(&drifted), &payload), Err(DbError::Conflict(_))));
+    // The same document with other whitespace is still the base
+    let reformatted = serde_json::to_vec_pretty(&serde_json::from_slice::<serde_json::Value>(&old).unwrap()).unwrap();
+    assert!(delta::apply(Some(&reformatted), &payload).is_ok());
+}
+
+#[test]
+fn test_replication_record_from_update_event() {
+    let old = large_doc("active");
+    let new = large_doc("disabled");
+    let event = ChangeEvent {
+        id: "e1".to_string(),
+        timestamp: 1,
+        operation: Operation::Update,
+        table: "users".to_string(),
+        key: Some(b"u1".to_vec()),
+        old_value: Some(old.clone()),
+        new_value: Some(new.clone()),
+    };
+
+    let record = event.to_replication_record(64);
+    assert!(matches!(record.payload, ChangePayload::Delta { .. }));
+    let applied: serde_json::Value = serde_json::from_slice(&record.apply_to(Some(&old)).unwrap().unwrap()).unwrap();
+    assert_eq!(applied["status"], "disabled");
+}
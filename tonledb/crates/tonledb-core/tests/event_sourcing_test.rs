@@ -65,4 +65,34 @@ fn test_event_publishing() {
     
     // Unregister the feed
     EVENT_MANAGER.unregister_feed("test_feed");
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_prefix_feeds() {
+    let manager = EventSourcingManager::new();
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let sink = received.clone();
+    manager.register_prefix_feed("users".to_string(), Some("kv".to_string()), b"user:".to_vec(), move |event| {
+        sink.lock().unwrap().push(event.key.unwrap());
+    }).unwrap();
+    assert!(manager.wants("kv", &Operation::Update, b"user:1"));
+    assert!(!manager.wants("kv", &Operation::Update, b"order:1"));
+    assert!(!manager.wants("docs", &Operation::Update, b"user:1"));
+
+    for key in [b"user:1".as_slice(), b"order:1"] {
+        manager.publish_event(tonledb_core::event_sourcing::ChangeEvent {
+            id: "id".to_string(),
+            timestamp: 0,
+            operation: Operation::Update,
+            table: "kv".to_string(),
+            key: Some(key.to_vec()),
+            old_value: None,
+            new_value: None,
+        });
+    }
+    assert_eq!(*received.lock().unwrap(), [b"user:1".to_vec()]);
+
+    // A feed without a key filter wants everything on its table
+    manager.register_feed("all".to_string(), Some("kv".to_string()), None, |_event| {}).unwrap();
+    assert!(manager.wants("kv", &Operation::Delete, b"order:1"));
+}
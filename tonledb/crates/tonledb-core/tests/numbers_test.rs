@@ -0,0 +1,51 @@
+//! Tests for decimal vs float JSON number handling
+
+use std::cmp::Ordering;
+use serde_json::{json, Number, Value as Json};
+use tonledb_core::numbers::{self, NumberMode, Source};
+use tonledb_storage::InMemoryStore;
+
+fn num(s: &str) -> Number {
+    serde_json::from_str(s).unwrap()
+}
+
+#[test]
+fn test_decimal_literals_keep_every_digit() {
+    let exact = numbers::number_literal("12345678901234567.89", NumberMode::Decimal).unwrap();
+    assert_eq!(exact.to_string(), "12345678901234567.89");
+    let float = numbers::number_literal("12345678901234567.89", NumberMode::Float).unwrap();
+    assert_ne!(float.to_string(), "12345678901234567.89");
+    // Integers that fit 64 bits are exact in both modes
+    assert_eq!(numbers::number_literal("42", NumberMode::Float).unwrap().to_string(), "42");
+}
+
+#[test]
+fn test_cmp_numbers_is_exact() {
+    assert_eq!(numbers::cmp_numbers(&num("1"), &num("1.0")), Some(Ordering::Equal));
+    assert_eq!(numbers::cmp_numbers(&num("1.5e2"), &num("150")), Some(Ordering::Equal));
+    assert_eq!(numbers::cmp_numbers(&num("0.1"), &num("0.10000000000000001")), Some(Ordering::Less));
+    assert_eq!(numbers::cmp_numbers(&num("-2.5"), &num("-2.49")), Some(Ordering::Less));
+    assert_eq!(numbers::cmp_numbers(&num("-0.0"), &num("0")), Some(Ordering::Equal));
+    assert_eq!(numbers::cmp_numbers(&num("0.05"), &num("0.5")), Some(Ordering::Less));
+    assert!(numbers::values_equal(&json!(2), &Json::Number(num("2.00"))));
+    assert_eq!(numbers::canonical(&num("2.00")), numbers::canonical(&num("2")));
+}
+
+#[test]
+fn test_column_overrides_pick_the_mode_per_field() {
+    let store = InMemoryStore::new(100);
+    numbers::set_column_mode(&store, Source::Table("orders"), "amount", Some(NumberMode::Decimal)).unwrap();
+    let modes = numbers::column_modes(&store, Source::Table("orders")).unwrap();
+    assert_eq!(numbers::mode_for(&modes, "amount"), NumberMode::Decimal);
+    assert_eq!(numbers::mode_for(&modes, "weight"), numbers::number_mode());
+
+    let mut doc: Json = serde_json::from_str(r#"{"amount": 0.10000000000000000001, "weight": 0.10000000000000000001}"#).unwrap();
+    numbers::normalize_doc(&mut doc, &modes);
+    assert_eq!(doc["amount"].to_string(), "0.10000000000000000001");
+    assert_eq!(doc["weight"].to_string(), "0.1");
+
+    // A collection of the same name has its own
+    assert!(numbers::column_modes(&store, Source::Collection("orders")).unwrap().is_empty());
+    numbers::set_column_mode(&store, Source::Table("orders"), "amount", None).unwrap();
+    assert!(numbers::column_modes(&store, Source::Table("orders")).unwrap().is_empty());
+}
@@ -0,0 +1,37 @@
+//! Tests for per-request storage operation tracing
+
+use std::sync::Arc;
+use tonledb_core::op_trace::{collect, TracedStorage};
+use tonledb_core::{Space, Storage};
+use tonledb_storage::arc_inmem_with_wal;
+
+#[test]
+fn test_collect_records_ops_only_inside_scope() {
+    let storage = TracedStorage::new(arc_inmem_with_wal(None, 1000));
+    let space = Space("kv".to_string());
+
+    // Not traced
+    storage.put(&space, b"a".to_vec(), b"1".to_vec()).unwrap();
+
+    let (val, ops) = collect(|| {
+        storage.put(&space, b"b".to_vec(), b"2".to_vec()).unwrap();
+        let _ = storage.scan_prefix(&space, b"").unwrap().count();
+        storage.get(&space, b"a").unwrap()
+    });
+
+    assert_eq!(val, Some(b"1".to_vec()));
+    let names: Vec<&str> = ops.iter().map(|o| o.op).collect();
+    assert_eq!(names, vec!["put", "scan", "get"]);
+    assert_eq!(ops[2].key, "a");
+    assert_eq!(ops[2].space, "kv");
+
+    // Nothing is recorded after the scope ends
+    let (_, ops) = collect(|| ());
+    assert!(ops.is_empty());
+}
+
+#[test]
+fn test_traced_storage_forwards_snapshots() {
+    let storage: Arc<dyn Storage> = Arc::new(TracedStorage::new(arc_inmem_with_wal(None, 1000)));
+    assert!(storage.snapshot().is_ok());
+}
@@ -0,0 +1,45 @@
+//! Tests for schema-on-read inference of document collections
+
+use serde_json::json;
+use tonledb_core::schema_inference::{self, JsonType};
+use tonledb_core::{DataType, Space, Storage};
+use tonledb_storage::InMemoryStore;
+
+#[test]
+fn test_infer_types_and_null_rates() {
+    let docs = vec![
+        json!({"name": "ann", "age": 31, "score": 1.5, "tags": ["a"]}),
+        json!({"name": "bob", "age": null, "score": 2}),
+        json!({"name": "cy", "age": 40, "extra": {"k": 1}}),
+        json!({"name": 7}),
+    ];
+    let schema = schema_inference::infer("people", &docs);
+    assert_eq!(schema.sampled, 4);
+
+    let age = schema.field("age").unwrap();
+    assert_eq!(age.types, vec![JsonType::Integer]);
+    assert_eq!(age.data_type(), DataType::Integer);
+    assert!((age.null_rate - 0.5).abs() < 1e-9);
+
+    assert_eq!(schema.field("score").unwrap().data_type(), DataType::Float);
+    assert_eq!(schema.field("name").unwrap().data_type(), DataType::Json);
+    assert_eq!(schema.field("tags").unwrap().data_type(), DataType::Json);
+    assert!((schema.field("extra").unwrap().null_rate - 0.75).abs() < 1e-9);
+}
+
+#[test]
+fn test_refresh_stores_schema_in_catalog() {
+    let store = InMemoryStore::new(1000);
+    let data = Space("data".to_string());
+    for i in 0..10 {
+        let doc = json!({"_id": i.to_string(), "n": i});
+        store.put(&data, format!("doc/items/{}", i).into_bytes(), serde_json::to_vec(&doc).unwrap()).unwrap();
+    }
+    assert!(schema_inference::load(&store, "items").unwrap().is_none());
+
+    let schema = schema_inference::refresh(&store, "items", 5).unwrap();
+    assert_eq!(schema.sampled, 5);
+    assert_eq!(schema_inference::load(&store, "items").unwrap(), Some(schema.clone()));
+    assert_eq!(schema_inference::list(&store).unwrap().len(), 1);
+    assert_eq!(schema.field("n").unwrap().data_type(), DataType::Integer);
+}
@@ -0,0 +1,62 @@
+//! Tests for the per-user statement history
+
+use std::sync::Arc;
+use tonledb_core::statement_history::{self, HistoryQuery, HistoryRetention, StatementHistory, StatementStatus};
+use tonledb_storage::InMemoryStore;
+
+fn history(retention: HistoryRetention) -> StatementHistory {
+    StatementHistory::new(Arc::new(InMemoryStore::new(100)), retention).unwrap()
+}
+
+#[test]
+fn test_fingerprint_ignores_literals_case_and_whitespace() {
+    let a = statement_history::fingerprint("SELECT * FROM users WHERE id = 42 AND name = 'ann'");
+    let b = statement_history::fingerprint("select *  from users\nwhere id = 7 and name = 'it''s';");
+    assert_eq!(a, b);
+    assert_ne!(a, statement_history::fingerprint("SELECT * FROM users2 WHERE id = 42 AND name = 'ann'"));
+    assert_eq!(statement_history::normalize("SELECT a1 FROM t WHERE x = 1.5"), "select a1 from t where x = ?");
+}
+
+#[test]
+fn test_query_by_user_time_and_status() {
+    let h = history(HistoryRetention { store_text: true, ..Default::default() });
+    let now = 1_000_000_000_000;
+    h.record("ann", "SELECT 1", now - 86_400_000, 3, Ok(())).unwrap();
+    h.record("ann", "SELECT nope", now - 1_000, 1, Err("no such table".into())).unwrap();
+    h.record("bob", "SELECT 2", now - 500, 2, Ok(())).unwrap();
+
+    let ann = h.query(&HistoryQuery { user: Some("ann".into()), ..Default::default() }).unwrap();
+    assert_eq!(ann.len(), 2);
+    assert_eq!(ann[0].text.as_deref(), Some("SELECT 1"));
+
+    let recent = h.query(&HistoryQuery { since_ms: Some(now - 3_600_000), ..Default::default() }).unwrap();
+    assert_eq!(recent.iter().map(|r| r.user.as_str()).collect::<Vec<_>>(), vec!["ann", "bob"]);
+
+    let failed = h.query(&HistoryQuery { status: Some(StatementStatus::Error), ..Default::default() }).unwrap();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].error.as_deref(), Some("no such table"));
+}
+
+#[test]
+fn test_text_is_not_stored_by_default() {
+    let h = history(HistoryRetention::default());
+    let rec = h.record("ann", "SELECT secret FROM t WHERE pw = 'hunter2'", 1, 0, Ok(())).unwrap();
+    assert_eq!(rec.text, None);
+    assert_eq!(rec.fingerprint.len(), 16);
+}
+
+#[test]
+fn test_retention_caps_per_user_and_drops_old_statements() {
+    let h = history(HistoryRetention { max_age_ms: Some(60_000), max_per_user: Some(3), store_text: false });
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis() as u64;
+    h.record("ann", "SELECT old", now - 120_000, 0, Ok(())).unwrap();
+    for i in 0..5 {
+        h.record("ann", "SELECT 1", now + i, 0, Ok(())).unwrap();
+    }
+    h.record("bob", "SELECT 1", now, 0, Ok(())).unwrap();
+
+    assert_eq!(h.prune().unwrap(), 3);
+    let ann = h.query(&HistoryQuery { user: Some("ann".into()), ..Default::default() }).unwrap();
+    assert_eq!(ann.iter().map(|r| r.started_ms).collect::<Vec<_>>(), vec![now + 2, now + 3, now + 4]);
+    assert_eq!(h.query(&HistoryQuery { user: Some("bob".into()), ..Default::default() }).unwrap().len(), 1);
+}
@@ -0,0 +1,67 @@
+//! Tests for column statistics and misestimate feedback
+
+use serde_json::json;
+use tonledb_core::stats;
+use tonledb_core::{Space, Storage};
+use tonledb_storage::InMemoryStore;
+
+fn put_row(store: &InMemoryStore, id: u32, city: &str) {
+    let row = json!({"id": id, "city": city});
+    store.put(&Space("data".into()), format!("tbl/people/{}", id).into_bytes(), serde_json::to_vec(&row).unwrap()).unwrap();
+}
+
+#[test]
+fn test_analyze_counts_rows_and_distinct_values() {
+    let store = InMemoryStore::new(100);
+    for i in 0..12 {
+        put_row(&store, i, ["paris", "rome", "oslo"][i as usize % 3]);
+    }
+    let s = stats::analyze(&store, "people", "city").unwrap();
+    assert_eq!((s.rows, s.distinct), (12, 3));
+    assert!((s.estimate_eq() - 4.0).abs() < 1e-9);
+    assert_eq!(stats::load(&store, "people", "city").unwrap(), Some(s));
+}
+
+#[test]
+fn test_record_actual_corrects_estimate_and_counts_misestimates() {
+    let store = InMemoryStore::new(100);
+    for i in 0..10 {
+        put_row(&store, i, &format!("c{}", i));
+    }
+    stats::analyze(&store, "people", "city").unwrap();
+
+    // Estimated 1 row per value; seeing 1 is on target
+    assert!(!stats::record_actual(&store, "people", "city", 1).unwrap());
+    // Seeing 20 is far off: counted, and the estimate moves toward it
+    assert!(stats::record_actual(&store, "people", "city", 20).unwrap());
+    let s = stats::load(&store, "people", "city").unwrap().unwrap();
+    assert_eq!(s.misestimates, 1);
+    assert!((s.estimate_eq() - 10.5).abs() < 1e-9);
+}
+
+#[test]
+fn test_record_actual_without_stats_is_a_noop() {
+    let store = InMemoryStore::new(100);
+    assert!(!stats::record_actual(&store, "people", "city", 50).unwrap());
+    assert_eq!(stats::load(&store, "people", "city").unwrap(), None);
+}
+
+#[test]
+fn test_record_actual_skips_writes_near_the_estimate() {
+    let store = InMemoryStore::new(100);
+    for i in 0..12 {
+        put_row(&store, i, ["paris", "rome", "oslo"][i as usize % 3]);
+    }
+    let analyzed = stats::analyze(&store, "people", "city").unwrap();
+
+    // Estimated 4 rows per value: 4 and 5 leave the stored statistics untouched
+    for actual in [4, 5, 4] {
+        assert!(!stats::record_actual(&store, "people", "city", actual).unwrap());
+    }
+    assert_eq!(stats::load(&store, "people", "city").unwrap().as_ref(), Some(&analyzed));
+
+    // 8 is on target, but moves the estimate enough to be kept
+    assert!(!stats::record_actual(&store, "people", "city", 8).unwrap());
+    let s = stats::load(&store, "people", "city").unwrap().unwrap();
+    assert_eq!((s.misestimates, s.observed_eq_rows), (0, Some(6.0)));
+}
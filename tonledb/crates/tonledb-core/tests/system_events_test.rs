@@ -0,0 +1,51 @@
+//! Tests for the system event log and alert rules
+
+use std::sync::{Arc, Mutex};
+use tonledb_core::system_events::{AlertRule, AlertSink, EventQuery, Severity, SystemEvent, SystemEventKind, SystemEventLog};
+use tonledb_storage::arc_inmem_with_wal;
+
+struct RecordingSink(Mutex<Vec<(String, u64)>>);
+
+impl AlertSink for RecordingSink {
+    fn fire(&self, rule: &AlertRule, event: &SystemEvent) {
+        self.0.lock().unwrap().push((rule.name.clone(), event.seq));
+    }
+}
+
+#[test]
+fn test_record_and_query_events() {
+    let log = SystemEventLog::new(arc_inmem_with_wal(None, 1000)).unwrap();
+    log.record(SystemEventKind::NodeStarted, Severity::Info, "started", Default::default()).unwrap();
+    log.record(SystemEventKind::BackupFailed, Severity::Critical, "disk full", Default::default()).unwrap();
+    log.record(SystemEventKind::WalRotated, Severity::Info, "segment 2", Default::default()).unwrap();
+
+    let all = log.query(&EventQuery::default()).unwrap();
+    assert_eq!(all.len(), 3);
+    assert_eq!(all[0].kind, SystemEventKind::NodeStarted);
+
+    let critical = log.query(&EventQuery { min_severity: Some(Severity::Warning), ..Default::default() }).unwrap();
+    assert_eq!(critical.len(), 1);
+    assert_eq!(critical[0].message, "disk full");
+
+    let last = log.query(&EventQuery { limit: Some(1), ..Default::default() }).unwrap();
+    assert_eq!(last[0].kind, SystemEventKind::WalRotated);
+}
+
+#[test]
+fn test_alert_rules_fire_on_match() {
+    let log = SystemEventLog::new(arc_inmem_with_wal(None, 1000)).unwrap();
+    let sink = Arc::new(RecordingSink(Mutex::new(Vec::new())));
+    log.set_sink(sink.clone());
+    log.set_rules(vec![AlertRule {
+        name: "backups".to_string(),
+        kinds: vec![SystemEventKind::BackupFailed],
+        min_severity: Severity::Warning,
+        webhook: "http://localhost/hook".to_string(),
+    }]);
+
+    log.record(SystemEventKind::NodeStarted, Severity::Critical, "ignored kind", Default::default()).unwrap();
+    log.record(SystemEventKind::BackupFailed, Severity::Info, "below threshold", Default::default()).unwrap();
+    let fired = log.record(SystemEventKind::BackupFailed, Severity::Critical, "fires", Default::default()).unwrap();
+
+    assert_eq!(*sink.0.lock().unwrap(), vec![("backups".to_string(), fired.seq)]);
+}
@@ -1,6 +1,6 @@
 //! Tests for transaction functionality
 
-use tonledb_core::{transaction::{Transaction, TransactionState, TXN_MANAGER}, Space, Storage};
+use tonledb_core::{transaction::{Transaction, TransactionManager, TransactionState, TXN_MANAGER}, DbError, Space, Storage};
 use tonledb_storage::InMemoryStore;
 
 #[test]
@@ -52,4 +52,42 @@ fn test_transaction_put_get() {
     // Get the value from the transaction
     let result = txn.get(&store, &space, &key).unwrap();
     assert_eq!(result, Some(value));
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_snapshot_isolation_reads() {
+    let store = InMemoryStore::new(1000);
+    let manager = TransactionManager::new();
+    let space = Space("test".to_string());
+
+    let setup = manager.begin().unwrap();
+    manager.put(setup, space.clone(), b"k".to_vec(), b"v1".to_vec()).unwrap();
+    manager.commit(&store, setup).unwrap();
+
+    let reader = manager.begin().unwrap();
+
+    let writer = manager.begin().unwrap();
+    manager.put(writer, space.clone(), b"k".to_vec(), b"v2".to_vec()).unwrap();
+    manager.commit(&store, writer).unwrap();
+
+    // The reader keeps seeing the value as of its snapshot
+    assert_eq!(manager.get(&store, reader, &space, b"k").unwrap(), Some(b"v1".to_vec()));
+    assert_eq!(store.get(&space, b"k").unwrap(), Some(b"v2".to_vec()));
+}
+
+#[test]
+fn test_write_write_conflict() {
+    let store = InMemoryStore::new(1000);
+    let manager = TransactionManager::new();
+    let space = Space("test".to_string());
+
+    let t1 = manager.begin().unwrap();
+    let t2 = manager.begin().unwrap();
+    manager.put(t1, space.clone(), b"k".to_vec(), b"a".to_vec()).unwrap();
+    manager.put(t2, space.clone(), b"k".to_vec(), b"b".to_vec()).unwrap();
+
+    assert!(manager.commit(&store, t1).is_ok());
+    assert!(matches!(manager.commit(&store, t2), Err(DbError::Conflict(_))));
+    assert_eq!(manager.get_transaction(t2).unwrap().state, TransactionState::Aborted);
+    assert_eq!(store.get(&space, b"k").unwrap(), Some(b"a".to_vec()));
+}
@@ -0,0 +1,56 @@
+//! Who may call what
+//!
+//! Callers name themselves in the `x-auth-name` and `x-auth-token` metadata of
+//! each call, the way HTTP clients do in headers. A wrong token answers
+//! `UNAUTHENTICATED`; a user without the access a call needs, `PERMISSION_DENIED`.
+//! Reads need `Read`; writes and SQL need `Write`.
+
+use tonic::{Request, Status};
+use crate::GrpcOptions;
+
+/// What a user may do, each level including the ones before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Access {
+    Read,
+    Write,
+    Admin,
+}
+
+/// Checks the tokens of callers
+pub trait TokenVerifier: Send + Sync {
+    /// What the user `name` holding `token` may do; `None` when the token is wrong
+    fn verify(&self, name: &str, token: &str) -> Option<Access>;
+}
+
+/// Why a call was turned away
+pub(crate) enum Denied {
+    /// The token is wrong
+    Unauthenticated,
+    /// The user lacks the access the call needs
+    Forbidden,
+}
+
+impl From<Denied> for Status {
+    fn from(denied: Denied) -> Self {
+        match denied {
+            Denied::Unauthenticated => Status::unauthenticated("invalid token"),
+            Denied::Forbidden => Status::permission_denied("forbidden"),
+        }
+    }
+}
+
+/// Fail unless the caller of `request` has `needed`; the name the caller gave,
+/// `anonymous` if none
+pub(crate) fn authorize<T>(options: &GrpcOptions, request: &Request<T>, needed: Access) -> Result<String, Denied> {
+    let metadata = |key: &str| request.metadata().get(key).and_then(|v| v.to_str().ok()).unwrap_or("");
+    let name = match metadata("x-auth-name") {
+        "" => "anonymous".to_string(),
+        name => name.to_string(),
+    };
+    let Some(tokens) = &options.tokens else { return Ok(name) };
+    match tokens.verify(metadata("x-auth-name"), metadata("x-auth-token")) {
+        None => Err(Denied::Unauthenticated),
+        Some(access) if access < needed => Err(Denied::Forbidden),
+        Some(_) => Ok(name),
+    }
+}
@@ -0,0 +1,82 @@
+//! `DocumentService`: documents of the collections, as `/doc` serves them
+//!
+//! Views read like collections (see `tonledb_nosql_doc::view`). `Find` answers
+//! every match unless given a limit, then a page at a time like `/doc/:col/query`.
+
+use std::sync::Arc;
+use serde_json::Value as Json;
+use tonic::{Request, Response, Status};
+use tonledb_core::{Db, DbError};
+use tonledb_nosql_doc::filter::Filter;
+use tonledb_nosql_doc::Paging;
+use crate::auth::{authorize, Access};
+use crate::pb::document_service_server::DocumentService;
+use crate::pb::*;
+use crate::{blocking, deadline, parse_json, GrpcOptions};
+
+pub struct DocumentApi {
+    db: Arc<Db>,
+    options: Arc<GrpcOptions>,
+}
+
+impl DocumentApi {
+    pub fn new(db: Arc<Db>, options: Arc<GrpcOptions>) -> Self {
+        Self { db, options }
+    }
+}
+
+/// The filter of a request; an empty one matches every document
+fn filter(text: &str) -> Result<Filter, DbError> {
+    if text.trim().is_empty() {
+        return Ok(Filter::all());
+    }
+    Filter::parse(&parse_json(text, "filter")?)
+}
+
+#[tonic::async_trait]
+impl DocumentService for DocumentApi {
+    async fn insert(&self, request: Request<InsertRequest>) -> Result<Response<InsertResponse>, Status> {
+        authorize(&self.options, &request, Access::Write)?;
+        let (deadline, req, db) = (deadline(&self.options, request.metadata()), request.into_inner(), self.db.clone());
+        let id = blocking(deadline, move || {
+            let document = parse_json(&req.document, "document")?;
+            tonledb_nosql_doc::insert(&*db.storage, &req.collection, document)
+        })
+        .await?;
+        Ok(Response::new(InsertResponse { id }))
+    }
+
+    async fn get(&self, request: Request<GetDocumentRequest>) -> Result<Response<GetDocumentResponse>, Status> {
+        authorize(&self.options, &request, Access::Read)?;
+        let (deadline, req, db) = (deadline(&self.options, request.metadata()), request.into_inner(), self.db.clone());
+        let document = blocking(deadline, move || tonledb_nosql_doc::get(&*db.storage, &req.collection, &req.id, true)).await?;
+        Ok(Response::new(GetDocumentResponse { document: document.map(|d| d.to_string()) }))
+    }
+
+    async fn delete(&self, request: Request<DeleteDocumentRequest>) -> Result<Response<DeleteDocumentResponse>, Status> {
+        authorize(&self.options, &request, Access::Write)?;
+        let (deadline, req, db) = (deadline(&self.options, request.metadata()), request.into_inner(), self.db.clone());
+        let deleted = blocking(deadline, move || tonledb_nosql_doc::delete(&*db.storage, &req.collection, &req.id)).await?;
+        Ok(Response::new(DeleteDocumentResponse { deleted }))
+    }
+
+    async fn find(&self, request: Request<FindRequest>) -> Result<Response<FindResponse>, Status> {
+        authorize(&self.options, &request, Access::Read)?;
+        let (deadline, req, db) = (deadline(&self.options, request.metadata()), request.into_inner(), self.db.clone());
+        let page = blocking(deadline, move || {
+            let filter = filter(&req.filter)?;
+            let paging = Paging { after: req.after, skip: req.skip as usize, limit: req.limit.map(|l| l as usize) };
+            tonledb_nosql_doc::query_page(&*db.storage, &req.collection, &filter, true, &paging)
+        })
+        .await?;
+        let documents = page.docs.iter().map(Json::to_string).collect();
+        Ok(Response::new(FindResponse { documents, next: page.next }))
+    }
+
+    async fn count(&self, request: Request<CountRequest>) -> Result<Response<CountResponse>, Status> {
+        authorize(&self.options, &request, Access::Read)?;
+        let (deadline, req, db) = (deadline(&self.options, request.metadata()), request.into_inner(), self.db.clone());
+        let count = blocking(deadline, move || tonledb_nosql_doc::count(&*db.storage, &req.collection, &filter(&req.filter)?)).await?;
+        Ok(Response::new(CountResponse { count: count as u64 }))
+    }
+}
@@ -0,0 +1,82 @@
+//! `KvService`: keys and values of the key-value store, as `/kv` serves them
+//!
+//! Keys and values are bytes. `Scan` answers a page at a time; its `next` is the
+//! last key of the page, which the caller passes back as the cursor.
+
+use std::sync::Arc;
+use std::time::Duration;
+use tonic::{Request, Response, Status};
+use tonledb_core::{Consistency, Db, DbError};
+use crate::auth::{authorize, Access};
+use crate::pb::kv_service_server::KvService;
+use crate::pb::*;
+use crate::{blocking, deadline, GrpcOptions};
+
+const PAGE_DEFAULT: usize = 100;
+const PAGE_MAX: usize = 1000;
+
+pub struct KvApi {
+    db: Arc<Db>,
+    options: Arc<GrpcOptions>,
+}
+
+impl KvApi {
+    pub fn new(db: Arc<Db>, options: Arc<GrpcOptions>) -> Self {
+        Self { db, options }
+    }
+}
+
+/// The consistency a request names; the default when it names none
+fn consistency(name: &str) -> Result<Consistency, DbError> {
+    if name.is_empty() {
+        return Ok(Consistency::default());
+    }
+    Consistency::parse(name).ok_or_else(|| DbError::Invalid(format!("invalid consistency level: {}", name)))
+}
+
+#[tonic::async_trait]
+impl KvService for KvApi {
+    async fn get(&self, request: Request<KvGetRequest>) -> Result<Response<KvGetResponse>, Status> {
+        authorize(&self.options, &request, Access::Read)?;
+        let (deadline, req, db) = (deadline(&self.options, request.metadata()), request.into_inner(), self.db.clone());
+        let value = blocking(deadline, move || tonledb_nosql_kv::get_with(&*db.storage, &req.key, consistency(&req.consistency)?)).await?;
+        Ok(Response::new(KvGetResponse { value }))
+    }
+
+    async fn put(&self, request: Request<KvPutRequest>) -> Result<Response<KvPutResponse>, Status> {
+        authorize(&self.options, &request, Access::Write)?;
+        let (deadline, req, db) = (deadline(&self.options, request.metadata()), request.into_inner(), self.db.clone());
+        blocking(deadline, move || {
+            let consistency = consistency(&req.consistency)?;
+            match req.ttl_ms {
+                Some(ms) => tonledb_nosql_kv::put_with_ttl(&*db.storage, req.key, req.value, Duration::from_millis(ms)),
+                None => tonledb_nosql_kv::put_with(&*db.storage, req.key, req.value, consistency),
+            }
+        })
+        .await?;
+        Ok(Response::new(KvPutResponse {}))
+    }
+
+    async fn delete(&self, request: Request<KvDeleteRequest>) -> Result<Response<KvDeleteResponse>, Status> {
+        authorize(&self.options, &request, Access::Write)?;
+        let (deadline, req, db) = (deadline(&self.options, request.metadata()), request.into_inner(), self.db.clone());
+        blocking(deadline, move || tonledb_nosql_kv::del(&*db.storage, &req.key)).await?;
+        Ok(Response::new(KvDeleteResponse {}))
+    }
+
+    async fn scan(&self, request: Request<KvScanRequest>) -> Result<Response<KvScanResponse>, Status> {
+        authorize(&self.options, &request, Access::Read)?;
+        let (deadline, req, db) = (deadline(&self.options, request.metadata()), request.into_inner(), self.db.clone());
+        let limit = match req.limit {
+            0 => PAGE_DEFAULT,
+            n => (n as usize).min(PAGE_MAX),
+        };
+        let page = blocking(deadline, move || {
+            let after = (!req.cursor.is_empty()).then_some(req.cursor.as_slice());
+            tonledb_nosql_kv::scan_prefix_page(&*db.storage, &req.prefix, after, limit)
+        })
+        .await?;
+        let items = page.items.into_iter().map(|(key, value)| KvPair { key, value }).collect();
+        Ok(Response::new(KvScanResponse { items, next: page.next }))
+    }
+}
@@ -0,0 +1,140 @@
+//! gRPC API for TonleDB
+//!
+//! Typed services over a `Db` for service-to-service use, as an alternative to
+//! the HTTP JSON API: `KvService` (see `kv`), `DocumentService` (see `doc`) and
+//! `SqlService` (see `sql`), whose `Query` streams the rows of a statement one
+//! message each. The messages are in `proto/tonledb.proto`; clients generate
+//! their stubs from it, and Rust clients can use the ones in `pb`. Documents,
+//! filters and results are JSON text inside the messages, as the HTTP API takes
+//! and answers them.
+//!
+//! With a `TokenVerifier` in the `GrpcOptions`, calls need a user and token in
+//! their metadata (see `auth`). There is no TLS, so without a proxy in front the
+//! listener belongs on a trusted network.
+//!
+//! Calls run on blocking threads under the deadline the client sent as
+//! `grpc-timeout`, capped by `GrpcOptions::query_timeout` (see
+//! `tonledb_core::deadline`); past it they answer `DEADLINE_EXCEEDED`.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tonic::metadata::MetadataMap;
+use tonic::transport::server::Router;
+use tonic::transport::Server;
+use tonic::Status;
+use tonledb_core::{deadline, Db, DbError};
+use crate::auth::TokenVerifier;
+use crate::pb::document_service_server::DocumentServiceServer;
+use crate::pb::kv_service_server::KvServiceServer;
+use crate::pb::sql_service_server::SqlServiceServer;
+
+pub mod auth;
+pub mod doc;
+pub mod kv;
+pub mod sql;
+
+/// Messages, clients and service traits generated from `proto/tonledb.proto`
+pub mod pb {
+    tonic::include_proto!("tonledb.v1");
+}
+
+/// How the server lets callers in
+#[derive(Clone, Default)]
+pub struct GrpcOptions {
+    /// Checks callers' tokens; `None` lets every call through
+    pub tokens: Option<Arc<dyn TokenVerifier>>,
+    /// Longest a call may run; `grpc-timeout` can only shorten it
+    pub query_timeout: Option<Duration>,
+    /// Told about every statement `SqlService` runs
+    pub statements: Option<Arc<dyn StatementLog>>,
+}
+
+/// The server's record of SQL statements: its statement history and audit trail
+pub trait StatementLog: Send + Sync {
+    /// `user` ran `sql`, starting at `started_ms` (Unix milliseconds) and taking
+    /// `duration_ms`; `outcome` holds the error of a statement that failed
+    fn record(&self, user: &str, sql: &str, started_ms: u64, duration_ms: u64, outcome: Result<(), String>);
+}
+
+/// The three services over `db`, ready to serve
+pub fn router(db: Arc<Db>, options: GrpcOptions) -> Router {
+    let options = Arc::new(options);
+    Server::builder()
+        .add_service(KvServiceServer::new(kv::KvApi::new(db.clone(), options.clone())))
+        .add_service(DocumentServiceServer::new(doc::DocumentApi::new(db.clone(), options.clone())))
+        .add_service(SqlServiceServer::new(sql::SqlApi::new(db, options)))
+}
+
+/// Start a gRPC server
+pub async fn start_grpc_server(db: Arc<Db>, bind_addr: &str, options: GrpcOptions) -> Result<(), anyhow::Error> {
+    let addr = bind_addr.parse()?;
+    tracing::info!(%bind_addr, "gRPC server listening");
+    router(db, options).serve(addr).await?;
+    Ok(())
+}
+
+/// gRPC status of an engine error
+pub fn status(e: DbError) -> Status {
+    let message = e.to_string();
+    match e {
+        DbError::Invalid(_) => Status::invalid_argument(message),
+        DbError::NotFound(_) => Status::not_found(message),
+        DbError::Conflict(_) => Status::aborted(message),
+        DbError::ResourceExhausted(_) => Status::resource_exhausted(message),
+        DbError::DeadlineExceeded(_) => Status::deadline_exceeded(message),
+        DbError::UniqueViolation(_) => Status::already_exists(message),
+        DbError::NotNullViolation(_) => Status::invalid_argument(message),
+        DbError::Storage(_) => Status::internal(message),
+        DbError::Corruption(_) => Status::data_loss(message),
+    }
+}
+
+/// The JSON in `text`, the field `what` of a request
+pub(crate) fn parse_json(text: &str, what: &str) -> Result<serde_json::Value, DbError> {
+    serde_json::from_str(text).map_err(|e| DbError::Invalid(format!("{} is not JSON: {}", what, e)))
+}
+
+/// When a call must be answered by: its `grpc-timeout` from now, capped by the
+/// server's `query_timeout`
+pub(crate) fn deadline(options: &GrpcOptions, metadata: &MetadataMap) -> Option<Instant> {
+    let asked = metadata.get("grpc-timeout").and_then(|v| v.to_str().ok()).and_then(grpc_timeout);
+    let timeout = match (asked, options.query_timeout) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    };
+    timeout.map(|t| Instant::now() + t)
+}
+
+/// A `grpc-timeout` value: at most eight digits and a unit (`H`, `M`, `S`, `m`,
+/// `u` or `n`)
+fn grpc_timeout(value: &str) -> Option<Duration> {
+    if !value.is_ascii() {
+        return None;
+    }
+    let (digits, unit) = value.split_at(value.len().checked_sub(1)?);
+    if digits.is_empty() || digits.len() > 8 {
+        return None;
+    }
+    let n: u64 = digits.parse().ok()?;
+    Some(match unit {
+        "H" => Duration::from_secs(n * 3600),
+        "M" => Duration::from_secs(n * 60),
+        "S" => Duration::from_secs(n),
+        "m" => Duration::from_millis(n),
+        "u" => Duration::from_micros(n),
+        "n" => Duration::from_nanos(n),
+        _ => return None,
+    })
+}
+
+/// Run `f` on a blocking thread with `deadline` in force, so engine calls don't
+/// hold up the executor
+pub(crate) async fn blocking<T: Send + 'static>(
+    deadline: Option<Instant>,
+    f: impl FnOnce() -> Result<T, DbError> + Send + 'static,
+) -> Result<T, Status> {
+    match tokio::task::spawn_blocking(move || deadline::within(deadline, f)).await {
+        Ok(result) => result.map_err(status),
+        Err(e) => Err(Status::internal(e.to_string())),
+    }
+}
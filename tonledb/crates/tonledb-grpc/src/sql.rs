@@ -0,0 +1,80 @@
+//! `SqlService`: statements run with `tonledb_sql::execute_sql`, as `/sql` runs them
+//!
+//! `Execute` answers the whole result as one JSON value. `Query` sends the rows
+//! one message each, encoding each as it is sent, so a large result never has to
+//! fit in one message; a result that isn't rows (`EXPLAIN`, `ANALYZE`) comes as a
+//! single message. Like `/sql`, every statement is timed in the `sql` query
+//! latency and told to the `StatementLog`, if there is one.
+
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use serde_json::Value as Json;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+use tonledb_core::Db;
+use tonledb_metrics::QueryTimer;
+use crate::auth::{authorize, Access};
+use crate::pb::sql_service_server::SqlService;
+use crate::pb::*;
+use crate::{blocking, deadline, GrpcOptions};
+
+pub struct SqlApi {
+    db: Arc<Db>,
+    options: Arc<GrpcOptions>,
+}
+
+impl SqlApi {
+    pub fn new(db: Arc<Db>, options: Arc<GrpcOptions>) -> Self {
+        Self { db, options }
+    }
+
+    /// Run `sql` for `user` on a blocking thread under `deadline`, timing it and
+    /// telling the statement log
+    async fn run(&self, user: String, sql: String, deadline: Option<Instant>) -> Result<Json, Status> {
+        let (db, statements) = (self.db.clone(), self.options.statements.clone());
+        blocking(deadline, move || {
+            let timer = QueryTimer::start("sql");
+            let started_ms = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+            let started = Instant::now();
+            let result = tonledb_sql::execute_sql(&db, &sql);
+            timer.stop();
+            if let Some(log) = &statements {
+                let outcome = result.as_ref().map(|_| ()).map_err(ToString::to_string);
+                log.record(&user, &sql, started_ms, started.elapsed().as_millis() as u64, outcome);
+            }
+            result
+        })
+        .await
+    }
+}
+
+#[tonic::async_trait]
+impl SqlService for SqlApi {
+    async fn execute(&self, request: Request<SqlRequest>) -> Result<Response<SqlResponse>, Status> {
+        let user = authorize(&self.options, &request, Access::Write)?;
+        let deadline = deadline(&self.options, request.metadata());
+        let result = self.run(user, request.into_inner().sql, deadline).await?;
+        Ok(Response::new(SqlResponse { result: result.to_string() }))
+    }
+
+    type QueryStream = ReceiverStream<Result<Row, Status>>;
+
+    async fn query(&self, request: Request<SqlRequest>) -> Result<Response<Self::QueryStream>, Status> {
+        let user = authorize(&self.options, &request, Access::Write)?;
+        let deadline = deadline(&self.options, request.metadata());
+        let rows = match self.run(user, request.into_inner().sql, deadline).await? {
+            Json::Array(rows) => rows,
+            other => vec![other],
+        };
+        // Each row is encoded when the client is ready for it
+        let (tx, rx) = tokio::sync::mpsc::channel(16);
+        tokio::spawn(async move {
+            for row in rows {
+                if tx.send(Ok(Row { json: row.to_string() })).await.is_err() {
+                    break; // the client went away
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
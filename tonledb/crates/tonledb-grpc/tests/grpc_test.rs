@@ -0,0 +1,184 @@
+//! Tests for the gRPC services, called through the generated clients over a
+//! local connection
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use serde_json::json;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tokio_stream::StreamExt;
+use tonic::transport::Channel;
+use tonic::{Code, Request};
+use tonledb_core::{DataType, Db};
+use tonledb_grpc::auth::{Access, TokenVerifier};
+use tonledb_grpc::pb::document_service_client::DocumentServiceClient;
+use tonledb_grpc::pb::kv_service_client::KvServiceClient;
+use tonledb_grpc::pb::sql_service_client::SqlServiceClient;
+use tonledb_grpc::pb::*;
+use tonledb_grpc::{router, GrpcOptions, StatementLog};
+use tonledb_sql::fixture;
+use tonledb_storage::InMemoryStore;
+
+fn orders_db() -> Arc<Db> {
+    let db = Db::new(Arc::new(InMemoryStore::new(1000)));
+    fixture::create_table(&db, "orders", &[("id", DataType::Integer), ("customer", DataType::Text)], Some("id"));
+    fixture::insert_rows(&db, "orders", &[json!({"id": 1, "customer": "ann"}), json!({"id": 2, "customer": "bob"}), json!({"id": 3, "customer": "cy"})]).unwrap();
+    Arc::new(db)
+}
+
+/// Serve `db` on a free local port; a channel to it
+async fn serve(db: Arc<Db>, options: GrpcOptions) -> Channel {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(router(db, options).serve_with_incoming(TcpListenerStream::new(listener)));
+    Channel::from_shared(format!("http://{}", addr)).unwrap().connect().await.unwrap()
+}
+
+#[tokio::test]
+async fn test_kv_get_put_delete_and_scan() {
+    let mut kv = KvServiceClient::new(serve(orders_db(), GrpcOptions::default()).await);
+    for key in ["user:1", "user:2", "user:3", "other"] {
+        kv.put(KvPutRequest { key: key.into(), value: format!("v-{}", key).into(), ..Default::default() }).await.unwrap();
+    }
+    let value = kv.get(KvGetRequest { key: b"user:2".to_vec(), consistency: String::new() }).await.unwrap().into_inner().value;
+    assert_eq!(value.as_deref(), Some(&b"v-user:2"[..]));
+
+    kv.delete(KvDeleteRequest { key: b"user:2".to_vec() }).await.unwrap();
+    assert_eq!(kv.get(KvGetRequest { key: b"user:2".to_vec(), consistency: String::new() }).await.unwrap().into_inner().value, None);
+
+    // A page at a time, resumed from `next`
+    let page = kv.scan(KvScanRequest { prefix: b"user:".to_vec(), cursor: vec![], limit: 1 }).await.unwrap().into_inner();
+    assert_eq!(page.items, vec![KvPair { key: b"user:1".to_vec(), value: b"v-user:1".to_vec() }]);
+    let cursor = page.next.unwrap();
+    let page = kv.scan(KvScanRequest { prefix: b"user:".to_vec(), cursor, limit: 10 }).await.unwrap().into_inner();
+    assert_eq!(page.items.iter().map(|p| p.key.as_slice()).collect::<Vec<_>>(), [&b"user:3"[..]]);
+    assert_eq!(page.next, None);
+
+    let bad = kv.get(KvGetRequest { key: b"user:1".to_vec(), consistency: "most".into() }).await.unwrap_err();
+    assert_eq!(bad.code(), Code::InvalidArgument);
+}
+
+#[tokio::test]
+async fn test_documents() {
+    let mut docs = DocumentServiceClient::new(serve(orders_db(), GrpcOptions::default()).await);
+    let mut ids = Vec::new();
+    for (name, age) in [("ann", 31), ("bob", 17), ("cy", 45)] {
+        let document = json!({"name": name, "age": age}).to_string();
+        ids.push(docs.insert(InsertRequest { collection: "people".into(), document }).await.unwrap().into_inner().id);
+    }
+    let got = docs.get(GetDocumentRequest { collection: "people".into(), id: ids[1].clone() }).await.unwrap().into_inner();
+    assert_eq!(serde_json::from_str::<serde_json::Value>(&got.document.unwrap()).unwrap()["name"], "bob");
+
+    let adults = r#"{"age": {"$gte": 18}}"#.to_string();
+    let count = docs.count(CountRequest { collection: "people".into(), filter: adults.clone() }).await.unwrap().into_inner().count;
+    assert_eq!(count, 2);
+    let found = docs.find(FindRequest { collection: "people".into(), filter: adults, ..Default::default() }).await.unwrap().into_inner();
+    let mut names: Vec<String> = found.documents.iter().map(|d| serde_json::from_str::<serde_json::Value>(d).unwrap()["name"].as_str().unwrap().to_string()).collect();
+    names.sort();
+    assert_eq!(names, ["ann", "cy"]);
+
+    // Paged, then every document for an empty filter
+    let page = docs.find(FindRequest { collection: "people".into(), limit: Some(2), ..Default::default() }).await.unwrap().into_inner();
+    assert_eq!(page.documents.len(), 2);
+    let rest = docs.find(FindRequest { collection: "people".into(), after: page.next, ..Default::default() }).await.unwrap().into_inner();
+    assert_eq!(rest.documents.len(), 1);
+
+    let deleted = docs.delete(DeleteDocumentRequest { collection: "people".into(), id: ids[0].clone() }).await.unwrap().into_inner();
+    assert!(deleted.deleted);
+    let got = docs.get(GetDocumentRequest { collection: "people".into(), id: ids[0].clone() }).await.unwrap().into_inner();
+    assert_eq!(got.document, None);
+
+    let bad = docs.insert(InsertRequest { collection: "people".into(), document: "{name".into() }).await.unwrap_err();
+    assert_eq!(bad.code(), Code::InvalidArgument);
+}
+
+#[tokio::test]
+async fn test_sql_execute_and_streamed_query() {
+    let mut sql = SqlServiceClient::new(serve(orders_db(), GrpcOptions::default()).await);
+    let result = sql.execute(SqlRequest { sql: "SELECT customer FROM orders WHERE id = 2".into() }).await.unwrap().into_inner().result;
+    assert_eq!(serde_json::from_str::<serde_json::Value>(&result).unwrap(), json!([{"customer": "bob"}]));
+
+    let mut rows = sql.query(SqlRequest { sql: "SELECT * FROM orders".into() }).await.unwrap().into_inner();
+    let mut customers = Vec::new();
+    while let Some(row) = rows.next().await {
+        let row: serde_json::Value = serde_json::from_str(&row.unwrap().json).unwrap();
+        customers.push(row["customer"].as_str().unwrap().to_string());
+    }
+    customers.sort();
+    assert_eq!(customers, ["ann", "bob", "cy"]);
+
+    let bad = sql.query(SqlRequest { sql: "DELETE FROM orders".into() }).await.unwrap_err();
+    assert_eq!(bad.code(), Code::InvalidArgument);
+}
+
+/// `reader` may read and `writer` may write; both with the token `secret`
+struct Users;
+
+impl TokenVerifier for Users {
+    fn verify(&self, name: &str, token: &str) -> Option<Access> {
+        match (name, token) {
+            ("reader", "secret") => Some(Access::Read),
+            ("writer", "secret") => Some(Access::Write),
+            _ => None,
+        }
+    }
+}
+
+fn as_user<T>(message: T, name: &str, token: &str) -> Request<T> {
+    let mut request = Request::new(message);
+    request.metadata_mut().insert("x-auth-name", name.parse().unwrap());
+    request.metadata_mut().insert("x-auth-token", token.parse().unwrap());
+    request
+}
+
+#[tokio::test]
+async fn test_tokens() {
+    let options = GrpcOptions { tokens: Some(Arc::new(Users)), ..Default::default() };
+    let mut kv = KvServiceClient::new(serve(orders_db(), options).await);
+    let put = || KvPutRequest { key: b"k".to_vec(), value: b"v".to_vec(), ..Default::default() };
+    let get = || KvGetRequest { key: b"k".to_vec(), consistency: String::new() };
+
+    assert_eq!(kv.get(get()).await.unwrap_err().code(), Code::Unauthenticated);
+    assert_eq!(kv.get(as_user(get(), "reader", "wrong")).await.unwrap_err().code(), Code::Unauthenticated);
+    assert_eq!(kv.put(as_user(put(), "reader", "secret")).await.unwrap_err().code(), Code::PermissionDenied);
+    kv.put(as_user(put(), "writer", "secret")).await.unwrap();
+    let value = kv.get(as_user(get(), "reader", "secret")).await.unwrap().into_inner().value;
+    assert_eq!(value.as_deref(), Some(&b"v"[..]));
+}
+
+/// The statements told to it: user, SQL and whether it succeeded
+#[derive(Default)]
+struct Statements(Mutex<Vec<(String, String, bool)>>);
+
+impl StatementLog for Statements {
+    fn record(&self, user: &str, sql: &str, _started_ms: u64, _duration_ms: u64, outcome: Result<(), String>) {
+        self.0.lock().unwrap().push((user.into(), sql.into(), outcome.is_ok()));
+    }
+}
+
+#[tokio::test]
+async fn test_statements_are_logged() {
+    let statements = Arc::new(Statements::default());
+    let options = GrpcOptions { tokens: Some(Arc::new(Users)), statements: Some(statements.clone()), ..Default::default() };
+    let mut sql = SqlServiceClient::new(serve(orders_db(), options).await);
+    sql.execute(as_user(SqlRequest { sql: "SELECT * FROM orders".into() }, "writer", "secret")).await.unwrap();
+    sql.query(as_user(SqlRequest { sql: "DELETE FROM orders".into() }, "writer", "secret")).await.unwrap_err();
+    assert_eq!(
+        *statements.0.lock().unwrap(),
+        [("writer".into(), "SELECT * FROM orders".into(), true), ("writer".into(), "DELETE FROM orders".into(), false)]
+    );
+}
+
+#[tokio::test]
+async fn test_calls_past_the_deadline_give_up() {
+    let options = GrpcOptions { query_timeout: Some(Duration::ZERO), ..Default::default() };
+    let mut sql = SqlServiceClient::new(serve(orders_db(), options).await);
+    let late = sql.execute(SqlRequest { sql: "SELECT * FROM orders".into() }).await.unwrap_err();
+    assert_eq!(late.code(), Code::DeadlineExceeded);
+
+    // A client's `grpc-timeout` applies when there is no cap
+    let mut sql = SqlServiceClient::new(serve(orders_db(), GrpcOptions::default()).await);
+    let mut request = Request::new(SqlRequest { sql: "SELECT * FROM orders".into() });
+    request.set_timeout(Duration::from_secs(10));
+    sql.execute(request).await.unwrap();
+}
@@ -2,6 +2,7 @@ use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use anyhow::Result;
+use tonledb_core::schema_inference::InferredSchema;
 
 #[derive(Debug, Clone)]
 pub struct ConnectionConfig {
@@ -15,12 +16,15 @@ pub struct ConnectionConfig {
 #[derive(Debug)]
 pub struct ConnectionManager {
     connections: Arc<RwLock<HashMap<String, ConnectionConfig>>>,
+    /// Inferred collection schemas, keyed by collection name, used for completions
+    schemas: Arc<RwLock<HashMap<String, InferredSchema>>>,
 }
 
 impl ConnectionManager {
     pub fn new() -> Self {
         Self {
             connections: Arc::new(RwLock::new(HashMap::new())),
+            schemas: Arc::new(RwLock::new(HashMap::new())),
         }
     }
     
@@ -58,4 +62,15 @@ impl ConnectionManager {
             Err(anyhow::anyhow!("Connection '{}' not found", name))
         }
     }
-}
\ No newline at end of file
+    
+    /// Replace the known collection schemas (e.g. from `schema_inference::list`)
+    pub async fn set_schemas(&self, schemas: Vec<InferredSchema>) {
+        let mut known = self.schemas.write().await;
+        *known = schemas.into_iter().map(|s| (s.collection.clone(), s)).collect();
+    }
+    
+    pub async fn schemas(&self) -> Vec<InferredSchema> {
+        let known = self.schemas.read().await;
+        known.values().cloned().collect()
+    }
+}
@@ -4,6 +4,8 @@ use tower_lsp::lsp_types::*;
 use std::sync::Arc;
 use tokio::io::{stdin, stdout};
 
+use tonledb_core::schema_inference::InferredSchema;
+
 mod connection;
 pub use connection::{ConnectionManager, ConnectionConfig};
 
@@ -50,8 +52,9 @@ impl LanguageServer for Backend {
     }
 
     async fn completion(&self, _: CompletionParams) -> Result<Option<CompletionResponse>> {
-        // Provide completion suggestions
-        Ok(None)
+        // Suggest collections and their inferred fields
+        let items = completion_items(&self.connection_manager.schemas().await);
+        Ok(if items.is_empty() { None } else { Some(CompletionResponse::Array(items)) })
     }
 
     async fn hover(&self, _: HoverParams) -> Result<Option<Hover>> {
@@ -60,11 +63,37 @@ impl LanguageServer for Backend {
     }
 }
 
+/// Completion items for every collection and each field of its inferred schema
+pub fn completion_items(schemas: &[InferredSchema]) -> Vec<CompletionItem> {
+    let mut items = Vec::new();
+    for schema in schemas {
+        items.push(CompletionItem {
+            label: schema.collection.clone(),
+            kind: Some(CompletionItemKind::STRUCT),
+            detail: Some(format!("collection ({} docs sampled)", schema.sampled)),
+            ..CompletionItem::default()
+        });
+        for field in &schema.fields {
+            items.push(CompletionItem {
+                label: field.name.clone(),
+                kind: Some(CompletionItemKind::FIELD),
+                detail: Some(format!("{}.{}: {:?} ({:.0}% null)", schema.collection, field.name, field.data_type(), field.null_rate * 100.0)),
+                ..CompletionItem::default()
+            });
+        }
+    }
+    items
+}
+
 pub struct LanguageServerManager;
 
 impl LanguageServerManager {
     pub async fn run() -> Result<()> {
-        let connection_manager = Arc::new(ConnectionManager::new());
+        Self::run_with(Arc::new(ConnectionManager::new())).await
+    }
+    
+    /// Run with a caller-provided connection manager (e.g. preloaded with schemas)
+    pub async fn run_with(connection_manager: Arc<ConnectionManager>) -> Result<()> {
         let (service, socket) = LspService::new(|client| Backend { 
             client, 
             connection_manager 
@@ -1,6 +1,7 @@
 use once_cell::sync::Lazy;
 use prometheus::{
-    Encoder, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder,
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
 };
 use std::time::Duration;
 use tracing_subscriber::{fmt, EnvFilter};
@@ -25,6 +26,51 @@ static WAL_APPENDS: Lazy<IntCounterVec> = Lazy::new(|| {
     .unwrap()
 });
 
+static WAL_APPEND_LATENCY: Lazy<Histogram> = Lazy::new(|| {
+    Histogram::with_opts(
+        HistogramOpts::new(
+            "tonledb_wal_append_latency_seconds",
+            "Time to append a record (or transaction) to the WAL, rotation included",
+        )
+        .buckets(vec![
+            0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.05, 0.1,
+        ]),
+    )
+    .unwrap()
+});
+
+static WAL_FSYNCS: Lazy<IntCounter> = Lazy::new(|| {
+    IntCounter::new("tonledb_wal_fsyncs_total", "fsync calls made by the WAL").unwrap()
+});
+
+static WAL_SIZE: Lazy<IntGauge> = Lazy::new(|| {
+    IntGauge::new(
+        "tonledb_wal_size_bytes",
+        "Bytes a restart would replay: newest checkpoint, sealed segments after it, active segment",
+    )
+    .unwrap()
+});
+
+static WAL_REPLAY: Lazy<Histogram> = Lazy::new(|| {
+    Histogram::with_opts(
+        HistogramOpts::new("tonledb_wal_replay_duration_seconds", "Time to read back the whole WAL")
+            .buckets(vec![0.001, 0.01, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0]),
+    )
+    .unwrap()
+});
+
+static KV_KEYS: Lazy<IntGauge> = Lazy::new(|| {
+    IntGauge::new("tonledb_kv_keys", "Keys in the kv space").unwrap()
+});
+
+static KV_VALUE_BYTES: Lazy<IntGauge> = Lazy::new(|| {
+    IntGauge::new("tonledb_kv_value_bytes", "Bytes of values in the kv space").unwrap()
+});
+
+static KV_TTL_KEYS: Lazy<IntGauge> = Lazy::new(|| {
+    IntGauge::new("tonledb_kv_ttl_keys", "Keys in the kv space that expire").unwrap()
+});
+
 static QUERY_LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
     HistogramVec::new(
         HistogramOpts::new(
@@ -41,6 +87,62 @@ static QUERY_LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
     .unwrap()
 });
 
+static TIER_BYTES: Lazy<IntGaugeVec> = Lazy::new(|| {
+    IntGaugeVec::new(
+        Opts::new("tonledb_tier_bytes", "Bytes held per storage tier and collection"),
+        &["tier", "collection"], // tier: "hot" | "cold"
+    )
+    .unwrap()
+});
+
+static DOC_DOCUMENTS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    IntGaugeVec::new(Opts::new("tonledb_doc_documents", "Documents per collection"), &["collection"]).unwrap()
+});
+
+static DOC_BYTES: Lazy<IntGaugeVec> = Lazy::new(|| {
+    IntGaugeVec::new(Opts::new("tonledb_doc_bytes", "Bytes of documents per collection"), &["collection"]).unwrap()
+});
+
+static DOC_INDEX_BYTES: Lazy<IntGaugeVec> = Lazy::new(|| {
+    IntGaugeVec::new(
+        Opts::new("tonledb_doc_index_bytes", "Bytes of index entries per collection and field"),
+        &["collection", "field"],
+    )
+    .unwrap()
+});
+
+static BACKUPS: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new("tonledb_scheduled_backups_total", "Scheduled backups run"),
+        &["result"], // "ok" | "err"
+    )
+    .unwrap()
+});
+
+static BACKUP_DURATION: Lazy<Histogram> = Lazy::new(|| {
+    Histogram::with_opts(
+        HistogramOpts::new("tonledb_backup_duration_seconds", "Time to take a scheduled backup")
+            .buckets(vec![0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0, 900.0, 3600.0]),
+    )
+    .unwrap()
+});
+
+static BACKUP_LAST_SUCCESS: Lazy<IntGauge> = Lazy::new(|| {
+    IntGauge::new(
+        "tonledb_backup_last_success_timestamp_seconds",
+        "When the last scheduled backup succeeded (Unix seconds)",
+    )
+    .unwrap()
+});
+
+static BACKUP_LAST_BYTES: Lazy<IntGauge> = Lazy::new(|| {
+    IntGauge::new("tonledb_backup_last_size_bytes", "Size of the last successful scheduled backup").unwrap()
+});
+
+static BACKUPS_KEPT: Lazy<IntGauge> = Lazy::new(|| {
+    IntGauge::new("tonledb_backups_retained", "Backups held in the scheduled backup target after rotation").unwrap()
+});
+
 /// Initialize tracing and register metrics. Idempotent.
 pub fn init_tracing_and_metrics(default_level: &str) {
     // Tracing
@@ -53,7 +155,23 @@ pub fn init_tracing_and_metrics(default_level: &str) {
     // Metrics registration (ignore AlreadyReg errors)
     let _ = REGISTRY.register(Box::new(HTTP_REQS.clone()));
     let _ = REGISTRY.register(Box::new(WAL_APPENDS.clone()));
+    let _ = REGISTRY.register(Box::new(WAL_APPEND_LATENCY.clone()));
+    let _ = REGISTRY.register(Box::new(WAL_FSYNCS.clone()));
+    let _ = REGISTRY.register(Box::new(WAL_SIZE.clone()));
+    let _ = REGISTRY.register(Box::new(WAL_REPLAY.clone()));
+    let _ = REGISTRY.register(Box::new(KV_KEYS.clone()));
+    let _ = REGISTRY.register(Box::new(KV_VALUE_BYTES.clone()));
+    let _ = REGISTRY.register(Box::new(KV_TTL_KEYS.clone()));
     let _ = REGISTRY.register(Box::new(QUERY_LATENCY.clone()));
+    let _ = REGISTRY.register(Box::new(TIER_BYTES.clone()));
+    let _ = REGISTRY.register(Box::new(DOC_DOCUMENTS.clone()));
+    let _ = REGISTRY.register(Box::new(DOC_BYTES.clone()));
+    let _ = REGISTRY.register(Box::new(DOC_INDEX_BYTES.clone()));
+    let _ = REGISTRY.register(Box::new(BACKUPS.clone()));
+    let _ = REGISTRY.register(Box::new(BACKUP_DURATION.clone()));
+    let _ = REGISTRY.register(Box::new(BACKUP_LAST_SUCCESS.clone()));
+    let _ = REGISTRY.register(Box::new(BACKUP_LAST_BYTES.clone()));
+    let _ = REGISTRY.register(Box::new(BACKUPS_KEPT.clone()));
 }
 
 /// Observe one HTTP request
@@ -68,6 +186,69 @@ pub fn observe_wal_append(result: &str) {
     WAL_APPENDS.with_label_values(&[result]).inc();
 }
 
+/// Observe how long one WAL append took
+pub fn observe_wal_append_latency(elapsed: Duration) {
+    WAL_APPEND_LATENCY.observe(duration_to_secs(elapsed));
+}
+
+/// Count one WAL fsync
+pub fn inc_wal_fsyncs() {
+    WAL_FSYNCS.inc();
+}
+
+/// Set the bytes a restart would replay
+pub fn set_wal_size_bytes(bytes: u64) {
+    WAL_SIZE.set(bytes as i64);
+}
+
+/// Observe how long a full WAL replay took
+pub fn observe_wal_replay(elapsed: Duration) {
+    WAL_REPLAY.observe(duration_to_secs(elapsed));
+}
+
+/// Set the size of the kv space: keys, value bytes and keys with a TTL
+pub fn set_kv_stats(keys: u64, value_bytes: u64, ttl_keys: u64) {
+    KV_KEYS.set(keys as i64);
+    KV_VALUE_BYTES.set(value_bytes as i64);
+    KV_TTL_KEYS.set(ttl_keys as i64);
+}
+
+/// Set the size of document `collection`: documents, their bytes and the bytes of
+/// each index, as `(field, bytes)`
+pub fn set_doc_stats(collection: &str, documents: u64, bytes: u64, index_bytes: &[(&str, u64)]) {
+    DOC_DOCUMENTS.with_label_values(&[collection]).set(documents as i64);
+    DOC_BYTES.with_label_values(&[collection]).set(bytes as i64);
+    for (field, bytes) in index_bytes {
+        DOC_INDEX_BYTES.with_label_values(&[collection, field]).set(*bytes as i64);
+    }
+}
+
+/// Set the current size of `collection` in `tier` ("hot" | "cold")
+pub fn set_tier_bytes(tier: &str, collection: &str, bytes: u64) {
+    TIER_BYTES
+        .with_label_values(&[tier, collection])
+        .set(bytes as i64);
+}
+
+/// Observe a scheduled backup that succeeded: its size, how long it took and
+/// when it finished (Unix seconds)
+pub fn observe_backup_ok(bytes: u64, elapsed: Duration, finished_at: u64) {
+    BACKUPS.with_label_values(&["ok"]).inc();
+    BACKUP_DURATION.observe(duration_to_secs(elapsed));
+    BACKUP_LAST_SUCCESS.set(finished_at as i64);
+    BACKUP_LAST_BYTES.set(bytes as i64);
+}
+
+/// Count a scheduled backup that failed
+pub fn observe_backup_err() {
+    BACKUPS.with_label_values(&["err"]).inc();
+}
+
+/// Set how many backups the scheduled backup target holds
+pub fn set_backups_retained(count: u64) {
+    BACKUPS_KEPT.set(count as i64);
+}
+
 /// Time a query and record latency under `kind`
 pub struct QueryTimer {
     start: std::time::Instant,
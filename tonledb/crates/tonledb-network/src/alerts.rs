@@ -0,0 +1,21 @@
+use tonledb_core::system_events::{AlertRule, AlertSink, SystemEvent};
+
+/// Delivers fired alert rules as JSON POSTs to the rule's webhook URL.
+pub struct WebhookSink { client: reqwest::Client, rt: tokio::runtime::Handle }
+
+impl WebhookSink {
+    /// Must be created inside the Tokio runtime; deliveries are spawned onto it.
+    pub fn new() -> Self { Self { client: reqwest::Client::new(), rt: tokio::runtime::Handle::current() } }
+}
+
+impl AlertSink for WebhookSink {
+    fn fire(&self, rule: &AlertRule, event: &SystemEvent) {
+        let body = serde_json::json!({ "rule": rule.name, "event": event });
+        let (client, url, name) = (self.client.clone(), rule.webhook.clone(), rule.name.clone());
+        self.rt.spawn(async move {
+            if let Err(e) = client.post(&url).json(&body).send().await {
+                tracing::warn!(rule=%name, %url, error=%e, "alert webhook delivery failed");
+            }
+        });
+    }
+}
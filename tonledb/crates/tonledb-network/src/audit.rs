@@ -14,3 +14,23 @@ pub fn log(ev: &AuditEvent) {
     let mut f = AUDIT.lock().unwrap();
     let _ = writeln!(f, "{}", s);
 }
+
+/// The pg port's COPY FROM goes to the same trail as the HTTP API
+pub struct PgAudit;
+impl tonledb_wire_pg::AuditLog for PgAudit {
+    fn record(&self, user: &str, action: &str, resource: &str, result: &str) {
+        log(&AuditEvent{ ts: &chrono::Utc::now().to_rfc3339(), who: user, action, resource, result });
+    }
+}
+
+/// The gRPC port's SQL goes to the same statement history and audit trail as `/sql`
+pub struct GrpcStatements(pub std::sync::Arc<tonledb_core::statement_history::StatementHistory>);
+impl tonledb_grpc::StatementLog for GrpcStatements {
+    fn record(&self, user: &str, sql: &str, started_ms: u64, duration_ms: u64, outcome: Result<(), String>) {
+        let result = if outcome.is_ok() { "ok" } else { "error" };
+        if let Err(e) = self.0.record(user, sql, started_ms, duration_ms, outcome) {
+            tracing::warn!(error=%e, "failed to record statement history");
+        }
+        log(&AuditEvent{ ts: &chrono::Utc::now().to_rfc3339(), who: user, action:"SQL", resource:"grpc:SqlService", result });
+    }
+}
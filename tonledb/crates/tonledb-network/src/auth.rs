@@ -11,18 +11,32 @@ pub enum Role { Admin, ReadWrite, ReadOnly }
 impl Role { pub fn from_str(s: &str) -> Self { match s { "admin"=>Self::Admin, "readwrite"=>Self::ReadWrite, _=>Self::ReadOnly } } }
 
 #[derive(Deserialize)]
-struct TokenEntry { name: String, role: String, hash: String }
+struct TokenEntry {
+    name: String, role: String, hash: String,
+    /// Postgres password for the pg port, as `pg_authid.rolpassword` holds it (`SCRAM-SHA-256$…`
+    /// or `md5…`); without one, pg clients send the token in cleartext
+    #[serde(default)] pg_password: Option<String>,
+    /// `mysql_native_password` hash for the mysql port (`*` and 40 hex digits); without one,
+    /// mysql clients send the token in cleartext
+    #[serde(default)] mysql_password: Option<String>,
+}
 #[derive(Deserialize)]
 struct TokenFile { tokens: Vec<TokenEntry> }
 
 #[derive(Clone)]
-pub struct TokenStore { map: HashMap<String,(String,Role)> }
+pub struct TokenStore { map: HashMap<String,(String,Role)>, pg: HashMap<String,String>, mysql: HashMap<String,String> }
 impl TokenStore {
     pub fn from_file(p: &str) -> anyhow::Result<Self> {
         let tf: TokenFile = serde_json::from_str(&fs::read_to_string(p)?)?;
         let mut map = HashMap::new();
-        for t in tf.tokens { map.insert(t.name.clone(), (t.hash, Role::from_str(&t.role))); }
-        Ok(Self{ map })
+        let mut pg = HashMap::new();
+        let mut mysql = HashMap::new();
+        for t in tf.tokens {
+            if let Some(pw) = t.pg_password { pg.insert(t.name.clone(), pw); }
+            if let Some(pw) = t.mysql_password { mysql.insert(t.name.clone(), pw); }
+            map.insert(t.name.clone(), (t.hash, Role::from_str(&t.role)));
+        }
+        Ok(Self{ map, pg, mysql })
     }
     pub fn verify(&self, name:&str, token:&str) -> Option<Identity> {
         let (hash, role) = self.map.get(name)?;
@@ -30,10 +44,48 @@ impl TokenStore {
         Argon2::default().verify_password(token.as_bytes(), &parsed).ok()?;
         Some(Identity{ name: name.to_string(), role: role.clone() })
     }
+    /// Add a user unless one of that name exists; `hash` is the Argon2 hash of its token
+    pub fn add_if_absent(&mut self, name:&str, hash:&str, role:Role) -> bool {
+        if self.map.contains_key(name) { return false; }
+        self.map.insert(name.to_string(), (hash.to_string(), role));
+        true
+    }
 }
 impl Default for TokenStore {
     fn default() -> Self {
-        Self { map: HashMap::new() }
+        Self { map: HashMap::new(), pg: HashMap::new(), mysql: HashMap::new() }
+    }
+}
+/// The pg port checks the same users: their token, or their `pg_password` when set
+impl tonledb_wire_pg::auth::PasswordStore for TokenStore {
+    fn verify(&self, user: &str, password: &str) -> bool { TokenStore::verify(self, user, password).is_some() }
+    fn access(&self, user: &str) -> tonledb_wire_pg::auth::Access {
+        use tonledb_wire_pg::auth::Access;
+        match self.map.get(user).map(|(_, role)| role) { Some(Role::Admin) => Access::Admin, Some(Role::ReadWrite) => Access::Write, _ => Access::Read }
+    }
+    fn stored_password(&self, user: &str) -> Option<String> { self.pg.get(user).cloned() }
+}
+/// So does the mysql port: their token, or their `mysql_password` when set
+impl tonledb_wire_mysql::auth::PasswordStore for TokenStore {
+    fn verify(&self, user: &str, password: &str) -> bool { TokenStore::verify(self, user, password).is_some() }
+    fn access(&self, user: &str) -> tonledb_wire_mysql::auth::Access {
+        use tonledb_wire_mysql::auth::Access;
+        match self.map.get(user).map(|(_, role)| role) { Some(Role::Admin) => Access::Admin, Some(Role::ReadWrite) => Access::Write, _ => Access::Read }
+    }
+    fn native_password(&self, user: &str) -> Option<String> { self.mysql.get(user).cloned() }
+}
+/// The mongo port logs users in with their token as the SASL PLAIN password
+impl tonledb_wire_mongo::auth::PasswordStore for TokenStore {
+    fn verify(&self, user: &str, password: &str) -> Option<tonledb_wire_mongo::auth::Access> {
+        use tonledb_wire_mongo::auth::Access;
+        Some(match TokenStore::verify(self, user, password)?.role { Role::Admin => Access::Admin, Role::ReadWrite => Access::Write, Role::ReadOnly => Access::Read })
+    }
+}
+/// gRPC calls name a user and token in their metadata, with the user's role as on HTTP
+impl tonledb_grpc::auth::TokenVerifier for TokenStore {
+    fn verify(&self, name: &str, token: &str) -> Option<tonledb_grpc::auth::Access> {
+        use tonledb_grpc::auth::Access;
+        Some(match TokenStore::verify(self, name, token)?.role { Role::Admin => Access::Admin, Role::ReadWrite => Access::Write, Role::ReadOnly => Access::Read })
     }
 }
 #[derive(Clone)] pub enum AuthMode { None, Token }
@@ -0,0 +1,106 @@
+//! Backup and restore over HTTP: `/admin/backup` streams a snapshot down, `/admin/restore`
+//! takes one up, so operators need no shell access to the node.
+
+use std::io::{self, BufReader, Write};
+use std::sync::Arc;
+use axum::body::Body;
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use futures::StreamExt;
+use serde::Deserialize;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tonledb_core::system_events::{Severity, SystemEventKind, SystemEventLog};
+use tonledb_core::{Space, Storage};
+
+/// Spaces a backup covers unless asked otherwise: tables, collections and their
+/// indexes, kv with its metadata, and the graph
+pub const DEFAULT_SPACES: [&str; 6] = ["data", "catalog", "doc_index", "kv", "kv_meta", "graph"];
+const CHUNK_BYTES: usize = 64 << 10;
+
+/// `spaces` is a comma-separated list. A backup defaults to `DEFAULT_SPACES`, a
+/// restore to every space in the upload.
+#[derive(Deserialize, Default)]
+pub struct BackupParams { pub spaces:Option<String> }
+
+impl BackupParams {
+    fn spaces(&self) -> Option<Vec<Space>> {
+        self.spaces.as_deref().map(|list| list.split(',').map(str::trim).filter(|s| !s.is_empty()).map(|s| Space(s.into())).collect())
+    }
+}
+
+/// Hands what is written to the response body in chunks of `CHUNK_BYTES`
+pub(crate) struct ChunkWriter { tx: mpsc::Sender<io::Result<Vec<u8>>>, buf: Vec<u8> }
+
+impl ChunkWriter {
+    pub(crate) fn new(tx: mpsc::Sender<io::Result<Vec<u8>>>) -> Self {
+        Self { tx, buf: Vec::with_capacity(CHUNK_BYTES) }
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, b: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(b);
+        if self.buf.len() >= CHUNK_BYTES { self.flush()?; }
+        Ok(b.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() { return Ok(()); }
+        let chunk = std::mem::replace(&mut self.buf, Vec::with_capacity(CHUNK_BYTES));
+        self.tx.blocking_send(Ok(chunk)).map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client went away"))
+    }
+}
+
+/// Stream a snapshot of the chosen spaces (see `tonledb_backup::write_snapshot`) as a
+/// download. It is written from one consistent view while it is sent; if writing
+/// fails part way the body is cut off, so the client never gets a complete-looking
+/// file, and a `BackupFailed` event is recorded.
+pub fn backup(storage: Arc<dyn Storage>, events: Arc<SystemEventLog>, p: BackupParams) -> Response {
+    let spaces = p.spaces().unwrap_or_else(|| DEFAULT_SPACES.iter().map(|s| Space(s.to_string())).collect());
+    let (tx, rx) = mpsc::channel(4);
+    tokio::task::spawn_blocking(move || {
+        let mut out = ChunkWriter::new(tx.clone());
+        let res = tonledb_backup::write_snapshot(&*storage, &spaces, &mut out).and_then(|_| out.flush().map_err(|e| tonledb_core::DbError::Storage(e.to_string())));
+        if let Err(e) = res {
+            let _ = events.record(SystemEventKind::BackupFailed, Severity::Warning, format!("backup download failed: {}", e), Default::default());
+            let _ = tx.blocking_send(Err(io::Error::other(e.to_string())));
+        }
+    });
+    let chunks = futures::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|c| (c, rx)) });
+    let mut resp = Body::from_stream(chunks).into_response();
+    let name = format!("attachment; filename=\"tonledb-{}.snap\"", chrono::Utc::now().format("%Y%m%dT%H%M%SZ"));
+    resp.headers_mut().insert(header::CONTENT_TYPE, header::HeaderValue::from_static("application/octet-stream"));
+    if let Ok(v) = header::HeaderValue::from_str(&name) { resp.headers_mut().insert(header::CONTENT_DISPOSITION, v); }
+    resp
+}
+
+/// Restore the snapshot uploaded as `body`; returns how many keys were written. The
+/// upload is spooled to a temporary file and decoded once before anything is
+/// written, so a damaged or cut-off upload leaves the store untouched. Keys are
+/// written over what the store holds; other keys are left alone.
+pub async fn restore(storage: Arc<dyn Storage>, p: BackupParams, body: Body) -> Result<u64, String> {
+    let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    let path = std::env::temp_dir().join(format!("tonledb-restore-{}-{}.snap", std::process::id(), nanos));
+    let res: Result<u64, String> = async {
+        let mut file = tokio::fs::File::create(&path).await.map_err(|e| e.to_string())?;
+        let mut chunks = body.into_data_stream();
+        while let Some(chunk) = chunks.next().await {
+            file.write_all(&chunk.map_err(|e| e.to_string())?).await.map_err(|e| e.to_string())?;
+        }
+        file.flush().await.map_err(|e| e.to_string())?;
+        drop(file);
+        let spooled = path.clone();
+        tokio::task::spawn_blocking(move || {
+            let open = || std::fs::File::open(&spooled).map(BufReader::new).map_err(|e| tonledb_core::DbError::Storage(e.to_string()));
+            tonledb_backup::check_snapshot(open()?)?;
+            let spaces = p.spaces();
+            tonledb_backup::read_snapshot(&*storage, open()?, |space, _| spaces.as_ref().is_none_or(|s| s.contains(space)))
+        })
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+    }
+    .await;
+    let _ = tokio::fs::remove_file(&path).await;
+    res
+}
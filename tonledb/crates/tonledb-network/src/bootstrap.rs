@@ -0,0 +1,154 @@
+//! Declarative schema applied at startup.
+//!
+//! The `[bootstrap]` section of `tonledb.toml`, and the file passed with
+//! `--init-schema <path>` (the same layout, without the section header), list the
+//! tables, collections, indexes and users a node should have. Everything is created
+//! only if missing, so the same schema can be applied on every start and a container
+//! comes up provisioned without any DDL being run by hand.
+
+use figment::providers::{Format, Toml};
+use serde::Deserialize;
+use tonledb_core::{Column, ColumnConstraint, DataType, Db, DbError, IndexType, Result, TableSchema};
+use crate::auth;
+
+#[derive(Deserialize, Default)]
+pub struct Bootstrap {
+    #[serde(default)] pub tables: Vec<TableSpec>,
+    #[serde(default)] pub collections: Vec<CollectionSpec>,
+    #[serde(default)] pub users: Vec<UserSpec>,
+}
+
+#[derive(Deserialize)]
+pub struct TableSpec {
+    pub name: String,
+    pub columns: Vec<ColumnSpec>,
+    #[serde(default)] pub pk: Option<String>,
+    #[serde(default)] pub indexes: Vec<TableIndexSpec>,
+}
+
+#[derive(Deserialize)]
+pub struct ColumnSpec {
+    pub name: String,
+    /// integer | float | text | boolean | json | decimal
+    #[serde(rename = "type")] pub data_type: String,
+    #[serde(default)] pub not_null: bool,
+    #[serde(default)] pub unique: bool,
+}
+
+#[derive(Deserialize)]
+pub struct TableIndexSpec {
+    pub column: String,
+    /// btree (default) | hash
+    #[serde(default, rename = "type")] pub index_type: Option<String>,
+    #[serde(default)] pub unique: bool,
+}
+
+#[derive(Deserialize)]
+pub struct CollectionSpec {
+    pub name: String,
+    /// Document fields to index (dotted paths allowed)
+    #[serde(default)] pub indexes: Vec<String>,
+}
+
+/// A token user, in the same form as an entry of the token file
+#[derive(Deserialize)]
+pub struct UserSpec {
+    pub name: String,
+    pub role: String,
+    /// Argon2 hash of the user's token
+    pub hash: String,
+}
+
+/// What one `apply` created; everything else already existed
+#[derive(Debug, Default)]
+pub struct BootstrapReport {
+    pub tables: usize,
+    pub table_indexes: usize,
+    pub collections: usize,
+    pub collection_indexes: usize,
+    pub users: usize,
+}
+
+impl Bootstrap {
+    /// Read an `--init-schema` file
+    pub fn from_file(path: &str) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path).map_err(|e| anyhow::anyhow!("init schema {}: {}", path, e))?;
+        Ok(figment::Figment::from(Toml::string(&text)).extract()?)
+    }
+
+    /// Create whatever is missing. Collections live in storage, so they are left to the
+    /// primary when `read_only` (a historical replica receives them from its archive).
+    pub fn apply(&self, db: &Db, tokens: &mut auth::TokenStore, read_only: bool) -> Result<BootstrapReport> {
+        let mut report = BootstrapReport::default();
+        for t in &self.tables {
+            if !db.catalog.read().tables.contains_key(&t.name) {
+                let schema = t.schema()?;
+                db.catalog.write().tables.insert(t.name.clone(), schema);
+                report.tables += 1;
+            }
+            for i in &t.indexes {
+                if db.get_index(&t.name, &i.column)?.is_none() {
+                    db.create_index(&t.name, &i.column, index_type(i.index_type.as_deref())?, i.unique)?;
+                    report.table_indexes += 1;
+                }
+            }
+        }
+        if !read_only {
+            for c in &self.collections {
+                let catalog = tonledb_core::Space("catalog".into());
+                if db.storage.get(&catalog, format!("col/{}", c.name).as_bytes())?.is_none() {
+                    tonledb_nosql_doc::create_collection(&*db.storage, &c.name)?;
+                    report.collections += 1;
+                }
+                let existing = tonledb_nosql_doc::index::indexed_fields(&*db.storage, &c.name)?;
+                for field in c.indexes.iter().filter(|f| !existing.contains(f)) {
+                    tonledb_nosql_doc::index::create_index(&*db.storage, &c.name, field)?;
+                    report.collection_indexes += 1;
+                }
+            }
+        }
+        for u in &self.users {
+            if tokens.add_if_absent(&u.name, &u.hash, auth::Role::from_str(&u.role)) {
+                report.users += 1;
+            }
+        }
+        Ok(report)
+    }
+}
+
+impl TableSpec {
+    fn schema(&self) -> Result<TableSchema> {
+        let mut columns = Vec::with_capacity(self.columns.len());
+        for c in &self.columns {
+            let mut constraints = Vec::new();
+            if c.not_null { constraints.push(ColumnConstraint::NotNull); }
+            if c.unique { constraints.push(ColumnConstraint::Unique); }
+            if self.pk.as_ref() == Some(&c.name) { constraints.push(ColumnConstraint::PrimaryKey); }
+            columns.push(Column { name: c.name.clone(), data_type: data_type(&c.data_type)?, constraints });
+        }
+        if let Some(pk) = self.pk.as_ref().filter(|pk| !self.columns.iter().any(|c| c.name == **pk)) {
+            return Err(DbError::Invalid(format!("table {}: primary key {} is not a column", self.name, pk)));
+        }
+        Ok(TableSchema { name: self.name.clone(), columns, pk: self.pk.clone(), constraints: Vec::new() })
+    }
+}
+
+fn data_type(s: &str) -> Result<DataType> {
+    Ok(match s.to_ascii_lowercase().as_str() {
+        "integer" | "int" | "bigint" => DataType::Integer,
+        "float" | "double" | "real" => DataType::Float,
+        "text" | "string" | "varchar" => DataType::Text,
+        "boolean" | "bool" => DataType::Boolean,
+        "json" => DataType::Json,
+        "decimal" | "numeric" => DataType::Decimal,
+        other => return Err(DbError::Invalid(format!("unknown column type: {}", other))),
+    })
+}
+
+fn index_type(s: Option<&str>) -> Result<IndexType> {
+    match s.map(str::to_ascii_lowercase).as_deref() {
+        None | Some("btree") => Ok(IndexType::BTree),
+        Some("hash") => Ok(IndexType::Hash),
+        Some(other) => Err(DbError::Invalid(format!("unknown index type: {}", other))),
+    }
+}
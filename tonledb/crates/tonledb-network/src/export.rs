@@ -0,0 +1,161 @@
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use axum::body::Body;
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use flate2::{write::GzEncoder, Compression};
+use serde::Deserialize;
+use tonledb_core::{Space, Storage};
+
+/// Upper bound on export throughput (rows/s); clients may ask for less, never more.
+pub const MAX_ROWS_PER_SEC: u32 = 5_000;
+const BATCH_ROWS: usize = 256;
+
+/// `cursor` resumes after the row that carried it; `rate` is rows per second.
+#[derive(Deserialize, Default)]
+pub struct ExportParams { pub cursor:Option<String>, pub rate:Option<u32>, pub gzip:Option<bool> }
+
+type Rows = Box<dyn Iterator<Item=(Vec<u8>, Vec<u8>)> + Send>;
+
+struct ExportState { rows: Rows, prefix_len: usize, gzip: Option<GzEncoder<Vec<u8>>>, rate: u32, sent: u64, started: Instant, done: bool }
+
+/// Stream every row under `prefix` of the data space as NDJSON lines `{"cursor":..,"row":..}`.
+///
+/// Rows are read from a snapshot taken when the export starts. An interrupted
+/// download resumes by passing the last received `cursor` back; rows are throttled
+/// to `rate` (capped at `MAX_ROWS_PER_SEC`) and optionally gzip-encoded. Reading and
+/// compressing block, so they run on the blocking pool a batch at a time.
+pub async fn export(storage: Arc<dyn Storage>, prefix: String, p: ExportParams) -> Response {
+    let cursor = match p.cursor.as_deref().map(|c| URL_SAFE_NO_PAD.decode(c)).transpose() {
+        Ok(c) => c,
+        Err(_) => return axum::Json(serde_json::json!({"error":"invalid cursor"})).into_response(),
+    };
+    let scan_prefix = prefix.clone();
+    let scan = tokio::task::spawn_blocking(move || storage.snapshot().and_then(|s| s.scan_prefix(&Space("data".into()), scan_prefix.as_bytes()))).await;
+    let snapshot_rows = match scan {
+        Ok(Ok(rows)) => rows,
+        Ok(Err(e)) => return axum::Json(serde_json::json!({"error":e.to_string()})).into_response(),
+        Err(e) => return axum::Json(serde_json::json!({"error":e.to_string()})).into_response(),
+    };
+    let prefix_len = prefix.len();
+    let rows: Rows = match cursor {
+        Some(after) => Box::new(snapshot_rows.filter(move |(k, _)| k[prefix_len..] > after[..])),
+        None => snapshot_rows,
+    };
+    let gzip = p.gzip.unwrap_or(false);
+    let state = ExportState {
+        rows, prefix_len,
+        gzip: gzip.then(|| GzEncoder::new(Vec::new(), Compression::default())),
+        rate: p.rate.unwrap_or(MAX_ROWS_PER_SEC).clamp(1, MAX_ROWS_PER_SEC),
+        sent: 0, started: Instant::now(), done: false,
+    };
+    let stream = futures::stream::unfold(Some(state), |st| async move {
+        let st = st.filter(|st| !st.done)?;
+        // Hold the stream back until the rows already sent fit within the rate
+        let due = st.started + Duration::from_secs_f64(st.sent as f64 / st.rate as f64);
+        tokio::time::sleep_until(due.into()).await;
+        match tokio::task::spawn_blocking(move || st.next_chunk()).await {
+            Ok(Some((chunk, st))) => Some((chunk, Some(st))),
+            Ok(None) => None,
+            Err(e) => Some((Err(std::io::Error::other(e)), None)),
+        }
+    });
+    let mut resp = Body::from_stream(stream).into_response();
+    resp.headers_mut().insert(header::CONTENT_TYPE, header::HeaderValue::from_static("application/x-ndjson"));
+    if gzip {
+        resp.headers_mut().insert(header::CONTENT_ENCODING, header::HeaderValue::from_static("gzip"));
+    }
+    resp
+}
+
+impl ExportState {
+    /// The next batch of lines, gzip-encoded if asked; `None` once everything was sent
+    fn next_chunk(mut self) -> Option<(std::io::Result<Vec<u8>>, Self)> {
+        let mut chunk = Vec::new();
+        for (k, v) in self.rows.by_ref().take(BATCH_ROWS) {
+            let row: serde_json::Value = serde_json::from_slice(&v).unwrap_or(serde_json::Value::Null);
+            let line = serde_json::json!({"cursor": URL_SAFE_NO_PAD.encode(&k[self.prefix_len..]), "row": row});
+            chunk.extend_from_slice(line.to_string().as_bytes());
+            chunk.push(b'\n');
+            self.sent += 1;
+        }
+        self.done = chunk.is_empty();
+        let out = match self.gzip.as_mut() {
+            None => chunk,
+            Some(enc) => {
+                let res = if self.done {
+                    self.gzip.take().unwrap().finish()
+                } else {
+                    enc.write_all(&chunk).and_then(|_| enc.flush()).map(|_| std::mem::take(enc.get_mut()))
+                };
+                match res { Ok(b) => b, Err(e) => { self.done = true; return Some((Err(e), self)); } }
+            }
+        };
+        if out.is_empty() && self.done { return None; }
+        Some((Ok(out), self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use tonledb_core::{Space, Storage};
+    use tonledb_storage::InMemoryStore;
+    use super::{export, ExportParams};
+
+    fn store(rows: usize) -> Arc<InMemoryStore> {
+        let store = Arc::new(InMemoryStore::new(1000));
+        for i in 0..rows {
+            let row = serde_json::json!({"id": i});
+            store.put(&Space("data".into()), format!("tbl/t/{:03}", i).into_bytes(), row.to_string().into_bytes()).unwrap();
+        }
+        store
+    }
+
+    async fn body(store: &Arc<InMemoryStore>, p: ExportParams) -> Vec<u8> {
+        axum::body::to_bytes(export(store.clone(), "tbl/t/".into(), p).await.into_body(), usize::MAX).await.unwrap().to_vec()
+    }
+
+    fn lines(body: &[u8]) -> Vec<serde_json::Value> {
+        body.split(|b| *b == b'\n').filter(|l| !l.is_empty()).map(|l| serde_json::from_slice(l).unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_export_resumes_after_cursor() {
+        let store = store(5);
+        let all = lines(&body(&store, ExportParams::default()).await);
+        assert_eq!(all.iter().map(|l| l["row"]["id"].as_u64().unwrap()).collect::<Vec<_>>(), [0, 1, 2, 3, 4]);
+
+        let cursor = all[1]["cursor"].as_str().unwrap().to_string();
+        let rest = lines(&body(&store, ExportParams { cursor: Some(cursor), ..Default::default() }).await);
+        assert_eq!(rest, all[2..]);
+
+        let bad = lines(&body(&store, ExportParams { cursor: Some("*".into()), ..Default::default() }).await);
+        assert_eq!(bad, [serde_json::json!({"error": "invalid cursor"})]);
+    }
+
+    #[tokio::test]
+    async fn test_export_gzip_matches_plain() {
+        let store = store(300);
+        let plain = body(&store, ExportParams::default()).await;
+        let gzipped = body(&store, ExportParams { gzip: Some(true), ..Default::default() }).await;
+        let mut unzipped = Vec::new();
+        flate2::read::GzDecoder::new(&gzipped[..]).read_to_end(&mut unzipped).unwrap();
+        assert_eq!(unzipped, plain);
+        assert_eq!(lines(&plain).len(), 300);
+    }
+
+    #[tokio::test]
+    async fn test_export_is_throttled_to_rate() {
+        let store = store(10);
+        let started = Instant::now();
+        let rows = lines(&body(&store, ExportParams { rate: Some(20), ..Default::default() }).await);
+        assert_eq!(rows.len(), 10);
+        // 10 rows at 20 rows/s
+        assert!(started.elapsed() >= Duration::from_millis(450), "took {:?}", started.elapsed());
+    }
+}
@@ -1,24 +1,164 @@
 use std::{net::SocketAddr, sync::Arc};
-use axum::{routing::{get, post}, Router, extract::State, Json};
+use axum::{routing::{get, post}, Router, extract::State, http::HeaderMap, Json};
+use axum::response::{IntoResponse, Response, sse::{Event, KeepAlive, Sse}};
 use serde::Deserialize;
-use tonledb_core::Db;
+use tonledb_core::{Db, Storage as _};
+use tonledb_nosql_doc::{aggregate::Pipeline, filter::Filter};
+use tonledb_core::schema_inference;
+use tonledb_nosql_kv::graph;
+use tonledb_nosql_kv::meta::{self as kv_meta, KeyMeta};
+use tonledb_nosql_kv::scratch::{ScratchLimits, ScratchRegistry};
+use tonledb_core::system_events::{AlertRule, EventQuery, Severity, SystemEventKind, SystemEventLog};
+use tonledb_core::statement_history::{HistoryQuery, HistoryRetention, StatementHistory, StatementStatus};
 use base64::{Engine as _, engine::general_purpose};
 use figment::providers::Format;
 
+mod alerts;
 mod auth;
 mod audit;
+mod backup;
+mod bootstrap;
+mod export;
+mod tls;
 
 #[derive(Clone)]
-struct AppState { db: Arc<Db>, auth: auth::AppAuth }
+struct AppState { db: Arc<Db>, auth: auth::AppAuth, events: Arc<SystemEventLog>, history: Arc<StatementHistory>, scratch: Arc<ScratchRegistry>, replica: Option<Arc<tonledb_storage::DelayedReplica>> }
 
 #[derive(Deserialize)]
 struct ConfServer { bind:String }
 #[derive(Deserialize)]
 struct ConfAuth { mode:String, token_file:String }
+/// Postgres wire protocol port; clients log in as token users when `[auth] mode = "token"`
 #[derive(Deserialize)]
-struct ConfStorage { wal_path:String }
+struct ConfPg {
+    bind:String,
+    /// Refuse pg clients that don't negotiate TLS (needs `[tls] enabled`)
+    #[serde(default)] require_tls:bool,
+    /// Most pg clients connected at once; unlimited when unset
+    #[serde(default)] max_connections:Option<usize>,
+    /// Disconnect pg clients that haven't logged in this long after connecting; 60 seconds when unset
+    #[serde(default)] authentication_timeout_ms:Option<u64>,
+    /// Close pg connections idle this long
+    #[serde(default)] idle_timeout_ms:Option<u64>,
+    /// Settings every pg session starts with, e.g. `search_path = "app, public"`
+    #[serde(default)] settings:std::collections::BTreeMap<String,String>,
+    /// Parsed query strings the pg connections share; 0 turns the cache off
+    #[serde(default)] statement_cache:Option<usize>,
+}
+/// MongoDB wire protocol port for the document collections; clients log in as token users (SASL PLAIN) when `[auth] mode = "token"`,
+/// which needs `[tls] enabled` since PLAIN is only taken over TLS
+#[derive(Deserialize)]
+struct ConfMongo { bind:String }
+/// MySQL wire protocol port; clients log in as token users when `[auth] mode = "token"`, over TLS unless they have a `mysql_password`
+#[derive(Deserialize)]
+struct ConfMysql { bind:String }
+/// gRPC port for the KV, document and SQL services; calls carry token users' credentials when `[auth] mode = "token"`,
+/// run within `[limits] query_timeout_ms`, and their SQL goes to the statement history and audit trail like `/sql`'s
+#[derive(Deserialize)]
+struct ConfGrpc { bind:String }
+/// Server certificate; the pg and mysql ports accept TLS with it when `enabled`
+#[derive(Deserialize)]
+struct ConfTls { #[serde(default)] enabled:bool, cert_path:String, key_path:String, #[serde(default)] require_client_auth:bool, #[serde(default)] ca_path:Option<String> }
+#[derive(Deserialize)]
+struct ConfStorage {
+    wal_path:String,
+    #[serde(default)] ttl_sweep_ms:Option<u64>,
+    #[serde(default)] number_mode:tonledb_core::numbers::NumberMode,
+    #[serde(default)] encrypt_at_rest:bool,
+    /// Environment variable holding the base64 key encryption key
+    #[serde(default = "default_kek_env")] kek_env:String,
+    /// Spaces sealed when `encrypt_at_rest` is on; empty encrypts every space
+    #[serde(default)] encrypted_spaces:Vec<String>,
+    /// Values larger than this many bytes are split into chunks; unset disables chunking
+    #[serde(default)] chunk_threshold_bytes:Option<usize>,
+    /// Copy every WAL record here, time-stamped, for historical replicas to follow
+    #[serde(default)] wal_archive_dir:Option<String>,
+    /// Split the WAL into segments of this many bytes; unset keeps one file
+    #[serde(default)] wal_segment_bytes:Option<u64>,
+    /// Copy every sealed WAL segment here, e.g. a volume shipped off-node
+    #[serde(default)] wal_segment_archive_dir:Option<String>,
+    /// Upload every sealed WAL segment to an S3 bucket instead
+    #[serde(default)] wal_segment_archive_s3:Option<ConfS3>,
+    /// fsync the WAL before acknowledging a write; concurrent writes share one fsync
+    #[serde(default)] wal_sync_commits:bool,
+    /// Checkpoint the store this often so restarts replay only the WAL since; unset disables
+    #[serde(default)] checkpoint_interval_ms:Option<u64>,
+    /// Version and time-stamp KV keys, for ETags and conditional writes on /kv/:key
+    #[serde(default)] kv_metadata:bool,
+    /// Refresh the kv size gauges on /metrics this often (a walk of every key); unset disables
+    #[serde(default)] kv_stats_interval_ms:Option<u64>,
+    /// Reject documents whose encoded JSON is larger than this many bytes; unset allows any size
+    #[serde(default)] doc_max_bytes:Option<usize>,
+    /// Tables also kept column by column in the `olap` space, for analytic scans that read few columns
+    #[serde(default)] olap_tables:Vec<String>,
+}
+fn default_kek_env()->String{ "TLDB_KEK".into() }
+/// S3 or S3-compatible bucket; credentials come from `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY`
+#[derive(Deserialize)]
+struct ConfS3 { endpoint:String, region:String, bucket:String, #[serde(default)] prefix:String }
+/// Run as a read-only replica replaying `archive_dir` `delay_ms` behind the primary
+#[derive(Deserialize)]
+struct ConfHistorical {
+    archive_dir:String,
+    #[serde(default = "default_replica_delay_ms")] delay_ms:u64,
+    #[serde(default = "default_replica_poll_ms")] poll_ms:u64,
+}
+fn default_replica_delay_ms()->u64{ 3_600_000 }
+fn default_replica_poll_ms()->u64{ 1_000 }
+const WAL_ARCHIVE_SEGMENT_BYTES: u64 = 64 << 20;
+#[derive(Deserialize, Default)]
+struct ConfGc {
+    /// How long WAL segments retired by compaction are kept (`1h`, `3d`, `2w`)
+    #[serde(default)] wal_retention:Option<String>,
+    /// How long scheduled backups are kept (see `[backup]`)
+    #[serde(default)] snapshot_retention:Option<String>,
+}
+/// Snapshots taken on a schedule into a directory or bucket, rotated by count and age
 #[derive(Deserialize)]
-struct Conf { server:ConfServer, auth:ConfAuth, storage:ConfStorage }
+struct ConfBackup {
+    /// `6h`-style interval or `daily 02:30` (UTC)
+    schedule:String,
+    #[serde(default)] dir:Option<String>,
+    #[serde(default)] s3:Option<ConfS3>,
+    /// Spaces covered; empty covers the same ones as `/admin/backup`
+    #[serde(default)] spaces:Vec<String>,
+    /// Scheduled backups kept, newest first; unset keeps any number
+    #[serde(default)] keep:Option<usize>,
+}
+/// `30s`, `15m`, `12h`, `3d`, `2w` in milliseconds
+fn parse_duration_ms(s: &str) -> anyhow::Result<u64> {
+    let (n, unit) = s.split_at(s.len() - s.trim_start_matches(|c: char| c.is_ascii_digit()).len());
+    let n: u64 = n.parse().map_err(|_| anyhow::anyhow!("invalid duration: {}", s))?;
+    let unit_ms = match unit { "s" => 1_000, "m" => 60_000, "h" => 3_600_000, "d" => 86_400_000, "w" => 604_800_000, _ => anyhow::bail!("invalid duration unit in {} (use s, m, h, d or w)", s) };
+    Ok(n * unit_ms)
+}
+fn spawn_scheduled_backups(b: &ConfBackup, gc: &ConfGc, storage: Arc<dyn tonledb_core::Storage>, events: Arc<SystemEventLog>) -> anyhow::Result<tonledb_backup::schedule::ScheduledBackups> {
+    use tonledb_backup::schedule::{BackupJob, Retention, Schedule, ScheduledBackups};
+    use tonledb_backup::target::{BackupTarget, LocalTarget, ObjectStoreTarget};
+    let target: Arc<dyn BackupTarget> = match (&b.dir, &b.s3) {
+        (Some(dir), None) => Arc::new(LocalTarget::new(dir)?),
+        (None, Some(s3)) => {
+            let objects = tonledb_core::object_store::S3ObjectStore::new(tonledb_core::object_store::S3Config::from_env(&s3.endpoint, &s3.region, &s3.bucket, &s3.prefix)?)?;
+            Arc::new(ObjectStoreTarget::new(Arc::new(objects)))
+        }
+        _ => anyhow::bail!("[backup] needs exactly one of dir and s3"),
+    };
+    let spaces = if b.spaces.is_empty() { backup::DEFAULT_SPACES.iter().map(|s| s.to_string()).collect() } else { b.spaces.clone() };
+    let max_age = gc.snapshot_retention.as_deref().map(parse_duration_ms).transpose()?.map(std::time::Duration::from_millis);
+    let job = BackupJob { spaces: spaces.into_iter().map(tonledb_core::Space).collect(), prefix: "scheduled-".into(), retention: Retention { keep: b.keep, max_age } };
+    Ok(ScheduledBackups::spawn(storage, target, Schedule::parse(&b.schedule)?, job, move |e| {
+        let _ = events.record(SystemEventKind::BackupFailed, Severity::Warning, format!("scheduled backup failed: {}", e), Default::default());
+    }))
+}
+#[derive(Deserialize, Default)]
+struct ConfLimits {
+    /// Longest a request may run; `x-tonledb-timeout-ms` can only shorten it
+    #[serde(default)] query_timeout_ms:Option<u64>,
+}
+#[derive(Deserialize, Default)]
+struct ConfAlerts { #[serde(default)] rules:Vec<AlertRule> }
+#[derive(Deserialize)]
+struct Conf { server:ConfServer, auth:ConfAuth, storage:ConfStorage, #[serde(default)] alerts:ConfAlerts, #[serde(default)] history:HistoryRetention, #[serde(default)] historical:Option<ConfHistorical>, #[serde(default)] bootstrap:bootstrap::Bootstrap, #[serde(default)] limits:ConfLimits, #[serde(default)] gc:ConfGc, #[serde(default)] backup:Option<ConfBackup>, #[serde(default)] pg:Option<ConfPg>, #[serde(default)] mongo:Option<ConfMongo>, #[serde(default)] mysql:Option<ConfMysql>, #[serde(default)] grpc:Option<ConfGrpc>, #[serde(default)] tls:Option<ConfTls> }
 
 #[derive(Deserialize)]
 struct SqlBody { sql: String }
@@ -31,24 +171,194 @@ async fn main()->anyhow::Result<()>{
         .merge(figment::providers::Env::prefixed("TLDB_"))
         .extract()?;
 
-    // Storage base (existing in-mem+WAL)
-    let base = tonledb_storage::arc_inmem_with_wal(Some(&cfg.storage.wal_path), 100_000);
-    let storage: Arc<dyn tonledb_core::Storage> = base;
+    tonledb_core::numbers::set_number_mode(cfg.storage.number_mode);
+    tonledb_nosql_kv::meta::set_enabled(cfg.storage.kv_metadata);
+    tonledb_nosql_doc::set_max_doc_bytes(cfg.storage.doc_max_bytes);
+    // Storage base: in-mem+WAL, or a read-only replica of a primary's WAL archive
+    let replica = cfg.historical.as_ref().map(|h| Arc::new(tonledb_storage::DelayedReplica::new(&h.archive_dir, h.delay_ms, 100_000)));
+    let mut _checkpointer = None;
+    let mut base: Arc<dyn tonledb_core::Storage> = match &replica {
+        Some(r) => { r.catch_up()?; r.clone() }
+        None => {
+            let retention_ms = cfg.gc.wal_retention.as_deref().map(parse_duration_ms).transpose()?.unwrap_or(0);
+            let wal_opts = tonledb_wal::WalOptions { segment_bytes: cfg.storage.wal_segment_bytes.unwrap_or(0), retention: std::time::Duration::from_millis(retention_ms), sync_commits: cfg.storage.wal_sync_commits, metrics: true };
+            let store = tonledb_storage::InMemoryStore::with_wal_options(&cfg.storage.wal_path, 100_000, wal_opts)?;
+            if let Some(dir) = &cfg.storage.wal_archive_dir { store.set_wal_archive(dir, WAL_ARCHIVE_SEGMENT_BYTES)?; }
+            match (&cfg.storage.wal_segment_archive_dir, &cfg.storage.wal_segment_archive_s3) {
+                (Some(_), Some(_)) => anyhow::bail!("set only one of wal_segment_archive_dir and wal_segment_archive_s3"),
+                (Some(dir), None) => store.set_wal_segment_archiver(Box::new(tonledb_wal::CopyArchiver::new(dir)?))?,
+                (None, Some(s3)) => {
+                    let objects = tonledb_core::object_store::S3ObjectStore::new(tonledb_core::object_store::S3Config::from_env(&s3.endpoint, &s3.region, &s3.bucket, &s3.prefix)?)?;
+                    store.set_wal_segment_archiver(Box::new(tonledb_storage::ObjectStoreArchiver::new(Arc::new(objects), "")))?;
+                }
+                (None, None) => {}
+            }
+            let store = Arc::new(store);
+            // Stops when dropped at the end of main
+            _checkpointer = cfg.storage.checkpoint_interval_ms.map(|ms| tonledb_storage::Checkpointer::spawn(store.clone(), std::time::Duration::from_millis(ms)));
+            store
+        }
+    };
+    let _follower = replica.clone().zip(cfg.historical.as_ref()).map(|(r, h)| tonledb_storage::ReplicaFollower::spawn(r, std::time::Duration::from_millis(h.poll_ms)));
+    // Below encryption, so chunks hold ciphertext of encrypted spaces
+    if let Some(threshold) = cfg.storage.chunk_threshold_bytes {
+        let opts = tonledb_storage::ChunkOptions { threshold, ..Default::default() };
+        base = Arc::new(tonledb_storage::ChunkedStorage::new(base, opts));
+    }
+    if cfg.storage.encrypt_at_rest {
+        let kek = std::env::var(&cfg.storage.kek_env).map_err(|_| anyhow::anyhow!("encrypt_at_rest is on but {} is not set", cfg.storage.kek_env))?;
+        let mut enc = tonledb_storage::EncryptedStorage::new(base, &kek)?;
+        enc = if cfg.storage.encrypted_spaces.is_empty() { enc.with_all_spaces() } else {
+            cfg.storage.encrypted_spaces.iter().fold(enc, |e, sp| e.with_space(tonledb_core::Space(sp.clone())))
+        };
+        base = Arc::new(enc);
+    }
+    // Above encryption, so `olap` can be sealed like any other space; a replica only follows.
+    // Wrapped even with no tables, so the segments of tables taken off the list are dropped
+    if replica.is_none() {
+        base = Arc::new(tonledb_storage::ColumnarStorage::new(base, cfg.storage.olap_tables.iter().cloned())?);
+    }
+    // Traced wrapper: records storage calls only for requests sent with ?trace=true
+    let traced: Arc<dyn tonledb_core::Storage> = Arc::new(tonledb_core::op_trace::TracedStorage::new(base));
+    // Refuses storage calls once the request being served is past its deadline
+    let storage: Arc<dyn tonledb_core::Storage> = Arc::new(tonledb_core::deadline::DeadlineStorage::new(traced));
 
     let db = Arc::new(tonledb_core::Db::new(storage));
-    let tokens = auth::TokenStore::from_file(&cfg.auth.token_file).unwrap_or_else(|_| auth::TokenStore::default());
+    // Reclaims keys written with a TTL; stops when dropped at the end of main
+    let _ttl_sweeper = tonledb_storage::ttl::TtlSweeper::spawn(db.storage.clone(), std::time::Duration::from_millis(cfg.storage.ttl_sweep_ms.unwrap_or(1000)));
+    if let Some(ms) = cfg.storage.kv_stats_interval_ms {
+        let storage = db.storage.clone();
+        tokio::spawn(async move {
+            let mut tick = tokio::time::interval(std::time::Duration::from_millis(ms));
+            loop {
+                tick.tick().await;
+                let s = storage.clone();
+                if let Ok(Ok(stats)) = tokio::task::spawn_blocking(move || tonledb_nosql_kv::stats(&*s)).await { observe_kv_stats(&stats); }
+            }
+        });
+    }
+    // A historical replica can't be written, so node-local logs stay in memory there
+    let local: Arc<dyn tonledb_core::Storage> = if replica.is_some() { Arc::new(tonledb_storage::InMemoryStore::new(10_000)) } else { db.storage.clone() };
+    let events = Arc::new(SystemEventLog::new(local.clone())?);
+    events.set_rules(cfg.alerts.rules);
+    events.set_sink(Arc::new(alerts::WebhookSink::new()));
+    let history = Arc::new(StatementHistory::new(local, cfg.history)?);
+    // Stops when dropped at the end of main; a replica has nothing of its own to back up
+    let _backups = match cfg.backup.as_ref().filter(|_| replica.is_none()) {
+        Some(b) => Some(spawn_scheduled_backups(b, &cfg.gc, db.storage.clone(), events.clone())?),
+        None => None,
+    };
+    let mut tokens = auth::TokenStore::from_file(&cfg.auth.token_file).unwrap_or_else(|_| auth::TokenStore::default());
+    // Declared schema: the [bootstrap] section, then `--init-schema <file>`
+    let init_schema = std::env::args().skip_while(|a| a != "--init-schema").nth(1);
+    let schemas = std::iter::once(Ok(cfg.bootstrap)).chain(init_schema.as_deref().map(bootstrap::Bootstrap::from_file));
+    for schema in schemas {
+        let report = schema?.apply(&db, &mut tokens, replica.is_some())?;
+        tracing::info!(?report, "schema bootstrap applied");
+    }
     let mode = match cfg.auth.mode.as_str(){ "token"=>auth::AuthMode::Token, _=>auth::AuthMode::None };
     let app_auth = auth::AppAuth{ tokens, mode };
+    let tls = match cfg.tls.as_ref().filter(|t| t.enabled && (cfg.pg.is_some() || cfg.mysql.is_some() || cfg.mongo.is_some())) {
+        Some(t) => Some(tls::tls_config(&t.cert_path, &t.key_path, t.ca_path.as_deref(), t.require_client_auth)?),
+        None => None,
+    };
+    if let Some(pg) = &cfg.pg {
+        let passwords = matches!(app_auth.mode, auth::AuthMode::Token).then(|| Arc::new(app_auth.tokens.clone()) as Arc<dyn tonledb_wire_pg::auth::PasswordStore>);
+        anyhow::ensure!(tls.is_some() || !pg.require_tls, "[pg] require_tls needs [tls] enabled");
+        let settings: Vec<(String, String)> = pg.settings.clone().into_iter().collect();
+        tonledb_wire_pg::session::check_settings(&settings).map_err(|e| anyhow::anyhow!("[pg.settings]: {}", e.message))?;
+        let options = tonledb_wire_pg::PgOptions {
+            passwords,
+            tls: tls.clone(),
+            require_tls: pg.require_tls,
+            connections: Arc::new(tonledb_wire_pg::connections::ConnectionRegistry::new(pg.max_connections)),
+            authentication_timeout: Some(std::time::Duration::from_millis(pg.authentication_timeout_ms.unwrap_or(60_000))),
+            idle_timeout: pg.idle_timeout_ms.map(std::time::Duration::from_millis),
+            settings,
+            statement_cache: Arc::new(tonledb_wire_pg::cache::StatementCache::new(pg.statement_cache.unwrap_or(tonledb_wire_pg::cache::DEFAULT_CAPACITY))),
+            audit: Some(Arc::new(audit::PgAudit)),
+        };
+        let (db, bind) = (db.clone(), pg.bind.clone());
+        tokio::spawn(async move {
+            if let Err(e) = tonledb_wire_pg::start_pg_server(db, &bind, options).await { tracing::error!(error = %e, "pg server stopped"); }
+        });
+    }
+    if let Some(mongo) = &cfg.mongo {
+        let passwords = matches!(app_auth.mode, auth::AuthMode::Token).then(|| Arc::new(app_auth.tokens.clone()) as Arc<dyn tonledb_wire_mongo::auth::PasswordStore>);
+        anyhow::ensure!(tls.is_some() || passwords.is_none(), "[mongo] with [auth] mode = \"token\" needs [tls] enabled");
+        let options = tonledb_wire_mongo::MongoOptions { passwords, tls: tls.clone() };
+        let (db, bind) = (db.clone(), mongo.bind.clone());
+        tokio::spawn(async move {
+            if let Err(e) = tonledb_wire_mongo::start_mongo_server(db, &bind, options).await { tracing::error!(error = %e, "mongo server stopped"); }
+        });
+    }
+    if let Some(mysql) = &cfg.mysql {
+        let passwords = matches!(app_auth.mode, auth::AuthMode::Token).then(|| Arc::new(app_auth.tokens.clone()) as Arc<dyn tonledb_wire_mysql::auth::PasswordStore>);
+        let options = tonledb_wire_mysql::MysqlOptions { passwords, tls: tls.clone() };
+        let (db, bind) = (db.clone(), mysql.bind.clone());
+        tokio::spawn(async move {
+            if let Err(e) = tonledb_wire_mysql::start_mysql_server(db, &bind, options).await { tracing::error!(error = %e, "mysql server stopped"); }
+        });
+    }
+    if let Some(grpc) = &cfg.grpc {
+        let tokens = matches!(app_auth.mode, auth::AuthMode::Token).then(|| Arc::new(app_auth.tokens.clone()) as Arc<dyn tonledb_grpc::auth::TokenVerifier>);
+        let options = tonledb_grpc::GrpcOptions {
+            tokens,
+            query_timeout: cfg.limits.query_timeout_ms.map(std::time::Duration::from_millis),
+            statements: Some(Arc::new(audit::GrpcStatements(history.clone()))),
+        };
+        let (db, bind) = (db.clone(), grpc.bind.clone());
+        tokio::spawn(async move {
+            if let Err(e) = tonledb_grpc::start_grpc_server(db, &bind, options).await { tracing::error!(error = %e, "grpc server stopped"); }
+        });
+    }
 
     let app = Router::new()
         .route("/health", get(|| async {"ok"}))
         .route("/metrics", get(tonledb_metrics::axum_handler::metrics))
         .route("/sql", post(sql_handler))
+        .route("/kv", get(kv_scan))
+        .route("/kv/watch", get(kv_watch))
+        .route("/kv/export", get(kv_export))
         .route("/kv/:key", get(kv_get).post(kv_put))
-        .route("/doc/:col", post(doc_insert))
-        .with_state(AppState{ db, auth: app_auth.clone() });
+        .route("/kv/scratch", post(scratch_begin))
+        .route("/kv/scratch/:session", axum::routing::delete(scratch_end))
+        .route("/kv/scratch/:session/:key", get(scratch_get).post(scratch_put).delete(scratch_del))
+        .route("/doc/:col", post(doc_insert).delete(doc_drop))
+        .route("/doc/:col/rename/:to", post(doc_rename))
+        .route("/doc/:col/capped", post(doc_capped))
+        .route("/doc/:col/timeseries", post(doc_timeseries))
+        .route("/doc/:col/timestamps", post(doc_timestamps))
+        .route("/doc/:col/range", get(doc_range))
+        .route("/doc/:col/view", get(doc_view).post(doc_create_view).delete(doc_drop_view))
+        .route("/doc/:col/query", post(doc_query))
+        .route("/doc/:col/count", post(doc_count))
+        .route("/doc/:col/distinct/:field", post(doc_distinct))
+        .route("/doc/:col/index/:field", post(doc_create_index).delete(doc_drop_index))
+        .route("/doc/:col/aggregate", post(doc_aggregate))
+        .route("/doc/:col/watch", get(doc_watch))
+        .route("/doc/:col/schema", get(doc_schema))
+        .route("/doc/:col/export", get(doc_export))
+        .route("/table/:name/export", get(table_export))
+        .route("/graph/node", post(graph_put_node))
+        .route("/graph/node/:id", get(graph_get_node).delete(graph_delete_node))
+        .route("/graph/node/:id/neighbors", get(graph_neighbors))
+        .route("/graph/edge", post(graph_put_edge))
+        .route("/graph/bfs", get(graph_bfs))
+        .route("/graph/path", get(graph_path))
+        .route("/admin/events", get(admin_events))
+        .route("/admin/history", get(admin_history))
+        .route("/admin/compact/:space", post(admin_compact))
+        .route("/admin/replica", get(admin_replica))
+        .route("/admin/kv/stats", get(admin_kv_stats))
+        .route("/admin/doc/:col/stats", get(admin_doc_stats))
+        .route("/admin/backup", get(admin_backup))
+        .route("/admin/restore", post(admin_restore))
+        .layer(axum::middleware::from_fn_with_state(cfg.limits.query_timeout_ms, deadline_layer))
+        .with_state(AppState{ db, auth: app_auth.clone(), events: events.clone(), history, scratch: Arc::new(ScratchRegistry::new(ScratchLimits::default())), replica });
 
     let addr: SocketAddr = cfg.server.bind.parse()?;
+    events.record(SystemEventKind::NodeStarted, Severity::Info, format!("node listening on {}", addr), Default::default())?;
     tracing::warn!("TLS disabled (dev only).");
     let listener = tokio::net::TcpListener::bind(addr).await?;
     tracing::info!(%addr, "TonleDB listening (HTTP)");
@@ -56,28 +366,770 @@ async fn main()->anyhow::Result<()>{
     Ok(())
 }
 
-async fn sql_handler(State(app):State<AppState>, user:auth::User, Json(p):Json<SqlBody>)->Json<serde_json::Value>{
-    if !auth::require(auth::Role::ReadWrite, &user.0.role) { return Json(serde_json::json!({"error":"forbidden"})); }
+const TIMEOUT_HEADER: &str = "x-tonledb-timeout-ms";
+
+/// Give the request a deadline: `x-tonledb-timeout-ms` from now, capped by `[limits]
+/// query_timeout_ms`. It is in force while the handler runs (see `tonledb_core::deadline`),
+/// and a handler still running when it passes is dropped with a 504.
+async fn deadline_layer(State(limit):State<Option<u64>>, req:axum::extract::Request, next:axum::middleware::Next)->Response{
+    let asked = match req.headers().get(TIMEOUT_HEADER).map(|v| v.to_str().ok().and_then(|s| s.parse::<u64>().ok())) {
+        None => None,
+        Some(Some(ms)) => Some(ms),
+        Some(None) => return (axum::http::StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": format!("invalid {} header", TIMEOUT_HEADER)}))).into_response(),
+    };
+    let Some(ms) = asked.into_iter().chain(limit).min() else { return next.run(req).await };
+    let deadline = std::time::Instant::now() + std::time::Duration::from_millis(ms);
+    let mut fut = Box::pin(next.run(req));
+    // Installed around every poll, since handlers do their work synchronously inside them
+    let run = std::future::poll_fn(move |cx| tonledb_core::deadline::within(Some(deadline), || std::future::Future::poll(fut.as_mut(), cx)));
+    match tokio::time::timeout_at(tokio::time::Instant::from_std(deadline), run).await {
+        Ok(resp) => resp,
+        Err(_) => (axum::http::StatusCode::GATEWAY_TIMEOUT, Json(serde_json::json!({"error": "deadline exceeded"}))).into_response(),
+    }
+}
+
+#[derive(Deserialize, Default)]
+struct TraceParams { trace:Option<bool> }
+
+/// Debug tracing is requested with `?trace=true` or an `x-tonledb-trace: true` header.
+fn trace_requested(q:&TraceParams, headers:&HeaderMap)->bool{
+    q.trace.unwrap_or(false) || headers.get("x-tonledb-trace").and_then(|v| v.to_str().ok()).is_some_and(|v| v=="true" || v=="1")
+}
+
+/// Run `f`, and when tracing wrap its result as `{"result":..,"trace":{..}}`.
+fn with_trace(trace:bool, f:impl FnOnce()->serde_json::Value)->serde_json::Value{
+    if !trace { return f(); }
+    let start = std::time::Instant::now();
+    let (res, ops) = tonledb_core::op_trace::collect(f);
+    serde_json::json!({ "result": res, "trace": { "total_micros": start.elapsed().as_micros() as u64, "ops": ops } })
+}
+
+/// With `Accept: application/vnd.apache.arrow.stream` the rows come back as an Arrow
+/// IPC stream instead of JSON; errors are still JSON.
+async fn sql_handler(State(app):State<AppState>, user:auth::User, headers:HeaderMap, Query(tq):Query<TraceParams>, Json(p):Json<SqlBody>)->Response{
+    if !auth::require(auth::Role::ReadWrite, &user.0.role) { return Json(serde_json::json!({"error":"forbidden"})).into_response(); }
+    let arrow = header_str(&headers, axum::http::header::ACCEPT).is_some_and(|a| a.split(',').any(|t| t.trim().starts_with(tonledb_arrow::ipc::ARROW_STREAM_MEDIA_TYPE)));
+    let traced = trace_requested(&tq, &headers);
     let t = tonledb_metrics::QueryTimer::start("sql");
-    let res = tonledb_sql::execute_sql(&app.db, &p.sql).map_err(|e| serde_json::json!({"error":e.to_string()})).unwrap_or_else(|e|e);
+    let started_ms = chrono::Utc::now().timestamp_millis() as u64;
+    let started = std::time::Instant::now();
+    let mut outcome = Ok(());
+    let res = with_trace(traced, || tonledb_sql::execute_sql(&app.db, &p.sql).map_err(|e| { outcome = Err(e.to_string()); serde_json::json!({"error":e.to_string()}) }).unwrap_or_else(|e|e));
     t.stop();
+    if let Err(e) = app.history.record(&user.0.name, &p.sql, started_ms, started.elapsed().as_millis() as u64, outcome) {
+        tracing::warn!(error=%e, "failed to record statement history");
+    }
     audit::log(&audit::AuditEvent{ ts: &chrono::Utc::now().to_rfc3339(), who: &user.0.name, action:"SQL", resource:"/sql", result:"ok" });
-    Json(res)
+    if !arrow || res.get("error").is_some() { return Json(res).into_response(); }
+    // Columns come from the catalog; a traced answer is one object, streamed as one
+    // row with inferred columns
+    let columns = match traced { true => Ok(None), false => tonledb_sql::parse_statement(&p.sql).and_then(|stmt| tonledb_sql::result_columns(&app.db, &stmt)) };
+    let columns = match columns { Ok(c) => c, Err(e) => return Json(serde_json::json!({"error":e.to_string()})).into_response() };
+    let rows = match res { serde_json::Value::Array(rows) => rows, other => vec![other] };
+    arrow_stream(columns, rows)
 }
 
-use axum::extract::Path;
-async fn kv_get(State(app):State<AppState>, user:auth::User, Path(key):Path<String>)->Json<serde_json::Value>{
+/// `rows` as an Arrow IPC stream body, encoded a record batch at a time on a blocking
+/// thread while it is sent; if encoding fails part way the body is cut off.
+fn arrow_stream(columns:Option<Vec<tonledb_core::Column>>, rows:Vec<serde_json::Value>)->Response{
+    let (tx, rx) = tokio::sync::mpsc::channel(4);
+    tokio::task::spawn_blocking(move || {
+        let mut out = backup::ChunkWriter::new(tx.clone());
+        let res = tonledb_arrow::ipc::write_ipc_stream(columns.as_deref(), rows, &mut out)
+            .and_then(|_| std::io::Write::flush(&mut out).map_err(|e| tonledb_core::DbError::Storage(e.to_string())));
+        if let Err(e) = res { let _ = tx.blocking_send(Err(std::io::Error::other(e.to_string()))); }
+    });
+    let chunks = futures::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|c| (c, rx)) });
+    ([(axum::http::header::CONTENT_TYPE, tonledb_arrow::ipc::ARROW_STREAM_MEDIA_TYPE)], axum::body::Body::from_stream(chunks)).into_response()
+}
+
+/// Per-request replica consistency from the `x-tonledb-consistency` header (one|quorum|all).
+fn consistency_of(headers:&HeaderMap)->Result<tonledb_core::Consistency, Json<serde_json::Value>>{
+    match headers.get("x-tonledb-consistency").and_then(|v| v.to_str().ok()) {
+        None => Ok(tonledb_core::Consistency::default()),
+        Some(s) => tonledb_core::Consistency::parse(s).ok_or_else(|| Json(serde_json::json!({"error": format!("invalid consistency level: {}", s)}))),
+    }
+}
+
+use axum::extract::{Path, Query};
+/// ETag of a key version: created_at and version, so a recreated key never repeats one
+fn kv_etag(m:&KeyMeta)->String{ format!("\"{}.{}\"", m.created_at, m.version) }
+fn parse_kv_etag(tag:&str)->Option<KeyMeta>{
+    let (created_at, version) = tag.trim().strip_prefix('"')?.strip_suffix('"')?.split_once('.')?;
+    Some(KeyMeta{ version: version.parse().ok()?, created_at: created_at.parse().ok()?, updated_at: 0 })
+}
+fn header_str(headers:&HeaderMap, name:axum::http::header::HeaderName)->Option<&str>{ headers.get(name).and_then(|v| v.to_str().ok()) }
+
+/// With key metadata on, answers carry an `ETag` and honour `If-None-Match`.
+async fn kv_get(State(app):State<AppState>, user:auth::User, headers:HeaderMap, Query(tq):Query<TraceParams>, Path(key):Path<String>)->Response{
+    if !auth::require(auth::Role::ReadOnly, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})).into_response(); }
+    let consistency = match consistency_of(&headers) { Ok(c)=>c, Err(e)=>return e.into_response() };
+    // Read before the value: a write in between leaves a stale tag, which only fails conditions
+    let etag = match kv_meta::enabled().then(|| kv_meta::get_meta(&*app.db.storage, key.as_bytes())).transpose() {
+        Ok(m) => m.flatten().map(|m| kv_etag(&m)),
+        Err(e) => return Json(serde_json::json!({"error":e.to_string()})).into_response(),
+    };
+    if let Some(tag) = &etag {
+        if header_str(&headers, axum::http::header::IF_NONE_MATCH).is_some_and(|h| h.split(',').any(|t| t.trim() == tag)) {
+            return (axum::http::StatusCode::NOT_MODIFIED, [(axum::http::header::ETAG, tag.clone())]).into_response();
+        }
+    }
+    let body = Json(with_trace(trace_requested(&tq, &headers), || {
+        match tonledb_nosql_kv::get_with(&*app.db.storage, key.as_bytes(), consistency) {
+            Ok(Some(b)) => serde_json::json!({"value": general_purpose::STANDARD.encode(b)}),
+            Ok(None) => serde_json::json!({"value":null}),
+            Err(e) => serde_json::json!({"error":e.to_string()}),
+        }
+    }));
+    match etag { Some(tag) => ([(axum::http::header::ETAG, tag)], body).into_response(), None => body.into_response() }
+}
+/// `ttl_ms` makes the key expire; such writes use the default replica consistency.
+#[derive(Deserialize)]
+struct KvPutParams { ttl_ms:Option<u64> }
+
+/// `If-Match: <etag>`, `If-Match: *` (the key exists) or `If-None-Match: *` make the
+/// write conditional (key metadata must be on); a failed condition answers 412.
+async fn kv_put(State(app):State<AppState>, user:auth::User, headers:HeaderMap, Path(key):Path<String>, Query(p):Query<KvPutParams>, body:String)->Response{
+    if !auth::require(auth::Role::ReadWrite, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})).into_response(); }
+    let if_match = header_str(&headers, axum::http::header::IF_MATCH);
+    let create_only = header_str(&headers, axum::http::header::IF_NONE_MATCH).is_some_and(|h| h.trim() == "*");
+    if if_match.is_some() || create_only {
+        if p.ttl_ms.is_some() { return Json(serde_json::json!({"error":"conditional writes can't set a TTL"})).into_response(); }
+        let res = match if_match.map(str::trim) {
+            Some("*") => kv_meta::put_if_exists(&*app.db.storage, key.into_bytes(), body.into_bytes()),
+            Some(tag) => match parse_kv_etag(tag) {
+                Some(m) => kv_meta::put_if(&*app.db.storage, key.into_bytes(), body.into_bytes(), Some(&m)),
+                None => return (axum::http::StatusCode::PRECONDITION_FAILED, Json(serde_json::json!({"error":"unrecognised If-Match tag"}))).into_response(),
+            },
+            None => kv_meta::put_if(&*app.db.storage, key.into_bytes(), body.into_bytes(), None),
+        };
+        return match res {
+            Ok(m) => ([(axum::http::header::ETAG, kv_etag(&m))], Json(serde_json::json!({"ok":true}))).into_response(),
+            Err(tonledb_core::DbError::Conflict(e)) => (axum::http::StatusCode::PRECONDITION_FAILED, Json(serde_json::json!({"error":e}))).into_response(),
+            Err(e) => Json(serde_json::json!({"error":e.to_string()})).into_response(),
+        };
+    }
+    let consistency = match consistency_of(&headers) { Ok(c)=>c, Err(e)=>return e.into_response() };
+    let res = match p.ttl_ms {
+        Some(ms) => tonledb_nosql_kv::put_with_ttl(&*app.db.storage, key.into_bytes(), body.into_bytes(), std::time::Duration::from_millis(ms)),
+        None => tonledb_nosql_kv::put_with(&*app.db.storage, key.into_bytes(), body.into_bytes(), consistency),
+    };
+    if let Err(e) = res { return Json(serde_json::json!({"error":e.to_string()})).into_response(); }
+    Json(serde_json::json!({"ok":true})).into_response()
+}
+/// `cursor` is the `next` of the previous page, opaque to clients (the last key, base64).
+#[derive(Deserialize)]
+struct KvScanParams { #[serde(default)] prefix:String, cursor:Option<String>, limit:Option<usize> }
+
+const KV_PAGE_DEFAULT: usize = 100;
+const KV_PAGE_MAX: usize = 1000;
+
+/// One page of keys under `prefix`; values are base64.
+async fn kv_scan(State(app):State<AppState>, user:auth::User, Query(p):Query<KvScanParams>)->Json<serde_json::Value>{
     if !auth::require(auth::Role::ReadOnly, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})); }
-    let v = tonledb_nosql_kv::get(&*app.db.storage, key.as_bytes()).unwrap();
-    Json(match v { Some(b)=>serde_json::json!({"value": general_purpose::STANDARD.encode(b)}), None=>serde_json::json!({"value":null}) })
+    let after = match p.cursor.as_deref().map(|c| general_purpose::URL_SAFE_NO_PAD.decode(c)).transpose() {
+        Ok(a) => a,
+        Err(_) => return Json(serde_json::json!({"error":"invalid cursor"})),
+    };
+    let limit = p.limit.unwrap_or(KV_PAGE_DEFAULT).clamp(1, KV_PAGE_MAX);
+    match tonledb_nosql_kv::scan_prefix_page(&*app.db.storage, p.prefix.as_bytes(), after.as_deref(), limit) {
+        Ok(page) => {
+            let items: Vec<serde_json::Value> = page.items.into_iter()
+                .map(|(k, v)| serde_json::json!({"key": String::from_utf8_lossy(&k), "value": general_purpose::STANDARD.encode(v)}))
+                .collect();
+            Json(serde_json::json!({"items": items, "next": page.next.map(|k| general_purpose::URL_SAFE_NO_PAD.encode(k))}))
+        }
+        Err(e) => Json(serde_json::json!({"error":e.to_string()})),
+    }
+}
+#[derive(Deserialize)]
+struct KvExportParams { #[serde(default)] prefix:String }
+
+/// Every pair under `prefix` as NDJSON lines `{"key":..,"value":..}` (value base64),
+/// read from storage in batches as the response is written.
+async fn kv_export(State(app):State<AppState>, user:auth::User, Query(p):Query<KvExportParams>)->Response{
+    if !auth::require(auth::Role::ReadOnly, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})).into_response(); }
+    // The scan reads storage as it goes, so it runs on the blocking pool and hands
+    // lines over a small channel; a client that stops reading stops the scan
+    let (tx, rx) = tokio::sync::mpsc::channel(16);
+    let storage = app.db.storage.clone();
+    tokio::task::spawn_blocking(move || {
+        for row in tonledb_nosql_kv::scan_prefix_iter(storage, p.prefix.as_bytes()) {
+            let line = row.map_err(|e| std::io::Error::other(e.to_string())).map(|(k, v)| {
+                let mut line = serde_json::json!({"key": String::from_utf8_lossy(&k), "value": general_purpose::STANDARD.encode(v)}).to_string();
+                line.push('\n');
+                line
+            });
+            let failed = line.is_err();
+            if tx.blocking_send(line).is_err() || failed { break; }
+        }
+    });
+    let lines = futures::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|l| (l, rx)) });
+    let mut resp = axum::body::Body::from_stream(lines).into_response();
+    resp.headers_mut().insert(axum::http::header::CONTENT_TYPE, axum::http::HeaderValue::from_static("application/x-ndjson"));
+    resp
+}
+#[derive(Deserialize)]
+struct KvWatchParams { #[serde(default)] prefix:String }
+
+/// Server-sent `put`/`delete` events for keys under `prefix`; values are base64.
+/// A client that falls too far behind is disconnected (see `tonledb_nosql_kv::watch`).
+async fn kv_watch(user:auth::User, Query(p):Query<KvWatchParams>)->Response{
+    use futures::StreamExt as _;
+    use tonledb_nosql_kv::watch::KvEventKind;
+    if !auth::require(auth::Role::ReadOnly, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})).into_response(); }
+    let events = match tonledb_nosql_kv::watch::watch(p.prefix.as_bytes()) {
+        Ok(w) => w,
+        Err(e) => return Json(serde_json::json!({"error": e.to_string()})).into_response(),
+    };
+    let stream = events.map(|e| {
+        let kind = match e.kind { KvEventKind::Put => "put", KvEventKind::Delete => "delete" };
+        let data = serde_json::json!({"key": String::from_utf8_lossy(&e.key), "value": e.value.map(|v| general_purpose::STANDARD.encode(v)), "timestamp": e.timestamp});
+        Ok::<_, std::convert::Infallible>(Event::default().event(kind).json_data(data).unwrap_or_default())
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default()).into_response()
 }
-async fn kv_put(State(app):State<AppState>, user:auth::User, Path(key):Path<String>, body:String)->Json<serde_json::Value>{
+/// Open a scratch session: private to the caller, in memory only, dropped when
+/// ended or after being idle (see `tonledb_nosql_kv::scratch`).
+async fn scratch_begin(State(app):State<AppState>, user:auth::User)->Json<serde_json::Value>{
     if !auth::require(auth::Role::ReadWrite, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})); }
-    tonledb_nosql_kv::put(&*app.db.storage, key.into_bytes(), body.into_bytes()).unwrap();
-    Json(serde_json::json!({"ok":true}))
+    Json(serde_json::json!({"session": app.scratch.begin(&user.0.name)}))
+}
+async fn scratch_end(State(app):State<AppState>, user:auth::User, Path(session):Path<String>)->Json<serde_json::Value>{
+    match app.scratch.end(&user.0.name, &session) {
+        Ok(ended) => Json(serde_json::json!({"ended": ended})),
+        Err(e) => Json(serde_json::json!({"error": e.to_string()})),
+    }
+}
+async fn scratch_get(State(app):State<AppState>, user:auth::User, Path((session, key)):Path<(String, String)>)->Json<serde_json::Value>{
+    match app.scratch.get(&user.0.name, &session, key.as_bytes()) {
+        Ok(Some(b)) => Json(serde_json::json!({"value": general_purpose::STANDARD.encode(b)})),
+        Ok(None) => Json(serde_json::json!({"value":null})),
+        Err(e) => Json(serde_json::json!({"error": e.to_string()})),
+    }
+}
+async fn scratch_put(State(app):State<AppState>, user:auth::User, Path((session, key)):Path<(String, String)>, body:String)->Json<serde_json::Value>{
+    match app.scratch.put(&user.0.name, &session, key.into_bytes(), body.into_bytes()) {
+        Ok(()) => Json(serde_json::json!({"ok":true})),
+        Err(e) => Json(serde_json::json!({"error": e.to_string()})),
+    }
+}
+async fn scratch_del(State(app):State<AppState>, user:auth::User, Path((session, key)):Path<(String, String)>)->Json<serde_json::Value>{
+    match app.scratch.del(&user.0.name, &session, key.as_bytes()) {
+        Ok(()) => Json(serde_json::json!({"ok":true})),
+        Err(e) => Json(serde_json::json!({"error": e.to_string()})),
+    }
+}
+/// Insert a document; 409 if it repeats a value of a unique index.
+async fn doc_insert(State(app):State<AppState>, user:auth::User, Path(col):Path<String>, Json(doc):Json<serde_json::Value>)->Response{
+    if !auth::require(auth::Role::ReadWrite, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})).into_response(); }
+    match tonledb_nosql_doc::insert(&*app.db.storage, &col, doc) {
+        Ok(id) => Json(serde_json::json!({"id":id})).into_response(),
+        Err(tonledb_core::DbError::Conflict(e)) => (axum::http::StatusCode::CONFLICT, Json(serde_json::json!({"error":e}))).into_response(),
+        Err(e) => Json(serde_json::json!({"error": e.to_string()})).into_response(),
+    }
+}
+
+async fn doc_drop(State(app):State<AppState>, user:auth::User, Path(col):Path<String>)->Json<serde_json::Value>{
+    if !auth::require(auth::Role::Admin, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})); }
+    match tonledb_nosql_doc::drop_collection(&*app.db.storage, &col) {
+        Ok(removed) => Json(serde_json::json!({"removed": removed})),
+        Err(e) => Json(serde_json::json!({"error": e.to_string()})),
+    }
+}
+
+/// Make an empty collection capped: `{"max_docs": 1000}` and/or `{"max_bytes": ...}`
+async fn doc_capped(State(app):State<AppState>, user:auth::User, Path(col):Path<String>, Json(cap):Json<tonledb_nosql_doc::capped::Cap>)->Response{
+    if !auth::require(auth::Role::Admin, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})).into_response(); }
+    match tonledb_nosql_doc::capped::create_capped_collection(&*app.db.storage, &col, cap) {
+        Ok(()) => Json(serde_json::json!({"capped": col})).into_response(),
+        Err(tonledb_core::DbError::Conflict(e)) => (axum::http::StatusCode::CONFLICT, Json(serde_json::json!({"error":e}))).into_response(),
+        Err(e) => Json(serde_json::json!({"error": e.to_string()})).into_response(),
+    }
+}
+
+/// Make an empty collection a time series: `{"time_field": "ts", "retention_ms": 86400000}`
+async fn doc_timeseries(State(app):State<AppState>, user:auth::User, Path(col):Path<String>, Json(ts):Json<tonledb_nosql_doc::timeseries::TimeSeries>)->Response{
+    if !auth::require(auth::Role::Admin, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})).into_response(); }
+    match tonledb_nosql_doc::timeseries::create_timeseries(&*app.db.storage, &col, ts) {
+        Ok(()) => Json(serde_json::json!({"timeseries": col})).into_response(),
+        Err(tonledb_core::DbError::Conflict(e)) => (axum::http::StatusCode::CONFLICT, Json(serde_json::json!({"error":e}))).into_response(),
+        Err(e) => Json(serde_json::json!({"error": e.to_string()})).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct RangeParams { from:u64, to:u64, bucket_ms:Option<u64>, field:Option<String>, after:Option<String>, limit:Option<usize> }
+
+const RANGE_PAGE_DEFAULT: usize = 1000;
+const RANGE_PAGE_MAX: usize = 10_000;
+
+/// Documents of a time series in `from..to`, a page at a time with `?limit=` and
+/// `?after=<next>`, or with `bucket_ms` and `field` the count, min, max and
+/// average of `field` per bucket
+async fn doc_range(State(app):State<AppState>, user:auth::User, Path(col):Path<String>, Query(p):Query<RangeParams>)->Json<serde_json::Value>{
+    if !auth::require(auth::Role::ReadOnly, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})); }
+    use tonledb_nosql_doc::timeseries;
+    let res = match (p.bucket_ms, &p.field) {
+        (Some(bucket_ms), Some(field)) => timeseries::downsample(&*app.db.storage, &col, p.from, p.to, bucket_ms, field).map(|b| serde_json::json!({"buckets": b})),
+        (None, None) => {
+            let paging = tonledb_nosql_doc::Paging { after: p.after, skip: 0, limit: Some(p.limit.unwrap_or(RANGE_PAGE_DEFAULT).clamp(1, RANGE_PAGE_MAX)) };
+            timeseries::range_page(&*app.db.storage, &col, p.from, p.to, &paging).map(|page| serde_json::json!({"docs": page.docs, "next": page.next}))
+        }
+        _ => return Json(serde_json::json!({"error": "bucket_ms and field go together"})),
+    };
+    match res {
+        Ok(v) => Json(v),
+        Err(e) => Json(serde_json::json!({"error": e.to_string()})),
+    }
+}
+
+#[derive(Deserialize)]
+struct TimestampsBody { enabled:bool }
+
+/// Turn server-side `_created_at`/`_updated_at` stamping on or off for a collection
+async fn doc_timestamps(State(app):State<AppState>, user:auth::User, Path(col):Path<String>, Json(b):Json<TimestampsBody>)->Json<serde_json::Value>{
+    if !auth::require(auth::Role::Admin, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})); }
+    match tonledb_nosql_doc::set_write_timestamps(&*app.db.storage, &col, b.enabled) {
+        Ok(()) => Json(serde_json::json!({"timestamps": b.enabled})),
+        Err(e) => Json(serde_json::json!({"error": e.to_string()})),
+    }
+}
+
+/// Definition of view `:col`
+async fn doc_view(State(app):State<AppState>, user:auth::User, Path(col):Path<String>)->Response{
+    if !auth::require(auth::Role::ReadOnly, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})).into_response(); }
+    match tonledb_nosql_doc::view::get_view(&*app.db.storage, &col) {
+        Ok(Some(view)) => Json(serde_json::json!(view)).into_response(),
+        Ok(None) => (axum::http::StatusCode::NOT_FOUND, Json(serde_json::json!({"error": format!("view {}", col)}))).into_response(),
+        Err(e) => Json(serde_json::json!({"error": e.to_string()})).into_response(),
+    }
 }
-async fn doc_insert(State(app):State<AppState>, user:auth::User, Path(col):Path<String>, Json(doc):Json<serde_json::Value>)->Json<serde_json::Value>{
+
+/// Define a read-only view, queried through `/doc/:col/query` and `/doc/:col/count`
+/// like a collection: `{"collection": "orders", "filter": {...}, "fields": ["total"]}`
+async fn doc_create_view(State(app):State<AppState>, user:auth::User, Path(col):Path<String>, Json(view):Json<tonledb_nosql_doc::view::View>)->Response{
+    if !auth::require(auth::Role::Admin, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})).into_response(); }
+    match tonledb_nosql_doc::view::create_view(&*app.db.storage, &col, &view) {
+        Ok(()) => Json(serde_json::json!({"view": col})).into_response(),
+        Err(tonledb_core::DbError::Conflict(e)) => (axum::http::StatusCode::CONFLICT, Json(serde_json::json!({"error":e}))).into_response(),
+        Err(e) => Json(serde_json::json!({"error": e.to_string()})).into_response(),
+    }
+}
+
+async fn doc_drop_view(State(app):State<AppState>, user:auth::User, Path(col):Path<String>)->Json<serde_json::Value>{
+    if !auth::require(auth::Role::Admin, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})); }
+    match tonledb_nosql_doc::view::drop_view(&*app.db.storage, &col) {
+        Ok(dropped) => Json(serde_json::json!({"dropped": dropped})),
+        Err(e) => Json(serde_json::json!({"error": e.to_string()})),
+    }
+}
+
+async fn doc_rename(State(app):State<AppState>, user:auth::User, Path((col, to)):Path<(String, String)>)->Response{
+    if !auth::require(auth::Role::Admin, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})).into_response(); }
+    match tonledb_nosql_doc::rename_collection(&*app.db.storage, &col, &to) {
+        Ok(moved) => Json(serde_json::json!({"moved": moved})).into_response(),
+        Err(tonledb_core::DbError::Conflict(e)) => (axum::http::StatusCode::CONFLICT, Json(serde_json::json!({"error":e}))).into_response(),
+        Err(tonledb_core::DbError::NotFound(e)) => (axum::http::StatusCode::NOT_FOUND, Json(serde_json::json!({"error":e}))).into_response(),
+        Err(e) => Json(serde_json::json!({"error": e.to_string()})).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct DocPageParams { after:Option<String>, #[serde(default)] skip:usize, limit:Option<usize> }
+
+/// Documents matching the filter in the body, a page at a time with `?limit=` and
+/// `?after=<next>` (and `?skip=`); without a limit, every match.
+async fn doc_query(State(app):State<AppState>, user:auth::User, Path(col):Path<String>, Query(p):Query<DocPageParams>, Json(filter):Json<serde_json::Value>)->Json<serde_json::Value>{
+    if !auth::require(auth::Role::ReadOnly, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})); }
+    let filter = match Filter::parse(&filter) { Ok(f)=>f, Err(e)=>return Json(serde_json::json!({"error":e.to_string()})) };
+    let paging = tonledb_nosql_doc::Paging { after: p.after, skip: p.skip, limit: p.limit };
+    match tonledb_nosql_doc::query_page(&*app.db.storage, &col, &filter, true, &paging) {
+        Ok(page) => Json(serde_json::json!({"docs": page.docs, "next": page.next})),
+        Err(e) => Json(serde_json::json!({"error": e.to_string()})),
+    }
+}
+
+/// Number of documents matching the filter in the body
+async fn doc_count(State(app):State<AppState>, user:auth::User, Path(col):Path<String>, Json(filter):Json<serde_json::Value>)->Json<serde_json::Value>{
+    if !auth::require(auth::Role::ReadOnly, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})); }
+    let filter = match Filter::parse(&filter) { Ok(f)=>f, Err(e)=>return Json(serde_json::json!({"error":e.to_string()})) };
+    match tonledb_nosql_doc::count(&*app.db.storage, &col, &filter) {
+        Ok(count) => Json(serde_json::json!({"count": count})),
+        Err(e) => Json(serde_json::json!({"error": e.to_string()})),
+    }
+}
+
+/// Distinct values of `field` among documents matching the filter in the body
+async fn doc_distinct(State(app):State<AppState>, user:auth::User, Path((col, field)):Path<(String, String)>, Json(filter):Json<serde_json::Value>)->Json<serde_json::Value>{
+    if !auth::require(auth::Role::ReadOnly, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})); }
+    let filter = match Filter::parse(&filter) { Ok(f)=>f, Err(e)=>return Json(serde_json::json!({"error":e.to_string()})) };
+    match tonledb_nosql_doc::distinct(&*app.db.storage, &col, &field, &filter) {
+        Ok(values) => Json(serde_json::json!({"values": values})),
+        Err(e) => Json(serde_json::json!({"error": e.to_string()})),
+    }
+}
+
+#[derive(Deserialize)]
+struct IndexParams { #[serde(default)] unique:bool }
+
+/// Index `field` of `col` (multikey for arrays), backfilling existing documents.
+/// With `?unique=true` the index also rejects duplicate values.
+async fn doc_create_index(State(app):State<AppState>, user:auth::User, Path((col, field)):Path<(String, String)>, Query(p):Query<IndexParams>)->Json<serde_json::Value>{
+    if !auth::require(auth::Role::Admin, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})); }
+    let created = if p.unique {
+        tonledb_nosql_doc::index::create_unique_index(&*app.db.storage, &col, &field)
+    } else {
+        tonledb_nosql_doc::index::create_index(&*app.db.storage, &col, &field)
+    };
+    match created {
+        Ok(entries) => Json(serde_json::json!({"entries": entries})),
+        Err(e) => Json(serde_json::json!({"error": e.to_string()})),
+    }
+}
+
+async fn doc_drop_index(State(app):State<AppState>, user:auth::User, Path((col, field)):Path<(String, String)>)->Json<serde_json::Value>{
+    if !auth::require(auth::Role::Admin, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})); }
+    match tonledb_nosql_doc::index::drop_index(&*app.db.storage, &col, &field) {
+        Ok(()) => Json(serde_json::json!({"ok": true})),
+        Err(e) => Json(serde_json::json!({"error": e.to_string()})),
+    }
+}
+
+/// Run an aggregation pipeline (`$match`, `$lookup`, `$skip`, `$limit`) over `col`.
+async fn doc_aggregate(State(app):State<AppState>, user:auth::User, Path(col):Path<String>, Json(pipeline):Json<serde_json::Value>)->Json<serde_json::Value>{
+    if !auth::require(auth::Role::ReadOnly, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})); }
+    let pipeline = match Pipeline::parse(&pipeline) { Ok(p)=>p, Err(e)=>return Json(serde_json::json!({"error":e.to_string()})) };
+    match tonledb_nosql_doc::aggregate::aggregate(&*app.db.storage, &col, &pipeline) {
+        Ok(docs) => Json(serde_json::json!({"docs": docs})),
+        Err(e) => Json(serde_json::json!({"error": e.to_string()})),
+    }
+}
+
+/// Stream a whole collection as NDJSON; see `export::export` for cursor, rate and gzip.
+async fn doc_export(State(app):State<AppState>, user:auth::User, Path(col):Path<String>, Query(p):Query<export::ExportParams>)->Response{
+    if !auth::require(auth::Role::ReadOnly, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})).into_response(); }
+    export::export(app.db.storage.clone(), format!("doc/{}/", col), p).await
+}
+
+/// Stream every row of a SQL table as NDJSON.
+async fn table_export(State(app):State<AppState>, user:auth::User, Path(name):Path<String>, Query(p):Query<export::ExportParams>)->Response{
+    if !auth::require(auth::Role::ReadOnly, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})).into_response(); }
+    export::export(app.db.storage.clone(), format!("tbl/{}/", name), p).await
+}
+
+#[derive(Deserialize)]
+struct SchemaParams { refresh:Option<bool>, sample:Option<usize> }
+
+/// Inferred schema of a collection; `?refresh=true` re-samples it.
+async fn doc_schema(State(app):State<AppState>, user:auth::User, Path(col):Path<String>, Query(p):Query<SchemaParams>)->Json<serde_json::Value>{
+    if !auth::require(auth::Role::ReadOnly, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})); }
+    let res = if p.refresh.unwrap_or(false) {
+        if !auth::require(auth::Role::ReadWrite, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})); }
+        schema_inference::refresh(&*app.db.storage, &col, p.sample.unwrap_or(schema_inference::DEFAULT_SAMPLE_SIZE))
+    } else {
+        schema_inference::load_or_refresh(&*app.db.storage, &col)
+    };
+    match res {
+        Ok(schema) => Json(serde_json::json!({"schema": schema})),
+        Err(e) => Json(serde_json::json!({"error": e.to_string()})),
+    }
+}
+
+/// `filter` is a URL-encoded filter in the `/doc/:col/query` syntax.
+#[derive(Deserialize)]
+struct WatchParams { filter:Option<String> }
+
+/// Server-sent events for changes to `col`; the filter is evaluated server-side.
+/// A client that falls too far behind is disconnected (see `tonledb_nosql_doc::watch`).
+async fn doc_watch(user:auth::User, Path(col):Path<String>, Query(p):Query<WatchParams>)->Response{
+    use futures::StreamExt as _;
+    if !auth::require(auth::Role::ReadOnly, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})).into_response(); }
+    let filter = match p.filter.as_deref().map(|f| {
+        serde_json::from_str(f).map_err(|e| e.to_string()).and_then(|expr| Filter::parse(&expr).map_err(|e| e.to_string()))
+    }).transpose() {
+        Ok(f) => f,
+        Err(e) => return Json(serde_json::json!({"error": format!("invalid filter: {}", e)})).into_response(),
+    };
+
+    // The watch unregisters itself once the SSE stream is dropped (client disconnected)
+    let changes = match tonledb_nosql_doc::watch::watch_collection(&col, filter) {
+        Ok(w) => w,
+        Err(e) => return Json(serde_json::json!({"error": e})).into_response(),
+    };
+    let stream = changes.map(|change| {
+        Ok::<_, std::convert::Infallible>(Event::default().event("change").json_data(&change).unwrap_or_default())
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default()).into_response()
+}
+
+fn graph_result<T: serde::Serialize>(key:&str, r:tonledb_core::Result<T>)->Json<serde_json::Value>{
+    match r { Ok(v)=>Json(serde_json::json!({key: v})), Err(e)=>Json(serde_json::json!({"error":e.to_string()})) }
+}
+
+async fn graph_put_node(State(app):State<AppState>, user:auth::User, Json(node):Json<graph::Node>)->Json<serde_json::Value>{
     if !auth::require(auth::Role::ReadWrite, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})); }
-    let id = tonledb_nosql_doc::insert(&*app.db.storage, &col, doc).unwrap();
-    Json(serde_json::json!({"id":id}))
-}
\ No newline at end of file
+    graph_result("ok", graph::put_node(&*app.db.storage, &node).map(|_| true))
+}
+async fn graph_get_node(State(app):State<AppState>, user:auth::User, Path(id):Path<String>)->Json<serde_json::Value>{
+    if !auth::require(auth::Role::ReadOnly, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})); }
+    graph_result("node", graph::get_node(&*app.db.storage, &id))
+}
+async fn graph_delete_node(State(app):State<AppState>, user:auth::User, Path(id):Path<String>)->Json<serde_json::Value>{
+    if !auth::require(auth::Role::ReadWrite, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})); }
+    graph_result("deleted", graph::delete_node(&*app.db.storage, &id))
+}
+async fn graph_put_edge(State(app):State<AppState>, user:auth::User, Json(edge):Json<graph::Edge>)->Json<serde_json::Value>{
+    if !auth::require(auth::Role::ReadWrite, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})); }
+    graph_result("ok", graph::put_edge(&*app.db.storage, &edge).map(|_| true))
+}
+
+/// Traversal bounds are clamped server-side so one request can't walk the whole graph.
+#[derive(Deserialize)]
+struct GraphParams { start:Option<String>, from:Option<String>, to:Option<String>, edge_type:Option<String>, #[serde(default)] direction:graph::Direction, depth:Option<usize>, limit:Option<usize> }
+
+const GRAPH_MAX_DEPTH: usize = 8;
+const GRAPH_MAX_NODES: usize = 10_000;
+
+impl GraphParams {
+    fn bounds(&self)->graph::Bounds{
+        let d = graph::Bounds::default();
+        graph::Bounds{ max_depth: self.depth.unwrap_or(d.max_depth).min(GRAPH_MAX_DEPTH), max_nodes: self.limit.unwrap_or(d.max_nodes).min(GRAPH_MAX_NODES) }
+    }
+}
+
+async fn graph_neighbors(State(app):State<AppState>, user:auth::User, Path(id):Path<String>, Query(p):Query<GraphParams>)->Json<serde_json::Value>{
+    if !auth::require(auth::Role::ReadOnly, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})); }
+    graph_result("neighbors", graph::neighbors(&*app.db.storage, &id, p.edge_type.as_deref(), p.direction))
+}
+async fn graph_bfs(State(app):State<AppState>, user:auth::User, Query(p):Query<GraphParams>)->Json<serde_json::Value>{
+    if !auth::require(auth::Role::ReadOnly, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})); }
+    let Some(start) = p.start.as_deref() else { return Json(serde_json::json!({"error":"start is required"})) };
+    graph_result("visited", graph::bfs(&*app.db.storage, start, p.edge_type.as_deref(), p.direction, p.bounds()))
+}
+async fn graph_path(State(app):State<AppState>, user:auth::User, Query(p):Query<GraphParams>)->Json<serde_json::Value>{
+    if !auth::require(auth::Role::ReadOnly, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})); }
+    let (Some(from), Some(to)) = (p.from.as_deref(), p.to.as_deref()) else { return Json(serde_json::json!({"error":"from and to are required"})) };
+    graph_result("path", graph::shortest_path(&*app.db.storage, from, to, p.edge_type.as_deref(), p.direction, p.bounds()))
+}
+
+#[derive(Deserialize)]
+struct EventsParams { since_ms:Option<u64>, kind:Option<SystemEventKind>, min_severity:Option<Severity>, limit:Option<usize> }
+
+async fn admin_events(State(app):State<AppState>, user:auth::User, Query(p):Query<EventsParams>)->Json<serde_json::Value>{
+    if !auth::require(auth::Role::Admin, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})); }
+    let q = EventQuery{ since_ms: p.since_ms, kind: p.kind, min_severity: p.min_severity, limit: Some(p.limit.unwrap_or(100)) };
+    match app.events.query(&q) {
+        Ok(evs) => Json(serde_json::json!({"events": evs})),
+        Err(e) => Json(serde_json::json!({"error": e.to_string()})),
+    }
+}
+
+/// Compact `space` online: drop expired keys and delete markers, re-encode values and
+/// shrink its WAL records to one per live key. Runs off the async workers.
+fn observe_kv_stats(s:&tonledb_nosql_kv::KvStats){ tonledb_metrics::set_kv_stats(s.keys as u64, s.value_bytes, s.ttl_keys as u64); }
+
+/// Key count, value bytes and expiring keys of the kv space; also refreshes the gauges.
+async fn admin_kv_stats(State(app):State<AppState>, user:auth::User)->Json<serde_json::Value>{
+    if !auth::require(auth::Role::Admin, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})); }
+    let storage = app.db.storage.clone();
+    match tokio::task::spawn_blocking(move || tonledb_nosql_kv::stats(&*storage)).await {
+        Ok(Ok(stats)) => { observe_kv_stats(&stats); Json(serde_json::json!({"stats": stats})) }
+        Ok(Err(e)) => Json(serde_json::json!({"error": e.to_string()})),
+        Err(e) => Json(serde_json::json!({"error": e.to_string()})),
+    }
+}
+
+fn observe_doc_stats(col:&str, s:&tonledb_nosql_doc::CollectionStats){
+    let index_bytes: Vec<(&str, u64)> = s.indexes.iter().map(|i| (i.field.as_str(), i.bytes)).collect();
+    tonledb_metrics::set_doc_stats(col, s.documents as u64, s.bytes, &index_bytes);
+}
+
+/// Document count, bytes and index sizes of one collection; also refreshes its gauges.
+async fn admin_doc_stats(State(app):State<AppState>, user:auth::User, Path(col):Path<String>)->Json<serde_json::Value>{
+    if !auth::require(auth::Role::Admin, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})); }
+    let storage = app.db.storage.clone();
+    let name = col.clone();
+    match tokio::task::spawn_blocking(move || tonledb_nosql_doc::collection_stats(&*storage, &name)).await {
+        Ok(Ok(stats)) => { observe_doc_stats(&col, &stats); Json(serde_json::json!({"stats": stats})) }
+        Ok(Err(e)) => Json(serde_json::json!({"error": e.to_string()})),
+        Err(e) => Json(serde_json::json!({"error": e.to_string()})),
+    }
+}
+
+async fn admin_compact(State(app):State<AppState>, user:auth::User, Path(space):Path<String>)->Json<serde_json::Value>{
+    if !auth::require(auth::Role::Admin, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})); }
+    let storage = app.db.storage.clone();
+    let res = tokio::task::spawn_blocking(move || storage.compact_space(&tonledb_core::Space(space))).await;
+    match res {
+        Ok(Ok(report)) => {
+            let msg = format!("compacted {}: {} live keys, WAL records {} -> {}", report.space, report.live_keys, report.wal_records_before, report.wal_records_after);
+            let _ = app.events.record(SystemEventKind::CompactionFinished, Severity::Info, msg, Default::default());
+            Json(serde_json::json!({"report": report}))
+        }
+        Ok(Err(e)) => Json(serde_json::json!({"error": e.to_string()})),
+        Err(e) => Json(serde_json::json!({"error": e.to_string()})),
+    }
+}
+
+/// Download a snapshot of `spaces` (default `backup::DEFAULT_SPACES`), streamed as it is written.
+async fn admin_backup(State(app):State<AppState>, user:auth::User, Query(p):Query<backup::BackupParams>)->Response{
+    if !auth::require(auth::Role::Admin, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})).into_response(); }
+    audit::log(&audit::AuditEvent{ ts: &chrono::Utc::now().to_rfc3339(), who: &user.0.name, action:"BACKUP", resource:"/admin/backup", result:"ok" });
+    backup::backup(app.db.storage.clone(), app.events.clone(), p)
+}
+
+/// Restore the snapshot in the request body, e.g. one downloaded from /admin/backup;
+/// `spaces` restores only those.
+async fn admin_restore(State(app):State<AppState>, user:auth::User, Query(p):Query<backup::BackupParams>, body:axum::body::Body)->Json<serde_json::Value>{
+    if !auth::require(auth::Role::Admin, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})); }
+    let res = backup::restore(app.db.storage.clone(), p, body).await;
+    audit::log(&audit::AuditEvent{ ts: &chrono::Utc::now().to_rfc3339(), who: &user.0.name, action:"RESTORE", resource:"/admin/restore", result: if res.is_ok() { "ok" } else { "error" } });
+    match res {
+        Ok(n) => Json(serde_json::json!({"restored": n})),
+        Err(e) => {
+            let _ = app.events.record(SystemEventKind::BackupFailed, Severity::Warning, format!("restore upload failed: {}", e), Default::default());
+            Json(serde_json::json!({"error": e}))
+        }
+    }
+}
+
+/// How far behind the primary this node is, when it runs as a historical replica.
+async fn admin_replica(State(app):State<AppState>, user:auth::User)->Json<serde_json::Value>{
+    if !auth::require(auth::Role::Admin, &user.0.role){ return Json(serde_json::json!({"error":"forbidden"})); }
+    match &app.replica {
+        Some(r) => Json(serde_json::json!({"replica": r.status()})),
+        None => Json(serde_json::json!({"error": "not a historical replica"})),
+    }
+}
+
+#[derive(Deserialize)]
+struct HistoryParams { user:Option<String>, since_ms:Option<u64>, until_ms:Option<u64>, fingerprint:Option<String>, status:Option<StatementStatus>, limit:Option<usize> }
+
+/// Admins may read anyone's history (all users if `user` is omitted); others only their own.
+async fn admin_history(State(app):State<AppState>, user:auth::User, Query(p):Query<HistoryParams>)->Json<serde_json::Value>{
+    let who = if auth::require(auth::Role::Admin, &user.0.role) { p.user } else {
+        if p.user.as_ref().is_some_and(|u| *u != user.0.name) { return Json(serde_json::json!({"error":"forbidden"})); }
+        Some(user.0.name.clone())
+    };
+    let q = HistoryQuery{ user: who, since_ms: p.since_ms, until_ms: p.until_ms, fingerprint: p.fingerprint, status: p.status, limit: Some(p.limit.unwrap_or(100)) };
+    match app.history.query(&q) {
+        Ok(stmts) => Json(serde_json::json!({"statements": stmts})),
+        Err(e) => Json(serde_json::json!({"error": e.to_string()})),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::header::{HeaderName, ETAG, IF_MATCH, IF_NONE_MATCH};
+    use axum::http::{HeaderValue, StatusCode};
+    use super::*;
+
+    fn app() -> AppState {
+        let storage: Arc<dyn tonledb_core::Storage> = Arc::new(tonledb_storage::InMemoryStore::new(1000));
+        AppState {
+            db: Arc::new(Db::new(storage.clone())),
+            auth: auth::AppAuth { tokens: auth::TokenStore::default(), mode: auth::AuthMode::None },
+            events: Arc::new(SystemEventLog::new(storage.clone()).unwrap()),
+            history: Arc::new(StatementHistory::new(storage, HistoryRetention::default()).unwrap()),
+            scratch: Arc::new(ScratchRegistry::new(ScratchLimits::default())),
+            replica: None,
+        }
+    }
+
+    fn header(name: HeaderName, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    fn user() -> auth::User {
+        auth::User(auth::Identity { name: "ann".into(), role: auth::Role::ReadWrite })
+    }
+
+    async fn put(app: &AppState, key: &str, value: &str, headers: HeaderMap) -> Response {
+        kv_put(State(app.clone()), user(), headers, Path(key.into()), Query(KvPutParams { ttl_ms: None }), value.into()).await
+    }
+
+    async fn get(app: &AppState, key: &str, headers: HeaderMap) -> Response {
+        kv_get(State(app.clone()), user(), headers, Query(TraceParams { trace: None }), Path(key.into())).await
+    }
+
+    fn etag(resp: &Response) -> String {
+        resp.headers()[ETAG].to_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_kv_etags_answer_304_and_412() {
+        kv_meta::set_enabled(true);
+        let app = app();
+        // `If-Match: *` wants the key to exist, `If-None-Match: *` wants it absent
+        assert_eq!(put(&app, "etag/k", "1", header(IF_MATCH, "*")).await.status(), StatusCode::PRECONDITION_FAILED);
+        let created = put(&app, "etag/k", "1", header(IF_NONE_MATCH, "*")).await;
+        assert_eq!(created.status(), StatusCode::OK);
+        let first = etag(&created);
+        assert_eq!(put(&app, "etag/k", "1", header(IF_NONE_MATCH, "*")).await.status(), StatusCode::PRECONDITION_FAILED);
+
+        let unchanged = get(&app, "etag/k", header(IF_NONE_MATCH, &first)).await;
+        assert_eq!((unchanged.status(), etag(&unchanged)), (StatusCode::NOT_MODIFIED, first.clone()));
+
+        let updated = put(&app, "etag/k", "2", header(IF_MATCH, "*")).await;
+        assert_eq!(updated.status(), StatusCode::OK);
+        let second = etag(&updated);
+        assert_ne!(second, first);
+
+        // A stale tag fails the write and no longer matches on reads
+        assert_eq!(put(&app, "etag/k", "3", header(IF_MATCH, &first)).await.status(), StatusCode::PRECONDITION_FAILED);
+        let changed = get(&app, "etag/k", header(IF_NONE_MATCH, &first)).await;
+        assert_eq!((changed.status(), etag(&changed)), (StatusCode::OK, second.clone()));
+        assert_eq!(put(&app, "etag/k", "3", header(IF_MATCH, &second)).await.status(), StatusCode::OK);
+        assert_eq!(put(&app, "etag/k", "4", header(IF_MATCH, "\"bogus\"")).await.status(), StatusCode::PRECONDITION_FAILED);
+    }
+
+    #[tokio::test]
+    async fn test_sql_answers_an_arrow_stream_when_asked() {
+        use arrow::array::AsArray;
+        use arrow::datatypes::{DataType as ArrowType, Int64Type};
+        use tonledb_core::{Column, DataType, TableSchema};
+        let app = app();
+        let columns = vec![
+            Column { name: "id".into(), data_type: DataType::Integer, constraints: vec![] },
+            Column { name: "name".into(), data_type: DataType::Text, constraints: vec![] },
+        ];
+        let table = TableSchema { name: "items".into(), columns, pk: Some("id".into()), constraints: vec![] };
+        app.db.catalog.write().tables.insert("items".into(), table.clone());
+        let mut writer = tonledb_sql::rows::RowWriter::new(&app.db);
+        for id in 0..10_000 {
+            writer.insert(&table, &[], &serde_json::json!({"id": id, "name": format!("item {}", id)})).unwrap();
+        }
+        let sql = |sql: &str, headers: HeaderMap| sql_handler(State(app.clone()), user(), headers, Query(TraceParams { trace: None }), Json(SqlBody { sql: sql.into() }));
+
+        let accept = header(axum::http::header::ACCEPT, tonledb_arrow::ipc::ARROW_STREAM_MEDIA_TYPE);
+        let resp = sql("SELECT id, name AS label FROM items", accept.clone()).await;
+        assert_eq!(resp.headers()[axum::http::header::CONTENT_TYPE], tonledb_arrow::ipc::ARROW_STREAM_MEDIA_TYPE);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let reader = arrow::ipc::reader::StreamReader::try_new(std::io::Cursor::new(body), None).unwrap();
+        let schema = reader.schema();
+        let fields: Vec<_> = schema.fields().iter().map(|f| (f.name().as_str(), f.data_type().clone())).collect();
+        assert_eq!(fields, vec![("id", ArrowType::Int64), ("label", ArrowType::Utf8)]);
+        let batches: Vec<_> = reader.map(Result::unwrap).collect();
+        assert_eq!(batches.len(), 2, "one record batch per 8192 rows");
+        let ids: Vec<i64> = batches.iter().flat_map(|b| b.column(0).as_primitive::<Int64Type>().values().to_vec()).collect();
+        assert_eq!(ids.len(), 10_000);
+        assert_eq!(batches[0].column(1).as_string::<i32>().value(0), format!("item {}", ids[0]));
+
+        // An empty result still carries its columns
+        let resp = sql("SELECT id FROM items WHERE id = 20000", accept.clone()).await;
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let reader = arrow::ipc::reader::StreamReader::try_new(std::io::Cursor::new(body), None).unwrap();
+        assert_eq!(reader.schema().field(0).data_type(), &ArrowType::Int64);
+        assert_eq!(reader.map(|b| b.unwrap().num_rows()).sum::<usize>(), 0);
+
+        // Errors, and clients that don't ask for Arrow, get JSON
+        let resp = sql("SELEC id FROM items", accept).await;
+        assert_eq!(resp.headers()[axum::http::header::CONTENT_TYPE], "application/json");
+        let resp = sql("SELECT id FROM items WHERE id = 7", HeaderMap::new()).await;
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap(), serde_json::json!([{"id": 7}]));
+    }
+}
@@ -0,0 +1,147 @@
+//! Aggregation pipelines over a collection (`/doc/:col/aggregate`).
+//!
+//! A pipeline is a JSON array of single-key stage objects applied in order:
+//!
+//! ```text
+//! [
+//!   { "$match": { "status": "open" } },
+//!   { "$lookup": { "from": "users", "localField": "owner", "foreignField": "_id", "as": "owner_doc" } },
+//!   { "$skip": 10 },
+//!   { "$limit": 20 }
+//! ]
+//! ```
+//!
+//! `$lookup` is a server-side hash join: the foreign collection is scanned once,
+//! bucketed by `foreignField`, and each input document is probed against it, so a
+//! join costs one scan per side instead of one fetch per document. Matches are
+//! stored as an array under `as`; an array-valued `localField` joins on any element.
+//! Both join fields may be dotted paths into nested documents.
+
+use std::collections::HashMap;
+use serde_json::Value as Json;
+use tonledb_core::numbers;
+use tonledb_core::{DbError, Result, Storage};
+use crate::filter::{self, Filter};
+
+/// One pipeline stage
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stage {
+    Match(Filter),
+    Lookup(Lookup),
+    Skip(usize),
+    Limit(usize),
+}
+
+/// Join documents from `from` whose `foreign_field` equals the input's `local_field`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lookup {
+    pub from: String,
+    pub local_field: String,
+    pub foreign_field: String,
+    pub as_field: String,
+}
+
+/// A parsed aggregation pipeline
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Pipeline {
+    pub stages: Vec<Stage>,
+}
+
+impl Pipeline {
+    /// Parse a pipeline from its JSON array form
+    pub fn parse(expr: &Json) -> Result<Self> {
+        let stages = expr.as_array()
+            .ok_or_else(|| DbError::Invalid("pipeline must be a JSON array of stages".into()))?
+            .iter()
+            .map(parse_stage)
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { stages })
+    }
+
+    /// Run the pipeline over `docs`, reading joined collections from `storage`
+    pub fn run<S: Storage + ?Sized>(&self, storage: &S, mut docs: Vec<Json>) -> Result<Vec<Json>> {
+        for stage in &self.stages {
+            docs = match stage {
+                Stage::Match(f) => docs.into_iter().filter(|d| f.matches(d)).collect(),
+                Stage::Lookup(l) => lookup(storage, l, docs)?,
+                Stage::Skip(n) => docs.into_iter().skip(*n).collect(),
+                Stage::Limit(n) => docs.into_iter().take(*n).collect(),
+            };
+        }
+        Ok(docs)
+    }
+}
+
+/// Run `pipeline` over the live (non-expired) documents of `collection`
+pub fn aggregate<S: Storage + ?Sized>(storage: &S, collection: &str, pipeline: &Pipeline) -> Result<Vec<Json>> {
+    // A leading $match is applied during the scan rather than after materializing everything
+    let (docs, rest) = match pipeline.stages.split_first() {
+        Some((Stage::Match(f), rest)) => (crate::find_where(storage, collection, |d| f.matches(d), true)?, rest),
+        _ => (crate::list_all(storage, collection, true)?, &pipeline.stages[..]),
+    };
+    Pipeline { stages: rest.to_vec() }.run(storage, docs)
+}
+
+fn parse_stage(stage: &Json) -> Result<Stage> {
+    let (name, arg) = match stage.as_object() {
+        Some(o) if o.len() == 1 => o.iter().next().unwrap(),
+        _ => return Err(DbError::Invalid("each pipeline stage must be an object with exactly one key".into())),
+    };
+    match name.as_str() {
+        "$match" => Ok(Stage::Match(Filter::parse(arg)?)),
+        "$lookup" => {
+            let field = |k: &str| arg.get(k).and_then(Json::as_str).map(str::to_string)
+                .ok_or_else(|| DbError::Invalid(format!("$lookup requires string field {}", k)));
+            Ok(Stage::Lookup(Lookup {
+                from: field("from")?,
+                local_field: field("localField")?,
+                foreign_field: field("foreignField")?,
+                as_field: field("as")?,
+            }))
+        }
+        "$skip" | "$limit" => {
+            let n = arg.as_u64().ok_or_else(|| DbError::Invalid(format!("{} expects a non-negative integer", name)))? as usize;
+            Ok(if name == "$skip" { Stage::Skip(n) } else { Stage::Limit(n) })
+        }
+        other => Err(DbError::Invalid(format!("unknown pipeline stage {}", other))),
+    }
+}
+
+fn lookup<S: Storage + ?Sized>(storage: &S, l: &Lookup, mut docs: Vec<Json>) -> Result<Vec<Json>> {
+    if docs.is_empty() {
+        return Ok(docs);
+    }
+    // Build side: the foreign collection bucketed by join key
+    let foreign = crate::list_all(storage, &l.from, true)?;
+    let mut table: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, f) in foreign.iter().enumerate() {
+        if let Some(k) = filter::lookup(f, &l.foreign_field).and_then(join_key) {
+            table.entry(k).or_default().push(i);
+        }
+    }
+    // Probe side; a foreign doc matched by several array elements is joined once
+    for doc in &mut docs {
+        let keys: Vec<String> = match filter::lookup(doc, &l.local_field) {
+            Some(Json::Array(vs)) => vs.iter().filter_map(join_key).collect(),
+            Some(v) => join_key(v).into_iter().collect(),
+            None => Vec::new(),
+        };
+        let mut hits: Vec<usize> = keys.iter().filter_map(|k| table.get(k)).flatten().copied().collect();
+        hits.sort_unstable();
+        hits.dedup();
+        if let Some(obj) = doc.as_object_mut() {
+            obj.insert(l.as_field.clone(), hits.into_iter().map(|i| foreign[i].clone()).collect());
+        }
+    }
+    Ok(docs)
+}
+
+/// Hashable form of a join value; nulls never join
+fn join_key(v: &Json) -> Option<String> {
+    match v {
+        Json::Null => None,
+        // 1 and 1.0 should join, so numbers are keyed by their exact value
+        Json::Number(n) => numbers::canonical(n).map(|c| format!("n:{}", c)),
+        other => Some(other.to_string()),
+    }
+}
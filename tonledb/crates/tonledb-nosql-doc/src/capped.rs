@@ -0,0 +1,180 @@
+//! Capped collections: a maximum document count and/or byte size, kept by evicting
+//! the oldest documents on insert. Suited to logs and recent-activity feeds.
+//!
+//! A capped collection is registered in the catalog under `capped/<collection>`
+//! with its limits and a running tally (next sequence, documents, bytes). Its
+//! documents get sequential ids (16 hex digits), so key order is insertion order
+//! and eviction pops from the front of the collection.
+//!
+//! Writes to a capped collection are serialised in-process, and each is committed
+//! together with its evictions and the updated tally in one atomic write, so the
+//! tally stays exact. Documents are created by `insert` only: no TTLs, no upserts, and no
+//! writes through `txn`. A single document larger than `max_bytes` is rejected.
+
+use std::sync::{Mutex, MutexGuard};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as Json;
+use tonledb_core::transaction::{next_timestamp, WriteSet};
+use tonledb_core::{DbError, Result, Space, Storage};
+use crate::index::{self, EntryChanges};
+
+const CATALOG_SPACE: &str = "catalog";
+
+static CAPPED_WRITES: Mutex<()> = Mutex::new(());
+
+/// Limits of a capped collection; at least one must be set
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Cap {
+    pub max_docs: Option<u64>,
+    pub max_bytes: Option<u64>,
+}
+
+/// Catalog record: the limits and the running tally
+#[derive(Debug, Clone, Copy, Default)]
+struct Record {
+    cap: Cap,
+    next: u64,
+    documents: u64,
+    bytes: u64,
+}
+
+/// Make `collection` capped. It must be empty; re-capping an empty capped
+/// collection replaces its limits.
+pub fn create_capped_collection<S: Storage + ?Sized>(storage: &S, collection: &str, cap: Cap) -> Result<()> {
+    if cap.max_docs.is_none() && cap.max_bytes.is_none() {
+        return Err(DbError::Invalid("a capped collection needs max_docs or max_bytes".into()));
+    }
+    if cap.max_docs == Some(0) || cap.max_bytes == Some(0) {
+        return Err(DbError::Invalid("capped collection limits must be positive".into()));
+    }
+    if crate::timeseries::timeseries(storage, collection)?.is_some() {
+        return Err(DbError::Invalid(format!("{} is a time-series collection", collection)));
+    }
+    let _serial = lock();
+    let prefix = format!("doc/{}/", collection).into_bytes();
+    if !storage.scan_prefix_page(&Space(crate::DATA_SPACE.into()), &prefix, None, 1)?.is_empty() {
+        return Err(DbError::Conflict(format!("collection {} already has documents", collection)));
+    }
+    crate::create_collection(storage, collection)?;
+    let next = read(storage, collection)?.map(|r| r.next).unwrap_or(0);
+    write(storage, collection, &Record { cap, next, ..Default::default() })
+}
+
+/// Limits of `collection`; `None` if it isn't capped
+pub fn cap<S: Storage + ?Sized>(storage: &S, collection: &str) -> Result<Option<Cap>> {
+    Ok(read(storage, collection)?.map(|r| r.cap))
+}
+
+/// A write to a capped collection in progress; holds the write lock
+pub(crate) struct Capped {
+    record: Record,
+    /// Document and index writes held back to be committed with the tally
+    writes: WriteSet,
+    _serial: MutexGuard<'static, ()>,
+}
+
+/// Lock `collection` for a write if it is capped
+pub(crate) fn guard<S: Storage + ?Sized>(storage: &S, collection: &str) -> Result<Option<Capped>> {
+    if read(storage, collection)?.is_none() {
+        return Ok(None);
+    }
+    let serial = lock();
+    // Re-read under the lock: the tally may have moved while we waited
+    Ok(read(storage, collection)?.map(|record| Capped { record, writes: WriteSet::new(), _serial: serial }))
+}
+
+impl Capped {
+    /// Id for the next inserted document
+    pub(crate) fn next_id(&mut self) -> String {
+        let id = format!("{:016x}", self.record.next);
+        self.record.next += 1;
+        id
+    }
+
+    /// Reject a new document of `len` bytes that could never fit
+    pub(crate) fn check_fits(&self, collection: &str, len: usize) -> Result<()> {
+        match self.record.cap.max_bytes {
+            Some(max) if len as u64 > max => Err(DbError::Invalid(format!(
+                "document of {} bytes exceeds capped collection {} ({} bytes)", len, collection, max
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Hold back document `key` going from `old` bytes to `new` (either may be
+    /// absent) and its index `entries` for `commit`, and account for it in the tally
+    pub(crate) fn stage(&mut self, key: Vec<u8>, old: Option<usize>, new: Option<Vec<u8>>, entries: EntryChanges) {
+        let r = &mut self.record;
+        if let Some(len) = old {
+            r.documents = r.documents.saturating_sub(1);
+            r.bytes = r.bytes.saturating_sub(len as u64);
+        }
+        if let Some(v) = &new {
+            r.documents += 1;
+            r.bytes += v.len() as u64;
+        }
+        let (gone, added) = entries;
+        let space = Space(index::INDEX_SPACE.into());
+        for k in gone {
+            self.writes.insert((space.clone(), k), None);
+        }
+        for k in added {
+            self.writes.insert((space.clone(), k), Some(Vec::new()));
+        }
+        self.writes.insert((Space(crate::DATA_SPACE.into()), key), new);
+    }
+
+    /// What document `key` holds once the staged writes are committed; `None` if
+    /// they don't touch it
+    pub(crate) fn staged(&self, key: &[u8]) -> Option<Option<&[u8]>> {
+        self.writes.get(&(Space(crate::DATA_SPACE.into()), key.to_vec())).map(Option::as_deref)
+    }
+
+    /// Whether the tally is over a limit
+    pub(crate) fn over(&self) -> bool {
+        let r = &self.record;
+        r.cap.max_docs.is_some_and(|max| r.documents > max) || r.cap.max_bytes.is_some_and(|max| r.bytes > max)
+    }
+
+    /// Commit the staged writes and the tally in one atomic write
+    pub(crate) fn commit<S: Storage + ?Sized>(&mut self, storage: &S, collection: &str) -> Result<()> {
+        let mut writes = std::mem::take(&mut self.writes);
+        writes.insert((Space(CATALOG_SPACE.into()), key(collection)), Some(encode(&self.record)));
+        storage.commit_writes(&writes, next_timestamp())
+    }
+}
+
+fn lock() -> MutexGuard<'static, ()> {
+    CAPPED_WRITES.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn key(collection: &str) -> Vec<u8> {
+    format!("capped/{}", collection).into_bytes()
+}
+
+fn read<S: Storage + ?Sized>(storage: &S, collection: &str) -> Result<Option<Record>> {
+    let Some(bytes) = storage.get(&Space(CATALOG_SPACE.into()), &key(collection))? else { return Ok(None) };
+    let v: Json = serde_json::from_slice(&bytes).map_err(|e| DbError::Corruption(format!("capped/{}: {}", collection, e)))?;
+    let n = |field: &str| v.get(field).and_then(Json::as_u64);
+    Ok(Some(Record {
+        cap: Cap { max_docs: n("max_docs"), max_bytes: n("max_bytes") },
+        next: n("next").unwrap_or(0),
+        documents: n("documents").unwrap_or(0),
+        bytes: n("bytes").unwrap_or(0),
+    }))
+}
+
+fn write<S: Storage + ?Sized>(storage: &S, collection: &str, r: &Record) -> Result<()> {
+    storage.put(&Space(CATALOG_SPACE.into()), key(collection), encode(r))
+}
+
+fn encode(r: &Record) -> Vec<u8> {
+    let v = serde_json::json!({
+        "max_docs": r.cap.max_docs,
+        "max_bytes": r.cap.max_bytes,
+        "next": r.next,
+        "documents": r.documents,
+        "bytes": r.bytes,
+    });
+    serde_json::to_vec(&v).unwrap()
+}
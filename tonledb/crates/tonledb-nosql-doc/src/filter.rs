@@ -0,0 +1,187 @@
+//! Filter expressions for document queries (`/doc/:col/query`) and watches.
+//!
+//! A filter is a JSON object. Plain fields test equality; a field mapped to an
+//! operator object applies each operator:
+//!
+//! ```text
+//! { "status": "open", "priority": { "$gte": 2, "$lt": 5 } }
+//! { "$or": [ { "owner": "ann" }, { "tags": { "$contains": "urgent" } } ] }
+//! ```
+//!
+//! Supported operators: `$eq`, `$ne`, `$gt`, `$gte`, `$lt`, `$lte`, `$in`, `$nin`,
+//! `$contains`, `$exists`, `$elemMatch`, `$and`, `$or`. As in MongoDB, equality against an array
+//! field (plain values, `$eq`, `$in`, and their negations) matches when the array
+//! equals the value or holds an element equal to it; `$contains` spells out that
+//! same test. `{"$exists": false}` matches documents without the field.
+//!
+//! `$elemMatch` matches an array field with an element satisfying its sub-filter:
+//! a filter over the element's fields, or operators applied to the element itself:
+//!
+//! ```text
+//! { "items": { "$elemMatch": { "sku": "A1", "qty": { "$gte": 2 } } } }
+//! { "scores": { "$elemMatch": { "$gte": 80, "$lt": 90 } } }
+//! ```
+//!
+//! Field names are dotted paths into nested objects, with numeric segments
+//! indexing arrays: `{ "address.city": "Oslo", "lines.0.sku": "A1" }`.
+
+use std::cmp::Ordering;
+use serde_json::Value as Json;
+use tonledb_core::numbers;
+use tonledb_core::{DbError, Result};
+
+/// A parsed filter expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    /// Every sub-filter must match (an empty list matches everything)
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Field { field: String, op: Cmp, value: Json },
+    /// Some element of the array at `field` matches `filter`
+    ElemMatch { field: String, filter: Box<Filter> },
+}
+
+/// Comparison applied to one field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cmp {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    In,
+    Nin,
+    Contains,
+    Exists,
+}
+
+impl Filter {
+    /// Filter that matches every document
+    pub fn all() -> Self {
+        Filter::And(Vec::new())
+    }
+
+    /// Parse a filter from its JSON form
+    pub fn parse(expr: &Json) -> Result<Self> {
+        let obj = expr.as_object().ok_or_else(|| DbError::Invalid("filter must be a JSON object".into()))?;
+        let mut clauses = Vec::new();
+        for (key, val) in obj {
+            match key.as_str() {
+                "$and" | "$or" => {
+                    let subs = val.as_array()
+                        .ok_or_else(|| DbError::Invalid(format!("{} expects an array of filters", key)))?
+                        .iter()
+                        .map(Filter::parse)
+                        .collect::<Result<Vec<_>>>()?;
+                    clauses.push(if key == "$and" { Filter::And(subs) } else { Filter::Or(subs) });
+                }
+                k if k.starts_with('$') => return Err(DbError::Invalid(format!("unknown filter operator {}", k))),
+                field => clauses.extend(parse_field(field, val)?),
+            }
+        }
+        Ok(if clauses.len() == 1 { clauses.pop().unwrap() } else { Filter::And(clauses) })
+    }
+
+    /// Does `doc` satisfy this filter?
+    pub fn matches(&self, doc: &Json) -> bool {
+        match self {
+            Filter::And(subs) => subs.iter().all(|f| f.matches(doc)),
+            Filter::Or(subs) => subs.iter().any(|f| f.matches(doc)),
+            Filter::Field { field, op, value } => {
+                let actual = lookup(doc, field);
+                match op {
+                    Cmp::Eq => holds(actual, value),
+                    Cmp::Ne => !holds(actual, value),
+                    Cmp::In => value.as_array().is_some_and(|vs| vs.iter().any(|v| holds(actual, v))),
+                    Cmp::Nin => value.as_array().is_some_and(|vs| !vs.iter().any(|v| holds(actual, v))),
+                    Cmp::Exists => actual.is_some() == value.as_bool().unwrap_or(false),
+                    Cmp::Contains => holds(actual, value),
+                    Cmp::Gt => compare(actual, value) == Some(Ordering::Greater),
+                    Cmp::Gte => matches!(compare(actual, value), Some(Ordering::Greater | Ordering::Equal)),
+                    Cmp::Lt => compare(actual, value) == Some(Ordering::Less),
+                    Cmp::Lte => matches!(compare(actual, value), Some(Ordering::Less | Ordering::Equal)),
+                }
+            }
+            Filter::ElemMatch { field, filter } => match lookup(doc, field) {
+                Some(Json::Array(items)) => items.iter().any(|item| filter.matches(item)),
+                _ => false,
+            },
+        }
+    }
+}
+
+fn parse_field(field: &str, val: &Json) -> Result<Vec<Filter>> {
+    // An object whose keys are all operators is an operator object; anything else is an equality value
+    let ops = match val.as_object() {
+        Some(o) if !o.is_empty() && o.keys().all(|k| k.starts_with('$')) => o,
+        _ => return Ok(vec![Filter::Field { field: field.to_string(), op: Cmp::Eq, value: val.clone() }]),
+    };
+    ops.iter()
+        .map(|(name, value)| {
+            if name == "$elemMatch" {
+                let filter = Box::new(element_filter(value)?);
+                return Ok(Filter::ElemMatch { field: field.to_string(), filter });
+            }
+            let op = match name.as_str() {
+                "$eq" => Cmp::Eq,
+                "$ne" => Cmp::Ne,
+                "$gt" => Cmp::Gt,
+                "$gte" => Cmp::Gte,
+                "$lt" => Cmp::Lt,
+                "$lte" => Cmp::Lte,
+                "$in" if value.is_array() => Cmp::In,
+                "$nin" if value.is_array() => Cmp::Nin,
+                "$contains" => Cmp::Contains,
+                "$exists" if value.is_boolean() => Cmp::Exists,
+                "$in" | "$nin" => return Err(DbError::Invalid(format!("{} on {} expects an array", name, field))),
+                "$exists" => return Err(DbError::Invalid(format!("$exists on {} expects true or false", field))),
+                other => return Err(DbError::Invalid(format!("unknown filter operator {}", other))),
+            };
+            Ok(Filter::Field { field: field.to_string(), op, value: value.clone() })
+        })
+        .collect()
+}
+
+/// Filter over one array element: operators apply to the element itself
+/// (`{"$gt": 1}`), anything else is a filter over its fields
+pub(crate) fn element_filter(spec: &Json) -> Result<Filter> {
+    let obj = spec.as_object().ok_or_else(|| DbError::Invalid("$elemMatch expects an object".into()))?;
+    if !obj.is_empty() && obj.keys().all(|k| k.starts_with('$') && k != "$and" && k != "$or") {
+        let mut clauses = parse_field("", spec)?;
+        return Ok(if clauses.len() == 1 { clauses.pop().unwrap() } else { Filter::And(clauses) });
+    }
+    Filter::parse(spec)
+}
+
+/// Whether `actual` equals `expected` or, being an array, has an element equal to it
+fn holds(actual: Option<&Json>, expected: &Json) -> bool {
+    match actual {
+        Some(a) if numbers::values_equal(a, expected) => true,
+        Some(Json::Array(items)) => items.iter().any(|a| numbers::values_equal(a, expected)),
+        _ => false,
+    }
+}
+
+/// The value at dotted `path` in `doc`: object keys, or array positions for numeric
+/// segments. `None` when any step is missing; the empty path is `doc` itself.
+pub fn lookup<'a>(doc: &'a Json, path: &str) -> Option<&'a Json> {
+    if path.is_empty() {
+        return Some(doc);
+    }
+    path.split('.').try_fold(doc, |v, seg| match v {
+        Json::Object(map) => map.get(seg),
+        Json::Array(items) => seg.parse::<usize>().ok().and_then(|i| items.get(i)),
+        _ => None,
+    })
+}
+
+/// Order two JSON values of the same kind; mixed kinds don't compare
+fn compare(actual: Option<&Json>, expected: &Json) -> Option<Ordering> {
+    match (actual?, expected) {
+        (Json::Number(a), Json::Number(b)) => numbers::cmp_numbers(a, b),
+        (Json::String(a), Json::String(b)) => Some(a.cmp(b)),
+        (Json::Bool(a), Json::Bool(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
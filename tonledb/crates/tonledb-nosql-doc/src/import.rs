@@ -0,0 +1,146 @@
+//! Loading a collection from JSON Lines, the way `/doc/:col/export` wrote it.
+//!
+//! Each non-blank line is either an export record `{"cursor": .., "row": doc}` or a
+//! bare document. A document keeps its `_id` (one without gets a generated id);
+//! unless `upsert` is set, an `_id` that already exists fails the import with
+//! `DbError::Conflict`. Documents are written `batch_size` at a time, each batch in
+//! one transaction, so a failed import leaves whole batches behind: `progress` is
+//! called after every committed batch and its `lines` tells where to resume.
+//!
+//! Documents keep the `_created_at`/`_updated_at` they carry, so a collection
+//! with write timestamps (see `crate::set_write_timestamps`) comes back as it was
+//! exported; only documents without them are stamped with the time of the import.
+//!
+//! Capped and time-series collections assign their own ids and don't take part in
+//! `txn`, so their documents are inserted one by one under new ids; `upsert`
+//! doesn't apply to them.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use serde_json::Value as Json;
+use tonledb_core::{deadline, DbError, Result, Storage};
+use crate::{capped, timeseries, txn};
+
+/// How `import_collection_jsonl` writes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportOptions {
+    /// Documents per transaction
+    pub batch_size: usize,
+    /// Replace documents whose `_id` already exists instead of failing
+    pub upsert: bool,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self { batch_size: 1000, upsert: false }
+    }
+}
+
+/// How far an import got
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ImportProgress {
+    /// Lines of the file consumed, blank ones included
+    pub lines: usize,
+    /// Documents written
+    pub imported: usize,
+    /// Of those, documents that replaced an existing one
+    pub replaced: usize,
+}
+
+/// Import the JSON Lines file at `path` into `collection`; see the module docs
+pub fn import_collection_jsonl<S, P, F>(storage: &S, collection: &str, path: P, opts: &ImportOptions, mut progress: F) -> Result<ImportProgress>
+where
+    S: Storage + ?Sized,
+    P: AsRef<Path>,
+    F: FnMut(&ImportProgress),
+{
+    if opts.batch_size == 0 {
+        return Err(DbError::Invalid("batch_size must be positive".into()));
+    }
+    crate::not_a_view(storage, collection)?;
+    let one_by_one = capped::cap(storage, collection)?.is_some() || timeseries::timeseries(storage, collection)?.is_some();
+    if one_by_one && opts.upsert {
+        return Err(DbError::Invalid(format!("{} assigns its own ids; it can't be imported with upsert", collection)));
+    }
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|e| DbError::Storage(format!("open {}: {}", path.display(), e)))?;
+
+    let mut done = ImportProgress::default();
+    let mut batch: Vec<Json> = Vec::with_capacity(opts.batch_size);
+    let mut pending_lines = 0;
+    for line in BufReader::new(file).lines() {
+        deadline::check()?;
+        let line = line.map_err(|e| DbError::Storage(format!("read {}: {}", path.display(), e)))?;
+        pending_lines += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let lineno = done.lines + pending_lines;
+        let mut doc: Json = serde_json::from_str(&line)
+            .map_err(|e| DbError::Invalid(format!("{} line {}: {}", path.display(), lineno, e)))?;
+        if let Some(row) = export_row(&mut doc) {
+            doc = row;
+        }
+        if !doc.is_object() {
+            return Err(DbError::Invalid(format!("{} line {}: documents must be objects", path.display(), lineno)));
+        }
+        batch.push(doc);
+        if batch.len() == opts.batch_size {
+            write_batch(storage, collection, &mut batch, opts.upsert, one_by_one, &mut done)?;
+            done.lines += std::mem::take(&mut pending_lines);
+            progress(&done);
+        }
+    }
+    if !batch.is_empty() || pending_lines > 0 {
+        write_batch(storage, collection, &mut batch, opts.upsert, one_by_one, &mut done)?;
+        done.lines += pending_lines;
+        progress(&done);
+    }
+    Ok(done)
+}
+
+/// The document of a `{"cursor", "row"}` export record; `None` for a bare document
+fn export_row(line: &mut Json) -> Option<Json> {
+    let obj = line.as_object_mut()?;
+    if obj.len() != 2 || !obj.contains_key("cursor") {
+        return None;
+    }
+    obj.remove("row")
+}
+
+fn write_batch<S: Storage + ?Sized>(storage: &S, collection: &str, batch: &mut Vec<Json>, upsert: bool, one_by_one: bool, done: &mut ImportProgress) -> Result<()> {
+    if one_by_one {
+        for mut doc in batch.drain(..) {
+            if let Some(obj) = doc.as_object_mut() {
+                obj.remove("_id");
+            }
+            crate::insert_loaded(storage, collection, doc)?;
+            done.imported += 1;
+        }
+        return Ok(());
+    }
+    let txn = txn::begin(storage)?;
+    let mut replaced = 0;
+    for doc in batch.iter() {
+        match doc.get("_id") {
+            Some(Json::String(id)) => {
+                if !upsert && txn.get(collection, id)?.is_some() {
+                    return Err(DbError::Conflict(format!("document {} already exists in {}", id, collection)));
+                }
+                if txn.load(collection, id, doc.clone())? {
+                    replaced += 1;
+                }
+            }
+            Some(other) => return Err(DbError::Invalid(format!("_id must be a string, got {}", other))),
+            None => {
+                txn.load(collection, &nanoid::nanoid!(), doc.clone())?;
+            }
+        }
+    }
+    txn.commit()?;
+    done.imported += batch.len();
+    done.replaced += replaced;
+    batch.clear();
+    Ok(())
+}
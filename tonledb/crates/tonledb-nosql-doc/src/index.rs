@@ -0,0 +1,291 @@
+//! Secondary indexes on document fields.
+//!
+//! An index on `field` of a collection is registered in the catalog under
+//! `docidx/<collection>/<field>` and keeps one entry per (value, document) in
+//! `Space("doc_index")`: key = `<collection>/<field>\0<value>\0<id>`, empty value.
+//! Indexes are multikey: when the field holds an array, each distinct element gets
+//! its own entry, so `{"tags": {"$contains": "x"}}` finds documents by element.
+//! Numbers are keyed by their canonical value, so `1` and `1.0` share entries.
+//! Like filters, `field` may be a dotted path into nested objects.
+//!
+//! Entries are maintained by every write in this crate; `query` picks an index
+//! for an `$eq`, `$in` or `$contains` clause and re-checks the whole filter on
+//! the candidates.
+//!
+//! A unique index (`create_unique_index`, catalog value `unique`) also rejects any
+//! write that would give a second document one of its values, with
+//! `DbError::Conflict`. For array fields no value may appear in two documents.
+//! Writes to collections with a unique index are serialised in-process, so two
+//! concurrent inserts cannot both claim the same value.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Mutex, MutexGuard};
+use serde_json::Value as Json;
+use tonledb_core::numbers;
+use tonledb_core::transaction::WriteSet;
+use tonledb_core::{DbError, Result, Space, Storage};
+use crate::filter::{self, Cmp, Filter};
+
+pub(crate) const INDEX_SPACE: &str = "doc_index";
+const CATALOG_SPACE: &str = "catalog";
+const UNIQUE: &[u8] = b"unique";
+
+/// Held from a unique check until the write's entries are in place
+static UNIQUE_WRITES: Mutex<()> = Mutex::new(());
+
+/// Index `field` of `collection`, backfilling entries for existing documents.
+/// Idempotent; returns the number of entries written.
+pub fn create_index<S: Storage + ?Sized>(storage: &S, collection: &str, field: &str) -> Result<usize> {
+    build(storage, collection, field, false)
+}
+
+/// `create_index` that also enforces uniqueness of the field's values. Fails with
+/// `DbError::Conflict`, creating nothing, if existing documents already share one.
+pub fn create_unique_index<S: Storage + ?Sized>(storage: &S, collection: &str, field: &str) -> Result<usize> {
+    build(storage, collection, field, true)
+}
+
+/// Whether `field` of `collection` has a unique index
+pub fn is_unique<S: Storage + ?Sized>(storage: &S, collection: &str, field: &str) -> Result<bool> {
+    Ok(storage.get(&Space(CATALOG_SPACE.into()), &meta_key(collection, field))?.as_deref() == Some(UNIQUE))
+}
+
+fn build<S: Storage + ?Sized>(storage: &S, collection: &str, field: &str, unique: bool) -> Result<usize> {
+    let _serial = unique.then(lock_writes);
+    let mut entries = BTreeSet::new();
+    let mut owners = BTreeMap::new();
+    let prefix = format!("doc/{}/", collection).into_bytes();
+    for (k, v) in storage.scan_prefix(&Space(crate::DATA_SPACE.into()), &prefix)? {
+        let doc = crate::decode(&k, &v)?;
+        let id = String::from_utf8_lossy(&k[prefix.len()..]).into_owned();
+        for value in field_values(&doc, field) {
+            let key = value_prefix(collection, field, value);
+            if unique && owners.insert(key.clone(), id.clone()).is_some_and(|other| other != id) {
+                return Err(duplicate(collection, field, value));
+            }
+            entries.insert([key, id.as_bytes().to_vec()].concat());
+        }
+    }
+    let written = entries.len();
+    for key in entries {
+        storage.put(&space(), key, Vec::new())?;
+    }
+    let marker = if unique { UNIQUE.to_vec() } else { Vec::new() };
+    storage.put(&Space(CATALOG_SPACE.into()), meta_key(collection, field), marker)?;
+    Ok(written)
+}
+
+/// Remove the index on `field` and all its entries
+pub fn drop_index<S: Storage + ?Sized>(storage: &S, collection: &str, field: &str) -> Result<()> {
+    storage.del(&Space(CATALOG_SPACE.into()), &meta_key(collection, field))?;
+    storage.delete_prefix(&space(), &field_prefix(collection, field))?;
+    Ok(())
+}
+
+/// Indexed fields of `collection`, sorted
+pub fn indexed_fields<S: Storage + ?Sized>(storage: &S, collection: &str) -> Result<Vec<String>> {
+    let prefix = meta_key(collection, "");
+    Ok(storage
+        .scan_prefix(&Space(CATALOG_SPACE.into()), &prefix)?
+        .map(|(k, _)| String::from_utf8_lossy(&k[prefix.len()..]).into_owned())
+        .collect())
+}
+
+/// Size of one index
+#[derive(Debug, Clone, PartialEq, Eq, Default, serde::Serialize)]
+pub struct IndexStats {
+    pub field: String,
+    pub unique: bool,
+    /// One per (value, document)
+    pub entries: usize,
+    /// Bytes of entry keys
+    pub bytes: u64,
+}
+
+/// Count the entries of the index on `field`
+pub fn index_stats<S: Storage + ?Sized>(storage: &S, collection: &str, field: &str) -> Result<IndexStats> {
+    let mut stats = IndexStats { field: field.to_string(), unique: is_unique(storage, collection, field)?, ..Default::default() };
+    for (k, _) in storage.scan_prefix(&space(), &field_prefix(collection, field))? {
+        stats.entries += 1;
+        stats.bytes += k.len() as u64;
+    }
+    Ok(stats)
+}
+
+/// Drop every index of `collection` (used when the collection is dropped)
+pub(crate) fn drop_all<S: Storage + ?Sized>(storage: &S, collection: &str) -> Result<()> {
+    for field in indexed_fields(storage, collection)? {
+        storage.del(&Space(CATALOG_SPACE.into()), &meta_key(collection, &field))?;
+    }
+    storage.delete_prefix(&space(), format!("{}/", collection).as_bytes())?;
+    Ok(())
+}
+
+/// Add to `writes` the moves of every index of collection `from` (definitions and
+/// entries) to collection `to`, and of entries still written under `from` for
+/// indexes already moved
+pub(crate) fn rename_writes<S: Storage + ?Sized>(storage: &S, from: &str, to: &str, writes: &mut WriteSet) -> Result<()> {
+    let catalog = Space(CATALOG_SPACE.into());
+    let mut fields: BTreeSet<String> = indexed_fields(storage, to)?.into_iter().collect();
+    for field in indexed_fields(storage, from)? {
+        let marker = storage.get(&catalog, &meta_key(from, &field))?.unwrap_or_default();
+        writes.insert((catalog.clone(), meta_key(to, &field)), Some(marker));
+        writes.insert((catalog.clone(), meta_key(from, &field)), None);
+        fields.insert(field);
+    }
+    for field in fields {
+        let (old_prefix, new_prefix) = (field_prefix(from, &field), field_prefix(to, &field));
+        for (k, _) in storage.scan_prefix(&space(), &old_prefix)? {
+            writes.insert((space(), [new_prefix.as_slice(), &k[old_prefix.len()..]].concat()), Some(Vec::new()));
+            writes.insert((space(), k), None);
+        }
+    }
+    Ok(())
+}
+
+/// Check `doc`, about to be written as document `id`, against the unique indexes
+/// of `collection`. When there are any, the returned guard serialises the write:
+/// hold it until `update_entries` has run.
+pub(crate) fn check_unique<S: Storage + ?Sized>(storage: &S, collection: &str, id: &str, doc: &Json) -> Result<Option<MutexGuard<'static, ()>>> {
+    check_unique_all(storage, &[(collection, id, Some(doc))])
+}
+
+/// `check_unique` for several writes at once, `(collection, id, new document)` with
+/// `None` for deletes: values must be unique among the new documents, and may
+/// only be taken in storage by documents the writes replace.
+pub(crate) fn check_unique_all<S: Storage + ?Sized>(storage: &S, writes: &[(&str, &str, Option<&Json>)]) -> Result<Option<MutexGuard<'static, ()>>> {
+    let catalog = Space(CATALOG_SPACE.into());
+    let rewritten: BTreeSet<(&str, &[u8])> = writes.iter().map(|&(c, id, _)| (c, id.as_bytes())).collect();
+    let mut claimed: BTreeMap<Vec<u8>, &str> = BTreeMap::new();
+    let mut guard = None;
+    for &(collection, id, doc) in writes {
+        let Some(doc) = doc else { continue };
+        for field in indexed_fields(storage, collection)? {
+            if storage.get(&catalog, &meta_key(collection, &field))?.as_deref() != Some(UNIQUE) {
+                continue;
+            }
+            let _ = guard.get_or_insert_with(lock_writes);
+            for value in field_values(doc, &field) {
+                let prefix = value_prefix(collection, &field, value);
+                let mut owners = storage.scan_prefix(&space(), &prefix)?;
+                if owners.any(|(k, _)| !rewritten.contains(&(collection, &k[prefix.len()..])))
+                    || claimed.insert(prefix, id).is_some_and(|other| other != id)
+                {
+                    return Err(duplicate(collection, &field, value));
+                }
+            }
+        }
+    }
+    Ok(guard)
+}
+
+fn lock_writes() -> MutexGuard<'static, ()> {
+    UNIQUE_WRITES.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Bring the entries of document `id` from `old` to `new` (either may be absent)
+pub(crate) fn update_entries<S: Storage + ?Sized>(storage: &S, collection: &str, id: &str, old: Option<&Json>, new: Option<&Json>) -> Result<()> {
+    let (gone, added) = entry_changes(storage, collection, id, old, new)?;
+    for key in gone {
+        storage.del(&space(), &key)?;
+    }
+    for key in added {
+        storage.put(&space(), key, Vec::new())?;
+    }
+    Ok(())
+}
+
+/// Entry keys to remove and to add
+pub(crate) type EntryChanges = (Vec<Vec<u8>>, Vec<Vec<u8>>);
+
+/// Entries to remove and to add in `Space("doc_index")` for `update_entries`
+pub(crate) fn entry_changes<S: Storage + ?Sized>(storage: &S, collection: &str, id: &str, old: Option<&Json>, new: Option<&Json>) -> Result<EntryChanges> {
+    let (mut gone, mut added) = (Vec::new(), Vec::new());
+    for field in indexed_fields(storage, collection)? {
+        let before: BTreeSet<Vec<u8>> = old.map(|d| entry_keys(collection, &field, id, d)).unwrap_or_default();
+        let after: BTreeSet<Vec<u8>> = new.map(|d| entry_keys(collection, &field, id, d)).unwrap_or_default();
+        gone.extend(before.difference(&after).cloned());
+        added.extend(after.difference(&before).cloned());
+    }
+    Ok((gone, added))
+}
+
+/// Ids of documents that may match `filter`, from an index on one of its clauses;
+/// `None` when no index applies and the collection has to be scanned
+pub fn candidates<S: Storage + ?Sized>(storage: &S, collection: &str, filter: &Filter) -> Result<Option<BTreeSet<String>>> {
+    let fields = indexed_fields(storage, collection)?;
+    let clauses = match filter {
+        Filter::And(subs) => subs.iter().collect::<Vec<_>>(),
+        f => vec![f],
+    };
+    for clause in clauses {
+        let Filter::Field { field, op, value } = clause else { continue };
+        if !fields.contains(field) {
+            continue;
+        }
+        let values: Vec<&Json> = match op {
+            // Equality with a whole array can't be answered from element entries
+            Cmp::Eq if !value.is_array() => vec![value],
+            Cmp::Contains => vec![value],
+            Cmp::In => match value.as_array() {
+                Some(vs) if !vs.iter().any(Json::is_array) => vs.iter().collect(),
+                _ => continue,
+            },
+            _ => continue,
+        };
+        let mut ids = BTreeSet::new();
+        for v in values {
+            let prefix = value_prefix(collection, field, v);
+            for (k, _) in storage.scan_prefix(&space(), &prefix)? {
+                ids.insert(String::from_utf8_lossy(&k[prefix.len()..]).into_owned());
+            }
+        }
+        return Ok(Some(ids));
+    }
+    Ok(None)
+}
+
+// ---------- helpers ----------
+
+fn space() -> Space {
+    Space(INDEX_SPACE.into())
+}
+
+fn meta_key(collection: &str, field: &str) -> Vec<u8> {
+    format!("docidx/{}/{}", collection, field).into_bytes()
+}
+
+fn field_prefix(collection: &str, field: &str) -> Vec<u8> {
+    format!("{}/{}\0", collection, field).into_bytes()
+}
+
+fn value_prefix(collection: &str, field: &str, value: &Json) -> Vec<u8> {
+    [field_prefix(collection, field), encode_value(value), vec![0]].concat()
+}
+
+/// Index keys of `doc` for `field`: one per distinct array element, or one for a scalar
+fn entry_keys(collection: &str, field: &str, id: &str, doc: &Json) -> BTreeSet<Vec<u8>> {
+    field_values(doc, field).into_iter().map(|v| [value_prefix(collection, field, v), id.as_bytes().to_vec()].concat()).collect()
+}
+
+/// Values `field` of `doc` is indexed under: each array element, or the scalar
+fn field_values<'a>(doc: &'a Json, field: &str) -> Vec<&'a Json> {
+    match filter::lookup(doc, field) {
+        None => Vec::new(),
+        Some(Json::Array(items)) => items.iter().collect(),
+        Some(v) => vec![v],
+    }
+}
+
+fn duplicate(collection: &str, field: &str, value: &Json) -> DbError {
+    DbError::Conflict(format!("duplicate value {} for unique field {} of {}", value, field, collection))
+}
+
+/// Value as key bytes. JSON text escapes NUL, so the separator stays unambiguous;
+/// numbers use their canonical form so equal values share entries.
+pub(crate) fn encode_value(value: &Json) -> Vec<u8> {
+    match value {
+        Json::Number(n) => format!("n:{}", numbers::canonical(n).unwrap_or_else(|| n.to_string())).into_bytes(),
+        other => other.to_string().into_bytes(),
+    }
+}
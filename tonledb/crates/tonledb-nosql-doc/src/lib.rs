@@ -5,15 +5,95 @@
 //! - Documents under `Space("data")`: key = `doc/<collection>/<id>` -> JSON bytes
 //!
 //! The API below provides basic CRUD, listing, prefix scans, and simple
-//! filter queries (client-side predicate). TTL is supported by convention:
-//! if a document contains a numeric field `_ttl_epoch_ms`, callers can
-//! decide to ignore expired docs (option here).
+//! filter queries (client-side predicate). Documents inserted with a TTL are
+//! written with `Storage::put_with_ttl`, so the engine removes them once they
+//! expire; they also carry a numeric `_ttl_epoch_ms` field so readers can ignore
+//! expired docs before the sweeper gets to them (option here).
+//!
+//! Every write publishes a change event (table = collection, key = id) to the
+//! global changefeed manager; see `watch` for filtered subscriptions.
+//! Multi-stage queries, including cross-collection joins, live in `aggregate`;
+//! nested updates (JSON Patch and deep merge) in `patch`, update operators
+//! (`$set`, `$inc`, `$push`, `$pull`, ...) in `update`; `txn` writes several
+//! documents, in any collections, atomically. `import` loads a collection back
+//! from a JSON Lines export.
+//! Fields can be indexed (multikey for arrays, see `index`); `query` uses an
+//! index when the filter allows it. Writes that would duplicate a value of a
+//! unique index fail with `DbError::Conflict`. Capped collections (see `capped`)
+//! evict their oldest documents to stay within a count or size; time-series
+//! collections (see `timeseries`) key documents by time for range queries.
+//! Views (see `view`) are named, read-only filters and projections over a
+//! collection; writes to a view's name fail.
+//!
+//! Writes that read a document before writing it (updates, replaces, deletes and
+//! inserts under a given id) hold a lock on it meanwhile, so two of them in one
+//! process take turns rather than both starting from its old value.
+//!
+//! Writes fail with `DbError::Invalid` when the encoded document is larger than
+//! `max_doc_bytes` (unlimited by default). Stored bytes that don't decode as JSON
+//! are reported as `DbError::Corruption` naming their key, never read as `null`.
+//!
+//! Collections can have writes stamp `_created_at` and `_updated_at` from the
+//! server's clock (`set_write_timestamps`).
+//!
+//! Numbers in written documents follow the number mode of each field (see
+//! `tonledb_core::numbers`): rounded through f64, or kept as exact decimals.
 
-use tonledb_core::{Result, Space, Storage};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use tonledb_core::{deadline, DbError, Result, Space, Storage};
+use tonledb_core::event_sourcing::{ChangeEvent, Operation, EVENT_MANAGER};
+use tonledb_core::numbers;
+use tonledb_core::transaction::{next_timestamp, WriteSet};
 use serde_json::Value as Json;
+use filter::Filter;
+
+pub mod aggregate;
+pub mod capped;
+pub mod filter;
+pub mod import;
+pub mod index;
+pub mod patch;
+pub mod timeseries;
+pub mod txn;
+pub mod update;
+pub mod view;
+pub mod watch;
 
 const CATALOG_SPACE: &str = "catalog";
 const DATA_SPACE: &str = "data";
+/// Catalog entries kept per collection under `<kind>/<name>`: the entry itself,
+/// then its inferred schema and settings
+const CATALOG_ENTRIES: [&str; 6] = ["col", "schema", "numbers/col", "capped", "timeseries", "timestamps"];
+
+/// Largest encoded document writes accept, in bytes; 0 is unlimited
+static MAX_DOC_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// Held by `rename_collection`
+static RENAMES: Mutex<()> = Mutex::new(());
+
+const DOC_LOCK_STRIPES: usize = 64;
+/// Held from reading a document to writing it back (see `lock_doc`)
+static DOC_LOCKS: [Mutex<()>; DOC_LOCK_STRIPES] = [const { Mutex::new(()) }; DOC_LOCK_STRIPES];
+
+/// Lock document `id` of `collection`; documents hashing to the same stripe
+/// share the lock
+fn lock_doc(collection: &str, id: &str) -> MutexGuard<'static, ()> {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    (collection, id).hash(&mut hasher);
+    DOC_LOCKS[hasher.finish() as usize % DOC_LOCK_STRIPES].lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Cap the encoded size of documents written from now on (process-wide); `None`
+/// lifts the cap
+pub fn set_max_doc_bytes(max: Option<usize>) {
+    MAX_DOC_BYTES.store(max.unwrap_or(0), Ordering::Relaxed);
+}
+
+pub fn max_doc_bytes() -> Option<usize> {
+    Some(MAX_DOC_BYTES.load(Ordering::Relaxed)).filter(|&max| max > 0)
+}
 
 /// Create a collection entry in the catalog (idempotent).
 pub fn create_collection<S: Storage + ?Sized>(storage: &S, name: &str) -> Result<()> {
@@ -23,18 +103,156 @@ pub fn create_collection<S: Storage + ?Sized>(storage: &S, name: &str) -> Result
     storage.put(&Space(CATALOG_SPACE.into()), key, serde_json::to_vec(&meta).unwrap())
 }
 
+/// Drop a collection: all its documents (one `delete_prefix`), its catalog entry,
+/// its inferred schema, its number modes, its other settings and its indexes, so a
+/// collection created again under the name starts afresh. Returns the number of
+/// documents removed.
+pub fn drop_collection<S: Storage + ?Sized>(storage: &S, name: &str) -> Result<usize> {
+    let removed = storage.delete_prefix(&Space(DATA_SPACE.into()), format!("doc/{}/", name).as_bytes())?;
+    let catalog = Space(CATALOG_SPACE.into());
+    for kind in CATALOG_ENTRIES {
+        storage.del(&catalog, format!("{}/{}", kind, name).as_bytes())?;
+    }
+    index::drop_all(storage, name)?;
+    Ok(removed)
+}
+
+/// Rename collection `from` to `to`: its documents, catalog entry, inferred schema,
+/// number modes, cap or time-series settings, timestamp setting and indexes move in one atomic write. Fails with `DbError::NotFound`
+/// if `from` has neither documents nor a catalog entry, and `DbError::Conflict` if
+/// `to` has either. Returns the number of documents moved.
+///
+/// Renames run one at a time, so two can't claim the same name. Documents written
+/// to `from` while the move is under way are moved after it, in further atomic
+/// writes, rather than left behind.
+///
+/// No change events are published, and moved documents lose their storage TTL
+/// (their `_ttl_epoch_ms` still hides them once expired).
+pub fn rename_collection<S: Storage + ?Sized>(storage: &S, from: &str, to: &str) -> Result<usize> {
+    let _serial = RENAMES.lock().unwrap_or_else(|e| e.into_inner());
+    let data = Space(DATA_SPACE.into());
+    let catalog = Space(CATALOG_SPACE.into());
+    let exists = |name: &str| -> Result<bool> {
+        Ok(storage.get(&catalog, format!("col/{}", name).as_bytes())?.is_some()
+            || !storage.scan_prefix_page(&data, format!("doc/{}/", name).as_bytes(), None, 1)?.is_empty())
+    };
+    if from == to || !exists(from)? {
+        return Err(DbError::NotFound(format!("collection {}", from)));
+    }
+    if exists(to)? {
+        return Err(DbError::Conflict(format!("collection {} already exists", to)));
+    }
+    let mut moved = 0;
+    let (old_prefix, new_prefix) = (format!("doc/{}/", from), format!("doc/{}/", to));
+    loop {
+        let mut writes = WriteSet::new();
+        for (k, v) in storage.scan_prefix(&data, old_prefix.as_bytes())? {
+            let id = String::from_utf8_lossy(&k[old_prefix.len()..]).into_owned();
+            writes.insert((data.clone(), [new_prefix.as_bytes(), id.as_bytes()].concat()), Some(v));
+            writes.insert((data.clone(), k), None);
+            moved += 1;
+        }
+        for kind in CATALOG_ENTRIES {
+            let old_key = format!("{}/{}", kind, from).into_bytes();
+            if let Some(v) = storage.get(&catalog, &old_key)? {
+                // The catalog entry records the collection's name
+                let v = if kind == "col" { serde_json::to_vec(&serde_json::json!({ "name": to })).unwrap() } else { v };
+                writes.insert((catalog.clone(), format!("{}/{}", kind, to).into_bytes()), Some(v));
+                writes.insert((catalog.clone(), old_key), None);
+            }
+        }
+        index::rename_writes(storage, from, to, &mut writes)?;
+        if writes.is_empty() {
+            return Ok(moved);
+        }
+        storage.commit_writes(&writes, next_timestamp())?;
+    }
+}
+
+/// Names of the collections that have a catalog entry or any documents, sorted
+pub fn list_collections<S: Storage + ?Sized>(storage: &S) -> Result<Vec<String>> {
+    let mut names = std::collections::BTreeSet::new();
+    for (k, _) in storage.scan_prefix(&Space(CATALOG_SPACE.into()), b"col/")? {
+        names.insert(String::from_utf8_lossy(&k[4..]).into_owned());
+    }
+    // Read one document per collection, skipping past the rest of its keys (ids
+    // are UTF-8, so no id sorts after a 0xff byte)
+    let data = Space(DATA_SPACE.into());
+    let mut after: Option<Vec<u8>> = None;
+    while let Some((k, _)) = storage.scan_prefix_page(&data, b"doc/", after.as_deref(), 1)?.into_iter().next() {
+        let Some(end) = k[4..].iter().position(|&b| b == b'/') else { break };
+        names.insert(String::from_utf8_lossy(&k[4..4 + end]).into_owned());
+        after = Some([&k[..4 + end + 1], &[0xffu8][..]].concat());
+    }
+    Ok(names.into_iter().collect())
+}
+
 /// Insert a new document and return its generated id (nanoid).
 /// If ttl_seconds is provided, the document will expire after that many seconds.
 pub fn insert_with_ttl<S: Storage + ?Sized>(
     storage: &S, 
     collection: &str, 
-    mut doc: Json, 
+    doc: Json, 
     ttl_seconds: Option<u64>
 ) -> Result<String> {
+    insert_as(storage, collection, None, doc, ttl_seconds, false)
+}
+
+/// Insert a document under `id`, which becomes its `_id`. Fails with
+/// `DbError::Conflict` if the collection already holds one, and with
+/// `DbError::Invalid` for capped and time-series collections, which choose their
+/// own ids.
+pub fn insert_with_id<S: Storage + ?Sized>(storage: &S, collection: &str, id: &str, doc: Json) -> Result<()> {
+    insert_as(storage, collection, Some(id), doc, None, false).map(|_| ())
+}
+
+/// Insert a document loaded from an export under a generated id, keeping the
+/// write timestamps it carries (see `stamp_loaded_times`)
+pub(crate) fn insert_loaded<S: Storage + ?Sized>(storage: &S, collection: &str, doc: Json) -> Result<String> {
+    insert_as(storage, collection, None, doc, None, true)
+}
+
+fn insert_as<S: Storage + ?Sized>(
+    storage: &S,
+    collection: &str,
+    id: Option<&str>,
+    mut doc: Json,
+    ttl_seconds: Option<u64>,
+    loaded: bool,
+) -> Result<String> {
+    not_a_view(storage, collection)?;
+    // Capped collections number their documents so the oldest sort first; time
+    // series key them by time
+    let mut capped = capped::guard(storage, collection)?;
+    let series = timeseries::timeseries(storage, collection)?;
     // ensure an id field (not required but useful)
-    let id = nanoid::nanoid!();
+    let given = id.is_some();
+    // Held until the document is written, so no other insert takes its id meanwhile
+    let _doc = id.filter(|_| capped.is_none() && series.is_none()).map(|id| lock_doc(collection, id));
+    let id = match (&mut capped, &series, id) {
+        (Some(_), _, _) if ttl_seconds.is_some() => {
+            return Err(DbError::Invalid(format!("capped collection {} doesn't take TTLs", collection)));
+        }
+        (Some(_), _, Some(_)) | (None, Some(_), Some(_)) => {
+            return Err(DbError::Invalid(format!("collection {} chooses its own document ids", collection)));
+        }
+        (Some(c), _, None) => c.next_id(),
+        (None, Some(ts), None) => timeseries::doc_id(timeseries::stamp(&mut doc, &ts.time_field)?),
+        (None, None, Some(id)) => {
+            if storage.get(&Space(DATA_SPACE.into()), &doc_key(collection, id))?.is_some() {
+                return Err(DbError::Conflict(format!("{} already has a document {}", collection, id)));
+            }
+            id.to_string()
+        }
+        (None, None, None) => nanoid::nanoid!(),
+    };
+    numbers::normalize_doc(&mut doc, &numbers::column_modes(storage, numbers::Source::Collection(collection))?);
     if let Some(obj) = doc.as_object_mut() {
-        obj.entry("_id".to_string()).or_insert(Json::String(id.clone()));
+        if given {
+            obj.insert("_id".to_string(), Json::String(id.clone()));
+        } else {
+            obj.entry("_id".to_string()).or_insert(Json::String(id.clone()));
+        }
         
         // Add TTL if specified
         if let Some(ttl) = ttl_seconds {
@@ -46,17 +264,54 @@ pub fn insert_with_ttl<S: Storage + ?Sized>(
             obj.insert("_ttl_epoch_ms".to_string(), Json::Number(ttl_epoch_ms.into()));
         }
     }
+    if loaded {
+        stamp_loaded_times(storage, collection, None, &mut doc)?;
+    } else {
+        stamp_times(storage, collection, None, &mut doc)?;
+    }
     let key = doc_key(collection, &id);
-    storage.put(&Space(DATA_SPACE.into()), key, serde_json::to_vec(&doc).unwrap())?;
+    let bytes = encode(collection, &id, &doc)?;
+    if let Some(c) = &capped {
+        c.check_fits(collection, bytes.len())?;
+    }
+    let space = Space(DATA_SPACE.into());
+    let _unique = index::check_unique(storage, collection, &id, &doc)?;
+    if let Some(c) = &mut capped {
+        write_capped(storage, collection, c, &id, None, Some((bytes, &doc)))?;
+        return Ok(id);
+    }
+    match ttl_seconds {
+        Some(ttl) => match storage.put_with_ttl(&space, key.clone(), bytes.clone(), ttl.saturating_mul(1000)) {
+            // Engines without TTL support fall back to the `_ttl_epoch_ms` convention alone
+            Err(DbError::Invalid(_)) => storage.put(&space, key, bytes.clone())?,
+            res => res?,
+        },
+        None => storage.put(&space, key, bytes.clone())?,
+    }
+    index::update_entries(storage, collection, &id, None, Some(&doc))?;
+    publish(Operation::Insert, collection, &id, None, Some(bytes));
+    if series.is_some_and(|ts| ts.retention_ms.is_some()) {
+        timeseries::enforce_retention(storage, collection, now_ms())?;
+    }
     Ok(id)
 }
 
 /// Get a document by id. If `ignore_expired` is true, documents with a
 /// numeric `_ttl_epoch_ms` in the past are returned as `Ok(None)`.
 pub fn get<S: Storage + ?Sized>(storage: &S, collection: &str, id: &str, ignore_expired: bool) -> Result<Option<Json>> {
+    if let Some(view) = view::get_view(storage, collection)? {
+        let narrow = view.narrow(&Filter::all())?;
+        let doc = get_doc(storage, &view.collection, id, ignore_expired)?;
+        return Ok(doc.filter(|d| narrow.matches(d)).map(|d| view.show(d)));
+    }
+    get_doc(storage, collection, id, ignore_expired)
+}
+
+/// `get` from a collection, not a view
+fn get_doc<S: Storage + ?Sized>(storage: &S, collection: &str, id: &str, ignore_expired: bool) -> Result<Option<Json>> {
     match storage.get(&Space(DATA_SPACE.into()), &doc_key(collection, id))? {
         Some(bytes) => {
-            let doc: Json = serde_json::from_slice(&bytes).unwrap_or(Json::Null);
+            let doc = decode(&doc_key(collection, id), &bytes)?;
             if ignore_expired && is_expired(&doc) { return Ok(None); }
             Ok(Some(doc))
         }
@@ -64,17 +319,42 @@ pub fn get<S: Storage + ?Sized>(storage: &S, collection: &str, id: &str, ignore_
     }
 }
 
+/// `DbError::Invalid` if `collection` names a view, which can't be written
+pub(crate) fn not_a_view<S: Storage + ?Sized>(storage: &S, collection: &str) -> Result<()> {
+    if view::get_view(storage, collection)?.is_some() {
+        return Err(DbError::Invalid(format!("{} is a read-only view", collection)));
+    }
+    Ok(())
+}
+
 /// Replace (overwrite) a document by id. Returns `true` if replaced, `false` if missing.
 pub fn replace<S: Storage + ?Sized>(storage: &S, collection: &str, id: &str, mut doc: Json) -> Result<bool> {
+    let mut capped = capped::guard(storage, collection)?;
+    let _doc = lock_doc(collection, id);
     let key = doc_key(collection, id);
     let space = Space(DATA_SPACE.into());
-    if storage.get(&space, &key)?.is_none() {
-        return Ok(false);
-    }
+    let old = match storage.get(&space, &key)? {
+        Some(old) => old,
+        None => return Ok(false),
+    };
+    let old_doc = decode(&key, &old)?;
+    numbers::normalize_doc(&mut doc, &numbers::column_modes(storage, numbers::Source::Collection(collection))?);
     if let Some(obj) = doc.as_object_mut() {
         obj.insert("_id".to_string(), Json::String(id.to_string()));
     }
-    storage.put(&space, key, serde_json::to_vec(&doc).unwrap())?;
+    stamp_times(storage, collection, Some(&old_doc), &mut doc)?;
+    let bytes = encode(collection, id, &doc)?;
+    if let Some(c) = &capped {
+        c.check_fits(collection, bytes.len())?;
+    }
+    let _unique = index::check_unique(storage, collection, id, &doc)?;
+    if let Some(c) = &mut capped {
+        write_capped(storage, collection, c, id, Some((old, Some(&old_doc))), Some((bytes, &doc)))?;
+        return Ok(true);
+    }
+    storage.put(&space, key, bytes.clone())?;
+    index::update_entries(storage, collection, id, Some(&old_doc), Some(&doc))?;
+    publish(Operation::Update, collection, id, Some(old), Some(bytes));
     Ok(true)
 }
 
@@ -87,63 +367,202 @@ pub fn update_merge<S: Storage + ?Sized>(
     patch: Json,
     upsert: bool,
 ) -> Result<bool> {
+    update_with(storage, collection, id, upsert, |base| Ok(merge_json(base, patch)))
+}
+
+/// `update_merge` that merges nested objects too, removing fields set to `null`
+/// (JSON Merge Patch, see `patch::deep_merge`).
+pub fn update_deep_merge<S: Storage + ?Sized>(storage: &S, collection: &str, id: &str, patch: Json, upsert: bool) -> Result<bool> {
+    update_with(storage, collection, id, upsert, |mut base| {
+        patch::deep_merge(&mut base, patch);
+        Ok(base)
+    })
+}
+
+/// Apply a JSON Patch (an array of RFC 6902 operations, see `patch`) to a document.
+/// Returns `false` if the document doesn't exist; a failing operation writes nothing.
+pub fn update_patch<S: Storage + ?Sized>(storage: &S, collection: &str, id: &str, ops: &Json) -> Result<bool> {
+    update_with(storage, collection, id, false, |mut doc| {
+        patch::apply(&mut doc, ops)?;
+        Ok(doc)
+    })
+}
+
+/// Apply update operators (`$set`, `$inc`, `$push`, ..., see `update`) to a
+/// document, starting from `{}` when absent and `upsert`. Returns `false` if the
+/// document doesn't exist (and no upsert); a failing operator writes nothing.
+pub fn update_ops<S: Storage + ?Sized>(storage: &S, collection: &str, id: &str, ops: &Json, upsert: bool) -> Result<bool> {
+    update_with(storage, collection, id, upsert, |mut doc| {
+        update::apply(&mut doc, ops)?;
+        Ok(doc)
+    })
+}
+
+/// Rewrite document `id` as `change` of its current value (an empty object when
+/// absent and `upsert`); `Ok(false)` when absent otherwise.
+fn update_with<S, F>(storage: &S, collection: &str, id: &str, upsert: bool, change: F) -> Result<bool>
+where
+    S: Storage + ?Sized,
+    F: FnOnce(Json) -> Result<Json>,
+{
+    let mut capped = capped::guard(storage, collection)?;
+    let _doc = lock_doc(collection, id);
     let key = doc_key(collection, id);
     let space = Space(DATA_SPACE.into());
 
-    let base = match storage.get(&space, &key)? {
-        Some(bytes) => serde_json::from_slice::<Json>(&bytes).unwrap_or(Json::Null),
+    let old = storage.get(&space, &key)?;
+    let old_doc = old.as_deref().map(|bytes| decode(&key, bytes)).transpose()?;
+    let base = match &old_doc {
+        Some(doc) => doc.clone(),
         None => {
             if !upsert { return Ok(false); }
+            not_a_view(storage, collection)?;
+            if capped.is_some() {
+                return Err(DbError::Invalid(format!("capped collection {} only creates documents by insert", collection)));
+            }
             Json::Object(Default::default())
         }
     };
-    let merged = merge_json(base, patch);
-
-    let mut merged = merged;
+    let mut merged = change(base)?;
+    numbers::normalize_doc(&mut merged, &numbers::column_modes(storage, numbers::Source::Collection(collection))?);
     if let Some(obj) = merged.as_object_mut() {
         obj.insert("_id".into(), Json::String(id.to_string()));
     }
-    storage.put(&space, key, serde_json::to_vec(&merged).unwrap())?;
+    stamp_times(storage, collection, old_doc.as_ref(), &mut merged)?;
+    let bytes = encode(collection, id, &merged)?;
+    if let Some(c) = &capped {
+        c.check_fits(collection, bytes.len())?;
+    }
+    let _unique = index::check_unique(storage, collection, id, &merged)?;
+    if let Some(c) = &mut capped {
+        // Capped collections don't upsert, so the document exists
+        write_capped(storage, collection, c, id, old.map(|b| (b, old_doc.as_ref())), Some((bytes, &merged)))?;
+        return Ok(true);
+    }
+    storage.put(&space, key, bytes.clone())?;
+    index::update_entries(storage, collection, id, old_doc.as_ref(), Some(&merged))?;
+    let op = if old.is_some() { Operation::Update } else { Operation::Insert };
+    publish(op, collection, id, old, Some(bytes));
     Ok(true)
 }
 
 /// Delete a document. Returns `true` if existed.
 pub fn delete<S: Storage + ?Sized>(storage: &S, collection: &str, id: &str) -> Result<bool> {
+    let Some(mut capped) = capped::guard(storage, collection)? else {
+        return Ok(remove(storage, collection, id)?.is_some());
+    };
+    let key = doc_key(collection, id);
+    let Some(old) = storage.get(&Space(DATA_SPACE.into()), &key)? else { return Ok(false) };
+    let old_doc = decode(&key, &old).ok();
+    write_capped(storage, collection, &mut capped, id, Some((old, old_doc.as_ref())), None)?;
+    Ok(true)
+}
+
+/// Delete a document, returning its size if it existed
+fn remove<S: Storage + ?Sized>(storage: &S, collection: &str, id: &str) -> Result<Option<usize>> {
+    let _doc = lock_doc(collection, id);
     let key = doc_key(collection, id);
     let space = Space(DATA_SPACE.into());
-    let existed = storage.get(&space, &key)?.is_some();
+    let old = storage.get(&space, &key)?;
+    // A document that no longer decodes can still be deleted; its index entries
+    // are left for `index::create_index` to rebuild
+    let old_doc = old.as_deref().and_then(|bytes| decode(&key, bytes).ok());
     storage.del(&space, &key)?;
-    Ok(existed)
+    index::update_entries(storage, collection, id, old_doc.as_ref(), None)?;
+    let len = old.as_ref().map(Vec::len);
+    if old.is_some() {
+        publish(Operation::Delete, collection, id, old, None);
+    }
+    Ok(len)
 }
 
-/// List documents in a collection. If `ignore_expired` is true, skip docs with TTL in the past.
-/// WARNING: Returns the entire collection; for large sets implement paging.
-pub fn list_all<S: Storage + ?Sized>(storage: &S, collection: &str, ignore_expired: bool) -> Result<Vec<Json>> {
+/// Write document `id` of a capped collection from `old` to `new` (bytes and
+/// document; either may be absent) with its index entries, evict the oldest
+/// documents until the collection is within its limits, and commit all of it with
+/// the tally in one write. Change events are published once it is committed.
+fn write_capped<S: Storage + ?Sized>(
+    storage: &S,
+    collection: &str,
+    capped: &mut capped::Capped,
+    id: &str,
+    old: Option<(Vec<u8>, Option<&Json>)>,
+    new: Option<(Vec<u8>, &Json)>,
+) -> Result<()> {
+    let entries = index::entry_changes(storage, collection, id, old.as_ref().and_then(|(_, d)| *d), new.as_ref().map(|(_, d)| *d))?;
+    capped.stage(doc_key(collection, id), old.as_ref().map(|(b, _)| b.len()), new.as_ref().map(|(b, _)| b.clone()), entries);
+    let data = Space(DATA_SPACE.into());
     let prefix = format!("doc/{}/", collection).into_bytes();
-    let it = storage.scan_prefix(&Space(DATA_SPACE.into()), &prefix)?;
-    let mut out = Vec::new();
-    for (_k, v) in it {
-        let doc: Json = serde_json::from_slice(&v).unwrap_or(Json::Null);
-        if ignore_expired && is_expired(&doc) { continue; }
-        out.push(doc);
+    let mut evicted = Vec::new();
+    let mut after: Option<Vec<u8>> = None;
+    while capped.over() {
+        // Not yet written: the scan sees documents as stored, the staged writes say what they become
+        let Some((k, stored)) = storage.scan_prefix_page(&data, &prefix, after.as_deref(), 1)?.into_iter().next() else { break };
+        after = Some(k.clone());
+        let Some(bytes) = capped.staged(&k).map_or(Some(stored), |v| v.map(<[u8]>::to_vec)) else { continue };
+        let oldest = String::from_utf8_lossy(&k[prefix.len()..]).into_owned();
+        // A document that no longer decodes is evicted all the same
+        let doc = decode(&k, &bytes).ok();
+        let entries = index::entry_changes(storage, collection, &oldest, doc.as_ref(), None)?;
+        capped.stage(k, Some(bytes.len()), None, entries);
+        evicted.push((oldest, bytes));
+    }
+    capped.commit(storage, collection)?;
+    let op = match (&old, &new) {
+        (None, _) => Operation::Insert,
+        (_, None) => Operation::Delete,
+        _ => Operation::Update,
+    };
+    publish(op, collection, id, old.map(|(b, _)| b), new.map(|(b, _)| b));
+    for (oldest, bytes) in evicted {
+        publish(Operation::Delete, collection, &oldest, Some(bytes), None);
     }
-    Ok(out)
+    Ok(())
+}
+
+/// Where a page of documents starts and how many it holds
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Paging {
+    /// Resume after the document with this id (a previous `DocPage::next`)
+    pub after: Option<String>,
+    /// Matching documents to pass over before the page starts
+    pub skip: usize,
+    /// Most documents in the page; `None` for all the rest
+    pub limit: Option<usize>,
+}
+
+/// One page of documents, in id order
+#[derive(Debug, Clone, PartialEq)]
+pub struct DocPage {
+    pub docs: Vec<Json>,
+    /// Id to pass as `Paging::after` for the next page; `None` once the collection
+    /// is exhausted. A full page always carries one, so the last page may be empty.
+    pub next: Option<String>,
+}
+
+/// Documents read from storage per step of a paged scan
+const PAGE_BATCH: usize = 256;
+
+/// List documents in a collection. If `ignore_expired` is true, skip docs with TTL in the past.
+/// Returns the entire collection; use `list_page` for large ones.
+pub fn list_all<S: Storage + ?Sized>(storage: &S, collection: &str, ignore_expired: bool) -> Result<Vec<Json>> {
+    Ok(list_page(storage, collection, ignore_expired, &Paging::default())?.docs)
+}
+
+/// One page of `list_all`
+pub fn list_page<S: Storage + ?Sized>(storage: &S, collection: &str, ignore_expired: bool, paging: &Paging) -> Result<DocPage> {
+    find_where_page(storage, collection, |_| true, ignore_expired, paging)
 }
 
-/// Find all documents where `field == value` (simple equality filter).
+/// Find all documents where `field == value` (simple equality filter). `field` may
+/// be a dotted path such as `address.city`.
 /// Client-side filter for MVP; later replace with indexed field lookups.
 pub fn find_eq<S: Storage + ?Sized>(storage: &S, collection: &str, field: &str, value: &Json, ignore_expired: bool) -> Result<Vec<Json>> {
-    let prefix = format!("doc/{}/", collection).into_bytes();
-    let it = storage.scan_prefix(&Space(DATA_SPACE.into()), &prefix)?;
-    let mut out = Vec::new();
-    for (_k, v) in it {
-        let doc: Json = serde_json::from_slice(&v).unwrap_or(Json::Null);
-        if ignore_expired && is_expired(&doc) { continue; }
-        if json_field_eq(&doc, field, value) {
-            out.push(doc);
-        }
-    }
-    Ok(out)
+    Ok(find_eq_page(storage, collection, field, value, ignore_expired, &Paging::default())?.docs)
+}
+
+/// One page of `find_eq`
+pub fn find_eq_page<S: Storage + ?Sized>(storage: &S, collection: &str, field: &str, value: &Json, ignore_expired: bool, paging: &Paging) -> Result<DocPage> {
+    find_where_page(storage, collection, |doc| json_field_eq(doc, field, value), ignore_expired, paging)
 }
 
 /// Find with a custom predicate closure.
@@ -151,28 +570,258 @@ pub fn find_eq<S: Storage + ?Sized>(storage: &S, collection: &str, field: &str,
 /// ```ignore
 /// let res = find_where(&*db.storage, "todos", |doc| doc["done"] == true.into(), true)?;
 /// ```
-pub fn find_where<S, F>(storage: &S, collection: &str, mut pred: F, ignore_expired: bool) -> Result<Vec<Json>>
+pub fn find_where<S, F>(storage: &S, collection: &str, pred: F, ignore_expired: bool) -> Result<Vec<Json>>
+where
+    S: Storage + ?Sized,
+    F: FnMut(&Json) -> bool,
+{
+    Ok(find_where_page(storage, collection, pred, ignore_expired, &Paging::default())?.docs)
+}
+
+/// One page of `find_where`. Storage is read a batch at a time from `paging.after`
+/// on, so a page costs about as many reads as it passes over, however large the
+/// collection is.
+pub fn find_where_page<S, F>(storage: &S, collection: &str, mut pred: F, ignore_expired: bool, paging: &Paging) -> Result<DocPage>
+where
+    S: Storage + ?Sized,
+    F: FnMut(&Json) -> bool,
+{
+    match view::get_view(storage, collection)? {
+        Some(view) => {
+            let narrow = view.narrow(&Filter::all())?;
+            let page = scan_page(storage, &view.collection, |d| narrow.matches(d) && pred(d), ignore_expired, paging)?;
+            Ok(view.show_page(page))
+        }
+        None => scan_page(storage, collection, pred, ignore_expired, paging),
+    }
+}
+
+/// `find_where_page` over a collection, not a view
+fn scan_page<S, F>(storage: &S, collection: &str, mut pred: F, ignore_expired: bool, paging: &Paging) -> Result<DocPage>
 where
     S: Storage + ?Sized,
     F: FnMut(&Json) -> bool,
 {
     let prefix = format!("doc/{}/", collection).into_bytes();
-    let it = storage.scan_prefix(&Space(DATA_SPACE.into()), &prefix)?;
-    let mut out = Vec::new();
-    for (_k, v) in it {
-        let doc: Json = serde_json::from_slice(&v).unwrap_or(Json::Null);
-        if ignore_expired && is_expired(&doc) { continue; }
-        if pred(&doc) { out.push(doc); }
+    let space = Space(DATA_SPACE.into());
+    let limit = paging.limit.unwrap_or(usize::MAX);
+    let mut after = paging.after.as_ref().map(|id| [prefix.as_slice(), id.as_bytes()].concat());
+    let mut skip = paging.skip;
+    let mut docs = Vec::new();
+    while docs.len() < limit {
+        let batch = storage.scan_prefix_page(&space, &prefix, after.as_deref(), PAGE_BATCH)?;
+        let exhausted = batch.len() < PAGE_BATCH;
+        for (k, v) in batch {
+            deadline::check()?;
+            let doc = decode(&k, &v)?;
+            after = Some(k);
+            if ignore_expired && is_expired(&doc) { continue; }
+            if !pred(&doc) { continue; }
+            if skip > 0 {
+                skip -= 1;
+                continue;
+            }
+            docs.push(doc);
+            if docs.len() == limit {
+                let id = after.as_ref().map(|k| String::from_utf8_lossy(&k[prefix.len()..]).into_owned());
+                return Ok(DocPage { docs, next: id });
+            }
+        }
+        if exhausted {
+            break;
+        }
+    }
+    Ok(DocPage { docs, next: None })
+}
+
+/// Documents matching `filter`, looked up through a field index when one of its
+/// `$eq`/`$in`/`$contains` clauses is on an indexed field, otherwise by a scan.
+pub fn query<S: Storage + ?Sized>(storage: &S, collection: &str, filter: &Filter, ignore_expired: bool) -> Result<Vec<Json>> {
+    Ok(query_page(storage, collection, filter, ignore_expired, &Paging::default())?.docs)
+}
+
+/// One page of `query`, in id order either way
+pub fn query_page<S: Storage + ?Sized>(storage: &S, collection: &str, filter: &Filter, ignore_expired: bool, paging: &Paging) -> Result<DocPage> {
+    if let Some(view) = view::get_view(storage, collection)? {
+        return query_page(storage, &view.collection, &view.narrow(filter)?, ignore_expired, paging).map(|page| view.show_page(page));
+    }
+    let Some(ids) = index::candidates(storage, collection, filter)? else {
+        return scan_page(storage, collection, |d| filter.matches(d), ignore_expired, paging);
+    };
+    let limit = paging.limit.unwrap_or(usize::MAX);
+    let mut skip = paging.skip;
+    let mut docs = Vec::new();
+    let after = paging.after.as_deref().unwrap_or("");
+    if limit == 0 {
+        return Ok(DocPage { docs, next: None });
+    }
+    for id in ids.into_iter().filter(|id| id.as_str() > after) {
+        let Some(doc) = get_doc(storage, collection, &id, ignore_expired)? else { continue };
+        if !filter.matches(&doc) { continue; }
+        if skip > 0 {
+            skip -= 1;
+            continue;
+        }
+        docs.push(doc);
+        if docs.len() == limit {
+            return Ok(DocPage { docs, next: Some(id) });
+        }
+    }
+    Ok(DocPage { docs, next: None })
+}
+
+/// Documents of `collection` matching a JSON filter, e.g.
+/// `{"age": {"$gte": 18}, "address.city": {"$in": ["Oslo", "Bergen"]}}`; see `filter`
+/// for the operators. Expired documents are skipped.
+pub fn find<S: Storage + ?Sized>(storage: &S, collection: &str, filter: &Json) -> Result<Vec<Json>> {
+    query(storage, collection, &Filter::parse(filter)?, true)
+}
+
+/// Number of documents matching `filter`. Each document is still read and checked
+/// against the whole filter, but only the count is kept; an index on one of the
+/// filter's clauses narrows which documents are read, as in `query`.
+pub fn count<S: Storage + ?Sized>(storage: &S, collection: &str, filter: &Filter) -> Result<usize> {
+    let mut n = 0;
+    for_each_match(storage, collection, filter, |_| n += 1)?;
+    Ok(n)
+}
+
+/// Distinct values of `field` (a dotted path) among documents matching `filter`,
+/// which are read as for `count`. Array fields contribute each element; numbers
+/// that compare equal count once. Values are ordered by their index encoding.
+pub fn distinct<S: Storage + ?Sized>(storage: &S, collection: &str, field: &str, filter: &Filter) -> Result<Vec<Json>> {
+    let mut seen = std::collections::BTreeMap::new();
+    for_each_match(storage, collection, filter, |doc| {
+        let values = match filter::lookup(doc, field) {
+            Some(Json::Array(items)) => items.iter().collect(),
+            Some(v) => vec![v],
+            None => Vec::new(),
+        };
+        for v in values {
+            seen.entry(index::encode_value(v)).or_insert_with(|| v.clone());
+        }
+    })?;
+    Ok(seen.into_values().collect())
+}
+
+/// Size of one collection, for capacity planning
+#[derive(Debug, Clone, PartialEq, Eq, Default, serde::Serialize)]
+pub struct CollectionStats {
+    /// Stored documents, expired ones the sweeper hasn't removed included
+    pub documents: usize,
+    /// Bytes of encoded documents
+    pub bytes: u64,
+    pub avg_doc_bytes: u64,
+    /// One entry per indexed field, sorted by field
+    pub indexes: Vec<index::IndexStats>,
+}
+
+/// Count the documents and bytes of `collection` and the entries of each of its
+/// indexes. Walks every document, so call it periodically rather than per request.
+pub fn collection_stats<S: Storage + ?Sized>(storage: &S, collection: &str) -> Result<CollectionStats> {
+    let mut stats = CollectionStats::default();
+    let prefix = format!("doc/{}/", collection).into_bytes();
+    for (_k, v) in storage.scan_prefix(&Space(DATA_SPACE.into()), &prefix)? {
+        deadline::check()?;
+        stats.documents += 1;
+        stats.bytes += v.len() as u64;
+    }
+    if stats.documents > 0 {
+        stats.avg_doc_bytes = stats.bytes / stats.documents as u64;
+    }
+    stats.indexes = index::indexed_fields(storage, collection)?
+        .iter()
+        .map(|field| index::index_stats(storage, collection, field))
+        .collect::<Result<_>>()?;
+    Ok(stats)
+}
+
+/// Call `f` on each unexpired document matching `filter`, one at a time
+fn for_each_match<S, F>(storage: &S, collection: &str, filter: &Filter, mut f: F) -> Result<()>
+where
+    S: Storage + ?Sized,
+    F: FnMut(&Json),
+{
+    match view::get_view(storage, collection)? {
+        Some(view) => visit_matches(storage, &view.collection, &view.narrow(filter)?, |doc| f(&view.show(doc.clone()))),
+        None => visit_matches(storage, collection, filter, f),
+    }
+}
+
+/// `for_each_match` over a collection, not a view
+fn visit_matches<S, F>(storage: &S, collection: &str, filter: &Filter, mut f: F) -> Result<()>
+where
+    S: Storage + ?Sized,
+    F: FnMut(&Json),
+{
+    let mut visit = |key: &[u8], bytes: &[u8]| -> Result<()> {
+        let doc = decode(key, bytes)?;
+        if !is_expired(&doc) && filter.matches(&doc) {
+            f(&doc);
+        }
+        Ok(())
+    };
+    match index::candidates(storage, collection, filter)? {
+        Some(ids) => {
+            for id in ids {
+                deadline::check()?;
+                let key = doc_key(collection, &id);
+                if let Some(bytes) = storage.get(&Space(DATA_SPACE.into()), &key)? {
+                    visit(&key, &bytes)?;
+                }
+            }
+        }
+        None => {
+            let prefix = format!("doc/{}/", collection).into_bytes();
+            for (k, v) in storage.scan_prefix(&Space(DATA_SPACE.into()), &prefix)? {
+                deadline::check()?;
+                visit(&k, &v)?;
+            }
+        }
     }
-    Ok(out)
+    Ok(())
 }
 
 // ---------- helpers ----------
 
+/// Decode the stored document at `key`
+pub(crate) fn decode(key: &[u8], bytes: &[u8]) -> Result<Json> {
+    serde_json::from_slice(bytes)
+        .map_err(|e| DbError::Corruption(format!("document {}: {}", String::from_utf8_lossy(key), e)))
+}
+
+/// Encode document `id` for writing, enforcing `max_doc_bytes`
+pub(crate) fn encode(collection: &str, id: &str, doc: &Json) -> Result<Vec<u8>> {
+    let bytes = serde_json::to_vec(doc).map_err(|e| DbError::Invalid(e.to_string()))?;
+    match max_doc_bytes() {
+        Some(max) if bytes.len() > max => Err(DbError::Invalid(format!(
+            "document {}/{} is {} bytes, over the {} byte limit", collection, id, bytes.len(), max
+        ))),
+        _ => Ok(bytes),
+    }
+}
+
 fn doc_key(collection: &str, id: &str) -> Vec<u8> {
     format!("doc/{}/{}", collection, id).into_bytes()
 }
 
+/// Notify changefeeds (and doc watches) of a write
+fn publish(operation: Operation, collection: &str, id: &str, old_value: Option<Vec<u8>>, new_value: Option<Vec<u8>>) {
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    EVENT_MANAGER.publish_event(ChangeEvent {
+        id: nanoid::nanoid!(),
+        timestamp,
+        operation,
+        table: collection.to_string(),
+        key: Some(id.as_bytes().to_vec()),
+        old_value,
+        new_value,
+    });
+}
+
 fn merge_json(base: Json, patch: Json) -> Json {
     match (base, patch) {
         (Json::Object(mut a), Json::Object(b)) => {
@@ -186,10 +835,61 @@ fn merge_json(base: Json, patch: Json) -> Json {
 }
 
 fn json_field_eq(doc: &Json, field: &str, needle: &Json) -> bool {
-    match doc {
-        Json::Object(map) => map.get(field).map(|v| v == needle).unwrap_or(false),
-        _ => false,
+    filter::lookup(doc, field) == Some(needle)
+}
+
+/// Turn write timestamps on or off for `collection`. While on, every write sets
+/// `_updated_at` to the server's clock (epoch ms), and `_created_at` on creation;
+/// later writes keep the `_created_at` of the stored document, whatever they send.
+pub fn set_write_timestamps<S: Storage + ?Sized>(storage: &S, collection: &str, on: bool) -> Result<()> {
+    let key = format!("timestamps/{}", collection).into_bytes();
+    if on {
+        storage.put(&Space(CATALOG_SPACE.into()), key, Vec::new())
+    } else {
+        storage.del(&Space(CATALOG_SPACE.into()), &key)
+    }
+}
+
+pub fn write_timestamps<S: Storage + ?Sized>(storage: &S, collection: &str) -> Result<bool> {
+    Ok(storage.get(&Space(CATALOG_SPACE.into()), format!("timestamps/{}", collection).as_bytes())?.is_some())
+}
+
+/// Stamp `doc`, replacing `old` (absent on creation), if `collection` has write timestamps
+pub(crate) fn stamp_times<S: Storage + ?Sized>(storage: &S, collection: &str, old: Option<&Json>, doc: &mut Json) -> Result<()> {
+    if !write_timestamps(storage, collection)? {
+        return Ok(());
+    }
+    let Some(obj) = doc.as_object_mut() else { return Ok(()) };
+    let now = Json::from(now_ms());
+    let created = old.map_or(Some(&now), |o| o.get("_created_at"));
+    match created {
+        Some(created) => obj.insert("_created_at".into(), created.clone()),
+        // Written before timestamps were turned on
+        None => obj.remove("_created_at"),
+    };
+    obj.insert("_updated_at".into(), now);
+    Ok(())
+}
+
+/// `stamp_times` for a document loaded from an export: the write timestamps it
+/// carries are kept, and only missing ones are stamped
+pub(crate) fn stamp_loaded_times<S: Storage + ?Sized>(storage: &S, collection: &str, old: Option<&Json>, doc: &mut Json) -> Result<()> {
+    let carried: Vec<(String, Json)> = ["_created_at", "_updated_at"]
+        .into_iter()
+        .filter_map(|field| Some((field.to_string(), doc.get(field)?.clone())))
+        .collect();
+    stamp_times(storage, collection, old, doc)?;
+    if let Some(obj) = doc.as_object_mut() {
+        obj.extend(carried);
     }
+    Ok(())
+}
+
+pub(crate) fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
 }
 
 /// TTL convention: if document contains numeric `_ttl_epoch_ms` and now >= TTL, it is expired.
@@ -0,0 +1,174 @@
+//! Document patches: JSON Patch (RFC 6902) and deep merge (RFC 7386).
+//!
+//! A JSON Patch is an array of operations applied in order, each naming a JSON
+//! Pointer `path` (`/address/city`, `/tags/0`, `/tags/-` for the end of an array):
+//!
+//! ```text
+//! [ { "op": "replace", "path": "/address/city", "value": "Oslo" },
+//!   { "op": "move", "from": "/nick", "path": "/alias" } ]
+//! ```
+//!
+//! Supported operations: `add`, `remove`, `replace`, `move`, `copy`, `test`. A
+//! failing operation fails the whole patch and leaves the document unchanged.
+//!
+//! `deep_merge` merges objects recursively; a `null` in the patch removes the field.
+
+use serde_json::Value as Json;
+use tonledb_core::{DbError, Result};
+
+/// Apply the JSON Patch `ops` to `doc`. On error `doc` is left as it was.
+pub fn apply(doc: &mut Json, ops: &Json) -> Result<()> {
+    let ops = ops.as_array().ok_or_else(|| invalid("a JSON Patch must be an array of operations"))?;
+    let mut out = doc.clone();
+    for op in ops {
+        apply_one(&mut out, op)?;
+    }
+    *doc = out;
+    Ok(())
+}
+
+/// Merge `patch` into `base`: objects merge key by key at every depth, `null`
+/// removes a key, and anything else replaces the value.
+pub fn deep_merge(base: &mut Json, patch: Json) {
+    let Json::Object(fields) = patch else {
+        *base = patch;
+        return;
+    };
+    if !base.is_object() {
+        *base = Json::Object(Default::default());
+    }
+    let Some(obj) = base.as_object_mut() else { return };
+    for (k, v) in fields {
+        if v.is_null() {
+            obj.remove(&k);
+        } else {
+            deep_merge(obj.entry(k).or_insert(Json::Null), v);
+        }
+    }
+}
+
+fn apply_one(doc: &mut Json, op: &Json) -> Result<()> {
+    let name = op.get("op").and_then(Json::as_str).ok_or_else(|| invalid("patch operation without \"op\""))?;
+    let path = pointer(op, "path")?;
+    let value = || op.get("value").cloned().ok_or_else(|| invalid(format!("{} at {} needs a \"value\"", name, path)));
+    match name {
+        "add" => add(doc, path, value()?),
+        "remove" => remove(doc, path).map(drop),
+        "replace" => {
+            let slot = resolve_mut(doc, path)?;
+            *slot = value()?;
+            Ok(())
+        }
+        "move" => {
+            let from = pointer(op, "from")?;
+            if path.starts_with(from) && path[from.len()..].starts_with('/') {
+                return Err(invalid(format!("cannot move {} into its own child {}", from, path)));
+            }
+            let moved = remove(doc, from)?;
+            add(doc, path, moved)
+        }
+        "copy" => {
+            let copied = resolve(doc, pointer(op, "from")?)?.clone();
+            add(doc, path, copied)
+        }
+        "test" => {
+            if *resolve(doc, path)? == value()? {
+                Ok(())
+            } else {
+                Err(DbError::Conflict(format!("test failed at {}", path)))
+            }
+        }
+        other => Err(invalid(format!("unknown patch operation {}", other))),
+    }
+}
+
+fn pointer<'a>(op: &'a Json, field: &str) -> Result<&'a str> {
+    let p = op.get(field).and_then(Json::as_str).ok_or_else(|| invalid(format!("patch operation without \"{}\"", field)))?;
+    if !p.is_empty() && !p.starts_with('/') {
+        return Err(invalid(format!("invalid JSON Pointer {:?}", p)));
+    }
+    Ok(p)
+}
+
+/// Path segments, unescaped (`~1` is `/`, `~0` is `~`)
+fn segments(path: &str) -> Vec<String> {
+    path.split('/').skip(1).map(|s| s.replace("~1", "/").replace("~0", "~")).collect()
+}
+
+/// Parent container of `path` and the last segment; `None` for the root
+fn parent<'a>(doc: &'a mut Json, path: &str) -> Result<Option<(&'a mut Json, String)>> {
+    let mut segs = segments(path);
+    let Some(last) = segs.pop() else { return Ok(None) };
+    let mut cur = doc;
+    for seg in &segs {
+        cur = step(cur, seg).ok_or_else(|| missing(path))?;
+    }
+    Ok(Some((cur, last)))
+}
+
+fn step<'a>(v: &'a mut Json, seg: &str) -> Option<&'a mut Json> {
+    match v {
+        Json::Object(map) => map.get_mut(seg),
+        Json::Array(items) => index(seg).and_then(|i| items.get_mut(i)),
+        _ => None,
+    }
+}
+
+/// Array index segment: digits without a leading zero
+fn index(seg: &str) -> Option<usize> {
+    if seg.is_empty() || (seg.len() > 1 && seg.starts_with('0')) || !seg.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    seg.parse().ok()
+}
+
+fn resolve<'a>(doc: &'a Json, path: &str) -> Result<&'a Json> {
+    segments(path).iter().try_fold(doc, |v, seg| match v {
+        Json::Object(map) => map.get(seg.as_str()),
+        Json::Array(items) => index(seg).and_then(|i| items.get(i)),
+        _ => None,
+    }).ok_or_else(|| missing(path))
+}
+
+fn resolve_mut<'a>(doc: &'a mut Json, path: &str) -> Result<&'a mut Json> {
+    segments(path).iter().try_fold(doc, |v, seg| step(v, seg)).ok_or_else(|| missing(path))
+}
+
+fn add(doc: &mut Json, path: &str, value: Json) -> Result<()> {
+    let Some((target, last)) = parent(doc, path)? else {
+        *doc = value;
+        return Ok(());
+    };
+    match target {
+        Json::Object(map) => {
+            map.insert(last, value);
+        }
+        Json::Array(items) if last == "-" => items.push(value),
+        Json::Array(items) => match index(&last) {
+            Some(i) if i <= items.len() => items.insert(i, value),
+            _ => return Err(missing(path)),
+        },
+        _ => return Err(missing(path)),
+    }
+    Ok(())
+}
+
+fn remove(doc: &mut Json, path: &str) -> Result<Json> {
+    let Some((target, last)) = parent(doc, path)? else {
+        return Err(invalid("cannot remove the whole document"));
+    };
+    match target {
+        Json::Object(map) => map.remove(&last),
+        Json::Array(items) => index(&last).filter(|&i| i < items.len()).map(|i| items.remove(i)),
+        _ => None,
+    }
+    .ok_or_else(|| missing(path))
+}
+
+fn invalid(msg: impl Into<String>) -> DbError {
+    DbError::Invalid(msg.into())
+}
+
+fn missing(path: &str) -> DbError {
+    DbError::Invalid(format!("no value at {}", path))
+}
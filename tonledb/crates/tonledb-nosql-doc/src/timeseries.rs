@@ -0,0 +1,216 @@
+//! Time-series collections: documents keyed by their timestamp, so a time range
+//! is one range scan, with optional retention and downsampling.
+//!
+//! A time-series collection is registered in the catalog under
+//! `timeseries/<collection>` with its time field and retention. `insert` takes the
+//! document's time field (epoch milliseconds, set to now when absent) and gives the
+//! document the id `<16 hex digits of the time><random suffix>`, so key order is
+//! time order. Range queries and retention go by that key: updating the time field
+//! later doesn't move a document.
+//!
+//! With a retention, each insert deletes the documents older than `now - retention`
+//! (starting from the oldest, so this costs nothing when none are due);
+//! `enforce_retention` does the same on demand.
+//!
+//! Time-series collections can't be capped and don't take part in `txn`.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value as Json;
+use tonledb_core::{deadline, DbError, Result, Space, Storage};
+use crate::{DocPage, Paging};
+
+const CATALOG_SPACE: &str = "catalog";
+const RANGE_BATCH: usize = 256;
+
+/// Settings of a time-series collection
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeSeries {
+    /// Field holding the time in epoch milliseconds
+    pub time_field: String,
+    /// Delete documents older than this; unset keeps everything
+    #[serde(default)]
+    pub retention_ms: Option<u64>,
+}
+
+/// One downsampling bucket: `[start, start + bucket_ms)`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Bucket {
+    pub start: u64,
+    /// Documents in the bucket with a numeric value
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+}
+
+/// Make `collection` a time-series collection. It must be empty; settings of an
+/// empty time-series collection are replaced.
+pub fn create_timeseries<S: Storage + ?Sized>(storage: &S, collection: &str, ts: TimeSeries) -> Result<()> {
+    if ts.time_field.is_empty() || ts.retention_ms == Some(0) {
+        return Err(DbError::Invalid("a time series needs a time field and a positive retention".into()));
+    }
+    if crate::capped::cap(storage, collection)?.is_some() {
+        return Err(DbError::Invalid(format!("{} is a capped collection", collection)));
+    }
+    if !storage.scan_prefix_page(&Space(crate::DATA_SPACE.into()), &prefix(collection), None, 1)?.is_empty() {
+        return Err(DbError::Conflict(format!("collection {} already has documents", collection)));
+    }
+    crate::create_collection(storage, collection)?;
+    let v = serde_json::json!({ "time_field": ts.time_field, "retention_ms": ts.retention_ms });
+    storage.put(&Space(CATALOG_SPACE.into()), key(collection), serde_json::to_vec(&v).unwrap())
+}
+
+/// Settings of `collection`; `None` if it isn't a time series
+pub fn timeseries<S: Storage + ?Sized>(storage: &S, collection: &str) -> Result<Option<TimeSeries>> {
+    let Some(bytes) = storage.get(&Space(CATALOG_SPACE.into()), &key(collection))? else { return Ok(None) };
+    let v: Json = serde_json::from_slice(&bytes)
+        .map_err(|e| DbError::Corruption(format!("timeseries/{}: {}", collection, e)))?;
+    Ok(Some(TimeSeries {
+        time_field: v.get("time_field").and_then(Json::as_str).unwrap_or_default().to_string(),
+        retention_ms: v.get("retention_ms").and_then(Json::as_u64),
+    }))
+}
+
+/// Documents of `collection` with a time in `from..to` (epoch ms), oldest first
+pub fn range<S: Storage + ?Sized>(storage: &S, collection: &str, from: u64, to: u64) -> Result<Vec<Json>> {
+    Ok(range_page(storage, collection, from, to, &Paging::default())?.docs)
+}
+
+/// One page of `range`; `Paging::after` is the id of the last document of the
+/// page before (`DocPage::next`)
+pub fn range_page<S: Storage + ?Sized>(storage: &S, collection: &str, from: u64, to: u64, paging: &Paging) -> Result<DocPage> {
+    let limit = paging.limit.unwrap_or(usize::MAX);
+    let mut skip = paging.skip;
+    let mut page = DocPage { docs: Vec::new(), next: None };
+    if limit == 0 {
+        return Ok(page);
+    }
+    for_each_in_range(storage, collection, from, to, paging.after.as_deref(), |id, _, doc| {
+        if skip > 0 {
+            skip -= 1;
+            return true;
+        }
+        page.docs.push(doc);
+        if page.docs.len() < limit {
+            return true;
+        }
+        page.next = Some(id.to_string());
+        false
+    })?;
+    Ok(page)
+}
+
+/// Downsample `field` over `from..to` into buckets of `bucket_ms`, aligned to
+/// multiples of it. Documents without a numeric `field` are skipped, and so are
+/// buckets left empty.
+pub fn downsample<S: Storage + ?Sized>(storage: &S, collection: &str, from: u64, to: u64, bucket_ms: u64, field: &str) -> Result<Vec<Bucket>> {
+    if bucket_ms == 0 {
+        return Err(DbError::Invalid("bucket_ms must be positive".into()));
+    }
+    let mut buckets: Vec<Bucket> = Vec::new();
+    for_each_in_range(storage, collection, from, to, None, |_, time, doc| {
+        let Some(x) = crate::filter::lookup(&doc, field).and_then(Json::as_f64) else { return true };
+        let start = time - time % bucket_ms;
+        match buckets.last_mut() {
+            Some(b) if b.start == start => {
+                // `avg` holds the running sum until the end
+                b.count += 1;
+                b.min = b.min.min(x);
+                b.max = b.max.max(x);
+                b.avg += x;
+            }
+            _ => buckets.push(Bucket { start, count: 1, min: x, max: x, avg: x }),
+        }
+        true
+    })?;
+    for b in &mut buckets {
+        b.avg /= b.count as f64;
+    }
+    Ok(buckets)
+}
+
+/// Delete the documents of `collection` older than its retention allows at `now_ms`;
+/// returns how many were deleted
+pub fn enforce_retention<S: Storage + ?Sized>(storage: &S, collection: &str, now_ms: u64) -> Result<usize> {
+    let Some(retention) = timeseries(storage, collection)?.and_then(|ts| ts.retention_ms) else { return Ok(0) };
+    let (prefix, end) = (prefix(collection), bound(collection, now_ms.saturating_sub(retention)));
+    let mut removed = 0;
+    loop {
+        let batch = storage.scan_range(&Space(crate::DATA_SPACE.into()), &prefix, Some(&end), false, RANGE_BATCH)?;
+        if batch.is_empty() {
+            return Ok(removed);
+        }
+        for (k, _) in batch {
+            crate::delete(storage, collection, &String::from_utf8_lossy(&k[prefix.len()..]))?;
+            removed += 1;
+        }
+    }
+}
+
+/// Id for a document of time `time`
+pub(crate) fn doc_id(time: u64) -> String {
+    format!("{:016x}{}", time, nanoid::nanoid!(8))
+}
+
+/// Time of document `doc`'s `field`, set to now when absent
+pub(crate) fn stamp(doc: &mut Json, field: &str) -> Result<u64> {
+    let obj = doc.as_object_mut().ok_or_else(|| DbError::Invalid("time-series documents must be objects".into()))?;
+    match obj.get(field) {
+        Some(v) => v.as_u64().ok_or_else(|| DbError::Invalid(format!("{} must be epoch milliseconds, got {}", field, v))),
+        None => {
+            let now = crate::now_ms();
+            obj.insert(field.to_string(), Json::from(now));
+            Ok(now)
+        }
+    }
+}
+
+/// Call `f` with the id, time and document of each document of `collection` in
+/// `from..to` after document `after`, oldest first, until it returns `false`
+fn for_each_in_range<S, F>(storage: &S, collection: &str, from: u64, to: u64, after: Option<&str>, mut f: F) -> Result<()>
+where
+    S: Storage + ?Sized,
+    F: FnMut(&str, u64, Json) -> bool,
+{
+    if timeseries(storage, collection)?.is_none() {
+        return Err(DbError::Invalid(format!("{} is not a time-series collection", collection)));
+    }
+    let prefix = prefix(collection);
+    let end = bound(collection, to);
+    let mut start = bound(collection, from);
+    if let Some(after) = after {
+        start = start.max([prefix.as_slice(), after.as_bytes(), &[0]].concat());
+    }
+    loop {
+        let batch = storage.scan_range(&Space(crate::DATA_SPACE.into()), &start, Some(&end), false, RANGE_BATCH)?;
+        let exhausted = batch.len() < RANGE_BATCH;
+        for (k, v) in batch {
+            deadline::check()?;
+            let id = std::str::from_utf8(&k[prefix.len()..]).ok();
+            let time = id.and_then(|id| id.get(..16)).and_then(|hex| u64::from_str_radix(hex, 16).ok());
+            if let (Some(id), Some(time)) = (id, time) {
+                if !f(id, time, crate::decode(&k, &v)?) {
+                    return Ok(());
+                }
+            }
+            start = k;
+            start.push(0);
+        }
+        if exhausted {
+            return Ok(());
+        }
+    }
+}
+
+fn prefix(collection: &str) -> Vec<u8> {
+    format!("doc/{}/", collection).into_bytes()
+}
+
+/// First key at time `time`
+fn bound(collection: &str, time: u64) -> Vec<u8> {
+    format!("doc/{}/{:016x}", collection, time).into_bytes()
+}
+
+fn key(collection: &str) -> Vec<u8> {
+    format!("timeseries/{}", collection).into_bytes()
+}
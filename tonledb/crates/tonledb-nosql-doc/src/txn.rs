@@ -0,0 +1,198 @@
+//! Multi-document transactions.
+//!
+//! A `DocTxn` is a transaction of the core `TransactionManager` spanning any
+//! number of collections: reads see the snapshot taken at `begin` plus the
+//! transaction's own writes; writes are buffered and applied by `commit` at one
+//! version, so other readers see all of them or none. Index entries are worked
+//! out at commit, against the documents as stored then, so a write made outside
+//! the transaction meanwhile leaves no stale entries behind. `commit` fails with `DbError::Conflict` if another transaction
+//! committed to one of the same documents first, or if the writes break a unique
+//! index. Change events are published only once the commit succeeds. A
+//! transaction dropped without committing is aborted.
+//!
+//! Documents written in a transaction cannot carry a TTL.
+
+use std::sync::Arc;
+use serde_json::Value as Json;
+use tonledb_core::event_sourcing::Operation;
+use tonledb_core::numbers;
+use tonledb_core::transaction::{TransactionManager, TXN_MANAGER};
+use tonledb_core::{DbError, Result, Space, Storage};
+use crate::{capped, decode, doc_key, encode, index, merge_json, publish, timeseries, DATA_SPACE};
+
+pub struct DocTxn<'a, S: Storage + ?Sized> {
+    storage: &'a S,
+    manager: Arc<TransactionManager>,
+    id: u64,
+    done: bool,
+}
+
+/// Start a transaction on the process-wide transaction manager
+pub fn begin<S: Storage + ?Sized>(storage: &S) -> Result<DocTxn<'_, S>> {
+    begin_with(storage, TXN_MANAGER.clone())
+}
+
+/// Start a transaction on `manager`
+pub fn begin_with<S: Storage + ?Sized>(storage: &S, manager: Arc<TransactionManager>) -> Result<DocTxn<'_, S>> {
+    let id = manager.begin()?;
+    Ok(DocTxn { storage, manager, id, done: false })
+}
+
+impl<S: Storage + ?Sized> DocTxn<'_, S> {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn get(&self, collection: &str, id: &str) -> Result<Option<Json>> {
+        let key = doc_key(collection, id);
+        self.manager.get(self.storage, self.id, &data(), &key)?.map(|bytes| decode(&key, &bytes)).transpose()
+    }
+
+    /// Insert a new document and return its generated id
+    pub fn insert(&self, collection: &str, doc: Json) -> Result<String> {
+        let id = nanoid::nanoid!();
+        self.write(collection, &id, None, Some(doc))?;
+        Ok(id)
+    }
+
+    /// Replace a document; `false` if it doesn't exist
+    pub fn replace(&self, collection: &str, id: &str, doc: Json) -> Result<bool> {
+        let Some(old) = self.get(collection, id)? else { return Ok(false) };
+        self.write(collection, id, Some(&old), Some(doc))?;
+        Ok(true)
+    }
+
+    /// Write document `id`, replacing it if it exists; `true` if it did
+    pub fn upsert(&self, collection: &str, id: &str, doc: Json) -> Result<bool> {
+        let old = self.get(collection, id)?;
+        self.write(collection, id, old.as_ref(), Some(doc))?;
+        Ok(old.is_some())
+    }
+
+    /// `upsert` a document loaded from an export, keeping the write timestamps it
+    /// carries (see `crate::stamp_loaded_times`)
+    pub(crate) fn load(&self, collection: &str, id: &str, doc: Json) -> Result<bool> {
+        let old = self.get(collection, id)?;
+        self.write_as(collection, id, old.as_ref(), Some(doc), true)?;
+        Ok(old.is_some())
+    }
+
+    /// Shallow-merge `patch` into a document, as `crate::update_merge`
+    pub fn update_merge(&self, collection: &str, id: &str, patch: Json, upsert: bool) -> Result<bool> {
+        let old = self.get(collection, id)?;
+        let base = match &old {
+            Some(doc) => doc.clone(),
+            None if upsert => Json::Object(Default::default()),
+            None => return Ok(false),
+        };
+        self.write(collection, id, old.as_ref(), Some(merge_json(base, patch)))?;
+        Ok(true)
+    }
+
+    /// Delete a document; `false` if it doesn't exist
+    pub fn delete(&self, collection: &str, id: &str) -> Result<bool> {
+        let Some(old) = self.get(collection, id)? else { return Ok(false) };
+        self.write(collection, id, Some(&old), None)?;
+        Ok(true)
+    }
+
+    /// Apply every buffered write atomically. On a conflict nothing is written and
+    /// the transaction is aborted.
+    pub fn commit(mut self) -> Result<()> {
+        self.done = true;
+        let txn = self.manager.get_transaction(self.id)
+            .ok_or_else(|| DbError::NotFound(format!("Transaction {} not found", self.id)))?;
+        let docs: Vec<(String, String, Option<Json>)> = txn.write_set.iter()
+            .filter(|((space, _), _)| *space == data())
+            .filter_map(|((_, key), val)| {
+                let (collection, id) = String::from_utf8_lossy(key).strip_prefix("doc/")?.rsplit_once('/').map(|(c, i)| (c.to_string(), i.to_string()))?;
+                // Buffered values were encoded by `write`, so they decode
+                Some((collection, id, val.as_deref().and_then(|bytes| decode(key, bytes).ok())))
+            })
+            .collect();
+        let writes: Vec<(&str, &str, Option<&Json>)> = docs.iter().map(|(c, i, d)| (c.as_str(), i.as_str(), d.as_ref())).collect();
+        let _unique = match index::check_unique_all(self.storage, &writes) {
+            Ok(guard) => guard,
+            Err(e) => {
+                let _ = self.manager.abort(self.id);
+                return Err(e);
+            }
+        };
+        let entries = Space(index::INDEX_SPACE.into());
+        let mut events = Vec::with_capacity(docs.len());
+        for (collection, id, doc) in &docs {
+            let key = doc_key(collection, id);
+            let stored = self.storage.get(&data(), &key)?.map(|bytes| decode(&key, &bytes)).transpose()?;
+            let (gone, added) = index::entry_changes(self.storage, collection, id, stored.as_ref(), doc.as_ref())?;
+            for k in gone {
+                self.manager.delete(self.id, entries.clone(), k)?;
+            }
+            for k in added {
+                self.manager.put(self.id, entries.clone(), k, Vec::new())?;
+            }
+            let old = self.storage.get_versioned(&data(), &key, txn.timestamp)?;
+            let new = txn.write_set.get(&(data(), key)).cloned().flatten();
+            events.push((collection, id, old, new));
+        }
+        self.manager.commit(self.storage, self.id)?;
+        for (collection, id, old, new) in events {
+            let op = match (&old, &new) {
+                (None, _) => Operation::Insert,
+                (_, None) => Operation::Delete,
+                _ => Operation::Update,
+            };
+            publish(op, collection, id, old, new);
+        }
+        Ok(())
+    }
+
+    /// Discard every buffered write
+    pub fn abort(mut self) -> Result<()> {
+        self.done = true;
+        self.manager.abort(self.id)
+    }
+
+    /// Buffer document `id` going from `old` to `new`
+    fn write(&self, collection: &str, id: &str, old: Option<&Json>, new: Option<Json>) -> Result<()> {
+        self.write_as(collection, id, old, new, false)
+    }
+
+    /// `write`; a `loaded` document keeps the write timestamps it carries
+    fn write_as(&self, collection: &str, id: &str, old: Option<&Json>, new: Option<Json>, loaded: bool) -> Result<()> {
+        crate::not_a_view(self.storage, collection)?;
+        if capped::cap(self.storage, collection)?.is_some() {
+            return Err(DbError::Invalid(format!("capped collection {} can't be written in a transaction", collection)));
+        }
+        if timeseries::timeseries(self.storage, collection)?.is_some() {
+            return Err(DbError::Invalid(format!("time-series collection {} can't be written in a transaction", collection)));
+        }
+        let key = doc_key(collection, id);
+        match new {
+            Some(mut doc) => {
+                numbers::normalize_doc(&mut doc, &numbers::column_modes(self.storage, numbers::Source::Collection(collection))?);
+                if let Some(obj) = doc.as_object_mut() {
+                    obj.insert("_id".into(), Json::String(id.to_string()));
+                }
+                if loaded {
+                    crate::stamp_loaded_times(self.storage, collection, old, &mut doc)?;
+                } else {
+                    crate::stamp_times(self.storage, collection, old, &mut doc)?;
+                }
+                self.manager.put(self.id, data(), key, encode(collection, id, &doc)?)
+            }
+            None => self.manager.delete(self.id, data(), key),
+        }
+    }
+}
+
+impl<S: Storage + ?Sized> Drop for DocTxn<'_, S> {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.manager.abort(self.id);
+        }
+    }
+}
+
+fn data() -> Space {
+    Space(DATA_SPACE.into())
+}
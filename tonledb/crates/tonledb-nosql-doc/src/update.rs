@@ -0,0 +1,155 @@
+//! Update operators: `$set`, `$unset`, `$inc`, `$push`, `$pull` and `$addToSet`.
+//!
+//! An update maps each operator to an object of dotted field paths:
+//!
+//! ```text
+//! { "$set": { "status": "shipped", "address.city": "Oslo" },
+//!   "$inc": { "visits": 1 },
+//!   "$push": { "items": { "sku": "A1", "qty": 2 } },
+//!   "$addToSet": { "tags": { "$each": ["rust", "db"] } },
+//!   "$pull": { "scores": { "$lt": 50 }, "labels": "stale" } }
+//! ```
+//!
+//! `$set` writes a value and `$unset` removes a field (an array element it names
+//! becomes `null`). `$inc` adds to a number, starting a missing field at the
+//! increment; integers stay exact unless the sum overflows. `$set` and `$inc`
+//! create missing parent objects.
+//!
+//! `$push` appends; `$addToSet` appends values the array doesn't hold yet. Both
+//! take `{"$each": [...]}` for several values and create a missing array (and any
+//! missing parent objects). `$pull` removes every element equal to its value or,
+//! for an object, matching it as an `$elemMatch` filter. An operator on a field
+//! of the wrong kind fails the whole update and leaves the document unchanged.
+
+use serde_json::{Number, Value as Json};
+use tonledb_core::numbers;
+use tonledb_core::{DbError, Result};
+use crate::filter;
+
+/// Apply the update operators in `update` to `doc`. On error `doc` is left as it was.
+pub fn apply(doc: &mut Json, update: &Json) -> Result<()> {
+    let ops = update.as_object().ok_or_else(|| invalid("an update must be an object of operators"))?;
+    if ops.is_empty() {
+        return Err(invalid("an update needs at least one operator"));
+    }
+    let mut out = doc.clone();
+    for (op, fields) in ops {
+        let fields = fields.as_object().ok_or_else(|| invalid(format!("{} expects an object of fields", op)))?;
+        for (field, arg) in fields {
+            match op.as_str() {
+                "$set" => *value_mut(&mut out, field, true)?.expect("created") = arg.clone(),
+                "$unset" => {
+                    let (parent, last) = match field.rsplit_once('.') {
+                        Some((parent, last)) => (value_mut(&mut out, parent, false)?, last),
+                        None => (Some(&mut out), field.as_str()),
+                    };
+                    match parent {
+                        Some(Json::Object(map)) => {
+                            map.remove(last);
+                        }
+                        Some(Json::Array(items)) => {
+                            if let Some(v) = last.parse::<usize>().ok().and_then(|i| items.get_mut(i)) {
+                                *v = Json::Null;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                "$inc" => {
+                    let Json::Number(by) = arg else {
+                        return Err(invalid(format!("$inc of {} needs a number", field)));
+                    };
+                    let slot = value_mut(&mut out, field, true)?.expect("created");
+                    *slot = match &*slot {
+                        Json::Null => Json::Number(by.clone()),
+                        Json::Number(n) => Json::Number(add(n, by).ok_or_else(|| invalid(format!("$inc overflows {}", field)))?),
+                        _ => return Err(invalid(format!("{} is not a number", field))),
+                    };
+                }
+                "$push" => array_mut(&mut out, field, true)?.unwrap().extend(each(arg)),
+                "$addToSet" => {
+                    let items = array_mut(&mut out, field, true)?.unwrap();
+                    for v in each(arg) {
+                        if !items.iter().any(|x| numbers::values_equal(x, &v)) {
+                            items.push(v);
+                        }
+                    }
+                }
+                "$pull" => {
+                    let Some(items) = array_mut(&mut out, field, false)? else { continue };
+                    if arg.is_object() {
+                        let cond = filter::element_filter(arg)?;
+                        items.retain(|x| !cond.matches(x));
+                    } else {
+                        items.retain(|x| !numbers::values_equal(x, arg));
+                    }
+                }
+                other => return Err(invalid(format!("unknown update operator {}", other))),
+            }
+        }
+    }
+    *doc = out;
+    Ok(())
+}
+
+/// The values a `$push`/`$addToSet` argument adds
+fn each(arg: &Json) -> Vec<Json> {
+    match arg.get("$each").and_then(Json::as_array) {
+        Some(values) if arg.as_object().is_some_and(|o| o.len() == 1) => values.clone(),
+        _ => vec![arg.clone()],
+    }
+}
+
+/// `a + b`, exact for integers whose sum fits; `None` when it isn't finite
+fn add(a: &Number, b: &Number) -> Option<Number> {
+    match a.as_i64().zip(b.as_i64()).and_then(|(x, y)| x.checked_add(y)) {
+        Some(sum) => Some(sum.into()),
+        None => Number::from_f64(a.as_f64()? + b.as_f64()?),
+    }
+}
+
+/// The value at dotted `path`. When `create`, missing objects along the way and
+/// the value itself (as `null`) are added; otherwise a missing path gives `None`.
+/// Anything else in the way fails.
+fn value_mut<'a>(doc: &'a mut Json, path: &str, create: bool) -> Result<Option<&'a mut Json>> {
+    let blocked = || invalid(format!("{} runs through a value that isn't an object", path));
+    let mut cur = doc;
+    for seg in path.split('.') {
+        if create && cur.is_null() {
+            *cur = Json::Object(Default::default());
+        }
+        cur = match cur {
+            Json::Object(map) => {
+                if !create && !map.contains_key(seg) {
+                    return Ok(None);
+                }
+                map.entry(seg.to_string()).or_insert(Json::Null)
+            }
+            Json::Array(items) => match seg.parse::<usize>().ok().and_then(|i| items.get_mut(i)) {
+                Some(v) => v,
+                None if create => return Err(blocked()),
+                None => return Ok(None),
+            },
+            _ => return Err(blocked()),
+        };
+    }
+    Ok(Some(cur))
+}
+
+/// The array at dotted `path`, as `value_mut` finds it; when `create`, a missing
+/// array is added
+fn array_mut<'a>(doc: &'a mut Json, path: &str, create: bool) -> Result<Option<&'a mut Vec<Json>>> {
+    let not_array = || invalid(format!("{} is not an array", path));
+    let Some(cur) = value_mut(doc, path, create).map_err(|_| not_array())? else { return Ok(None) };
+    if create && cur.is_null() {
+        *cur = Json::Array(Vec::new());
+    }
+    match cur {
+        Json::Array(items) => Ok(Some(items)),
+        _ => Err(not_array()),
+    }
+}
+
+fn invalid(msg: impl Into<String>) -> DbError {
+    DbError::Invalid(msg.into())
+}
@@ -0,0 +1,168 @@
+//! Read-only views: a named, stored filter and projection over one collection.
+//!
+//! A view is registered in the catalog under `view/<name>`:
+//!
+//! ```text
+//! { "collection": "orders", "filter": { "status": "open" }, "fields": ["customer.name", "total"] }
+//! ```
+//!
+//! Querying a view queries its collection with the view's filter and the caller's
+//! combined, so indexes still apply, then keeps only `fields` (dotted paths, plus
+//! `_id`) of each match. Without `fields` whole documents are returned. A view's
+//! name can't be used for a collection: inserts into it fail.
+//!
+//! The reads of the crate (`get`, `list_page`, `find_where_page`, `query_page`,
+//! `count`, `distinct` and those built on them) resolve a view's name this way,
+//! so a view is read like a collection.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value as Json};
+use tonledb_core::{DbError, Result, Space, Storage};
+use crate::filter::{self, Filter};
+use crate::{DocPage, Paging};
+
+const CATALOG_SPACE: &str = "catalog";
+
+/// Definition of a view
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct View {
+    pub collection: String,
+    /// Filter in its JSON form (see `filter`); an empty object matches everything
+    #[serde(default = "match_all")]
+    pub filter: Json,
+    /// Fields to keep; `None` keeps whole documents
+    #[serde(default)]
+    pub fields: Option<Vec<String>>,
+}
+
+fn match_all() -> Json {
+    Json::Object(Map::new())
+}
+
+impl View {
+    /// The view's filter and `filter` as one, to run over `collection`
+    pub(crate) fn narrow(&self, filter: &Filter) -> Result<Filter> {
+        Ok(both(Filter::parse(&self.filter)?, filter.clone()))
+    }
+
+    /// `doc` as the view shows it
+    pub(crate) fn show(&self, doc: Json) -> Json {
+        match &self.fields {
+            Some(fields) => project(&doc, fields),
+            None => doc,
+        }
+    }
+
+    /// `page` of the view's collection as the view shows it
+    pub(crate) fn show_page(&self, page: DocPage) -> DocPage {
+        DocPage { docs: page.docs.into_iter().map(|doc| self.show(doc)).collect(), next: page.next }
+    }
+}
+
+/// Define (or redefine) view `name`. Fails with `DbError::Conflict` if a collection
+/// of that name exists, and `DbError::Invalid` if the filter doesn't parse.
+pub fn create_view<S: Storage + ?Sized>(storage: &S, name: &str, view: &View) -> Result<()> {
+    Filter::parse(&view.filter)?;
+    if view.collection == name {
+        return Err(DbError::Invalid(format!("view {} can't be over itself", name)));
+    }
+    if get_view(storage, &view.collection)?.is_some() {
+        return Err(DbError::Invalid(format!("{} is a view; views are over collections", view.collection)));
+    }
+    let catalog = Space(CATALOG_SPACE.into());
+    let has_docs = !storage.scan_prefix_page(&Space(crate::DATA_SPACE.into()), format!("doc/{}/", name).as_bytes(), None, 1)?.is_empty();
+    if has_docs || storage.get(&catalog, format!("col/{}", name).as_bytes())?.is_some() {
+        return Err(DbError::Conflict(format!("{} is a collection", name)));
+    }
+    let v = serde_json::json!({ "collection": view.collection, "filter": view.filter, "fields": view.fields });
+    storage.put(&catalog, key(name), serde_json::to_vec(&v).unwrap())
+}
+
+/// Remove view `name`; `false` if there was none
+pub fn drop_view<S: Storage + ?Sized>(storage: &S, name: &str) -> Result<bool> {
+    let catalog = Space(CATALOG_SPACE.into());
+    let existed = storage.get(&catalog, &key(name))?.is_some();
+    storage.del(&catalog, &key(name))?;
+    Ok(existed)
+}
+
+/// Definition of view `name`; `None` if there is no such view
+pub fn get_view<S: Storage + ?Sized>(storage: &S, name: &str) -> Result<Option<View>> {
+    let Some(bytes) = storage.get(&Space(CATALOG_SPACE.into()), &key(name))? else { return Ok(None) };
+    let v: Json = serde_json::from_slice(&bytes).map_err(|e| DbError::Corruption(format!("view/{}: {}", name, e)))?;
+    let fields = v.get("fields").and_then(Json::as_array).map(|fs| {
+        fs.iter().filter_map(Json::as_str).map(str::to_string).collect()
+    });
+    Ok(Some(View {
+        collection: v.get("collection").and_then(Json::as_str).unwrap_or_default().to_string(),
+        filter: v.get("filter").cloned().unwrap_or_else(match_all),
+        fields,
+    }))
+}
+
+/// Names of all views, sorted
+pub fn list_views<S: Storage + ?Sized>(storage: &S) -> Result<Vec<String>> {
+    Ok(storage
+        .scan_prefix(&Space(CATALOG_SPACE.into()), b"view/")?
+        .map(|(k, _)| String::from_utf8_lossy(&k[b"view/".len()..]).into_owned())
+        .collect())
+}
+
+/// Documents of view `name` matching `filter`, projected
+pub fn query_view<S: Storage + ?Sized>(storage: &S, name: &str, filter: &Filter) -> Result<Vec<Json>> {
+    Ok(query_view_page(storage, name, filter, &Paging::default())?.docs)
+}
+
+/// One page of `query_view`; `next` resumes it as in `crate::query_page`
+pub fn query_view_page<S: Storage + ?Sized>(storage: &S, name: &str, filter: &Filter, paging: &Paging) -> Result<DocPage> {
+    let view = get_view(storage, name)?.ok_or_else(|| DbError::NotFound(format!("view {}", name)))?;
+    crate::query_page(storage, &view.collection, &view.narrow(filter)?, true, paging).map(|page| view.show_page(page))
+}
+
+/// Number of documents of view `name` matching `filter`
+pub fn count_view<S: Storage + ?Sized>(storage: &S, name: &str, filter: &Filter) -> Result<usize> {
+    let view = get_view(storage, name)?.ok_or_else(|| DbError::NotFound(format!("view {}", name)))?;
+    crate::count(storage, &view.collection, &view.narrow(filter)?)
+}
+
+/// `a` and `b` as one flat conjunction, so `index::candidates` sees every clause
+fn both(a: Filter, b: Filter) -> Filter {
+    let clauses = |f: Filter| match f {
+        Filter::And(subs) => subs,
+        other => vec![other],
+    };
+    let mut all = clauses(a);
+    all.extend(clauses(b));
+    Filter::And(all)
+}
+
+/// `_id` and the `fields` present in `doc`, at the same paths
+fn project(doc: &Json, fields: &[String]) -> Json {
+    let mut out = Map::new();
+    for field in std::iter::once("_id").chain(fields.iter().map(String::as_str)) {
+        if let Some(value) = filter::lookup(doc, field) {
+            place(&mut out, &field.split('.').collect::<Vec<_>>(), value.clone());
+        }
+    }
+    Json::Object(out)
+}
+
+/// Set `path` in `obj` to `value`, creating the parent objects
+fn place(obj: &mut Map<String, Json>, path: &[&str], value: Json) {
+    match path {
+        [] => {}
+        [last] => {
+            obj.insert(last.to_string(), value);
+        }
+        [seg, rest @ ..] => {
+            // Parents found by `lookup` are objects, and so are their projections
+            if let Json::Object(next) = obj.entry(seg.to_string()).or_insert_with(|| Json::Object(Map::new())) {
+                place(next, rest, value);
+            }
+        }
+    }
+}
+
+fn key(name: &str) -> Vec<u8> {
+    format!("view/{}", name).into_bytes()
+}
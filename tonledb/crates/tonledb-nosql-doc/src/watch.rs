@@ -0,0 +1,160 @@
+//! Live watches on document collections.
+//!
+//! Document writes publish change events to the global changefeed manager; a
+//! watch is a changefeed scoped to one collection with an optional filter that is
+//! evaluated server-side, so subscribers only receive changes they care about.
+//! `watch` hands each change to a callback; `watch_collection` queues them for a
+//! `Stream` consumer instead, up to a fixed number. A consumer that falls that far
+//! behind is disconnected: its queue is dropped and its stream ends.
+
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use futures_core::Stream;
+use serde::Serialize;
+use serde_json::Value as Json;
+use tonledb_core::event_sourcing::{ChangeEvent, Operation, EVENT_MANAGER};
+use crate::filter::Filter;
+
+static WATCH_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// Changes a collection watch queues before it is disconnected
+pub const DEFAULT_WATCH_CAPACITY: usize = 10_000;
+
+/// A change to one document as delivered to watchers
+#[derive(Debug, Clone, Serialize)]
+pub struct DocChange {
+    pub operation: Operation,
+    pub collection: String,
+    pub id: String,
+    pub timestamp: u64,
+    /// The document after the change; for deletes, the document that was removed
+    pub doc: Option<Json>,
+}
+
+impl DocChange {
+    fn from_event(event: &ChangeEvent) -> Self {
+        Self {
+            operation: event.operation.clone(),
+            collection: event.table.clone(),
+            id: event.key.as_deref().map(|k| String::from_utf8_lossy(k).into_owned()).unwrap_or_default(),
+            timestamp: event.timestamp,
+            doc: affected_doc(event),
+        }
+    }
+}
+
+/// Watch `collection`, calling `callback` for every change whose document matches
+/// `filter` (all changes if `None`). Deletes are matched against the removed document.
+pub fn watch<F>(id: &str, collection: &str, filter: Option<Filter>, callback: F) -> Result<(), String>
+where
+    F: Fn(DocChange) + Send + Sync + 'static,
+{
+    let filter = filter.unwrap_or_else(Filter::all);
+    EVENT_MANAGER.register_filtered_feed(
+        id.to_string(),
+        Some(collection.to_string()),
+        None,
+        move |event| affected_doc(event).is_some_and(|doc| filter.matches(&doc)),
+        move |event| callback(DocChange::from_event(&event)),
+    )
+}
+
+/// Stop a watch; returns `false` if it was not registered
+pub fn unwatch(id: &str) -> bool {
+    EVENT_MANAGER.unregister_feed(id)
+}
+
+#[derive(Default)]
+struct Inbox {
+    changes: VecDeque<DocChange>,
+    waker: Option<Waker>,
+    /// Set once the queue overflowed; no changes are queued after that
+    disconnected: bool,
+}
+
+/// A live collection watch, consumed as a `Stream` of `DocChange`s; stops when
+/// dropped. Changes queue up until received, up to the watch's capacity.
+pub struct CollectionWatch {
+    id: String,
+    inbox: Arc<Mutex<Inbox>>,
+}
+
+/// Watch inserts, updates and deletes in `collection` whose document matches
+/// `filter` (all of them if `None`), queueing up to `DEFAULT_WATCH_CAPACITY` changes
+pub fn watch_collection(collection: &str, filter: Option<Filter>) -> Result<CollectionWatch, String> {
+    watch_collection_with_capacity(collection, filter, DEFAULT_WATCH_CAPACITY)
+}
+
+/// `watch_collection`, disconnecting the watcher once `capacity` changes wait to be received
+pub fn watch_collection_with_capacity(collection: &str, filter: Option<Filter>, capacity: usize) -> Result<CollectionWatch, String> {
+    let id = format!("doc-watch-{}", WATCH_SEQ.fetch_add(1, Ordering::Relaxed));
+    let inbox = Arc::new(Mutex::new(Inbox::default()));
+    let sink = inbox.clone();
+    watch(&id, collection, filter, move |change| {
+        let mut inbox = sink.lock().unwrap_or_else(|e| e.into_inner());
+        if inbox.disconnected {
+            return;
+        }
+        if inbox.changes.len() >= capacity {
+            inbox.changes.clear();
+            inbox.disconnected = true;
+        } else {
+            inbox.changes.push_back(change);
+        }
+        if let Some(w) = inbox.waker.take() {
+            w.wake();
+        }
+    })?;
+    Ok(CollectionWatch { id, inbox })
+}
+
+impl CollectionWatch {
+    /// The changefeed id this watch is registered under
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Whether the watcher fell too far behind and was disconnected
+    pub fn is_disconnected(&self) -> bool {
+        self.inbox.lock().unwrap_or_else(|e| e.into_inner()).disconnected
+    }
+
+    /// The next queued change, if any
+    pub fn try_recv(&self) -> Option<DocChange> {
+        self.inbox.lock().unwrap_or_else(|e| e.into_inner()).changes.pop_front()
+    }
+}
+
+impl Stream for CollectionWatch {
+    type Item = DocChange;
+
+    /// Ends only when the watcher is disconnected; drop the watch to stop it
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<DocChange>> {
+        let mut inbox = self.inbox.lock().unwrap_or_else(|e| e.into_inner());
+        match inbox.changes.pop_front() {
+            Some(c) => Poll::Ready(Some(c)),
+            None if inbox.disconnected => Poll::Ready(None),
+            None => {
+                inbox.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for CollectionWatch {
+    fn drop(&mut self) {
+        unwatch(&self.id);
+    }
+}
+
+fn affected_doc(event: &ChangeEvent) -> Option<Json> {
+    let bytes = match event.operation {
+        Operation::Delete => event.old_value.as_deref(),
+        _ => event.new_value.as_deref(),
+    }?;
+    serde_json::from_slice(bytes).ok()
+}
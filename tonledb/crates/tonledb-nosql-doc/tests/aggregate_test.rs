@@ -0,0 +1,73 @@
+//! Tests for aggregation pipelines and `$lookup` joins
+
+use serde_json::json;
+use tonledb_nosql_doc::aggregate::{aggregate, Pipeline};
+use tonledb_storage::arc_inmem_with_wal;
+
+#[test]
+fn test_lookup_joins_across_collections() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    tonledb_nosql_doc::insert(&*storage, "users", json!({"_id": "u1", "name": "ann"})).unwrap();
+    tonledb_nosql_doc::insert(&*storage, "users", json!({"_id": "u2", "name": "bob"})).unwrap();
+    tonledb_nosql_doc::insert(&*storage, "orders", json!({"n": 1, "owner": "u1", "open": true})).unwrap();
+    tonledb_nosql_doc::insert(&*storage, "orders", json!({"n": 2, "owner": "u3", "open": true})).unwrap();
+    tonledb_nosql_doc::insert(&*storage, "orders", json!({"n": 3, "owner": "u2", "open": false})).unwrap();
+
+    let pipeline = Pipeline::parse(&json!([
+        {"$match": {"open": true}},
+        {"$lookup": {"from": "users", "localField": "owner", "foreignField": "_id", "as": "user"}},
+    ])).unwrap();
+    let mut docs = aggregate(&*storage, "orders", &pipeline).unwrap();
+    docs.sort_by_key(|d| d["n"].as_i64());
+    assert_eq!(docs.len(), 2);
+    assert_eq!(docs[0]["user"], json!([{"_id": "u1", "name": "ann"}]));
+    assert_eq!(docs[1]["user"], json!([]));
+}
+
+#[test]
+fn test_lookup_on_array_field_joins_each_match_once() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    tonledb_nosql_doc::insert(&*storage, "tags", json!({"code": 1, "label": "a"})).unwrap();
+    tonledb_nosql_doc::insert(&*storage, "tags", json!({"code": 2.0, "label": "b"})).unwrap();
+    tonledb_nosql_doc::insert(&*storage, "posts", json!({"tags": [1, 2, 1, null]})).unwrap();
+
+    let pipeline = Pipeline::parse(&json!([
+        {"$lookup": {"from": "tags", "localField": "tags", "foreignField": "code", "as": "resolved"}},
+    ])).unwrap();
+    let docs = aggregate(&*storage, "posts", &pipeline).unwrap();
+    let mut labels: Vec<&str> = docs[0]["resolved"].as_array().unwrap().iter().map(|t| t["label"].as_str().unwrap()).collect();
+    labels.sort();
+    assert_eq!(labels, vec!["a", "b"]);
+}
+
+#[test]
+fn test_lookup_joins_on_nested_fields() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    tonledb_nosql_doc::insert(&*storage, "users", json!({"name": "ann", "ids": {"login": "u1"}})).unwrap();
+    tonledb_nosql_doc::insert(&*storage, "orders", json!({"meta": {"owner": "u1"}})).unwrap();
+
+    let pipeline = Pipeline::parse(&json!([
+        {"$lookup": {"from": "users", "localField": "meta.owner", "foreignField": "ids.login", "as": "user"}},
+    ])).unwrap();
+    let docs = aggregate(&*storage, "orders", &pipeline).unwrap();
+    assert_eq!(docs[0]["user"][0]["name"], "ann");
+}
+
+#[test]
+fn test_pipeline_parse_errors() {
+    assert!(Pipeline::parse(&json!({"$match": {}})).is_err());
+    assert!(Pipeline::parse(&json!([{"$group": {}}])).is_err());
+    assert!(Pipeline::parse(&json!([{"$lookup": {"from": "x"}}])).is_err());
+    assert!(Pipeline::parse(&json!([{"$skip": -1}])).is_err());
+    assert!(Pipeline::parse(&json!([{"$skip": 1, "$limit": 2}])).is_err());
+}
+
+#[test]
+fn test_skip_and_limit() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    for n in 0..5 {
+        tonledb_nosql_doc::insert(&*storage, "nums", json!({"n": n})).unwrap();
+    }
+    let pipeline = Pipeline::parse(&json!([{"$skip": 1}, {"$limit": 3}])).unwrap();
+    assert_eq!(aggregate(&*storage, "nums", &pipeline).unwrap().len(), 3);
+}
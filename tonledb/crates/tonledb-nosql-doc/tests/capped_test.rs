@@ -0,0 +1,90 @@
+//! Tests for capped collections
+
+use serde_json::json;
+use tonledb_core::DbError;
+use tonledb_nosql_doc::capped::{self, Cap};
+use tonledb_storage::arc_inmem_with_wal;
+
+fn ns(storage: &dyn tonledb_core::Storage, col: &str) -> Vec<i64> {
+    tonledb_nosql_doc::list_all(storage, col, true).unwrap().iter().map(|d| d["n"].as_i64().unwrap()).collect()
+}
+
+#[test]
+fn test_max_docs_evicts_the_oldest() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    capped::create_capped_collection(&*storage, "log", Cap { max_docs: Some(3), max_bytes: None }).unwrap();
+    let ids: Vec<String> = (1..=5).map(|n| tonledb_nosql_doc::insert(&*storage, "log", json!({"n": n})).unwrap()).collect();
+    assert!(ids.windows(2).all(|w| w[0] < w[1]));
+    assert_eq!(ns(&*storage, "log"), vec![3, 4, 5]);
+
+    // Deletes free room without evicting
+    assert!(tonledb_nosql_doc::delete(&*storage, "log", &ids[3]).unwrap());
+    tonledb_nosql_doc::insert(&*storage, "log", json!({"n": 6})).unwrap();
+    assert_eq!(ns(&*storage, "log"), vec![3, 5, 6]);
+    assert_eq!(tonledb_nosql_doc::collection_stats(&*storage, "log").unwrap().documents, 3);
+}
+
+#[test]
+fn test_max_bytes_evicts_until_the_collection_fits() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    capped::create_capped_collection(&*storage, "feed", Cap { max_docs: None, max_bytes: Some(200) }).unwrap();
+    for n in 1..=10 {
+        tonledb_nosql_doc::insert(&*storage, "feed", json!({"n": n, "pad": "x".repeat(30)})).unwrap();
+        assert!(tonledb_nosql_doc::collection_stats(&*storage, "feed").unwrap().bytes <= 200);
+    }
+    let kept = ns(&*storage, "feed");
+    assert_eq!(*kept.last().unwrap(), 10);
+    assert!(kept.windows(2).all(|w| w[1] == w[0] + 1));
+
+    let huge = tonledb_nosql_doc::insert(&*storage, "feed", json!({"pad": "x".repeat(300)}));
+    assert!(matches!(huge, Err(DbError::Invalid(_))));
+    assert_eq!(ns(&*storage, "feed"), kept);
+}
+
+#[test]
+fn test_capped_restrictions() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    assert!(capped::create_capped_collection(&*storage, "c", Cap::default()).is_err());
+    tonledb_nosql_doc::insert(&*storage, "full", json!({"n": 1})).unwrap();
+    let res = capped::create_capped_collection(&*storage, "full", Cap { max_docs: Some(1), max_bytes: None });
+    assert!(matches!(res, Err(DbError::Conflict(_))));
+    assert_eq!(capped::cap(&*storage, "full").unwrap(), None);
+
+    let cap = Cap { max_docs: Some(2), max_bytes: None };
+    capped::create_capped_collection(&*storage, "c", cap).unwrap();
+    assert_eq!(capped::cap(&*storage, "c").unwrap(), Some(cap));
+    assert!(tonledb_nosql_doc::insert_with_ttl(&*storage, "c", json!({}), Some(60)).is_err());
+    assert!(tonledb_nosql_doc::update_merge(&*storage, "c", "mine", json!({}), true).is_err());
+    assert!(matches!(tonledb_nosql_doc::insert_with_id(&*storage, "c", "mine", json!({})), Err(DbError::Invalid(_))));
+    let txn = tonledb_nosql_doc::txn::begin(&*storage).unwrap();
+    assert!(txn.insert("c", json!({})).is_err());
+    txn.abort().unwrap();
+
+    // Dropping forgets the cap
+    tonledb_nosql_doc::drop_collection(&*storage, "c").unwrap();
+    assert_eq!(capped::cap(&*storage, "c").unwrap(), None);
+}
+
+#[test]
+fn test_tally_follows_updates_and_survives_replay() {
+    let path = std::env::temp_dir().join(format!("tonledb-capped-{}.wal", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let wal = path.to_str().unwrap();
+    let storage = arc_inmem_with_wal(Some(wal), 1000);
+    capped::create_capped_collection(&*storage, "events", Cap { max_docs: None, max_bytes: Some(250) }).unwrap();
+    let ids: Vec<String> = (1..=3).map(|n| tonledb_nosql_doc::insert(&*storage, "events", json!({"n": n, "pad": "x".repeat(30)})).unwrap()).collect();
+
+    // Growing the oldest document pushes the collection over; that document goes first
+    assert_eq!(ns(&*storage, "events"), vec![1, 2, 3]);
+    assert!(tonledb_nosql_doc::update_merge(&*storage, "events", &ids[0], json!({"pad": "x".repeat(120)}), false).unwrap());
+    assert_eq!(ns(&*storage, "events"), vec![2, 3]);
+    drop(storage);
+
+    let storage = arc_inmem_with_wal(Some(wal), 1000);
+    for n in 4..=8 {
+        tonledb_nosql_doc::insert(&*storage, "events", json!({"n": n, "pad": "x".repeat(30)})).unwrap();
+        assert!(tonledb_nosql_doc::collection_stats(&*storage, "events").unwrap().bytes <= 250);
+    }
+    assert_eq!(ns(&*storage, "events"), vec![6, 7, 8]);
+    std::fs::remove_file(&path).unwrap();
+}
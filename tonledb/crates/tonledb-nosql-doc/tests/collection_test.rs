@@ -0,0 +1,123 @@
+//! Tests for renaming, dropping and listing collections, and documents with chosen ids
+
+use serde_json::json;
+use tonledb_core::numbers::{self, NumberMode, Source};
+use tonledb_core::{DbError, Space, Storage};
+use tonledb_nosql_doc::filter::Filter;
+use tonledb_nosql_doc::index;
+use tonledb_storage::arc_inmem_with_wal;
+
+fn index_entries(storage: &dyn Storage) -> usize {
+    storage.scan_prefix(&Space("doc_index".into()), b"").unwrap().count()
+}
+
+#[test]
+fn test_rename_moves_documents_catalog_and_indexes() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    tonledb_nosql_doc::create_collection(&*storage, "users").unwrap();
+    index::create_unique_index(&*storage, "users", "email").unwrap();
+    let id = tonledb_nosql_doc::insert(&*storage, "users", json!({"email": "a@x"})).unwrap();
+    tonledb_nosql_doc::insert(&*storage, "users", json!({"email": "b@x"})).unwrap();
+
+    assert_eq!(tonledb_nosql_doc::rename_collection(&*storage, "users", "people").unwrap(), 2);
+    assert!(tonledb_nosql_doc::list_all(&*storage, "users", true).unwrap().is_empty());
+    assert!(index::indexed_fields(&*storage, "users").unwrap().is_empty());
+    assert_eq!(tonledb_nosql_doc::get(&*storage, "people", &id, true).unwrap().unwrap()["email"], "a@x");
+    let catalog = Space("catalog".into());
+    assert!(storage.get(&catalog, b"col/users").unwrap().is_none());
+    assert!(storage.get(&catalog, b"col/people").unwrap().is_some());
+
+    // The unique index moved with its entries
+    assert!(index::is_unique(&*storage, "people", "email").unwrap());
+    assert_eq!(index_entries(&*storage), 2);
+    let filter = Filter::parse(&json!({"email": "b@x"})).unwrap();
+    assert_eq!(index::candidates(&*storage, "people", &filter).unwrap().unwrap().len(), 1);
+    let dup = tonledb_nosql_doc::insert(&*storage, "people", json!({"email": "a@x"}));
+    assert!(matches!(dup, Err(DbError::Conflict(_))));
+}
+
+#[test]
+fn test_rename_rejects_missing_source_and_existing_target() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    assert!(matches!(tonledb_nosql_doc::rename_collection(&*storage, "nope", "other"), Err(DbError::NotFound(_))));
+
+    tonledb_nosql_doc::insert(&*storage, "a", json!({"n": 1})).unwrap();
+    tonledb_nosql_doc::create_collection(&*storage, "b").unwrap();
+    assert!(matches!(tonledb_nosql_doc::rename_collection(&*storage, "a", "b"), Err(DbError::Conflict(_))));
+    assert_eq!(tonledb_nosql_doc::list_all(&*storage, "a", true).unwrap().len(), 1);
+}
+
+
+#[test]
+fn test_list_collections() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    tonledb_nosql_doc::create_collection(&*storage, "empty").unwrap();
+    for n in 0..3 {
+        tonledb_nosql_doc::insert(&*storage, "users", json!({"n": n})).unwrap();
+    }
+    tonledb_nosql_doc::insert(&*storage, "a", json!({"n": 1})).unwrap();
+    assert_eq!(tonledb_nosql_doc::list_collections(&*storage).unwrap(), ["a", "empty", "users"]);
+
+    tonledb_nosql_doc::drop_collection(&*storage, "users").unwrap();
+    assert_eq!(tonledb_nosql_doc::list_collections(&*storage).unwrap(), ["a", "empty"]);
+}
+
+#[test]
+fn test_recreated_collection_starts_without_number_modes() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    tonledb_nosql_doc::create_collection(&*storage, "orders").unwrap();
+    numbers::set_column_mode(&*storage, Source::Collection("orders"), "amount", Some(NumberMode::Decimal)).unwrap();
+    tonledb_nosql_doc::drop_collection(&*storage, "orders").unwrap();
+    tonledb_nosql_doc::create_collection(&*storage, "orders").unwrap();
+    assert!(numbers::column_modes(&*storage, Source::Collection("orders")).unwrap().is_empty());
+}
+
+#[test]
+fn test_insert_with_id() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    tonledb_nosql_doc::insert_with_id(&*storage, "users", "ann", json!({"_id": "other", "n": 1})).unwrap();
+    assert_eq!(tonledb_nosql_doc::get(&*storage, "users", "ann", true).unwrap().unwrap(), json!({"_id": "ann", "n": 1}));
+    let dup = tonledb_nosql_doc::insert_with_id(&*storage, "users", "ann", json!({"n": 2}));
+    assert!(matches!(dup, Err(DbError::Conflict(_))));
+    assert_eq!(tonledb_nosql_doc::get(&*storage, "users", "ann", true).unwrap().unwrap()["n"], 1);
+}
+
+#[test]
+fn test_concurrent_inserts_under_one_id_let_one_through() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    for round in 0..20 {
+        let id = format!("doc{}", round);
+        let results: Vec<_> = (0..4)
+            .map(|n| {
+                let (storage, id) = (storage.clone(), id.clone());
+                std::thread::spawn(move || tonledb_nosql_doc::insert_with_id(&*storage, "users", &id, json!({"n": n})))
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|t| t.join().unwrap())
+            .collect();
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+        assert_eq!(results.iter().filter(|r| matches!(r, Err(DbError::Conflict(_)))).count(), 3);
+    }
+}
+
+#[test]
+fn test_concurrent_renames_to_one_name_let_one_through() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    for round in 0..20 {
+        let (a, b, to) = (format!("ra{}", round), format!("rb{}", round), format!("rc{}", round));
+        tonledb_nosql_doc::insert(&*storage, &a, json!({"n": 1})).unwrap();
+        tonledb_nosql_doc::insert(&*storage, &b, json!({"n": 2})).unwrap();
+        let results: Vec<_> = [a, b]
+            .map(|from| {
+                let (storage, to) = (storage.clone(), to.clone());
+                std::thread::spawn(move || tonledb_nosql_doc::rename_collection(&*storage, &from, &to))
+            })
+            .into_iter()
+            .map(|t| t.join().unwrap())
+            .collect();
+        assert_eq!(results.iter().filter(|r| matches!(r, Ok(1))).count(), 1);
+        assert_eq!(results.iter().filter(|r| matches!(r, Err(DbError::Conflict(_)))).count(), 1);
+        assert_eq!(tonledb_nosql_doc::list_all(&*storage, &to, true).unwrap().len(), 1);
+    }
+}
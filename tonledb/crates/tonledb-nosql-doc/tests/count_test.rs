@@ -0,0 +1,32 @@
+//! Tests for count and distinct
+
+use serde_json::json;
+use tonledb_nosql_doc::filter::Filter;
+use tonledb_nosql_doc::index;
+use tonledb_storage::arc_inmem_with_wal;
+
+#[test]
+fn test_count_and_distinct_with_and_without_index() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    let col = "products";
+    tonledb_nosql_doc::insert(&*storage, col, json!({"kind": "book", "price": 10, "tags": ["new", "sale"]})).unwrap();
+    tonledb_nosql_doc::insert(&*storage, col, json!({"kind": "book", "price": 10.0, "tags": ["sale"]})).unwrap();
+    tonledb_nosql_doc::insert(&*storage, col, json!({"kind": "pen", "price": 2})).unwrap();
+    tonledb_nosql_doc::insert_with_ttl(&*storage, col, json!({"kind": "book", "price": 99}), Some(0)).unwrap();
+
+    let books = Filter::parse(&json!({"kind": "book"})).unwrap();
+    for indexed in [false, true] {
+        if indexed {
+            index::create_index(&*storage, col, "kind").unwrap();
+            assert!(index::candidates(&*storage, col, &books).unwrap().is_some());
+        }
+        assert_eq!(tonledb_nosql_doc::count(&*storage, col, &books).unwrap(), 2);
+        assert_eq!(tonledb_nosql_doc::count(&*storage, col, &Filter::all()).unwrap(), 3);
+        // 10 and 10.0 are one value; which of the two is kept depends on document order
+        let prices = tonledb_nosql_doc::distinct(&*storage, col, "price", &books).unwrap();
+        assert_eq!(prices.len(), 1);
+        assert_eq!(prices[0].as_f64(), Some(10.0));
+        assert_eq!(tonledb_nosql_doc::distinct(&*storage, col, "tags", &Filter::all()).unwrap(), vec![json!("new"), json!("sale")]);
+        assert_eq!(tonledb_nosql_doc::distinct(&*storage, col, "kind", &Filter::all()).unwrap().len(), 2);
+    }
+}
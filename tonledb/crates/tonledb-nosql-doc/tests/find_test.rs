@@ -0,0 +1,71 @@
+//! Tests for JSON filter queries
+
+use serde_json::json;
+use tonledb_nosql_doc::filter::{self, Filter};
+use tonledb_storage::arc_inmem_with_wal;
+
+fn ns(docs: &[serde_json::Value]) -> Vec<i64> {
+    let mut ns: Vec<i64> = docs.iter().map(|d| d["n"].as_i64().unwrap()).collect();
+    ns.sort();
+    ns
+}
+
+#[test]
+fn test_find_with_operators_and_nested_paths() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    let col = "people";
+    tonledb_nosql_doc::insert(&*storage, col, json!({"n": 1, "age": 17, "address": {"city": "Oslo"}})).unwrap();
+    tonledb_nosql_doc::insert(&*storage, col, json!({"n": 2, "age": 30, "address": {"city": "Bergen"}, "email": "b@x"})).unwrap();
+    tonledb_nosql_doc::insert(&*storage, col, json!({"n": 3, "age": 45, "lines": [{"sku": "A1"}]})).unwrap();
+
+    let find = |f: serde_json::Value| ns(&tonledb_nosql_doc::find(&*storage, col, &f).unwrap());
+    assert_eq!(find(json!({"age": {"$gt": 17, "$lt": 45}})), vec![2]);
+    assert_eq!(find(json!({"address.city": {"$in": ["Oslo", "Bergen"]}})), vec![1, 2]);
+    assert_eq!(find(json!({"address.city": {"$nin": ["Oslo"]}})), vec![2, 3]);
+    assert_eq!(find(json!({"email": {"$exists": true}})), vec![2]);
+    assert_eq!(find(json!({"email": {"$exists": false}})), vec![1, 3]);
+    assert_eq!(find(json!({"lines.0.sku": "A1"})), vec![3]);
+    assert_eq!(find(json!({"$or": [{"age": {"$eq": 17}}, {"$and": [{"age": {"$gte": 40}}, {"lines": {"$exists": true}}]}]})), vec![1, 3]);
+
+    assert!(tonledb_nosql_doc::find(&*storage, col, &json!({"age": {"$exists": 1}})).is_err());
+    assert!(tonledb_nosql_doc::find(&*storage, col, &json!({"age": {"$nin": 1}})).is_err());
+}
+
+#[test]
+fn test_lookup_walks_objects_and_arrays() {
+    let doc = json!({"a": {"b": [10, {"c": true}]}});
+    assert_eq!(filter::lookup(&doc, "a.b.0"), Some(&json!(10)));
+    assert_eq!(filter::lookup(&doc, "a.b.1.c"), Some(&json!(true)));
+    assert_eq!(filter::lookup(&doc, "a.b.2"), None);
+    assert_eq!(filter::lookup(&doc, "a.x"), None);
+    assert!(Filter::parse(&json!({"a.b.1.c": true})).unwrap().matches(&doc));
+}
+
+#[test]
+fn test_equality_matches_array_elements() {
+    let doc = json!({"tags": ["rust", "db"], "n": 1});
+    let matches = |f: serde_json::Value| Filter::parse(&f).unwrap().matches(&doc);
+    assert!(matches(json!({"tags": "db"})));
+    assert!(matches(json!({"tags": ["rust", "db"]})));
+    assert!(matches(json!({"tags": {"$in": ["go", "rust"]}})));
+    assert!(!matches(json!({"tags": {"$nin": ["db"]}})));
+    assert!(!matches(json!({"tags": {"$ne": "rust"}})));
+    assert!(matches(json!({"tags": {"$ne": "go"}})));
+}
+
+#[test]
+fn test_find_eq_and_indexes_take_dotted_paths() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    let col = "people";
+    tonledb_nosql_doc::insert(&*storage, col, json!({"n": 1, "address": {"city": "Oslo"}})).unwrap();
+    tonledb_nosql_doc::insert(&*storage, col, json!({"n": 2, "address": {"city": "Bergen"}})).unwrap();
+    tonledb_nosql_doc::insert(&*storage, col, json!({"n": 3, "address.city": "Oslo"})).unwrap();
+
+    let oslo = json!("Oslo");
+    assert_eq!(ns(&tonledb_nosql_doc::find_eq(&*storage, col, "address.city", &oslo, true).unwrap()), vec![1]);
+
+    assert_eq!(tonledb_nosql_doc::index::create_index(&*storage, col, "address.city").unwrap(), 2);
+    let filter = Filter::parse(&json!({"address.city": "Oslo"})).unwrap();
+    assert_eq!(tonledb_nosql_doc::index::candidates(&*storage, col, &filter).unwrap().unwrap().len(), 1);
+    assert_eq!(ns(&tonledb_nosql_doc::query(&*storage, col, &filter, true).unwrap()), vec![1]);
+}
@@ -0,0 +1,77 @@
+//! Tests for JSON Lines import
+
+use std::io::Write;
+use serde_json::json;
+use tonledb_core::DbError;
+use tonledb_nosql_doc::import::{import_collection_jsonl, ImportOptions, ImportProgress};
+use tonledb_storage::arc_inmem_with_wal;
+
+fn jsonl(name: &str, lines: &[String]) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("tonledb-import-{}-{}.jsonl", name, std::process::id()));
+    let mut f = std::fs::File::create(&path).unwrap();
+    for line in lines {
+        writeln!(f, "{}", line).unwrap();
+    }
+    path
+}
+
+#[test]
+fn test_import_batches_and_reports_progress() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    let mut lines: Vec<String> = (0..5).map(|n| json!({"_id": format!("d{}", n), "n": n}).to_string()).collect();
+    // Export records and documents without an id are accepted too
+    lines.push(json!({"cursor": "ZDU", "row": {"_id": "d5", "n": 5}}).to_string());
+    lines.push(String::new());
+    lines.push(json!({"n": 6}).to_string());
+    let path = jsonl("batches", &lines);
+
+    let mut seen = Vec::new();
+    let opts = ImportOptions { batch_size: 3, upsert: false };
+    let done = import_collection_jsonl(&*storage, "items", &path, &opts, |p| seen.push(*p)).unwrap();
+    assert_eq!(done, ImportProgress { lines: 8, imported: 7, replaced: 0 });
+    assert_eq!(seen.iter().map(|p| p.imported).collect::<Vec<_>>(), vec![3, 6, 7]);
+    assert_eq!(tonledb_nosql_doc::get(&*storage, "items", "d5", true).unwrap().unwrap()["n"], json!(5));
+    assert_eq!(tonledb_nosql_doc::list_all(&*storage, "items", true).unwrap().len(), 7);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_import_upsert_and_conflicts() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    let txn = tonledb_nosql_doc::txn::begin(&*storage).unwrap();
+    txn.upsert("items", "a", json!({"v": 0})).unwrap();
+    txn.commit().unwrap();
+
+    let path = jsonl("upsert", &[json!({"_id": "a", "v": 1}).to_string(), json!({"_id": "b", "v": 1}).to_string()]);
+    let res = import_collection_jsonl(&*storage, "items", &path, &ImportOptions::default(), |_| {});
+    assert!(matches!(res, Err(DbError::Conflict(_))));
+    assert!(tonledb_nosql_doc::get(&*storage, "items", "b", true).unwrap().is_none());
+
+    let opts = ImportOptions { upsert: true, ..Default::default() };
+    let done = import_collection_jsonl(&*storage, "items", &path, &opts, |_| {}).unwrap();
+    assert_eq!((done.imported, done.replaced), (2, 1));
+    assert_eq!(tonledb_nosql_doc::get(&*storage, "items", "a", true).unwrap().unwrap()["v"], json!(1));
+
+    let bad = jsonl("bad", &[json!({"_id": "c"}).to_string(), "{not json".to_string()]);
+    assert!(matches!(import_collection_jsonl(&*storage, "items", &bad, &opts, |_| {}), Err(DbError::Invalid(_))));
+    assert!(import_collection_jsonl(&*storage, "items", "/nonexistent/file.jsonl", &opts, |_| {}).is_err());
+    std::fs::remove_file(path).unwrap();
+    std::fs::remove_file(bad).unwrap();
+}
+
+#[test]
+fn test_import_keeps_write_timestamps() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    tonledb_nosql_doc::set_write_timestamps(&*storage, "items", true).unwrap();
+    let path = jsonl("timestamps", &[
+        json!({"_id": "old", "_created_at": 1000, "_updated_at": 2000}).to_string(),
+        json!({"_id": "new"}).to_string(),
+    ]);
+    import_collection_jsonl(&*storage, "items", &path, &ImportOptions::default(), |_| {}).unwrap();
+    let old = tonledb_nosql_doc::get(&*storage, "items", "old", true).unwrap().unwrap();
+    assert_eq!((old["_created_at"].clone(), old["_updated_at"].clone()), (json!(1000), json!(2000)));
+    // Documents exported without timestamps are stamped with the time of the import
+    let new = tonledb_nosql_doc::get(&*storage, "items", "new", true).unwrap().unwrap();
+    assert!(new["_created_at"].as_u64().unwrap() > 2000);
+    std::fs::remove_file(path).unwrap();
+}
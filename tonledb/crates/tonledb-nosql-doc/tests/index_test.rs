@@ -0,0 +1,60 @@
+//! Tests for multikey field indexes
+
+use serde_json::json;
+use tonledb_core::{Space, Storage};
+use tonledb_nosql_doc::filter::Filter;
+use tonledb_nosql_doc::index;
+use tonledb_storage::arc_inmem_with_wal;
+
+fn entries(storage: &dyn Storage) -> usize {
+    storage.scan_prefix(&Space("doc_index".into()), b"").unwrap().count()
+}
+
+#[test]
+fn test_array_elements_get_one_entry_each() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    tonledb_nosql_doc::insert(&*storage, "posts", json!({"n": 1, "tags": ["rust", "db", "rust"]})).unwrap();
+    assert_eq!(index::create_index(&*storage, "posts", "tags").unwrap(), 2);
+    tonledb_nosql_doc::insert(&*storage, "posts", json!({"n": 2, "tags": ["db"]})).unwrap();
+    tonledb_nosql_doc::insert(&*storage, "posts", json!({"n": 3, "tags": "db"})).unwrap();
+    assert_eq!(entries(&*storage), 4);
+
+    let filter = Filter::parse(&json!({"tags": {"$contains": "db"}})).unwrap();
+    assert_eq!(index::candidates(&*storage, "posts", &filter).unwrap().unwrap().len(), 3);
+    let mut docs = tonledb_nosql_doc::query(&*storage, "posts", &filter, true).unwrap();
+    docs.sort_by_key(|d| d["n"].as_i64());
+    assert_eq!(docs.iter().map(|d| d["n"].as_i64().unwrap()).collect::<Vec<_>>(), vec![1, 2, 3]);
+
+    let rust = Filter::parse(&json!({"tags": {"$contains": "rust"}})).unwrap();
+    assert_eq!(tonledb_nosql_doc::query(&*storage, "posts", &rust, true).unwrap().len(), 1);
+}
+
+#[test]
+fn test_writes_keep_entries_in_sync() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    index::create_index(&*storage, "posts", "tags").unwrap();
+    let id = tonledb_nosql_doc::insert(&*storage, "posts", json!({"tags": ["a", "b"]})).unwrap();
+    tonledb_nosql_doc::update_merge(&*storage, "posts", &id, json!({"tags": ["b", "c"]}), false).unwrap();
+
+    let a = Filter::parse(&json!({"tags": {"$contains": "a"}})).unwrap();
+    let c = Filter::parse(&json!({"tags": {"$in": ["c", "z"]}})).unwrap();
+    assert!(tonledb_nosql_doc::query(&*storage, "posts", &a, true).unwrap().is_empty());
+    assert_eq!(tonledb_nosql_doc::query(&*storage, "posts", &c, true).unwrap().len(), 1);
+
+    tonledb_nosql_doc::delete(&*storage, "posts", &id).unwrap();
+    assert_eq!(entries(&*storage), 0);
+}
+
+#[test]
+fn test_unindexed_filters_fall_back_to_a_scan() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    index::create_index(&*storage, "posts", "tags").unwrap();
+    tonledb_nosql_doc::insert(&*storage, "posts", json!({"tags": ["a"], "score": 3})).unwrap();
+    let filter = Filter::parse(&json!({"score": {"$gt": 1}})).unwrap();
+    assert!(index::candidates(&*storage, "posts", &filter).unwrap().is_none());
+    assert_eq!(tonledb_nosql_doc::query(&*storage, "posts", &filter, true).unwrap().len(), 1);
+
+    tonledb_nosql_doc::drop_collection(&*storage, "posts").unwrap();
+    assert!(index::indexed_fields(&*storage, "posts").unwrap().is_empty());
+    assert_eq!(entries(&*storage), 0);
+}
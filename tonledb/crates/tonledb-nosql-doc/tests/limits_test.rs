@@ -0,0 +1,50 @@
+//! Tests for the document size limit and strict decoding
+
+use serde_json::json;
+use tonledb_core::{DbError, Space, Storage};
+use tonledb_nosql_doc::filter::Filter;
+use tonledb_storage::arc_inmem_with_wal;
+
+#[test]
+fn test_max_doc_bytes_rejects_large_writes() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    let id = tonledb_nosql_doc::insert(&*storage, "notes", json!({"body": "short"})).unwrap();
+
+    tonledb_nosql_doc::set_max_doc_bytes(Some(100));
+    let big = json!({"body": "x".repeat(200)});
+    let res = tonledb_nosql_doc::insert(&*storage, "notes", big.clone());
+    assert!(matches!(res, Err(DbError::Invalid(_))));
+    assert!(matches!(tonledb_nosql_doc::replace(&*storage, "notes", &id, big.clone()), Err(DbError::Invalid(_))));
+    assert!(matches!(tonledb_nosql_doc::update_merge(&*storage, "notes", &id, big.clone(), false), Err(DbError::Invalid(_))));
+    let txn = tonledb_nosql_doc::txn::begin(&*storage).unwrap();
+    assert!(matches!(txn.insert("notes", big.clone()), Err(DbError::Invalid(_))));
+    txn.abort().unwrap();
+    assert_eq!(tonledb_nosql_doc::get(&*storage, "notes", &id, true).unwrap().unwrap()["body"], "short");
+    assert_eq!(tonledb_nosql_doc::list_all(&*storage, "notes", true).unwrap().len(), 1);
+
+    tonledb_nosql_doc::set_max_doc_bytes(None);
+    assert_eq!(tonledb_nosql_doc::max_doc_bytes(), None);
+    tonledb_nosql_doc::insert(&*storage, "notes", big).unwrap();
+}
+
+#[test]
+fn test_undecodable_documents_are_corruption() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    tonledb_nosql_doc::insert(&*storage, "notes", json!({"n": 1})).unwrap();
+    storage.put(&Space("data".into()), b"doc/notes/bad".to_vec(), b"{not json".to_vec()).unwrap();
+
+    let corrupt = |res: tonledb_core::Result<_>| match res {
+        Err(DbError::Corruption(msg)) => assert!(msg.contains("doc/notes/bad"), "{}", msg),
+        Err(e) => panic!("expected corruption, got {}", e),
+        Ok(_) => panic!("expected corruption"),
+    };
+    corrupt(tonledb_nosql_doc::get(&*storage, "notes", "bad", true).map(drop));
+    corrupt(tonledb_nosql_doc::list_all(&*storage, "notes", true).map(drop));
+    corrupt(tonledb_nosql_doc::find_eq(&*storage, "notes", "n", &json!(1), true).map(drop));
+    corrupt(tonledb_nosql_doc::count(&*storage, "notes", &Filter::all()).map(drop));
+    corrupt(tonledb_nosql_doc::update_merge(&*storage, "notes", "bad", json!({"n": 2}), false).map(drop));
+
+    // The broken document can still be removed
+    assert!(tonledb_nosql_doc::delete(&*storage, "notes", "bad").unwrap());
+    assert_eq!(tonledb_nosql_doc::list_all(&*storage, "notes", true).unwrap().len(), 1);
+}
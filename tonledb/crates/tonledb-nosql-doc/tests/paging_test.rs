@@ -0,0 +1,70 @@
+//! Tests for paged listing and finds
+
+use serde_json::json;
+use tonledb_nosql_doc::filter::Filter;
+use tonledb_nosql_doc::{index, DocPage, Paging};
+use tonledb_storage::arc_inmem_with_wal;
+
+fn ns(page: &DocPage) -> Vec<i64> {
+    page.docs.iter().map(|d| d["n"].as_i64().unwrap()).collect()
+}
+
+#[test]
+fn test_cursor_pages_walk_the_collection_once() {
+    let storage = arc_inmem_with_wal(None, 10_000);
+    for n in 0..600 {
+        tonledb_nosql_doc::insert(&*storage, "items", json!({"n": n, "even": n % 2 == 0})).unwrap();
+    }
+    let mut seen = Vec::new();
+    let mut paging = Paging { limit: Some(70), ..Paging::default() };
+    loop {
+        let page = tonledb_nosql_doc::find_eq_page(&*storage, "items", "even", &json!(true), true, &paging).unwrap();
+        assert!(page.docs.len() <= 70);
+        seen.extend(ns(&page));
+        match page.next {
+            Some(id) => paging.after = Some(id),
+            None => break,
+        }
+    }
+    seen.sort();
+    assert_eq!(seen, (0..600).filter(|n| n % 2 == 0).collect::<Vec<_>>());
+    assert_eq!(tonledb_nosql_doc::list_all(&*storage, "items", true).unwrap().len(), 600);
+}
+
+#[test]
+fn test_skip_and_limit() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    for n in 0..10 {
+        tonledb_nosql_doc::insert(&*storage, "items", json!({"n": n})).unwrap();
+    }
+    let all = tonledb_nosql_doc::list_page(&*storage, "items", true, &Paging::default()).unwrap();
+    assert_eq!(all.docs.len(), 10);
+    assert_eq!(all.next, None);
+
+    let page = tonledb_nosql_doc::list_page(&*storage, "items", true, &Paging { skip: 3, limit: Some(4), after: None }).unwrap();
+    assert_eq!(page.docs, all.docs[3..7].to_vec());
+    assert!(page.next.is_some());
+    let rest = tonledb_nosql_doc::list_page(&*storage, "items", true, &Paging { after: page.next, ..Paging::default() }).unwrap();
+    assert_eq!(rest.docs, all.docs[7..].to_vec());
+
+    let past = tonledb_nosql_doc::find_where_page(&*storage, "items", |d| d["n"].as_i64() > Some(7), true, &Paging { skip: 5, ..Paging::default() }).unwrap();
+    assert!(past.docs.is_empty());
+}
+
+#[test]
+fn test_indexed_query_pages_in_id_order() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    index::create_index(&*storage, "items", "kind").unwrap();
+    for n in 0..9 {
+        tonledb_nosql_doc::insert(&*storage, "items", json!({"n": n, "kind": if n < 6 { "a" } else { "b" }})).unwrap();
+    }
+    let filter = Filter::parse(&json!({"kind": "a"})).unwrap();
+    let all = tonledb_nosql_doc::query(&*storage, "items", &filter, true).unwrap();
+    assert_eq!(all.len(), 6);
+
+    let first = tonledb_nosql_doc::query_page(&*storage, "items", &filter, true, &Paging { limit: Some(4), ..Paging::default() }).unwrap();
+    assert_eq!(first.docs, all[..4].to_vec());
+    let second = tonledb_nosql_doc::query_page(&*storage, "items", &filter, true, &Paging { after: first.next, limit: Some(4), skip: 1 }).unwrap();
+    assert_eq!(second.docs, all[5..].to_vec());
+    assert_eq!(second.next, None);
+}
@@ -0,0 +1,57 @@
+//! Tests for JSON Patch and deep-merge updates
+
+use serde_json::json;
+use tonledb_nosql_doc::patch;
+use tonledb_storage::arc_inmem_with_wal;
+
+#[test]
+fn test_patch_operations_on_nested_paths() {
+    let mut doc = json!({"name": "ann", "address": {"city": "Oslo"}, "tags": ["a", "b"], "a/b": 1});
+    patch::apply(&mut doc, &json!([
+        {"op": "replace", "path": "/address/city", "value": "Bergen"},
+        {"op": "add", "path": "/address/zip", "value": "5003"},
+        {"op": "add", "path": "/tags/1", "value": "x"},
+        {"op": "add", "path": "/tags/-", "value": "z"},
+        {"op": "remove", "path": "/tags/0"},
+        {"op": "move", "from": "/name", "path": "/profile"},
+        {"op": "copy", "from": "/address/zip", "path": "/zip"},
+        {"op": "test", "path": "/a~1b", "value": 1},
+    ])).unwrap();
+    assert_eq!(doc, json!({"profile": "ann", "address": {"city": "Bergen", "zip": "5003"}, "tags": ["x", "b", "z"], "a/b": 1, "zip": "5003"}));
+}
+
+#[test]
+fn test_failed_patch_changes_nothing() {
+    let mut doc = json!({"n": 1, "list": [1]});
+    let before = doc.clone();
+    assert!(patch::apply(&mut doc, &json!([{"op": "remove", "path": "/n"}, {"op": "replace", "path": "/missing", "value": 2}])).is_err());
+    assert!(patch::apply(&mut doc, &json!([{"op": "remove", "path": "/n"}, {"op": "test", "path": "/list/0", "value": 2}])).is_err());
+    assert!(patch::apply(&mut doc, &json!([{"op": "add", "path": "/list/5", "value": 2}])).is_err());
+    assert!(patch::apply(&mut doc, &json!([{"op": "move", "from": "/list", "path": "/list/0"}])).is_err());
+    assert!(patch::apply(&mut doc, &json!({"op": "remove", "path": "/n"})).is_err());
+    assert_eq!(doc, before);
+}
+
+#[test]
+fn test_deep_merge() {
+    let mut doc = json!({"a": {"b": 1, "c": {"d": 2}}, "keep": true, "drop": 1});
+    patch::deep_merge(&mut doc, json!({"a": {"c": {"e": 3}, "b": null}, "drop": null, "new": [1]}));
+    assert_eq!(doc, json!({"a": {"c": {"d": 2, "e": 3}}, "keep": true, "new": [1]}));
+}
+
+#[test]
+fn test_update_patch_and_deep_merge_write_documents() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    let id = tonledb_nosql_doc::insert(&*storage, "people", json!({"address": {"city": "Oslo", "zip": "0150"}})).unwrap();
+
+    assert!(tonledb_nosql_doc::update_patch(&*storage, "people", &id, &json!([{"op": "replace", "path": "/address/city", "value": "Bergen"}, {"op": "remove", "path": "/_id"}])).unwrap());
+    let doc = tonledb_nosql_doc::get(&*storage, "people", &id, true).unwrap().unwrap();
+    assert_eq!(doc, json!({"_id": id, "address": {"city": "Bergen", "zip": "0150"}}));
+
+    assert!(tonledb_nosql_doc::update_deep_merge(&*storage, "people", &id, json!({"address": {"zip": null, "street": "Main"}}), false).unwrap());
+    let doc = tonledb_nosql_doc::get(&*storage, "people", &id, true).unwrap().unwrap();
+    assert_eq!(doc["address"], json!({"city": "Bergen", "street": "Main"}));
+
+    assert!(!tonledb_nosql_doc::update_patch(&*storage, "people", "nope", &json!([])).unwrap());
+    assert!(tonledb_nosql_doc::update_patch(&*storage, "people", &id, &json!([{"op": "bogus", "path": "/x"}])).is_err());
+}
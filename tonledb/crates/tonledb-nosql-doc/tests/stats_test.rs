@@ -0,0 +1,28 @@
+//! Tests for collection statistics
+
+use serde_json::json;
+use tonledb_nosql_doc::index;
+use tonledb_storage::arc_inmem_with_wal;
+
+#[test]
+fn test_collection_stats_counts_documents_and_index_entries() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    assert_eq!(tonledb_nosql_doc::collection_stats(&*storage, "posts").unwrap(), Default::default());
+
+    index::create_index(&*storage, "posts", "tags").unwrap();
+    index::create_unique_index(&*storage, "posts", "slug").unwrap();
+    tonledb_nosql_doc::insert(&*storage, "posts", json!({"slug": "a", "tags": ["x", "y"]})).unwrap();
+    tonledb_nosql_doc::insert(&*storage, "posts", json!({"slug": "b", "tags": ["x"]})).unwrap();
+    tonledb_nosql_doc::insert(&*storage, "other", json!({"slug": "c"})).unwrap();
+
+    let stats = tonledb_nosql_doc::collection_stats(&*storage, "posts").unwrap();
+    assert_eq!(stats.documents, 2);
+    let docs = tonledb_nosql_doc::list_all(&*storage, "posts", true).unwrap();
+    let bytes: usize = docs.iter().map(|d| serde_json::to_vec(d).unwrap().len()).sum();
+    assert_eq!(stats.bytes, bytes as u64);
+    assert_eq!(stats.avg_doc_bytes, bytes as u64 / 2);
+
+    let fields: Vec<(&str, bool, usize)> = stats.indexes.iter().map(|i| (i.field.as_str(), i.unique, i.entries)).collect();
+    assert_eq!(fields, vec![("slug", true, 2), ("tags", false, 3)]);
+    assert!(stats.indexes.iter().all(|i| i.bytes > 0));
+}
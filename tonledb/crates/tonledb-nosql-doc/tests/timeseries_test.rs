@@ -0,0 +1,84 @@
+//! Tests for time-series collections
+
+use serde_json::json;
+use tonledb_core::DbError;
+use tonledb_nosql_doc::timeseries::{self, TimeSeries};
+use tonledb_nosql_doc::Paging;
+use tonledb_storage::arc_inmem_with_wal;
+
+fn series(retention_ms: Option<u64>) -> TimeSeries {
+    TimeSeries { time_field: "ts".into(), retention_ms }
+}
+
+#[test]
+fn test_range_returns_documents_in_time_order() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    timeseries::create_timeseries(&*storage, "cpu", series(None)).unwrap();
+    for ts in [3000u64, 1000, 2000, 2500, 4000] {
+        tonledb_nosql_doc::insert(&*storage, "cpu", json!({"ts": ts, "load": ts / 1000})).unwrap();
+    }
+    let times = |docs: Vec<serde_json::Value>| docs.iter().map(|d| d["ts"].as_u64().unwrap()).collect::<Vec<_>>();
+    assert_eq!(times(timeseries::range(&*storage, "cpu", 2000, 4000).unwrap()), vec![2000, 2500, 3000]);
+    assert_eq!(times(timeseries::range(&*storage, "cpu", 0, u64::MAX).unwrap()).len(), 5);
+
+    // A missing time is stamped with now; a non-integer one is rejected
+    let id = tonledb_nosql_doc::insert(&*storage, "cpu", json!({"load": 1})).unwrap();
+    assert!(tonledb_nosql_doc::get(&*storage, "cpu", &id, true).unwrap().unwrap()["ts"].as_u64().unwrap() > 4000);
+    assert!(matches!(tonledb_nosql_doc::insert(&*storage, "cpu", json!({"ts": "noon"})), Err(DbError::Invalid(_))));
+    assert!(timeseries::range(&*storage, "other", 0, 1).is_err());
+}
+
+#[test]
+fn test_range_pages_resume_after_the_last_document() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    timeseries::create_timeseries(&*storage, "cpu", series(None)).unwrap();
+    // Two documents share each time, and the pages cross scan batches
+    for ts in 0..300u64 {
+        tonledb_nosql_doc::insert(&*storage, "cpu", json!({"ts": ts / 2 * 10})).unwrap();
+    }
+    let mut paging = Paging { limit: Some(70), ..Paging::default() };
+    let mut times = Vec::new();
+    loop {
+        let page = timeseries::range_page(&*storage, "cpu", 100, 1400, &paging).unwrap();
+        assert!(page.docs.len() <= 70);
+        times.extend(page.docs.iter().map(|d| d["ts"].as_u64().unwrap()));
+        match page.next {
+            Some(next) => paging.after = Some(next),
+            None => break,
+        }
+    }
+    let expected: Vec<u64> = (20..280).map(|i| i / 2 * 10).collect();
+    assert_eq!(times, expected);
+}
+
+#[test]
+fn test_downsample_buckets() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    timeseries::create_timeseries(&*storage, "temp", series(None)).unwrap();
+    for (ts, c) in [(0u64, 10.0), (400, 20.0), (999, 30.0), (1000, 5.0), (3500, 7.0)] {
+        tonledb_nosql_doc::insert(&*storage, "temp", json!({"ts": ts, "c": c})).unwrap();
+    }
+    tonledb_nosql_doc::insert(&*storage, "temp", json!({"ts": 1200})).unwrap();
+
+    let buckets = timeseries::downsample(&*storage, "temp", 0, 10_000, 1000, "c").unwrap();
+    let summary: Vec<(u64, usize, f64, f64, f64)> = buckets.iter().map(|b| (b.start, b.count, b.min, b.max, b.avg)).collect();
+    assert_eq!(summary, vec![(0, 3, 10.0, 30.0, 20.0), (1000, 1, 5.0, 5.0, 5.0), (3000, 1, 7.0, 7.0, 7.0)]);
+    assert!(timeseries::downsample(&*storage, "temp", 0, 1, 0, "c").is_err());
+}
+
+#[test]
+fn test_retention_deletes_old_documents() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    timeseries::create_timeseries(&*storage, "events", series(Some(60_000))).unwrap();
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis() as u64;
+    tonledb_nosql_doc::insert(&*storage, "events", json!({"ts": now - 120_000})).unwrap();
+    tonledb_nosql_doc::insert(&*storage, "events", json!({"ts": now - 1_000})).unwrap();
+    // Each insert enforces the retention, so the old document is already gone
+    assert_eq!(timeseries::range(&*storage, "events", 0, u64::MAX).unwrap().len(), 1);
+    assert_eq!(timeseries::enforce_retention(&*storage, "events", now + 60_000).unwrap(), 1);
+    assert!(tonledb_nosql_doc::list_all(&*storage, "events", true).unwrap().is_empty());
+
+    let txn = tonledb_nosql_doc::txn::begin(&*storage).unwrap();
+    assert!(txn.insert("events", json!({})).is_err());
+    txn.abort().unwrap();
+}
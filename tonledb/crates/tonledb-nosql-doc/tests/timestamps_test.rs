@@ -0,0 +1,41 @@
+//! Tests for server-side write timestamps
+
+use serde_json::json;
+use tonledb_storage::arc_inmem_with_wal;
+
+#[test]
+fn test_writes_stamp_created_and_updated() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    let plain = tonledb_nosql_doc::insert(&*storage, "notes", json!({"n": 0})).unwrap();
+    assert!(!tonledb_nosql_doc::write_timestamps(&*storage, "notes").unwrap());
+    tonledb_nosql_doc::set_write_timestamps(&*storage, "notes", true).unwrap();
+
+    // Client-sent times are overwritten
+    let id = tonledb_nosql_doc::insert(&*storage, "notes", json!({"n": 1, "_created_at": 5})).unwrap();
+    let doc = tonledb_nosql_doc::get(&*storage, "notes", &id, true).unwrap().unwrap();
+    let created = doc["_created_at"].as_u64().unwrap();
+    assert!(created > 5);
+    assert_eq!(doc["_updated_at"], doc["_created_at"]);
+
+    std::thread::sleep(std::time::Duration::from_millis(5));
+    tonledb_nosql_doc::replace(&*storage, "notes", &id, json!({"n": 2, "_created_at": 7})).unwrap();
+    let doc = tonledb_nosql_doc::get(&*storage, "notes", &id, true).unwrap().unwrap();
+    assert_eq!(doc["_created_at"].as_u64(), Some(created));
+    assert!(doc["_updated_at"].as_u64().unwrap() > created);
+
+    let txn = tonledb_nosql_doc::txn::begin(&*storage).unwrap();
+    txn.update_merge("notes", &id, json!({"n": 3}), false).unwrap();
+    let fresh = txn.insert("notes", json!({"n": 4})).unwrap();
+    txn.commit().unwrap();
+    assert_eq!(tonledb_nosql_doc::get(&*storage, "notes", &id, true).unwrap().unwrap()["_created_at"].as_u64(), Some(created));
+    assert!(tonledb_nosql_doc::get(&*storage, "notes", &fresh, true).unwrap().unwrap()["_created_at"].is_u64());
+
+    // Documents from before timestamps were on get only `_updated_at`
+    tonledb_nosql_doc::update_merge(&*storage, "notes", &plain, json!({"n": 9}), false).unwrap();
+    let doc = tonledb_nosql_doc::get(&*storage, "notes", &plain, true).unwrap().unwrap();
+    assert!(doc.get("_created_at").is_none() && doc["_updated_at"].is_u64());
+
+    tonledb_nosql_doc::set_write_timestamps(&*storage, "notes", false).unwrap();
+    let id = tonledb_nosql_doc::insert(&*storage, "notes", json!({"n": 5})).unwrap();
+    assert!(tonledb_nosql_doc::get(&*storage, "notes", &id, true).unwrap().unwrap().get("_updated_at").is_none());
+}
@@ -0,0 +1,96 @@
+//! Tests for multi-document transactions
+
+use std::sync::Arc;
+use serde_json::json;
+use tonledb_core::event_sourcing::Operation;
+use tonledb_core::transaction::TransactionManager;
+use tonledb_core::DbError;
+use tonledb_nosql_doc::filter::Filter;
+use tonledb_nosql_doc::{index, txn, watch};
+use tonledb_storage::InMemoryStore;
+
+#[test]
+fn test_commit_applies_writes_across_collections() {
+    let store = InMemoryStore::new(1000);
+    index::create_index(&store, "orders_txn", "status").unwrap();
+    let acct = tonledb_nosql_doc::insert(&store, "accounts_txn", json!({"balance": 10})).unwrap();
+    let gone = tonledb_nosql_doc::insert(&store, "accounts_txn", json!({"balance": 0})).unwrap();
+    let changes = watch::watch_collection("accounts_txn", None).unwrap();
+
+    let t = txn::begin(&store).unwrap();
+    assert!(t.update_merge("accounts_txn", &acct, json!({"balance": 5}), false).unwrap());
+    let order = t.insert("orders_txn", json!({"status": "paid", "amount": 5})).unwrap();
+    assert!(t.delete("accounts_txn", &gone).unwrap());
+    // Own writes are visible, other readers see nothing yet
+    assert_eq!(t.get("orders_txn", &order).unwrap().unwrap()["amount"], 5);
+    assert_eq!(tonledb_nosql_doc::get(&store, "orders_txn", &order, true).unwrap(), None);
+    assert!(changes.try_recv().is_none());
+    t.commit().unwrap();
+
+    assert_eq!(tonledb_nosql_doc::get(&store, "accounts_txn", &acct, true).unwrap().unwrap()["balance"], 5);
+    assert_eq!(tonledb_nosql_doc::get(&store, "accounts_txn", &gone, true).unwrap(), None);
+    let paid = Filter::parse(&json!({"status": "paid"})).unwrap();
+    assert_eq!(index::candidates(&store, "orders_txn", &paid).unwrap().unwrap().len(), 1);
+    let mut ops = vec![changes.try_recv().unwrap().operation, changes.try_recv().unwrap().operation];
+    ops.sort_by_key(|op| format!("{:?}", op));
+    assert_eq!(ops, vec![Operation::Delete, Operation::Update]);
+}
+
+#[test]
+fn test_conflicting_or_dropped_txn_writes_nothing() {
+    let store = InMemoryStore::new(1000);
+    let manager = Arc::new(TransactionManager::new());
+    let doc = tonledb_nosql_doc::insert(&store, "stock_txn", json!({"qty": 1})).unwrap();
+    let first = txn::begin_with(&store, manager.clone()).unwrap();
+    let second = txn::begin_with(&store, manager.clone()).unwrap();
+    first.update_merge("stock_txn", &doc, json!({"qty": 0}), false).unwrap();
+    second.update_merge("stock_txn", &doc, json!({"qty": 2}), false).unwrap();
+    let extra = second.insert("stock_txn", json!({"qty": 9})).unwrap();
+    first.commit().unwrap();
+    assert!(matches!(second.commit(), Err(DbError::Conflict(_))));
+    assert_eq!(tonledb_nosql_doc::get(&store, "stock_txn", &doc, true).unwrap().unwrap()["qty"], 0);
+    assert_eq!(tonledb_nosql_doc::get(&store, "stock_txn", &extra, true).unwrap(), None);
+
+    let dropped = txn::begin_with(&store, manager).unwrap();
+    let never = dropped.insert("stock_txn", json!({"qty": 3})).unwrap();
+    drop(dropped);
+    assert_eq!(tonledb_nosql_doc::get(&store, "stock_txn", &never, true).unwrap(), None);
+}
+
+#[test]
+fn test_unique_index_checked_at_commit() {
+    let store = InMemoryStore::new(1000);
+    index::create_unique_index(&store, "users_txn", "email").unwrap();
+    let ann = tonledb_nosql_doc::insert(&store, "users_txn", json!({"email": "ann@x"})).unwrap();
+
+    let t = txn::begin(&store).unwrap();
+    t.insert("users_txn", json!({"email": "bob@x"})).unwrap();
+    t.insert("users_txn", json!({"email": "bob@x"})).unwrap();
+    assert!(matches!(t.commit(), Err(DbError::Conflict(_))));
+    assert_eq!(tonledb_nosql_doc::list_all(&store, "users_txn", true).unwrap().len(), 1);
+
+    // Handing a value over inside one transaction is fine
+    let t = txn::begin(&store).unwrap();
+    t.update_merge("users_txn", &ann, json!({"email": "ann@y"}), false).unwrap();
+    t.insert("users_txn", json!({"email": "ann@x"})).unwrap();
+    t.commit().unwrap();
+    assert!(tonledb_nosql_doc::insert(&store, "users_txn", json!({"email": "ann@y"})).is_err());
+}
+
+#[test]
+fn test_commit_indexes_against_documents_written_meanwhile() {
+    let store = InMemoryStore::new(1000);
+    index::create_index(&store, "tickets_txn", "status").unwrap();
+    let doc = tonledb_nosql_doc::insert(&store, "tickets_txn", json!({"status": "open"})).unwrap();
+
+    let t = txn::begin(&store).unwrap();
+    t.update_merge("tickets_txn", &doc, json!({"status": "closed"}), false).unwrap();
+    // Written outside the transaction after it read the document
+    tonledb_nosql_doc::update_merge(&store, "tickets_txn", &doc, json!({"status": "held"}), false).unwrap();
+    t.commit().unwrap();
+
+    let ids = |status: &str| index::candidates(&store, "tickets_txn", &Filter::parse(&json!({"status": status})).unwrap()).unwrap().unwrap();
+    assert_eq!(ids("closed").into_iter().collect::<Vec<_>>(), vec![doc.clone()]);
+    assert!(ids("held").is_empty());
+    assert!(ids("open").is_empty());
+}
@@ -0,0 +1,52 @@
+//! Tests for unique field indexes
+
+use serde_json::json;
+use tonledb_core::DbError;
+use tonledb_nosql_doc::index;
+use tonledb_storage::arc_inmem_with_wal;
+
+#[test]
+fn test_unique_field_rejects_duplicates() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    assert_eq!(index::create_unique_index(&*storage, "users", "email").unwrap(), 0);
+    assert!(index::is_unique(&*storage, "users", "email").unwrap());
+    let ann = tonledb_nosql_doc::insert(&*storage, "users", json!({"email": "ann@x", "n": 1})).unwrap();
+    let bob = tonledb_nosql_doc::insert(&*storage, "users", json!({"email": "bob@x"})).unwrap();
+
+    let dup = tonledb_nosql_doc::insert(&*storage, "users", json!({"email": "ann@x"}));
+    assert!(matches!(dup, Err(DbError::Conflict(_))));
+    assert!(matches!(tonledb_nosql_doc::update_merge(&*storage, "users", &bob, json!({"email": "ann@x"}), false), Err(DbError::Conflict(_))));
+    assert_eq!(tonledb_nosql_doc::list_all(&*storage, "users", true).unwrap().len(), 2);
+
+    // Rewriting a document with its own value is fine, and freed values can be reused
+    tonledb_nosql_doc::update_merge(&*storage, "users", &ann, json!({"n": 2}), false).unwrap();
+    tonledb_nosql_doc::replace(&*storage, "users", &bob, json!({"email": "rob@x"})).unwrap();
+    tonledb_nosql_doc::insert(&*storage, "users", json!({"email": "bob@x"})).unwrap();
+    tonledb_nosql_doc::delete(&*storage, "users", &ann).unwrap();
+    tonledb_nosql_doc::insert(&*storage, "users", json!({"email": "ann@x"})).unwrap();
+    // Documents without the field don't collide
+    tonledb_nosql_doc::insert(&*storage, "users", json!({"n": 3})).unwrap();
+    tonledb_nosql_doc::insert(&*storage, "users", json!({"n": 4})).unwrap();
+}
+
+#[test]
+fn test_unique_index_refuses_existing_duplicates() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    tonledb_nosql_doc::insert(&*storage, "users", json!({"email": "ann@x"})).unwrap();
+    tonledb_nosql_doc::insert(&*storage, "users", json!({"email": "ann@x"})).unwrap();
+    assert!(matches!(index::create_unique_index(&*storage, "users", "email"), Err(DbError::Conflict(_))));
+    assert!(index::indexed_fields(&*storage, "users").unwrap().is_empty());
+
+    // A plain index on the same field is allowed and doesn't enforce anything
+    assert_eq!(index::create_index(&*storage, "users", "email").unwrap(), 2);
+    assert!(!index::is_unique(&*storage, "users", "email").unwrap());
+}
+
+#[test]
+fn test_unique_array_elements() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    index::create_unique_index(&*storage, "accounts", "aliases").unwrap();
+    tonledb_nosql_doc::insert(&*storage, "accounts", json!({"aliases": ["a", "b"]})).unwrap();
+    assert!(tonledb_nosql_doc::insert(&*storage, "accounts", json!({"aliases": ["c", "b"]})).is_err());
+    tonledb_nosql_doc::insert(&*storage, "accounts", json!({"aliases": ["c", "c"]})).unwrap();
+}
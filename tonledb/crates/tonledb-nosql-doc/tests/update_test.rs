@@ -0,0 +1,99 @@
+//! Tests for update operators and `$elemMatch`
+
+use serde_json::json;
+use tonledb_nosql_doc::filter::Filter;
+use tonledb_nosql_doc::update;
+use tonledb_storage::arc_inmem_with_wal;
+
+#[test]
+fn test_push_add_to_set_and_pull() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    let id = tonledb_nosql_doc::insert(&*storage, "posts", json!({"tags": ["rust"], "scores": [40, 75, 90]})).unwrap();
+
+    let ops = json!({
+        "$push": {"meta.log": "created"},
+        "$addToSet": {"tags": {"$each": ["rust", "db", "db"]}},
+        "$pull": {"scores": {"$lt": 50}},
+    });
+    assert!(tonledb_nosql_doc::update_ops(&*storage, "posts", &id, &ops, false).unwrap());
+    let doc = tonledb_nosql_doc::get(&*storage, "posts", &id, true).unwrap().unwrap();
+    assert_eq!(doc["tags"], json!(["rust", "db"]));
+    assert_eq!(doc["scores"], json!([75, 90]));
+    assert_eq!(doc["meta"], json!({"log": ["created"]}));
+
+    let ops = json!({"$pull": {"tags": "rust", "missing": 1}, "$push": {"scores": {"$each": [1, 2]}}});
+    tonledb_nosql_doc::update_ops(&*storage, "posts", &id, &ops, false).unwrap();
+    let doc = tonledb_nosql_doc::get(&*storage, "posts", &id, true).unwrap().unwrap();
+    assert_eq!(doc["tags"], json!(["db"]));
+    assert_eq!(doc["scores"], json!([75, 90, 1, 2]));
+
+    assert!(!tonledb_nosql_doc::update_ops(&*storage, "posts", "nope", &ops, false).unwrap());
+}
+
+#[test]
+fn test_pull_by_filter_and_failed_updates() {
+    let mut doc = json!({"items": [{"sku": "A1", "qty": 0}, {"sku": "B2", "qty": 3}], "n": 1});
+    update::apply(&mut doc, &json!({"$pull": {"items": {"qty": 0}}})).unwrap();
+    assert_eq!(doc["items"], json!([{"sku": "B2", "qty": 3}]));
+
+    // One bad operator leaves the document untouched
+    let before = doc.clone();
+    assert!(update::apply(&mut doc, &json!({"$push": {"items": {"sku": "C3"}, "n": 2}})).is_err());
+    assert!(update::apply(&mut doc, &json!({"$rename": {"n": "m"}})).is_err());
+    assert!(update::apply(&mut doc, &json!({})).is_err());
+    assert_eq!(doc, before);
+}
+
+#[test]
+fn test_set_unset_and_inc() {
+    let mut doc = json!({"name": "ann", "visits": 1, "score": 1.5, "tags": ["a", "b"], "address": {"city": "Oslo", "zip": "0150"}});
+    let ops = json!({
+        "$set": {"address.city": "Bergen", "meta.source": "import"},
+        "$unset": {"address.zip": "", "tags.0": "", "missing.field": ""},
+        "$inc": {"visits": 2, "score": 1, "likes": 5},
+    });
+    update::apply(&mut doc, &ops).unwrap();
+    assert_eq!(doc["address"], json!({"city": "Bergen"}));
+    assert_eq!(doc["meta"], json!({"source": "import"}));
+    assert_eq!(doc["tags"], json!([null, "b"]));
+    assert_eq!((doc["visits"].as_i64(), doc["score"].as_f64(), doc["likes"].as_i64()), (Some(3), Some(2.5), Some(5)));
+
+    let before = doc.clone();
+    assert!(update::apply(&mut doc, &json!({"$inc": {"name": 1}})).is_err());
+    assert!(update::apply(&mut doc, &json!({"$inc": {"visits": "1"}})).is_err());
+    assert!(update::apply(&mut doc, &json!({"$set": {"name.first": "Ann"}})).is_err());
+    assert_eq!(doc, before);
+}
+
+#[test]
+fn test_concurrent_incs_all_count() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    let id = tonledb_nosql_doc::insert(&*storage, "counters", json!({"n": 0})).unwrap();
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let (storage, id) = (storage.clone(), id.clone());
+            std::thread::spawn(move || {
+                for _ in 0..50 {
+                    tonledb_nosql_doc::update_ops(&*storage, "counters", &id, &json!({"$inc": {"n": 1}}), false).unwrap();
+                }
+            })
+        })
+        .collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+    assert_eq!(tonledb_nosql_doc::get(&*storage, "counters", &id, true).unwrap().unwrap()["n"], 200);
+}
+
+#[test]
+fn test_elem_match() {
+    let doc = json!({"items": [{"sku": "A1", "qty": 1}, {"sku": "B2", "qty": 5}], "scores": [40, 85]});
+    let matches = |f: serde_json::Value| Filter::parse(&f).unwrap().matches(&doc);
+    assert!(matches(json!({"items": {"$elemMatch": {"sku": "B2", "qty": {"$gte": 2}}}})));
+    // Both conditions must hold on the same element
+    assert!(!matches(json!({"items": {"$elemMatch": {"sku": "A1", "qty": {"$gte": 2}}}})));
+    assert!(matches(json!({"scores": {"$elemMatch": {"$gte": 80, "$lt": 90}}})));
+    assert!(!matches(json!({"scores": {"$elemMatch": {"$gt": 90}}})));
+    assert!(!matches(json!({"missing": {"$elemMatch": {}}})));
+    assert!(Filter::parse(&json!({"items": {"$elemMatch": 1}})).is_err());
+}
@@ -0,0 +1,95 @@
+//! Tests for read-only views
+
+use serde_json::json;
+use tonledb_core::DbError;
+use tonledb_nosql_doc::filter::Filter;
+use tonledb_nosql_doc::view::{self, View};
+use tonledb_nosql_doc::Paging;
+use tonledb_storage::arc_inmem_with_wal;
+
+fn open_orders() -> View {
+    View {
+        collection: "orders".into(),
+        filter: json!({"status": "open"}),
+        fields: Some(vec!["customer.name".into(), "total".into()]),
+    }
+}
+
+#[test]
+fn test_view_filters_and_projects() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    for (n, status) in [(1, "open"), (2, "closed"), (3, "open"), (4, "open")] {
+        let doc = json!({"_id": format!("o{}", n), "status": status, "total": n * 10, "customer": {"name": "ann", "email": "a@x"}});
+        tonledb_nosql_doc::insert(&*storage, "orders", doc).unwrap();
+    }
+    view::create_view(&*storage, "open_orders", &open_orders()).unwrap();
+    assert_eq!(view::list_views(&*storage).unwrap(), vec!["open_orders".to_string()]);
+
+    let all = Filter::parse(&json!({})).unwrap();
+    let docs = view::query_view(&*storage, "open_orders", &all).unwrap();
+    assert_eq!(docs.len(), 3);
+    assert!(docs.iter().all(|d| d.get("status").is_none() && d["customer"] == json!({"name": "ann"})));
+
+    let big = Filter::parse(&json!({"total": {"$gte": 30}})).unwrap();
+    let docs = view::query_view(&*storage, "open_orders", &big).unwrap();
+    let totals: Vec<i64> = docs.iter().map(|d| d["total"].as_i64().unwrap()).collect();
+    assert_eq!(totals.len(), 2);
+    assert!(totals.iter().all(|t| *t >= 30));
+    assert_eq!(view::count_view(&*storage, "open_orders", &big).unwrap(), 2);
+
+    // Pages resume like collection queries
+    let first = view::query_view_page(&*storage, "open_orders", &all, &Paging { limit: Some(2), ..Default::default() }).unwrap();
+    assert_eq!(first.docs.len(), 2);
+    let rest = view::query_view_page(&*storage, "open_orders", &all, &Paging { after: first.next, ..Default::default() }).unwrap();
+    assert_eq!(rest.docs.len(), 1);
+
+    // New documents show up; the view stores no data of its own
+    tonledb_nosql_doc::insert(&*storage, "orders", json!({"status": "open", "total": 50})).unwrap();
+    assert_eq!(view::count_view(&*storage, "open_orders", &all).unwrap(), 4);
+}
+
+#[test]
+fn test_collection_reads_resolve_views() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    for (n, status) in [(1, "open"), (2, "closed"), (3, "open")] {
+        let doc = json!({"status": status, "total": n * 10, "customer": {"name": "ann", "email": "a@x"}});
+        tonledb_nosql_doc::insert_with_id(&*storage, "orders", &format!("o{}", n), doc).unwrap();
+    }
+    view::create_view(&*storage, "open_orders", &open_orders()).unwrap();
+
+    let ids = |docs: Vec<serde_json::Value>| docs.iter().map(|d| d["_id"].as_str().unwrap().to_string()).collect::<Vec<_>>();
+    assert_eq!(ids(tonledb_nosql_doc::list_all(&*storage, "open_orders", true).unwrap()), vec!["o1", "o3"]);
+    assert_eq!(ids(tonledb_nosql_doc::find(&*storage, "open_orders", &json!({"total": {"$gt": 10}})).unwrap()), vec!["o3"]);
+    assert_eq!(tonledb_nosql_doc::count(&*storage, "open_orders", &Filter::all()).unwrap(), 2);
+
+    // Documents outside the view are not found through it, and those inside are projected
+    assert_eq!(tonledb_nosql_doc::get(&*storage, "open_orders", "o2", true).unwrap(), None);
+    let doc = tonledb_nosql_doc::get(&*storage, "open_orders", "o1", true).unwrap().unwrap();
+    assert_eq!(doc, json!({"_id": "o1", "total": 10, "customer": {"name": "ann"}}));
+    let status = tonledb_nosql_doc::distinct(&*storage, "open_orders", "status", &Filter::all()).unwrap();
+    assert!(status.is_empty());
+    let totals = tonledb_nosql_doc::distinct(&*storage, "open_orders", "total", &Filter::all()).unwrap();
+    assert_eq!(totals, vec![json!(10), json!(30)]);
+}
+
+#[test]
+fn test_views_are_read_only() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    view::create_view(&*storage, "open_orders", &open_orders()).unwrap();
+    assert!(matches!(tonledb_nosql_doc::insert(&*storage, "open_orders", json!({})), Err(DbError::Invalid(_))));
+    assert!(tonledb_nosql_doc::update_merge(&*storage, "open_orders", "x", json!({}), true).is_err());
+    let txn = tonledb_nosql_doc::txn::begin(&*storage).unwrap();
+    assert!(txn.insert("open_orders", json!({})).is_err());
+    txn.abort().unwrap();
+
+    // Names of collections, views over views and bad filters are refused
+    tonledb_nosql_doc::insert(&*storage, "orders", json!({})).unwrap();
+    assert!(matches!(view::create_view(&*storage, "orders", &View { collection: "x".into(), ..open_orders() }), Err(DbError::Conflict(_))));
+    assert!(view::create_view(&*storage, "v2", &View { collection: "open_orders".into(), ..open_orders() }).is_err());
+    assert!(view::create_view(&*storage, "v3", &View { filter: json!({"n": {"$bogus": 1}}), ..open_orders() }).is_err());
+
+    assert!(view::drop_view(&*storage, "open_orders").unwrap());
+    assert!(!view::drop_view(&*storage, "open_orders").unwrap());
+    assert!(matches!(view::query_view(&*storage, "open_orders", &Filter::And(vec![])), Err(DbError::NotFound(_))));
+    tonledb_nosql_doc::insert(&*storage, "open_orders", json!({})).unwrap();
+}
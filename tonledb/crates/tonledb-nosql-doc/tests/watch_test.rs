@@ -0,0 +1,92 @@
+//! Tests for filtered document watches
+
+use std::sync::{Arc, Mutex};
+use serde_json::json;
+use tonledb_core::event_sourcing::Operation;
+use tonledb_nosql_doc::filter::Filter;
+use tonledb_nosql_doc::watch::{self, DocChange};
+use tonledb_storage::arc_inmem_with_wal;
+
+fn collect_into(sink: &Arc<Mutex<Vec<DocChange>>>) -> impl Fn(DocChange) + Send + Sync + 'static {
+    let sink = sink.clone();
+    move |c| sink.lock().unwrap().push(c)
+}
+
+#[test]
+fn test_filter_operators() {
+    let doc = json!({"status": "open", "priority": 3, "owner": "ann"});
+    let f = Filter::parse(&json!({"status": "open", "priority": {"$gte": 2, "$lt": 5}})).unwrap();
+    assert!(f.matches(&doc));
+    let f = Filter::parse(&json!({"$or": [{"owner": "bob"}, {"priority": {"$in": [1, 3]}}]})).unwrap();
+    assert!(f.matches(&doc));
+    let f = Filter::parse(&json!({"status": {"$ne": "open"}})).unwrap();
+    assert!(!f.matches(&doc));
+    assert!(Filter::parse(&json!({"priority": {"$near": 1}})).is_err());
+    assert!(Filter::parse(&json!({"priority": {"$in": 1}})).is_err());
+}
+
+#[test]
+fn test_watch_delivers_only_matching_changes() {
+    let storage = arc_inmem_with_wal(None, 1000);
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let filter = Filter::parse(&json!({"status": "open"})).unwrap();
+    watch::watch("w-open", "tickets_watch", Some(filter), collect_into(&seen)).unwrap();
+
+    let open = tonledb_nosql_doc::insert(&*storage, "tickets_watch", json!({"status": "open"})).unwrap();
+    tonledb_nosql_doc::insert(&*storage, "tickets_watch", json!({"status": "closed"})).unwrap();
+    tonledb_nosql_doc::insert(&*storage, "other_watch", json!({"status": "open"})).unwrap();
+    tonledb_nosql_doc::update_merge(&*storage, "tickets_watch", &open, json!({"note": "x"}), false).unwrap();
+    tonledb_nosql_doc::delete(&*storage, "tickets_watch", &open).unwrap();
+
+    let ops: Vec<Operation> = seen.lock().unwrap().iter().map(|c| c.operation.clone()).collect();
+    assert_eq!(ops, vec![Operation::Insert, Operation::Update, Operation::Delete]);
+    assert!(seen.lock().unwrap().iter().all(|c| c.id == open && c.collection == "tickets_watch"));
+    assert_eq!(seen.lock().unwrap()[2].doc.as_ref().unwrap()["note"], "x");
+
+    assert!(watch::unwatch("w-open"));
+    tonledb_nosql_doc::insert(&*storage, "tickets_watch", json!({"status": "open"})).unwrap();
+    assert_eq!(seen.lock().unwrap().len(), 3);
+}
+
+#[test]
+fn test_collection_watch_streams_changes_until_dropped() {
+    use std::pin::Pin;
+    use std::task::{Context, Poll, Waker};
+    use futures_core::Stream;
+
+    let storage = arc_inmem_with_wal(None, 1000);
+    let mut changes = watch::watch_collection("orders_stream", None).unwrap();
+    let id = tonledb_nosql_doc::insert(&*storage, "orders_stream", json!({"total": 5})).unwrap();
+    tonledb_nosql_doc::insert(&*storage, "other_stream", json!({"total": 1})).unwrap();
+    tonledb_nosql_doc::delete(&*storage, "orders_stream", &id).unwrap();
+
+    let mut cx = Context::from_waker(Waker::noop());
+    let Poll::Ready(Some(first)) = Pin::new(&mut changes).poll_next(&mut cx) else { panic!("expected a change") };
+    assert_eq!((first.operation, first.id.as_str()), (Operation::Insert, id.as_str()));
+    assert_eq!(first.doc.unwrap()["total"], 5);
+    assert_eq!(changes.try_recv().unwrap().operation, Operation::Delete);
+    assert!(Pin::new(&mut changes).poll_next(&mut cx).is_pending());
+
+    let feed = changes.id().to_string();
+    drop(changes);
+    assert!(!watch::unwatch(&feed));
+}
+
+#[test]
+fn test_collection_watch_falling_behind_ends() {
+    use std::pin::Pin;
+    use std::task::{Context, Poll, Waker};
+    use futures_core::Stream;
+
+    let storage = arc_inmem_with_wal(None, 1000);
+    let mut changes = watch::watch_collection_with_capacity("orders_slow", None, 2).unwrap();
+    tonledb_nosql_doc::insert(&*storage, "orders_slow", json!({"total": 1})).unwrap();
+    tonledb_nosql_doc::insert(&*storage, "orders_slow", json!({"total": 2})).unwrap();
+    assert!(!changes.is_disconnected());
+    tonledb_nosql_doc::insert(&*storage, "orders_slow", json!({"total": 3})).unwrap();
+
+    assert!(changes.is_disconnected());
+    assert!(changes.try_recv().is_none());
+    let mut cx = Context::from_waker(Waker::noop());
+    assert!(matches!(Pin::new(&mut changes).poll_next(&mut cx), Poll::Ready(None)));
+}
@@ -0,0 +1,120 @@
+//! Named buckets: separate key namespaces inside the kv space.
+//!
+//! A bucket's keys are stored as `\0b/<name>/<key>`. The leading NUL keeps them
+//! out of the way of ordinary keys, though a flat scan from the empty prefix
+//! still sees them. Bucket names may not be empty or contain `/`. Writes go
+//! through the flat API, so they are published to watches under the full key.
+
+use std::time::Duration;
+use tonledb_core::event_sourcing::Operation;
+use tonledb_core::{DbError, Result, Space, Storage};
+use crate::{meta, watch, Page, KV_SPACE};
+
+const BUCKET_PREFIX: &[u8] = b"\0b/";
+
+/// Key and byte counts of a bucket
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BucketStats {
+    pub keys: usize,
+    /// Keys plus values, as seen by readers (before compression or encryption)
+    pub bytes: u64,
+}
+
+/// Handle to one bucket; cheap to create, holds no storage
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bucket {
+    name: String,
+}
+
+impl Bucket {
+    pub fn new(name: &str) -> Self {
+        Self { name: name.to_string() }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The storage key prefix shared by every key of the bucket
+    pub fn prefix(&self) -> Result<Vec<u8>> {
+        if self.name.is_empty() || self.name.contains('/') {
+            return Err(DbError::Invalid(format!("invalid bucket name: {:?}", self.name)));
+        }
+        let mut p = BUCKET_PREFIX.to_vec();
+        p.extend_from_slice(self.name.as_bytes());
+        p.push(b'/');
+        Ok(p)
+    }
+
+    fn key(&self, key: &[u8]) -> Result<Vec<u8>> {
+        let mut k = self.prefix()?;
+        k.extend_from_slice(key);
+        Ok(k)
+    }
+
+    pub fn get<S: Storage + ?Sized>(&self, storage: &S, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        crate::get(storage, &self.key(key)?)
+    }
+
+    pub fn put<S: Storage + ?Sized>(&self, storage: &S, key: Vec<u8>, val: Vec<u8>) -> Result<()> {
+        crate::put(storage, self.key(&key)?, val)
+    }
+
+    pub fn put_with_ttl<S: Storage + ?Sized>(&self, storage: &S, key: Vec<u8>, val: Vec<u8>, ttl: Duration) -> Result<()> {
+        crate::put_with_ttl(storage, self.key(&key)?, val, ttl)
+    }
+
+    pub fn del<S: Storage + ?Sized>(&self, storage: &S, key: &[u8]) -> Result<()> {
+        crate::del(storage, &self.key(key)?)
+    }
+
+    pub fn exists<S: Storage + ?Sized>(&self, storage: &S, key: &[u8]) -> Result<bool> {
+        crate::exists(storage, &self.key(key)?)
+    }
+
+    pub fn set_if_absent<S: Storage + ?Sized>(&self, storage: &S, key: Vec<u8>, val: Vec<u8>) -> Result<bool> {
+        crate::set_if_absent(storage, self.key(&key)?, val)
+    }
+
+    /// Entries of the bucket under `prefix`, with keys relative to the bucket
+    pub fn scan_prefix<S: Storage + ?Sized>(&self, storage: &S, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let strip = self.prefix()?.len();
+        Ok(crate::scan_prefix(storage, &self.key(prefix)?)?.into_iter().map(|(k, v)| (k[strip..].to_vec(), v)).collect())
+    }
+
+    /// One page of `scan_prefix`; `after_key` and `Page::next` are relative to the bucket
+    pub fn scan_prefix_page<S: Storage + ?Sized>(&self, storage: &S, prefix: &[u8], after_key: Option<&[u8]>, limit: usize) -> Result<Page> {
+        let strip = self.prefix()?.len();
+        let after = after_key.map(|a| self.key(a)).transpose()?;
+        let page = crate::scan_prefix_page(storage, &self.key(prefix)?, after.as_deref(), limit)?;
+        Ok(Page {
+            items: page.items.into_iter().map(|(k, v)| (k[strip..].to_vec(), v)).collect(),
+            next: page.next.map(|k| k[strip..].to_vec()),
+        })
+    }
+
+    /// Every key of the bucket, relative to it
+    pub fn keys<S: Storage + ?Sized>(&self, storage: &S) -> Result<Vec<Vec<u8>>> {
+        Ok(self.scan_prefix(storage, b"")?.into_iter().map(|(k, _)| k).collect())
+    }
+
+    /// Delete every key of the bucket; returns how many were removed
+    pub fn clear<S: Storage + ?Sized>(&self, storage: &S) -> Result<usize> {
+        let prefix = self.prefix()?;
+        // Listed first so watches hear about each deleted key
+        let keys = crate::keys_with_prefix(storage, &prefix)?;
+        let removed = storage.delete_prefix(&Space(KV_SPACE.into()), &prefix)?;
+        meta::clear_prefix(storage, &prefix)?;
+        for k in &keys {
+            watch::publish(Operation::Delete, k, None);
+        }
+        Ok(removed)
+    }
+
+    pub fn stats<S: Storage + ?Sized>(&self, storage: &S) -> Result<BucketStats> {
+        let strip = self.prefix()?.len();
+        crate::scan_prefix_iter(storage, &self.prefix()?).try_fold(BucketStats::default(), |s, row| {
+            row.map(|(k, v)| BucketStats { keys: s.keys + 1, bytes: s.bytes + (k.len() - strip + v.len()) as u64 })
+        })
+    }
+}
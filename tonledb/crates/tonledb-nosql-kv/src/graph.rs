@@ -0,0 +1,213 @@
+//! Lightweight property graph over TonleDB storage.
+//!
+//! Layout (all under `Space("graph")`):
+//! - `n/<id>` -> node JSON (`{id, label, props}`)
+//! - `o/<from>/<type>/<to>` -> edge JSON (outgoing adjacency)
+//! - `i/<to>/<type>/<from>` -> empty (incoming adjacency, for reverse traversals)
+//!
+//! Neighbors of a node are one prefix scan. Traversals are breadth-first and
+//! bounded by depth and by the number of nodes visited. Node ids and edge types
+//! may not contain `/`.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as Json;
+use tonledb_core::{DbError, Result, Space, Storage};
+
+const GRAPH_SPACE: &str = "graph";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Node {
+    pub id: String,
+    pub label: String,
+    #[serde(default)]
+    pub props: Json,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+    pub edge_type: String,
+    #[serde(default)]
+    pub props: Json,
+}
+
+/// Which edges of a node to follow
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    #[default]
+    Out,
+    In,
+    Both,
+}
+
+/// Limits for traversals
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bounds {
+    pub max_depth: usize,
+    pub max_nodes: usize,
+}
+
+impl Default for Bounds {
+    fn default() -> Self {
+        Self { max_depth: 3, max_nodes: 1000 }
+    }
+}
+
+/// Create or replace a node
+pub fn put_node<S: Storage + ?Sized>(storage: &S, node: &Node) -> Result<()> {
+    check_part("node id", &node.id)?;
+    storage.put(&space(), node_key(&node.id), to_json(node)?)
+}
+
+pub fn get_node<S: Storage + ?Sized>(storage: &S, id: &str) -> Result<Option<Node>> {
+    match storage.get(&space(), &node_key(id))? {
+        Some(b) => serde_json::from_slice(&b).map(Some).map_err(|e| DbError::Storage(format!("corrupt graph node: {}", e))),
+        None => Ok(None),
+    }
+}
+
+/// Delete a node and every edge touching it. Returns `true` if the node existed.
+pub fn delete_node<S: Storage + ?Sized>(storage: &S, id: &str) -> Result<bool> {
+    let sp = space();
+    let existed = storage.get(&sp, &node_key(id))?.is_some();
+    for (edge_type, to) in adjacent(storage, b'o', id)? {
+        storage.del(&sp, &adj_key(b'i', &to, &edge_type, id))?;
+        storage.del(&sp, &adj_key(b'o', id, &edge_type, &to))?;
+    }
+    for (edge_type, from) in adjacent(storage, b'i', id)? {
+        storage.del(&sp, &adj_key(b'o', &from, &edge_type, id))?;
+        storage.del(&sp, &adj_key(b'i', id, &edge_type, &from))?;
+    }
+    storage.del(&sp, &node_key(id))?;
+    Ok(existed)
+}
+
+/// Create or replace an edge. Both endpoints must exist.
+pub fn put_edge<S: Storage + ?Sized>(storage: &S, edge: &Edge) -> Result<()> {
+    check_part("edge type", &edge.edge_type)?;
+    for id in [&edge.from, &edge.to] {
+        if get_node(storage, id)?.is_none() {
+            return Err(DbError::NotFound(format!("graph node {}", id)));
+        }
+    }
+    let sp = space();
+    storage.put(&sp, adj_key(b'i', &edge.to, &edge.edge_type, &edge.from), Vec::new())?;
+    storage.put(&sp, adj_key(b'o', &edge.from, &edge.edge_type, &edge.to), to_json(edge)?)
+}
+
+/// Delete an edge. Returns `true` if it existed.
+pub fn delete_edge<S: Storage + ?Sized>(storage: &S, from: &str, edge_type: &str, to: &str) -> Result<bool> {
+    let sp = space();
+    let key = adj_key(b'o', from, edge_type, to);
+    let existed = storage.get(&sp, &key)?.is_some();
+    storage.del(&sp, &key)?;
+    storage.del(&sp, &adj_key(b'i', to, edge_type, from))?;
+    Ok(existed)
+}
+
+/// Ids of the nodes one hop from `node`, optionally restricted to one edge type
+pub fn neighbors<S: Storage + ?Sized>(storage: &S, node: &str, edge_type: Option<&str>, dir: Direction) -> Result<Vec<String>> {
+    let mut out = Vec::new();
+    let sides: &[u8] = match dir {
+        Direction::Out => b"o",
+        Direction::In => b"i",
+        Direction::Both => b"oi",
+    };
+    for side in sides {
+        for (t, other) in adjacent(storage, *side, node)? {
+            if edge_type.is_none_or(|want| want == t) && !out.contains(&other) {
+                out.push(other);
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Breadth-first traversal from `start`; returns `(node, depth)` in visit order, `start` first
+pub fn bfs<S: Storage + ?Sized>(storage: &S, start: &str, edge_type: Option<&str>, dir: Direction, bounds: Bounds) -> Result<Vec<(String, usize)>> {
+    let mut seen = HashSet::from([start.to_string()]);
+    let mut queue = VecDeque::from([(start.to_string(), 0)]);
+    let mut out = Vec::new();
+    while let Some((node, depth)) = queue.pop_front() {
+        out.push((node.clone(), depth));
+        if out.len() >= bounds.max_nodes {
+            break;
+        }
+        if depth >= bounds.max_depth {
+            continue;
+        }
+        for next in neighbors(storage, &node, edge_type, dir)? {
+            if seen.insert(next.clone()) {
+                queue.push_back((next, depth + 1));
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Fewest-hops path from `from` to `to` (both included), or `None` if there is none within `bounds`
+pub fn shortest_path<S: Storage + ?Sized>(storage: &S, from: &str, to: &str, edge_type: Option<&str>, dir: Direction, bounds: Bounds) -> Result<Option<Vec<String>>> {
+    let mut parent: HashMap<String, Option<String>> = HashMap::from([(from.to_string(), None)]);
+    let mut queue = VecDeque::from([(from.to_string(), 0)]);
+    while let Some((node, depth)) = queue.pop_front() {
+        if node == to {
+            let mut path = vec![node];
+            while let Some(Some(p)) = parent.get(path.last().unwrap()) {
+                path.push(p.clone());
+            }
+            path.reverse();
+            return Ok(Some(path));
+        }
+        if depth >= bounds.max_depth || parent.len() >= bounds.max_nodes {
+            continue;
+        }
+        for next in neighbors(storage, &node, edge_type, dir)? {
+            if !parent.contains_key(&next) {
+                parent.insert(next.clone(), Some(node.clone()));
+                queue.push_back((next, depth + 1));
+            }
+        }
+    }
+    Ok(None)
+}
+
+// ---------- helpers ----------
+
+fn space() -> Space {
+    Space(GRAPH_SPACE.into())
+}
+
+fn node_key(id: &str) -> Vec<u8> {
+    format!("n/{}", id).into_bytes()
+}
+
+fn adj_key(side: u8, node: &str, edge_type: &str, other: &str) -> Vec<u8> {
+    format!("{}/{}/{}/{}", side as char, node, edge_type, other).into_bytes()
+}
+
+/// `(edge type, other node)` of every edge on `side` of `node`
+fn adjacent<S: Storage + ?Sized>(storage: &S, side: u8, node: &str) -> Result<Vec<(String, String)>> {
+    let prefix = format!("{}/{}/", side as char, node).into_bytes();
+    let rows = storage.scan_prefix(&space(), &prefix)?;
+    Ok(rows
+        .filter_map(|(k, _)| {
+            let rest = String::from_utf8(k[prefix.len()..].to_vec()).ok()?;
+            let (t, other) = rest.split_once('/')?;
+            Some((t.to_string(), other.to_string()))
+        })
+        .collect())
+}
+
+fn check_part(what: &str, s: &str) -> Result<()> {
+    if s.is_empty() || s.contains('/') {
+        return Err(DbError::Invalid(format!("{} must be non-empty and may not contain '/': {:?}", what, s)));
+    }
+    Ok(())
+}
+
+fn to_json<T: Serialize>(v: &T) -> Result<Vec<u8>> {
+    serde_json::to_vec(v).map_err(|e| DbError::Storage(e.to_string()))
+}
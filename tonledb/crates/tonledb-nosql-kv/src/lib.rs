@@ -2,12 +2,38 @@
 //!
 //! Keys live in the dedicated `Space("kv")`. Values are arbitrary bytes.
 //! This module provides simple CRUD and convenience helpers (exists, list,
-//! prefix scan, atomic-style set-if-absent).
+//! prefix scan, atomic-style set-if-absent, expiring keys). A small property graph with
+//! adjacency-list keys lives in `graph`; per-session scratch maps that never
+//! touch storage live in `scratch`. Writes are published as change events, which
+//! `watch` turns into per-prefix subscriptions. `bucket` splits the space into
+//! named namespaces with their own listing, clearing and stats; `txn` updates
+//! several keys atomically; `meta` optionally versions and time-stamps keys.
+//! `structures` layers Redis-style lists, sets and hashes over the space.
 
-use tonledb_core::{Result, Space, Storage};
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+use tonledb_core::event_sourcing::Operation;
+use tonledb_core::{Consistency, Result, Space, Storage};
+use meta::Change;
+
+pub mod bucket;
+pub mod graph;
+pub mod meta;
+pub mod scratch;
+pub mod structures;
+pub mod txn;
+pub mod watch;
 
 const KV_SPACE: &str = "kv";
 
+/// The bucket called `name`, e.g. `kv::bucket("sessions").put(&storage, key, val)`
+pub fn bucket(name: &str) -> bucket::Bucket {
+    bucket::Bucket::new(name)
+}
+
 /// Get a value by key. Returns `Ok(Some(bytes))` if present.
 pub fn get<S: Storage + ?Sized>(storage: &S, key: &[u8]) -> Result<Option<Vec<u8>>> {
     storage.get(&Space(KV_SPACE.into()), key)
@@ -15,12 +41,52 @@ pub fn get<S: Storage + ?Sized>(storage: &S, key: &[u8]) -> Result<Option<Vec<u8
 
 /// Put (set) a value by key (overwrites any existing value).
 pub fn put<S: Storage + ?Sized>(storage: &S, key: Vec<u8>, val: Vec<u8>) -> Result<()> {
-    storage.put(&Space(KV_SPACE.into()), key, val)
+    tracked_put(storage, key, val, Operation::Update, None, |key, val| storage.put(&Space(KV_SPACE.into()), key, val))
+}
+
+/// Put a value that expires after `ttl`. Expired keys read as absent straight away
+/// and are reclaimed by the storage's background sweep (`tonledb_storage::ttl`); a
+/// later plain `put` makes the key permanent again.
+pub fn put_with_ttl<S: Storage + ?Sized>(storage: &S, key: Vec<u8>, val: Vec<u8>, ttl: Duration) -> Result<()> {
+    let ttl_ms = u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX);
+    tracked_put(storage, key, val, Operation::Update, Some(ttl_ms), |key, val| storage.put_with_ttl(&Space(KV_SPACE.into()), key, val, ttl_ms))
+}
+
+/// Get a value with an explicit replica consistency level (replicated deployments).
+pub fn get_with<S: Storage + ?Sized>(storage: &S, key: &[u8], consistency: Consistency) -> Result<Option<Vec<u8>>> {
+    storage.get_with(&Space(KV_SPACE.into()), key, consistency)
+}
+
+/// Put a value with an explicit replica consistency level (replicated deployments).
+pub fn put_with<S: Storage + ?Sized>(storage: &S, key: Vec<u8>, val: Vec<u8>, consistency: Consistency) -> Result<()> {
+    tracked_put(storage, key, val, Operation::Update, None, |key, val| storage.put_with(&Space(KV_SPACE.into()), key, val, consistency))
+}
+
+/// Run `write`, a put of `val` under `key` in the kv space, recording its metadata
+/// and publishing it. The key and value are copied only when metadata or a watcher
+/// needs them after `write` has taken them.
+fn tracked_put<S: Storage + ?Sized>(
+    storage: &S,
+    key: Vec<u8>,
+    val: Vec<u8>,
+    operation: Operation,
+    ttl_ms: Option<u64>,
+    write: impl FnOnce(Vec<u8>, Vec<u8>) -> Result<()>,
+) -> Result<()> {
+    let published = watch::wanted(&operation, &key).then(|| val.clone());
+    let Some(kept) = (published.is_some() || meta::enabled()).then(|| key.clone()) else { return write(key, val) };
+    meta::tracked(storage, &kept, Change::Put { ttl_ms }, || write(key, val))?;
+    if let Some(val) = published {
+        watch::publish(operation, &kept, Some(val));
+    }
+    Ok(())
 }
 
 /// Delete a key (no-op if absent).
 pub fn del<S: Storage + ?Sized>(storage: &S, key: &[u8]) -> Result<()> {
-    storage.del(&Space(KV_SPACE.into()), key)
+    meta::tracked(storage, key, Change::Delete, || storage.del(&Space(KV_SPACE.into()), key))?;
+    watch::publish(Operation::Delete, key, None);
+    Ok(())
 }
 
 /// Return `true` if the key exists.
@@ -34,19 +100,158 @@ pub fn set_if_absent<S: Storage + ?Sized>(storage: &S, key: Vec<u8>, val: Vec<u8
     if storage.get(&space, &key)?.is_some() {
         return Ok(false);
     }
-    storage.put(&space, key, val)?;
+    tracked_put(storage, key, val, Operation::Insert, None, |key, val| storage.put(&space, key, val))?;
     Ok(true)
 }
 
 /// List all keys having the given prefix. Returns (key, value) pairs.
-/// NOTE: For large datasets you may want paging – this returns all matches.
+/// This returns all matches; use `scan_prefix_iter` or `scan_prefix_page` to walk
+/// large key ranges.
 pub fn scan_prefix<S: Storage + ?Sized>(storage: &S, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
     let it = storage.scan_prefix(&Space(KV_SPACE.into()), prefix)?;
     Ok(it.collect())
 }
 
+/// One page of a prefix scan
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page {
+    /// Matching (key, value) pairs, in key order
+    pub items: Vec<(Vec<u8>, Vec<u8>)>,
+    /// Pass as `after_key` to get the next page; `None` once the scan is done
+    pub next: Option<Vec<u8>>,
+}
+
+/// Up to `limit` pairs under `prefix` with keys after `after_key` (from the start
+/// when `None`). Pages are read straight from storage, so the scan can be resumed
+/// over ranges far too large to collect at once.
+pub fn scan_prefix_page<S: Storage + ?Sized>(storage: &S, prefix: &[u8], after_key: Option<&[u8]>, limit: usize) -> Result<Page> {
+    // One row past the page tells whether anything follows it
+    let mut items = storage.scan_prefix_page(&Space(KV_SPACE.into()), prefix, after_key, limit.saturating_add(1))?;
+    let more = items.len() > limit;
+    items.truncate(limit);
+    let next = if more { items.last().map(|(k, _)| k.clone()) } else { None };
+    Ok(Page { items, next })
+}
+
+/// Rows fetched from storage per step of a `ScanIter`
+const SCAN_BATCH: usize = 256;
+
+/// Lazy prefix scan, see `scan_prefix_iter`
+pub struct ScanIter<H> {
+    storage: H,
+    prefix: Vec<u8>,
+    after: Option<Vec<u8>>,
+    batch: VecDeque<(Vec<u8>, Vec<u8>)>,
+    done: bool,
+}
+
+impl<H> Iterator for ScanIter<H>
+where
+    H: Deref,
+    H::Target: Storage,
+{
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.batch.is_empty() && !self.done {
+            let page = match scan_prefix_page(&*self.storage, &self.prefix, self.after.as_deref(), SCAN_BATCH) {
+                Ok(page) => page,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            };
+            self.done = page.next.is_none();
+            self.after = page.next;
+            self.batch = page.items.into();
+        }
+        self.batch.pop_front().map(Ok)
+    }
+}
+
+/// All pairs under `prefix`, in key order, read from storage a batch at a time as
+/// the iterator advances, so memory stays flat however many keys match. `storage`
+/// is any handle to a store: `&store`, or an `Arc<dyn Storage>` for an iterator
+/// that outlives the caller (e.g. one streamed out of an HTTP handler).
+///
+/// This is not a snapshot: each batch resumes after the last key returned, so keys
+/// written ahead of the scan while it runs may or may not be seen.
+pub fn scan_prefix_iter<H>(storage: H, prefix: &[u8]) -> ScanIter<H>
+where
+    H: Deref,
+    H::Target: Storage,
+{
+    ScanIter { storage, prefix: prefix.to_vec(), after: None, batch: VecDeque::new(), done: false }
+}
+
+/// Up to `limit` pairs with `start <= key < end` (no upper bound if `end` is `None`),
+/// in key order.
+pub fn scan_range<S: Storage + ?Sized>(storage: &S, start: &[u8], end: Option<&[u8]>, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    storage.scan_range(&Space(KV_SPACE.into()), start, end, false, limit)
+}
+
+/// `scan_range` from the top: the last `limit` pairs of the range, highest key first
+pub fn scan_range_rev<S: Storage + ?Sized>(storage: &S, start: &[u8], end: Option<&[u8]>, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    storage.scan_range(&Space(KV_SPACE.into()), start, end, true, limit)
+}
+
+/// The last `limit` pairs under `prefix`, highest key first; with timestamp-ordered
+/// keys (`events/<ts>`), the newest N.
+pub fn scan_prefix_rev<S: Storage + ?Sized>(storage: &S, prefix: &[u8], limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    scan_range_rev(storage, prefix, prefix_end(prefix).as_deref(), limit)
+}
+
+/// The first key after every key starting with `prefix`; `None` if there is none
+/// (the prefix is empty or all `0xff`).
+fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let last = prefix.iter().rposition(|b| *b != 0xff)?;
+    let mut end = prefix[..=last].to_vec();
+    end[last] += 1;
+    Some(end)
+}
+
+/// Size of the kv space, for capacity planning
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize)]
+pub struct KvStats {
+    pub keys: usize,
+    pub value_bytes: u64,
+    /// Keys with a TTL that has not run out yet
+    pub ttl_keys: usize,
+}
+
+/// Count the keys, value bytes and expiring keys of the space. Walks every key, so
+/// call it periodically rather than per request.
+pub fn stats<S: Storage + ?Sized>(storage: &S) -> Result<KvStats> {
+    let space = Space(KV_SPACE.into());
+    let mut stats = KvStats::default();
+    for row in scan_prefix_iter(storage, b"") {
+        let (k, v) = row?;
+        stats.keys += 1;
+        stats.value_bytes += v.len() as u64;
+        if storage.expires_at(&space, &k)?.is_some() {
+            stats.ttl_keys += 1;
+        }
+    }
+    Ok(stats)
+}
+
 /// Convenience helper: list just keys that match a prefix.
 pub fn keys_with_prefix<S: Storage + ?Sized>(storage: &S, prefix: &[u8]) -> Result<Vec<Vec<u8>>> {
-    let it = storage.scan_prefix(&Space(KV_SPACE.into()), prefix)?;
-    Ok(it.map(|(k, _)| k).collect())
+    scan_prefix_iter(storage, prefix).map(|row| row.map(|(k, _)| k)).collect()
+}
+
+/// Striped in-process locks serialising read-modify-writes of a key
+pub(crate) struct Stripes([Mutex<()>; 64]);
+
+impl Stripes {
+    pub(crate) const fn new() -> Self {
+        Self([const { Mutex::new(()) }; 64])
+    }
+
+    pub(crate) fn lock(&self, key: &[u8]) -> MutexGuard<'_, ()> {
+        let mut h = std::collections::hash_map::DefaultHasher::new();
+        key.hash(&mut h);
+        // A panic while holding a stripe leaves nothing half-updated that the lock guards
+        self.0[h.finish() as usize % self.0.len()].lock().unwrap_or_else(|e| e.into_inner())
+    }
 }
@@ -0,0 +1,198 @@
+//! Per-key metadata: a version counter and created/updated times.
+//!
+//! Off by default (`set_enabled`). When on, every write through this crate also
+//! records a `KeyMeta` under the same key in `Space("kv_meta")`: a delete removes
+//! it and a TTL write gives it the same TTL. `get_with_meta` reads both, and
+//! `put_if` / `del_if` make a write conditional on the metadata, e.g. for HTTP
+//! `If-Match`. Keys written while metadata was off have none until their next write.
+//!
+//! A value and its metadata are two storage writes. Writes to one key are
+//! serialised within the process, but a crash between the two can leave the
+//! metadata one write behind.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::MutexGuard;
+use tonledb_core::event_sourcing::Operation;
+use tonledb_core::{DbError, Result, Space, Storage};
+use crate::{watch, Stripes, KV_SPACE};
+
+const META_SPACE: &str = "kv_meta";
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static LOCKS: Stripes = Stripes::new();
+
+/// Metadata of one key; times are epoch milliseconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyMeta {
+    /// 1 for the write that created the key, then one more per write
+    pub version: u64,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+impl KeyMeta {
+    /// Whether `self` and `other` describe the same write of a key. `updated_at` is
+    /// not compared; `created_at` tells a key apart from one deleted and recreated.
+    pub fn same_version(&self, other: &KeyMeta) -> bool {
+        self.version == other.version && self.created_at == other.created_at
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        [self.version, self.created_at, self.updated_at].iter().flat_map(|n| n.to_be_bytes()).collect()
+    }
+
+    fn decode(raw: &[u8]) -> Result<Self> {
+        let n = |i: usize| raw.get(i * 8..i * 8 + 8).and_then(|b| b.try_into().ok()).map(u64::from_be_bytes);
+        match (n(0), n(1), n(2)) {
+            (Some(version), Some(created_at), Some(updated_at)) if raw.len() == 24 => Ok(Self { version, created_at, updated_at }),
+            _ => Err(DbError::Storage(format!("corrupt key metadata ({} bytes)", raw.len()))),
+        }
+    }
+}
+
+/// Record metadata for writes from now on (process-wide)
+pub fn set_enabled(on: bool) {
+    ENABLED.store(on, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// A value with its metadata, `None` if the key has none
+pub fn get_with_meta<S: Storage + ?Sized>(storage: &S, key: &[u8]) -> Result<Option<(Vec<u8>, Option<KeyMeta>)>> {
+    let Some(val) = crate::get(storage, key)? else { return Ok(None) };
+    Ok(Some((val, read(storage, key)?)))
+}
+
+/// Just the metadata of a key
+pub fn get_meta<S: Storage + ?Sized>(storage: &S, key: &[u8]) -> Result<Option<KeyMeta>> {
+    read(storage, key)
+}
+
+/// Put `val` only if the key's current metadata is the same version as `expected`
+/// (the key must not exist if `None`); `DbError::Conflict` otherwise. Returns the
+/// metadata of the write. Needs metadata to be enabled.
+pub fn put_if<S: Storage + ?Sized>(storage: &S, key: Vec<u8>, val: Vec<u8>, expected: Option<&KeyMeta>) -> Result<KeyMeta> {
+    put_when(storage, key, val, expected.map_or(Expect::Absent, Expect::Version))
+}
+
+/// Put `val` only if the key exists, whatever its version (HTTP `If-Match: *`);
+/// `DbError::Conflict` otherwise
+pub fn put_if_exists<S: Storage + ?Sized>(storage: &S, key: Vec<u8>, val: Vec<u8>) -> Result<KeyMeta> {
+    put_when(storage, key, val, Expect::Present)
+}
+
+fn put_when<S: Storage + ?Sized>(storage: &S, key: Vec<u8>, val: Vec<u8>, expected: Expect<'_>) -> Result<KeyMeta> {
+    let _g = conditional(storage, &key, expected)?;
+    let published = watch::wanted(&Operation::Update, &key).then(|| val.clone());
+    storage.put(&Space(KV_SPACE.into()), key.clone(), val)?;
+    let meta = record(storage, &key, Change::Put { ttl_ms: None })?.expect("puts record metadata");
+    if let Some(val) = published {
+        watch::publish(Operation::Update, &key, Some(val));
+    }
+    Ok(meta)
+}
+
+/// Delete the key only if its current metadata is the same version as `expected`
+pub fn del_if<S: Storage + ?Sized>(storage: &S, key: &[u8], expected: &KeyMeta) -> Result<()> {
+    let _g = conditional(storage, key, Expect::Version(expected))?;
+    storage.del(&Space(KV_SPACE.into()), key)?;
+    record(storage, key, Change::Delete)?;
+    watch::publish(Operation::Delete, key, None);
+    Ok(())
+}
+
+/// What a conditional write expects of the key's current state
+#[derive(Clone, Copy)]
+enum Expect<'a> {
+    Absent,
+    Present,
+    Version(&'a KeyMeta),
+}
+
+/// Lock `key` and check it against `expected`
+fn conditional<S: Storage + ?Sized>(storage: &S, key: &[u8], expected: Expect<'_>) -> Result<MutexGuard<'static, ()>> {
+    if !enabled() {
+        return Err(DbError::Invalid("conditional writes need key metadata to be enabled".into()));
+    }
+    let guard = lock(key);
+    let current = match crate::get(storage, key)? {
+        Some(_) => Some(read(storage, key)?),
+        None => None,
+    };
+    let matches = match (current, expected) {
+        (None, Expect::Absent) | (Some(_), Expect::Present) => true,
+        (Some(Some(cur)), Expect::Version(exp)) => cur.same_version(exp),
+        _ => false,
+    };
+    if !matches {
+        return Err(DbError::Conflict(format!("key {:?} has changed", String::from_utf8_lossy(key))));
+    }
+    Ok(guard)
+}
+
+pub(crate) enum Change {
+    Put { ttl_ms: Option<u64> },
+    Delete,
+}
+
+/// Run `write`, a write of `key` to the kv space, and record its metadata if enabled
+pub(crate) fn tracked<S: Storage + ?Sized>(storage: &S, key: &[u8], change: Change, write: impl FnOnce() -> Result<()>) -> Result<()> {
+    if !enabled() {
+        return write();
+    }
+    let _g = lock(key);
+    write()?;
+    record(storage, key, change)?;
+    Ok(())
+}
+
+/// Record writes already applied to the kv space (a committed transaction)
+pub(crate) fn record_all<'a, S: Storage + ?Sized>(storage: &S, writes: impl IntoIterator<Item = (&'a [u8], bool)>) -> Result<()> {
+    if !enabled() {
+        return Ok(());
+    }
+    for (key, deleted) in writes {
+        let _g = lock(key);
+        record(storage, key, if deleted { Change::Delete } else { Change::Put { ttl_ms: None } })?;
+    }
+    Ok(())
+}
+
+/// Drop the metadata of every key under `prefix` (after clearing them)
+pub(crate) fn clear_prefix<S: Storage + ?Sized>(storage: &S, prefix: &[u8]) -> Result<()> {
+    if enabled() {
+        storage.delete_prefix(&Space(META_SPACE.into()), prefix)?;
+    }
+    Ok(())
+}
+
+fn record<S: Storage + ?Sized>(storage: &S, key: &[u8], change: Change) -> Result<Option<KeyMeta>> {
+    let space = Space(META_SPACE.into());
+    let ttl_ms = match change {
+        Change::Delete => return storage.del(&space, key).map(|_| None),
+        Change::Put { ttl_ms } => ttl_ms,
+    };
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    let meta = match read(storage, key)? {
+        Some(m) => KeyMeta { version: m.version + 1, updated_at: now, ..m },
+        None => KeyMeta { version: 1, created_at: now, updated_at: now },
+    };
+    match ttl_ms {
+        Some(ttl) => storage.put_with_ttl(&space, key.to_vec(), meta.encode(), ttl)?,
+        None => storage.put(&space, key.to_vec(), meta.encode())?,
+    }
+    Ok(Some(meta))
+}
+
+fn read<S: Storage + ?Sized>(storage: &S, key: &[u8]) -> Result<Option<KeyMeta>> {
+    storage.get(&Space(META_SPACE.into()), key)?.map(|raw| KeyMeta::decode(&raw)).transpose()
+}
+
+fn lock(key: &[u8]) -> MutexGuard<'static, ()> {
+    LOCKS.lock(key)
+}
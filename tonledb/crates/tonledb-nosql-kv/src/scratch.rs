@@ -0,0 +1,180 @@
+//! Session-scoped scratch namespace.
+//!
+//! A scratch session is a private, in-memory key/value map for passing
+//! intermediate state between the steps of a pipeline (a stored procedure, a
+//! multi-request batch) without writing to the durable `Space("kv")`: nothing is
+//! logged to the WAL and everything is gone when the session ends. A session ends
+//! when `end` is called, when its `ScratchGuard` is dropped (tie one to a
+//! transaction's scope and it is cleared at commit or abort), or after
+//! `ScratchLimits::idle_ms` without use.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use parking_lot::RwLock;
+use tonledb_core::{DbError, Result};
+
+#[derive(Debug, Clone, Copy)]
+pub struct ScratchLimits {
+    /// Sessions unused for this long are dropped
+    pub idle_ms: u64,
+    /// Bytes (keys + values) one session may hold
+    pub max_bytes: usize,
+}
+
+impl Default for ScratchLimits {
+    fn default() -> Self {
+        Self { idle_ms: 600_000, max_bytes: 16 << 20 }
+    }
+}
+
+struct Session {
+    owner: String,
+    data: BTreeMap<Vec<u8>, Vec<u8>>,
+    bytes: usize,
+    last_used_ms: u64,
+}
+
+/// All open scratch sessions
+pub struct ScratchRegistry {
+    limits: ScratchLimits,
+    sessions: RwLock<HashMap<String, Session>>,
+    next: AtomicU64,
+}
+
+impl ScratchRegistry {
+    pub fn new(limits: ScratchLimits) -> Self {
+        Self { limits, sessions: RwLock::new(HashMap::new()), next: AtomicU64::new(1) }
+    }
+
+    /// Open a session for `owner` and return its id. Idle sessions are dropped first.
+    pub fn begin(&self, owner: &str) -> String {
+        self.expire_idle();
+        let id = format!("s{:x}-{:x}", now_ms(), self.next.fetch_add(1, Ordering::Relaxed));
+        let session = Session { owner: owner.to_string(), data: BTreeMap::new(), bytes: 0, last_used_ms: now_ms() };
+        self.sessions.write().insert(id.clone(), session);
+        id
+    }
+
+    /// Open a session that ends when the returned guard is dropped
+    pub fn scoped(&self, owner: &str) -> ScratchGuard<'_> {
+        ScratchGuard { registry: self, owner: owner.to_string(), id: self.begin(owner) }
+    }
+
+    /// End a session, discarding its data. Returns `false` if it was not open.
+    pub fn end(&self, owner: &str, session: &str) -> Result<bool> {
+        let mut sessions = self.sessions.write();
+        match sessions.get(session) {
+            None => Ok(false),
+            Some(s) if s.owner != owner => Err(not_found(session)),
+            Some(_) => Ok(sessions.remove(session).is_some()),
+        }
+    }
+
+    pub fn get(&self, owner: &str, session: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.with_session(owner, session, |s| Ok(s.data.get(key).cloned()))
+    }
+
+    pub fn put(&self, owner: &str, session: &str, key: Vec<u8>, val: Vec<u8>) -> Result<()> {
+        let max = self.limits.max_bytes;
+        self.with_session(owner, session, |s| {
+            let old = s.data.get(&key).map_or(0, |v| key.len() + v.len());
+            let bytes = s.bytes - old + key.len() + val.len();
+            if bytes > max {
+                return Err(DbError::ResourceExhausted(format!("scratch session {} is over its {} byte limit", session, max)));
+            }
+            s.bytes = bytes;
+            s.data.insert(key, val);
+            Ok(())
+        })
+    }
+
+    pub fn del(&self, owner: &str, session: &str, key: &[u8]) -> Result<()> {
+        self.with_session(owner, session, |s| {
+            if let Some(v) = s.data.remove(key) {
+                s.bytes -= key.len() + v.len();
+            }
+            Ok(())
+        })
+    }
+
+    /// (key, value) pairs of the session whose key starts with `prefix`, sorted by key
+    pub fn scan_prefix(&self, owner: &str, session: &str, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.with_session(owner, session, |s| {
+            Ok(s.data
+                .range(prefix.to_vec()..)
+                .take_while(|(k, _)| k.starts_with(prefix))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect())
+        })
+    }
+
+    /// Drop sessions idle for longer than the limit. Returns how many were dropped.
+    pub fn expire_idle(&self) -> usize {
+        let cutoff = now_ms().saturating_sub(self.limits.idle_ms);
+        let mut sessions = self.sessions.write();
+        let before = sessions.len();
+        sessions.retain(|_, s| s.last_used_ms >= cutoff);
+        before - sessions.len()
+    }
+
+    pub fn open_sessions(&self) -> usize {
+        self.sessions.read().len()
+    }
+
+    /// Run `f` on an open session of `owner`, marking it used. Another user's
+    /// session is reported as missing rather than forbidden, so ids can't be probed.
+    fn with_session<T>(&self, owner: &str, session: &str, f: impl FnOnce(&mut Session) -> Result<T>) -> Result<T> {
+        let mut sessions = self.sessions.write();
+        let s = sessions.get_mut(session).filter(|s| s.owner == owner).ok_or_else(|| not_found(session))?;
+        s.last_used_ms = now_ms();
+        f(s)
+    }
+}
+
+/// A scratch session that ends when dropped
+pub struct ScratchGuard<'a> {
+    registry: &'a ScratchRegistry,
+    owner: String,
+    id: String,
+}
+
+impl ScratchGuard<'_> {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.registry.get(&self.owner, &self.id, key)
+    }
+
+    pub fn put(&self, key: Vec<u8>, val: Vec<u8>) -> Result<()> {
+        self.registry.put(&self.owner, &self.id, key, val)
+    }
+
+    pub fn del(&self, key: &[u8]) -> Result<()> {
+        self.registry.del(&self.owner, &self.id, key)
+    }
+
+    pub fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.registry.scan_prefix(&self.owner, &self.id, prefix)
+    }
+}
+
+impl Drop for ScratchGuard<'_> {
+    fn drop(&mut self) {
+        self.registry.sessions.write().remove(&self.id);
+    }
+}
+
+// ---------- helpers ----------
+
+fn not_found(session: &str) -> DbError {
+    DbError::NotFound(format!("scratch session {}", session))
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
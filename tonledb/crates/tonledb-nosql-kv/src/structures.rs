@@ -0,0 +1,216 @@
+//! Redis-style lists, sets and hashes over the kv space.
+//!
+//! Each structure is a run of keys sharing a prefix: a NUL, a tag byte (`h` hash,
+//! `s` set, `l` list), the name's length as a u32, then the name. A hash field or
+//! set member follows the prefix directly, so a whole hash or set is one prefix
+//! scan and a field is one point read. List elements follow it as an
+//! order-preserving position, and a separate `\0L` key holds the positions of the
+//! head and tail: pushes and pops at either end are O(1) and `lrange` is one range
+//! scan. Read-modify-writes of one structure are serialised within the process.
+//! Lists, sets and hashes have separate namespaces, and an emptied structure
+//! leaves no keys behind.
+
+use tonledb_core::{DbError, Result, Storage};
+use crate::Stripes;
+
+const HASH: u8 = b'h';
+const SET: u8 = b's';
+const LIST: u8 = b'l';
+const LIST_ENDS: u8 = b'L';
+
+static LOCKS: Stripes = Stripes::new();
+
+fn prefix(tag: u8, name: &[u8]) -> Vec<u8> {
+    let mut p = vec![0, tag];
+    p.extend_from_slice(&(name.len() as u32).to_be_bytes());
+    p.extend_from_slice(name);
+    p
+}
+
+fn member_key(tag: u8, name: &[u8], member: &[u8]) -> Vec<u8> {
+    let mut k = prefix(tag, name);
+    k.extend_from_slice(member);
+    k
+}
+
+/// Members under `prefix` with the prefix stripped, and their values
+fn members<S: Storage + ?Sized>(storage: &S, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    Ok(crate::scan_prefix(storage, prefix)?.into_iter().map(|(k, v)| (k[prefix.len()..].to_vec(), v)).collect())
+}
+
+fn count<S: Storage + ?Sized>(storage: &S, prefix: &[u8]) -> Result<usize> {
+    crate::scan_prefix_iter(storage, prefix).try_fold(0, |n, row| row.map(|_| n + 1))
+}
+
+// ---------- hashes ----------
+
+/// Set `field` of hash `name`; returns `true` if the field is new
+pub fn hset<S: Storage + ?Sized>(storage: &S, name: &[u8], field: &[u8], value: Vec<u8>) -> Result<bool> {
+    let _g = LOCKS.lock(&prefix(HASH, name));
+    let key = member_key(HASH, name, field);
+    let new = crate::get(storage, &key)?.is_none();
+    crate::put(storage, key, value)?;
+    Ok(new)
+}
+
+pub fn hget<S: Storage + ?Sized>(storage: &S, name: &[u8], field: &[u8]) -> Result<Option<Vec<u8>>> {
+    crate::get(storage, &member_key(HASH, name, field))
+}
+
+/// Remove `field`; returns `true` if it was there
+pub fn hdel<S: Storage + ?Sized>(storage: &S, name: &[u8], field: &[u8]) -> Result<bool> {
+    let _g = LOCKS.lock(&prefix(HASH, name));
+    let key = member_key(HASH, name, field);
+    if crate::get(storage, &key)?.is_none() {
+        return Ok(false);
+    }
+    crate::del(storage, &key)?;
+    Ok(true)
+}
+
+/// Every (field, value) of the hash, in field order
+pub fn hgetall<S: Storage + ?Sized>(storage: &S, name: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    members(storage, &prefix(HASH, name))
+}
+
+pub fn hlen<S: Storage + ?Sized>(storage: &S, name: &[u8]) -> Result<usize> {
+    count(storage, &prefix(HASH, name))
+}
+
+// ---------- sets ----------
+
+/// Add `member` to set `name`; returns `true` if it was not already there
+pub fn sadd<S: Storage + ?Sized>(storage: &S, name: &[u8], member: &[u8]) -> Result<bool> {
+    let _g = LOCKS.lock(&prefix(SET, name));
+    let key = member_key(SET, name, member);
+    if crate::get(storage, &key)?.is_some() {
+        return Ok(false);
+    }
+    crate::put(storage, key, Vec::new())?;
+    Ok(true)
+}
+
+/// Remove `member`; returns `true` if it was there
+pub fn srem<S: Storage + ?Sized>(storage: &S, name: &[u8], member: &[u8]) -> Result<bool> {
+    let _g = LOCKS.lock(&prefix(SET, name));
+    let key = member_key(SET, name, member);
+    if crate::get(storage, &key)?.is_none() {
+        return Ok(false);
+    }
+    crate::del(storage, &key)?;
+    Ok(true)
+}
+
+pub fn sismember<S: Storage + ?Sized>(storage: &S, name: &[u8], member: &[u8]) -> Result<bool> {
+    crate::exists(storage, &member_key(SET, name, member))
+}
+
+/// Every member of the set, in byte order
+pub fn smembers<S: Storage + ?Sized>(storage: &S, name: &[u8]) -> Result<Vec<Vec<u8>>> {
+    Ok(members(storage, &prefix(SET, name))?.into_iter().map(|(m, _)| m).collect())
+}
+
+pub fn scard<S: Storage + ?Sized>(storage: &S, name: &[u8]) -> Result<usize> {
+    count(storage, &prefix(SET, name))
+}
+
+// ---------- lists ----------
+
+/// Positions of the head and tail of a non-empty list, both inclusive
+#[derive(Clone, Copy)]
+struct Ends {
+    head: i64,
+    tail: i64,
+}
+
+impl Ends {
+    fn len(&self) -> usize {
+        (self.tail - self.head + 1) as usize
+    }
+}
+
+/// Big-endian with the sign bit flipped, so positions sort as numbers
+fn element_key(name: &[u8], pos: i64) -> Vec<u8> {
+    member_key(LIST, name, &((pos as u64) ^ (1 << 63)).to_be_bytes())
+}
+
+fn read_ends<S: Storage + ?Sized>(storage: &S, name: &[u8]) -> Result<Option<Ends>> {
+    let Some(raw) = crate::get(storage, &prefix(LIST_ENDS, name))? else { return Ok(None) };
+    let n = |i: usize| raw.get(i * 8..i * 8 + 8).and_then(|b| b.try_into().ok()).map(i64::from_be_bytes);
+    match (n(0), n(1)) {
+        (Some(head), Some(tail)) if raw.len() == 16 && head <= tail => Ok(Some(Ends { head, tail })),
+        _ => Err(DbError::Storage(format!("corrupt list header for {:?}", String::from_utf8_lossy(name)))),
+    }
+}
+
+fn write_ends<S: Storage + ?Sized>(storage: &S, name: &[u8], ends: Option<Ends>) -> Result<()> {
+    match ends {
+        Some(e) => crate::put(storage, prefix(LIST_ENDS, name), [e.head.to_be_bytes(), e.tail.to_be_bytes()].concat()),
+        None => crate::del(storage, &prefix(LIST_ENDS, name)),
+    }
+}
+
+fn push<S: Storage + ?Sized>(storage: &S, name: &[u8], value: Vec<u8>, front: bool) -> Result<usize> {
+    let _g = LOCKS.lock(&prefix(LIST, name));
+    let ends = match read_ends(storage, name)? {
+        None => Ends { head: 0, tail: 0 },
+        Some(e) if front => Ends { head: e.head - 1, ..e },
+        Some(e) => Ends { tail: e.tail + 1, ..e },
+    };
+    let pos = if front { ends.head } else { ends.tail };
+    crate::put(storage, element_key(name, pos), value)?;
+    write_ends(storage, name, Some(ends))?;
+    Ok(ends.len())
+}
+
+fn pop<S: Storage + ?Sized>(storage: &S, name: &[u8], front: bool) -> Result<Option<Vec<u8>>> {
+    let _g = LOCKS.lock(&prefix(LIST, name));
+    let Some(ends) = read_ends(storage, name)? else { return Ok(None) };
+    let pos = if front { ends.head } else { ends.tail };
+    let key = element_key(name, pos);
+    let value = crate::get(storage, &key)?;
+    crate::del(storage, &key)?;
+    let rest = match ends.len() {
+        1 => None,
+        _ if front => Some(Ends { head: ends.head + 1, ..ends }),
+        _ => Some(Ends { tail: ends.tail - 1, ..ends }),
+    };
+    write_ends(storage, name, rest)?;
+    Ok(value)
+}
+
+/// Push `value` onto the front of list `name`; returns the new length
+pub fn lpush<S: Storage + ?Sized>(storage: &S, name: &[u8], value: Vec<u8>) -> Result<usize> {
+    push(storage, name, value, true)
+}
+
+/// Push `value` onto the back of list `name`; returns the new length
+pub fn rpush<S: Storage + ?Sized>(storage: &S, name: &[u8], value: Vec<u8>) -> Result<usize> {
+    push(storage, name, value, false)
+}
+
+pub fn lpop<S: Storage + ?Sized>(storage: &S, name: &[u8]) -> Result<Option<Vec<u8>>> {
+    pop(storage, name, true)
+}
+
+pub fn rpop<S: Storage + ?Sized>(storage: &S, name: &[u8]) -> Result<Option<Vec<u8>>> {
+    pop(storage, name, false)
+}
+
+pub fn llen<S: Storage + ?Sized>(storage: &S, name: &[u8]) -> Result<usize> {
+    Ok(read_ends(storage, name)?.map_or(0, |e| e.len()))
+}
+
+/// Elements `start..=stop` from the front; negative indexes count from the back
+/// (`-1` is the last element), as in Redis `LRANGE`.
+pub fn lrange<S: Storage + ?Sized>(storage: &S, name: &[u8], start: i64, stop: i64) -> Result<Vec<Vec<u8>>> {
+    let Some(ends) = read_ends(storage, name)? else { return Ok(Vec::new()) };
+    let len = ends.len() as i64;
+    let start = if start < 0 { len + start } else { start }.max(0);
+    let stop = if stop < 0 { len + stop } else { stop }.min(len - 1);
+    if start > stop {
+        return Ok(Vec::new());
+    }
+    let (from, to) = (element_key(name, ends.head + start), element_key(name, ends.head + stop + 1));
+    Ok(crate::scan_range(storage, &from, Some(&to), (stop - start + 1) as usize)?.into_iter().map(|(_, v)| v).collect())
+}
@@ -0,0 +1,78 @@
+//! Multi-key transactions over the kv space.
+//!
+//! A `KvTxn` is a transaction of the core `TransactionManager`: reads see the
+//! snapshot taken at `begin` plus the transaction's own writes, writes are
+//! buffered, and `commit` applies them all at one version or fails with
+//! `DbError::Conflict` if another transaction committed to one of the same keys
+//! first. A transaction dropped without committing is aborted. Conflicts are only
+//! detected against other transactions: plain `put`s carry no version.
+
+use std::sync::Arc;
+use tonledb_core::event_sourcing::Operation;
+use tonledb_core::transaction::{TransactionManager, TXN_MANAGER};
+use tonledb_core::{Result, Space, Storage};
+use crate::{meta, watch, KV_SPACE};
+
+pub struct KvTxn<'a, S: Storage + ?Sized> {
+    storage: &'a S,
+    manager: Arc<TransactionManager>,
+    id: u64,
+    done: bool,
+}
+
+/// Start a transaction on the process-wide transaction manager
+pub fn begin<S: Storage + ?Sized>(storage: &S) -> Result<KvTxn<'_, S>> {
+    begin_with(storage, TXN_MANAGER.clone())
+}
+
+/// Start a transaction on `manager`
+pub fn begin_with<S: Storage + ?Sized>(storage: &S, manager: Arc<TransactionManager>) -> Result<KvTxn<'_, S>> {
+    let id = manager.begin()?;
+    Ok(KvTxn { storage, manager, id, done: false })
+}
+
+impl<S: Storage + ?Sized> KvTxn<'_, S> {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.manager.get(self.storage, self.id, &Space(KV_SPACE.into()), key)
+    }
+
+    pub fn put(&self, key: Vec<u8>, val: Vec<u8>) -> Result<()> {
+        self.manager.put(self.id, Space(KV_SPACE.into()), key, val)
+    }
+
+    pub fn del(&self, key: &[u8]) -> Result<()> {
+        self.manager.delete(self.id, Space(KV_SPACE.into()), key.to_vec())
+    }
+
+    /// Apply every buffered write atomically. On a conflict nothing is written and
+    /// the transaction is aborted.
+    pub fn commit(mut self) -> Result<()> {
+        self.done = true;
+        let writes = self.manager.get_transaction(self.id).map(|t| t.write_set).unwrap_or_default();
+        self.manager.commit(self.storage, self.id)?;
+        meta::record_all(self.storage, writes.iter().map(|((_, key), val)| (key.as_slice(), val.is_none())))?;
+        for ((_, key), val) in writes {
+            let op = if val.is_some() { Operation::Update } else { Operation::Delete };
+            watch::publish(op, &key, val);
+        }
+        Ok(())
+    }
+
+    /// Discard every buffered write
+    pub fn abort(mut self) -> Result<()> {
+        self.done = true;
+        self.manager.abort(self.id)
+    }
+}
+
+impl<S: Storage + ?Sized> Drop for KvTxn<'_, S> {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.manager.abort(self.id);
+        }
+    }
+}
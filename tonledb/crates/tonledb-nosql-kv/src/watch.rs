@@ -0,0 +1,200 @@
+//! Prefix watches on KV keys.
+//!
+//! Writes made through this crate publish change events (table `kv`) to the
+//! global changefeed manager; a watch is a changefeed over the keys under one
+//! prefix, consumed as a `Stream` of `KvEvent`s or by polling. Puts are published
+//! as updates: the KV API does not read a key before writing it.
+//!
+//! Each watch queues at most a fixed number of events. A watcher that falls that
+//! far behind is disconnected: its queue is dropped and its stream ends, so the
+//! client can reconnect and re-read what it missed.
+
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+use futures_core::Stream;
+use parking_lot::{Condvar, Mutex};
+use tonledb_core::event_sourcing::{ChangeEvent, Operation, EVENT_MANAGER};
+use tonledb_core::{DbError, Result};
+use crate::KV_SPACE;
+
+static WATCH_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// Events a watch queues before it is disconnected (see `watch_with_capacity`)
+pub const DEFAULT_WATCH_CAPACITY: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KvEventKind {
+    Put,
+    Delete,
+}
+
+/// A write to one key as delivered to watchers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KvEvent {
+    pub kind: KvEventKind,
+    pub key: Vec<u8>,
+    /// The value written; `None` for deletes
+    pub value: Option<Vec<u8>>,
+    /// Wall-clock time of the write, epoch milliseconds
+    pub timestamp: u64,
+}
+
+#[derive(Default)]
+struct Inbox {
+    events: VecDeque<KvEvent>,
+    waker: Option<Waker>,
+    /// Set once the queue overflowed; no events are queued after that
+    disconnected: bool,
+}
+
+#[derive(Default)]
+struct Shared {
+    inbox: Mutex<Inbox>,
+    ready: Condvar,
+}
+
+/// A live watch; stops when dropped. Events queue up until received, up to the
+/// watch's capacity; past it the watch is disconnected.
+pub struct KvWatch {
+    id: String,
+    shared: Arc<Shared>,
+}
+
+/// Watch every put and delete of keys starting with `prefix` (all keys if empty),
+/// queueing up to `DEFAULT_WATCH_CAPACITY` events
+pub fn watch(prefix: &[u8]) -> Result<KvWatch> {
+    watch_with_capacity(prefix, DEFAULT_WATCH_CAPACITY)
+}
+
+/// `watch`, disconnecting the watcher once `capacity` events wait to be received
+pub fn watch_with_capacity(prefix: &[u8], capacity: usize) -> Result<KvWatch> {
+    let id = format!("kv-watch-{}", WATCH_SEQ.fetch_add(1, Ordering::Relaxed));
+    let shared = Arc::new(Shared::default());
+    let sink = shared.clone();
+    EVENT_MANAGER
+        .register_prefix_feed(
+            id.clone(),
+            Some(KV_SPACE.to_string()),
+            prefix.to_vec(),
+            move |event| {
+                let mut inbox = sink.inbox.lock();
+                if inbox.disconnected {
+                    return;
+                }
+                if inbox.events.len() >= capacity {
+                    inbox.events.clear();
+                    inbox.disconnected = true;
+                } else {
+                    inbox.events.push_back(KvEvent::from_event(event));
+                }
+                if let Some(w) = inbox.waker.take() {
+                    w.wake();
+                }
+                sink.ready.notify_all();
+            },
+        )
+        .map_err(DbError::Invalid)?;
+    Ok(KvWatch { id, shared })
+}
+
+impl KvWatch {
+    /// The changefeed id this watch is registered under
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Whether the watcher fell too far behind and was disconnected; it receives
+    /// nothing more
+    pub fn is_disconnected(&self) -> bool {
+        self.shared.inbox.lock().disconnected
+    }
+
+    /// The next queued event, if any
+    pub fn try_recv(&self) -> Option<KvEvent> {
+        self.shared.inbox.lock().events.pop_front()
+    }
+
+    /// Wait up to `timeout` for the next event
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<KvEvent> {
+        let deadline = Instant::now() + timeout;
+        let mut inbox = self.shared.inbox.lock();
+        loop {
+            if let Some(e) = inbox.events.pop_front() {
+                return Some(e);
+            }
+            if inbox.disconnected {
+                return None;
+            }
+            if self.shared.ready.wait_until(&mut inbox, deadline).timed_out() {
+                return inbox.events.pop_front();
+            }
+        }
+    }
+}
+
+impl Stream for KvWatch {
+    type Item = KvEvent;
+
+    /// Ends only when the watcher is disconnected; drop the watch to stop it
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<KvEvent>> {
+        let mut inbox = self.shared.inbox.lock();
+        match inbox.events.pop_front() {
+            Some(e) => Poll::Ready(Some(e)),
+            None if inbox.disconnected => Poll::Ready(None),
+            None => {
+                inbox.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for KvWatch {
+    fn drop(&mut self) {
+        EVENT_MANAGER.unregister_feed(&self.id);
+    }
+}
+
+impl KvEvent {
+    fn from_event(event: ChangeEvent) -> Self {
+        Self {
+            kind: match event.operation {
+                Operation::Delete => KvEventKind::Delete,
+                Operation::Insert | Operation::Update => KvEventKind::Put,
+            },
+            key: event.key.unwrap_or_default(),
+            value: event.new_value,
+            timestamp: event.timestamp,
+        }
+    }
+}
+
+/// Whether a changefeed (or KV watch) would receive a write of `key`; writers check
+/// it before copying a value to publish
+pub(crate) fn wanted(operation: &Operation, key: &[u8]) -> bool {
+    EVENT_MANAGER.wants(KV_SPACE, operation, key)
+}
+
+/// Notify changefeeds (and KV watches) of a write
+pub(crate) fn publish(operation: Operation, key: &[u8], new_value: Option<Vec<u8>>) {
+    if !wanted(&operation, key) {
+        return;
+    }
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    EVENT_MANAGER.publish_event(ChangeEvent {
+        id: nanoid::nanoid!(),
+        timestamp,
+        operation,
+        table: KV_SPACE.to_string(),
+        key: Some(key.to_vec()),
+        old_value: None,
+        new_value,
+    });
+}
@@ -0,0 +1,47 @@
+//! Tests for named KV buckets
+
+use tonledb_core::DbError;
+use tonledb_nosql_kv::bucket::BucketStats;
+use tonledb_nosql_kv::{bucket, get, put};
+use tonledb_storage::InMemoryStore;
+
+#[test]
+fn test_buckets_are_separate_namespaces() {
+    let store = InMemoryStore::new(100);
+    let sessions = bucket("sessions");
+    let carts = bucket("carts");
+    sessions.put(&store, b"u1".to_vec(), b"s".to_vec()).unwrap();
+    carts.put(&store, b"u1".to_vec(), b"c".to_vec()).unwrap();
+    put(&store, b"u1".to_vec(), b"flat".to_vec()).unwrap();
+
+    assert_eq!(sessions.get(&store, b"u1").unwrap(), Some(b"s".to_vec()));
+    assert_eq!(carts.get(&store, b"u1").unwrap(), Some(b"c".to_vec()));
+    assert_eq!(get(&store, b"u1").unwrap(), Some(b"flat".to_vec()));
+    assert_eq!(sessions.keys(&store).unwrap(), vec![b"u1".to_vec()]);
+    assert!(!sessions.set_if_absent(&store, b"u1".to_vec(), b"x".to_vec()).unwrap());
+
+    assert!(matches!(bucket("a/b").put(&store, b"k".to_vec(), b"v".to_vec()), Err(DbError::Invalid(_))));
+    assert!(bucket("").get(&store, b"k").is_err());
+}
+
+#[test]
+fn test_listing_clearing_and_stats() {
+    let store = InMemoryStore::new(100);
+    let b = bucket("jobs");
+    for i in 0..5u8 {
+        b.put(&store, vec![b'j', b'0' + i], vec![i; 10]).unwrap();
+    }
+    bucket("other").put(&store, b"j0".to_vec(), b"x".to_vec()).unwrap();
+    assert_eq!(b.stats(&store).unwrap(), BucketStats { keys: 5, bytes: 5 * 12 });
+
+    let page = b.scan_prefix_page(&store, b"j", None, 3).unwrap();
+    assert_eq!(page.items.len(), 3);
+    assert_eq!(page.next, Some(b"j2".to_vec()));
+    let rest = b.scan_prefix_page(&store, b"j", page.next.as_deref(), 3).unwrap();
+    assert_eq!(rest.items.iter().map(|(k, _)| k.clone()).collect::<Vec<_>>(), vec![b"j3".to_vec(), b"j4".to_vec()]);
+    assert_eq!(rest.next, None);
+
+    assert_eq!(b.clear(&store).unwrap(), 5);
+    assert_eq!(b.stats(&store).unwrap(), BucketStats::default());
+    assert_eq!(bucket("other").get(&store, b"j0").unwrap(), Some(b"x".to_vec()));
+}
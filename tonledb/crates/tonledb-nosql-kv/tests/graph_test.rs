@@ -0,0 +1,75 @@
+//! Tests for the graph layer: adjacency, traversals and deletes
+
+use serde_json::Value as Json;
+use tonledb_core::Storage;
+use tonledb_nosql_kv::graph::{self, Bounds, Direction, Edge, Node};
+use tonledb_storage::InMemoryStore;
+
+fn node(s: &dyn Storage, id: &str) {
+    graph::put_node(s, &Node { id: id.into(), label: "user".into(), props: Json::Null }).unwrap();
+}
+
+fn edge(s: &dyn Storage, from: &str, t: &str, to: &str) {
+    graph::put_edge(s, &Edge { from: from.into(), to: to.into(), edge_type: t.into(), props: Json::Null }).unwrap();
+}
+
+fn social() -> InMemoryStore {
+    let s = InMemoryStore::new(100);
+    for id in ["ann", "bob", "cat", "dan", "eve"] {
+        node(&s, id);
+    }
+    edge(&s, "ann", "follows", "bob");
+    edge(&s, "bob", "follows", "cat");
+    edge(&s, "cat", "follows", "dan");
+    edge(&s, "ann", "blocks", "eve");
+    s
+}
+
+#[test]
+fn test_neighbors_by_type_and_direction() {
+    let s = social();
+    assert_eq!(graph::neighbors(&s, "ann", Some("follows"), Direction::Out).unwrap(), vec!["bob"]);
+    let mut all = graph::neighbors(&s, "ann", None, Direction::Out).unwrap();
+    all.sort();
+    assert_eq!(all, vec!["bob", "eve"]);
+    assert_eq!(graph::neighbors(&s, "bob", None, Direction::In).unwrap(), vec!["ann"]);
+    let mut both = graph::neighbors(&s, "bob", None, Direction::Both).unwrap();
+    both.sort();
+    assert_eq!(both, vec!["ann", "cat"]);
+}
+
+#[test]
+fn test_bfs_respects_depth() {
+    let s = social();
+    let visited = graph::bfs(&s, "ann", Some("follows"), Direction::Out, Bounds { max_depth: 2, max_nodes: 100 }).unwrap();
+    assert_eq!(visited, vec![("ann".to_string(), 0), ("bob".to_string(), 1), ("cat".to_string(), 2)]);
+    let capped = graph::bfs(&s, "ann", None, Direction::Out, Bounds { max_depth: 5, max_nodes: 2 }).unwrap();
+    assert_eq!(capped.len(), 2);
+}
+
+#[test]
+fn test_shortest_path() {
+    let s = social();
+    let path = graph::shortest_path(&s, "ann", "dan", Some("follows"), Direction::Out, Bounds::default()).unwrap();
+    assert_eq!(path, Some(vec!["ann".to_string(), "bob".into(), "cat".into(), "dan".into()]));
+    let too_far = graph::shortest_path(&s, "ann", "dan", None, Direction::Out, Bounds { max_depth: 2, max_nodes: 100 }).unwrap();
+    assert_eq!(too_far, None);
+    assert_eq!(graph::shortest_path(&s, "dan", "ann", None, Direction::Out, Bounds::default()).unwrap(), None);
+}
+
+#[test]
+fn test_delete_node_removes_incident_edges() {
+    let s = social();
+    assert!(graph::delete_node(&s, "bob").unwrap());
+    assert!(graph::get_node(&s, "bob").unwrap().is_none());
+    assert!(graph::neighbors(&s, "ann", Some("follows"), Direction::Out).unwrap().is_empty());
+    assert!(graph::neighbors(&s, "cat", None, Direction::In).unwrap().is_empty());
+}
+
+#[test]
+fn test_edge_requires_existing_nodes() {
+    let s = social();
+    let e = Edge { from: "ann".into(), to: "zed".into(), edge_type: "follows".into(), props: Json::Null };
+    assert!(graph::put_edge(&s, &e).is_err());
+    assert!(graph::put_node(&s, &Node { id: "a/b".into(), label: "x".into(), props: Json::Null }).is_err());
+}
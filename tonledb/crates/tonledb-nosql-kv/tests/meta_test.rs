@@ -0,0 +1,52 @@
+//! Tests for per-key metadata and conditional writes
+
+use tonledb_core::DbError;
+use tonledb_nosql_kv::meta::{self, get_with_meta, put_if};
+use tonledb_nosql_kv::{del, put, txn};
+use tonledb_storage::InMemoryStore;
+
+// Metadata is switched on process-wide; every test here wants it on
+fn store() -> InMemoryStore {
+    meta::set_enabled(true);
+    InMemoryStore::new(100)
+}
+
+#[test]
+fn test_versions_count_writes_and_reset_on_recreate() {
+    let store = store();
+    put(&store, b"k".to_vec(), b"1".to_vec()).unwrap();
+    let (_, first) = get_with_meta(&store, b"k").unwrap().unwrap();
+    let first = first.unwrap();
+    assert_eq!(first.version, 1);
+    std::thread::sleep(std::time::Duration::from_millis(3));
+    put(&store, b"k".to_vec(), b"2".to_vec()).unwrap();
+    let (val, second) = get_with_meta(&store, b"k").unwrap().unwrap();
+    let second = second.unwrap();
+    assert_eq!((val.as_slice(), second.version, second.created_at), (&b"2"[..], 2, first.created_at));
+    assert!(second.updated_at > first.updated_at);
+
+    let t = txn::begin(&store).unwrap();
+    t.put(b"k".to_vec(), b"3".to_vec()).unwrap();
+    t.commit().unwrap();
+    assert_eq!(get_with_meta(&store, b"k").unwrap().unwrap().1.unwrap().version, 3);
+
+    del(&store, b"k").unwrap();
+    assert_eq!(get_with_meta(&store, b"k").unwrap(), None);
+    put(&store, b"k".to_vec(), b"4".to_vec()).unwrap();
+    assert_eq!(get_with_meta(&store, b"k").unwrap().unwrap().1.unwrap().version, 1);
+}
+
+#[test]
+fn test_conditional_writes() {
+    let store = store();
+    let created = put_if(&store, b"c".to_vec(), b"1".to_vec(), None).unwrap();
+    assert!(matches!(put_if(&store, b"c".to_vec(), b"x".to_vec(), None), Err(DbError::Conflict(_))));
+
+    let updated = put_if(&store, b"c".to_vec(), b"2".to_vec(), Some(&created)).unwrap();
+    assert_eq!(updated.version, 2);
+    // A stale version loses
+    assert!(matches!(put_if(&store, b"c".to_vec(), b"x".to_vec(), Some(&created)), Err(DbError::Conflict(_))));
+    assert!(meta::del_if(&store, b"c", &created).is_err());
+    meta::del_if(&store, b"c", &updated).unwrap();
+    assert_eq!(get_with_meta(&store, b"c").unwrap(), None);
+}
@@ -0,0 +1,41 @@
+//! Tests for range and reverse KV scans
+
+use tonledb_core::{Space, Storage};
+use tonledb_nosql_kv::{put, scan_prefix_rev, scan_range, scan_range_rev};
+use tonledb_storage::{ChunkOptions, ChunkedStorage, InMemoryStore};
+
+fn keys(rows: Vec<(Vec<u8>, Vec<u8>)>) -> Vec<String> {
+    rows.into_iter().map(|(k, _)| String::from_utf8(k).unwrap()).collect()
+}
+
+fn fill<S: Storage + ?Sized>(store: &S) {
+    for ts in [100, 105, 110, 120, 130] {
+        put(store, format!("ev/{}", ts).into_bytes(), b"e".to_vec()).unwrap();
+    }
+    put(store, b"ev0".to_vec(), b"x".to_vec()).unwrap();
+    put(store, b"a".to_vec(), b"x".to_vec()).unwrap();
+}
+
+#[test]
+fn test_range_bounds_and_direction() {
+    let store = InMemoryStore::with_shards(100, 4);
+    fill(&store);
+    // Another space sorts after "kv" and must not leak into an open-ended scan
+    store.put(&Space("kw".into()), b"zzz".to_vec(), b"x".to_vec()).unwrap();
+
+    assert_eq!(keys(scan_range(&store, b"ev/105", Some(b"ev/130"), 10).unwrap()), ["ev/105", "ev/110", "ev/120"]);
+    assert_eq!(keys(scan_range(&store, b"ev/105", Some(b"ev/130"), 2).unwrap()), ["ev/105", "ev/110"]);
+    assert_eq!(keys(scan_range_rev(&store, b"ev/105", Some(b"ev/130"), 2).unwrap()), ["ev/120", "ev/110"]);
+    assert_eq!(keys(scan_range_rev(&store, b"", None, 2).unwrap()), ["ev0", "ev/130"]);
+    assert_eq!(keys(scan_prefix_rev(&store, b"ev/", 3).unwrap()), ["ev/130", "ev/120", "ev/110"]);
+    assert!(scan_range(&store, b"z", Some(b"a"), 10).unwrap().is_empty());
+    assert!(scan_range(&store, b"ev/110", Some(b"ev/110"), 10).unwrap().is_empty());
+}
+
+#[test]
+fn test_default_implementation_through_a_wrapper() {
+    let store = ChunkedStorage::new(InMemoryStore::new(100), ChunkOptions::default());
+    fill(&store);
+    assert_eq!(keys(scan_prefix_rev(&store, b"ev/", 2).unwrap()), ["ev/130", "ev/120"]);
+    assert_eq!(keys(scan_range(&store, b"ev/", Some(b"ev/111"), 10).unwrap()), ["ev/100", "ev/105", "ev/110"]);
+}
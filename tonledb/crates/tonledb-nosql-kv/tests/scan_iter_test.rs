@@ -0,0 +1,33 @@
+//! Tests for lazy prefix scans
+
+use std::sync::Arc;
+use tonledb_core::Storage;
+use tonledb_nosql_kv::{put, scan_prefix, scan_prefix_iter};
+use tonledb_storage::InMemoryStore;
+
+#[test]
+fn test_iter_matches_collected_scan_across_batches() {
+    let store = InMemoryStore::with_shards(100, 4);
+    // More than one internal batch
+    for i in 0..600u32 {
+        put(&store, format!("log/{:05}", i).into_bytes(), i.to_be_bytes().to_vec()).unwrap();
+    }
+    put(&store, b"loh".to_vec(), b"x".to_vec()).unwrap();
+
+    let rows: Vec<_> = scan_prefix_iter(&store, b"log/").collect::<Result<_, _>>().unwrap();
+    assert_eq!(rows.len(), 600);
+    assert_eq!(rows, scan_prefix(&store, b"log/").unwrap());
+    assert_eq!(scan_prefix_iter(&store, b"nothing/").count(), 0);
+}
+
+#[test]
+fn test_iter_is_lazy_and_can_own_its_store() {
+    let store: Arc<dyn Storage> = Arc::new(InMemoryStore::new(100));
+    put(&*store, b"a/1".to_vec(), b"1".to_vec()).unwrap();
+    let mut it = scan_prefix_iter(store.clone(), b"a/");
+    // Nothing is read until the first `next`, so this key is seen
+    put(&*store, b"a/2".to_vec(), b"2".to_vec()).unwrap();
+    let keys: Vec<_> = it.by_ref().map(|r| r.unwrap().0).collect();
+    assert_eq!(keys, vec![b"a/1".to_vec(), b"a/2".to_vec()]);
+    assert!(it.next().is_none());
+}
@@ -0,0 +1,51 @@
+//! Tests for paged prefix scans
+
+use tonledb_core::{Space, Storage};
+use tonledb_nosql_kv::{put, scan_prefix, scan_prefix_page};
+use tonledb_storage::InMemoryStore;
+
+#[test]
+fn test_pages_cover_the_prefix_once_in_order() {
+    let store = InMemoryStore::with_shards(100, 4);
+    for i in 0..25u32 {
+        put(&store, format!("user/{:03}", i).into_bytes(), i.to_be_bytes().to_vec()).unwrap();
+    }
+    put(&store, b"usez".to_vec(), b"x".to_vec()).unwrap();
+    put(&store, b"other".to_vec(), b"x".to_vec()).unwrap();
+
+    let mut seen = Vec::new();
+    let mut after: Option<Vec<u8>> = None;
+    loop {
+        let page = scan_prefix_page(&store, b"user/", after.as_deref(), 10).unwrap();
+        assert!(page.items.len() <= 10);
+        seen.extend(page.items);
+        match page.next {
+            Some(k) => after = Some(k),
+            None => break,
+        }
+    }
+    assert_eq!(seen, scan_prefix(&store, b"user/").unwrap());
+    assert_eq!(seen.len(), 25);
+
+    // A full last page has nothing after it
+    let page = scan_prefix_page(&store, b"user/", Some(b"user/014"), 10).unwrap();
+    assert_eq!(page.items.len(), 10);
+    assert_eq!(page.next, None);
+}
+
+#[test]
+fn test_expired_keys_are_skipped_without_shortening_pages() {
+    let store = InMemoryStore::new(100);
+    let kv = Space("kv".into());
+    for i in 0..6u8 {
+        put(&store, vec![b'k', i], vec![i]).unwrap();
+    }
+    store.put_with_ttl(&kv, vec![b'k', 1], vec![1], 1).unwrap();
+    store.put_with_ttl(&kv, vec![b'k', 2], vec![2], 1).unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(20));
+
+    let page = scan_prefix_page(&store, b"k", None, 3).unwrap();
+    let keys: Vec<Vec<u8>> = page.items.into_iter().map(|(k, _)| k).collect();
+    assert_eq!(keys, vec![vec![b'k', 0], vec![b'k', 3], vec![b'k', 4]]);
+    assert_eq!(page.next, Some(vec![b'k', 4]));
+}
@@ -0,0 +1,51 @@
+//! Tests for session-scoped scratch namespaces
+
+use tonledb_core::DbError;
+use tonledb_nosql_kv::scratch::{ScratchLimits, ScratchRegistry};
+use tonledb_storage::InMemoryStore;
+
+#[test]
+fn test_sessions_are_private_and_never_reach_storage() {
+    let store = InMemoryStore::new(100);
+    let reg = ScratchRegistry::new(ScratchLimits::default());
+    let s = reg.begin("ann");
+    reg.put("ann", &s, b"step/1".to_vec(), b"a".to_vec()).unwrap();
+    reg.put("ann", &s, b"step/2".to_vec(), b"b".to_vec()).unwrap();
+    reg.put("ann", &s, b"other".to_vec(), b"c".to_vec()).unwrap();
+
+    assert_eq!(reg.get("ann", &s, b"step/1").unwrap(), Some(b"a".to_vec()));
+    assert_eq!(reg.scan_prefix("ann", &s, b"step/").unwrap().len(), 2);
+    assert!(matches!(reg.get("bob", &s, b"step/1"), Err(DbError::NotFound(_))));
+    assert!(tonledb_nosql_kv::scan_prefix(&store, b"").unwrap().is_empty());
+
+    assert!(reg.end("ann", &s).unwrap());
+    assert!(reg.get("ann", &s, b"step/1").is_err());
+    assert!(!reg.end("ann", &s).unwrap());
+}
+
+#[test]
+fn test_guard_clears_the_session_on_drop() {
+    let reg = ScratchRegistry::new(ScratchLimits::default());
+    let id = {
+        let scratch = reg.scoped("ann");
+        scratch.put(b"k".to_vec(), b"v".to_vec()).unwrap();
+        assert_eq!(scratch.get(b"k").unwrap(), Some(b"v".to_vec()));
+        scratch.id().to_string()
+    };
+    assert_eq!(reg.open_sessions(), 0);
+    assert!(reg.get("ann", &id, b"k").is_err());
+}
+
+#[test]
+fn test_limits_cap_bytes_and_expire_idle_sessions() {
+    let reg = ScratchRegistry::new(ScratchLimits { idle_ms: 0, max_bytes: 8 });
+    let s = reg.begin("ann");
+    reg.put("ann", &s, b"k".to_vec(), b"1234".to_vec()).unwrap();
+    // Overwrites are charged the difference only
+    reg.put("ann", &s, b"k".to_vec(), b"1234567".to_vec()).unwrap();
+    assert!(matches!(reg.put("ann", &s, b"k2".to_vec(), b"x".to_vec()), Err(DbError::ResourceExhausted(_))));
+
+    std::thread::sleep(std::time::Duration::from_millis(5));
+    assert_eq!(reg.expire_idle(), 1);
+    assert_eq!(reg.open_sessions(), 0);
+}
@@ -0,0 +1,50 @@
+//! Tests for Redis-style lists, sets and hashes
+
+use tonledb_nosql_kv::structures::*;
+use tonledb_nosql_kv::keys_with_prefix;
+use tonledb_storage::InMemoryStore;
+
+#[test]
+fn test_list_push_pop_and_range() {
+    let store = InMemoryStore::new(100);
+    assert_eq!(rpush(&store, b"q", b"b".to_vec()).unwrap(), 1);
+    assert_eq!(rpush(&store, b"q", b"c".to_vec()).unwrap(), 2);
+    assert_eq!(lpush(&store, b"q", b"a".to_vec()).unwrap(), 3);
+    assert_eq!(llen(&store, b"q").unwrap(), 3);
+    assert_eq!(lrange(&store, b"q", 0, -1).unwrap(), [b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]);
+    assert_eq!(lrange(&store, b"q", -2, 10).unwrap(), [b"b".to_vec(), b"c".to_vec()]);
+    assert!(lrange(&store, b"q", 2, 1).unwrap().is_empty());
+
+    assert_eq!(lpop(&store, b"q").unwrap(), Some(b"a".to_vec()));
+    assert_eq!(rpop(&store, b"q").unwrap(), Some(b"c".to_vec()));
+    assert_eq!(rpop(&store, b"q").unwrap(), Some(b"b".to_vec()));
+    assert_eq!(rpop(&store, b"q").unwrap(), None);
+    assert_eq!(llen(&store, b"q").unwrap(), 0);
+    assert!(keys_with_prefix(&store, b"").unwrap().is_empty());
+}
+
+#[test]
+fn test_sets_and_hashes() {
+    let store = InMemoryStore::new(100);
+    assert!(sadd(&store, b"tags", b"red").unwrap());
+    assert!(sadd(&store, b"tags", b"blue").unwrap());
+    assert!(!sadd(&store, b"tags", b"red").unwrap());
+    assert!(sismember(&store, b"tags", b"blue").unwrap());
+    assert_eq!(smembers(&store, b"tags").unwrap(), [b"blue".to_vec(), b"red".to_vec()]);
+    assert!(srem(&store, b"tags", b"red").unwrap());
+    assert!(!srem(&store, b"tags", b"red").unwrap());
+    assert_eq!(scard(&store, b"tags").unwrap(), 1);
+    // A name that is a prefix of another doesn't see its members
+    sadd(&store, b"tagsx", b"green").unwrap();
+    assert_eq!(scard(&store, b"tags").unwrap(), 1);
+
+    assert!(hset(&store, b"user:1", b"name", b"ann".to_vec()).unwrap());
+    assert!(!hset(&store, b"user:1", b"name", b"anne".to_vec()).unwrap());
+    hset(&store, b"user:1", b"age", b"30".to_vec()).unwrap();
+    assert_eq!(hget(&store, b"user:1", b"name").unwrap(), Some(b"anne".to_vec()));
+    assert_eq!(hgetall(&store, b"user:1").unwrap(), [(b"age".to_vec(), b"30".to_vec()), (b"name".to_vec(), b"anne".to_vec())]);
+    assert!(hdel(&store, b"user:1", b"age").unwrap());
+    assert_eq!(hlen(&store, b"user:1").unwrap(), 1);
+    // Types have separate namespaces
+    assert!(smembers(&store, b"user:1").unwrap().is_empty());
+}
@@ -0,0 +1,48 @@
+//! Tests for expiring KV keys
+
+use std::sync::Arc;
+use std::time::Duration;
+use tonledb_core::Storage;
+use tonledb_nosql_kv::{exists, get, keys_with_prefix, put, put_with_ttl};
+use tonledb_storage::InMemoryStore;
+use tonledb_storage::ttl::TtlSweeper;
+
+#[test]
+fn test_expired_keys_vanish_on_read() {
+    let store = InMemoryStore::new(100);
+    put_with_ttl(&store, b"cache/a".to_vec(), b"1".to_vec(), Duration::from_millis(20)).unwrap();
+    put_with_ttl(&store, b"cache/b".to_vec(), b"2".to_vec(), Duration::from_secs(60)).unwrap();
+    put(&store, b"cache/c".to_vec(), b"3".to_vec()).unwrap();
+    assert_eq!(get(&store, b"cache/a").unwrap(), Some(b"1".to_vec()));
+
+    std::thread::sleep(Duration::from_millis(40));
+    assert_eq!(get(&store, b"cache/a").unwrap(), None);
+    assert!(!exists(&store, b"cache/a").unwrap());
+    assert_eq!(keys_with_prefix(&store, b"cache/").unwrap(), vec![b"cache/b".to_vec(), b"cache/c".to_vec()]);
+}
+
+#[test]
+fn test_background_sweep_reclaims_expired_keys() {
+    let store = Arc::new(InMemoryStore::new(100));
+    put_with_ttl(&*store, b"k".to_vec(), vec![0; 1024], Duration::from_millis(10)).unwrap();
+    let before = store.memory_usage();
+    let _sweeper = TtlSweeper::spawn(store.clone(), Duration::from_millis(10));
+    std::thread::sleep(Duration::from_millis(80));
+    assert!(store.memory_usage() < before);
+    assert_eq!(store.sweep_expired().unwrap(), 0);
+}
+
+#[test]
+fn test_stats_count_keys_bytes_and_expiring_keys() {
+    let store = InMemoryStore::new(100);
+    put(&store, b"a".to_vec(), vec![0; 10]).unwrap();
+    put_with_ttl(&store, b"b".to_vec(), vec![0; 5], Duration::from_secs(60)).unwrap();
+    put_with_ttl(&store, b"c".to_vec(), vec![0; 7], Duration::from_millis(10)).unwrap();
+    let stats = tonledb_nosql_kv::stats(&store).unwrap();
+    assert_eq!((stats.keys, stats.value_bytes, stats.ttl_keys), (3, 22, 2));
+
+    std::thread::sleep(Duration::from_millis(30));
+    let stats = tonledb_nosql_kv::stats(&store).unwrap();
+    assert_eq!((stats.keys, stats.value_bytes, stats.ttl_keys), (2, 15, 1));
+    assert!(store.expires_at(&tonledb_core::Space("kv".into()), b"a").unwrap().is_none());
+}
@@ -0,0 +1,49 @@
+//! Tests for multi-key KV transactions
+
+use std::sync::Arc;
+use tonledb_core::transaction::TransactionManager;
+use tonledb_core::DbError;
+use tonledb_nosql_kv::{get, put, txn};
+use tonledb_storage::InMemoryStore;
+
+#[test]
+fn test_commit_applies_every_write() {
+    let store = InMemoryStore::new(100);
+    put(&store, b"acct/a".to_vec(), b"10".to_vec()).unwrap();
+    put(&store, b"acct/old".to_vec(), b"x".to_vec()).unwrap();
+
+    let t = txn::begin(&store).unwrap();
+    assert_eq!(t.get(b"acct/a").unwrap(), Some(b"10".to_vec()));
+    t.put(b"acct/a".to_vec(), b"5".to_vec()).unwrap();
+    t.put(b"acct/b".to_vec(), b"5".to_vec()).unwrap();
+    t.del(b"acct/old").unwrap();
+    // Own writes are visible, other readers see nothing yet
+    assert_eq!(t.get(b"acct/b").unwrap(), Some(b"5".to_vec()));
+    assert_eq!(get(&store, b"acct/b").unwrap(), None);
+    t.commit().unwrap();
+
+    assert_eq!(get(&store, b"acct/a").unwrap(), Some(b"5".to_vec()));
+    assert_eq!(get(&store, b"acct/b").unwrap(), Some(b"5".to_vec()));
+    assert_eq!(get(&store, b"acct/old").unwrap(), None);
+}
+
+#[test]
+fn test_conflicting_commit_and_dropped_txn_write_nothing() {
+    let store = InMemoryStore::new(100);
+    let manager = Arc::new(TransactionManager::new());
+    let first = txn::begin_with(&store, manager.clone()).unwrap();
+    let second = txn::begin_with(&store, manager.clone()).unwrap();
+    first.put(b"k".to_vec(), b"1".to_vec()).unwrap();
+    second.put(b"k".to_vec(), b"2".to_vec()).unwrap();
+    second.put(b"other".to_vec(), b"2".to_vec()).unwrap();
+    first.commit().unwrap();
+    assert!(matches!(second.commit(), Err(DbError::Conflict(_))));
+    assert_eq!(get(&store, b"k").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(get(&store, b"other").unwrap(), None);
+
+    {
+        let t = txn::begin_with(&store, manager.clone()).unwrap();
+        t.put(b"dropped".to_vec(), b"x".to_vec()).unwrap();
+    }
+    assert_eq!(get(&store, b"dropped").unwrap(), None);
+}
@@ -0,0 +1,77 @@
+//! Tests for KV prefix watches
+
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::time::Duration;
+use futures_core::Stream;
+use tonledb_core::event_sourcing::EVENT_MANAGER;
+use tonledb_nosql_kv::watch::{self, KvEventKind};
+use tonledb_nosql_kv::{del, put, set_if_absent};
+use tonledb_storage::InMemoryStore;
+
+#[test]
+fn test_watch_sees_puts_and_deletes_under_its_prefix() {
+    let store = InMemoryStore::new(100);
+    let w = watch::watch(b"cfg/app/").unwrap();
+    put(&store, b"cfg/app/port".to_vec(), b"80".to_vec()).unwrap();
+    put(&store, b"cfg/other/port".to_vec(), b"81".to_vec()).unwrap();
+    set_if_absent(&store, b"cfg/app/host".to_vec(), b"a".to_vec()).unwrap();
+    set_if_absent(&store, b"cfg/app/host".to_vec(), b"b".to_vec()).unwrap();
+    del(&store, b"cfg/app/port").unwrap();
+
+    let e = w.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!((e.kind, e.key.as_slice(), e.value.as_deref()), (KvEventKind::Put, &b"cfg/app/port"[..], Some(&b"80"[..])));
+    let e = w.try_recv().unwrap();
+    assert_eq!((e.kind, e.value.as_deref()), (KvEventKind::Put, Some(&b"a"[..])));
+    let e = w.try_recv().unwrap();
+    assert_eq!((e.kind, e.key.as_slice(), e.value), (KvEventKind::Delete, &b"cfg/app/port"[..], None));
+    assert!(w.try_recv().is_none());
+    assert!(w.recv_timeout(Duration::from_millis(10)).is_none());
+}
+
+struct CountWakes(AtomicUsize);
+
+impl Wake for CountWakes {
+    fn wake(self: Arc<Self>) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn test_stream_wakes_on_write_and_drop_unregisters() {
+    let store = InMemoryStore::new(100);
+    let mut w = watch::watch(b"stream/").unwrap();
+    let wakes = Arc::new(CountWakes(AtomicUsize::new(0)));
+    let waker = Waker::from(wakes.clone());
+    let mut cx = Context::from_waker(&waker);
+
+    assert!(Pin::new(&mut w).poll_next(&mut cx).is_pending());
+    put(&store, b"stream/k".to_vec(), b"v".to_vec()).unwrap();
+    assert_eq!(wakes.0.load(Ordering::SeqCst), 1);
+    match Pin::new(&mut w).poll_next(&mut cx) {
+        Poll::Ready(Some(e)) => assert_eq!(e.key, b"stream/k"),
+        other => panic!("expected an event, got {:?}", other),
+    }
+
+    let id = w.id().to_string();
+    assert!(EVENT_MANAGER.list_feeds().contains(&id));
+    drop(w);
+    assert!(!EVENT_MANAGER.list_feeds().contains(&id));
+}
+
+#[test]
+fn test_watcher_falling_behind_is_disconnected() {
+    let store = InMemoryStore::new(100);
+    let mut w = watch::watch_with_capacity(b"slow/", 2).unwrap();
+    for i in 0..3 {
+        put(&store, format!("slow/{}", i).into_bytes(), b"v".to_vec()).unwrap();
+    }
+    assert!(w.is_disconnected());
+    assert!(w.try_recv().is_none());
+    put(&store, b"slow/3".to_vec(), b"v".to_vec()).unwrap();
+    assert!(w.recv_timeout(Duration::from_millis(10)).is_none());
+    let mut cx = Context::from_waker(Waker::noop());
+    assert!(matches!(Pin::new(&mut w).poll_next(&mut cx), Poll::Ready(None)));
+}
@@ -0,0 +1,63 @@
+//! Rows loaded into a table in bulk, e.g. by a Postgres `COPY ... FROM STDIN`:
+//! written under their primary key (or a generated key) with the table's indexes
+//! kept up to date, `BULK_BATCH_ROWS` rows at a time in one
+//! `Storage::commit_writes`. A row with a NULL in a NOT NULL column, or with a
+//! primary key already taken, stops the load (see `RowWriter::stage`); a load that
+//! stops part way keeps the batches committed before it.
+
+use serde_json::Value as Json;
+use tonledb_core::transaction::{next_timestamp, WriteSet};
+use tonledb_core::{Db, Result, TableSchema};
+use crate::rows::{self, RowWriter};
+
+/// Rows committed together by a `BulkInsert`
+pub const BULK_BATCH_ROWS: usize = 8192;
+
+/// Rows being loaded into one table
+pub struct BulkInsert<'a> {
+    db: &'a Db,
+    table: TableSchema,
+    indexed: Vec<String>,
+    writer: RowWriter<'a>,
+    /// Rows (and index entries) of the batch not yet committed
+    writes: WriteSet,
+    staged: usize,
+    written: u64,
+}
+
+impl<'a> BulkInsert<'a> {
+    pub fn new(db: &'a Db, table: TableSchema) -> Self {
+        let indexed = rows::indexed_columns(db, &table.name);
+        Self { db, table, indexed, writer: RowWriter::new(db), writes: WriteSet::new(), staged: 0, written: 0 }
+    }
+
+    pub fn table(&self) -> &TableSchema {
+        &self.table
+    }
+
+    /// Add `row`, committing the batch once it is full
+    pub fn push(&mut self, row: &Json) -> Result<()> {
+        self.writer.stage(&self.table, &self.indexed, row, &mut self.writes)?;
+        self.staged += 1;
+        if self.staged == BULK_BATCH_ROWS {
+            self.commit()?;
+        }
+        Ok(())
+    }
+
+    /// Commit the last batch; returns how many rows were written in all
+    pub fn finish(mut self) -> Result<u64> {
+        self.commit()?;
+        Ok(self.written)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        if self.staged > 0 {
+            self.db.storage.commit_writes(&self.writes, next_timestamp())?;
+            self.written += self.staged as u64;
+        }
+        self.writes.clear();
+        self.staged = 0;
+        Ok(())
+    }
+}
@@ -0,0 +1,32 @@
+//! Tables for tests. The engine runs queries only, so a test adds its table to the
+//! catalog and writes the rows the way a restore does.
+
+use serde_json::Value as Json;
+use tonledb_core::{Column, ColumnConstraint, DataType, Db, DbError, Result, TableSchema};
+use crate::rows::{self, RowWriter};
+
+/// Add table `name` of `columns` to the catalog, keyed by column `pk` (rows of a
+/// table without one get generated keys)
+pub fn create_table(db: &Db, name: &str, columns: &[(&str, DataType)], pk: Option<&str>) {
+    let columns = columns
+        .iter()
+        .map(|(column, data_type)| Column {
+            name: column.to_string(),
+            data_type: data_type.clone(),
+            constraints: if pk == Some(*column) { vec![ColumnConstraint::PrimaryKey] } else { vec![] },
+        })
+        .collect();
+    let schema = TableSchema { name: name.into(), columns, pk: pk.map(String::from), constraints: vec![] };
+    db.catalog.write().tables.insert(name.into(), schema);
+}
+
+/// Write `rows` into table `name` and its indexes
+pub fn insert_rows(db: &Db, name: &str, rows: &[Json]) -> Result<()> {
+    let table = db.catalog.read().tables.get(name).cloned().ok_or_else(|| DbError::NotFound(format!("Table {} not found", name)))?;
+    let indexed = rows::indexed_columns(db, name);
+    let mut writer = RowWriter::new(db);
+    for row in rows {
+        writer.insert(&table, &indexed, row)?;
+    }
+    Ok(())
+}
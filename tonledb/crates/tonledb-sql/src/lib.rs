@@ -1,15 +1,142 @@
+use std::collections::HashSet;
 use sqlparser::{dialect::GenericDialect, parser::Parser};
-use tonledb_core::{Db, DbError, Result, Space};
+use tonledb_core::{deadline, Column, DataType, Db, DbError, Result, Space};
+use tonledb_core::schema_inference::{self, InferredSchema};
+use tonledb_core::numbers::{self, ColumnModes, NumberMode};
+use tonledb_core::stats::{self, ColumnStats, MISESTIMATE_FACTOR};
+
+pub mod bulk;
+pub mod fixture;
+pub mod rows;
 
 const TBL_PREFIX: &str = "tbl/";
+/// The index is planned only when statistics expect it to return at most this share of the table
+const INDEX_MAX_FRACTION: f64 = 0.3;
+/// Rows a full scan reads before checking whether the index would have been the better plan
+const PROBE_ROWS: u64 = 256;
 
 pub fn execute_sql(db: &Db, sql: &str) -> Result<serde_json::Value> {
-    let stmts = Parser::parse_sql(&GenericDialect, sql).map_err(|e| DbError::Invalid(e.to_string()))?;
+    execute_statement(db, &parse_statement(sql)?)
+}
+
+/// Parse `sql`, which must hold a single statement
+pub fn parse_statement(sql: &str) -> Result<sqlparser::ast::Statement> {
+    let mut stmts = Parser::parse_sql(&GenericDialect, sql).map_err(|e| DbError::Invalid(e.to_string()))?;
     if stmts.len() != 1 {
         return Err(DbError::Invalid("only single statement supported".into()));
     }
-    
-    match &stmts[0] {
+    Ok(stmts.remove(0))
+}
+
+/// Columns of the rows `stmt` returns, in order, worked out without running it: for
+/// a SELECT from the catalog (or a collection's inferred schema), where projected
+/// names the table doesn't have are `Text`; for EXPLAIN and ANALYZE the fields of
+/// their answers. `None` for statements that return no rows.
+pub fn result_columns(db: &Db, stmt: &sqlparser::ast::Statement) -> Result<Option<Vec<Column>>> {
+    let fixed = |columns: &[(&str, DataType)]| {
+        Ok(Some(columns.iter().map(|(name, data_type)| Column { name: name.to_string(), data_type: data_type.clone(), constraints: vec![] }).collect()))
+    };
+    let q = match stmt {
+        sqlparser::ast::Statement::Query(q) => q,
+        sqlparser::ast::Statement::Explain { .. } => return fixed(&[("plan", DataType::Json), ("estimate", DataType::Json), ("cache", DataType::Json)]),
+        sqlparser::ast::Statement::Analyze { .. } => return fixed(&[
+            ("table", DataType::Text), ("column", DataType::Text), ("rows", DataType::Integer), ("distinct", DataType::Integer),
+            ("observed_eq_rows", DataType::Float), ("misestimates", DataType::Integer), ("updated_ms", DataType::Integer),
+        ]),
+        _ => return Ok(None),
+    };
+    let sqlparser::ast::SetExpr::Select(sel) = &*q.body else { return Ok(None) };
+    if sel.from.len() != 1 {
+        return Err(DbError::Invalid("SELECT from exactly one table".into()));
+    }
+    let tname = sel.from[0].relation.to_string();
+    let table: Vec<(String, DataType)> = match row_source(db, &table_plan(db, tname.clone()))?.1 {
+        Some(schema) => schema.fields.iter().map(|f| (f.name.clone(), f.data_type())).collect(),
+        None => db.catalog.read().tables.get(&tname)
+            .map(|t| t.columns.iter().map(|c| (c.name.clone(), c.data_type.clone())).collect())
+            .unwrap_or_default(),
+    };
+    let column = |name: &str, source: &str| Column {
+        name: name.to_string(),
+        data_type: table.iter().find(|(n, _)| n == source).map_or(DataType::Text, |(_, t)| t.clone()),
+        constraints: vec![],
+    };
+    if let [sqlparser::ast::SelectItem::Wildcard(_)] = sel.projection.as_slice() {
+        return Ok(Some(table.iter().map(|(n, _)| column(n, n)).collect()));
+    }
+    sel.projection.iter().map(|it| match it {
+        sqlparser::ast::SelectItem::UnnamedExpr(sqlparser::ast::Expr::Identifier(id)) => Ok(column(&id.value, &id.value)),
+        sqlparser::ast::SelectItem::ExprWithAlias { expr: sqlparser::ast::Expr::Identifier(id), alias } => Ok(column(&alias.value, &id.value)),
+        _ => Err(DbError::Invalid("projection supports identifiers only".into())),
+    }).collect::<Result<Vec<_>>>().map(Some)
+}
+
+/// What running a statement takes from the catalog: for a SELECT, whether it reads
+/// a SQL table, and that table's `Decimal` and indexed columns. A plan holds while
+/// the catalog version it was made at does (see `Db::catalog_version`), so a caller
+/// that keeps parsed statements can keep their plans beside them and skip the
+/// catalog on later runs. The access path is still chosen on each run, from the
+/// statistics at the time.
+#[derive(Debug, Clone)]
+pub struct Plan {
+    catalog_version: u64,
+    table: Option<TablePlan>,
+}
+
+impl Plan {
+    /// The catalog version the plan was made at
+    pub fn catalog_version(&self) -> u64 {
+        self.catalog_version
+    }
+}
+
+/// The catalog's part in reading one table
+#[derive(Debug, Clone)]
+struct TablePlan {
+    name: String,
+    /// A SQL table; otherwise it may be a document collection
+    is_table: bool,
+    decimal_columns: Vec<String>,
+    indexed_columns: Vec<String>,
+}
+
+/// The plan of `stmt` at the catalog as it is now. Placeholders in `stmt` don't
+/// change the plan, so one made for a prepared statement serves it bound.
+pub fn plan_statement(db: &Db, stmt: &sqlparser::ast::Statement) -> Plan {
+    let catalog_version = db.catalog_version();
+    let table = match stmt {
+        sqlparser::ast::Statement::Query(q) => match &*q.body {
+            sqlparser::ast::SetExpr::Select(sel) if sel.from.len() == 1 => Some(table_plan(db, sel.from[0].relation.to_string())),
+            _ => None,
+        },
+        _ => None,
+    };
+    Plan { catalog_version, table }
+}
+
+fn table_plan(db: &Db, name: String) -> TablePlan {
+    let catalog = db.catalog.read();
+    let table = catalog.tables.get(&name);
+    TablePlan {
+        is_table: table.is_some(),
+        decimal_columns: table
+            .map(|t| t.columns.iter().filter(|c| c.data_type == DataType::Decimal).map(|c| c.name.clone()).collect())
+            .unwrap_or_default(),
+        indexed_columns: catalog.indexes.values().filter(|i| i.table == name).map(|i| i.column.clone()).collect(),
+        name,
+    }
+}
+
+/// Run a statement already parsed, as `execute_sql` runs its text. The access path
+/// is planned on each run, from the statistics at the time.
+pub fn execute_statement(db: &Db, stmt: &sqlparser::ast::Statement) -> Result<serde_json::Value> {
+    execute_planned(db, stmt, &plan_statement(db, stmt))
+}
+
+/// Run `stmt` with `plan`, which `plan_statement` made for it; a plan from an older
+/// catalog version is made again
+pub fn execute_planned(db: &Db, stmt: &sqlparser::ast::Statement, plan: &Plan) -> Result<serde_json::Value> {
+    match stmt {
         sqlparser::ast::Statement::Query(q) => {
             if let sqlparser::ast::SetExpr::Select(sel) = &*q.body {
                 if sel.from.len() != 1 {
@@ -22,37 +149,14 @@ pub fn execute_sql(db: &Db, sql: &str) -> Result<serde_json::Value> {
                 let order_by = &q.order_by;
                 let limit = &q.limit;
                 
-                let mut results = vec![];
-                
-                // Check if we can use an index for the query
-                if let Some(index_scan) = try_index_scan(db, tname, selection)? {
-                    // Use index scan
-                    for row_key in index_scan.row_keys {
-                        if let Some(row_data) = db.storage.get(&Space("data".into()), &row_key)? {
-                            let obj: serde_json::Value = serde_json::from_slice(&row_data)
-                                .map_err(|e| DbError::Storage(e.to_string()))?;
-                            if let Some(sel) = selection {
-                                if !eval_simple_where(&obj, &sel)? {
-                                    continue;
-                                }
-                            }
-                            results.push(obj);
-                        }
-                    }
-                } else {
-                    // Fallback: full scan with selection
-                    let prefix = format!("{}{}{}", TBL_PREFIX, tname, "/").into_bytes();
-                    let iter = db.storage.scan_prefix(&Space("data".into()), &prefix)?;
-                    for (_, v) in iter { 
-                        let obj: serde_json::Value = serde_json::from_slice(&v).map_err(|e| DbError::Storage(e.to_string()))?; 
-                        if let Some(sel) = selection { 
-                            if !eval_simple_where(&obj, &sel)? { 
-                                continue; 
-                            } 
-                        } 
-                        results.push(obj);
-                    }
-                }
+                let current = plan.table.as_ref().filter(|t| t.name == *tname && plan.catalog_version == db.catalog_version());
+                let table = match current {
+                    Some(table) => std::borrow::Cow::Borrowed(table),
+                    None => std::borrow::Cow::Owned(table_plan(db, tname.clone())),
+                };
+                let (prefix, doc_schema) = row_source(db, &table)?;
+                let modes = number_modes(db, &table)?;
+                let mut results = select_rows(db, &table, &prefix, selection, &modes)?;
                 
                 // Apply ORDER BY if specified
                 if !order_by.is_empty() {
@@ -71,6 +175,12 @@ pub fn execute_sql(db: &Db, sql: &str) -> Result<serde_json::Value> {
                 // Apply projection to all results
                 let mut out = vec![];
                 for mut obj in results {
+                    // Collections are schemaless: give every row the inferred columns
+                    if let (Some(schema), Some(map)) = (&doc_schema, obj.as_object_mut()) {
+                        for f in &schema.fields {
+                            map.entry(f.name.clone()).or_insert(serde_json::Value::Null);
+                        }
+                    }
                     out.push(project_simple(&projection, &mut obj)?);
                 }
                 
@@ -79,18 +189,89 @@ pub fn execute_sql(db: &Db, sql: &str) -> Result<serde_json::Value> {
                 Err(DbError::Invalid("only SELECT supported".into()))
             }
         }
+        sqlparser::ast::Statement::Explain { statement, .. } => explain(db, statement),
+        sqlparser::ast::Statement::Analyze { table_name, columns, .. } => analyze(db, &table_name.to_string(), columns),
         _ => Err(DbError::Invalid("only SELECT supported".into())),
     }
 }
 
-fn eval_simple_where(row: &serde_json::Value, expr: &sqlparser::ast::Expr) -> Result<bool> {
+/// EXPLAIN for a single-table SELECT: the access path and how many row reads the LRU
+/// cache is expected to serve, judged from what is resident in the cache right now.
+fn explain(db: &Db, stmt: &sqlparser::ast::Statement) -> Result<serde_json::Value> {
+    let sel = match stmt {
+        sqlparser::ast::Statement::Query(q) => match &*q.body {
+            sqlparser::ast::SetExpr::Select(sel) => sel,
+            _ => return Err(DbError::Invalid("EXPLAIN supports SELECT only".into())),
+        },
+        _ => return Err(DbError::Invalid("EXPLAIN supports SELECT only".into())),
+    };
+    if sel.from.len() != 1 {
+        return Err(DbError::Invalid("SELECT from exactly one table".into()));
+    }
+    let tname = sel.from[0].relation.to_string();
+    let table = table_plan(db, tname.clone());
+    let (prefix, doc_schema) = row_source(db, &table)?;
+    let space = Space("data".into());
+    let plan = plan_access(db, &table, &sel.selection)?;
+    let (access, rows, cached) = match try_index_scan(db, &table, &sel.selection)? {
+        Some(scan) => {
+            let cached = scan.row_keys.iter().filter(|k| db.storage.is_cached(&space, k)).count();
+            ("index_scan", scan.row_keys.len(), cached)
+        }
+        // Prefix scans read rows straight from the map and never go through the LRU
+        None => ("full_scan", db.storage.scan_prefix(&space, &prefix)?.count(), 0),
+    };
+    let latency = match (rows, cached) {
+        (0, _) => "empty",
+        (r, c) if c == r => "warm",
+        (_, 0) => "cold",
+        _ => "mixed",
+    };
+    let cache = db.storage.cache_stats().map(|s| serde_json::json!({
+        "capacity": s.capacity, "entries": s.entries, "hits": s.hits, "misses": s.misses, "hit_ratio": s.hit_ratio(),
+    }));
+    Ok(serde_json::json!({
+        "plan": {
+            "access": access,
+            "source": if doc_schema.is_some() { "collection" } else { "table" },
+            "name": tname,
+            "filter": sel.selection.as_ref().map(|e| e.to_string()),
+            "index": plan.as_ref().map(|p| &p.eq.column),
+            "stats": plan.as_ref().and_then(|p| p.stats.as_ref()).map(|s| serde_json::json!({
+                "rows": s.rows, "distinct": s.distinct, "estimate_eq": s.estimate_eq(), "misestimates": s.misestimates,
+            })),
+        },
+        "estimate": { "rows": rows, "cache_hits": cached, "storage_reads": rows - cached, "latency": latency },
+        "cache": cache,
+    }))
+}
+
+/// Key prefix holding the rows of `table`: a SQL table, or else a document collection
+/// registered in the catalog, which also gets its inferred schema.
+fn row_source(db: &Db, table: &TablePlan) -> Result<(Vec<u8>, Option<InferredSchema>)> {
+    let name = &table.name;
+    let is_collection = !table.is_table
+        && db.storage.get(&Space("catalog".into()), format!("col/{}", name).as_bytes())?.is_some();
+    if is_collection {
+        let schema = schema_inference::load_or_refresh(&*db.storage, name)?;
+        return Ok((format!("doc/{}/", name).into_bytes(), Some(schema)));
+    }
+    Ok((format!("{}{}{}", TBL_PREFIX, name, "/").into_bytes(), None))
+}
+
+fn eval_simple_where(row: &serde_json::Value, expr: &sqlparser::ast::Expr, modes: &ColumnModes) -> Result<bool> {
     match expr { 
         sqlparser::ast::Expr::BinaryOp { left, op, right } => {
-            let (l, r) = (value_of(row, left)?, value_of(row, right)?);
+            // A literal compared with a column is read in that column's number mode
+            let mode = match (&**left, &**right) {
+                (sqlparser::ast::Expr::Identifier(id), _) | (_, sqlparser::ast::Expr::Identifier(id)) => numbers::mode_for(modes, &id.value),
+                _ => numbers::number_mode(),
+            };
+            let (l, r) = (value_of(row, left, mode)?, value_of(row, right, mode)?);
             
             match op {
-                sqlparser::ast::BinaryOperator::Eq => Ok(l == r),
-                sqlparser::ast::BinaryOperator::NotEq => Ok(l != r),
+                sqlparser::ast::BinaryOperator::Eq => Ok(numbers::values_equal(&l, &r)),
+                sqlparser::ast::BinaryOperator::NotEq => Ok(!numbers::values_equal(&l, &r)),
                 sqlparser::ast::BinaryOperator::Gt => Ok(compare_values(&l, &r) == std::cmp::Ordering::Greater),
                 sqlparser::ast::BinaryOperator::Lt => Ok(compare_values(&l, &r) == std::cmp::Ordering::Less),
                 sqlparser::ast::BinaryOperator::GtEq => Ok(compare_values(&l, &r) != std::cmp::Ordering::Less),
@@ -101,25 +282,25 @@ fn eval_simple_where(row: &serde_json::Value, expr: &sqlparser::ast::Expr) -> Re
         sqlparser::ast::Expr::UnaryOp { op, expr } => {
             match op {
                 sqlparser::ast::UnaryOperator::Not => {
-                    let val = eval_simple_where(row, expr)?;
+                    let val = eval_simple_where(row, expr, modes)?;
                     Ok(!val)
                 }
                 _ => Err(DbError::Invalid(format!("Unsupported unary operator: {:?}", op))),
             }
         }
         sqlparser::ast::Expr::Nested(expr) => {
-            eval_simple_where(row, expr)
+            eval_simple_where(row, expr, modes)
         }
         _ => Err(DbError::Invalid("Unsupported expression type".into())),
     }
 }
 
-fn value_of(row: &serde_json::Value, expr: &sqlparser::ast::Expr) -> Result<serde_json::Value> {
+fn value_of(row: &serde_json::Value, expr: &sqlparser::ast::Expr, mode: NumberMode) -> Result<serde_json::Value> {
     match expr { 
         sqlparser::ast::Expr::Identifier(sqlparser::ast::Ident { value, .. }) => 
             Ok(row.get(value).cloned().unwrap_or(serde_json::Value::Null)), 
         sqlparser::ast::Expr::Value(v) => 
-            Ok(lit_sql_to_json(v.clone())), 
+            Ok(lit_sql_to_json(v.clone(), mode)), 
         _ => Err(DbError::Invalid("unsupported expression".into())), 
     }
 }
@@ -157,10 +338,10 @@ fn project_simple(proj: &Vec<sqlparser::ast::SelectItem>, row: &mut serde_json::
     Ok(serde_json::Value::Object(out))
 }
 
-fn lit_sql_to_json(v: sqlparser::ast::Value) -> serde_json::Value { 
+fn lit_sql_to_json(v: sqlparser::ast::Value, mode: NumberMode) -> serde_json::Value { 
     match v {
         sqlparser::ast::Value::Number(n, _) => 
-            serde_json::json!(n.parse::<f64>().unwrap()),
+            numbers::number_literal(&n, mode).unwrap_or(serde_json::Value::Null),
         sqlparser::ast::Value::SingleQuotedString(s) | sqlparser::ast::Value::DoubleQuotedString(s) => 
             serde_json::json!(s),
         sqlparser::ast::Value::Boolean(b) => 
@@ -176,11 +357,164 @@ struct IndexScan {
     row_keys: Vec<Vec<u8>>,
 }
 
+/// `column = literal` where `column` has a secondary index
+struct IndexedEq {
+    column: String,
+    value: String,
+}
+
+/// Planner's choice for a query that could use an index
+struct AccessPlan {
+    eq: IndexedEq,
+    stats: Option<ColumnStats>,
+    use_index: bool,
+}
+
+impl AccessPlan {
+    /// The index returned far more candidates than estimated, too many to fetch one by one
+    fn index_overshoots(&self, candidates: usize) -> bool {
+        self.stats.as_ref().is_some_and(|s| {
+            let n = candidates as f64;
+            n > s.estimate_eq() * MISESTIMATE_FACTOR && n > s.rows as f64 * INDEX_MAX_FRACTION
+        })
+    }
+
+    /// A full scan projects to far fewer matches than estimated, few enough for the index
+    fn scan_undershoots(&self, matched: usize, scanned: u64) -> bool {
+        self.stats.as_ref().is_some_and(|s| {
+            let projected = matched as f64 / scanned as f64 * s.rows as f64;
+            projected * MISESTIMATE_FACTOR < s.estimate_eq() && projected <= s.rows as f64 * INDEX_MAX_FRACTION
+        })
+    }
+}
+
+/// Pick the access path for an equality on an indexed column. `None` means there is
+/// no index to consider and the query is a plain full scan.
+fn plan_access(db: &Db, table: &TablePlan, selection: &Option<sqlparser::ast::Expr>) -> Result<Option<AccessPlan>> {
+    use sqlparser::ast::{BinaryOperator, Expr};
+    let Some(Expr::BinaryOp { left, op: BinaryOperator::Eq, right }) = selection else { return Ok(None) };
+    let (column, lit) = match (&**left, &**right) {
+        (Expr::Identifier(id), lit @ Expr::Value(_)) | (lit @ Expr::Value(_), Expr::Identifier(id)) => (id.value.clone(), lit),
+        _ => return Ok(None),
+    };
+    let Ok(value) = value_of_placeholder(lit) else { return Ok(None) };
+    if !table.indexed_columns.contains(&column) {
+        return Ok(None);
+    }
+    let stats = stats::load(&*db.storage, &table.name, &column)?;
+    // Without statistics the index is assumed selective
+    let use_index = stats.as_ref().is_none_or(|s| s.rows == 0 || s.estimate_eq() <= s.rows as f64 * INDEX_MAX_FRACTION);
+    Ok(Some(AccessPlan { eq: IndexedEq { column, value }, stats, use_index }))
+}
+
 /// Try to optimize the query using an index
-fn try_index_scan(_db: &Db, _table_name: &str, _selection: &Option<sqlparser::ast::Expr>) -> Result<Option<IndexScan>> {
-    // For now, we'll just return None to indicate no index optimization
-    // In a future implementation, we could add index support here
-    Ok(None)
+fn try_index_scan(db: &Db, table: &TablePlan, selection: &Option<sqlparser::ast::Expr>) -> Result<Option<IndexScan>> {
+    match plan_access(db, table, selection)? {
+        Some(plan) if plan.use_index => Ok(Some(IndexScan { row_keys: index_rows(db, &table.name, &plan.eq)? })),
+        _ => Ok(None),
+    }
+}
+
+/// Row keys the secondary index `<table>.<column>` holds for the value
+fn index_rows(db: &Db, table_name: &str, eq: &IndexedEq) -> Result<Vec<Vec<u8>>> {
+    let space = Space(format!("index_{}.{}", table_name, eq.column));
+    let prefix = format!("{}#", eq.value).into_bytes();
+    Ok(db.storage.scan_prefix(&space, &prefix)?.map(|(k, _)| k[prefix.len()..].to_vec()).collect())
+}
+
+/// Rows under `prefix` matching `selection`.
+///
+/// When an index could serve the query, the planner's choice is checked against
+/// what the query actually sees and reversed mid-query if the statistics were far
+/// off; the real match count is then fed back into the column statistics.
+fn select_rows(db: &Db, table: &TablePlan, prefix: &[u8], selection: &Option<sqlparser::ast::Expr>, modes: &ColumnModes) -> Result<Vec<serde_json::Value>> {
+    let table_name = table.name.as_str();
+    let Some(plan) = plan_access(db, table, selection)? else {
+        return scan_rows(db, table_name, prefix, selection, modes, None);
+    };
+    let rows = if plan.use_index {
+        let keys = index_rows(db, table_name, &plan.eq)?;
+        if plan.index_overshoots(keys.len()) {
+            // One sequential pass is cheaper than a point read per candidate
+            scan_rows(db, table_name, prefix, selection, modes, None)?
+        } else {
+            let mut rows = vec![];
+            for key in keys {
+                deadline::check()?;
+                rows.extend(fetch_row(db, &key, selection, modes)?);
+            }
+            rows
+        }
+    } else {
+        scan_rows(db, table_name, prefix, selection, modes, Some(&plan))?
+    };
+    stats::record_actual(&*db.storage, table_name, &plan.eq.column, rows.len() as u64)?;
+    Ok(rows)
+}
+
+/// Full scan. With a `plan`, the scan switches to the index after `PROBE_ROWS` rows
+/// if the predicate turns out far more selective than estimated.
+fn scan_rows(db: &Db, table_name: &str, prefix: &[u8], selection: &Option<sqlparser::ast::Expr>, modes: &ColumnModes, plan: Option<&AccessPlan>) -> Result<Vec<serde_json::Value>> {
+    let mut results = vec![];
+    let mut seen = HashSet::new();
+    for (i, (k, v)) in db.storage.scan_prefix(&Space("data".into()), prefix)?.enumerate() {
+        deadline::check()?;
+        let scanned = i as u64 + 1;
+        let obj: serde_json::Value = serde_json::from_slice(&v).map_err(|e| DbError::Storage(e.to_string()))?;
+        if row_matches(&obj, selection, modes)? {
+            results.push(obj);
+            if plan.is_some() {
+                seen.insert(k);
+            }
+        }
+        if let Some(plan) = plan.filter(|p| scanned == PROBE_ROWS && p.scan_undershoots(results.len(), scanned)) {
+            for key in index_rows(db, table_name, &plan.eq)? {
+                if !seen.contains(&key) {
+                    results.extend(fetch_row(db, &key, selection, modes)?);
+                }
+            }
+            return Ok(results);
+        }
+    }
+    Ok(results)
+}
+
+/// Point read of one row, if it still exists and matches `selection`
+fn fetch_row(db: &Db, key: &[u8], selection: &Option<sqlparser::ast::Expr>, modes: &ColumnModes) -> Result<Option<serde_json::Value>> {
+    let Some(row_data) = db.storage.get(&Space("data".into()), key)? else { return Ok(None) };
+    let obj: serde_json::Value = serde_json::from_slice(&row_data).map_err(|e| DbError::Storage(e.to_string()))?;
+    Ok(row_matches(&obj, selection, modes)?.then_some(obj))
+}
+
+fn row_matches(row: &serde_json::Value, selection: &Option<sqlparser::ast::Expr>, modes: &ColumnModes) -> Result<bool> {
+    match selection {
+        Some(sel) => eval_simple_where(row, sel, modes),
+        None => Ok(true),
+    }
+}
+
+/// Number modes of `table`'s columns: registered overrides, then `Decimal` column types
+fn number_modes(db: &Db, table: &TablePlan) -> Result<ColumnModes> {
+    let mut modes = numbers::column_modes(&*db.storage, numbers::Source::Table(&table.name))?;
+    for c in &table.decimal_columns {
+        modes.entry(c.clone()).or_insert(NumberMode::Decimal);
+    }
+    Ok(modes)
+}
+
+/// ANALYZE TABLE: refresh statistics for the listed columns, or for every indexed column
+fn analyze(db: &Db, table_name: &str, columns: &[sqlparser::ast::Ident]) -> Result<serde_json::Value> {
+    let columns: Vec<String> = if columns.is_empty() {
+        db.catalog.read().indexes.values().filter(|i| i.table == table_name).map(|i| i.column.clone()).collect()
+    } else {
+        columns.iter().map(|c| c.value.clone()).collect()
+    };
+    let mut out = vec![];
+    for column in columns {
+        let s = stats::analyze(&*db.storage, table_name, &column)?;
+        out.push(serde_json::to_value(&s).map_err(|e| DbError::Storage(e.to_string()))?);
+    }
+    Ok(serde_json::Value::Array(out))
 }
 
 /// Extract a value from an expression for index lookups
@@ -202,14 +536,7 @@ fn value_of_placeholder(expr: &sqlparser::ast::Expr) -> Result<String> {
 fn compare_values(left: &serde_json::Value, right: &serde_json::Value) -> std::cmp::Ordering {
     match (left, right) {
         (serde_json::Value::Number(a), serde_json::Value::Number(b)) => {
-            // Try to compare as f64 first
-            if let (Some(a_f64), Some(b_f64)) = (a.as_f64(), b.as_f64()) {
-                a_f64.partial_cmp(&b_f64).unwrap_or(std::cmp::Ordering::Equal)
-            } else if let (Some(a_i64), Some(b_i64)) = (a.as_i64(), b.as_i64()) {
-                a_i64.cmp(&b_i64)
-            } else {
-                std::cmp::Ordering::Equal
-            }
+            numbers::cmp_numbers(a, b).unwrap_or(std::cmp::Ordering::Equal)
         }
         (serde_json::Value::String(a), serde_json::Value::String(b)) => a.cmp(b),
         (serde_json::Value::Bool(a), serde_json::Value::Bool(b)) => a.cmp(b),
@@ -0,0 +1,132 @@
+//! Rows written into tables by restores, imports and bulk loads: under their primary
+//! key, or a generated key for tables without one, with the table's indexes kept up
+//! to date. A restore (`RowWriter::insert`) puts a row over the one it replaces; a
+//! bulk load (`RowWriter::stage`) only adds rows, and checks NOT NULL columns and
+//! primary keys.
+
+use std::collections::HashMap;
+use serde_json::Value as Json;
+use tonledb_core::transaction::WriteSet;
+use tonledb_core::{ColumnConstraint, Db, DbError, Result, Space, TableSchema};
+
+pub struct RowWriter<'a> {
+    db: &'a Db,
+    /// Next generated row key of each table without a primary key
+    next_key: HashMap<String, u64>,
+}
+
+impl<'a> RowWriter<'a> {
+    pub fn new(db: &'a Db) -> Self {
+        Self { db, next_key: HashMap::new() }
+    }
+
+    /// Write `row` into `table`, adding it to the indexes on the `indexed` columns
+    pub fn insert(&mut self, table: &TableSchema, indexed: &[String], row: &Json) -> Result<()> {
+        let key = self.row_key(table, row)?;
+        for column in indexed {
+            self.index_row(&table.name, column, &key, row)?;
+        }
+        self.db.storage.put(&data_space(), key, encode(row)?)
+    }
+
+    /// Like `insert`, but add the row and its index entries to `writes`, for
+    /// committing many rows together with `Storage::commit_writes`. The row must
+    /// have a value in every NOT NULL column, and a primary key neither stored nor
+    /// already in `writes`.
+    pub fn stage(&mut self, table: &TableSchema, indexed: &[String], row: &Json, writes: &mut WriteSet) -> Result<()> {
+        for column in &table.columns {
+            let required = table.pk.as_ref() == Some(&column.name)
+                || column.constraints.iter().any(|c| matches!(c, ColumnConstraint::NotNull | ColumnConstraint::PrimaryKey));
+            if required && row.get(&column.name).is_none_or(Json::is_null) {
+                return Err(DbError::NotNullViolation(format!(
+                    "null value in column \"{}\" of relation \"{}\" violates not-null constraint", column.name, table.name
+                )));
+            }
+        }
+        let key = self.row_key(table, row)?;
+        if let Some(pk) = &table.pk {
+            let staged = writes.get(&(data_space(), key.clone())).is_some_and(Option::is_some);
+            if staged || self.db.storage.get(&data_space(), &key)?.is_some() {
+                return Err(DbError::UniqueViolation(format!(
+                    "duplicate key value violates unique constraint \"{}_pkey\": Key ({})=({}) already exists",
+                    table.name, pk, row.get(pk).and_then(index_value).unwrap_or_default()
+                )));
+            }
+        }
+        for column in indexed {
+            if let Some((space, entry)) = index_entry(&table.name, column, &key, row) {
+                writes.insert((space, entry), Some(Vec::new()));
+            }
+        }
+        writes.insert((data_space(), key), Some(encode(row)?));
+        Ok(())
+    }
+
+    /// Key of `row` in `table`: its primary key, or a generated one
+    fn row_key(&mut self, table: &TableSchema, row: &Json) -> Result<Vec<u8>> {
+        match table.pk.as_ref().and_then(|pk| row.get(pk)).and_then(index_value) {
+            Some(pk) => Ok(format!("{}{}", table_prefix(&table.name), pk).into_bytes()),
+            None => self.generated_key(&table.name),
+        }
+    }
+
+    /// Key for the next row of `table`, which has no primary key: one past the
+    /// highest generated key stored, so rows deleted since never get theirs reused
+    fn generated_key(&mut self, table: &str) -> Result<Vec<u8>> {
+        let prefix = table_prefix(table);
+        if !self.next_key.contains_key(table) {
+            // Generated keys are all digits, so the last key of `0..:` is the highest
+            let (start, end) = (format!("{}0", prefix), format!("{}:", prefix));
+            let last = self.db.storage.scan_range(&data_space(), start.as_bytes(), Some(end.as_bytes()), true, 1)?;
+            let highest = last.first()
+                .and_then(|(k, _)| std::str::from_utf8(&k[prefix.len()..]).ok()?.parse::<u64>().ok())
+                .unwrap_or(0);
+            self.next_key.insert(table.to_string(), highest);
+        }
+        let next = self.next_key.get_mut(table).expect("inserted above");
+        *next += 1;
+        Ok(format!("{}{:020}", prefix, next).into_bytes())
+    }
+
+    /// Add `row`, stored at `key`, to the index on `table.column`
+    pub fn index_row(&self, table: &str, column: &str, key: &[u8], row: &Json) -> Result<()> {
+        let Some((space, entry)) = index_entry(table, column, key, row) else { return Ok(()) };
+        self.db.storage.put(&space, entry, Vec::new())
+    }
+}
+
+/// Space and key of the entry for `row`, stored at `key`, in the index on
+/// `table.column`; `None` if the row has no indexable value there
+fn index_entry(table: &str, column: &str, key: &[u8], row: &Json) -> Option<(Space, Vec<u8>)> {
+    let v = row.get(column).and_then(index_value)?;
+    let mut entry = format!("{}#", v).into_bytes();
+    entry.extend_from_slice(key);
+    Some((Space(format!("index_{}.{}", table, column)), entry))
+}
+
+fn encode(row: &Json) -> Result<Vec<u8>> {
+    serde_json::to_vec(row).map_err(|e| DbError::Invalid(e.to_string()))
+}
+
+/// Columns of `table` with a secondary index
+pub fn indexed_columns(db: &Db, table: &str) -> Vec<String> {
+    db.catalog.read().indexes.values().filter(|i| i.table == table).map(|i| i.column.clone()).collect()
+}
+
+pub fn data_space() -> Space {
+    Space("data".into())
+}
+
+/// Prefix of the keys of `table`'s rows in `data_space`
+pub fn table_prefix(table: &str) -> String {
+    format!("{}{}/", crate::TBL_PREFIX, table)
+}
+
+/// How an indexed value is written in index keys, matching lookups by literal
+fn index_value(v: &Json) -> Option<String> {
+    match v {
+        Json::String(s) => Some(s.clone()),
+        Json::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
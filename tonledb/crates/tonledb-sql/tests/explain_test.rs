@@ -0,0 +1,55 @@
+//! Tests for EXPLAIN: the access path and cache estimates of a SELECT
+
+use std::sync::Arc;
+use serde_json::json;
+use tonledb_core::{Column, DataType, Db, IndexType, Space, Storage, TableSchema};
+use tonledb_sql::execute_sql;
+use tonledb_storage::InMemoryStore;
+
+/// `people` with 6 rows over 3 cities, `city` indexed
+fn people_db() -> Db {
+    let db = Db::new(Arc::new(InMemoryStore::new(1000)));
+    let columns = ["id", "city"].map(|name| Column { name: name.into(), data_type: DataType::Text, constraints: vec![] }).to_vec();
+    db.catalog.write().tables.insert("people".into(), TableSchema { name: "people".into(), columns, pk: None, constraints: vec![] });
+    db.create_index("people", "city", IndexType::BTree, false).unwrap();
+    for (id, city) in ["paris", "rome", "oslo", "paris", "rome", "paris"].iter().enumerate() {
+        let key = format!("tbl/people/{}", id);
+        db.storage.put(&Space("data".into()), key.clone().into_bytes(), serde_json::to_vec(&json!({"id": id, "city": city})).unwrap()).unwrap();
+        db.storage.put(&Space("index_people.city".into()), format!("{}#{}", city, key).into_bytes(), vec![]).unwrap();
+    }
+    db
+}
+
+#[test]
+fn test_explain_indexed_equality() {
+    let db = people_db();
+    let plan = execute_sql(&db, "EXPLAIN SELECT id FROM people WHERE city = 'paris'").unwrap();
+    assert_eq!(plan["plan"]["access"], "index_scan");
+    assert_eq!(plan["plan"]["source"], "table");
+    assert_eq!(plan["plan"]["index"], "city");
+    assert_eq!(plan["plan"]["stats"], serde_json::Value::Null);
+    // Rows just written are in the cache
+    assert_eq!(plan["estimate"], json!({"rows": 3, "cache_hits": 3, "storage_reads": 0, "latency": "warm"}));
+    assert!(plan["cache"]["capacity"].as_u64().unwrap() > 0);
+
+    // After ANALYZE the plan shows the statistics it was costed with
+    execute_sql(&db, "ANALYZE TABLE people").unwrap();
+    let plan = execute_sql(&db, "EXPLAIN SELECT id FROM people WHERE city = 'rome'").unwrap();
+    assert_eq!(plan["plan"]["stats"]["rows"], 6);
+    assert_eq!(plan["plan"]["stats"]["distinct"], 3);
+}
+
+#[test]
+fn test_explain_full_scan() {
+    let db = people_db();
+    let plan = execute_sql(&db, "EXPLAIN SELECT * FROM people WHERE id > 2").unwrap();
+    assert_eq!(plan["plan"]["access"], "full_scan");
+    assert_eq!(plan["plan"]["index"], serde_json::Value::Null);
+    assert_eq!(plan["plan"]["filter"], "id > 2");
+    // Scans bypass the cache
+    assert_eq!(plan["estimate"], json!({"rows": 6, "cache_hits": 0, "storage_reads": 6, "latency": "cold"}));
+
+    let empty = execute_sql(&db, "EXPLAIN SELECT * FROM nobody").unwrap();
+    assert_eq!(empty["estimate"]["latency"], "empty");
+    assert!(execute_sql(&db, "EXPLAIN SELECT * FROM people, people").is_err());
+}
@@ -0,0 +1,39 @@
+//! Tests for plans kept beside parsed statements and made again once the catalog
+//! changes
+
+use std::sync::Arc;
+use serde_json::json;
+use tonledb_core::{Column, DataType, Db, IndexType, Space, Storage, TableSchema};
+use tonledb_sql::{execute_planned, parse_statement, plan_statement};
+use tonledb_storage::InMemoryStore;
+
+/// `people` with 4 rows over 2 cities, and entries for an index on `city` that
+/// the catalog doesn't list yet
+fn people_db() -> Db {
+    let db = Db::new(Arc::new(InMemoryStore::new(1000)));
+    let columns = ["id", "city"].map(|name| Column { name: name.into(), data_type: DataType::Text, constraints: vec![] }).to_vec();
+    db.catalog.write().tables.insert("people".into(), TableSchema { name: "people".into(), columns, pk: None, constraints: vec![] });
+    for (id, city) in ["paris", "rome", "paris", "rome"].iter().enumerate() {
+        let key = format!("tbl/people/{}", id);
+        db.storage.put(&Space("data".into()), key.clone().into_bytes(), serde_json::to_vec(&json!({"id": id, "city": city})).unwrap()).unwrap();
+        db.storage.put(&Space("index_people.city".into()), format!("{}#{}", city, key).into_bytes(), vec![]).unwrap();
+    }
+    db
+}
+
+#[test]
+fn test_plan_holds_until_the_catalog_changes() {
+    let db = people_db();
+    let stmt = parse_statement("SELECT id FROM people WHERE city = 'paris'").unwrap();
+    let plan = plan_statement(&db, &stmt);
+    assert_eq!(plan.catalog_version(), db.catalog_version());
+    assert_eq!(execute_planned(&db, &stmt, &plan).unwrap(), json!([{"id": 0}, {"id": 2}]));
+    // Reading the catalog leaves the version alone
+    assert_eq!(plan_statement(&db, &stmt).catalog_version(), plan.catalog_version());
+
+    db.create_index("people", "city", IndexType::BTree, false).unwrap();
+    assert_ne!(plan.catalog_version(), db.catalog_version());
+    // The old plan is made again rather than used, and the rows come by the index
+    db.storage.del(&Space("index_people.city".into()), b"paris#tbl/people/2").unwrap();
+    assert_eq!(execute_planned(&db, &stmt, &plan).unwrap(), json!([{"id": 0}]));
+}
@@ -0,0 +1,27 @@
+//! Tests for writing rows outside SQL statements
+
+use std::sync::Arc;
+use serde_json::json;
+use tonledb_core::{Column, DataType, Db, Storage, TableSchema};
+use tonledb_sql::rows::{self, RowWriter};
+use tonledb_storage::InMemoryStore;
+
+#[test]
+fn test_generated_keys_follow_the_highest_stored_key() {
+    let db = Db::new(Arc::new(InMemoryStore::new(1000)));
+    let columns = vec![Column { name: "n".into(), data_type: DataType::Integer, constraints: vec![] }];
+    let table = TableSchema { name: "log".into(), columns, pk: None, constraints: vec![] };
+    let mut writer = RowWriter::new(&db);
+    for n in 1..=3 {
+        writer.insert(&table, &[], &json!({"n": n})).unwrap();
+    }
+    let first = db.storage.scan_prefix(&rows::data_space(), b"tbl/log/").unwrap().next().unwrap().0;
+    db.storage.del(&rows::data_space(), &first).unwrap();
+
+    // A new writer counts on from the last key, not the number of rows left
+    RowWriter::new(&db).insert(&table, &[], &json!({"n": 4})).unwrap();
+    let rows: Vec<i64> = db.storage.scan_prefix(&rows::data_space(), b"tbl/log/").unwrap()
+        .map(|(_, v)| serde_json::from_slice::<serde_json::Value>(&v).unwrap()["n"].as_i64().unwrap())
+        .collect();
+    assert_eq!(rows, vec![2, 3, 4]);
+}
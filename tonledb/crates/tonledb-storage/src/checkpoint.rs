@@ -0,0 +1,50 @@
+//! Periodic WAL checkpoints.
+//!
+//! Without checkpoints a restart replays every record logged since the last
+//! compaction. The checkpointer calls `InMemoryStore::checkpoint` on an interval,
+//! so replay only covers what was logged since the newest checkpoint.
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::InMemoryStore;
+
+/// Handle to a checkpointer thread; the thread stops when the handle is dropped.
+pub struct Checkpointer {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Checkpointer {
+    /// Checkpoint `store` every `interval` on a dedicated thread
+    pub fn spawn(store: Arc<InMemoryStore>, interval: Duration) -> Self {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let stop2 = stop.clone();
+        let handle = std::thread::Builder::new()
+            .name("tonledb-checkpointer".into())
+            .spawn(move || loop {
+                let (lock, cv) = &*stop2;
+                let stopped = cv.wait_timeout_while(lock.lock().unwrap(), interval, |s| !*s).unwrap().0;
+                if *stopped {
+                    return;
+                }
+                drop(stopped);
+                // A failed checkpoint is retried on the next tick; the log still holds everything
+                let _ = store.checkpoint();
+            })
+            .expect("spawn checkpointer");
+        Self { stop, handle: Some(handle) }
+    }
+}
+
+impl Drop for Checkpointer {
+    fn drop(&mut self) {
+        let (lock, cv) = &*self.stop;
+        *lock.lock().unwrap() = true;
+        cv.notify_all();
+        if let Some(h) = self.handle.take() {
+            let _ = h.join();
+        }
+    }
+}
@@ -0,0 +1,322 @@
+//! Transparent chunking of large values.
+//!
+//! `ChunkedStorage` stores values above `ChunkOptions::threshold` as a small
+//! manifest under their own key plus `chunk_size` pieces in a companion space
+//! (`<space>#chunks`), so a large document or blob is written as several bounded
+//! WAL records and cache entries instead of one huge one. Readers reassemble the
+//! value and check its length and CRC.
+//!
+//! Chunk keys are `key | generation (8 bytes BE) | index (4 bytes BE)`. A rewrite
+//! puts the new generation's chunks before switching the manifest and removes
+//! the old generation afterwards, so a crash never leaves a manifest pointing at
+//! half-written chunks. Versioned writes and merges are not chunked.
+
+use tonledb_core::transaction::{next_timestamp, WriteSet};
+use tonledb_core::{CacheStats, CompactionReport, Consistency, DbError, ReadView, Result, Space, Storage};
+
+/// Leads every manifest. Small values that happen to start with it are chunked
+/// too, so a stored value is never mistaken for a manifest.
+const MAGIC: &[u8] = b"\0tldb-chunked\0";
+const MANIFEST_LEN: usize = MAGIC.len() + 8 + 8 + 4 + 4;
+
+type ScanIter = Box<dyn Iterator<Item=(Vec<u8>, Vec<u8>)> + Send>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkOptions {
+    /// Values longer than this are chunked
+    pub threshold: usize,
+    /// Size of each chunk but the last
+    pub chunk_size: usize,
+}
+
+impl Default for ChunkOptions {
+    fn default() -> Self {
+        Self { threshold: 1 << 20, chunk_size: 256 << 10 }
+    }
+}
+
+/// Where the chunks of one value live and what they add up to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Manifest {
+    generation: u64,
+    len: u64,
+    chunks: u32,
+    crc: u32,
+}
+
+impl Manifest {
+    fn encode(&self) -> Vec<u8> {
+        [
+            MAGIC,
+            &self.generation.to_be_bytes(),
+            &self.len.to_be_bytes(),
+            &self.chunks.to_be_bytes(),
+            &self.crc.to_be_bytes(),
+        ]
+        .concat()
+    }
+
+    fn decode(raw: &[u8]) -> Option<Self> {
+        if raw.len() != MANIFEST_LEN || !raw.starts_with(MAGIC) {
+            return None;
+        }
+        let b = &raw[MAGIC.len()..];
+        Some(Self {
+            generation: u64::from_be_bytes(b[0..8].try_into().ok()?),
+            len: u64::from_be_bytes(b[8..16].try_into().ok()?),
+            chunks: u32::from_be_bytes(b[16..20].try_into().ok()?),
+            crc: u32::from_be_bytes(b[20..24].try_into().ok()?),
+        })
+    }
+}
+
+/// Storage wrapper splitting large values across several keys
+pub struct ChunkedStorage<S: Storage> {
+    inner: S,
+    opts: ChunkOptions,
+}
+
+impl<S: Storage> ChunkedStorage<S> {
+    pub fn new(inner: S, opts: ChunkOptions) -> Self {
+        Self { inner, opts: ChunkOptions { chunk_size: opts.chunk_size.max(1), ..opts } }
+    }
+
+    pub fn options(&self) -> ChunkOptions {
+        self.opts
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Whether `key` is currently stored as chunks
+    pub fn is_chunked(&self, space: &Space, key: &[u8]) -> Result<bool> {
+        Ok(self.inner.get(space, key)?.as_deref().and_then(Manifest::decode).is_some())
+    }
+
+    /// Write `val` through `write_main`, chunking it when large, then drop the
+    /// chunks of the value it replaces
+    fn write(&self, space: &Space, key: Vec<u8>, val: Vec<u8>, write_main: impl FnOnce(Vec<u8>, Vec<u8>) -> Result<()>, ttl_ms: Option<u64>) -> Result<()> {
+        let old = self.manifest_of(space, &key)?;
+        if val.len() <= self.opts.threshold && !val.starts_with(MAGIC) {
+            write_main(key.clone(), val)?;
+        } else {
+            let manifest = Manifest {
+                generation: next_timestamp(),
+                len: val.len() as u64,
+                chunks: val.len().div_ceil(self.opts.chunk_size) as u32,
+                crc: crc32fast::hash(&val),
+            };
+            let cs = chunk_space(space);
+            for (i, piece) in val.chunks(self.opts.chunk_size).enumerate() {
+                let ck = chunk_key(&key, manifest.generation, i as u32);
+                match ttl_ms {
+                    Some(ttl) => self.inner.put_with_ttl(&cs, ck, piece.to_vec(), ttl)?,
+                    None => self.inner.put(&cs, ck, piece.to_vec())?,
+                }
+            }
+            write_main(key.clone(), manifest.encode())?;
+        }
+        if let Some(old) = old {
+            self.drop_chunks(space, &key, &old)?;
+        }
+        Ok(())
+    }
+
+    fn manifest_of(&self, space: &Space, key: &[u8]) -> Result<Option<Manifest>> {
+        Ok(self.inner.get(space, key)?.as_deref().and_then(Manifest::decode))
+    }
+
+    fn drop_chunks(&self, space: &Space, key: &[u8], m: &Manifest) -> Result<()> {
+        // Exact keys: a prefix delete could reach the chunks of a longer key
+        let cs = chunk_space(space);
+        for i in 0..m.chunks {
+            self.inner.del(&cs, &chunk_key(key, m.generation, i))?;
+        }
+        Ok(())
+    }
+
+    fn delete(&self, space: &Space, key: &[u8], del_main: impl FnOnce() -> Result<()>) -> Result<()> {
+        let old = self.manifest_of(space, key)?;
+        del_main()?;
+        if let Some(old) = old {
+            self.drop_chunks(space, key, &old)?;
+        }
+        Ok(())
+    }
+
+    fn resolve(&self, space: &Space, key: &[u8], raw: Option<Vec<u8>>) -> Result<Option<Vec<u8>>> {
+        resolve(&|s, p| self.inner.scan_prefix(s, p), space, key, raw)
+    }
+}
+
+impl<S: Storage> Storage for ChunkedStorage<S> {
+    fn get(&self, space: &Space, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.resolve(space, key, self.inner.get(space, key)?)
+    }
+
+    fn put(&self, space: &Space, key: Vec<u8>, val: Vec<u8>) -> Result<()> {
+        self.write(space, key, val, |k, v| self.inner.put(space, k, v), None)
+    }
+
+    fn del(&self, space: &Space, key: &[u8]) -> Result<()> {
+        self.delete(space, key, || self.inner.del(space, key))
+    }
+
+    fn scan_prefix(&self, space: &Space, prefix: &[u8]) -> Result<ScanIter> {
+        resolve_scan(&|s, p| self.inner.scan_prefix(s, p), space, self.inner.scan_prefix(space, prefix)?)
+    }
+
+    fn scan_prefix_page(&self, space: &Space, prefix: &[u8], after: Option<&[u8]>, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let page = self.inner.scan_prefix_page(space, prefix, after, limit)?;
+        Ok(resolve_scan(&|s, p| self.inner.scan_prefix(s, p), space, Box::new(page.into_iter()))?.collect())
+    }
+
+    fn scan_range(&self, space: &Space, start: &[u8], end: Option<&[u8]>, reverse: bool, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let rows = self.inner.scan_range(space, start, end, reverse, limit)?;
+        Ok(resolve_scan(&|s, p| self.inner.scan_prefix(s, p), space, Box::new(rows.into_iter()))?.collect())
+    }
+
+    fn merge(&self, space: &Space, key: Vec<u8>, operand: Vec<u8>) -> Result<()> {
+        self.inner.merge(space, key, operand)
+    }
+
+    fn delete_prefix(&self, space: &Space, prefix: &[u8]) -> Result<usize> {
+        // Chunk keys start with their value's key, so the same prefix covers them
+        self.inner.delete_prefix(&chunk_space(space), prefix)?;
+        self.inner.delete_prefix(space, prefix)
+    }
+
+    fn get_versioned(&self, space: &Space, key: &[u8], version: u64) -> Result<Option<Vec<u8>>> {
+        self.resolve(space, key, self.inner.get_versioned(space, key, version)?)
+    }
+
+    fn put_versioned(&self, space: &Space, key: Vec<u8>, val: Vec<u8>, version: u64) -> Result<()> {
+        self.inner.put_versioned(space, key, val, version)
+    }
+
+    fn del_versioned(&self, space: &Space, key: &[u8], version: u64) -> Result<()> {
+        self.inner.del_versioned(space, key, version)
+    }
+
+    fn commit_writes(&self, writes: &WriteSet, version: u64) -> Result<()> {
+        self.inner.commit_writes(writes, version)
+    }
+
+    fn latest_version(&self, space: &Space, key: &[u8]) -> Result<Option<u64>> {
+        self.inner.latest_version(space, key)
+    }
+
+    fn gc_versions(&self, oldest_active: u64) -> Result<usize> {
+        self.inner.gc_versions(oldest_active)
+    }
+
+    fn get_with(&self, space: &Space, key: &[u8], consistency: Consistency) -> Result<Option<Vec<u8>>> {
+        self.resolve(space, key, self.inner.get_with(space, key, consistency)?)
+    }
+
+    fn put_with(&self, space: &Space, key: Vec<u8>, val: Vec<u8>, consistency: Consistency) -> Result<()> {
+        self.write(space, key, val, |k, v| self.inner.put_with(space, k, v, consistency), None)
+    }
+
+    fn del_with(&self, space: &Space, key: &[u8], consistency: Consistency) -> Result<()> {
+        self.delete(space, key, || self.inner.del_with(space, key, consistency))
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn ReadView>> {
+        Ok(Box::new(ChunkedView { inner: self.inner.snapshot()? }))
+    }
+
+    fn put_with_ttl(&self, space: &Space, key: Vec<u8>, val: Vec<u8>, ttl_ms: u64) -> Result<()> {
+        self.write(space, key, val, |k, v| self.inner.put_with_ttl(space, k, v, ttl_ms), Some(ttl_ms))
+    }
+
+    fn expires_at(&self, space: &Space, key: &[u8]) -> Result<Option<u64>> {
+        self.inner.expires_at(space, key)
+    }
+
+    fn sweep_expired(&self) -> Result<usize> {
+        self.inner.sweep_expired()
+    }
+
+    /// Compacts the companion chunk space too; the counts cover both
+    fn compact_space(&self, space: &Space) -> Result<CompactionReport> {
+        let mut report = self.inner.compact_space(space)?;
+        let chunks = self.inner.compact_space(&chunk_space(space))?;
+        report.expired_removed += chunks.expired_removed;
+        report.tombstones_removed += chunks.tombstones_removed;
+        report.recompressed += chunks.recompressed;
+        report.wal_records_after = chunks.wal_records_after;
+        Ok(report)
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.inner.cache_stats()
+    }
+
+    fn is_cached(&self, space: &Space, key: &[u8]) -> bool {
+        self.inner.is_cached(space, key)
+    }
+}
+
+/// Snapshot of a `ChunkedStorage`; chunks are read from the same snapshot
+struct ChunkedView {
+    inner: Box<dyn ReadView>,
+}
+
+impl ReadView for ChunkedView {
+    fn get(&self, space: &Space, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        resolve(&|s, p| self.inner.scan_prefix(s, p), space, key, self.inner.get(space, key)?)
+    }
+
+    fn scan_prefix(&self, space: &Space, prefix: &[u8]) -> Result<ScanIter> {
+        resolve_scan(&|s, p| self.inner.scan_prefix(s, p), space, self.inner.scan_prefix(space, prefix)?)
+    }
+}
+
+// ---------- helpers ----------
+
+type Scan<'a> = dyn Fn(&Space, &[u8]) -> Result<ScanIter> + 'a;
+
+fn chunk_space(space: &Space) -> Space {
+    Space(format!("{}#chunks", space.0))
+}
+
+fn generation_prefix(key: &[u8], generation: u64) -> Vec<u8> {
+    [key, &generation.to_be_bytes()].concat()
+}
+
+fn chunk_key(key: &[u8], generation: u64, index: u32) -> Vec<u8> {
+    [generation_prefix(key, generation), index.to_be_bytes().to_vec()].concat()
+}
+
+/// The stored value behind `raw`: itself, or the chunks its manifest points at
+fn resolve(scan: &Scan<'_>, space: &Space, key: &[u8], raw: Option<Vec<u8>>) -> Result<Option<Vec<u8>>> {
+    let Some(raw) = raw else { return Ok(None) };
+    let Some(m) = Manifest::decode(&raw) else { return Ok(Some(raw)) };
+    let prefix = generation_prefix(key, m.generation);
+    let mut out = Vec::with_capacity(m.len as usize);
+    let mut chunks = 0u32;
+    // Chunk indexes are big-endian, so the scan yields them in order
+    for (k, piece) in scan(&chunk_space(space), &prefix)? {
+        if k.len() != prefix.len() + 4 {
+            continue;
+        }
+        chunks += 1;
+        out.extend_from_slice(&piece);
+    }
+    if chunks != m.chunks || out.len() as u64 != m.len || crc32fast::hash(&out) != m.crc {
+        return Err(DbError::Corruption(format!(
+            "chunked value {} in {}: expected {} chunks / {} bytes, found {} / {}",
+            String::from_utf8_lossy(key), space.0, m.chunks, m.len, chunks, out.len()
+        )));
+    }
+    Ok(Some(out))
+}
+
+fn resolve_scan(scan: &Scan<'_>, space: &Space, it: ScanIter) -> Result<ScanIter> {
+    let rows = it
+        .map(|(k, v)| resolve(scan, space, &k, Some(v)).map(|v| (k, v.unwrap_or_default())))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Box::new(rows.into_iter()))
+}
@@ -0,0 +1,251 @@
+//! Keeps the column-oriented `olap` copy of chosen tables in step with their rows.
+//!
+//! `ColumnarStorage` passes everything through to the store it wraps, and adds to
+//! each write of a row of a mirrored table the segment writes of
+//! `tonledb_core::columnar::row_writes`, committed together with the row where the
+//! call allows it. Writes to mirrored rows take a lock, so segments change in the
+//! order rows do. Where the segments can only be written after the row and that
+//! fails, the table's segments are marked incomplete, so readers go back to its
+//! rows until they are rebuilt. Layout and readers are in `tonledb_core::columnar`.
+
+use std::collections::HashSet;
+use parking_lot::Mutex;
+use tonledb_core::columnar;
+use tonledb_core::transaction::{next_timestamp, WriteSet};
+use tonledb_core::{CacheStats, CompactionReport, Consistency, ReadView, Result, Space, Storage};
+
+const DATA_SPACE: &str = "data";
+const ROW_PREFIX: &[u8] = b"tbl/";
+
+/// Storage wrapper mirroring the rows of `tables` into column segments
+pub struct ColumnarStorage<S: Storage> {
+    inner: S,
+    tables: HashSet<String>,
+    /// Held while a mirrored row and its segments are written
+    row_writes: Mutex<()>,
+}
+
+impl<S: Storage> ColumnarStorage<S> {
+    /// Wrap `inner`, mirroring `tables`; the segments of those not marked complete
+    /// yet are rebuilt from their rows first. Other tables' segments are dropped,
+    /// as their rows may change from now on without them.
+    pub fn new(inner: S, tables: impl IntoIterator<Item = String>) -> Result<Self> {
+        let tables: HashSet<String> = tables.into_iter().collect();
+        for table in columnar::ready_tables(&inner)?.into_iter().filter(|t| !tables.contains(t)) {
+            columnar::invalidate(&inner, &table)?;
+            inner.delete_prefix(&columnar::olap_space(), &columnar::table_prefix(&table))?;
+        }
+        for table in &tables {
+            if !columnar::is_ready(&inner, table)? {
+                columnar::rebuild(&inner, table)?;
+            }
+        }
+        Ok(Self { inner, tables, row_writes: Mutex::new(()) })
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Table and row key written by a write of `key` to `space`, if it is mirrored
+    fn mirrored<'k>(&self, space: &Space, key: &'k [u8]) -> Option<(&'k str, &'k [u8])> {
+        if space.0 != DATA_SPACE {
+            return None;
+        }
+        columnar::split_row_key(key).filter(|(table, _)| self.tables.contains(*table))
+    }
+
+    /// Segment writes taking the row at `key` from what is stored to `new`
+    fn segment_writes(&self, table: &str, rowkey: &[u8], space: &Space, key: &[u8], new: Option<&[u8]>) -> Result<WriteSet> {
+        let old = self.inner.get(space, key)?;
+        let mut writes = WriteSet::new();
+        columnar::row_writes(table, rowkey, old.as_deref(), new, &mut writes);
+        Ok(writes)
+    }
+
+    /// `result` of writing segments after their rows were written; if it failed
+    /// the segments of `tables` no longer match the rows
+    fn after_rows<'t>(&self, tables: impl IntoIterator<Item = &'t str>, result: Result<()>) -> Result<()> {
+        if result.is_err() {
+            for table in tables {
+                let _ = columnar::invalidate(&self.inner, table);
+            }
+        }
+        result
+    }
+
+    fn write_one(&self, space: &Space, key: Vec<u8>, val: Option<Vec<u8>>, version: u64) -> Result<()> {
+        let mut writes = WriteSet::new();
+        writes.insert((space.clone(), key), val);
+        self.commit_writes(&writes, version)
+    }
+}
+
+impl<S: Storage> Storage for ColumnarStorage<S> {
+    fn get(&self, space: &Space, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.inner.get(space, key)
+    }
+
+    fn put(&self, space: &Space, key: Vec<u8>, val: Vec<u8>) -> Result<()> {
+        match self.mirrored(space, &key) {
+            Some(_) => self.write_one(space, key, Some(val), next_timestamp()),
+            None => self.inner.put(space, key, val),
+        }
+    }
+
+    fn del(&self, space: &Space, key: &[u8]) -> Result<()> {
+        match self.mirrored(space, key) {
+            Some(_) => self.write_one(space, key.to_vec(), None, next_timestamp()),
+            None => self.inner.del(space, key),
+        }
+    }
+
+    fn scan_prefix(&self, space: &Space, prefix: &[u8]) -> Result<Box<dyn Iterator<Item=(Vec<u8>, Vec<u8>)> + Send>> {
+        self.inner.scan_prefix(space, prefix)
+    }
+
+    fn merge(&self, space: &Space, key: Vec<u8>, operand: Vec<u8>) -> Result<()> {
+        let Some((table, rowkey)) = self.mirrored(space, &key) else { return self.inner.merge(space, key, operand) };
+        let _guard = self.row_writes.lock();
+        let old = self.inner.get(space, &key)?;
+        self.inner.merge(space, key.clone(), operand)?;
+        let result = self.inner.get(space, &key).and_then(|new| {
+            let mut writes = WriteSet::new();
+            columnar::row_writes(table, rowkey, old.as_deref(), new.as_deref(), &mut writes);
+            self.inner.commit_writes(&writes, next_timestamp())
+        });
+        self.after_rows([table], result)
+    }
+
+    fn scan_prefix_page(&self, space: &Space, prefix: &[u8], after: Option<&[u8]>, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.inner.scan_prefix_page(space, prefix, after, limit)
+    }
+
+    fn scan_range(&self, space: &Space, start: &[u8], end: Option<&[u8]>, reverse: bool, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.inner.scan_range(space, start, end, reverse, limit)
+    }
+
+    fn delete_prefix(&self, space: &Space, prefix: &[u8]) -> Result<usize> {
+        let may_hold_rows = space.0 == DATA_SPACE && (prefix.starts_with(ROW_PREFIX) || ROW_PREFIX.starts_with(prefix));
+        if !may_hold_rows || self.tables.is_empty() {
+            return self.inner.delete_prefix(space, prefix);
+        }
+        let _guard = self.row_writes.lock();
+        let mut writes = WriteSet::new();
+        let mut tables = HashSet::new();
+        for (key, old) in self.inner.scan_prefix(space, prefix)? {
+            if let Some((table, rowkey)) = self.mirrored(space, &key) {
+                columnar::row_writes(table, rowkey, Some(&old), None, &mut writes);
+                tables.insert(table.to_string());
+            }
+        }
+        let deleted = self.inner.delete_prefix(space, prefix)?;
+        let result = self.inner.commit_writes(&writes, next_timestamp());
+        self.after_rows(tables.iter().map(String::as_str), result)?;
+        Ok(deleted)
+    }
+
+    fn get_versioned(&self, space: &Space, key: &[u8], version: u64) -> Result<Option<Vec<u8>>> {
+        self.inner.get_versioned(space, key, version)
+    }
+
+    fn put_versioned(&self, space: &Space, key: Vec<u8>, val: Vec<u8>, version: u64) -> Result<()> {
+        match self.mirrored(space, &key) {
+            Some(_) => self.write_one(space, key, Some(val), version),
+            None => self.inner.put_versioned(space, key, val, version),
+        }
+    }
+
+    fn del_versioned(&self, space: &Space, key: &[u8], version: u64) -> Result<()> {
+        match self.mirrored(space, key) {
+            Some(_) => self.write_one(space, key.to_vec(), None, version),
+            None => self.inner.del_versioned(space, key, version),
+        }
+    }
+
+    /// The segment writes of mirrored rows join the transaction
+    fn commit_writes(&self, writes: &WriteSet, version: u64) -> Result<()> {
+        if !writes.keys().any(|(space, key)| self.mirrored(space, key).is_some()) {
+            return self.inner.commit_writes(writes, version);
+        }
+        let _guard = self.row_writes.lock();
+        let mut all = writes.clone();
+        for ((space, key), val) in writes {
+            if let Some((table, rowkey)) = self.mirrored(space, key) {
+                all.extend(self.segment_writes(table, rowkey, space, key, val.as_deref())?);
+            }
+        }
+        self.inner.commit_writes(&all, version)
+    }
+
+    fn latest_version(&self, space: &Space, key: &[u8]) -> Result<Option<u64>> {
+        self.inner.latest_version(space, key)
+    }
+
+    fn gc_versions(&self, oldest_active: u64) -> Result<usize> {
+        self.inner.gc_versions(oldest_active)
+    }
+
+    fn get_with(&self, space: &Space, key: &[u8], consistency: Consistency) -> Result<Option<Vec<u8>>> {
+        self.inner.get_with(space, key, consistency)
+    }
+
+    /// Segments are written after the row, at the same consistency
+    fn put_with(&self, space: &Space, key: Vec<u8>, val: Vec<u8>, consistency: Consistency) -> Result<()> {
+        let Some((table, rowkey)) = self.mirrored(space, &key) else { return self.inner.put_with(space, key, val, consistency) };
+        let _guard = self.row_writes.lock();
+        let segments = self.segment_writes(table, rowkey, space, &key, Some(&val))?;
+        self.inner.put_with(space, key.clone(), val, consistency)?;
+        let result = segments.into_iter().try_for_each(|((space, key), val)| match val {
+            Some(val) => self.inner.put_with(&space, key, val, consistency),
+            None => self.inner.del_with(&space, &key, consistency),
+        });
+        self.after_rows([table], result)
+    }
+
+    fn del_with(&self, space: &Space, key: &[u8], consistency: Consistency) -> Result<()> {
+        let Some((table, rowkey)) = self.mirrored(space, key) else { return self.inner.del_with(space, key, consistency) };
+        let _guard = self.row_writes.lock();
+        let segments = self.segment_writes(table, rowkey, space, key, None)?;
+        self.inner.del_with(space, key, consistency)?;
+        let result = segments.into_iter().try_for_each(|((space, key), _)| self.inner.del_with(&space, &key, consistency));
+        self.after_rows([table], result)
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn ReadView>> {
+        self.inner.snapshot()
+    }
+
+    /// Segments expire with the row
+    fn put_with_ttl(&self, space: &Space, key: Vec<u8>, val: Vec<u8>, ttl_ms: u64) -> Result<()> {
+        let Some((table, rowkey)) = self.mirrored(space, &key) else { return self.inner.put_with_ttl(space, key, val, ttl_ms) };
+        let _guard = self.row_writes.lock();
+        let segments = self.segment_writes(table, rowkey, space, &key, Some(&val))?;
+        self.inner.put_with_ttl(space, key.clone(), val, ttl_ms)?;
+        let result = segments.into_iter().try_for_each(|((space, key), val)| match val {
+            Some(val) => self.inner.put_with_ttl(&space, key, val, ttl_ms),
+            None => self.inner.del(&space, &key),
+        });
+        self.after_rows([table], result)
+    }
+
+    fn expires_at(&self, space: &Space, key: &[u8]) -> Result<Option<u64>> {
+        self.inner.expires_at(space, key)
+    }
+
+    fn sweep_expired(&self) -> Result<usize> {
+        self.inner.sweep_expired()
+    }
+
+    fn compact_space(&self, space: &Space) -> Result<CompactionReport> {
+        self.inner.compact_space(space)
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.inner.cache_stats()
+    }
+
+    fn is_cached(&self, space: &Space, key: &[u8]) -> bool {
+        self.inner.is_cached(space, key)
+    }
+}
@@ -0,0 +1,231 @@
+//! Transparent value compression, configurable per space.
+//!
+//! Every value written through `CompressedStorage` carries a one-byte codec tag so
+//! the codec or threshold of a space can change without rewriting existing data.
+//! Values below the space's `min_size`, or that do not shrink, are stored raw.
+
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use tonledb_core::transaction::WriteSet;
+use tonledb_core::{CacheStats, CompactionReport, Consistency, DbError, ReadView, Result, Space, Storage};
+
+const TAG_RAW: u8 = 0;
+const TAG_ZSTD: u8 = 1;
+const TAG_LZ4: u8 = 2;
+
+/// Compression algorithm for a space
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Codec {
+    None,
+    Zstd { level: i32 },
+    Lz4,
+}
+
+/// Compress values of at least `min_size` bytes with `codec`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompressionOptions {
+    pub codec: Codec,
+    pub min_size: usize,
+}
+
+impl Default for CompressionOptions {
+    fn default() -> Self {
+        Self { codec: Codec::Zstd { level: 3 }, min_size: 256 }
+    }
+}
+
+/// Encode a value with `opts`, prefixing the codec tag
+pub fn encode_value(opts: Option<&CompressionOptions>, val: &[u8]) -> Result<Vec<u8>> {
+    let compressed = match opts {
+        Some(o) if val.len() >= o.min_size => match o.codec {
+            Codec::None => None,
+            Codec::Zstd { level } => Some((TAG_ZSTD, zstd::encode_all(val, level)
+                .map_err(|e| DbError::Storage(format!("zstd compress: {}", e)))?)),
+            Codec::Lz4 => Some((TAG_LZ4, lz4_flex::compress_prepend_size(val))),
+        },
+        _ => None,
+    };
+    let (tag, body) = match compressed {
+        Some((tag, body)) if body.len() < val.len() => (tag, body),
+        _ => (TAG_RAW, val.to_vec()),
+    };
+    let mut out = Vec::with_capacity(body.len() + 1);
+    out.push(tag);
+    out.extend_from_slice(&body);
+    Ok(out)
+}
+
+/// Decode a value written by `encode_value`
+pub fn decode_value(raw: &[u8]) -> Result<Vec<u8>> {
+    let (tag, body) = raw.split_first()
+        .ok_or_else(|| DbError::Storage("compressed value missing codec tag".into()))?;
+    match *tag {
+        TAG_RAW => Ok(body.to_vec()),
+        TAG_ZSTD => zstd::decode_all(body).map_err(|e| DbError::Storage(format!("zstd decompress: {}", e))),
+        TAG_LZ4 => lz4_flex::decompress_size_prepended(body).map_err(|e| DbError::Storage(format!("lz4 decompress: {}", e))),
+        t => Err(DbError::Storage(format!("unknown codec tag {}", t))),
+    }
+}
+
+fn decode_opt(raw: Option<Vec<u8>>) -> Result<Option<Vec<u8>>> {
+    raw.map(|v| decode_value(&v)).transpose()
+}
+
+type ScanIter = Box<dyn Iterator<Item=(Vec<u8>, Vec<u8>)> + Send>;
+
+fn decode_scan(it: ScanIter) -> Result<ScanIter> {
+    let rows = it.map(|(k, v)| decode_value(&v).map(|v| (k, v))).collect::<Result<Vec<_>>>()?;
+    Ok(Box::new(rows.into_iter()))
+}
+
+/// Storage wrapper that compresses values according to per-space options
+pub struct CompressedStorage<S: Storage> {
+    inner: S,
+    spaces: HashMap<Space, CompressionOptions>,
+    default: Option<CompressionOptions>,
+}
+
+impl<S: Storage> CompressedStorage<S> {
+    /// Wrap `inner`; spaces without options are stored uncompressed (but still tagged)
+    pub fn new(inner: S) -> Self {
+        Self { inner, spaces: HashMap::new(), default: None }
+    }
+
+    /// Options for spaces that have none of their own
+    pub fn with_default(mut self, opts: CompressionOptions) -> Self {
+        self.default = Some(opts);
+        self
+    }
+
+    pub fn with_space(mut self, space: Space, opts: CompressionOptions) -> Self {
+        self.spaces.insert(space, opts);
+        self
+    }
+
+    pub fn options(&self, space: &Space) -> Option<&CompressionOptions> {
+        self.spaces.get(space).or(self.default.as_ref())
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    fn encode(&self, space: &Space, val: &[u8]) -> Result<Vec<u8>> {
+        encode_value(self.options(space), val)
+    }
+}
+
+impl<S: Storage> Storage for CompressedStorage<S> {
+    fn get(&self, space: &Space, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        decode_opt(self.inner.get(space, key)?)
+    }
+
+    fn put(&self, space: &Space, key: Vec<u8>, val: Vec<u8>) -> Result<()> {
+        self.inner.put(space, key, self.encode(space, &val)?)
+    }
+
+    fn del(&self, space: &Space, key: &[u8]) -> Result<()> {
+        self.inner.del(space, key)
+    }
+
+    fn scan_prefix(&self, space: &Space, prefix: &[u8]) -> Result<Box<dyn Iterator<Item=(Vec<u8>, Vec<u8>)> + Send>> {
+        decode_scan(self.inner.scan_prefix(space, prefix)?)
+    }
+
+    fn scan_prefix_page(&self, space: &Space, prefix: &[u8], after: Option<&[u8]>, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        Ok(decode_scan(Box::new(self.inner.scan_prefix_page(space, prefix, after, limit)?.into_iter()))?.collect())
+    }
+
+    fn scan_range(&self, space: &Space, start: &[u8], end: Option<&[u8]>, reverse: bool, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        Ok(decode_scan(Box::new(self.inner.scan_range(space, start, end, reverse, limit)?.into_iter()))?.collect())
+    }
+
+    fn delete_prefix(&self, space: &Space, prefix: &[u8]) -> Result<usize> {
+        self.inner.delete_prefix(space, prefix)
+    }
+
+    fn get_versioned(&self, space: &Space, key: &[u8], version: u64) -> Result<Option<Vec<u8>>> {
+        decode_opt(self.inner.get_versioned(space, key, version)?)
+    }
+
+    fn put_versioned(&self, space: &Space, key: Vec<u8>, val: Vec<u8>, version: u64) -> Result<()> {
+        self.inner.put_versioned(space, key, self.encode(space, &val)?, version)
+    }
+
+    fn del_versioned(&self, space: &Space, key: &[u8], version: u64) -> Result<()> {
+        self.inner.del_versioned(space, key, version)
+    }
+
+    fn commit_writes(&self, writes: &WriteSet, version: u64) -> Result<()> {
+        let mut encoded = WriteSet::with_capacity(writes.len());
+        for ((space, key), val) in writes {
+            let val = val.as_ref().map(|v| self.encode(space, v)).transpose()?;
+            encoded.insert((space.clone(), key.clone()), val);
+        }
+        self.inner.commit_writes(&encoded, version)
+    }
+
+    fn latest_version(&self, space: &Space, key: &[u8]) -> Result<Option<u64>> {
+        self.inner.latest_version(space, key)
+    }
+
+    fn gc_versions(&self, oldest_active: u64) -> Result<usize> {
+        self.inner.gc_versions(oldest_active)
+    }
+
+    fn get_with(&self, space: &Space, key: &[u8], consistency: Consistency) -> Result<Option<Vec<u8>>> {
+        decode_opt(self.inner.get_with(space, key, consistency)?)
+    }
+
+    fn put_with(&self, space: &Space, key: Vec<u8>, val: Vec<u8>, consistency: Consistency) -> Result<()> {
+        self.inner.put_with(space, key, self.encode(space, &val)?, consistency)
+    }
+
+    fn del_with(&self, space: &Space, key: &[u8], consistency: Consistency) -> Result<()> {
+        self.inner.del_with(space, key, consistency)
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn ReadView>> {
+        Ok(Box::new(CompressedView { inner: self.inner.snapshot()? }))
+    }
+
+    fn put_with_ttl(&self, space: &Space, key: Vec<u8>, val: Vec<u8>, ttl_ms: u64) -> Result<()> {
+        self.inner.put_with_ttl(space, key, self.encode(space, &val)?, ttl_ms)
+    }
+
+    fn expires_at(&self, space: &Space, key: &[u8]) -> Result<Option<u64>> {
+        self.inner.expires_at(space, key)
+    }
+
+    fn sweep_expired(&self) -> Result<usize> {
+        self.inner.sweep_expired()
+    }
+
+    fn compact_space(&self, space: &Space) -> Result<CompactionReport> {
+        self.inner.compact_space(space)
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.inner.cache_stats()
+    }
+
+    fn is_cached(&self, space: &Space, key: &[u8]) -> bool {
+        self.inner.is_cached(space, key)
+    }
+}
+
+/// Snapshot of a `CompressedStorage`, decoding values on read
+struct CompressedView {
+    inner: Box<dyn ReadView>,
+}
+
+impl ReadView for CompressedView {
+    fn get(&self, space: &Space, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        decode_opt(self.inner.get(space, key)?)
+    }
+
+    fn scan_prefix(&self, space: &Space, prefix: &[u8]) -> Result<Box<dyn Iterator<Item=(Vec<u8>, Vec<u8>)> + Send>> {
+        decode_scan(self.inner.scan_prefix(space, prefix)?)
+    }
+}
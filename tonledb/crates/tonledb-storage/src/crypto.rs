@@ -1,47 +1,281 @@
+//! Encryption at rest for selected spaces.
+//!
+//! `EncryptedStorage` seals values with AES-256-GCM before they reach the wrapped
+//! store (and so its WAL), for the spaces registered with `with_space`; other
+//! spaces pass through untouched, so e.g. "data" can be encrypted while "catalog"
+//! stays readable. Keys are not encrypted.
+//!
+//! Values are sealed with a data key (DEK) that is itself sealed with the key
+//! encryption key (KEK) given at open and stored in `Space("sys_keys")`, so data
+//! written before a restart stays readable with the same KEK. A sealed value is
+//! `nonce (12 bytes) | ciphertext + tag`, authenticated against its space and key.
+
+use std::collections::HashSet;
+use std::sync::Arc;
 use aes_gcm::{Aes256Gcm, aead::{Aead, KeyInit, Payload}, Key, Nonce};
+use base64::Engine;
 use rand::RngCore;
-use tonledb_core::{Result, Space, Storage, DbError};
-
-pub struct CryptoStorage<S: Storage> { inner: S, dek: [u8;32] }
-
-impl<S: Storage> CryptoStorage<S> {
-    pub fn new(inner:S, kek_b64:&str)->Result<Self>{
-        let kek = base64::decode(kek_b64).map_err(|e| DbError::Invalid(format!("KEK b64: {e}")))?;
-        if kek.len()!=32 { return Err(DbError::Invalid("KEK must be 32 bytes".into())); }
-        let mut dek=[0u8;32]; rand::thread_rng().fill_bytes(&mut dek);
-        Ok(Self{ inner, dek })
-    }
-    fn seal(&self, pt:&[u8], space:&Space, key:&[u8])->Result<Vec<u8>>{
-        let aead=Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&self.dek));
-        let mut nonce=[0u8;12]; rand::thread_rng().fill_bytes(&mut nonce);
-        let mut aad=Vec::new(); aad.extend_from_slice(space.0.as_bytes()); aad.extend_from_slice(key);
-        let mut ct=aead.encrypt(Nonce::from_slice(&nonce), Payload{msg:pt, aad:&aad}).map_err(|e|DbError::Storage(e.to_string()))?;
-        let mut out=Vec::with_capacity(12+ct.len()); out.extend_from_slice(&nonce); out.append(&mut ct); Ok(out)
-    }
-    fn open(&self, blob:&[u8], space:&Space, key:&[u8])->Result<Vec<u8>>{
-        if blob.len()<12 { return Err(DbError::Storage("ciphertext too short".into())); }
-        let (nonce, ct)=blob.split_at(12);
-        let aead=Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&self.dek));
-        let mut aad=Vec::new(); aad.extend_from_slice(space.0.as_bytes()); aad.extend_from_slice(key);
-        aead.decrypt(Nonce::from_slice(nonce), Payload{msg:ct, aad:&aad}).map_err(|_| DbError::Storage("decrypt failed".into()))
-    }
-}
-impl<S: Storage> Storage for CryptoStorage<S>{
-    fn get(&self, space:&Space, key:&[u8])->Result<Option<Vec<u8>>>{
-        match self.inner.get(space,key)? { Some(ct)=>Ok(Some(self.open(&ct,space,key)?)), None=>Ok(None) }
-    }
-    fn put(&self, space:&Space, key:Vec<u8>, val:Vec<u8>)->Result<()>{
-        self.inner.put(space, key.clone(), self.seal(&val,space,&key)?) }
-    fn del(&self, space:&Space, key:&[u8])->Result<()> { self.inner.del(space,key) }
-    fn scan_prefix(&self, space:&Space, prefix:&[u8])->Result<Box<dyn Iterator<Item=(Vec<u8>,Vec<u8>)>+Send>>{
-        let it=self.inner.scan_prefix(space,prefix)?;
-        let dek=self.dek; let sp=space.clone();
-        Ok(Box::new(it.filter_map(move|(k,v)|{
-            if v.len()<12 { return None; }
-            let aead=Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&dek));
-            let (nonce,ct)=v.split_at(12);
-            let mut aad=Vec::new(); aad.extend_from_slice(sp.0.as_bytes()); aad.extend_from_slice(&k);
-            aead.decrypt(Nonce::from_slice(nonce), Payload{msg:ct, aad:&aad}).ok().map(|pt|(k,pt))
-        })))
+use zeroize::Zeroizing;
+use tonledb_core::transaction::WriteSet;
+use tonledb_core::{CacheStats, CompactionReport, Consistency, DbError, ReadView, Result, Space, Storage};
+
+/// Holds the sealed DEK; never encrypted itself
+pub const KEYS_SPACE: &str = "sys_keys";
+const DEK_KEY: &[u8] = b"dek";
+const NONCE_LEN: usize = 12;
+
+type ScanIter = Box<dyn Iterator<Item=(Vec<u8>, Vec<u8>)> + Send>;
+
+/// Which spaces are sealed, and with which data key
+struct Sealer {
+    dek: Zeroizing<[u8; 32]>,
+    spaces: HashSet<Space>,
+    all: bool,
+}
+
+impl Sealer {
+    fn applies(&self, space: &Space) -> bool {
+        space.0 != KEYS_SPACE && (self.all || self.spaces.contains(space))
+    }
+
+    fn seal(&self, space: &Space, key: &[u8], pt: &[u8]) -> Result<Vec<u8>> {
+        if !self.applies(space) {
+            return Ok(pt.to_vec());
+        }
+        seal_with(&self.dek, &aad(space, key), pt)
+    }
+
+    fn open(&self, space: &Space, key: &[u8], blob: Vec<u8>) -> Result<Vec<u8>> {
+        if !self.applies(space) {
+            return Ok(blob);
+        }
+        open_with(&self.dek, &aad(space, key), &blob)
+    }
+
+    fn open_opt(&self, space: &Space, key: &[u8], blob: Option<Vec<u8>>) -> Result<Option<Vec<u8>>> {
+        blob.map(|b| self.open(space, key, b)).transpose()
+    }
+
+    fn open_scan(&self, space: &Space, it: ScanIter) -> Result<ScanIter> {
+        if !self.applies(space) {
+            return Ok(it);
+        }
+        let rows = it.map(|(k, v)| self.open(space, &k, v).map(|v| (k, v))).collect::<Result<Vec<_>>>()?;
+        Ok(Box::new(rows.into_iter()))
+    }
+}
+
+/// Storage wrapper encrypting the values of selected spaces
+pub struct EncryptedStorage<S: Storage> {
+    inner: S,
+    sealer: Arc<Sealer>,
+}
+
+impl<S: Storage> EncryptedStorage<S> {
+    /// Wrap `inner` with the base64 32-byte KEK `kek_b64`, loading the DEK sealed
+    /// in `inner` or creating one on first use. No space is encrypted until
+    /// registered with `with_space` (or `with_all_spaces`).
+    pub fn new(inner: S, kek_b64: &str) -> Result<Self> {
+        let kek = Zeroizing::new(
+            base64::engine::general_purpose::STANDARD
+                .decode(kek_b64.trim())
+                .map_err(|e| DbError::Invalid(format!("KEK base64: {}", e)))?,
+        );
+        let kek: Zeroizing<[u8; 32]> = Zeroizing::new(
+            kek.as_slice().try_into().map_err(|_| DbError::Invalid("KEK must be 32 bytes".into()))?,
+        );
+        let keys = Space(KEYS_SPACE.into());
+        let mut dek = Zeroizing::new([0u8; 32]);
+        match inner.get(&keys, DEK_KEY)? {
+            Some(sealed) => {
+                let plain = Zeroizing::new(
+                    open_with(&kek, DEK_KEY, &sealed).map_err(|_| DbError::Invalid("KEK does not match the stored data key".into()))?,
+                );
+                if plain.len() != 32 {
+                    return Err(DbError::Storage("stored data key is corrupt".into()));
+                }
+                dek.copy_from_slice(&plain);
+            }
+            None => {
+                rand::thread_rng().fill_bytes(dek.as_mut_slice());
+                inner.put(&keys, DEK_KEY.to_vec(), seal_with(&kek, DEK_KEY, dek.as_slice())?)?;
+            }
+        }
+        Ok(Self { inner, sealer: Arc::new(Sealer { dek, spaces: HashSet::new(), all: false }) })
+    }
+
+    /// Encrypt the values of `space`
+    pub fn with_space(mut self, space: Space) -> Self {
+        self.sealer_mut().spaces.insert(space);
+        self
+    }
+
+    /// Encrypt the values of every space
+    pub fn with_all_spaces(mut self) -> Self {
+        self.sealer_mut().all = true;
+        self
+    }
+
+    pub fn is_encrypted(&self, space: &Space) -> bool {
+        self.sealer.applies(space)
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    fn sealer_mut(&mut self) -> &mut Sealer {
+        Arc::get_mut(&mut self.sealer).expect("spaces are registered before the store is shared")
+    }
+}
+
+impl<S: Storage> Storage for EncryptedStorage<S> {
+    fn get(&self, space: &Space, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.sealer.open_opt(space, key, self.inner.get(space, key)?)
+    }
+
+    fn put(&self, space: &Space, key: Vec<u8>, val: Vec<u8>) -> Result<()> {
+        let sealed = self.sealer.seal(space, &key, &val)?;
+        self.inner.put(space, key, sealed)
+    }
+
+    fn del(&self, space: &Space, key: &[u8]) -> Result<()> {
+        self.inner.del(space, key)
+    }
+
+    fn scan_prefix(&self, space: &Space, prefix: &[u8]) -> Result<Box<dyn Iterator<Item=(Vec<u8>, Vec<u8>)> + Send>> {
+        self.sealer.open_scan(space, self.inner.scan_prefix(space, prefix)?)
+    }
+
+    fn scan_prefix_page(&self, space: &Space, prefix: &[u8], after: Option<&[u8]>, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        Ok(self.sealer.open_scan(space, Box::new(self.inner.scan_prefix_page(space, prefix, after, limit)?.into_iter()))?.collect())
+    }
+
+    fn scan_range(&self, space: &Space, start: &[u8], end: Option<&[u8]>, reverse: bool, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        Ok(self.sealer.open_scan(space, Box::new(self.inner.scan_range(space, start, end, reverse, limit)?.into_iter()))?.collect())
+    }
+
+    fn merge(&self, space: &Space, key: Vec<u8>, operand: Vec<u8>) -> Result<()> {
+        // The store can't apply an operator to ciphertext
+        if self.sealer.applies(space) {
+            return Err(DbError::Invalid(format!("merge is not supported on encrypted space {}", space.0)));
+        }
+        self.inner.merge(space, key, operand)
+    }
+
+    fn delete_prefix(&self, space: &Space, prefix: &[u8]) -> Result<usize> {
+        self.inner.delete_prefix(space, prefix)
+    }
+
+    fn get_versioned(&self, space: &Space, key: &[u8], version: u64) -> Result<Option<Vec<u8>>> {
+        self.sealer.open_opt(space, key, self.inner.get_versioned(space, key, version)?)
+    }
+
+    fn put_versioned(&self, space: &Space, key: Vec<u8>, val: Vec<u8>, version: u64) -> Result<()> {
+        let sealed = self.sealer.seal(space, &key, &val)?;
+        self.inner.put_versioned(space, key, sealed, version)
+    }
+
+    fn del_versioned(&self, space: &Space, key: &[u8], version: u64) -> Result<()> {
+        self.inner.del_versioned(space, key, version)
+    }
+
+    fn commit_writes(&self, writes: &WriteSet, version: u64) -> Result<()> {
+        let mut sealed = WriteSet::with_capacity(writes.len());
+        for ((space, key), val) in writes {
+            let val = val.as_ref().map(|v| self.sealer.seal(space, key, v)).transpose()?;
+            sealed.insert((space.clone(), key.clone()), val);
+        }
+        self.inner.commit_writes(&sealed, version)
+    }
+
+    fn latest_version(&self, space: &Space, key: &[u8]) -> Result<Option<u64>> {
+        self.inner.latest_version(space, key)
+    }
+
+    fn gc_versions(&self, oldest_active: u64) -> Result<usize> {
+        self.inner.gc_versions(oldest_active)
+    }
+
+    fn get_with(&self, space: &Space, key: &[u8], consistency: Consistency) -> Result<Option<Vec<u8>>> {
+        self.sealer.open_opt(space, key, self.inner.get_with(space, key, consistency)?)
+    }
+
+    fn put_with(&self, space: &Space, key: Vec<u8>, val: Vec<u8>, consistency: Consistency) -> Result<()> {
+        let sealed = self.sealer.seal(space, &key, &val)?;
+        self.inner.put_with(space, key, sealed, consistency)
+    }
+
+    fn del_with(&self, space: &Space, key: &[u8], consistency: Consistency) -> Result<()> {
+        self.inner.del_with(space, key, consistency)
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn ReadView>> {
+        Ok(Box::new(EncryptedView { inner: self.inner.snapshot()?, sealer: self.sealer.clone() }))
+    }
+
+    fn put_with_ttl(&self, space: &Space, key: Vec<u8>, val: Vec<u8>, ttl_ms: u64) -> Result<()> {
+        let sealed = self.sealer.seal(space, &key, &val)?;
+        self.inner.put_with_ttl(space, key, sealed, ttl_ms)
+    }
+
+    fn expires_at(&self, space: &Space, key: &[u8]) -> Result<Option<u64>> {
+        self.inner.expires_at(space, key)
+    }
+
+    fn sweep_expired(&self) -> Result<usize> {
+        self.inner.sweep_expired()
+    }
+
+    fn compact_space(&self, space: &Space) -> Result<CompactionReport> {
+        self.inner.compact_space(space)
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.inner.cache_stats()
+    }
+
+    fn is_cached(&self, space: &Space, key: &[u8]) -> bool {
+        self.inner.is_cached(space, key)
+    }
+}
+
+/// Snapshot of an `EncryptedStorage`, decrypting values on read
+struct EncryptedView {
+    inner: Box<dyn ReadView>,
+    sealer: Arc<Sealer>,
+}
+
+impl ReadView for EncryptedView {
+    fn get(&self, space: &Space, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.sealer.open_opt(space, key, self.inner.get(space, key)?)
+    }
+
+    fn scan_prefix(&self, space: &Space, prefix: &[u8]) -> Result<Box<dyn Iterator<Item=(Vec<u8>, Vec<u8>)> + Send>> {
+        self.sealer.open_scan(space, self.inner.scan_prefix(space, prefix)?)
+    }
+}
+
+// ---------- helpers ----------
+
+/// Length-prefixed space then key, so ("a", "bc") and ("ab", "c") differ
+fn aad(space: &Space, key: &[u8]) -> Vec<u8> {
+    [&(space.0.len() as u32).to_be_bytes()[..], space.0.as_bytes(), key].concat()
+}
+
+fn seal_with(key: &[u8; 32], aad: &[u8], pt: &[u8]) -> Result<Vec<u8>> {
+    let aead = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let ct = aead.encrypt(Nonce::from_slice(&nonce), Payload { msg: pt, aad }).map_err(|e| DbError::Storage(e.to_string()))?;
+    Ok([&nonce[..], &ct].concat())
+}
+
+fn open_with(key: &[u8; 32], aad: &[u8], blob: &[u8]) -> Result<Vec<u8>> {
+    if blob.len() < NONCE_LEN {
+        return Err(DbError::Storage("ciphertext too short".into()));
     }
+    let (nonce, ct) = blob.split_at(NONCE_LEN);
+    let aead = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    aead.decrypt(Nonce::from_slice(nonce), Payload { msg: ct, aad }).map_err(|_| DbError::Storage("decrypt failed".into()))
 }
@@ -0,0 +1,217 @@
+//! Read-only replica replaying a WAL archive a fixed delay behind the primary.
+//!
+//! A primary archives its WAL with `InMemoryStore::set_wal_archive`; a
+//! `DelayedReplica` follows that archive and applies only records at least
+//! `delay_ms` old, so it always shows the database as it was that long ago. It is a
+//! protection tier against logical corruption (a bad bulk delete only reaches it
+//! after the delay, leaving time to copy data back) and answers "as of an hour ago"
+//! queries without a point-in-time restore.
+//!
+//! Writes are rejected. TTL deadlines are wall-clock times, so a key expires on the
+//! replica when it expires on the primary, not `delay_ms` later.
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use serde::Serialize;
+use tonledb_core::transaction::WriteSet;
+use tonledb_core::{CacheStats, CompactionReport, Consistency, DbError, ReadView, Result, Space, Storage};
+use tonledb_wal::{read_archive, ArchiveCursor};
+use crate::{now_ms, InMemoryStore};
+
+type ScanIter = Box<dyn Iterator<Item=(Vec<u8>, Vec<u8>)> + Send>;
+
+/// How far behind the primary a replica is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ReplicaStatus {
+    pub delay_ms: u64,
+    /// Every archived write made up to this time (epoch ms) is applied
+    pub as_of_ms: u64,
+    pub applied_records: u64,
+}
+
+pub struct DelayedReplica {
+    store: InMemoryStore,
+    archive_dir: String,
+    delay_ms: u64,
+    cursor: Mutex<ArchiveCursor>,
+    as_of_ms: AtomicU64,
+    applied: AtomicU64,
+}
+
+impl DelayedReplica {
+    /// Follow the archive in `archive_dir`, `delay_ms` behind. Nothing is applied
+    /// until the first `catch_up`.
+    pub fn new(archive_dir: &str, delay_ms: u64, cache_cap: usize) -> Self {
+        Self {
+            store: InMemoryStore::new(cache_cap),
+            archive_dir: archive_dir.to_string(),
+            delay_ms,
+            cursor: Mutex::new(ArchiveCursor::default()),
+            as_of_ms: AtomicU64::new(0),
+            applied: AtomicU64::new(0),
+        }
+    }
+
+    /// Apply every archived record that is now at least `delay_ms` old; returns how many
+    pub fn catch_up(&self) -> Result<usize> {
+        let mut cursor = self.cursor.lock().unwrap_or_else(|e| e.into_inner());
+        let horizon = now_ms().saturating_sub(self.delay_ms);
+        let (records, next) = read_archive(&self.archive_dir, &cursor, horizon).map_err(|e| DbError::Storage(e.to_string()))?;
+        // On error the cursor stays put and the batch is re-applied next time; records are idempotent
+        for rec in &records {
+            self.store.apply_wal_record(&rec.record)?;
+        }
+        *cursor = next;
+        self.applied.fetch_add(records.len() as u64, Ordering::Relaxed);
+        self.as_of_ms.store(horizon, Ordering::Relaxed);
+        Ok(records.len())
+    }
+
+    pub fn status(&self) -> ReplicaStatus {
+        ReplicaStatus {
+            delay_ms: self.delay_ms,
+            as_of_ms: self.as_of_ms.load(Ordering::Relaxed),
+            applied_records: self.applied.load(Ordering::Relaxed),
+        }
+    }
+}
+
+fn read_only() -> DbError {
+    DbError::Invalid("historical replica is read-only".into())
+}
+
+impl Storage for DelayedReplica {
+    fn get(&self, space: &Space, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.store.get(space, key)
+    }
+
+    fn put(&self, _space: &Space, _key: Vec<u8>, _val: Vec<u8>) -> Result<()> {
+        Err(read_only())
+    }
+
+    fn del(&self, _space: &Space, _key: &[u8]) -> Result<()> {
+        Err(read_only())
+    }
+
+    fn scan_prefix(&self, space: &Space, prefix: &[u8]) -> Result<ScanIter> {
+        self.store.scan_prefix(space, prefix)
+    }
+
+    fn scan_prefix_page(&self, space: &Space, prefix: &[u8], after: Option<&[u8]>, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.store.scan_prefix_page(space, prefix, after, limit)
+    }
+
+    fn scan_range(&self, space: &Space, start: &[u8], end: Option<&[u8]>, reverse: bool, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.store.scan_range(space, start, end, reverse, limit)
+    }
+
+    fn merge(&self, _space: &Space, _key: Vec<u8>, _operand: Vec<u8>) -> Result<()> {
+        Err(read_only())
+    }
+
+    fn delete_prefix(&self, _space: &Space, _prefix: &[u8]) -> Result<usize> {
+        Err(read_only())
+    }
+
+    fn get_versioned(&self, space: &Space, key: &[u8], version: u64) -> Result<Option<Vec<u8>>> {
+        self.store.get_versioned(space, key, version)
+    }
+
+    fn put_versioned(&self, _space: &Space, _key: Vec<u8>, _val: Vec<u8>, _version: u64) -> Result<()> {
+        Err(read_only())
+    }
+
+    fn del_versioned(&self, _space: &Space, _key: &[u8], _version: u64) -> Result<()> {
+        Err(read_only())
+    }
+
+    fn commit_writes(&self, _writes: &WriteSet, _version: u64) -> Result<()> {
+        Err(read_only())
+    }
+
+    fn latest_version(&self, space: &Space, key: &[u8]) -> Result<Option<u64>> {
+        self.store.latest_version(space, key)
+    }
+
+    fn get_with(&self, space: &Space, key: &[u8], _consistency: Consistency) -> Result<Option<Vec<u8>>> {
+        self.store.get(space, key)
+    }
+
+    fn put_with(&self, _space: &Space, _key: Vec<u8>, _val: Vec<u8>, _consistency: Consistency) -> Result<()> {
+        Err(read_only())
+    }
+
+    fn del_with(&self, _space: &Space, _key: &[u8], _consistency: Consistency) -> Result<()> {
+        Err(read_only())
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn ReadView>> {
+        self.store.snapshot()
+    }
+
+    fn put_with_ttl(&self, _space: &Space, _key: Vec<u8>, _val: Vec<u8>, _ttl_ms: u64) -> Result<()> {
+        Err(read_only())
+    }
+
+    fn expires_at(&self, space: &Space, key: &[u8]) -> Result<Option<u64>> {
+        self.store.expires_at(space, key)
+    }
+
+    /// Expired keys already read as absent; this only reclaims their memory
+    fn sweep_expired(&self) -> Result<usize> {
+        self.store.sweep_expired()
+    }
+
+    fn compact_space(&self, space: &Space) -> Result<CompactionReport> {
+        self.store.compact_space(space)
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.store.cache_stats()
+    }
+
+    fn is_cached(&self, space: &Space, key: &[u8]) -> bool {
+        self.store.is_cached(space, key)
+    }
+}
+
+/// Handle to a thread calling `DelayedReplica::catch_up` on an interval; the
+/// thread stops when the handle is dropped.
+pub struct ReplicaFollower {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ReplicaFollower {
+    pub fn spawn(replica: Arc<DelayedReplica>, interval: Duration) -> Self {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let stop2 = stop.clone();
+        let handle = std::thread::Builder::new()
+            .name("tonledb-replica-follower".into())
+            .spawn(move || loop {
+                let (lock, cv) = &*stop2;
+                let stopped = cv.wait_timeout_while(lock.lock().unwrap(), interval, |s| !*s).unwrap().0;
+                if *stopped {
+                    return;
+                }
+                drop(stopped);
+                // A failed catch-up is retried on the next tick
+                let _ = replica.catch_up();
+            })
+            .expect("spawn replica follower");
+        Self { stop, handle: Some(handle) }
+    }
+}
+
+impl Drop for ReplicaFollower {
+    fn drop(&mut self) {
+        let (lock, cv) = &*self.stop;
+        *lock.lock().unwrap() = true;
+        cv.notify_all();
+        if let Some(h) = self.handle.take() {
+            let _ = h.join();
+        }
+    }
+}
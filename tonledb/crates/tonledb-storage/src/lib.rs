@@ -1,71 +1,978 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use parking_lot::RwLock;
 use clru::CLruCache;
-use tonledb_core::{DbError, Result, Space, Storage};
+use tonledb_core::{CacheStats, CompactionReport, DbError, ReadView, Result, Space, Storage};
+use tonledb_core::transaction::{next_timestamp, WriteSet};
 
+pub mod checkpoint;
+pub mod chunked;
+pub mod columnar;
+pub mod compression;
+pub mod crypto;
+pub mod delayed;
 pub mod index;
+pub mod options;
+pub mod replicated;
+pub mod segment_archive;
+pub mod shard;
+pub mod spill;
+pub mod tiered;
+pub mod ttl;
+mod wal_op;
+
+pub use checkpoint::Checkpointer;
+pub use chunked::{ChunkOptions, ChunkedStorage};
+pub use columnar::ColumnarStorage;
+pub use crypto::EncryptedStorage;
+pub use delayed::{DelayedReplica, ReplicaFollower, ReplicaStatus};
+pub use options::StorageOptions;
+pub use segment_archive::ObjectStoreArchiver;
+pub use shard::DEFAULT_SHARDS;
+pub use spill::{MemoryLimit, OnFull};
+pub use tiered::{TierPolicy, TierStats, TieredStorage};
+use shard::{shard_index, Shards};
+use spill::{SpillEntry, SpillFile, SpillIndex};
+use wal_op::WalOp;
+
+type Map = BTreeMap<(Space, Vec<u8>), Vec<u8>>;
+/// One shard of the live map: persistent, so a clone shares its nodes and a write
+/// to either copies only the nodes on the path to the key
+type Shard = im::OrdMap<(Space, Vec<u8>), Vec<u8>>;
+/// Version chain for one key, oldest first; `None` marks a delete.
+type Chain = Vec<(u64, Option<Vec<u8>>)>;
 
 /// In-memory store with best-effort WAL and an LRU around get/put keys for hot paths.
+/// The map is split into lock-striped shards (see `shard`), so writers to different
+/// keys proceed in parallel. Each shard is a persistent map: a snapshot clones
+/// every shard in constant time, and a write to a shard a snapshot still shares
+/// copies the O(log n) nodes on the path to its key rather than the shard.
+///
+/// MVCC: keys written through `put_versioned`/`del_versioned` keep a version chain
+/// so readers at an older timestamp still see the value as of that timestamp.
+/// Keys never written with a version are unversioned and visible at every timestamp.
+/// Version chains live in memory only; WAL replay restores the latest values.
+///
+/// Spaces can be tuned individually with `set_space_options` (own cache, in-memory
+/// compression, default TTL, merge operator); unregistered spaces share the store-wide cache.
+///
+/// Keys written with a TTL (`put_with_ttl` or a space's `default_ttl_ms`) read as
+/// absent once expired and are removed by `sweep_expired` (see `ttl::TtlSweeper`).
+/// Deadlines are logged to the WAL so they survive a restart.
+///
+/// `checkpoint` writes the whole map out as a WAL checkpoint, so a restart replays
+/// only what was logged since (see `checkpoint::Checkpointer`).
+///
+/// An optional `MemoryLimit` bounds the bytes held in the live map; over budget,
+/// writes are either rejected or cold entries (not in any LRU cache) are spilled
+/// to an overflow file and read back from there transparently.
+///
+/// `compact_space` rewrites one space online: expired keys and unobservable delete
+/// markers are dropped, values re-encoded, and the space's WAL records replaced by
+/// one record per live key.
 pub struct InMemoryStore {
-inner: RwLock<BTreeMap<(Space, Vec<u8>), Vec<u8>>>,
+inner: Shards<Shard>,
+versions: Shards<HashMap<(Space, Vec<u8>), Chain>>,
 wal: Option<RwLock<tonledb_wal::Wal>>,
-cache: RwLock<CLruCache<(Space, Vec<u8>), Vec<u8>>>,
+/// Group commit of `wal`, when it buffers appends
+commits: Option<Arc<tonledb_wal::CommitQueue>>,
+cache: Shards<Cache>,
+options: RwLock<Arc<HashMap<Space, StorageOptions>>>,
+space_caches: RwLock<HashMap<Space, SpaceCache>>,
+/// Expiry (epoch ms) of keys written to spaces with a default TTL
+expiries: RwLock<Arc<Expiries>>,
+/// Bytes (space + key + value) held in the live map
+mem_bytes: AtomicUsize,
+limit: RwLock<Option<MemoryLimit>>,
+spill: RwLock<Option<Arc<SpillFile>>>,
+cache_hits: AtomicU64,
+cache_misses: AtomicU64,
+/// Shared by writes spanning several steps (`delete_prefix`), exclusive for `compact_space`
+compaction: RwLock<()>,
 }
 
+type Expiries = HashMap<(Space, Vec<u8>), u64>;
+/// Store-wide LRU shard, for spaces without their own cache
+type Cache = CLruCache<(Space, Vec<u8>), Vec<u8>>;
+/// LRU of a space with its own `cache_capacity`
+type SpaceCache = CLruCache<Vec<u8>, Vec<u8>>;
+
 impl InMemoryStore {
 pub fn new(cap: usize) -> Self { 
-    Self { 
-        inner: RwLock::new(BTreeMap::new()), 
-        wal: None, 
-        cache: RwLock::new(CLruCache::new(cap.try_into().unwrap())),
-    } 
+    Self::with_shards(cap, DEFAULT_SHARDS)
+}
+
+/// Store striped over `shards` locks; the LRU capacity `cap` is split evenly between them.
+pub fn with_shards(cap: usize, shards: usize) -> Self {
+    Self::from_parts(BTreeMap::new(), None, cap, shards)
 }
 
 pub fn with_wal(path: &str, cap: usize) -> anyhow::Result<Self> {
-let mut wal = tonledb_wal::Wal::open(path)?;
+Self::with_wal_options(path, cap, tonledb_wal::WalOptions::default())
+}
+
+/// Store recovered from, and logging to, the WAL at `path`, split into segments per `opts`
+pub fn with_wal_options(path: &str, cap: usize, opts: tonledb_wal::WalOptions) -> anyhow::Result<Self> {
+let mut wal = tonledb_wal::Wal::open_with(path, opts)?;
+let records = wal.replay_records().map_err(replay_err)?;
+Self::recovered(wal, records, cap)
+}
+
+/// Store as it was at `target` (see `tonledb_wal::recovery`), e.g. from just before an
+/// accidental delete. The WAL is rewritten to hold that state, so what was logged after
+/// the target no longer replays; its segments are retired as after a compaction.
+pub fn restore(path: &str, cap: usize, opts: tonledb_wal::WalOptions, target: tonledb_wal::RecoveryTarget) -> anyhow::Result<Self> {
+let mut wal = tonledb_wal::Wal::open_with(path, opts)?;
+let records = wal.replay_to(target).map_err(replay_err)?;
+let store = Self::recovered(wal, records, cap)?;
+let state = state_records(&store.snapshot_view())?;
+if let Some(wal) = &store.wal { wal.write().rewrite(&state)?; }
+Ok(store)
+}
+
+/// Store holding the state `records` replay to, logging to `wal`
+fn recovered(wal: tonledb_wal::Wal, records: Vec<tonledb_wal::Record>, cap: usize) -> anyhow::Result<Self> {
 let mut m = BTreeMap::new();
-for rec in wal.replay()? { // record: space\tkey\tval
-let mut it = rec.splitn(3, |b| *b==b'\t');
-let sp = it.next().unwrap(); let k = it.next().unwrap(); let v = it.next().unwrap();
-m.insert((Space(String::from_utf8_lossy(sp).to_string()), k.to_vec()), v.to_vec());
+let mut expiries = HashMap::new();
+for rec in records {
+match WalOp::decode(&rec)? {
+    WalOp::Put { space, key, val } => {
+        let id = (space, key);
+        expiries.remove(&id);
+        m.insert(id, val);
+    }
+    WalOp::Delete { space, key } => {
+        let id = (space, key);
+        expiries.remove(&id); m.remove(&id);
+    }
+    WalOp::DeletePrefix { space, prefix } => {
+        let doomed: Vec<_> = m.range((space.clone(), prefix.clone())..).take_while(|((s, key), _)| *s == space && key.starts_with(&prefix)).map(|(id, _)| id.clone()).collect();
+        for id in doomed { expiries.remove(&id); m.remove(&id); }
+    }
+    WalOp::Ttl { space, key, deadline } => { expiries.insert((space, key), deadline); }
+}
+}
+// Keys that expired while the store was down never come back
+let now = now_ms();
+m.retain(|id, _| expiries.get(id).is_none_or(|exp| *exp > now));
+expiries.retain(|id, exp| *exp > now && m.contains_key(id));
+let store = Self::from_parts(m, Some(wal), cap, DEFAULT_SHARDS);
+*store.expiries.write() = Arc::new(expiries);
+Ok(store)
+}
+
+fn from_parts(map: Map, wal: Option<tonledb_wal::Wal>, cap: usize, shards: usize) -> Self {
+    let mem = map.iter().map(|(id, v)| entry_size(id, v)).sum();
+    let shards = shards.max(1);
+    let mut parts: Vec<Shard> = (0..shards).map(|_| Shard::new()).collect();
+    for (id, v) in map {
+        parts[shard_index(&id.0, &id.1, shards)].insert(id, v);
+    }
+    let mut parts = parts.into_iter();
+    let shard_cap = std::num::NonZeroUsize::new(cap.div_ceil(shards)).expect("cache capacity must be non-zero");
+    Self { 
+        inner: Shards::new(shards, || parts.next().unwrap_or_default()),
+        versions: Shards::new(shards, HashMap::new),
+        commits: wal.as_ref().map(|w| w.commit_queue()),
+        wal: wal.map(RwLock::new), 
+        cache: Shards::new(shards, || CLruCache::new(shard_cap)),
+        options: RwLock::new(Arc::new(HashMap::new())),
+        space_caches: RwLock::new(HashMap::new()),
+        expiries: RwLock::new(Arc::new(HashMap::new())),
+        mem_bytes: AtomicUsize::new(mem),
+        limit: RwLock::new(None),
+        spill: RwLock::new(None),
+        cache_hits: AtomicU64::new(0),
+        cache_misses: AtomicU64::new(0),
+        compaction: RwLock::new(()),
+    } 
+}
+
+/// Set (or clear) the memory budget. If the store is already over a spilling budget,
+/// cold entries are spilled right away; a rejecting budget only blocks growth.
+pub fn set_memory_limit(&self, limit: Option<MemoryLimit>) -> Result<()> {
+if let Some(MemoryLimit { on_full: OnFull::Spill { path }, .. }) = &limit {
+    let mut spill = self.spill.write();
+    match spill.as_ref() {
+        Some(cur) if cur.path() == path.as_path() => {}
+        Some(cur) if cur.len() > 0 => return Err(DbError::Invalid(format!("spill file {} still holds entries", cur.path().display()))),
+        _ => *spill = Some(Arc::new(SpillFile::create(path)?)),
+    }
+}
+*self.limit.write() = limit.clone();
+if let Some(MemoryLimit { max_bytes, on_full: OnFull::Spill { .. } }) = limit {
+    let used = self.memory_usage();
+    if used > max_bytes { self.spill_cold(used - max_bytes, None)?; }
+}
+Ok(())
+}
+
+/// Bytes currently held in memory by live values (excluding version chains and caches)
+pub fn memory_usage(&self) -> usize {
+self.mem_bytes.load(Ordering::Relaxed)
+}
+
+/// Number of entries currently living in the overflow file
+pub fn spilled_entries(&self) -> usize {
+self.spill.read().as_ref().map_or(0, |s| s.len())
+}
+
+/// Check a write of `size` bytes for `id` against the budget. Returns `true` if the
+/// entry itself has to go to the overflow file because not enough could be spilled.
+fn make_room(&self, id: &(Space, Vec<u8>), size: usize) -> Result<bool> {
+let Some(limit) = self.limit.read().clone() else { return Ok(false) };
+let old = self.inner.of(&id.0, &id.1).read().get(id).map_or(0, |v| entry_size(id, v));
+let projected = (self.memory_usage() + size).saturating_sub(old);
+if projected <= limit.max_bytes { return Ok(false); }
+match limit.on_full {
+    OnFull::Reject => Err(DbError::ResourceExhausted(format!(
+        "memory budget of {} bytes exceeded ({} bytes in use, write needs {})",
+        limit.max_bytes, self.memory_usage(), size))),
+    OnFull::Spill { .. } => {
+        self.spill_cold(projected - limit.max_bytes, Some(id))?;
+        Ok((self.memory_usage() + size).saturating_sub(old) > limit.max_bytes)
+    }
+}
+}
+
+/// Move at least `needed` bytes of entries that are not in any LRU cache to the overflow file.
+fn spill_cold(&self, needed: usize, keep: Option<&(Space, Vec<u8>)>) -> Result<()> {
+let Some(spill) = self.spill.read().clone() else { return Ok(()) };
+let mut victims: Vec<SpillEntry> = Vec::new();
+let mut freed = 0;
+for shard in self.inner.iter() {
+    if freed >= needed { break; }
+    let map = shard.read();
+    let space_caches = self.space_caches.read();
+    for (id, v) in map.iter() {
+        if freed >= needed { break; }
+        if Some(id) == keep { continue; }
+        let cold = self.cache.of(&id.0, &id.1).read().peek(id).is_none()
+            && space_caches.get(&id.0).is_none_or(|c| c.peek(&id.1).is_none());
+        if cold {
+            freed += entry_size(id, v);
+            victims.push((id.clone(), v.clone()));
+        }
+    }
+}
+// Persist before dropping from memory so the value is never unreachable
+spill.write(victims.clone())?;
+for (id, val) in victims {
+    let mut map = self.inner.of(&id.0, &id.1).write();
+    if map.get(&id) == Some(&val) {
+        map.remove(&id);
+        self.mem_bytes.fetch_sub(entry_size(&id, &val), Ordering::Relaxed);
+    } else {
+        // Rewritten meanwhile: the in-memory copy is newer
+        spill.remove(&id);
+    }
+}
+Ok(())
+}
+
+fn map_insert(&self, id: (Space, Vec<u8>), stored: Vec<u8>) {
+let size = entry_size(&id, &stored);
+let old = self.inner.of(&id.0, &id.1).write().insert(id.clone(), stored);
+self.mem_bytes.fetch_add(size, Ordering::Relaxed);
+if let Some(old) = old { self.mem_bytes.fetch_sub(entry_size(&id, &old), Ordering::Relaxed); }
+}
+
+fn map_remove(&self, id: &(Space, Vec<u8>)) {
+if let Some(old) = self.inner.of(&id.0, &id.1).write().remove(id) {
+    self.mem_bytes.fetch_sub(entry_size(id, &old), Ordering::Relaxed);
+}
+}
+
+/// Register settings for `space`, replacing any earlier ones. Values already in the
+/// space are re-encoded if the compression setting changes; TTLs apply to later writes.
+pub fn set_space_options(&self, space: Space, opts: StorageOptions) -> Result<()> {
+let old = self.space_options(&space);
+if old.compression != opts.compression {
+    for shard in self.inner.iter() {
+        let mut map = shard.write();
+        let ids: Vec<_> = map.range((space.clone(), Vec::new())..).take_while(|((s, _), _)| *s == space).map(|(id, _)| id.clone()).collect();
+        for id in &ids {
+            let v = map.get_mut(id).expect("listed under the shard's lock");
+            let before = entry_size(id, v);
+            let raw = if old.compression.is_some() { compression::decode_value(v)? } else { std::mem::take(v) };
+            *v = if opts.compression.is_some() { compression::encode_value(opts.compression.as_ref(), &raw)? } else { raw };
+            self.mem_bytes.fetch_add(entry_size(id, v), Ordering::Relaxed);
+            self.mem_bytes.fetch_sub(before, Ordering::Relaxed);
+        }
+    }
+}
+let mut caches = self.space_caches.write();
+match opts.cache_capacity.and_then(|c| c.try_into().ok()) {
+    Some(cap) if opts.cache_capacity != old.cache_capacity => { caches.insert(space.clone(), CLruCache::new(cap)); }
+    Some(_) => {}
+    None => { caches.remove(&space); }
+}
+drop(caches);
+// Entries cached under the old routing would go stale
+for shard in self.cache.iter() {
+    shard.write().retain(|(s, _), _| *s != space);
+}
+Arc::make_mut(&mut *self.options.write()).insert(space, opts);
+Ok(())
+}
+
+/// Settings in effect for `space` (defaults if none were registered)
+pub fn space_options(&self, space: &Space) -> StorageOptions {
+self.options.read().get(space).cloned().unwrap_or_default()
+}
+
+fn cache_get(&self, space: &Space, key: &[u8]) -> Option<Vec<u8>> {
+match self.options.read().get(space).and_then(|o| o.cache_capacity) {
+    None => self.cache.of(space, key).write().get(&(space.clone(), key.to_vec())).cloned(),
+    Some(_) => self.space_caches.write().get_mut(space).and_then(|c| c.get(key).cloned()),
+}
+}
+
+fn cache_put(&self, space: &Space, key: Vec<u8>, val: Vec<u8>) {
+match self.options.read().get(space).and_then(|o| o.cache_capacity) {
+    None => { self.cache.of(space, &key).write().put((space.clone(), key), val); }
+    Some(_) => { if let Some(c) = self.space_caches.write().get_mut(space) { c.put(key, val); } }
 }
-Ok(Self { 
-    inner: RwLock::new(m), 
-    wal: Some(RwLock::new(wal)), 
-    cache: RwLock::new(CLruCache::new(cap.try_into().unwrap())),
-})
+}
+
+fn cache_pop(&self, space: &Space, key: &[u8]) {
+self.cache.of(space, key).write().pop(&(space.clone(), key.to_vec()));
+if let Some(c) = self.space_caches.write().get_mut(space) { c.pop(key); }
+}
+
+fn is_expired(&self, space: &Space, key: &[u8]) -> bool {
+self.expiries.read().get(&(space.clone(), key.to_vec())).is_some_and(|exp| *exp <= now_ms())
+}
+
+/// Apply a write to the live map (WAL, cache and map), bypassing version bookkeeping.
+/// Values expire after `ttl_ms`, or the space's default TTL if `None`.
+fn write_current(&self, space: &Space, key: Vec<u8>, val: Option<Vec<u8>>, ttl_ms: Option<u64>) -> Result<()> {
+let expires = self.expiry_of(space, val.is_some(), ttl_ms);
+// Held until the map is updated, so a compaction never logs the space without this write
+let mut wal = self.wal.as_ref().map(|w| w.write());
+if let Some(w) = wal.as_mut() {
+    for rec in wal_op::write(space, &key, val.as_deref(), expires) { log(w, rec)?; }
+}
+let ticket = wal.as_ref().and_then(|w| w.commit_ticket());
+self.apply_current(space, key, val, expires)?;
+drop(wal);
+self.commit_wal(ticket)
+}
+
+/// Deadline (epoch ms) of a write from now: after `ttl_ms`, or the space's default TTL
+fn expiry_of(&self, space: &Space, is_put: bool, ttl_ms: Option<u64>) -> Option<u64> {
+if !is_put { return None; }
+ttl_ms.or_else(|| self.options.read().get(space).and_then(|o| o.default_ttl_ms)).map(|ttl| now_ms() + ttl)
+}
+
+/// Update the cache, expiries, spill file and map for a write, without logging
+fn apply_current(&self, space: &Space, key: Vec<u8>, val: Option<Vec<u8>>, expires: Option<u64>) -> Result<()> {
+let Some(val) = val else {
+    self.remove_current(space, key);
+    return Ok(());
+};
+match expires {
+    Some(exp) => { Arc::make_mut(&mut *self.expiries.write()).insert((space.clone(), key.clone()), exp); }
+    None => self.clear_expiry(space, &key),
+}
+let codec = self.options.read().get(space).and_then(|o| o.compression);
+let stored = match &codec { Some(c) => compression::encode_value(Some(c), &val)?, None => val.clone() };
+let id = (space.clone(), key);
+let to_disk = self.make_room(&id, entry_size(&id, &stored))?;
+self.cache_put(space, id.1.clone(), val);
+let spill = self.spill.read().clone();
+match spill {
+    Some(spill) if to_disk => {
+        spill.write(vec![(id.clone(), stored)])?;
+        self.map_remove(&id);
+    }
+    _ => {
+        if let Some(spill) = spill { spill.remove(&id); }
+        self.map_insert(id, stored);
+    }
+}
+Ok(())
+}
+
+/// Wait until a group commit has made the WAL appends up to `ticket` durable. Runs
+/// once the WAL guard is released, so concurrent writers can join the same commit;
+/// if it fails the write stays applied in memory but is reported as failed.
+fn commit_wal(&self, ticket: Option<u64>) -> Result<()> {
+let (Some(ticket), Some(commits), Some(wal)) = (ticket, &self.commits, &self.wal) else { return Ok(()) };
+commits.commit(ticket, || wal.write().take_batch()).map_err(|e| DbError::Storage(e.to_string()))
+}
+
+/// Drop `key` from the cache, expiries, spill file and map, without logging
+fn remove_current(&self, space: &Space, key: Vec<u8>) {
+self.cache_pop(space, &key);
+self.clear_expiry(space, &key);
+let id = (space.clone(), key);
+if let Some(spill) = self.spill.read().as_ref() { spill.remove(&id); }
+self.map_remove(&id);
+}
+
+fn clear_expiry(&self, space: &Space, key: &[u8]) {
+let id = (space.clone(), key.to_vec());
+if self.expiries.read().contains_key(&id) {
+    Arc::make_mut(&mut *self.expiries.write()).remove(&id);
+}
+}
+
+/// Insert `val` into the key's version chain at `version`, creating the chain on first use.
+/// The live map is updated only when this becomes the newest version.
+fn write_version(&self, space: &Space, key: Vec<u8>, val: Option<Vec<u8>>, version: u64) -> Result<()> {
+let mut versions = self.versions.of(space, &key).write();
+if self.insert_version(&mut versions, space, &key, val.clone(), version)? {
+    self.write_current(space, key, val, None)?;
+}
+Ok(())
+}
+
+/// Insert `val` into the key's chain in `versions` (its shard); returns whether it is now the newest version
+fn insert_version(&self, versions: &mut HashMap<(Space, Vec<u8>), Chain>, space: &Space, key: &[u8], val: Option<Vec<u8>>, version: u64) -> Result<bool> {
+let chain = match versions.entry((space.clone(), key.to_vec())) {
+    std::collections::hash_map::Entry::Occupied(e) => e.into_mut(),
+    std::collections::hash_map::Entry::Vacant(e) => {
+        // Seed with the unversioned value so readers older than this write still see it
+        let current = self.snapshot_view().get(space, key)?;
+        e.insert(current.map(|v| vec![(0, Some(v))]).unwrap_or_default())
+    }
+};
+let pos = chain.partition_point(|(v, _)| *v <= version);
+if pos > 0 && chain[pos - 1].0 == version { chain[pos - 1].1 = val; } else { chain.insert(pos, (version, val)); }
+Ok(chain.last().map(|(v, _)| *v) == Some(version))
+}
+
+/// Plain (unversioned) write: updates the live map and, if the key is versioned,
+/// appends a new version so existing readers keep their view.
+fn write_plain(&self, space: &Space, key: Vec<u8>, val: Option<Vec<u8>>, ttl_ms: Option<u64>) -> Result<()> {
+let mut versions = self.versions.of(space, &key).write();
+if let Some(chain) = versions.get_mut(&(space.clone(), key.clone())) {
+    chain.push((next_timestamp(), val.clone()));
+}
+self.write_current(space, key, val, ttl_ms)
+}
+
+/// Delete `key` if it is still expired; the versions lock keeps a concurrent rewrite from being lost.
+fn remove_if_expired(&self, space: &Space, key: &[u8]) -> Result<bool> {
+let mut versions = self.versions.of(space, key).write();
+if !self.is_expired(space, key) { return Ok(false); }
+if let Some(chain) = versions.get_mut(&(space.clone(), key.to_vec())) {
+    chain.push((next_timestamp(), None));
+}
+self.write_current(space, key.to_vec(), None, None)?;
+Ok(true)
 }
 
 }
 
 impl Storage for InMemoryStore {
 fn get(&self, space: &Space, key: &[u8]) -> Result<Option<Vec<u8>>> {
-if let Some(v) = self.cache.write().get(&(space.clone(), key.to_vec())).cloned() { return Ok(Some(v)); }
-let val = self.inner.read().get(&(space.clone(), key.to_vec())).cloned();
-if let Some(v) = val.clone() { self.cache.write().put((space.clone(), key.to_vec()), v.clone()); }
+if self.is_expired(space, key) { return Ok(None); }
+if let Some(v) = self.cache_get(space, key) { self.cache_hits.fetch_add(1, Ordering::Relaxed); return Ok(Some(v)); }
+self.cache_misses.fetch_add(1, Ordering::Relaxed);
+let id = (space.clone(), key.to_vec());
+let in_memory = self.inner.of(space, key).read().get(&id).cloned();
+let raw = match in_memory {
+    Some(r) => Some(r),
+    None => match self.spill.read().clone() { Some(spill) => spill.get(&id)?, None => None },
+};
+let val = match raw { Some(r) => Some(decode_stored(&self.options.read(), space, r)?), None => None };
+if let Some(v) = val.clone() { self.cache_put(space, key.to_vec(), v); }
 Ok(val)
 }
 
 fn put(&self, space: &Space, key: Vec<u8>, val: Vec<u8>) -> Result<()> {
-if let Some(w) = &self.wal { let rec = [space.0.as_bytes(), b"\t", &key, b"\t", &val].concat(); w.write().append(&rec).map_err(|e| DbError::Storage(e.to_string()))?; }
-self.cache.write().put((space.clone(), key.clone()), val.clone());
-self.inner.write().insert((space.clone(), key.clone()), val.clone()); Ok(())
+self.write_plain(space, key, Some(val), None)
 }
 
 fn del(&self, space: &Space, key: &[u8]) -> Result<()> { 
-    self.cache.write().pop(&(space.clone(), key.to_vec())); 
-    self.inner.write().remove(&(space.clone(), key.to_vec())); 
-    Ok(()) 
+    self.write_plain(space, key.to_vec(), None, None)
 }
 
 fn scan_prefix(&self, space: &Space, prefix: &[u8]) -> Result<Box<dyn Iterator<Item=(Vec<u8>, Vec<u8>)> + Send>> {
-let space = space.clone(); let p = prefix.to_vec();
-let v: Vec<(Vec<u8>, Vec<u8>)> = self.inner.read().iter().filter(|((s,k),_)| *s==space && k.starts_with(&p)).map(|((_,k),v)|(k.clone(),v.clone())).collect();
+self.snapshot_view().scan_prefix(space, prefix)
+}
+
+fn scan_prefix_page(&self, space: &Space, prefix: &[u8], after: Option<&[u8]>, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+self.snapshot_view().scan_prefix_page(space, prefix, after, limit)
+}
+
+fn scan_range(&self, space: &Space, start: &[u8], end: Option<&[u8]>, reverse: bool, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+self.snapshot_view().scan_range(space, start, end, reverse, limit)
+}
+
+fn get_versioned(&self, space: &Space, key: &[u8], version: u64) -> Result<Option<Vec<u8>>> {
+if let Some(chain) = self.versions.of(space, key).read().get(&(space.clone(), key.to_vec())) {
+    let pos = chain.partition_point(|(v, _)| *v <= version);
+    return Ok(if pos == 0 { None } else { chain[pos - 1].1.clone() });
+}
+self.get(space, key)
+}
+
+fn put_versioned(&self, space: &Space, key: Vec<u8>, val: Vec<u8>, version: u64) -> Result<()> {
+self.write_version(space, key, Some(val), version)
+}
+
+fn del_versioned(&self, space: &Space, key: &[u8], version: u64) -> Result<()> {
+self.write_version(space, key.to_vec(), None, version)
+}
+
+/// Logged as one WAL transaction. The keys' version locks are held throughout, so
+/// the writes become visible together and the log agrees with the live map.
+fn commit_writes(&self, writes: &WriteSet, version: u64) -> Result<()> {
+let mut versions = self.versions.write_many(writes.keys());
+// Only writes that become the newest version reach the live map, and so the log
+let mut current = Vec::new();
+for ((space, key), val) in writes {
+    let shard = versions.get_mut(&self.versions.index(space, key)).expect("shard locked above");
+    if self.insert_version(shard, space, key, val.clone(), version)? {
+        current.push((space, key, val, self.expiry_of(space, val.is_some(), None)));
+    }
+}
+if current.is_empty() { return Ok(()); }
+let mut wal = self.wal.as_ref().map(|w| w.write());
+if let Some(w) = wal.as_mut() {
+    let records: Vec<_> = current.iter().flat_map(|(space, key, val, exp)| wal_op::write(space, key, val.as_deref(), *exp)).collect();
+    w.append_txn(version, &records).map_err(|e| DbError::Storage(e.to_string()))?;
+}
+let ticket = wal.as_ref().and_then(|w| w.commit_ticket());
+for (space, key, val, exp) in current {
+    self.apply_current(space, key.clone(), val.clone(), exp)?;
+}
+drop(wal);
+drop(versions);
+self.commit_wal(ticket)
+}
+
+fn latest_version(&self, space: &Space, key: &[u8]) -> Result<Option<u64>> {
+Ok(self.versions.of(space, key).read().get(&(space.clone(), key.to_vec())).and_then(|c| c.last().map(|(v, _)| *v)))
+}
+
+fn gc_versions(&self, oldest_active: u64) -> Result<usize> {
+let mut removed = 0;
+for shard in self.versions.iter() {
+    shard.write().retain(|_, chain| {
+        // Keep the newest version visible at `oldest_active` and everything after it
+        let drop_n = chain.partition_point(|(v, _)| *v <= oldest_active).saturating_sub(1);
+        chain.drain(..drop_n);
+        removed += drop_n;
+        // A lone version visible to every reader is exactly what the live map holds
+        if chain.len() == 1 && chain[0].0 <= oldest_active { removed += 1; false } else { true }
+    });
+}
+Ok(removed)
+}
+
+fn snapshot(&self) -> Result<Box<dyn ReadView>> {
+Ok(Box::new(self.snapshot_view()))
+}
+
+/// The merged value is computed under the key's write lock and logged to the WAL as a
+/// plain write, so replay needs no operator.
+fn merge(&self, space: &Space, key: Vec<u8>, operand: Vec<u8>) -> Result<()> {
+let Some(op) = self.space_options(space).merge_operator else {
+    return Err(DbError::Invalid(format!("no merge operator registered for space {}", space.0)));
+};
+let mut versions = self.versions.of(space, &key).write();
+let merged = op.apply(self.get(space, &key)?.as_deref(), &operand)?;
+if let Some(chain) = versions.get_mut(&(space.clone(), key.clone())) {
+    chain.push((next_timestamp(), Some(merged.clone())));
+}
+self.write_current(space, key, Some(merged), None)
+}
+
+/// Logged as one WAL record, not one per key. Versioned keys get a tombstone, like `del`.
+fn delete_prefix(&self, space: &Space, prefix: &[u8]) -> Result<usize> {
+let _compaction = self.compaction.read();
+let mut ticket = None;
+if let Some(w) = &self.wal {
+    let mut w = w.write();
+    log(&mut w, wal_op::delete_prefix(space, prefix))?;
+    ticket = w.commit_ticket();
+}
+let keys = self.snapshot_view().keys_with_prefix(space, prefix);
+for key in &keys {
+    let mut versions = self.versions.of(space, key).write();
+    if let Some(chain) = versions.get_mut(&(space.clone(), key.clone())) {
+        chain.push((next_timestamp(), None));
+    }
+    self.remove_current(space, key.clone());
+}
+self.commit_wal(ticket)?;
+Ok(keys.len())
+}
+
+fn put_with_ttl(&self, space: &Space, key: Vec<u8>, val: Vec<u8>, ttl_ms: u64) -> Result<()> {
+self.write_plain(space, key, Some(val), Some(ttl_ms))
+}
+
+fn cache_stats(&self) -> Option<CacheStats> {
+let mut stats = CacheStats { hits: self.cache_hits.load(Ordering::Relaxed), misses: self.cache_misses.load(Ordering::Relaxed), ..Default::default() };
+for shard in self.cache.iter() {
+    let c = shard.read();
+    stats.capacity += c.capacity();
+    stats.entries += c.len();
+}
+for c in self.space_caches.read().values() {
+    stats.capacity += c.capacity();
+    stats.entries += c.len();
+}
+Some(stats)
+}
+
+fn is_cached(&self, space: &Space, key: &[u8]) -> bool {
+if self.is_expired(space, key) { return false; }
+match self.options.read().get(space).and_then(|o| o.cache_capacity) {
+    None => self.cache.of(space, key).read().peek(&(space.clone(), key.to_vec())).is_some(),
+    Some(_) => self.space_caches.read().get(space).is_some_and(|c| c.peek(key).is_some()),
+}
+}
+
+fn expires_at(&self, space: &Space, key: &[u8]) -> Result<Option<u64>> {
+Ok(self.expiries.read().get(&(space.clone(), key.to_vec())).copied().filter(|exp| *exp > now_ms()))
+}
+
+fn sweep_expired(&self) -> Result<usize> {
+let now = now_ms();
+let due: Vec<(Space, Vec<u8>)> = self.expiries.read().iter().filter(|(_, exp)| **exp <= now).map(|(id, _)| id.clone()).collect();
+let mut removed = 0;
+for (space, key) in due {
+    if self.remove_if_expired(&space, &key)? { removed += 1; }
+}
+Ok(removed)
+}
+
+fn compact_space(&self, space: &Space) -> Result<CompactionReport> {
+self.compact(space)
+}
+}
+
+impl InMemoryStore {
+/// Copy every WAL record written from now on to the archive in `dir` (see
+/// `tonledb_wal::archive`), for `DelayedReplica`s to follow.
+pub fn set_wal_archive(&self, dir: &str, segment_bytes: u64) -> Result<()> {
+let Some(wal) = &self.wal else { return Err(DbError::Invalid("WAL archiving needs a store opened with a WAL".into())) };
+let archive = tonledb_wal::WalArchive::open(dir, segment_bytes).map_err(|e| DbError::Storage(e.to_string()))?;
+wal.write().set_archive(archive);
+Ok(())
+}
+
+/// Ship every WAL segment to `archiver` once it is sealed (see `tonledb_wal::segment`)
+pub fn set_wal_segment_archiver(&self, archiver: Box<dyn tonledb_wal::SegmentArchiver>) -> Result<()> {
+let Some(wal) = &self.wal else { return Err(DbError::Invalid("WAL archiving needs a store opened with a WAL".into())) };
+wal.write().set_segment_archiver(archiver).map_err(|e| DbError::Storage(e.to_string()))
+}
+
+/// Position just past the last WAL record written, for an incremental export to start from
+pub fn wal_end_lsn(&self) -> Option<tonledb_wal::Lsn> {
+self.wal.as_ref().map(|w| w.read().end_lsn())
+}
+
+/// Export every WAL record written since `from` to the file `out` (see
+/// `tonledb_wal::export`) and return where the next export starts. Acknowledged
+/// writes are always included; the WAL is only locked to look up its path.
+pub fn export_wal_since(&self, from: tonledb_wal::Lsn, out: &std::path::Path) -> Result<tonledb_wal::Lsn> {
+let Some(wal) = &self.wal else { return Err(DbError::Invalid("WAL export needs a store opened with a WAL".into())) };
+let path = wal.read().path().to_path_buf();
+tonledb_wal::export_since(&path, from, out).map_err(|e| DbError::Storage(e.to_string()))
+}
+
+/// Write every live key out as a WAL checkpoint and retire the segments it covers
+/// (see `tonledb_wal::checkpoint`). Returns the checkpoint's LSN, or `None` if the
+/// store has no WAL or nothing was logged since the last checkpoint.
+pub fn checkpoint(&self) -> Result<Option<u64>> {
+let Some(wal) = &self.wal else { return Ok(None) };
+let storage_err = |e: anyhow::Error| DbError::Storage(e.to_string());
+// Keeps out compactions and prefix deletes, which do not log and apply in one step
+let _exclusive = self.compaction.write();
+let (lsn, path, view) = {
+    // Writes log and apply under the WAL lock, so this view holds exactly what the checkpoint covers
+    let mut w = wal.write();
+    let Some(lsn) = w.begin_checkpoint().map_err(storage_err)? else { return Ok(None) };
+    (lsn, w.path().to_path_buf(), self.snapshot_view())
+};
+let records = state_records(&view)?;
+tonledb_wal::write_checkpoint(&path, lsn, &records).map_err(storage_err)?;
+wal.write().complete_checkpoint(lsn).map_err(storage_err)?;
+Ok(Some(lsn))
+}
+
+/// Apply one WAL record the way replay does. Records are idempotent, so
+/// applying one twice leaves the same state. Transaction markers are not records
+/// to apply: callers replaying a log resolve them first.
+pub fn apply_wal_record(&self, rec: &tonledb_wal::Record) -> Result<()> {
+match WalOp::decode(rec)? {
+    WalOp::Put { space, key, val } => self.put(&space, key, val),
+    WalOp::Delete { space, key } => self.del(&space, &key),
+    WalOp::DeletePrefix { space, prefix } => self.delete_prefix(&space, &prefix).map(|_| ()),
+    WalOp::Ttl { space, key, deadline } => {
+        Arc::make_mut(&mut *self.expiries.write()).insert((space, key), deadline);
+        Ok(())
+    }
+}
+}
+
+/// Space a WAL record written by this store changes
+pub fn wal_record_space(rec: &tonledb_wal::Record) -> Result<Space> {
+WalOp::decode(rec).map(|op| op.space().clone())
+}
+
+/// Compact `space`. Each step takes only the locks it needs for as long as it needs
+/// them, so reads and writes carry on; the WAL is locked while it is rewritten.
+fn compact(&self, space: &Space) -> Result<CompactionReport> {
+let _exclusive = self.compaction.write();
+let mut report = CompactionReport { space: space.0.clone(), ..Default::default() };
+let now = now_ms();
+let due: Vec<Vec<u8>> = self.expiries.read().iter().filter(|((s, _), exp)| s == space && **exp <= now).map(|((_, k), _)| k.clone()).collect();
+for key in due {
+    if self.remove_if_expired(space, &key)? { report.expired_removed += 1; }
+}
+// Deletes before a key's first value hide nothing: readers that old see no value either way
+for shard in self.versions.iter() {
+    shard.write().retain(|(s, _), chain| {
+        if s != space { return true; }
+        let leading = chain.iter().take_while(|(_, v)| v.is_none()).count();
+        chain.drain(..leading);
+        report.tombstones_removed += leading;
+        !chain.is_empty()
+    });
+}
+if let Some(c) = self.space_options(space).compression {
+    for shard in self.inner.iter() {
+        let map = shard.read().clone();
+        let mut fresh = Vec::new();
+        for (id, v) in map.range((space.clone(), Vec::new())..).take_while(|((s, _), _)| s == space) {
+            let enc = compression::encode_value(Some(&c), &compression::decode_value(v)?)?;
+            if enc != *v { fresh.push((id.clone(), v, enc)); }
+        }
+        if fresh.is_empty() { continue; }
+        let mut live = shard.write();
+        for (id, old, enc) in fresh {
+            // Skip values rewritten since the read above
+            let Some(v) = live.get_mut(&id).filter(|v| **v == *old) else { continue };
+            self.mem_bytes.fetch_add(entry_size(&id, &enc), Ordering::Relaxed);
+            self.mem_bytes.fetch_sub(entry_size(&id, v), Ordering::Relaxed);
+            *v = enc;
+            report.recompressed += 1;
+        }
+    }
+}
+let Some(wal) = &self.wal else {
+    report.live_keys = self.snapshot_view().scan_prefix(space, b"")?.count();
+    return Ok(report);
+};
+let mut w = wal.write();
+let records = w.replay_records().map_err(|e| DbError::Storage(e.to_string()))?;
+report.wal_records_before = records.len();
+let mut kept = Vec::with_capacity(records.len());
+for rec in records {
+    if WalOp::decode(&rec)?.space() != space { kept.push(rec); }
+}
+// Writes log and apply under the WAL lock, so this view holds everything logged so far
+let view = self.snapshot_view();
+for (key, val) in view.scan_prefix(space, b"")? {
+    kept.push(wal_op::put(space, &key, &val));
+    if let Some(exp) = view.expiries.get(&(space.clone(), key.clone())) {
+        kept.push(wal_op::ttl(space, &key, *exp));
+    }
+    report.live_keys += 1;
+}
+report.wal_records_after = kept.len();
+w.rewrite(&kept).map_err(|e| DbError::Storage(e.to_string()))?;
+Ok(report)
+}
+
+fn snapshot_view(&self) -> MapSnapshot {
+MapSnapshot {
+    maps: self.inner.read_all().iter().map(|m| Shard::clone(m)).collect(),
+    options: self.options.read().clone(),
+    expiries: self.expiries.read().clone(),
+    at_ms: now_ms(),
+    spill: self.spill.read().as_ref().map(|s| (s.clone(), s.index())),
+}
+}
+}
+
+/// WAL records that recreate every live key of `view`
+fn state_records(view: &MapSnapshot) -> Result<Vec<tonledb_wal::Record>> {
+let mut records = Vec::new();
+for ((space, key), val) in view.entries()? {
+    records.push(wal_op::put(&space, &key, &val));
+    if let Some(exp) = view.expiries.get(&(space.clone(), key.clone())) {
+        records.push(wal_op::ttl(&space, &key, *exp));
+    }
+}
+Ok(records)
+}
+
+/// A checksum failure during replay as `DbError::Corruption`
+fn replay_err(e: anyhow::Error) -> anyhow::Error {
+match e.downcast::<tonledb_wal::CorruptRecord>() {
+    Ok(c) => DbError::Corruption(c.to_string()).into(),
+    Err(e) => e,
+}
+}
+
+/// Frozen view of an `InMemoryStore` map; each shard shares its nodes with the live one, less those written since.
+/// Keys with a TTL count as expired if they had expired when the snapshot was taken.
+pub struct MapSnapshot {
+maps: Vec<Shard>,
+options: Arc<HashMap<Space, StorageOptions>>,
+expiries: Arc<Expiries>,
+at_ms: u64,
+spill: Option<(Arc<SpillFile>, Arc<SpillIndex>)>,
+}
+
+impl MapSnapshot {
+fn live(&self, space: &Space, key: &[u8]) -> bool {
+self.expiries.is_empty() || self.expiries.get(&(space.clone(), key.to_vec())).is_none_or(|exp| *exp > self.at_ms)
+}
+
+/// Every stored key under `prefix`, in memory or spilled, expired or not
+fn keys_with_prefix(&self, space: &Space, prefix: &[u8]) -> Vec<Vec<u8>> {
+let start = (space.clone(), prefix.to_vec());
+let mut keys = std::collections::BTreeSet::new();
+if let Some((_, index)) = &self.spill {
+    keys.extend(index.range(start.clone()..).take_while(|((s,k),_)| s==space && k.starts_with(prefix)).map(|((_, k), _)| k.clone()));
+}
+for map in &self.maps {
+    keys.extend(map.range(start.clone()..).take_while(|((s,k),_)| s==space && k.starts_with(prefix)).map(|((_, k), _)| k.clone()));
+}
+keys.into_iter().collect()
+}
+
+/// Up to `limit` live entries under `prefix` with keys after `after`, in key order
+fn scan_prefix_page(&self, space: &Space, prefix: &[u8], after: Option<&[u8]>, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+let from = match after {
+    Some(a) if a >= prefix => Bound::Excluded(a),
+    _ => Bound::Included(prefix),
+};
+self.walk(space, from, Bound::Unbounded, false, limit, |k| k.starts_with(prefix))
+}
+
+/// Up to `limit` live entries with keys in `start..end`, from either end
+fn scan_range(&self, space: &Space, start: &[u8], end: Option<&[u8]>, reverse: bool, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+self.walk(space, Bound::Included(start), end.map_or(Bound::Unbounded, Bound::Excluded), reverse, limit, |_| true)
+}
+
+/// Up to `limit` live entries of `space` between `from` and `to`, walked forwards or
+/// backwards until the first key failing `keep`. Each source only needs reading up to
+/// its first `limit` live keys: any key of the result is among them in every source
+/// holding it.
+fn walk(&self, space: &Space, from: Bound<&[u8]>, to: Bound<&[u8]>, reverse: bool, limit: usize, keep: impl Fn(&[u8]) -> bool) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+let id = |k: &[u8]| (space.clone(), k.to_vec());
+let lo = match from {
+    Bound::Included(k) => Bound::Included(id(k)),
+    Bound::Excluded(k) => Bound::Excluded(id(k)),
+    Bound::Unbounded => Bound::Included(id(b"")),
+};
+// Appending a NUL gives the first space name after this one
+let hi = match to {
+    Bound::Included(k) => Bound::Included(id(k)),
+    Bound::Excluded(k) => Bound::Excluded(id(k)),
+    Bound::Unbounded => Bound::Excluded((Space(format!("{}\0", space.0)), Vec::new())),
+};
+// `BTreeMap::range` panics on a reversed or doubly-excluded empty range
+let empty = match (&lo, &hi) {
+    (Bound::Included(a), Bound::Included(b)) => a > b,
+    (Bound::Included(a) | Bound::Excluded(a), Bound::Included(b) | Bound::Excluded(b)) => a >= b,
+    _ => false,
+};
+if empty { return Ok(Vec::new()); }
+fn ordered<'a, T: 'a>(range: impl DoubleEndedIterator<Item = T> + 'a, reverse: bool) -> Box<dyn Iterator<Item = T> + 'a> {
+    if reverse { Box::new(range.rev()) } else { Box::new(range) }
+}
+let mut rows: BTreeMap<Vec<u8>, Vec<u8>> = BTreeMap::new();
+if let Some((file, index)) = &self.spill {
+    for ((_, k), loc) in ordered(index.range((lo.clone(), hi.clone())), reverse).take_while(|((_, k), _)| keep(k)).filter(|((_, k), _)| self.live(space, k)).take(limit) {
+        rows.insert(k.clone(), file.read_at(*loc)?);
+    }
+}
+for map in &self.maps {
+    for ((_, k), v) in ordered(map.range((lo.clone(), hi.clone())), reverse).take_while(|((_, k), _)| keep(k)).filter(|((_, k), _)| self.live(space, k)).take(limit) {
+        rows.insert(k.clone(), v.clone());
+    }
+}
+ordered(rows.into_iter(), reverse).take(limit).map(|(k, v)| decode_stored(&self.options, space, v).map(|v| (k, v))).collect()
+}
+
+/// Every live entry of every space, in memory or spilled, with its value decoded
+fn entries(&self) -> Result<Map> {
+let mut rows: Map = BTreeMap::new();
+if let Some((file, index)) = &self.spill {
+    for (id, loc) in index.iter() { rows.insert(id.clone(), file.read_at(*loc)?); }
+}
+for map in &self.maps {
+    rows.extend(map.iter().map(|(id, v)| (id.clone(), v.clone())));
+}
+rows.into_iter()
+    .filter(|((s, k), _)| self.live(s, k))
+    .map(|(id, v)| decode_stored(&self.options, &id.0, v).map(|v| (id, v)))
+    .collect()
+}
+}
+
+impl ReadView for MapSnapshot {
+fn get(&self, space: &Space, key: &[u8]) -> Result<Option<Vec<u8>>> {
+if !self.live(space, key) { return Ok(None); }
+let id = (space.clone(), key.to_vec());
+let shard = &self.maps[shard_index(space, key, self.maps.len())];
+let raw = match (shard.get(&id), &self.spill) {
+    (Some(raw), _) => raw.clone(),
+    (None, Some((file, index))) => match index.get(&id) { Some(loc) => file.read_at(*loc)?, None => return Ok(None) },
+    (None, None) => return Ok(None),
+};
+Ok(Some(decode_stored(&self.options, space, raw)?))
+}
+
+fn scan_prefix(&self, space: &Space, prefix: &[u8]) -> Result<Box<dyn Iterator<Item=(Vec<u8>, Vec<u8>)> + Send>> {
+let start = (space.clone(), prefix.to_vec());
+let mut rows: BTreeMap<Vec<u8>, Vec<u8>> = BTreeMap::new();
+if let Some((file, index)) = &self.spill {
+    for ((_, k), loc) in index.range(start.clone()..).take_while(|((s,k),_)| s==space && k.starts_with(prefix)) {
+        rows.insert(k.clone(), file.read_at(*loc)?);
+    }
+}
+for map in &self.maps {
+    for ((_, k), v) in map.range(start.clone()..).take_while(|((s,k),_)| s==space && k.starts_with(prefix)) {
+        rows.insert(k.clone(), v.clone());
+    }
+}
+let v: Vec<(Vec<u8>, Vec<u8>)> = rows.into_iter()
+    .filter(|(k,_)| self.live(space, k))
+    .map(|(k,v)| decode_stored(&self.options, space, v).map(|v| (k, v)))
+    .collect::<Result<_>>()?;
 Ok(Box::new(v.into_iter()))
 }
 }
 
+fn log(wal: &mut tonledb_wal::Wal, rec: tonledb_wal::Record) -> Result<()> {
+wal.append_record(rec.kind, &rec.payload).map_err(|e| DbError::Storage(e.to_string()))
+}
+
+/// Bytes an entry accounts for against the memory budget
+fn entry_size(id: &(Space, Vec<u8>), val: &[u8]) -> usize {
+id.0.0.len() + id.1.len() + val.len()
+}
+
+/// Undo in-memory compression for values of compressed spaces
+fn decode_stored(options: &HashMap<Space, StorageOptions>, space: &Space, raw: Vec<u8>) -> Result<Vec<u8>> {
+match options.get(space) {
+    Some(o) if o.compression.is_some() => compression::decode_value(&raw),
+    _ => Ok(raw),
+}
+}
+
+fn now_ms() -> u64 {
+std::time::SystemTime::now()
+    .duration_since(std::time::UNIX_EPOCH)
+    .map(|d| d.as_millis() as u64)
+    .unwrap_or(0)
+}
+
 pub fn arc_inmem_with_wal(path: Option<&str>, cache_cap: usize) -> Arc<dyn tonledb_core::Storage> {
 match path { Some(p) => Arc::new(InMemoryStore::with_wal(p, cache_cap).unwrap()), None => Arc::new(InMemoryStore::new(cache_cap)) }
 }
\ No newline at end of file
@@ -0,0 +1,25 @@
+//! Per-space storage settings, registered with `InMemoryStore::set_space_options`.
+
+use serde::{Deserialize, Serialize};
+use tonledb_core::merge::MergeOperator;
+use crate::compression::CompressionOptions;
+
+/// Settings for one space. Spaces without registered options use the defaults:
+/// the store-wide LRU cache, no compression, no TTL, no merge operator. Encryption
+/// at rest is configured on a `crypto::EncryptedStorage` wrapping the store.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StorageOptions {
+    /// Give the space its own LRU of this many entries (0 disables caching);
+    /// `None` shares the store-wide cache.
+    #[serde(default)]
+    pub cache_capacity: Option<usize>,
+    /// Compress values in memory; the WAL keeps raw values.
+    #[serde(default)]
+    pub compression: Option<CompressionOptions>,
+    /// Expire keys this many milliseconds after their last write.
+    #[serde(default)]
+    pub default_ttl_ms: Option<u64>,
+    /// Operator applied by `Storage::merge`; merges into a space without one are rejected.
+    #[serde(default)]
+    pub merge_operator: Option<MergeOperator>,
+}
@@ -0,0 +1,283 @@
+//! Replicated storage with tunable quorum reads/writes and read repair.
+//!
+//! Every value is written to each replica with a version header so that a
+//! quorum read can tell which copy is newest. Replicas that answered with an
+//! older copy (or none) are repaired in the background of the read path.
+//!
+//! Replicas are called in turn on the requesting thread, so they see the request's
+//! deadline (see `tonledb_core::deadline`); once it passes no further replica is
+//! contacted and read repair is skipped.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tonledb_core::{deadline, Consistency, DbError, Result, Space, Storage};
+
+const TAG_VALUE: u8 = 0;
+const TAG_TOMBSTONE: u8 = 1;
+const HEADER_LEN: usize = 9;
+
+/// A versioned copy of a key as stored on one replica
+#[derive(Debug, Clone, PartialEq)]
+struct Versioned {
+    version: u64,
+    value: Option<Vec<u8>>, // None = tombstone
+}
+
+impl Versioned {
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_LEN + self.value.as_ref().map_or(0, |v| v.len()));
+        out.extend_from_slice(&self.version.to_be_bytes());
+        match &self.value {
+            Some(v) => { out.push(TAG_VALUE); out.extend_from_slice(v); }
+            None => out.push(TAG_TOMBSTONE),
+        }
+        out
+    }
+
+    fn decode(raw: &[u8]) -> Result<Self> {
+        if raw.len() < HEADER_LEN {
+            return Err(DbError::Storage("replica value missing version header".into()));
+        }
+        let version = u64::from_be_bytes(raw[..8].try_into().unwrap());
+        let value = match raw[8] {
+            TAG_VALUE => Some(raw[HEADER_LEN..].to_vec()),
+            TAG_TOMBSTONE => None,
+            t => return Err(DbError::Storage(format!("unknown replica value tag {}", t))),
+        };
+        Ok(Self { version, value })
+    }
+}
+
+/// Storage fanning out to several replicas with per-request consistency.
+pub struct ReplicatedStore {
+    replicas: Vec<Arc<dyn Storage>>,
+    default_read: Consistency,
+    default_write: Consistency,
+    clock: AtomicU64,
+}
+
+impl ReplicatedStore {
+    pub fn new(replicas: Vec<Arc<dyn Storage>>, default_read: Consistency, default_write: Consistency) -> Result<Self> {
+        if replicas.is_empty() {
+            return Err(DbError::Invalid("replicated store needs at least one replica".into()));
+        }
+        Ok(Self { replicas, default_read, default_write, clock: AtomicU64::new(0) })
+    }
+
+    /// Next write version: wall-clock microseconds, forced to be strictly increasing.
+    fn next_version(&self) -> u64 {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or(0);
+        let mut cur = self.clock.load(Ordering::Relaxed);
+        loop {
+            let next = now.max(cur + 1);
+            match self.clock.compare_exchange_weak(cur, next, Ordering::SeqCst, Ordering::Relaxed) {
+                Ok(_) => return next,
+                Err(actual) => cur = actual,
+            }
+        }
+    }
+
+    fn write_all(&self, space: &Space, key: Vec<u8>, rec: Versioned, ttl_ms: Option<u64>, consistency: Consistency) -> Result<()> {
+        let needed = consistency.required(self.replicas.len());
+        let encoded = rec.encode();
+        let mut acks = 0;
+        let mut last_err = None;
+        for r in &self.replicas {
+            deadline::check()?;
+            let res = match ttl_ms {
+                Some(ttl) => r.put_with_ttl(space, key.clone(), encoded.clone(), ttl),
+                None => r.put(space, key.clone(), encoded.clone()),
+            };
+            match res {
+                Ok(()) => acks += 1,
+                Err(e) => last_err = Some(e),
+            }
+        }
+        if acks < needed {
+            return Err(DbError::Storage(format!(
+                "write quorum not reached: {} of {} acks ({})",
+                acks, needed,
+                last_err.map(|e| e.to_string()).unwrap_or_default()
+            )));
+        }
+        Ok(())
+    }
+
+    fn read_quorum(&self, space: &Space, key: &[u8], consistency: Consistency) -> Result<Option<Vec<u8>>> {
+        let needed = consistency.required(self.replicas.len());
+        let mut answers: Vec<(usize, Option<Versioned>)> = Vec::new();
+        for (i, r) in self.replicas.iter().enumerate() {
+            if answers.len() >= needed {
+                break;
+            }
+            deadline::check()?;
+            if let Ok(raw) = r.get(space, key) {
+                let copy = match raw {
+                    Some(b) => Some(Versioned::decode(&b)?),
+                    None => None,
+                };
+                answers.push((i, copy));
+            }
+        }
+        if answers.len() < needed {
+            return Err(DbError::Storage(format!("read quorum not reached: {} of {} replicas answered", answers.len(), needed)));
+        }
+
+        let newest = answers.iter().filter_map(|(_, c)| c.clone()).max_by_key(|c| c.version);
+        if let Some(newest) = newest.as_ref().filter(|_| !deadline::expired()) {
+            // Read repair: bring stale replicas that took part in the read up to date
+            let encoded = newest.encode();
+            for (i, copy) in &answers {
+                if copy.as_ref().map(|c| c.version) != Some(newest.version) {
+                    let _ = self.replicas[*i].put(space, key.to_vec(), encoded.clone());
+                }
+            }
+        }
+        Ok(newest.and_then(|c| c.value))
+    }
+
+    /// Up to `limit` live rows merged from ordered pages of every replica, keeping
+    /// the newest copy of each key. `fetch` reads a replica's page of `limit` past
+    /// `cursor`; tombstones take room in a page, so this may need several rounds.
+    fn merge_pages(
+        &self,
+        limit: usize,
+        reverse: bool,
+        mut cursor: Option<Vec<u8>>,
+        fetch: impl Fn(&dyn Storage, Option<&[u8]>) -> Result<Vec<(Vec<u8>, Vec<u8>)>>,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let needed = self.default_read.required(self.replicas.len());
+        let mut out = Vec::new();
+        while out.len() < limit {
+            let mut merged: BTreeMap<Vec<u8>, Versioned> = BTreeMap::new();
+            let mut answered = 0;
+            // Past the last key of a full page, that replica hasn't been read yet
+            let mut boundary: Option<Vec<u8>> = None;
+            for r in &self.replicas {
+                let Ok(page) = fetch(r.as_ref(), cursor.as_deref()) else { continue };
+                answered += 1;
+                let further = |last: &Vec<u8>| boundary.as_ref().is_none_or(|b| if reverse { last > b } else { last < b });
+                if let Some((last, _)) = page.last().filter(|(last, _)| page.len() == limit && further(last)) {
+                    boundary = Some(last.clone());
+                }
+                for (k, raw) in page {
+                    let copy = Versioned::decode(&raw)?;
+                    match merged.get(&k) {
+                        Some(cur) if cur.version >= copy.version => {}
+                        _ => { merged.insert(k, copy); }
+                    }
+                }
+            }
+            if answered < needed {
+                return Err(DbError::Storage(format!("read quorum not reached: {} of {} replicas answered", answered, needed)));
+            }
+            let read = |k: &[u8]| boundary.as_deref().is_none_or(|b| if reverse { k >= b } else { k <= b });
+            let mut rows: Vec<_> = merged.into_iter().collect();
+            if reverse { rows.reverse(); }
+            for (k, copy) in rows.into_iter().take_while(|(k, _)| read(k)) {
+                if let Some(v) = copy.value {
+                    out.push((k, v));
+                    if out.len() == limit {
+                        break;
+                    }
+                }
+            }
+            match boundary {
+                Some(b) => cursor = Some(b),
+                None => break,
+            }
+        }
+        Ok(out)
+    }
+}
+
+impl Storage for ReplicatedStore {
+    fn get(&self, space: &Space, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.read_quorum(space, key, self.default_read)
+    }
+
+    fn put(&self, space: &Space, key: Vec<u8>, val: Vec<u8>) -> Result<()> {
+        self.put_with(space, key, val, self.default_write)
+    }
+
+    fn del(&self, space: &Space, key: &[u8]) -> Result<()> {
+        self.del_with(space, key, self.default_write)
+    }
+
+    fn scan_prefix(&self, space: &Space, prefix: &[u8]) -> Result<Box<dyn Iterator<Item=(Vec<u8>, Vec<u8>)> + Send>> {
+        // Merge all reachable replicas, keeping the newest copy of each key
+        let mut merged: BTreeMap<Vec<u8>, Versioned> = BTreeMap::new();
+        let mut answered = 0;
+        for r in &self.replicas {
+            let Ok(it) = r.scan_prefix(space, prefix) else { continue };
+            answered += 1;
+            for (k, raw) in it {
+                let copy = Versioned::decode(&raw)?;
+                match merged.get(&k) {
+                    Some(cur) if cur.version >= copy.version => {}
+                    _ => { merged.insert(k, copy); }
+                }
+            }
+        }
+        let needed = self.default_read.required(self.replicas.len());
+        if answered < needed {
+            return Err(DbError::Storage(format!("read quorum not reached: {} of {} replicas answered", answered, needed)));
+        }
+        Ok(Box::new(merged.into_iter().filter_map(|(k, c)| c.value.map(|v| (k, v)))))
+    }
+
+    fn scan_prefix_page(&self, space: &Space, prefix: &[u8], after: Option<&[u8]>, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.merge_pages(limit, false, after.map(<[u8]>::to_vec), |r, cursor| r.scan_prefix_page(space, prefix, cursor, limit))
+    }
+
+    fn scan_range(&self, space: &Space, start: &[u8], end: Option<&[u8]>, reverse: bool, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.merge_pages(limit, reverse, None, |r, cursor| match cursor {
+            None => r.scan_range(space, start, end, reverse, limit),
+            // The range resumes just past the cursor: before it, or at its successor
+            Some(c) if reverse => r.scan_range(space, start, Some(c), true, limit),
+            Some(c) => r.scan_range(space, &[c, &[0]].concat(), end, false, limit),
+        })
+    }
+
+    fn get_with(&self, space: &Space, key: &[u8], consistency: Consistency) -> Result<Option<Vec<u8>>> {
+        self.read_quorum(space, key, consistency)
+    }
+
+    fn put_with(&self, space: &Space, key: Vec<u8>, val: Vec<u8>, consistency: Consistency) -> Result<()> {
+        let rec = Versioned { version: self.next_version(), value: Some(val) };
+        self.write_all(space, key, rec, None, consistency)
+    }
+
+    fn del_with(&self, space: &Space, key: &[u8], consistency: Consistency) -> Result<()> {
+        // Tombstones keep read repair from resurrecting deleted keys
+        let rec = Versioned { version: self.next_version(), value: None };
+        self.write_all(space, key.to_vec(), rec, None, consistency)
+    }
+
+    /// Each replica expires its copy independently; all share the same deadline.
+    fn put_with_ttl(&self, space: &Space, key: Vec<u8>, val: Vec<u8>, ttl_ms: u64) -> Result<()> {
+        let rec = Versioned { version: self.next_version(), value: Some(val) };
+        self.write_all(space, key, rec, Some(ttl_ms), self.default_write)
+    }
+
+    /// From the first replica that answers; every replica is written the same TTL
+    fn expires_at(&self, space: &Space, key: &[u8]) -> Result<Option<u64>> {
+        let mut last_err = None;
+        for r in &self.replicas {
+            match r.expires_at(space, key) {
+                Ok(at) => return Ok(at),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(last_err.expect("at least one replica"))
+    }
+
+    fn sweep_expired(&self) -> Result<usize> {
+        // Unreachable replicas sweep when they come back
+        Ok(self.replicas.iter().filter_map(|r| r.sweep_expired().ok()).sum())
+    }
+}
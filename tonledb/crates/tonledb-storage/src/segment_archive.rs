@@ -0,0 +1,33 @@
+//! Shipping sealed WAL segments to object storage.
+//!
+//! `ObjectStoreArchiver` is a `tonledb_wal::SegmentArchiver` that uploads each
+//! segment as `<prefix><seq>.seg` to an `ObjectStore`: a local directory or mounted
+//! bucket, or S3 and S3-compatible storage with the `s3` feature of `tonledb-core`.
+//! Uploads run on the WAL's archiver thread after a segment is sealed, so writers
+//! never wait on the network; one that fails stays pending and is retried on the
+//! next rotation.
+
+use std::path::Path;
+use std::sync::Arc;
+use tonledb_core::object_store::ObjectStore;
+use tonledb_wal::SegmentArchiver;
+
+pub struct ObjectStoreArchiver {
+    store: Arc<dyn ObjectStore>,
+    prefix: String,
+}
+
+impl ObjectStoreArchiver {
+    /// Upload segments to `store` under `prefix` (e.g. `wal/`)
+    pub fn new(store: Arc<dyn ObjectStore>, prefix: &str) -> Self {
+        Self { store, prefix: prefix.to_string() }
+    }
+}
+
+impl SegmentArchiver for ObjectStoreArchiver {
+    fn archive(&mut self, seq: u64, segment: &Path) -> anyhow::Result<()> {
+        // Named like `CopyArchiver`'s copies; an object is replaced whole, so a retry is harmless
+        self.store.put(&format!("{}{:08}.seg", self.prefix, seq), std::fs::read(segment)?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,60 @@
+//! Lock striping for `InMemoryStore`.
+//!
+//! Per-key state is split into shards by a hash of (space, key), each behind its
+//! own lock, so writers to different keys don't serialize on one lock. Every write
+//! touches exactly one shard, so holding the read locks of all shards at once
+//! (see `read_all`) observes a consistent cut of the store.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::collections::BTreeMap;
+use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use tonledb_core::Space;
+
+/// Shards used by `InMemoryStore::new` and `with_wal`
+pub const DEFAULT_SHARDS: usize = 16;
+
+pub(crate) struct Shards<T> {
+    shards: Box<[RwLock<T>]>,
+}
+
+impl<T> Shards<T> {
+    /// `n` shards (at least one), each initialized by `init`
+    pub(crate) fn new(n: usize, mut init: impl FnMut() -> T) -> Self {
+        Self { shards: (0..n.max(1)).map(|_| RwLock::new(init())).collect() }
+    }
+
+    /// The shard that owns `(space, key)`
+    pub(crate) fn of(&self, space: &Space, key: &[u8]) -> &RwLock<T> {
+        &self.shards[self.index(space, key)]
+    }
+
+    /// Index of the shard that owns `(space, key)`
+    pub(crate) fn index(&self, space: &Space, key: &[u8]) -> usize {
+        shard_index(space, key, self.shards.len())
+    }
+
+    /// Write-lock the shards owning `ids`, keyed by index. They are taken in index
+    /// order, so callers locking overlapping sets cannot deadlock.
+    pub(crate) fn write_many<'a>(&self, ids: impl IntoIterator<Item = &'a (Space, Vec<u8>)>) -> BTreeMap<usize, RwLockWriteGuard<'_, T>> {
+        let wanted: std::collections::BTreeSet<usize> = ids.into_iter().map(|(space, key)| self.index(space, key)).collect();
+        wanted.into_iter().map(|i| (i, self.shards[i].write())).collect()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &RwLock<T>> {
+        self.shards.iter()
+    }
+
+    /// Read-lock every shard, in order
+    pub(crate) fn read_all(&self) -> Vec<RwLockReadGuard<'_, T>> {
+        self.shards.iter().map(|s| s.read()).collect()
+    }
+}
+
+/// Shard of `(space, key)` among `n`
+pub(crate) fn shard_index(space: &Space, key: &[u8], n: usize) -> usize {
+    let mut h = DefaultHasher::new();
+    space.0.hash(&mut h);
+    key.hash(&mut h);
+    (h.finish() % n as u64) as usize
+}
@@ -0,0 +1,125 @@
+//! Disk overflow for `InMemoryStore` when it runs over its memory budget.
+//!
+//! Spilled values are appended to a single file and located through an in-memory
+//! index of offsets. The file is scratch space, not a durability mechanism: it is
+//! truncated when opened, and the WAL remains the source of truth on restart.
+//! Space held by overwritten or deleted entries is not reclaimed until reopen.
+//! Each entry's CRC32 is kept in the index and checked when it is read back.
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use parking_lot::{Mutex, RwLock};
+use tonledb_core::{DbError, Result, Space};
+
+/// Spilled key -> where its value lives in the file
+pub(crate) type SpillIndex = BTreeMap<(Space, Vec<u8>), SpillLoc>;
+/// A key and its stored value, as moved out of the live map
+pub(crate) type SpillEntry = ((Space, Vec<u8>), Vec<u8>);
+
+/// What `InMemoryStore` does when a write would exceed its memory budget
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OnFull {
+    /// Fail the write with `DbError::ResourceExhausted`
+    Reject,
+    /// Move cold entries (those outside the LRU cache) to an overflow file at `path`
+    Spill { path: PathBuf },
+}
+
+/// Memory budget for the values held by an `InMemoryStore`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryLimit {
+    /// Budget for space names, keys and values in the live map, in bytes
+    pub max_bytes: usize,
+    pub on_full: OnFull,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SpillLoc {
+    offset: u64,
+    len: u32,
+    crc: u32,
+}
+
+pub(crate) struct SpillFile {
+    path: PathBuf,
+    file: Mutex<File>,
+    len: Mutex<u64>,
+    index: RwLock<Arc<SpillIndex>>,
+}
+
+impl SpillFile {
+    pub(crate) fn create(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new().create(true).read(true).write(true).truncate(true).open(path)
+            .map_err(|e| DbError::Storage(format!("spill file {}: {}", path.display(), e)))?;
+        Ok(Self { path: path.to_path_buf(), file: Mutex::new(file), len: Mutex::new(0), index: RwLock::new(Arc::new(BTreeMap::new())) })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append `entries` and index them
+    pub(crate) fn write(&self, entries: Vec<SpillEntry>) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let mut file = self.file.lock();
+        let mut len = self.len.lock();
+        file.seek(SeekFrom::Start(*len)).map_err(io_err)?;
+        let mut buf = Vec::new();
+        let mut located = Vec::with_capacity(entries.len());
+        for (id, val) in entries {
+            located.push((id, SpillLoc { offset: *len + buf.len() as u64, len: val.len() as u32, crc: crc32fast::hash(&val) }));
+            buf.extend_from_slice(&val);
+        }
+        file.write_all(&buf).map_err(io_err)?;
+        *len += buf.len() as u64;
+        let mut index = self.index.write();
+        let index = Arc::make_mut(&mut *index);
+        for (id, loc) in located {
+            index.insert(id, loc);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn remove(&self, id: &(Space, Vec<u8>)) {
+        if self.index.read().contains_key(id) {
+            Arc::make_mut(&mut *self.index.write()).remove(id);
+        }
+    }
+
+    pub(crate) fn get(&self, id: &(Space, Vec<u8>)) -> Result<Option<Vec<u8>>> {
+        match self.index.read().get(id) {
+            Some(loc) => self.read_at(*loc).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Frozen copy of the index; entries it points at are never rewritten
+    pub(crate) fn index(&self) -> Arc<SpillIndex> {
+        self.index.read().clone()
+    }
+
+    pub(crate) fn read_at(&self, loc: SpillLoc) -> Result<Vec<u8>> {
+        let mut file = self.file.lock();
+        file.seek(SeekFrom::Start(loc.offset)).map_err(io_err)?;
+        let mut val = vec![0u8; loc.len as usize];
+        file.read_exact(&mut val).map_err(io_err)?;
+        if crc32fast::hash(&val) != loc.crc {
+            return Err(DbError::Corruption(format!("spill file {}: checksum mismatch at byte {}", self.path.display(), loc.offset)));
+        }
+        Ok(val)
+    }
+
+    /// Number of spilled entries
+    pub(crate) fn len(&self) -> usize {
+        self.index.read().len()
+    }
+}
+
+fn io_err(e: std::io::Error) -> DbError {
+    DbError::Storage(format!("spill file: {}", e))
+}
@@ -0,0 +1,441 @@
+//! Hot/cold tiering: recently accessed keys live in the wrapped (hot) store, keys
+//! idle for longer than `TierPolicy::idle_ms` are demoted to an `ObjectStore` (a
+//! local directory or a mounted bucket) and promoted back on their next read.
+//!
+//! Only spaces registered with `with_space` are tiered. Demotion runs when
+//! `demote_idle` is called, typically from a background task. Access times are
+//! kept in memory, so after a restart every hot key counts as accessed at open.
+//! Versioned reads/writes, TTLs and snapshots go to the hot tier only.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ops::Bound;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use parking_lot::RwLock;
+use tonledb_core::object_store::ObjectStore;
+use tonledb_core::transaction::WriteSet;
+use tonledb_core::{CacheStats, CompactionReport, Consistency, DbError, ReadView, Result, Space, Storage};
+use crate::shard::Shards;
+use crate::DEFAULT_SHARDS;
+
+/// Object path prefix of demoted values
+const TIER_PREFIX: &str = "tier/";
+
+#[derive(Debug, Clone, Copy)]
+pub struct TierPolicy {
+    /// Keys not read or written for this long are demoted by `demote_idle`
+    pub idle_ms: u64,
+}
+
+impl Default for TierPolicy {
+    fn default() -> Self {
+        Self { idle_ms: 3_600_000 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TierStats {
+    pub cold_keys: usize,
+    pub demotions: u64,
+    pub promotions: u64,
+}
+
+/// Storage wrapper moving idle keys of tiered spaces to a cold object store
+pub struct TieredStorage<S: Storage> {
+    inner: S,
+    cold: Arc<dyn ObjectStore>,
+    policy: TierPolicy,
+    spaces: HashSet<Space>,
+    /// Last access (epoch ms) per hot key; the shard lock also serializes
+    /// promotion, demotion and writes of the same key
+    access: Shards<HashMap<(Space, Vec<u8>), u64>>,
+    /// Keys whose value currently lives in the cold tier
+    cold_keys: RwLock<BTreeSet<(Space, Vec<u8>)>>,
+    opened_ms: u64,
+    demotions: AtomicU64,
+    promotions: AtomicU64,
+}
+
+impl<S: Storage> TieredStorage<S> {
+    /// Wrap `inner`, picking up values already demoted to `cold`
+    pub fn new(inner: S, cold: Arc<dyn ObjectStore>, policy: TierPolicy) -> Result<Self> {
+        let mut cold_keys = BTreeSet::new();
+        for path in cold.list(TIER_PREFIX)? {
+            match parse_path(&path) {
+                Some(k) => { cold_keys.insert(k); }
+                None => return Err(DbError::Storage(format!("unexpected object in cold tier: {}", path))),
+            }
+        }
+        Ok(Self {
+            inner,
+            cold,
+            policy,
+            spaces: HashSet::new(),
+            access: Shards::new(DEFAULT_SHARDS, HashMap::new),
+            cold_keys: RwLock::new(cold_keys),
+            opened_ms: now_ms(),
+            demotions: AtomicU64::new(0),
+            promotions: AtomicU64::new(0),
+        })
+    }
+
+    /// Tier `space`; other spaces always stay in the hot store
+    pub fn with_space(mut self, space: Space) -> Self {
+        self.spaces.insert(space);
+        self
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn stats(&self) -> TierStats {
+        TierStats {
+            cold_keys: self.cold_keys.read().len(),
+            demotions: self.demotions.load(Ordering::Relaxed),
+            promotions: self.promotions.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Whether the value of `key` currently lives in the cold tier
+    pub fn is_cold(&self, space: &Space, key: &[u8]) -> bool {
+        self.cold_keys.read().contains(&(space.clone(), key.to_vec()))
+    }
+
+    /// Demote every key of a tiered space idle for longer than the policy allows.
+    /// Returns the number of keys moved to the cold tier.
+    pub fn demote_idle(&self) -> Result<usize> {
+        let cutoff = now_ms().saturating_sub(self.policy.idle_ms);
+        let mut demoted = 0;
+        for space in &self.spaces {
+            let keys: Vec<Vec<u8>> = self.inner.scan_prefix(space, b"")?.map(|(k, _)| k).collect();
+            for key in keys {
+                let id = (space.clone(), key);
+                let mut access = self.access.of(&id.0, &id.1).write();
+                // Re-checked under the lock: a concurrent read may just have touched it
+                if access.get(&id).copied().unwrap_or(self.opened_ms) >= cutoff {
+                    continue;
+                }
+                let Some(val) = self.inner.get(&id.0, &id.1)? else { continue };
+                // Object first, so a crash in between leaves the value in both tiers
+                self.cold.put(&object_path(&id.0, &id.1), val)?;
+                self.cold_keys.write().insert(id.clone());
+                self.inner.del(&id.0, &id.1)?;
+                access.remove(&id);
+                demoted += 1;
+            }
+        }
+        self.demotions.fetch_add(demoted as u64, Ordering::Relaxed);
+        Ok(demoted)
+    }
+
+    fn tiered(&self, space: &Space) -> bool {
+        self.spaces.contains(space)
+    }
+
+    fn touch(&self, space: &Space, key: &[u8]) {
+        self.access.of(space, key).write().insert((space.clone(), key.to_vec()), now_ms());
+    }
+
+    /// Move a cold value back to the hot store
+    fn promote(&self, space: &Space, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let id = (space.clone(), key.to_vec());
+        let mut access = self.access.of(space, key).write();
+        // A writer or another reader may have won the race for the lock
+        if let Some(v) = self.inner.get(space, key)? {
+            access.insert(id, now_ms());
+            return Ok(Some(v));
+        }
+        if !self.cold_keys.read().contains(&id) {
+            return Ok(None);
+        }
+        let path = object_path(space, key);
+        let Some(val) = self.cold.get(&path)? else {
+            self.cold_keys.write().remove(&id);
+            return Ok(None);
+        };
+        self.inner.put(space, key.to_vec(), val.clone())?;
+        self.cold_keys.write().remove(&id);
+        self.cold.delete(&path)?;
+        access.insert(id, now_ms());
+        self.promotions.fetch_add(1, Ordering::Relaxed);
+        Ok(Some(val))
+    }
+
+    /// Drop the cold copy of a key being overwritten or deleted
+    fn forget_cold(&self, space: &Space, key: &[u8]) -> Result<()> {
+        let id = (space.clone(), key.to_vec());
+        if self.cold_keys.read().contains(&id) {
+            self.cold.delete(&object_path(space, key))?;
+            self.cold_keys.write().remove(&id);
+        }
+        Ok(())
+    }
+
+    /// Write-path wrapper: runs `write` under the key's lock and keeps the tiers consistent
+    fn write_key(&self, space: &Space, key: &[u8], deleted: bool, write: impl FnOnce() -> Result<()>) -> Result<()> {
+        if !self.tiered(space) {
+            return write();
+        }
+        let mut access = self.access.of(space, key).write();
+        write()?;
+        self.forget_cold(space, key)?;
+        let id = (space.clone(), key.to_vec());
+        if deleted { access.remove(&id); } else { access.insert(id, now_ms()); }
+        Ok(())
+    }
+
+    fn cold_in_prefix(&self, space: &Space, prefix: &[u8]) -> Vec<Vec<u8>> {
+        self.cold_keys
+            .read()
+            .range((space.clone(), prefix.to_vec())..)
+            .take_while(|(s, k)| s == space && k.starts_with(prefix))
+            .map(|(_, k)| k.clone())
+            .collect()
+    }
+
+    /// The first `limit` cold keys of a `scan_prefix_page`
+    fn cold_page(&self, space: &Space, prefix: &[u8], after: Option<&[u8]>, limit: usize) -> Vec<Vec<u8>> {
+        let from = match after {
+            Some(a) if a >= prefix => Bound::Excluded((space.clone(), a.to_vec())),
+            _ => Bound::Included((space.clone(), prefix.to_vec())),
+        };
+        self.cold_keys
+            .read()
+            .range((from, Bound::Unbounded))
+            .take_while(|(s, k)| s == space && k.starts_with(prefix))
+            .take(limit)
+            .map(|(_, k)| k.clone())
+            .collect()
+    }
+
+    /// The first `limit` cold keys of a `scan_range`, in its order
+    fn cold_range(&self, space: &Space, start: &[u8], end: Option<&[u8]>, reverse: bool, limit: usize) -> Vec<Vec<u8>> {
+        if end.is_some_and(|e| e < start) {
+            return Vec::new();
+        }
+        let keys = self.cold_keys.read();
+        let to = end.map_or(Bound::Unbounded, |e| Bound::Excluded((space.clone(), e.to_vec())));
+        let range = keys.range((Bound::Included((space.clone(), start.to_vec())), to));
+        let in_space = |(s, _): &&(Space, Vec<u8>)| s == space;
+        let keys: Box<dyn Iterator<Item = &(Space, Vec<u8>)>> = if reverse {
+            // Without an end the range runs on into the following spaces
+            Box::new(range.rev().skip_while(|e| !in_space(e)).take_while(in_space))
+        } else {
+            Box::new(range.take_while(in_space))
+        };
+        keys.take(limit).map(|(_, k)| k.clone()).collect()
+    }
+
+    /// The first `limit` rows of hot `rows` and `cold` keys merged in scan order;
+    /// cold values are read in place, as in `scan_prefix`
+    fn merge_cold(&self, space: &Space, rows: Vec<(Vec<u8>, Vec<u8>)>, cold: Vec<Vec<u8>>, reverse: bool, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut merged: BTreeMap<Vec<u8>, Option<Vec<u8>>> = rows.into_iter().map(|(k, v)| (k, Some(v))).collect();
+        for key in cold {
+            merged.entry(key).or_insert(None);
+        }
+        let mut out = Vec::with_capacity(limit.min(64));
+        let mut merged: Vec<_> = merged.into_iter().collect();
+        if reverse { merged.reverse(); }
+        for (key, val) in merged {
+            if out.len() == limit {
+                break;
+            }
+            let val = match val {
+                Some(v) => Some(v),
+                None => self.cold.get(&object_path(space, &key))?,
+            };
+            if let Some(v) = val {
+                out.push((key, v));
+            }
+        }
+        Ok(out)
+    }
+}
+
+impl<S: Storage> Storage for TieredStorage<S> {
+    fn get(&self, space: &Space, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if !self.tiered(space) {
+            return self.inner.get(space, key);
+        }
+        match self.inner.get(space, key)? {
+            Some(v) => {
+                self.touch(space, key);
+                Ok(Some(v))
+            }
+            None => self.promote(space, key),
+        }
+    }
+
+    fn put(&self, space: &Space, key: Vec<u8>, val: Vec<u8>) -> Result<()> {
+        let k = key.clone();
+        self.write_key(space, &k, false, || self.inner.put(space, key, val))
+    }
+
+    fn del(&self, space: &Space, key: &[u8]) -> Result<()> {
+        self.write_key(space, key, true, || self.inner.del(space, key))
+    }
+
+    /// Merges hot and cold values; cold values are read in place, not promoted,
+    /// so a scan does not pull a whole space back into memory
+    fn scan_prefix(&self, space: &Space, prefix: &[u8]) -> Result<Box<dyn Iterator<Item=(Vec<u8>, Vec<u8>)> + Send>> {
+        if !self.tiered(space) {
+            return self.inner.scan_prefix(space, prefix);
+        }
+        let mut rows: BTreeMap<Vec<u8>, Vec<u8>> = self.inner.scan_prefix(space, prefix)?.collect();
+        for key in self.cold_in_prefix(space, prefix) {
+            if rows.contains_key(&key) {
+                continue;
+            }
+            if let Some(v) = self.cold.get(&object_path(space, &key))? {
+                rows.insert(key, v);
+            }
+        }
+        Ok(Box::new(rows.into_iter()))
+    }
+
+    /// A page of the hot store merged with a page of the cold keys
+    fn scan_prefix_page(&self, space: &Space, prefix: &[u8], after: Option<&[u8]>, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let rows = self.inner.scan_prefix_page(space, prefix, after, limit)?;
+        if !self.tiered(space) {
+            return Ok(rows);
+        }
+        self.merge_cold(space, rows, self.cold_page(space, prefix, after, limit), false, limit)
+    }
+
+    fn scan_range(&self, space: &Space, start: &[u8], end: Option<&[u8]>, reverse: bool, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let rows = self.inner.scan_range(space, start, end, reverse, limit)?;
+        if !self.tiered(space) {
+            return Ok(rows);
+        }
+        self.merge_cold(space, rows, self.cold_range(space, start, end, reverse, limit), reverse, limit)
+    }
+
+    fn merge(&self, space: &Space, key: Vec<u8>, operand: Vec<u8>) -> Result<()> {
+        if self.tiered(space) {
+            // The operator needs the current value in the hot store
+            self.promote(space, &key)?;
+        }
+        let k = key.clone();
+        self.write_key(space, &k, false, || self.inner.merge(space, key, operand))
+    }
+
+    fn delete_prefix(&self, space: &Space, prefix: &[u8]) -> Result<usize> {
+        let mut removed = self.inner.delete_prefix(space, prefix)?;
+        if self.tiered(space) {
+            for key in self.cold_in_prefix(space, prefix) {
+                let mut access = self.access.of(space, &key).write();
+                self.forget_cold(space, &key)?;
+                access.remove(&(space.clone(), key));
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn get_versioned(&self, space: &Space, key: &[u8], version: u64) -> Result<Option<Vec<u8>>> {
+        self.inner.get_versioned(space, key, version)
+    }
+
+    fn put_versioned(&self, space: &Space, key: Vec<u8>, val: Vec<u8>, version: u64) -> Result<()> {
+        self.inner.put_versioned(space, key, val, version)
+    }
+
+    fn del_versioned(&self, space: &Space, key: &[u8], version: u64) -> Result<()> {
+        self.inner.del_versioned(space, key, version)
+    }
+
+    fn commit_writes(&self, writes: &WriteSet, version: u64) -> Result<()> {
+        self.inner.commit_writes(writes, version)
+    }
+
+    fn latest_version(&self, space: &Space, key: &[u8]) -> Result<Option<u64>> {
+        self.inner.latest_version(space, key)
+    }
+
+    fn gc_versions(&self, oldest_active: u64) -> Result<usize> {
+        self.inner.gc_versions(oldest_active)
+    }
+
+    fn get_with(&self, space: &Space, key: &[u8], consistency: Consistency) -> Result<Option<Vec<u8>>> {
+        match self.inner.get_with(space, key, consistency)? {
+            None if self.tiered(space) => self.promote(space, key),
+            v => Ok(v),
+        }
+    }
+
+    fn put_with(&self, space: &Space, key: Vec<u8>, val: Vec<u8>, consistency: Consistency) -> Result<()> {
+        let k = key.clone();
+        self.write_key(space, &k, false, || self.inner.put_with(space, key, val, consistency))
+    }
+
+    fn del_with(&self, space: &Space, key: &[u8], consistency: Consistency) -> Result<()> {
+        self.write_key(space, key, true, || self.inner.del_with(space, key, consistency))
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn ReadView>> {
+        self.inner.snapshot()
+    }
+
+    fn put_with_ttl(&self, space: &Space, key: Vec<u8>, val: Vec<u8>, ttl_ms: u64) -> Result<()> {
+        let k = key.clone();
+        self.write_key(space, &k, false, || self.inner.put_with_ttl(space, key, val, ttl_ms))
+    }
+
+    fn expires_at(&self, space: &Space, key: &[u8]) -> Result<Option<u64>> {
+        self.inner.expires_at(space, key)
+    }
+
+    fn sweep_expired(&self) -> Result<usize> {
+        self.inner.sweep_expired()
+    }
+
+    fn compact_space(&self, space: &Space) -> Result<CompactionReport> {
+        self.inner.compact_space(space)
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.inner.cache_stats()
+    }
+
+    fn is_cached(&self, space: &Space, key: &[u8]) -> bool {
+        self.inner.is_cached(space, key)
+    }
+}
+
+// ---------- helpers ----------
+
+/// `tier/<hex space>/<hex key>`: hex keeps arbitrary bytes valid as object paths
+fn object_path(space: &Space, key: &[u8]) -> String {
+    format!("{}{}/{}", TIER_PREFIX, hex(space.0.as_bytes()), hex(key))
+}
+
+fn parse_path(path: &str) -> Option<(Space, Vec<u8>)> {
+    let (space, key) = path.strip_prefix(TIER_PREFIX)?.split_once('/')?;
+    Some((Space(String::from_utf8(unhex(space)?).ok()?), unhex(key)?))
+}
+
+/// Lowercase hex, with `-` standing in for no bytes (object paths have no empty segments)
+fn hex(bytes: &[u8]) -> String {
+    if bytes.is_empty() {
+        return "-".into();
+    }
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if s == "-" {
+        return Some(Vec::new());
+    }
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
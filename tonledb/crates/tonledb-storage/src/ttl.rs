@@ -0,0 +1,48 @@
+//! Background removal of expired keys.
+//!
+//! Expired keys already read as absent; the sweeper reclaims their memory (and
+//! spill-file index entries) by calling `Storage::sweep_expired` on an interval.
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tonledb_core::Storage;
+
+/// Handle to a sweeper thread; the thread stops when the handle is dropped.
+pub struct TtlSweeper {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl TtlSweeper {
+    /// Sweep `storage` every `interval` on a dedicated thread
+    pub fn spawn(storage: Arc<dyn Storage>, interval: Duration) -> Self {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let stop2 = stop.clone();
+        let handle = std::thread::Builder::new()
+            .name("tonledb-ttl-sweeper".into())
+            .spawn(move || loop {
+                let (lock, cv) = &*stop2;
+                let stopped = cv.wait_timeout_while(lock.lock().unwrap(), interval, |s| !*s).unwrap().0;
+                if *stopped {
+                    return;
+                }
+                drop(stopped);
+                // A failed sweep is retried on the next tick
+                let _ = storage.sweep_expired();
+            })
+            .expect("spawn ttl sweeper");
+        Self { stop, handle: Some(handle) }
+    }
+}
+
+impl Drop for TtlSweeper {
+    fn drop(&mut self) {
+        let (lock, cv) = &*self.stop;
+        *lock.lock().unwrap() = true;
+        cv.notify_all();
+        if let Some(h) = self.handle.take() {
+            let _ = h.join();
+        }
+    }
+}
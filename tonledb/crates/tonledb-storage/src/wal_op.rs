@@ -0,0 +1,111 @@
+//! `InMemoryStore` changes as WAL records.
+//!
+//! Each change is a typed record (`RecordType`) whose fields are length-prefixed
+//! (`u32` BE), so spaces, keys and values may hold any byte. Records converted
+//! from the newline-delimited WAL come back as `RecordType::Raw` and are decoded
+//! from their tab-separated text layout.
+
+use tonledb_core::{DbError, Result, Space};
+use tonledb_wal::{Record, RecordType};
+
+/// Space field of legacy records that carry a TTL deadline rather than a value
+const LEGACY_TTL_TAG: &[u8] = b"\0ttl";
+/// Space field of legacy records that delete a whole key prefix
+const LEGACY_DEL_PREFIX_TAG: &[u8] = b"\0delp";
+/// Space field of legacy records that delete one key
+const LEGACY_DEL_TAG: &[u8] = b"\0del";
+
+/// A decoded WAL record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum WalOp {
+    Put { space: Space, key: Vec<u8>, val: Vec<u8> },
+    Delete { space: Space, key: Vec<u8> },
+    DeletePrefix { space: Space, prefix: Vec<u8> },
+    /// Expiry (epoch ms) of a key written just before
+    Ttl { space: Space, key: Vec<u8>, deadline: u64 },
+}
+
+impl WalOp {
+    pub(crate) fn space(&self) -> &Space {
+        match self {
+            WalOp::Put { space, .. } | WalOp::Delete { space, .. } | WalOp::DeletePrefix { space, .. } | WalOp::Ttl { space, .. } => space,
+        }
+    }
+
+    pub(crate) fn decode(rec: &Record) -> Result<Self> {
+        let malformed = || DbError::Corruption(format!("malformed {:?} WAL record of {} bytes", rec.kind, rec.payload.len()));
+        if rec.kind == RecordType::Raw {
+            return decode_legacy(&rec.payload).ok_or_else(malformed);
+        }
+        let mut rest = &rec.payload[..];
+        let space = field(&mut rest).map(|s| Space(String::from_utf8_lossy(s).into_owned())).ok_or_else(malformed)?;
+        let key = field(&mut rest).ok_or_else(malformed)?.to_vec();
+        Ok(match rec.kind {
+            RecordType::Put => WalOp::Put { space, key, val: rest.to_vec() },
+            RecordType::Delete => WalOp::Delete { space, key },
+            RecordType::DeletePrefix => WalOp::DeletePrefix { space, prefix: key },
+            RecordType::Ttl => {
+                let deadline = u64::from_be_bytes(rest.try_into().map_err(|_| malformed())?);
+                WalOp::Ttl { space, key, deadline }
+            }
+            // Consumed by replay, never handed out
+            RecordType::Reset | RecordType::TxnBegin | RecordType::TxnCommit | RecordType::Time => return Err(malformed()),
+            RecordType::Raw => unreachable!("handled above"),
+        })
+    }
+}
+
+pub(crate) fn put(space: &Space, key: &[u8], val: &[u8]) -> Record {
+    Record::new(RecordType::Put, [fields(space, key), val.to_vec()].concat())
+}
+
+/// Records of a write to the live map: a put with its expiry, or a delete
+pub(crate) fn write(space: &Space, key: &[u8], val: Option<&[u8]>, expires: Option<u64>) -> Vec<Record> {
+    match val {
+        Some(val) => std::iter::once(put(space, key, val)).chain(expires.map(|exp| ttl(space, key, exp))).collect(),
+        None => vec![delete(space, key)],
+    }
+}
+
+pub(crate) fn delete(space: &Space, key: &[u8]) -> Record {
+    Record::new(RecordType::Delete, fields(space, key))
+}
+
+pub(crate) fn delete_prefix(space: &Space, prefix: &[u8]) -> Record {
+    Record::new(RecordType::DeletePrefix, fields(space, prefix))
+}
+
+pub(crate) fn ttl(space: &Space, key: &[u8], deadline: u64) -> Record {
+    Record::new(RecordType::Ttl, [fields(space, key), deadline.to_be_bytes().to_vec()].concat())
+}
+
+fn fields(space: &Space, key: &[u8]) -> Vec<u8> {
+    [&(space.0.len() as u32).to_be_bytes()[..], space.0.as_bytes(), &(key.len() as u32).to_be_bytes(), key].concat()
+}
+
+/// Next length-prefixed field of `rest`, advancing past it
+fn field<'a>(rest: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = u32::from_be_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+    let f = rest.get(4..4 + len)?;
+    *rest = &rest[4 + len..];
+    Some(f)
+}
+
+/// `space\tkey\tval`, or a tag in the space field: `TTL\tspace\tkey\tdeadline`,
+/// `DEL\tspace\tkey` and `DELP\tspace\tprefix`
+fn decode_legacy(rec: &[u8]) -> Option<WalOp> {
+    let mut it = rec.splitn(3, |b| *b == b'\t');
+    let (sp, k, v) = (it.next()?, it.next()?, it.next()?);
+    let space = |b: &[u8]| Space(String::from_utf8_lossy(b).into_owned());
+    Some(if sp == LEGACY_DEL_PREFIX_TAG {
+        WalOp::DeletePrefix { space: space(k), prefix: v.to_vec() }
+    } else if sp == LEGACY_DEL_TAG {
+        WalOp::Delete { space: space(k), key: v.to_vec() }
+    } else if sp == LEGACY_TTL_TAG {
+        let cut = v.iter().rposition(|b| *b == b'\t')?;
+        let deadline = std::str::from_utf8(&v[cut + 1..]).ok()?.parse().ok()?;
+        WalOp::Ttl { space: space(k), key: v[..cut].to_vec(), deadline }
+    } else {
+        WalOp::Put { space: space(sp), key: k.to_vec(), val: v.to_vec() }
+    })
+}
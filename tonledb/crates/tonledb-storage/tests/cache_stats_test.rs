@@ -0,0 +1,27 @@
+//! Tests for cache introspection used by EXPLAIN
+
+use tonledb_core::{Space, Storage};
+use tonledb_storage::InMemoryStore;
+
+#[test]
+fn test_hits_misses_and_residency() {
+    let store = InMemoryStore::with_shards(4, 1);
+    let space = Space("data".to_string());
+    store.put(&space, b"a".to_vec(), b"1".to_vec()).unwrap();
+    assert!(store.is_cached(&space, b"a"));
+    assert!(!store.is_cached(&space, b"missing"));
+
+    store.get(&space, b"a").unwrap();
+    store.get(&space, b"missing").unwrap();
+    let stats = store.cache_stats().unwrap();
+    assert_eq!((stats.hits, stats.misses), (1, 1));
+    assert_eq!(stats.capacity, 4);
+    assert_eq!(stats.entries, 1);
+    assert_eq!(stats.hit_ratio(), 0.5);
+
+    // Pushed out of a full LRU: reads would come from storage
+    for k in [b"b", b"c", b"d", b"e"] {
+        store.put(&space, k.to_vec(), b"x".to_vec()).unwrap();
+    }
+    assert!(!store.is_cached(&space, b"a"));
+}
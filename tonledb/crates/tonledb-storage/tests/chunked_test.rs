@@ -0,0 +1,86 @@
+//! Tests for transparent chunking of large values
+
+use std::sync::Arc;
+use tonledb_core::{DbError, Space, Storage};
+use tonledb_storage::{ChunkOptions, ChunkedStorage, InMemoryStore};
+
+fn store() -> (Arc<InMemoryStore>, ChunkedStorage<Arc<InMemoryStore>>) {
+    let base = Arc::new(InMemoryStore::new(100));
+    (base.clone(), ChunkedStorage::new(base, ChunkOptions { threshold: 10, chunk_size: 4 }))
+}
+
+fn blob(n: usize) -> Vec<u8> {
+    (0..n).map(|i| (i % 251) as u8).collect()
+}
+
+fn chunk_count(base: &InMemoryStore) -> usize {
+    base.scan_prefix(&Space("data#chunks".into()), b"").unwrap().count()
+}
+
+#[test]
+fn test_large_values_round_trip_through_chunks() {
+    let (base, store) = store();
+    let space = Space("data".into());
+    store.put(&space, b"small".to_vec(), b"tiny".to_vec()).unwrap();
+    store.put(&space, b"big".to_vec(), blob(25)).unwrap();
+
+    assert!(!store.is_chunked(&space, b"small").unwrap());
+    assert!(store.is_chunked(&space, b"big").unwrap());
+    assert_eq!(chunk_count(&base), 7);
+    assert_eq!(store.get(&space, b"big").unwrap(), Some(blob(25)));
+    assert_eq!(store.get(&space, b"small").unwrap(), Some(b"tiny".to_vec()));
+
+    let rows: Vec<_> = store.scan_prefix(&space, b"").unwrap().collect();
+    assert_eq!(rows, vec![(b"big".to_vec(), blob(25)), (b"small".to_vec(), b"tiny".to_vec())]);
+    let snap = store.snapshot().unwrap();
+    assert_eq!(snap.get(&space, b"big").unwrap(), Some(blob(25)));
+}
+
+#[test]
+fn test_rewrites_and_deletes_drop_old_chunks() {
+    let (base, store) = store();
+    let space = Space("data".into());
+    store.put(&space, b"k".to_vec(), blob(40)).unwrap();
+    store.put(&space, b"k".to_vec(), blob(12)).unwrap();
+    assert_eq!(chunk_count(&base), 3);
+    assert_eq!(store.get(&space, b"k").unwrap(), Some(blob(12)));
+
+    store.put(&space, b"k".to_vec(), b"short".to_vec()).unwrap();
+    assert_eq!(chunk_count(&base), 0);
+    store.put(&space, b"k".to_vec(), blob(12)).unwrap();
+    store.del(&space, b"k").unwrap();
+    assert_eq!(chunk_count(&base), 0);
+    assert_eq!(store.get(&space, b"k").unwrap(), None);
+}
+
+#[test]
+fn test_values_that_look_like_manifests_are_stored_safely() {
+    let (_, store) = store();
+    let space = Space("data".into());
+    let tricky = b"\0tldb-chunked\0".to_vec();
+    store.put(&space, b"t".to_vec(), tricky.clone()).unwrap();
+    assert_eq!(store.get(&space, b"t").unwrap(), Some(tricky));
+}
+
+#[test]
+fn test_missing_chunks_are_reported_as_corruption() {
+    let (base, store) = store();
+    let space = Space("data".into());
+    store.put(&space, b"k".to_vec(), blob(20)).unwrap();
+    let first = base.scan_prefix(&Space("data#chunks".into()), b"k").unwrap().next().unwrap().0;
+    base.del(&Space("data#chunks".into()), &first).unwrap();
+    assert!(matches!(store.get(&space, b"k"), Err(DbError::Corruption(_))));
+}
+
+#[test]
+fn test_pages_and_ranges_reassemble_chunks() {
+    let (_, store) = store();
+    let space = Space("data".into());
+    for (i, n) in [3, 25, 4, 30].into_iter().enumerate() {
+        store.put(&space, format!("k{}", i).into_bytes(), blob(n)).unwrap();
+    }
+    let page = store.scan_prefix_page(&space, b"k", Some(b"k0"), 2).unwrap();
+    assert_eq!(page, vec![(b"k1".to_vec(), blob(25)), (b"k2".to_vec(), blob(4))]);
+    let newest = store.scan_range(&space, b"k", None, true, 2).unwrap();
+    assert_eq!(newest, vec![(b"k3".to_vec(), blob(30)), (b"k2".to_vec(), blob(4))]);
+}
@@ -0,0 +1,107 @@
+//! Tests for keeping column segments of tables in step with their rows
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use serde_json::json;
+use tonledb_core::columnar::{self, olap_space};
+use tonledb_core::transaction::{next_timestamp, WriteSet};
+use tonledb_core::{Consistency, DbError, Result, Space, Storage};
+use tonledb_storage::{ColumnarStorage, InMemoryStore};
+
+/// A store whose `olap` space refuses writes while `down` is set
+struct OlapDown {
+    inner: InMemoryStore,
+    down: AtomicBool,
+}
+
+impl OlapDown {
+    fn check(&self, space: &Space) -> Result<()> {
+        match space.0 == "olap" && self.down.load(Ordering::SeqCst) {
+            true => Err(DbError::Storage("down".into())),
+            false => Ok(()),
+        }
+    }
+}
+
+impl Storage for OlapDown {
+    fn get(&self, space: &Space, key: &[u8]) -> Result<Option<Vec<u8>>> { self.inner.get(space, key) }
+    fn put(&self, space: &Space, key: Vec<u8>, val: Vec<u8>) -> Result<()> { self.check(space)?; self.inner.put(space, key, val) }
+    fn del(&self, space: &Space, key: &[u8]) -> Result<()> { self.check(space)?; self.inner.del(space, key) }
+    fn scan_prefix(&self, space: &Space, prefix: &[u8]) -> Result<Box<dyn Iterator<Item=(Vec<u8>, Vec<u8>)> + Send>> { self.inner.scan_prefix(space, prefix) }
+}
+
+fn data() -> Space {
+    Space("data".into())
+}
+
+fn row(v: serde_json::Value) -> Vec<u8> {
+    serde_json::to_vec(&v).unwrap()
+}
+
+#[test]
+fn test_segments_follow_row_writes() {
+    let base = Arc::new(InMemoryStore::new(100));
+    // Rows written before the table is mirrored are picked up when it starts
+    base.put(&data(), b"tbl/orders/1".to_vec(), row(json!({"id": 1, "total": 10, "note": "a"}))).unwrap();
+    let store = ColumnarStorage::new(base.clone(), ["orders".to_string()]).unwrap();
+    assert!(columnar::is_ready(&store, "orders").unwrap());
+
+    store.put(&data(), b"tbl/orders/2".to_vec(), row(json!({"id": 2, "total": 5}))).unwrap();
+    // Other tables and spaces aren't mirrored
+    store.put(&data(), b"tbl/users/1".to_vec(), row(json!({"id": 1}))).unwrap();
+    store.put(&Space("kv".into()), b"tbl/orders/9".to_vec(), row(json!({"id": 9}))).unwrap();
+
+    let totals = columnar::scan_column(&store, "orders", "total").unwrap();
+    assert_eq!(totals, vec![(b"1".to_vec(), json!(10)), (b"2".to_vec(), json!(5))]);
+    assert_eq!(columnar::read_columns(&store, "orders", &["note"]).unwrap(), vec![json!({"note": "a"}), json!({})]);
+    assert!(base.scan_prefix(&olap_space(), b"users/").unwrap().next().is_none());
+
+    // An update drops the columns the row no longer has
+    store.put(&data(), b"tbl/orders/1".to_vec(), row(json!({"id": 1, "total": 12}))).unwrap();
+    assert!(columnar::scan_column(&store, "orders", "note").unwrap().is_empty());
+    store.del(&data(), b"tbl/orders/2").unwrap();
+    assert_eq!(columnar::read_columns(&store, "orders", &["total"]).unwrap(), vec![json!({"total": 12})]);
+
+    // Transactions carry the segments of their rows
+    let mut writes = WriteSet::new();
+    writes.insert((data(), b"tbl/orders/3".to_vec()), Some(row(json!({"id": 3, "total": 1}))));
+    store.commit_writes(&writes, next_timestamp()).unwrap();
+    assert_eq!(columnar::row_keys(&store, "orders").unwrap(), vec![b"1".to_vec(), b"3".to_vec()]);
+
+    // Dropping the table's rows empties its segments
+    store.delete_prefix(&data(), b"tbl/orders/").unwrap();
+    assert!(base.scan_prefix(&olap_space(), b"orders/").unwrap().next().is_none());
+}
+
+#[test]
+fn test_rebuild_repairs_segments() {
+    let base = Arc::new(InMemoryStore::new(100));
+    let store = ColumnarStorage::new(base.clone(), ["orders".to_string()]).unwrap();
+    store.put(&data(), b"tbl/orders/1".to_vec(), row(json!({"id": 1, "total": 10}))).unwrap();
+    // Written around the wrapper
+    base.put(&data(), b"tbl/orders/2".to_vec(), row(json!({"id": 2, "total": 20}))).unwrap();
+    assert_eq!(columnar::row_keys(&store, "orders").unwrap().len(), 1);
+    assert_eq!(columnar::rebuild(&store, "orders").unwrap(), 2);
+    assert_eq!(columnar::read_columns(&store, "orders", &["total"]).unwrap(), vec![json!({"total": 10}), json!({"total": 20})]);
+}
+
+#[test]
+fn test_segments_dropped_when_they_fall_behind() {
+    let base = Arc::new(OlapDown { inner: InMemoryStore::new(100), down: AtomicBool::new(false) });
+    let store = ColumnarStorage::new(base.clone(), ["orders".to_string(), "users".to_string()]).unwrap();
+    store.put(&data(), b"tbl/orders/1".to_vec(), row(json!({"id": 1, "total": 10}))).unwrap();
+
+    // The row is written but its segments can't be: they are no longer read
+    base.down.store(true, Ordering::SeqCst);
+    assert!(store.put_with(&data(), b"tbl/orders/2".to_vec(), row(json!({"id": 2, "total": 20})), Consistency::One).is_err());
+    assert!(!columnar::is_ready(&store, "orders").unwrap());
+    assert!(columnar::is_ready(&store, "users").unwrap());
+
+    // Opened again, the table is rebuilt; one taken off the list loses its segments
+    base.down.store(false, Ordering::SeqCst);
+    let store = ColumnarStorage::new(base.clone(), ["orders".to_string()]).unwrap();
+    assert_eq!(columnar::read_columns(&store, "orders", &["total"]).unwrap(), vec![json!({"total": 10}), json!({"total": 20})]);
+    assert!(!columnar::is_ready(&store, "users").unwrap());
+    store.put(&data(), b"tbl/users/1".to_vec(), row(json!({"id": 1}))).unwrap();
+    assert!(base.scan_prefix(&olap_space(), b"users/").unwrap().next().is_none());
+}
@@ -0,0 +1,107 @@
+//! Tests for online space compaction
+
+use std::sync::Arc;
+use std::time::Duration;
+use tonledb_core::{Space, Storage};
+use tonledb_storage::InMemoryStore;
+
+fn wal_path(name: &str) -> String {
+    let p = std::env::temp_dir().join(format!("tonledb-compact-{}-{}.wal", name, std::process::id()));
+    let _ = std::fs::remove_file(&p);
+    p.to_string_lossy().into_owned()
+}
+
+#[test]
+fn test_compaction_shrinks_wal_to_live_keys() {
+    let p = wal_path("shrink");
+    let kv = Space("kv".to_string());
+    let other = Space("other".to_string());
+    {
+        let store = InMemoryStore::with_wal(&p, 100).unwrap();
+        for i in 0..10 {
+            store.put(&kv, b"a".to_vec(), format!("v{}", i).into_bytes()).unwrap();
+        }
+        store.put(&kv, b"gone".to_vec(), b"x".to_vec()).unwrap();
+        store.del(&kv, b"gone").unwrap();
+        store.put_with_ttl(&kv, b"short".to_vec(), b"x".to_vec(), 10).unwrap();
+        store.put_with_ttl(&kv, b"long".to_vec(), b"y".to_vec(), 60_000).unwrap();
+        store.put(&other, b"b".to_vec(), b"1".to_vec()).unwrap();
+        std::thread::sleep(Duration::from_millis(30));
+
+        let report = store.compact_space(&kv).unwrap();
+        assert_eq!(report.space, "kv");
+        assert_eq!(report.expired_removed, 1);
+        assert_eq!(report.live_keys, 2);
+        // a, long + its deadline, and the untouched record of `other`
+        assert_eq!(report.wal_records_after, 4);
+        assert!(report.wal_records_before > report.wal_records_after);
+        assert_eq!(store.get(&kv, b"a").unwrap(), Some(b"v9".to_vec()));
+    }
+
+    let store = InMemoryStore::with_wal(&p, 100).unwrap();
+    assert_eq!(store.get(&kv, b"a").unwrap(), Some(b"v9".to_vec()));
+    assert_eq!(store.get(&kv, b"gone").unwrap(), None);
+    assert_eq!(store.get(&kv, b"long").unwrap(), Some(b"y".to_vec()));
+    assert_eq!(store.get(&other, b"b").unwrap(), Some(b"1".to_vec()));
+    let _ = std::fs::remove_file(&p);
+}
+
+#[test]
+fn test_compaction_drops_unobservable_tombstones() {
+    let store = InMemoryStore::new(100);
+    let kv = Space("kv".to_string());
+    store.put_versioned(&kv, b"k".to_vec(), b"1".to_vec(), 10).unwrap();
+    store.del_versioned(&kv, b"k", 20).unwrap();
+    store.del_versioned(&kv, b"dead", 5).unwrap();
+
+    let report = store.compact_space(&kv).unwrap();
+    // `dead` was never anything but deleted; `k` still needs its marker for readers past 20
+    assert_eq!(report.tombstones_removed, 1);
+    assert_eq!(store.get_versioned(&kv, b"k", 15).unwrap(), Some(b"1".to_vec()));
+    assert_eq!(store.get_versioned(&kv, b"k", 25).unwrap(), None);
+    assert_eq!(store.get_versioned(&kv, b"dead", 6).unwrap(), None);
+    assert_eq!(store.latest_version(&kv, b"dead").unwrap(), None);
+}
+
+#[test]
+fn test_writes_during_compaction_survive_reopen() {
+    let p = wal_path("online");
+    let kv = Space("kv".to_string());
+    {
+        let store = Arc::new(InMemoryStore::with_wal(&p, 100).unwrap());
+        for i in 0..200 {
+            store.put(&kv, format!("seed{}", i).into_bytes(), b"x".to_vec()).unwrap();
+        }
+        let writer = {
+            let store = store.clone();
+            let kv = kv.clone();
+            std::thread::spawn(move || {
+                for i in 0..200 {
+                    store.put(&kv, format!("live{}", i).into_bytes(), b"y".to_vec()).unwrap();
+                }
+            })
+        };
+        for _ in 0..5 {
+            store.compact_space(&kv).unwrap();
+        }
+        writer.join().unwrap();
+    }
+    let store = InMemoryStore::with_wal(&p, 100).unwrap();
+    assert_eq!(store.scan_prefix(&kv, b"live").unwrap().count(), 200);
+    assert_eq!(store.scan_prefix(&kv, b"seed").unwrap().count(), 200);
+    let _ = std::fs::remove_file(&p);
+}
+
+#[test]
+fn test_engines_without_compaction_report_invalid() {
+    struct Plain;
+    impl Storage for Plain {
+        fn get(&self, _: &Space, _: &[u8]) -> tonledb_core::Result<Option<Vec<u8>>> { Ok(None) }
+        fn put(&self, _: &Space, _: Vec<u8>, _: Vec<u8>) -> tonledb_core::Result<()> { Ok(()) }
+        fn del(&self, _: &Space, _: &[u8]) -> tonledb_core::Result<()> { Ok(()) }
+        fn scan_prefix(&self, _: &Space, _: &[u8]) -> tonledb_core::Result<Box<dyn Iterator<Item=(Vec<u8>, Vec<u8>)> + Send>> {
+            Ok(Box::new(std::iter::empty()))
+        }
+    }
+    assert!(matches!(Plain.compact_space(&Space("kv".into())), Err(tonledb_core::DbError::Invalid(_))));
+}
@@ -0,0 +1,63 @@
+//! Tests for transparent per-space value compression
+
+use std::sync::Arc;
+use tonledb_core::{Space, Storage};
+use tonledb_storage::InMemoryStore;
+use tonledb_storage::compression::{Codec, CompressedStorage, CompressionOptions};
+
+fn json_heavy() -> Vec<u8> {
+    let mut s = String::from("[");
+    for i in 0..200 {
+        s.push_str(&format!("{{\"name\":\"user\",\"active\":true,\"n\":{}}},", i % 3));
+    }
+    s.push_str("{}]");
+    s.into_bytes()
+}
+
+#[test]
+fn test_zstd_round_trip_and_shrinks() {
+    let base = Arc::new(InMemoryStore::new(1000));
+    let data = Space("data".to_string());
+    let store = CompressedStorage::new(base.clone())
+        .with_space(data.clone(), CompressionOptions { codec: Codec::Zstd { level: 3 }, min_size: 64 });
+
+    let val = json_heavy();
+    store.put(&data, b"doc/users/1".to_vec(), val.clone()).unwrap();
+
+    assert_eq!(store.get(&data, b"doc/users/1").unwrap(), Some(val.clone()));
+    let stored = base.get(&data, b"doc/users/1").unwrap().unwrap();
+    assert!(stored.len() < val.len());
+
+    let scanned: Vec<_> = store.scan_prefix(&data, b"doc/users/").unwrap().collect();
+    assert_eq!(scanned, vec![(b"doc/users/1".to_vec(), val)]);
+}
+
+#[test]
+fn test_lz4_round_trip() {
+    let store = CompressedStorage::new(InMemoryStore::new(1000))
+        .with_default(CompressionOptions { codec: Codec::Lz4, min_size: 16 });
+    let kv = Space("kv".to_string());
+    let val = json_heavy();
+    store.put(&kv, b"k".to_vec(), val.clone()).unwrap();
+    assert_eq!(store.get(&kv, b"k").unwrap(), Some(val));
+}
+
+#[test]
+fn test_small_values_and_unconfigured_spaces_stay_raw() {
+    let base = Arc::new(InMemoryStore::new(1000));
+    let data = Space("data".to_string());
+    let kv = Space("kv".to_string());
+    let store = CompressedStorage::new(base.clone())
+        .with_space(data.clone(), CompressionOptions { codec: Codec::Lz4, min_size: 1024 });
+
+    store.put(&data, b"small".to_vec(), b"tiny".to_vec()).unwrap();
+    store.put(&kv, b"k".to_vec(), b"v".to_vec()).unwrap();
+
+    // One tag byte, then the raw value
+    assert_eq!(base.get(&data, b"small").unwrap(), Some(b"\0tiny".to_vec()));
+    assert_eq!(base.get(&kv, b"k").unwrap(), Some(b"\0v".to_vec()));
+    assert_eq!(store.get(&data, b"small").unwrap(), Some(b"tiny".to_vec()));
+
+    let snap = store.snapshot().unwrap();
+    assert_eq!(snap.get(&kv, b"k").unwrap(), Some(b"v".to_vec()));
+}
@@ -0,0 +1,55 @@
+//! Tests for per-space encryption at rest
+
+use std::sync::Arc;
+use tonledb_core::{Space, Storage};
+use tonledb_storage::{EncryptedStorage, InMemoryStore};
+
+const KEK: &str = "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=";
+const OTHER_KEK: &str = "ZmVkY2JhOTg3NjU0MzIxMGZlZGNiYTk4NzY1NDMyMTA=";
+
+fn data() -> Space {
+    Space("data".into())
+}
+
+fn catalog() -> Space {
+    Space("catalog".into())
+}
+
+#[test]
+fn test_only_registered_spaces_are_sealed() {
+    let base = Arc::new(InMemoryStore::new(100));
+    let store = EncryptedStorage::new(base.clone(), KEK).unwrap().with_space(data());
+    store.put(&data(), b"doc/1".to_vec(), b"secret".to_vec()).unwrap();
+    store.put(&catalog(), b"col/x".to_vec(), b"meta".to_vec()).unwrap();
+
+    assert_eq!(store.get(&data(), b"doc/1").unwrap(), Some(b"secret".to_vec()));
+    let raw = base.get(&data(), b"doc/1").unwrap().unwrap();
+    assert!(!raw.windows(6).any(|w| w == b"secret"));
+    assert_eq!(base.get(&catalog(), b"col/x").unwrap(), Some(b"meta".to_vec()));
+
+    let rows: Vec<_> = store.scan_prefix(&data(), b"doc/").unwrap().collect();
+    assert_eq!(rows, vec![(b"doc/1".to_vec(), b"secret".to_vec())]);
+    assert!(store.is_encrypted(&data()) && !store.is_encrypted(&catalog()));
+}
+
+#[test]
+fn test_data_key_survives_reopen_with_the_same_kek() {
+    let base = Arc::new(InMemoryStore::new(100));
+    EncryptedStorage::new(base.clone(), KEK).unwrap().with_space(data()).put(&data(), b"k".to_vec(), b"v".to_vec()).unwrap();
+
+    let reopened = EncryptedStorage::new(base.clone(), KEK).unwrap().with_space(data());
+    assert_eq!(reopened.get(&data(), b"k").unwrap(), Some(b"v".to_vec()));
+    assert!(EncryptedStorage::new(base, OTHER_KEK).is_err());
+}
+
+#[test]
+fn test_values_are_bound_to_their_key() {
+    let base = Arc::new(InMemoryStore::new(100));
+    let store = EncryptedStorage::new(base.clone(), KEK).unwrap().with_space(data());
+    store.put(&data(), b"a".to_vec(), b"v".to_vec()).unwrap();
+    // Moving a sealed value to another key must not decrypt
+    let raw = base.get(&data(), b"a").unwrap().unwrap();
+    base.put(&data(), b"b".to_vec(), raw).unwrap();
+    assert!(store.get(&data(), b"b").is_err());
+    assert!(store.merge(&data(), b"a".to_vec(), b"1".to_vec()).is_err());
+}
@@ -0,0 +1,82 @@
+//! Tests for read-only replicas following a WAL archive with a delay
+
+use std::sync::Arc;
+use std::time::Duration;
+use tonledb_core::{Space, Storage};
+use tonledb_storage::{DelayedReplica, InMemoryStore, ReplicaFollower};
+
+fn temp(name: &str) -> String {
+    let p = std::env::temp_dir().join(format!("tonledb-delayed-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&p);
+    let _ = std::fs::remove_file(&p);
+    p.to_string_lossy().into_owned()
+}
+
+#[test]
+fn test_replica_replays_puts_deletes_and_prefix_deletes() {
+    let (wal, dir) = (temp("replay.wal"), temp("replay-archive"));
+    let kv = Space("kv".to_string());
+    let primary = InMemoryStore::with_wal(&wal, 100).unwrap();
+    primary.set_wal_archive(&dir, 1 << 20).unwrap();
+    primary.put(&kv, b"a".to_vec(), b"1".to_vec()).unwrap();
+    primary.put(&kv, b"b".to_vec(), b"2".to_vec()).unwrap();
+    primary.put(&kv, b"tmp/1".to_vec(), b"x".to_vec()).unwrap();
+    primary.put(&kv, b"tmp/2".to_vec(), b"x".to_vec()).unwrap();
+    primary.del(&kv, b"b").unwrap();
+    primary.delete_prefix(&kv, b"tmp/").unwrap();
+    primary.put_with_ttl(&kv, b"ttl".to_vec(), b"t".to_vec(), 60_000).unwrap();
+
+    let replica = DelayedReplica::new(&dir, 0, 100);
+    assert!(replica.catch_up().unwrap() >= 7);
+    assert_eq!(replica.get(&kv, b"a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(replica.get(&kv, b"b").unwrap(), None);
+    assert_eq!(replica.scan_prefix(&kv, b"tmp/").unwrap().count(), 0);
+    assert_eq!(replica.get(&kv, b"ttl").unwrap(), Some(b"t".to_vec()));
+    assert!(replica.status().as_of_ms > 0);
+    let _ = std::fs::remove_dir_all(&dir);
+    let _ = std::fs::remove_file(&wal);
+}
+
+#[test]
+fn test_replica_lags_by_its_delay_and_rejects_writes() {
+    let (wal, dir) = (temp("lag.wal"), temp("lag-archive"));
+    let kv = Space("kv".to_string());
+    let primary = InMemoryStore::with_wal(&wal, 100).unwrap();
+    primary.set_wal_archive(&dir, 1 << 20).unwrap();
+    primary.put(&kv, b"a".to_vec(), b"old".to_vec()).unwrap();
+    std::thread::sleep(Duration::from_millis(300));
+    primary.put(&kv, b"a".to_vec(), b"new".to_vec()).unwrap();
+
+    let replica = Arc::new(DelayedReplica::new(&dir, 150, 100));
+    replica.catch_up().unwrap();
+    // The second write is younger than the delay, so the replica still shows the first
+    assert_eq!(replica.get(&kv, b"a").unwrap(), Some(b"old".to_vec()));
+    assert!(replica.put(&kv, b"a".to_vec(), b"x".to_vec()).is_err());
+    assert!(replica.del(&kv, b"a").is_err());
+
+    let follower = ReplicaFollower::spawn(replica.clone(), Duration::from_millis(20));
+    std::thread::sleep(Duration::from_millis(300));
+    drop(follower);
+    assert_eq!(replica.get(&kv, b"a").unwrap(), Some(b"new".to_vec()));
+    assert_eq!(replica.status().applied_records, 2);
+    let _ = std::fs::remove_dir_all(&dir);
+    let _ = std::fs::remove_file(&wal);
+}
+
+#[test]
+fn test_deletes_survive_reopen() {
+    let wal = temp("reopen.wal");
+    let kv = Space("kv".to_string());
+    {
+        let store = InMemoryStore::with_wal(&wal, 100).unwrap();
+        store.put(&kv, b"a".to_vec(), b"1".to_vec()).unwrap();
+        store.del(&kv, b"a").unwrap();
+    }
+    assert_eq!(InMemoryStore::with_wal(&wal, 100).unwrap().get(&kv, b"a").unwrap(), None);
+    let _ = std::fs::remove_file(&wal);
+}
+
+#[test]
+fn test_archiving_needs_a_wal() {
+    assert!(InMemoryStore::new(10).set_wal_archive(&temp("no-wal"), 1024).is_err());
+}
@@ -0,0 +1,58 @@
+//! Tests for bulk prefix deletes
+
+use tonledb_core::{Space, Storage};
+use tonledb_storage::InMemoryStore;
+
+fn wal_path(name: &str) -> String {
+    let p = std::env::temp_dir().join(format!("tonledb-delp-{}-{}.wal", name, std::process::id()));
+    let _ = std::fs::remove_file(&p);
+    p.to_string_lossy().into_owned()
+}
+
+fn fill(store: &InMemoryStore, space: &Space) {
+    for k in ["doc/a/1", "doc/a/2", "doc/a/3", "doc/ab/1", "doc/b/1"] {
+        store.put(space, k.as_bytes().to_vec(), b"v".to_vec()).unwrap();
+    }
+}
+
+#[test]
+fn test_delete_prefix_removes_only_matching_keys() {
+    let store = InMemoryStore::new(100);
+    let data = Space("data".to_string());
+    let other = Space("other".to_string());
+    fill(&store, &data);
+    store.put(&other, b"doc/a/1".to_vec(), b"v".to_vec()).unwrap();
+
+    assert_eq!(store.delete_prefix(&data, b"doc/a/").unwrap(), 3);
+    assert_eq!(store.get(&data, b"doc/a/1").unwrap(), None);
+    let left: Vec<Vec<u8>> = store.scan_prefix(&data, b"").unwrap().map(|(k, _)| k).collect();
+    assert_eq!(left, vec![b"doc/ab/1".to_vec(), b"doc/b/1".to_vec()]);
+    assert!(store.get(&other, b"doc/a/1").unwrap().is_some());
+    assert_eq!(store.delete_prefix(&data, b"doc/a/").unwrap(), 0);
+}
+
+#[test]
+fn test_delete_prefix_survives_wal_replay() {
+    let path = wal_path("replay");
+    let data = Space("data".to_string());
+    {
+        let store = InMemoryStore::with_wal(&path, 100).unwrap();
+        fill(&store, &data);
+        store.delete_prefix(&data, b"doc/a/").unwrap();
+        // Written after the delete, so replay must keep it
+        store.put(&data, b"doc/a/9".to_vec(), b"new".to_vec()).unwrap();
+    }
+    let store = InMemoryStore::with_wal(&path, 100).unwrap();
+    let keys: Vec<Vec<u8>> = store.scan_prefix(&data, b"doc/a").unwrap().map(|(k, _)| k).collect();
+    assert_eq!(keys, vec![b"doc/a/9".to_vec(), b"doc/ab/1".to_vec()]);
+}
+
+#[test]
+fn test_delete_prefix_leaves_older_versions_readable() {
+    let store = InMemoryStore::new(100);
+    let data = Space("data".to_string());
+    store.put_versioned(&data, b"doc/a/1".to_vec(), b"v1".to_vec(), 10).unwrap();
+    store.delete_prefix(&data, b"doc/a/").unwrap();
+    assert_eq!(store.get(&data, b"doc/a/1").unwrap(), None);
+    assert_eq!(store.get_versioned(&data, b"doc/a/1", 10).unwrap(), Some(b"v1".to_vec()));
+}
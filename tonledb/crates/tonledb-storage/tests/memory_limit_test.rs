@@ -0,0 +1,70 @@
+//! Tests for the InMemoryStore memory budget
+
+use tonledb_core::{DbError, Space, Storage};
+use tonledb_storage::{InMemoryStore, MemoryLimit, OnFull};
+
+#[test]
+fn test_reject_when_budget_exceeded() {
+    let store = InMemoryStore::new(10);
+    let kv = Space("kv".to_string());
+    store.set_memory_limit(Some(MemoryLimit { max_bytes: 64, on_full: OnFull::Reject })).unwrap();
+
+    store.put(&kv, b"a".to_vec(), vec![0; 20]).unwrap();
+    let err = store.put(&kv, b"b".to_vec(), vec![0; 100]).unwrap_err();
+    assert!(matches!(err, DbError::ResourceExhausted(_)));
+    assert_eq!(store.get(&kv, b"b").unwrap(), None);
+
+    // Shrinking writes and deletes still work
+    store.put(&kv, b"a".to_vec(), vec![0; 5]).unwrap();
+    store.del(&kv, b"a").unwrap();
+    assert_eq!(store.memory_usage(), 0);
+}
+
+#[test]
+fn test_spill_keeps_everything_readable() {
+    let dir = std::env::temp_dir().join(format!("tonledb_spill_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let store = InMemoryStore::new(2);
+    let kv = Space("kv".to_string());
+    store.set_memory_limit(Some(MemoryLimit { max_bytes: 300, on_full: OnFull::Spill { path: dir.join("overflow.spill") } })).unwrap();
+
+    for i in 0..20u8 {
+        store.put(&kv, vec![b'k', i], vec![i; 50]).unwrap();
+    }
+    assert!(store.memory_usage() <= 300);
+    assert!(store.spilled_entries() > 0);
+
+    for i in 0..20u8 {
+        assert_eq!(store.get(&kv, &[b'k', i]).unwrap(), Some(vec![i; 50]));
+    }
+    let all: Vec<_> = store.scan_prefix(&kv, b"k").unwrap().collect();
+    assert_eq!(all.len(), 20);
+    assert_eq!(all[3], (vec![b'k', 3], vec![3; 50]));
+
+    store.del(&kv, &[b'k', 0]).unwrap();
+    assert_eq!(store.get(&kv, &[b'k', 0]).unwrap(), None);
+    assert_eq!(store.snapshot().unwrap().scan_prefix(&kv, b"k").unwrap().count(), 19);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_corrupt_spill_entry_is_detected() {
+    let dir = std::env::temp_dir().join(format!("tonledb_spill_crc_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("overflow.spill");
+    let store = InMemoryStore::new(1);
+    let kv = Space("kv".to_string());
+    store.set_memory_limit(Some(MemoryLimit { max_bytes: 100, on_full: OnFull::Spill { path: path.clone() } })).unwrap();
+    store.put(&kv, b"cold".to_vec(), vec![7; 60]).unwrap();
+    store.put(&kv, b"hot".to_vec(), vec![8; 60]).unwrap();
+    assert_eq!(store.spilled_entries(), 1);
+
+    let mut bytes = std::fs::read(&path).unwrap();
+    bytes[0] ^= 0xff;
+    std::fs::write(&path, &bytes).unwrap();
+    // Snapshots bypass the LRU, so the spilled entry is read back from disk
+    let snap = store.snapshot().unwrap();
+    let errs: Vec<DbError> = [&b"cold"[..], b"hot"].iter().filter_map(|k| snap.get(&kv, k).err()).collect();
+    assert!(matches!(errs.as_slice(), [DbError::Corruption(_)]));
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,78 @@
+//! Tests for merge operators
+
+use std::sync::Arc;
+use tonledb_core::merge::MergeOperator;
+use tonledb_core::{DbError, Space, Storage};
+use tonledb_storage::{InMemoryStore, StorageOptions};
+
+fn with_operator(store: &InMemoryStore, space: &Space, op: MergeOperator) {
+    store.set_space_options(space.clone(), StorageOptions { merge_operator: Some(op), ..Default::default() }).unwrap();
+}
+
+#[test]
+fn test_concurrent_counter_merges_are_not_lost() {
+    let store = Arc::new(InMemoryStore::new(100));
+    let counters = Space("counters".to_string());
+    with_operator(&store, &counters, MergeOperator::Counter);
+
+    let workers: Vec<_> = (0..8)
+        .map(|_| {
+            let store = store.clone();
+            let counters = counters.clone();
+            std::thread::spawn(move || {
+                for _ in 0..250 {
+                    store.merge(&counters, b"hits".to_vec(), b"1".to_vec()).unwrap();
+                }
+            })
+        })
+        .collect();
+    for w in workers {
+        w.join().unwrap();
+    }
+    store.merge(&counters, b"hits".to_vec(), b"-5".to_vec()).unwrap();
+    assert_eq!(store.get(&counters, b"hits").unwrap(), Some(b"1995".to_vec()));
+}
+
+#[test]
+fn test_append_and_set_union() {
+    let store = InMemoryStore::new(100);
+    let log = Space("log".to_string());
+    with_operator(&store, &log, MergeOperator::Append { separator: "\n".into() });
+    store.merge(&log, b"l".to_vec(), b"a".to_vec()).unwrap();
+    store.merge(&log, b"l".to_vec(), b"b".to_vec()).unwrap();
+    assert_eq!(store.get(&log, b"l").unwrap(), Some(b"a\nb".to_vec()));
+
+    let op = MergeOperator::SetUnion;
+    let v = op.apply(None, br#"["x","y"]"#).unwrap();
+    let v = op.apply(Some(&v), br#""x""#).unwrap();
+    let v = op.apply(Some(&v), br#"["z"]"#).unwrap();
+    assert_eq!(serde_json::from_slice::<Vec<String>>(&v).unwrap(), vec!["x", "y", "z"]);
+}
+
+#[test]
+fn test_merge_without_operator_or_with_bad_operand_is_rejected() {
+    let store = InMemoryStore::new(100);
+    let kv = Space("kv".to_string());
+    assert!(matches!(store.merge(&kv, b"k".to_vec(), b"1".to_vec()), Err(DbError::Invalid(_))));
+
+    with_operator(&store, &kv, MergeOperator::Counter);
+    store.put(&kv, b"k".to_vec(), b"not a number".to_vec()).unwrap();
+    assert!(matches!(store.merge(&kv, b"k".to_vec(), b"1".to_vec()), Err(DbError::Invalid(_))));
+    assert_eq!(store.get(&kv, b"k").unwrap(), Some(b"not a number".to_vec()));
+}
+
+#[test]
+fn test_merged_value_survives_wal_replay() {
+    let path = std::env::temp_dir().join(format!("tonledb-merge-{}.wal", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let path = path.to_string_lossy().into_owned();
+    let counters = Space("counters".to_string());
+    {
+        let store = InMemoryStore::with_wal(&path, 100).unwrap();
+        with_operator(&store, &counters, MergeOperator::Counter);
+        store.merge(&counters, b"c".to_vec(), b"3".to_vec()).unwrap();
+        store.merge(&counters, b"c".to_vec(), b"4".to_vec()).unwrap();
+    }
+    let store = InMemoryStore::with_wal(&path, 100).unwrap();
+    assert_eq!(store.get(&counters, b"c").unwrap(), Some(b"7".to_vec()));
+}
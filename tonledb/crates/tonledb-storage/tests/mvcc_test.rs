@@ -44,4 +44,54 @@ fn test_mvcc_fallback_to_current() {
     // Get versioned should fallback to current value
     let result = store.get_versioned(&space, &key, 1).unwrap();
     assert_eq!(result, Some(value));
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_mvcc_delete_visibility() {
+    let store = InMemoryStore::new(1000);
+    let space = Space("test".to_string());
+    let key = b"key".to_vec();
+
+    store.put_versioned(&space, key.clone(), b"v1".to_vec(), 10).unwrap();
+    store.del_versioned(&space, &key, 20).unwrap();
+
+    // Before the first version the key did not exist
+    assert_eq!(store.get_versioned(&space, &key, 5).unwrap(), None);
+    assert_eq!(store.get_versioned(&space, &key, 15).unwrap(), Some(b"v1".to_vec()));
+    assert_eq!(store.get_versioned(&space, &key, 25).unwrap(), None);
+    assert_eq!(store.get(&space, &key).unwrap(), None);
+    assert_eq!(store.latest_version(&space, &key).unwrap(), Some(20));
+}
+
+#[test]
+fn test_mvcc_unversioned_value_seeds_chain() {
+    let store = InMemoryStore::new(1000);
+    let space = Space("test".to_string());
+    let key = b"key".to_vec();
+
+    store.put(&space, key.clone(), b"legacy".to_vec()).unwrap();
+    store.put_versioned(&space, key.clone(), b"v1".to_vec(), 10).unwrap();
+
+    assert_eq!(store.get_versioned(&space, &key, 5).unwrap(), Some(b"legacy".to_vec()));
+    assert_eq!(store.get_versioned(&space, &key, 10).unwrap(), Some(b"v1".to_vec()));
+}
+
+#[test]
+fn test_mvcc_gc_old_versions() {
+    let store = InMemoryStore::new(1000);
+    let space = Space("test".to_string());
+    let key = b"key".to_vec();
+
+    store.put_versioned(&space, key.clone(), b"v1".to_vec(), 10).unwrap();
+    store.put_versioned(&space, key.clone(), b"v2".to_vec(), 20).unwrap();
+    store.put_versioned(&space, key.clone(), b"v3".to_vec(), 30).unwrap();
+
+    // A reader at 25 still needs v2 and v3
+    assert_eq!(store.gc_versions(25).unwrap(), 1);
+    assert_eq!(store.get_versioned(&space, &key, 25).unwrap(), Some(b"v2".to_vec()));
+
+    // Once every reader is past 30 the chain collapses into the live value
+    assert_eq!(store.gc_versions(35).unwrap(), 2);
+    assert_eq!(store.latest_version(&space, &key).unwrap(), None);
+    assert_eq!(store.get_versioned(&space, &key, 1).unwrap(), Some(b"v3".to_vec()));
+}
@@ -0,0 +1,100 @@
+//! Tests for quorum reads/writes and read repair
+
+use std::sync::Arc;
+use tonledb_core::{Consistency, DbError, Result, Space, Storage};
+use tonledb_storage::InMemoryStore;
+use tonledb_storage::replicated::ReplicatedStore;
+
+/// A replica that is always unreachable
+struct DownReplica;
+
+impl Storage for DownReplica {
+    fn get(&self, _: &Space, _: &[u8]) -> Result<Option<Vec<u8>>> { Err(DbError::Storage("down".into())) }
+    fn put(&self, _: &Space, _: Vec<u8>, _: Vec<u8>) -> Result<()> { Err(DbError::Storage("down".into())) }
+    fn del(&self, _: &Space, _: &[u8]) -> Result<()> { Err(DbError::Storage("down".into())) }
+    fn scan_prefix(&self, _: &Space, _: &[u8]) -> Result<Box<dyn Iterator<Item=(Vec<u8>, Vec<u8>)> + Send>> { Err(DbError::Storage("down".into())) }
+}
+
+fn replicas(n: usize) -> Vec<Arc<InMemoryStore>> {
+    (0..n).map(|_| Arc::new(InMemoryStore::new(100))).collect()
+}
+
+#[test]
+fn test_read_repair_restores_stale_replica() {
+    let reps = replicas(3);
+    let store = ReplicatedStore::new(
+        reps.iter().map(|r| r.clone() as Arc<dyn Storage>).collect(),
+        Consistency::Quorum,
+        Consistency::All,
+    ).unwrap();
+    let space = Space("kv".to_string());
+
+    store.put(&space, b"k".to_vec(), b"v1".to_vec()).unwrap();
+    // Simulate a replica that missed the write
+    reps[0].del(&space, b"k").unwrap();
+
+    assert_eq!(store.get_with(&space, b"k", Consistency::All).unwrap(), Some(b"v1".to_vec()));
+    assert!(reps[0].get(&space, b"k").unwrap().is_some());
+}
+
+#[test]
+fn test_deletes_are_not_resurrected() {
+    let reps = replicas(3);
+    let store = ReplicatedStore::new(
+        reps.iter().map(|r| r.clone() as Arc<dyn Storage>).collect(),
+        Consistency::Quorum,
+        Consistency::Quorum,
+    ).unwrap();
+    let space = Space("kv".to_string());
+
+    store.put_with(&space, b"k".to_vec(), b"v1".to_vec(), Consistency::All).unwrap();
+    store.del_with(&space, b"k", Consistency::All).unwrap();
+
+    assert_eq!(store.get_with(&space, b"k", Consistency::All).unwrap(), None);
+    assert_eq!(store.scan_prefix(&space, b"").unwrap().count(), 0);
+}
+
+#[test]
+fn test_quorum_not_reached() {
+    let up = Arc::new(InMemoryStore::new(100));
+    let store = ReplicatedStore::new(
+        vec![up as Arc<dyn Storage>, Arc::new(DownReplica), Arc::new(DownReplica)],
+        Consistency::One,
+        Consistency::One,
+    ).unwrap();
+    let space = Space("kv".to_string());
+
+    assert!(store.put(&space, b"k".to_vec(), b"v".to_vec()).is_ok());
+    assert_eq!(store.get(&space, b"k").unwrap(), Some(b"v".to_vec()));
+    assert!(store.get_with(&space, b"k", Consistency::Quorum).is_err());
+    assert!(store.put_with(&space, b"k".to_vec(), b"v".to_vec(), Consistency::All).is_err());
+}
+
+#[test]
+fn test_pages_skip_tombstones_and_take_newest_copies() {
+    let reps = replicas(3);
+    let store = ReplicatedStore::new(
+        reps.iter().map(|r| r.clone() as Arc<dyn Storage>).collect(),
+        Consistency::Quorum,
+        Consistency::All,
+    ).unwrap();
+    let space = Space("kv".to_string());
+    for i in 0..6 {
+        store.put(&space, format!("k{}", i).into_bytes(), b"v1".to_vec()).unwrap();
+    }
+    // Tombstones fill the first replica pages; one replica missed an update
+    store.del(&space, b"k0").unwrap();
+    store.del(&space, b"k1").unwrap();
+    let stale = reps[0].get(&space, b"k3").unwrap();
+    store.put(&space, b"k3".to_vec(), b"v2".to_vec()).unwrap();
+    reps[0].put(&space, b"k3".to_vec(), stale.unwrap()).unwrap();
+
+    let page = store.scan_prefix_page(&space, b"k", None, 2).unwrap();
+    assert_eq!(page, vec![(b"k2".to_vec(), b"v1".to_vec()), (b"k3".to_vec(), b"v2".to_vec())]);
+    let page = store.scan_prefix_page(&space, b"k", Some(b"k3"), 2).unwrap();
+    assert_eq!(page.iter().map(|(k, _)| k.as_slice()).collect::<Vec<_>>(), [b"k4", b"k5"]);
+    let newest = store.scan_range(&space, b"k", None, true, 5).unwrap();
+    assert_eq!(newest.iter().map(|(k, _)| k.as_slice()).collect::<Vec<_>>(), [b"k5", b"k4", b"k3", b"k2"]);
+    let oldest = store.scan_range(&space, b"k1", Some(b"k4"), false, 1).unwrap();
+    assert_eq!(oldest, vec![(b"k2".to_vec(), b"v1".to_vec())]);
+}
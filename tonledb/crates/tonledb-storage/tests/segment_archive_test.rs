@@ -0,0 +1,36 @@
+//! Tests for shipping sealed WAL segments to an object store
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tonledb_core::object_store::{LocalObjectStore, ObjectStore};
+use tonledb_core::{Space, Storage};
+use tonledb_storage::{InMemoryStore, ObjectStoreArchiver};
+
+#[test]
+fn test_sealed_segments_are_uploaded() {
+    let dir = std::env::temp_dir().join(format!("tonledb-segarchive-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("db.wal").to_string_lossy().into_owned();
+    let objects = Arc::new(LocalObjectStore::new(dir.join("bucket")).unwrap());
+    let opts = tonledb_wal::WalOptions { segment_bytes: 256, ..Default::default() };
+    let store = InMemoryStore::with_wal_options(&path, 100, opts).unwrap();
+    store.set_wal_segment_archiver(Box::new(ObjectStoreArchiver::new(objects.clone(), "wal/"))).unwrap();
+    let space = Space("kv".to_string());
+    for i in 0..40 {
+        store.put(&space, format!("k{:02}", i).into_bytes(), vec![b'v'; 16]).unwrap();
+    }
+
+    // Uploads run in the background
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut uploaded = objects.list("wal/").unwrap();
+    while uploaded.len() < 2 && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(20));
+        uploaded = objects.list("wal/").unwrap();
+    }
+    assert!(uploaded.len() > 1, "{:?}", uploaded);
+    assert_eq!(uploaded[0], "wal/00000001.seg");
+    let sealed = std::fs::read(format!("{}.00000001.seg", path)).unwrap();
+    assert_eq!(objects.get("wal/00000001.seg").unwrap(), Some(sealed));
+    let _ = std::fs::remove_dir_all(&dir);
+}
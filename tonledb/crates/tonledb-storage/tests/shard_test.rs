@@ -0,0 +1,53 @@
+//! Tests for the lock-striped InMemoryStore
+
+use std::sync::Arc;
+use std::thread;
+use tonledb_core::{Space, Storage};
+use tonledb_storage::InMemoryStore;
+
+#[test]
+fn test_concurrent_writers_across_shards() {
+    let store = Arc::new(InMemoryStore::with_shards(1000, 8));
+    let space = Space("data".to_string());
+    let handles: Vec<_> = (0..8)
+        .map(|t| {
+            let (store, space) = (store.clone(), space.clone());
+            thread::spawn(move || {
+                for i in 0..200 {
+                    store.put(&space, format!("k/{}/{:03}", t, i).into_bytes(), vec![t as u8]).unwrap();
+                }
+            })
+        })
+        .collect();
+    for h in handles {
+        h.join().unwrap();
+    }
+    assert_eq!(store.scan_prefix(&space, b"k/").unwrap().count(), 1600);
+    assert_eq!(store.get(&space, b"k/3/042").unwrap(), Some(vec![3]));
+}
+
+#[test]
+fn test_scan_merges_shards_in_key_order() {
+    let store = InMemoryStore::with_shards(10, 4);
+    let space = Space("data".to_string());
+    for k in ["d", "a", "c", "b", "e"] {
+        store.put(&space, k.as_bytes().to_vec(), b"v".to_vec()).unwrap();
+    }
+    store.put(&Space("other".to_string()), b"c".to_vec(), b"x".to_vec()).unwrap();
+    let snap = store.snapshot().unwrap();
+    store.del(&space, b"c").unwrap();
+
+    let keys: Vec<Vec<u8>> = snap.scan_prefix(&space, b"").unwrap().map(|(k, _)| k).collect();
+    assert_eq!(keys, vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec(), b"d".to_vec(), b"e".to_vec()]);
+    assert_eq!(store.scan_prefix(&space, b"").unwrap().count(), 4);
+}
+
+#[test]
+fn test_single_shard_behaves_like_unsharded() {
+    let store = InMemoryStore::with_shards(10, 1);
+    let space = Space("data".to_string());
+    store.put(&space, b"a".to_vec(), b"1".to_vec()).unwrap();
+    store.put_versioned(&space, b"a".to_vec(), b"2".to_vec(), 100).unwrap();
+    assert_eq!(store.get_versioned(&space, b"a", 50).unwrap(), Some(b"1".to_vec()));
+    assert_eq!(store.get(&space, b"a").unwrap(), Some(b"2".to_vec()));
+}
@@ -0,0 +1,40 @@
+//! Tests for consistent snapshot reads
+
+use tonledb_core::{Space, Storage};
+use tonledb_storage::InMemoryStore;
+
+#[test]
+fn test_snapshot_ignores_later_writes() {
+    let store = InMemoryStore::new(1000);
+    let space = Space("data".to_string());
+    store.put(&space, b"a".to_vec(), b"1".to_vec()).unwrap();
+    store.put(&space, b"b".to_vec(), b"2".to_vec()).unwrap();
+
+    let snap = store.snapshot().unwrap();
+
+    store.put(&space, b"a".to_vec(), b"changed".to_vec()).unwrap();
+    store.put(&space, b"c".to_vec(), b"3".to_vec()).unwrap();
+    store.del(&space, b"b").unwrap();
+
+    assert_eq!(snap.get(&space, b"a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(snap.get(&space, b"b").unwrap(), Some(b"2".to_vec()));
+    assert_eq!(snap.get(&space, b"c").unwrap(), None);
+
+    let keys: Vec<Vec<u8>> = snap.scan_prefix(&space, b"").unwrap().map(|(k, _)| k).collect();
+    assert_eq!(keys, vec![b"a".to_vec(), b"b".to_vec()]);
+
+    // Live store sees the new state
+    assert_eq!(store.get(&space, b"a").unwrap(), Some(b"changed".to_vec()));
+    assert_eq!(store.get(&space, b"b").unwrap(), None);
+}
+
+#[test]
+fn test_snapshot_scan_is_space_scoped() {
+    let store = InMemoryStore::new(1000);
+    store.put(&Space("kv".to_string()), b"k1".to_vec(), b"v".to_vec()).unwrap();
+    store.put(&Space("data".to_string()), b"k2".to_vec(), b"v".to_vec()).unwrap();
+
+    let snap = store.snapshot().unwrap();
+    let kv: Vec<_> = snap.scan_prefix(&Space("kv".to_string()), b"k").unwrap().collect();
+    assert_eq!(kv, vec![(b"k1".to_vec(), b"v".to_vec())]);
+}
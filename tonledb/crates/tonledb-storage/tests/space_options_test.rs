@@ -0,0 +1,50 @@
+//! Tests for per-space storage options
+
+use tonledb_core::{Space, Storage};
+use tonledb_storage::{InMemoryStore, StorageOptions};
+use tonledb_storage::compression::{Codec, CompressionOptions};
+
+#[test]
+fn test_space_compression_is_transparent() {
+    let store = InMemoryStore::new(1000);
+    let data = Space("data".to_string());
+    store.put(&data, b"before".to_vec(), vec![b'a'; 300]).unwrap();
+
+    let lz4 = CompressionOptions { codec: Codec::Lz4, min_size: 16 };
+    store.set_space_options(data.clone(), StorageOptions { compression: Some(lz4), ..Default::default() }).unwrap();
+    store.put(&data, b"after".to_vec(), vec![b'b'; 300]).unwrap();
+
+    assert_eq!(store.get(&data, b"before").unwrap(), Some(vec![b'a'; 300]));
+    assert_eq!(store.get(&data, b"after").unwrap(), Some(vec![b'b'; 300]));
+    let snap = store.snapshot().unwrap();
+    assert_eq!(snap.scan_prefix(&data, b"").unwrap().count(), 2);
+
+    // Turning compression off re-encodes the space back to raw values
+    store.set_space_options(data.clone(), StorageOptions::default()).unwrap();
+    assert_eq!(store.get(&data, b"after").unwrap(), Some(vec![b'b'; 300]));
+}
+
+#[test]
+fn test_space_default_ttl_expires_keys() {
+    let store = InMemoryStore::new(1000);
+    let sessions = Space("sessions".to_string());
+    let kv = Space("kv".to_string());
+    store.set_space_options(sessions.clone(), StorageOptions { default_ttl_ms: Some(0), ..Default::default() }).unwrap();
+
+    store.put(&sessions, b"s1".to_vec(), b"v".to_vec()).unwrap();
+    store.put(&kv, b"k1".to_vec(), b"v".to_vec()).unwrap();
+
+    assert_eq!(store.get(&sessions, b"s1").unwrap(), None);
+    assert_eq!(store.scan_prefix(&sessions, b"").unwrap().count(), 0);
+    assert_eq!(store.get(&kv, b"k1").unwrap(), Some(b"v".to_vec()));
+}
+
+#[test]
+fn test_space_cache_capacity() {
+    let store = InMemoryStore::new(1000);
+    let catalog = Space("catalog".to_string());
+    store.set_space_options(catalog.clone(), StorageOptions { cache_capacity: Some(0), ..Default::default() }).unwrap();
+    store.put(&catalog, b"col/a".to_vec(), b"{}".to_vec()).unwrap();
+    assert_eq!(store.get(&catalog, b"col/a").unwrap(), Some(b"{}".to_vec()));
+    assert_eq!(store.space_options(&catalog).cache_capacity, Some(0));
+}
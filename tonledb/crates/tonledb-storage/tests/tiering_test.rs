@@ -0,0 +1,121 @@
+//! Tests for hot/cold tiering
+
+use std::sync::Arc;
+use tonledb_core::object_store::{LocalObjectStore, ObjectStore};
+use tonledb_core::{Space, Storage};
+use tonledb_storage::{InMemoryStore, TierPolicy, TieredStorage};
+
+fn cold_dir(name: &str) -> std::path::PathBuf {
+    let p = std::env::temp_dir().join(format!("tonledb-tier-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&p);
+    p
+}
+
+fn tiered(dir: &std::path::Path, space: &Space) -> TieredStorage<InMemoryStore> {
+    let cold: Arc<dyn ObjectStore> = Arc::new(LocalObjectStore::new(dir).unwrap());
+    TieredStorage::new(InMemoryStore::new(100), cold, TierPolicy { idle_ms: 0 }).unwrap().with_space(space.clone())
+}
+
+fn idle() {
+    std::thread::sleep(std::time::Duration::from_millis(5));
+}
+
+#[test]
+fn test_idle_keys_are_demoted_and_promoted_on_read() {
+    let dir = cold_dir("promote");
+    let space = Space("data".into());
+    let store = tiered(&dir, &space);
+    store.put(&space, b"a".to_vec(), b"1".to_vec()).unwrap();
+    store.put(&space, b"b".to_vec(), b"2".to_vec()).unwrap();
+    idle();
+
+    assert_eq!(store.demote_idle().unwrap(), 2);
+    assert!(store.is_cold(&space, b"a"));
+    assert_eq!(store.inner().get(&space, b"a").unwrap(), None);
+
+    assert_eq!(store.get(&space, b"a").unwrap(), Some(b"1".to_vec()));
+    assert!(!store.is_cold(&space, b"a"));
+    assert_eq!(store.inner().get(&space, b"a").unwrap(), Some(b"1".to_vec()));
+    let stats = store.stats();
+    assert_eq!((stats.cold_keys, stats.demotions, stats.promotions), (1, 2, 1));
+
+    // Recently read keys stay hot
+    assert_eq!(store.demote_idle().unwrap(), 0);
+}
+
+#[test]
+fn test_untiered_spaces_stay_hot() {
+    let dir = cold_dir("untiered");
+    let space = Space("data".into());
+    let catalog = Space("catalog".into());
+    let store = tiered(&dir, &space);
+    store.put(&catalog, b"t".to_vec(), b"x".to_vec()).unwrap();
+    idle();
+    assert_eq!(store.demote_idle().unwrap(), 0);
+    assert_eq!(store.inner().get(&catalog, b"t").unwrap(), Some(b"x".to_vec()));
+}
+
+#[test]
+fn test_scans_and_writes_see_both_tiers() {
+    let dir = cold_dir("scan");
+    let space = Space("data".into());
+    let store = tiered(&dir, &space);
+    for k in ["k1", "k2", "k3"] {
+        store.put(&space, k.as_bytes().to_vec(), k.as_bytes().to_vec()).unwrap();
+    }
+    idle();
+    store.demote_idle().unwrap();
+    store.put(&space, b"k2".to_vec(), b"new".to_vec()).unwrap();
+    store.del(&space, b"k3").unwrap();
+    store.put(&space, b"k4".to_vec(), b"k4".to_vec()).unwrap();
+
+    let rows: Vec<_> = store.scan_prefix(&space, b"k").unwrap().collect();
+    assert_eq!(rows, vec![
+        (b"k1".to_vec(), b"k1".to_vec()),
+        (b"k2".to_vec(), b"new".to_vec()),
+        (b"k4".to_vec(), b"k4".to_vec()),
+    ]);
+    // Scans read cold values in place
+    assert!(store.is_cold(&space, b"k1"));
+    assert_eq!(store.stats().cold_keys, 1);
+    assert_eq!(store.delete_prefix(&space, b"k").unwrap(), 3);
+    assert_eq!(store.scan_prefix(&space, b"").unwrap().count(), 0);
+}
+
+#[test]
+fn test_cold_tier_survives_reopen() {
+    let dir = cold_dir("reopen");
+    let space = Space("data".into());
+    {
+        let store = tiered(&dir, &space);
+        store.put(&space, vec![0, 255], b"bin".to_vec()).unwrap();
+        idle();
+        store.demote_idle().unwrap();
+    }
+    let store = tiered(&dir, &space);
+    assert!(store.is_cold(&space, &[0, 255]));
+    assert_eq!(store.get(&space, &[0, 255]).unwrap(), Some(b"bin".to_vec()));
+}
+
+#[test]
+fn test_pages_and_ranges_merge_both_tiers() {
+    let dir = cold_dir("pages");
+    let space = Space("data".into());
+    let store = tiered(&dir, &space);
+    for k in ["a", "c", "e"] {
+        store.put(&space, k.as_bytes().to_vec(), k.to_uppercase().into_bytes()).unwrap();
+    }
+    idle();
+    store.demote_idle().unwrap();
+    for k in ["b", "d"] {
+        store.put(&space, k.as_bytes().to_vec(), k.to_uppercase().into_bytes()).unwrap();
+    }
+    let keys = |rows: Vec<(Vec<u8>, Vec<u8>)>| rows.into_iter().map(|(k, v)| format!("{}={}", String::from_utf8(k).unwrap(), String::from_utf8(v).unwrap())).collect::<Vec<_>>();
+
+    assert_eq!(keys(store.scan_prefix_page(&space, b"", None, 2).unwrap()), ["a=A", "b=B"]);
+    assert_eq!(keys(store.scan_prefix_page(&space, b"", Some(b"b"), 2).unwrap()), ["c=C", "d=D"]);
+    assert_eq!(keys(store.scan_range(&space, b"b", Some(b"e"), false, 10).unwrap()), ["b=B", "c=C", "d=D"]);
+    assert_eq!(keys(store.scan_range(&space, b"", None, true, 3).unwrap()), ["e=E", "d=D", "c=C"]);
+    // Read in place: a scan does not promote
+    assert!(store.is_cold(&space, b"c"));
+}
@@ -0,0 +1,74 @@
+//! Tests for storage-level TTLs and the background sweeper
+
+use std::sync::Arc;
+use std::time::Duration;
+use tonledb_core::{Space, Storage};
+use tonledb_storage::InMemoryStore;
+use tonledb_storage::ttl::TtlSweeper;
+
+fn wal_path(name: &str) -> String {
+    let p = std::env::temp_dir().join(format!("tonledb-ttl-{}-{}.wal", name, std::process::id()));
+    let _ = std::fs::remove_file(&p);
+    p.to_string_lossy().into_owned()
+}
+
+#[test]
+fn test_expired_keys_read_as_absent_and_are_swept() {
+    let store = InMemoryStore::new(100);
+    let space = Space("kv".to_string());
+    store.put_with_ttl(&space, b"short".to_vec(), b"v".to_vec(), 20).unwrap();
+    store.put_with_ttl(&space, b"long".to_vec(), b"v".to_vec(), 60_000).unwrap();
+    store.put(&space, b"plain".to_vec(), b"v".to_vec()).unwrap();
+    assert_eq!(store.get(&space, b"short").unwrap(), Some(b"v".to_vec()));
+
+    std::thread::sleep(Duration::from_millis(40));
+    assert_eq!(store.get(&space, b"short").unwrap(), None);
+    let before = store.memory_usage();
+    assert_eq!(store.sweep_expired().unwrap(), 1);
+    assert!(store.memory_usage() < before);
+    assert_eq!(store.sweep_expired().unwrap(), 0);
+    assert_eq!(store.scan_prefix(&space, b"").unwrap().count(), 2);
+}
+
+#[test]
+fn test_plain_put_clears_ttl() {
+    let store = InMemoryStore::new(100);
+    let space = Space("kv".to_string());
+    store.put_with_ttl(&space, b"k".to_vec(), b"1".to_vec(), 20).unwrap();
+    store.put(&space, b"k".to_vec(), b"2".to_vec()).unwrap();
+    std::thread::sleep(Duration::from_millis(40));
+    assert_eq!(store.sweep_expired().unwrap(), 0);
+    assert_eq!(store.get(&space, b"k").unwrap(), Some(b"2".to_vec()));
+}
+
+#[test]
+fn test_ttl_survives_wal_replay() {
+    let path = wal_path("replay");
+    let space = Space("kv".to_string());
+    {
+        let store = InMemoryStore::with_wal(&path, 100).unwrap();
+        store.put_with_ttl(&space, b"gone".to_vec(), b"v".to_vec(), 20).unwrap();
+        store.put_with_ttl(&space, b"kept".to_vec(), b"v".to_vec(), 60_000).unwrap();
+    }
+    std::thread::sleep(Duration::from_millis(40));
+    let store = InMemoryStore::with_wal(&path, 100).unwrap();
+    assert_eq!(store.get(&space, b"gone").unwrap(), None);
+    assert_eq!(store.get(&space, b"kept").unwrap(), Some(b"v".to_vec()));
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_background_sweeper() {
+    let store = Arc::new(InMemoryStore::new(100));
+    let space = Space("kv".to_string());
+    store.put_with_ttl(&space, b"k".to_vec(), vec![0; 64], 10).unwrap();
+    let used = store.memory_usage();
+    let sweeper = TtlSweeper::spawn(store.clone(), Duration::from_millis(10));
+    let mut waited = 0;
+    while store.memory_usage() == used && waited < 2000 {
+        std::thread::sleep(Duration::from_millis(10));
+        waited += 10;
+    }
+    drop(sweeper);
+    assert_eq!(store.memory_usage(), 0);
+}
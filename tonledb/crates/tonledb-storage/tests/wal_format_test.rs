@@ -0,0 +1,198 @@
+//! Tests for replaying the store from its WAL
+
+use tonledb_core::{Space, Storage};
+use tonledb_storage::InMemoryStore;
+
+fn wal_path(name: &str) -> String {
+    let p = std::env::temp_dir().join(format!("tonledb-walfmt-{}-{}.wal", name, std::process::id()));
+    let _ = std::fs::remove_file(&p);
+    p.to_string_lossy().into_owned()
+}
+
+#[test]
+fn test_separator_bytes_survive_replay() {
+    let path = wal_path("bytes");
+    let space = Space("tab\tspace".to_string());
+    {
+        let store = InMemoryStore::with_wal(&path, 100).unwrap();
+        store.put(&space, b"k\t1".to_vec(), b"line one\nline two\ttabbed".to_vec()).unwrap();
+        store.put(&space, b"k\n2".to_vec(), vec![0, b'\n', 0xff]).unwrap();
+        store.put_with_ttl(&space, b"k\t3".to_vec(), b"\n".to_vec(), 3_600_000).unwrap();
+        store.del(&space, b"k\n2").unwrap();
+    }
+    let store = InMemoryStore::with_wal(&path, 100).unwrap();
+    assert_eq!(store.get(&space, b"k\t1").unwrap(), Some(b"line one\nline two\ttabbed".to_vec()));
+    assert_eq!(store.get(&space, b"k\n2").unwrap(), None);
+    assert_eq!(store.get(&space, b"k\t3").unwrap(), Some(b"\n".to_vec()));
+}
+
+#[test]
+fn test_legacy_wal_is_replayed() {
+    let path = wal_path("legacy");
+    std::fs::write(&path, b"data\tk1\tv1\n\0ttl\tdata\tk1\t99999999999999\ndata\tk2\tv2\n\0del\tdata\tk2\n").unwrap();
+    let data = Space("data".to_string());
+    {
+        let store = InMemoryStore::with_wal(&path, 100).unwrap();
+        assert_eq!(store.get(&data, b"k1").unwrap(), Some(b"v1".to_vec()));
+        assert_eq!(store.get(&data, b"k2").unwrap(), None);
+        store.put(&data, b"k3".to_vec(), b"v3".to_vec()).unwrap();
+    }
+    // Converted on the first open, so the new record sits in the binary log with the old ones
+    assert!(std::fs::read(&path).unwrap().starts_with(b"TLDBWAL\0"));
+    let store = InMemoryStore::with_wal(&path, 100).unwrap();
+    assert_eq!(store.get(&data, b"k1").unwrap(), Some(b"v1".to_vec()));
+    assert_eq!(store.get(&data, b"k3").unwrap(), Some(b"v3".to_vec()));
+}
+
+#[test]
+fn test_segmented_wal_is_replayed_across_segments() {
+    let dir = std::env::temp_dir().join(format!("tonledb-walfmt-seg-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("db.wal").to_string_lossy().into_owned();
+    let opts = tonledb_wal::WalOptions { segment_bytes: 256, ..Default::default() };
+    let space = Space("kv".to_string());
+    {
+        let store = InMemoryStore::with_wal_options(&path, 100, opts).unwrap();
+        for i in 0..50 {
+            store.put(&space, format!("k{:02}", i).into_bytes(), vec![b'v'; 16]).unwrap();
+        }
+        store.del(&space, b"k07").unwrap();
+    }
+    assert!(std::fs::read_dir(&dir).unwrap().count() > 2);
+    let store = InMemoryStore::with_wal_options(&path, 100, opts).unwrap();
+    assert_eq!(store.scan_prefix(&space, b"k").unwrap().count(), 49);
+    assert_eq!(store.get(&space, b"k07").unwrap(), None);
+
+    // Compaction rewrites the log, leaving only the active segment
+    store.compact_space(&space).unwrap();
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_group_committed_writes_survive_reopen() {
+    let path = wal_path("group");
+    let opts = tonledb_wal::WalOptions { sync_commits: true, ..Default::default() };
+    let space = Space("kv".to_string());
+    {
+        let store = std::sync::Arc::new(InMemoryStore::with_wal_options(&path, 100, opts).unwrap());
+        let writers: Vec<_> = (0..4)
+            .map(|t| {
+                let (store, space) = (store.clone(), space.clone());
+                std::thread::spawn(move || {
+                    for i in 0..25 {
+                        store.put(&space, format!("{}-{}", t, i).into_bytes(), b"v".to_vec()).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for w in writers {
+            w.join().unwrap();
+        }
+        store.delete_prefix(&space, b"0-").unwrap();
+    }
+    let store = InMemoryStore::with_wal(&path, 100).unwrap();
+    assert_eq!(store.scan_prefix(&space, b"").unwrap().count(), 75);
+}
+
+#[test]
+fn test_checkpoint_bounds_replay_and_keeps_later_writes() {
+    let dir = std::env::temp_dir().join(format!("tonledb-walfmt-ckpt-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("db.wal").to_string_lossy().into_owned();
+    let opts = tonledb_wal::WalOptions { segment_bytes: 256, ..Default::default() };
+    let space = Space("kv".to_string());
+    {
+        let store = InMemoryStore::with_wal_options(&path, 100, opts).unwrap();
+        for i in 0..50 {
+            store.put(&space, format!("k{:02}", i).into_bytes(), vec![b'v'; 16]).unwrap();
+        }
+        store.put_with_ttl(&space, b"ttl".to_vec(), b"t".to_vec(), 3_600_000).unwrap();
+        assert!(store.checkpoint().unwrap().is_some());
+        assert_eq!(store.checkpoint().unwrap(), None);
+        // Only the checkpoint and the fresh active segment are left to replay
+        let names: Vec<String> = std::fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        assert!(!names.iter().any(|n| n.ends_with(".seg")), "{:?}", names);
+
+        store.del(&space, b"k07").unwrap();
+        store.delete_prefix(&space, b"k1").unwrap();
+        store.put(&space, b"late".to_vec(), b"l".to_vec()).unwrap();
+    }
+    let store = InMemoryStore::with_wal_options(&path, 100, opts).unwrap();
+    assert_eq!(store.scan_prefix(&space, b"k").unwrap().count(), 39);
+    assert_eq!(store.get(&space, b"k07").unwrap(), None);
+    assert_eq!(store.get(&space, b"late").unwrap(), Some(b"l".to_vec()));
+    assert_eq!(store.get(&space, b"ttl").unwrap(), Some(b"t".to_vec()));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_transaction_writes_replay_together() {
+    use tonledb_core::transaction::TransactionManager;
+    let path = wal_path("txn");
+    let space = Space("kv".to_string());
+    {
+        let store = InMemoryStore::with_wal(&path, 100).unwrap();
+        store.put(&space, b"gone".to_vec(), b"x".to_vec()).unwrap();
+        let manager = TransactionManager::new();
+        let txn = manager.begin().unwrap();
+        manager.put(txn, space.clone(), b"a".to_vec(), b"1".to_vec()).unwrap();
+        manager.put(txn, space.clone(), b"b".to_vec(), b"2".to_vec()).unwrap();
+        manager.delete(txn, space.clone(), b"gone".to_vec()).unwrap();
+        manager.commit(&store, txn).unwrap();
+    }
+    let kinds: Vec<_> = {
+        let mut wal = tonledb_wal::Wal::open(&path).unwrap();
+        wal.replay_records().unwrap().into_iter().map(|r| r.kind).collect()
+    };
+    assert_eq!(kinds.len(), 4);
+    let store = InMemoryStore::with_wal(&path, 100).unwrap();
+    assert_eq!(store.get(&space, b"a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(store.get(&space, b"b").unwrap(), Some(b"2".to_vec()));
+    assert_eq!(store.get(&space, b"gone").unwrap(), None);
+}
+
+#[test]
+fn test_restore_undoes_a_delete_and_sticks() {
+    let path = wal_path("restore");
+    let space = Space("kv".to_string());
+    let opts = tonledb_wal::WalOptions::default();
+    let before_delete = {
+        let store = InMemoryStore::with_wal(&path, 100).unwrap();
+        store.put(&space, b"a".to_vec(), b"1".to_vec()).unwrap();
+        store.put(&space, b"b".to_vec(), b"2".to_vec()).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        let t = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis() as u64;
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        store.delete_prefix(&space, b"").unwrap();
+        t
+    };
+    {
+        let store = InMemoryStore::restore(&path, 100, opts, tonledb_wal::RecoveryTarget::Time(before_delete)).unwrap();
+        assert_eq!(store.get(&space, b"a").unwrap(), Some(b"1".to_vec()));
+        store.put(&space, b"c".to_vec(), b"3".to_vec()).unwrap();
+    }
+    // The restored state, and what was written after, is what replays from now on
+    let store = InMemoryStore::with_wal(&path, 100).unwrap();
+    assert_eq!(store.scan_prefix(&space, b"").unwrap().count(), 3);
+}
+
+#[test]
+fn test_incremental_export_holds_writes_since_the_last_one() {
+    let path = wal_path("export");
+    let out = format!("{}.wlx", path);
+    let space = Space("kv".to_string());
+    let store = InMemoryStore::with_wal(&path, 100).unwrap();
+    store.put(&space, b"a".to_vec(), b"1".to_vec()).unwrap();
+    let since = store.wal_end_lsn().unwrap();
+    store.put(&space, b"b".to_vec(), b"2".to_vec()).unwrap();
+    store.del(&space, b"a").unwrap();
+
+    let end = store.export_wal_since(since, std::path::Path::new(&out)).unwrap();
+    assert_eq!(Some(end), store.wal_end_lsn());
+    let kinds: Vec<_> = tonledb_wal::read_export(std::path::Path::new(&out)).unwrap().records.iter().map(|r| r.record.kind).collect();
+    assert_eq!(kinds, vec![tonledb_wal::RecordType::Put, tonledb_wal::RecordType::Delete]);
+    let _ = std::fs::remove_file(&out);
+}
@@ -0,0 +1,102 @@
+//! Time-stamped archive of WAL records.
+//!
+//! A `Wal` with an archive attached (`Wal::set_archive`) also writes every record it
+//! appends to the archive, stamped with the wall-clock time of the append. The
+//! archive is a directory of segment files named `<first record ms>-<n>.seg`, in the
+//! WAL format with the time (u64 BE) leading each payload; a segment is closed once
+//! it passes `segment_bytes` and a new one is started on every open. Rewriting the
+//! WAL leaves the archive alone, so it keeps the full history for delayed replicas
+//! and recovery tooling.
+
+use std::path::{Path, PathBuf};
+use crate::{read_records, Record, RecordType, Wal};
+
+const SEGMENT_EXT: &str = "seg";
+
+/// A record as archived, with the time it was appended to the WAL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchivedRecord {
+    pub at_ms: u64,
+    pub record: Record,
+}
+
+/// How far a reader has got: every record before it has been returned
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArchiveCursor {
+    segment: Option<String>,
+    records: usize,
+}
+
+/// Writing end of an archive directory
+pub struct WalArchive {
+    dir: PathBuf,
+    segment_bytes: u64,
+    current: Option<(Wal, u64)>,
+}
+
+impl WalArchive {
+    /// Archive into `dir` (created if missing), starting a new segment every `segment_bytes`
+    pub fn open(dir: &str, segment_bytes: u64) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        Ok(Self { dir: PathBuf::from(dir), segment_bytes: segment_bytes.max(1), current: None })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn append(&mut self, at_ms: u64, kind: RecordType, payload: &[u8]) -> anyhow::Result<()> {
+        if self.current.as_ref().is_none_or(|(_, written)| *written >= self.segment_bytes) {
+            self.current = Some((Wal::open(&self.new_segment(at_ms))?, 0));
+        }
+        let (wal, written) = self.current.as_mut().expect("segment opened above");
+        let rec = [&at_ms.to_be_bytes()[..], payload].concat();
+        wal.append_record(kind, &rec)?;
+        *written += rec.len() as u64;
+        Ok(())
+    }
+
+    fn new_segment(&self, at_ms: u64) -> String {
+        // Names sort by time; the counter only breaks ties within one millisecond
+        (0u32..)
+            .map(|n| self.dir.join(format!("{:020}-{:04}.{}", at_ms, n, SEGMENT_EXT)))
+            .find(|p| !p.exists())
+            .expect("a free segment name")
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// Records of the archive in `dir` after `cursor` that were appended at or before
+/// `until_ms`, oldest first, and the cursor to continue from
+pub fn read_archive(dir: &str, cursor: &ArchiveCursor, until_ms: u64) -> anyhow::Result<(Vec<ArchivedRecord>, ArchiveCursor)> {
+    let mut segments: Vec<String> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .filter(|n| n.ends_with(SEGMENT_EXT))
+            .collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e.into()),
+    };
+    segments.sort();
+    let mut out = Vec::new();
+    let mut next = cursor.clone();
+    for name in segments.into_iter().filter(|n| cursor.segment.as_ref().is_none_or(|c| n >= c)) {
+        let skip = if Some(&name) == cursor.segment.as_ref() { cursor.records } else { 0 };
+        // Read without opening: a segment being written must not be repaired under its writer
+        let records = read_records(&Path::new(dir).join(&name))?;
+        next = ArchiveCursor { segment: Some(name), records: skip };
+        // Each payload carries its own time, so the segment's stamps are not needed
+        for rec in records.into_iter().filter(|r| r.kind != RecordType::Time).skip(skip) {
+            let Some(stamp) = rec.payload.get(..8) else { anyhow::bail!("archived record without a timestamp") };
+            let at_ms = u64::from_be_bytes(stamp.try_into()?);
+            if at_ms > until_ms {
+                return Ok((out, next));
+            }
+            out.push(ArchivedRecord { at_ms, record: Record::new(rec.kind, rec.payload[8..].to_vec()) });
+            next.records += 1;
+        }
+    }
+    Ok((out, next))
+}
@@ -0,0 +1,67 @@
+//! Checkpoints.
+//!
+//! A checkpoint is a full copy of the state the log describes, written by the
+//! owner of the log as records to `<path>.<lsn>.ckpt`. Its LSN is the sequence
+//! number of the last sealed segment it covers: replay starts from the newest
+//! checkpoint and only reads the segments after it, so restart time is bounded by
+//! the checkpoint interval rather than the age of the log. Segments a checkpoint
+//! covers are retired like those of a `Wal::rewrite` (kept for `retention`, never
+//! deleted before they are archived).
+//!
+//! Taking one is three steps, so the state can be captured without holding up
+//! appends while it is written out:
+//!
+//! 1. `Wal::begin_checkpoint` seals the active segment; the caller captures its
+//!    state before anything else is appended.
+//! 2. `write_checkpoint` writes that state to a side file, synced and then renamed
+//!    into place, so a crash leaves either no checkpoint or a complete one.
+//! 3. `Wal::complete_checkpoint` retires the covered segments and older checkpoints.
+
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use crate::segment::{list_segments, segment_path, SegmentState};
+use crate::{file_header, frame, stamp, Record, Wal, FILE_HEADER_LEN};
+
+impl Wal {
+/// Seal everything appended so far for a checkpoint and return its LSN, or `None`
+/// if nothing was appended since the newest checkpoint.
+pub fn begin_checkpoint(&mut self) -> anyhow::Result<Option<u64>> {
+let segments = list_segments(&self.path)?;
+let covered = segments.iter().rev().find(|(_, state, _)| *state == SegmentState::Checkpoint).map_or(0, |(seq, _, _)| *seq);
+let sealed_since = segments.iter().any(|(seq, state, _)| *state == SegmentState::Sealed && *seq > covered);
+let active = self.active_bytes > FILE_HEADER_LEN as u64;
+if !active && !sealed_since { return Ok(None); }
+if active { self.rotate()?; }
+Ok(Some(self.next_seq - 1))
+}
+/// Retire the segments the checkpoint at `lsn` covers and delete older checkpoints.
+/// The checkpoint must already be written (see `write_checkpoint`).
+pub fn complete_checkpoint(&mut self, lsn: u64) -> anyhow::Result<()> {
+for (seq, state, seg) in list_segments(&self.path)? {
+    match state {
+        SegmentState::Sealed if seq <= lsn => std::fs::rename(&seg, segment_path(&self.path, seq, SegmentState::Retired))?,
+        SegmentState::Checkpoint if seq < lsn => std::fs::remove_file(&seg)?,
+        _ => {}
+    }
+}
+self.refresh_size()?;
+self.prune()
+}
+}
+
+/// Write `records` as the checkpoint at `lsn` of the log at `log`. Needs no access
+/// to the `Wal`, so appends carry on meanwhile.
+pub fn write_checkpoint(log: &Path, lsn: u64, records: &[Record]) -> anyhow::Result<()> {
+let dest = segment_path(log, lsn, SegmentState::Checkpoint);
+let mut tmp_path = dest.clone().into_os_string(); tmp_path.push(".part");
+let mut tmp = File::create(&tmp_path)?;
+tmp.write_all(&file_header())?;
+// Later than any record the checkpoint covers, for `Wal::replay_to` to tell whether it can start from it
+tmp.write_all(&stamp())?;
+for rec in records { tmp.write_all(&frame(rec.kind, &rec.payload))?; }
+tmp.sync_all()?; drop(tmp);
+std::fs::rename(&tmp_path, &dest)?;
+Ok(())
+}
@@ -0,0 +1,70 @@
+//! Group commit.
+//!
+//! With `WalOptions::sync_commits` on, `Wal::append_record` only buffers the record
+//! and hands out a ticket. A writer then calls `CommitQueue::commit` with its ticket,
+//! outside any lock on the `Wal`: the first one in becomes the leader, writes out
+//! everything buffered so far with one write and one fsync, and wakes every writer
+//! whose record that covered. Writers arriving while the leader syncs queue up behind
+//! it and are committed together by the next leader, so under concurrency many puts
+//! share each fsync.
+
+use std::fs::File;
+use std::sync::{Condvar, Mutex};
+
+#[derive(Default)]
+struct CommitState {
+    /// Every append with a ticket up to this one is on disk
+    durable: u64,
+    /// A leader is writing and syncing a batch
+    syncing: bool,
+}
+
+/// Durability of buffered appends, shared by every writer of one `Wal`
+#[derive(Default)]
+pub struct CommitQueue {
+    state: Mutex<CommitState>,
+    synced: Condvar,
+    /// Count fsyncs in `tonledb_metrics`
+    metrics: bool,
+}
+
+impl CommitQueue {
+    pub(crate) fn new(metrics: bool) -> Self {
+        Self { metrics, ..Default::default() }
+    }
+
+    /// Wait until the append that got `ticket` is durable. `take_batch` is called by
+    /// the leader to write out the buffered records (see `Wal::take_batch`); it returns
+    /// a handle on the file they went to and the last ticket among them.
+    pub fn commit(&self, ticket: u64, mut take_batch: impl FnMut() -> anyhow::Result<(File, u64)>) -> anyhow::Result<()> {
+        let mut st = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            if st.durable >= ticket {
+                return Ok(());
+            }
+            if st.syncing {
+                st = self.synced.wait(st).unwrap_or_else(|e| e.into_inner());
+                continue;
+            }
+            st.syncing = true;
+            drop(st);
+            let res = take_batch().and_then(|(file, upto)| {
+                file.sync_data()?;
+                if self.metrics { tonledb_metrics::inc_wal_fsyncs(); }
+                Ok(upto)
+            });
+            st = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            st.syncing = false;
+            self.synced.notify_all();
+            // On failure the waiting writers elect a new leader, which retries
+            st.durable = st.durable.max(res?);
+        }
+    }
+
+    /// Record that everything up to `ticket` is on disk, synced by other means
+    pub(crate) fn mark_durable(&self, ticket: u64) {
+        let mut st = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        st.durable = st.durable.max(ticket);
+        self.synced.notify_all();
+    }
+}
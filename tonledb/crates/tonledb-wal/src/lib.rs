@@ -1,15 +1,557 @@
+//! Write-ahead log.
+//!
+//! A WAL file starts with `MAGIC` and a big-endian `u16` format version, followed by
+//! binary records: `len (u32 BE) | crc32 (u32 BE) | type (u8) | payload`, where the
+//! CRC covers the type byte and the payload. Payloads are opaque, so they may hold
+//! any bytes, newlines and tabs included.
+//!
+//! The log can be split into fixed-size segments (see `segment`). Appends can be made
+//! durable in batches (`commit`), replay can start from a checkpoint (`checkpoint`) or
+//! stop at a point in time (`recovery`), readers can follow the log as it grows
+//! (`tail`), and what was appended since a position can be exported for incremental
+//! backups (`export`).
+//!
+//! Files in the version 1 format (newline-delimited, optionally `0x01` + hex CRC
+//! framed) are converted on open; their records come back as `RecordType::Raw`.
+
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+
+pub mod archive;
+pub mod checkpoint;
+pub mod commit;
+pub mod export;
+pub mod recovery;
+pub mod segment;
+pub mod tail;
+
+pub use archive::{read_archive, ArchiveCursor, ArchivedRecord, WalArchive};
+pub use checkpoint::write_checkpoint;
+pub use commit::CommitQueue;
+pub use export::{export_since, read_export, WalExport};
+pub use recovery::RecoveryTarget;
+pub use segment::{CopyArchiver, SegmentArchiver, WalOptions};
+pub use tail::{Lsn, WalRecord, WalTail};
+use segment::{list_segments, segment_path, ArchiveWorker, SegmentState};
+
+const MAGIC: &[u8; 8] = b"TLDBWAL\0";
+/// Format written by this version; the newline-delimited format is version 1
+pub const FORMAT_VERSION: u16 = 2;
+const FILE_HEADER_LEN: usize = MAGIC.len() + 2;
+const RECORD_HEADER_LEN: usize = 9;
+/// Marks a version 1 record framed as `MARK <crc32 as 8 hex digits> <payload>`
+const LEGACY_CHECKSUM_MARK: u8 = 0x01;
+const LEGACY_HEADER_LEN: usize = 9;
+
+/// What a record holds. The log itself only stores the tag; callers pick the encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum RecordType {
+    /// Untyped payload, and every record converted from a version 1 file
+    Raw = 0,
+    Put = 1,
+    Delete = 2,
+    DeletePrefix = 3,
+    Ttl = 4,
+    /// Written first by `Wal::rewrite`: replay drops everything before it, so a
+    /// checkpoint or segment left behind by a crash cannot outlive the rewrite. The
+    /// payload is the segment number of the new log (u64 BE).
+    Reset = 5,
+    /// Opens a transaction appended with `Wal::append_txn`; the payload is its id
+    TxnBegin = 6,
+    /// Closes the transaction; without it replay drops the transaction's records
+    TxnCommit = 7,
+    /// Wall-clock time of the records after it (epoch ms, u64 BE). Written before
+    /// every append, so the log can be replayed up to a point in time (see `recovery`).
+    Time = 8,
+}
+
+impl RecordType {
+    fn from_u8(b: u8) -> Option<Self> {
+        Some(match b {
+            0 => Self::Raw,
+            1 => Self::Put,
+            2 => Self::Delete,
+            3 => Self::DeletePrefix,
+            4 => Self::Ttl,
+            5 => Self::Reset,
+            6 => Self::TxnBegin,
+            7 => Self::TxnCommit,
+            8 => Self::Time,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub kind: RecordType,
+    pub payload: Vec<u8>,
+}
+
+impl Record {
+    pub fn new(kind: RecordType, payload: Vec<u8>) -> Self {
+        Self { kind, payload }
+    }
+}
 
+/// A WAL record whose checksum does not match its payload, or that is cut short
+/// anywhere but at the end of the log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorruptRecord { pub index: usize, pub offset: u64 }
 
-pub struct Wal { file: File }
+impl std::fmt::Display for CorruptRecord {
+fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { write!(f, "WAL record {} at byte {} is corrupt", self.index, self.offset) }
+}
+impl std::error::Error for CorruptRecord {}
+
+pub struct Wal {
+    file: File,
+    path: PathBuf,
+    archive: Option<Box<WalArchive>>,
+    opts: WalOptions,
+    /// Size of the active segment
+    active_bytes: u64,
+    next_seq: u64,
+    archiver: Option<ArchiveWorker>,
+    /// Framed records not yet written, with `sync_commits`
+    buffered: Vec<u8>,
+    /// Records appended since open; the ticket of the latest one
+    appended: u64,
+    commits: Arc<CommitQueue>,
+    /// Size of the checkpoint and sealed segments replay reads, kept with `WalOptions::metrics`
+    sealed_bytes: u64,
+}
 impl Wal {
+/// Open or create the log at `path` as one unbounded file (see `open_with`)
 pub fn open(path: &str) -> anyhow::Result<Self> {
-let file = OpenOptions::new().create(true).read(true).append(true).open(path)?; Ok(Self { file })
+Self::open_with(path, WalOptions::default())
+}
+/// Open or create the log at `path`. A version 1 file is rewritten in the current
+/// format. A torn write left at the end by a crash (a record cut short or failing its
+/// checksum, or a transaction without its commit) is cut off with a warning; a
+/// checksum failure anywhere else is left for `replay` to report.
+pub fn open_with(path: &str, opts: WalOptions) -> anyhow::Result<Self> {
+let mut file = OpenOptions::new().create(true).read(true).append(true).open(path)?;
+let mut buf = Vec::new(); file.read_to_end(&mut buf)?;
+let path = PathBuf::from(path);
+let next_seq = list_segments(&path)?.last().map_or(1, |(seq, _, _)| seq + 1);
+let mut wal = Self {
+    file, path, archive: None, opts, active_bytes: buf.len() as u64, next_seq, archiver: None,
+    buffered: Vec::new(), appended: 0, commits: Arc::new(CommitQueue::new(opts.metrics)), sealed_bytes: 0,
+};
+wal.refresh_size()?;
+if buf.is_empty() {
+    wal.file.write_all(&file_header())?; wal.sync(&wal.file)?;
+    wal.active_bytes = FILE_HEADER_LEN as u64;
+    return Ok(wal);
+}
+let (records, mut end) = match parse_any(&buf) {
+    Ok(parsed) => parsed,
+    Err(e) => match e.downcast_ref::<CorruptRecord>() {
+        Some(c) if buf.starts_with(MAGIC) && is_last_record(&buf, c.offset) => parse(&buf[..c.offset as usize])?,
+        // A corrupt log is left as it is for `replay` to report
+        Some(_) => return Ok(wal),
+        None => return Err(e),
+    },
+};
+if !buf.starts_with(MAGIC) {
+    // Converted in place: nothing can be tailing a version 1 log
+    wal.replace(&records, false)?;
+    return Ok(wal);
+}
+wal.next_seq = wal.next_seq.max(reset_seq(&records).unwrap_or(0));
+// The time stamp of a torn append goes with it
+if end < buf.len() && records.last().is_some_and(|r| r.kind == RecordType::Time) { end -= RECORD_HEADER_LEN + 8; }
+let end = open_txn_start(&records).unwrap_or(end);
+if end < buf.len() {
+    tracing::warn!("WAL {}: cut off a torn write of {} bytes at byte {}", wal.path.display(), buf.len() - end, end);
+    wal.file.set_len(end as u64)?;
+    wal.active_bytes = end as u64;
+}
+Ok(wal)
+}
+pub fn append(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
+self.append_record(RecordType::Raw, bytes)
+}
+pub fn append_record(&mut self, kind: RecordType, payload: &[u8]) -> anyhow::Result<()> {
+self.append_framed(frame(kind, payload))?;
+if let Some(archive) = self.archive.as_mut() { archive.append(now_ms(), kind, payload)?; }
+Ok(())
+}
+/// Append `records` as one transaction, between `TxnBegin` and `TxnCommit` markers
+/// holding `id`, in a single write: replay returns all of them or none. The archive
+/// gets the records without the markers.
+pub fn append_txn(&mut self, id: u64, records: &[Record]) -> anyhow::Result<()> {
+let marker = id.to_be_bytes();
+let mut batch = frame(RecordType::TxnBegin, &marker);
+for rec in records { batch.extend_from_slice(&frame(rec.kind, &rec.payload)); }
+batch.extend_from_slice(&frame(RecordType::TxnCommit, &marker));
+self.append_framed(batch)?;
+if let Some(archive) = self.archive.as_mut() {
+    for rec in records { archive.append(now_ms(), rec.kind, &rec.payload)?; }
+}
+Ok(())
+}
+fn append_framed(&mut self, framed: Vec<u8>) -> anyhow::Result<()> {
+let start = Instant::now();
+let res = self.write_framed(framed);
+if self.opts.metrics {
+    tonledb_metrics::observe_wal_append(if res.is_ok() { "ok" } else { "err" });
+    tonledb_metrics::observe_wal_append_latency(start.elapsed());
+    tonledb_metrics::set_wal_size_bytes(self.sealed_bytes + self.active_bytes);
 }
-pub fn append(&mut self, bytes: &[u8]) -> anyhow::Result<()> { self.file.write_all(bytes)?; self.file.write_all(b"\n")?; self.file.flush()?; Ok(()) }
+res
+}
+fn write_framed(&mut self, framed: Vec<u8>) -> anyhow::Result<()> {
+// Rotated before the write, so an error here means nothing was logged
+if self.opts.segment_bytes > 0 && self.active_bytes >= self.opts.segment_bytes { self.rotate()?; }
+let framed = [stamp(), framed].concat();
+if self.opts.sync_commits {
+    self.buffered.extend_from_slice(&framed);
+} else {
+    self.file.write_all(&framed)?; self.file.flush()?;
+}
+self.active_bytes += framed.len() as u64;
+self.appended += 1;
+Ok(())
+}
+/// With `sync_commits`, the ticket to pass to `CommitQueue::commit` to wait until
+/// the records appended so far are durable
+pub fn commit_ticket(&self) -> Option<u64> {
+self.opts.sync_commits.then_some(self.appended)
+}
+pub fn commit_queue(&self) -> Arc<CommitQueue> {
+self.commits.clone()
+}
+/// Write out the buffered records; returns a handle to sync them through and the
+/// ticket of the last one. Called by the leader of a group commit.
+pub fn take_batch(&mut self) -> anyhow::Result<(File, u64)> {
+self.write_buffered()?;
+Ok((self.file.try_clone()?, self.appended))
+}
+fn write_buffered(&mut self) -> anyhow::Result<()> {
+if !self.buffered.is_empty() {
+    self.file.write_all(&self.buffered)?;
+    self.buffered.clear();
+}
+Ok(())
+}
+/// Copy every record appended from now on to `archive` (see `archive`)
+pub fn set_archive(&mut self, archive: WalArchive) { self.archive = Some(Box::new(archive)); }
+/// Hand every segment to `archiver`, on a background thread, once it is sealed.
+/// Segments already on disk are offered first, since whether they were archived
+/// before is not recorded.
+pub fn set_segment_archiver(&mut self, archiver: Box<dyn SegmentArchiver>) -> anyhow::Result<()> {
+let pending = list_segments(&self.path)?.into_iter().filter(|(_, state, _)| *state != SegmentState::Checkpoint).map(|(seq, _, _)| seq).collect();
+self.archiver = Some(ArchiveWorker::start(archiver, pending, &self.path, self.opts.retention)?);
+Ok(())
+}
+/// Archive the segments still waiting for it on this thread, oldest first, stopping at
+/// the first failure; waits for a background pass already running. Returns how many
+/// this call archived.
+pub fn archive_pending(&mut self) -> anyhow::Result<usize> {
+let Some(archiver) = &self.archiver else { return Ok(0) };
+archiver.run()
+}
+/// Seal the active segment and start a new one
+fn rotate(&mut self) -> anyhow::Result<()> {
+self.write_buffered()?;
+self.sync(&self.file)?;
+self.commits.mark_durable(self.appended);
+let seq = self.next_seq;
+std::fs::rename(&self.path, segment_path(&self.path, seq, SegmentState::Sealed))?;
+self.next_seq += 1;
+self.file = OpenOptions::new().create(true).read(true).append(true).open(&self.path)?;
+self.file.write_all(&file_header())?; self.sync(&self.file)?;
+self.active_bytes = FILE_HEADER_LEN as u64;
+self.refresh_size()?;
+if let Some(archiver) = &self.archiver { archiver.push(seq); }
+Ok(())
+}
+/// Delete retired segments that are archived (or need not be) and past retention
+fn prune(&mut self) -> anyhow::Result<()> {
+segment::prune(&self.path, self.opts.retention, &self.archiver.as_ref().map(ArchiveWorker::pending).unwrap_or_default())
+}
+/// `sync_all`, counted in `tonledb_metrics` with `WalOptions::metrics`
+fn sync(&self, file: &File) -> std::io::Result<()> {
+file.sync_all()?;
+if self.opts.metrics { tonledb_metrics::inc_wal_fsyncs(); }
+Ok(())
+}
+/// Recount `sealed_bytes` after the set of segments replay reads changed, and report the size
+fn refresh_size(&mut self) -> anyhow::Result<()> {
+if !self.opts.metrics { return Ok(()); }
+let segments = list_segments(&self.path)?;
+let covered = segments.iter().rev().find(|(_, state, _)| *state == SegmentState::Checkpoint).map_or(0, |(seq, _, _)| *seq);
+let mut bytes = 0;
+for (seq, state, seg) in &segments {
+    let replayed = match state {
+        SegmentState::Checkpoint => *seq == covered,
+        SegmentState::Sealed => *seq > covered,
+        SegmentState::Retired => false,
+    };
+    if replayed { bytes += std::fs::metadata(seg)?.len(); }
+}
+self.sealed_bytes = bytes;
+tonledb_metrics::set_wal_size_bytes(self.sealed_bytes + self.active_bytes);
+Ok(())
+}
+/// Replace the whole log with `records`. They are written to a side file and synced
+/// before it is renamed over the log, so a crash leaves either the old or the new log.
+/// A non-empty active segment is sealed first, so the new log starts a new segment and
+/// positions handed to tailers stay valid. Sealed segments are retired and checkpoints
+/// deleted afterwards; the new log starts with a `RecordType::Reset`, so a crash in
+/// between leaves them ignored.
+pub fn rewrite(&mut self, records: &[Record]) -> anyhow::Result<()> {
+self.replace(records, true)
+}
+fn replace(&mut self, records: &[Record], seal_old: bool) -> anyhow::Result<()> {
+if seal_old && self.active_bytes > FILE_HEADER_LEN as u64 { self.rotate()?; }
+self.buffered.clear();
+let mut tmp_path = self.path.clone().into_os_string(); tmp_path.push(".compact");
+let tmp_path = PathBuf::from(tmp_path);
+let mut tmp = File::create(&tmp_path)?;
+tmp.write_all(&file_header())?;
+tmp.write_all(&frame(RecordType::Reset, &self.next_seq.to_be_bytes()))?;
+tmp.write_all(&stamp())?;
+for rec in records { tmp.write_all(&frame(rec.kind, &rec.payload))?; }
+self.sync(&tmp)?; drop(tmp);
+std::fs::rename(&tmp_path, &self.path)?;
+self.file = OpenOptions::new().read(true).append(true).open(&self.path)?;
+self.active_bytes = self.file.metadata()?.len();
+self.commits.mark_durable(self.appended);
+for (seq, state, seg) in list_segments(&self.path)? {
+    match state {
+        SegmentState::Sealed => std::fs::rename(&seg, segment_path(&self.path, seq, SegmentState::Retired))?,
+        SegmentState::Checkpoint => std::fs::remove_file(&seg)?,
+        SegmentState::Retired => {}
+    }
+}
+self.refresh_size()?;
+// Segments the archiver was reading have just been renamed; it retries them as retired
+if let Some(archiver) = &self.archiver { archiver.wake(); }
+self.prune()
+}
+/// Payloads of all complete records in order (see `replay_records`)
 pub fn replay(&mut self) -> anyhow::Result<Vec<Vec<u8>>> {
+Ok(self.replay_records()?.into_iter().map(|r| r.payload).collect())
+}
+/// All complete records in order: the newest checkpoint, the sealed segments after
+/// it, then the active one. A record cut short at the end of the active segment is a
+/// torn write from a crash and is dropped; a checksum mismatch anywhere, or a record
+/// cut short anywhere else, fails with `CorruptRecord`.
+/// `RecordType::Reset`, time stamps and transaction markers are applied here and never returned.
+pub fn replay_records(&mut self) -> anyhow::Result<Vec<Record>> {
+let start = Instant::now();
+self.write_buffered()?;
+let segments = list_segments(&self.path)?;
+let mut out = Vec::new();
+let mut covered = 0;
+if let Some((lsn, _, ckpt)) = segments.iter().rev().find(|(_, state, _)| *state == SegmentState::Checkpoint) {
+    out = parse_sealed(&std::fs::read(ckpt)?)?;
+    covered = *lsn;
+}
+for (seq, state, seg) in &segments {
+    if *state == SegmentState::Sealed && *seq > covered { out.extend(parse_sealed(&std::fs::read(seg)?)?); }
+}
 let mut buf = Vec::new(); self.file.seek(SeekFrom::Start(0))?; self.file.read_to_end(&mut buf)?;
-Ok(buf.split(|b| *b==b'\n').filter(|r|!r.is_empty()).map(|r| r.to_vec()).collect())
+out.extend(parse_any(&buf)?.0);
+if self.opts.metrics { tonledb_metrics::observe_wal_replay(start.elapsed()); }
+Ok(resolve(out))
+}
+/// Path of the active segment
+pub fn path(&self) -> &std::path::Path {
+&self.path
+}
+}
+
+impl Drop for Wal {
+fn drop(&mut self) {
+    // Best effort: nobody is left waiting on these records
+    let _ = self.write_buffered();
+}
+}
+
+/// Records of the WAL file at `path`, read without opening it for writing. A file
+/// still being written may end in a partial record (or header), which is left out.
+pub fn read_records(path: &std::path::Path) -> anyhow::Result<Vec<Record>> {
+let buf = std::fs::read(path)?;
+if buf.len() < FILE_HEADER_LEN && MAGIC.starts_with(&buf) { return Ok(Vec::new()); }
+Ok(parse(&buf)?.0)
+}
+
+/// Segment number a `rewrite` gave the active segment, from its `RecordType::Reset`.
+/// Kept there because the sealed segments that numbered it may all have been pruned.
+fn reset_seq(records: &[Record]) -> Option<u64> {
+records.iter().rev().find(|r| r.kind == RecordType::Reset).and_then(|r| r.payload.as_slice().try_into().ok()).map(u64::from_be_bytes)
+}
+
+/// Whether the record at `offset` of a current-format file runs exactly to its end
+fn is_last_record(buf: &[u8], offset: u64) -> bool {
+let pos = offset as usize;
+buf.get(pos..pos + 4).and_then(|b| b.try_into().ok()).is_some_and(|len: [u8; 4]| pos + RECORD_HEADER_LEN + u32::from_be_bytes(len) as usize == buf.len())
+}
+
+/// Replayed `records` from the last `RecordType::Reset` on, without time stamps (see `resolve_txns`)
+fn resolve(mut records: Vec<Record>) -> Vec<Record> {
+if let Some(reset) = records.iter().rposition(|r| r.kind == RecordType::Reset) { records.drain(..=reset); }
+records.retain(|r| r.kind != RecordType::Time);
+resolve_txns(records)
+}
+
+/// `records` without transaction markers, or the records of transactions that never committed
+fn resolve_txns(records: Vec<Record>) -> Vec<Record> {
+let mut out = Vec::with_capacity(records.len());
+// Where the records of the open transaction start in `out`
+let mut open = None;
+for rec in records {
+    match rec.kind {
+        RecordType::TxnBegin => { if let Some(start) = open { out.truncate(start); } open = Some(out.len()); }
+        RecordType::TxnCommit => open = None,
+        _ => out.push(rec),
+    }
+}
+if let Some(start) = open { out.truncate(start); }
+out
+}
+
+/// Byte offset, in a current-format file holding `records`, of a transaction no
+/// `TxnCommit` closes, time stamp included
+fn open_txn_start(records: &[Record]) -> Option<usize> {
+let mut pos = FILE_HEADER_LEN;
+let mut start = None;
+let mut stamped_at = None;
+for rec in records {
+    match rec.kind {
+        RecordType::TxnBegin => start = Some(stamped_at.unwrap_or(pos)),
+        RecordType::TxnCommit => start = None,
+        _ => {}
+    }
+    stamped_at = (rec.kind == RecordType::Time).then_some(pos);
+    pos += RECORD_HEADER_LEN + rec.payload.len();
+}
+start
+}
+
+fn file_header() -> Vec<u8> {
+[&MAGIC[..], &FORMAT_VERSION.to_be_bytes()].concat()
+}
+
+fn frame(kind: RecordType, payload: &[u8]) -> Vec<u8> {
+let mut rec = Vec::with_capacity(RECORD_HEADER_LEN + payload.len());
+rec.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+rec.extend_from_slice(&checksum(kind as u8, payload).to_be_bytes());
+rec.push(kind as u8); rec.extend_from_slice(payload);
+rec
+}
+
+fn checksum(kind: u8, payload: &[u8]) -> u32 {
+let mut h = crc32fast::Hasher::new(); h.update(&[kind]); h.update(payload); h.finalize()
+}
+
+/// Records of a file in either format, and (for the current one) the end of the last complete record
+fn parse_any(buf: &[u8]) -> anyhow::Result<(Vec<Record>, usize)> {
+if buf.starts_with(MAGIC) { return parse(buf); }
+let records = legacy_records(buf)?.into_iter().map(|p| Record::new(RecordType::Raw, p)).collect();
+Ok((records, buf.len()))
+}
+
+/// Records of a current-format file, and the byte offset just past the last complete one
+fn parse(buf: &[u8]) -> anyhow::Result<(Vec<Record>, usize)> {
+let (records, end) = parse_at(buf, FILE_HEADER_LEN)?;
+Ok((records.into_iter().map(|(_, rec)| rec).collect(), end))
+}
+
+/// Records of a sealed segment or checkpoint. Both are synced before they are renamed
+/// into place, so a record cut short in one is damage rather than a torn write.
+fn parse_sealed(buf: &[u8]) -> anyhow::Result<Vec<Record>> {
+let (records, end) = parse(buf)?;
+ensure_complete(records.len(), end, buf)?;
+Ok(records)
+}
+
+/// Fail with `CorruptRecord` unless the `count` records parsed from `buf` end at `end`, its last byte
+fn ensure_complete(count: usize, end: usize, buf: &[u8]) -> anyhow::Result<()> {
+if end < buf.len() { return Err(CorruptRecord { index: count, offset: end as u64 }.into()); }
+Ok(())
+}
+
+/// Records of a current-format file from byte `start` on, each with its offset, and
+/// the byte offset just past the last complete one. A record cut short is taken for a
+/// torn write only if nothing appended later follows it (see `appended_after`).
+fn parse_at(buf: &[u8], start: usize) -> anyhow::Result<(Vec<(usize, Record)>, usize)> {
+if buf.len() < FILE_HEADER_LEN || !buf.starts_with(MAGIC) { anyhow::bail!("not a TonleDB WAL file"); }
+let version = u16::from_be_bytes([buf[MAGIC.len()], buf[MAGIC.len() + 1]]);
+if version != FORMAT_VERSION { anyhow::bail!("unsupported WAL format version {}", version); }
+let mut out = Vec::new();
+let mut pos = start.max(FILE_HEADER_LEN);
+while buf.len().saturating_sub(pos) >= RECORD_HEADER_LEN {
+    let len = u32::from_be_bytes(buf[pos..pos + 4].try_into()?) as usize;
+    let end = pos + RECORD_HEADER_LEN + len;
+    let corrupt = CorruptRecord { index: out.len(), offset: pos as u64 };
+    if end > buf.len() {
+        if appended_after(buf, pos + RECORD_HEADER_LEN) { return Err(corrupt.into()); }
+        break;
+    }
+    let want = u32::from_be_bytes(buf[pos + 4..pos + 8].try_into()?);
+    let (kind, payload) = (buf[pos + 8], &buf[pos + RECORD_HEADER_LEN..end]);
+    if checksum(kind, payload) != want { return Err(corrupt.into()); }
+    let Some(kind) = RecordType::from_u8(kind) else { anyhow::bail!("WAL record {} at byte {} has unknown type {}", corrupt.index, corrupt.offset, kind) };
+    out.push((pos, Record::new(kind, payload.to_vec())));
+    pos = end;
+}
+Ok((out, pos))
+}
+
+/// Whether `buf` holds a valid time stamp from byte `from` on. Every append starts with
+/// one, so after a record cut short it means later appends reached the disk: the
+/// record's length is damaged, and it is not a torn write at the end of the log.
+fn appended_after(buf: &[u8], from: usize) -> bool {
+const STAMP_LEN: usize = RECORD_HEADER_LEN + 8;
+(from..buf.len().saturating_sub(STAMP_LEN - 1)).any(|pos| {
+    let rec = &buf[pos..pos + STAMP_LEN];
+    rec[..4] == 8u32.to_be_bytes() && rec[8] == RecordType::Time as u8 && rec[4..8] == checksum(rec[8], &rec[RECORD_HEADER_LEN..]).to_be_bytes()
+})
+}
+
+/// Records of a version 1 file. A trailing record without its newline is a torn write
+/// and is dropped; bare records from before checksums pass through unverified.
+fn legacy_records(buf: &[u8]) -> anyhow::Result<Vec<Vec<u8>>> {
+let complete = match buf.iter().rposition(|b| *b == b'\n') { Some(end) => &buf[..=end], None => &[][..] };
+let mut out = Vec::new();
+let mut offset = 0u64;
+for (index, line) in complete.split(|b| *b==b'\n').enumerate() {
+    let at = offset;
+    offset += line.len() as u64 + 1;
+    if line.is_empty() { continue; }
+    out.push(legacy_verify(line).ok_or(CorruptRecord { index, offset: at })?.to_vec());
+}
+Ok(out)
+}
+
+fn legacy_verify(line: &[u8]) -> Option<&[u8]> {
+if line[0] != LEGACY_CHECKSUM_MARK { return Some(line); }
+if line.len() < LEGACY_HEADER_LEN { return None; }
+let want = std::str::from_utf8(&line[1..LEGACY_HEADER_LEN]).ok().and_then(|h| u32::from_str_radix(h, 16).ok())?;
+let payload = &line[LEGACY_HEADER_LEN..];
+(crc32fast::hash(payload) == want).then_some(payload)
+}
+
+/// Framed `RecordType::Time` record holding the current time
+fn stamp() -> Vec<u8> {
+frame(RecordType::Time, &now_ms().to_be_bytes())
+}
+
+/// Time held by a `RecordType::Time` record
+fn stamp_time(rec: &Record) -> Option<u64> {
+if rec.kind != RecordType::Time { return None; }
+rec.payload.as_slice().try_into().ok().map(u64::from_be_bytes)
+}
+
+fn now_ms() -> u64 {
+std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
 }
-}
\ No newline at end of file
@@ -0,0 +1,216 @@
+//! Segment rotation.
+//!
+//! With `WalOptions::segment_bytes` set, the log at `path` is only the active
+//! segment: once it reaches that size it is synced and renamed to
+//! `<path>.<seq>.seg` (sealed), and a fresh active file is started. Replay reads
+//! the sealed segments in sequence order, then the active one.
+//!
+//! `Wal::rewrite` makes every sealed segment redundant; they are renamed to
+//! `<path>.<seq>.old` (retired) and deleted once older than
+//! `WalOptions::retention`. A `SegmentArchiver` is handed each segment after it is
+//! sealed, for shipping off-node. It runs on a thread of its own, so a slow upload
+//! holds up neither the writer that sealed the segment nor anyone waiting on the log.
+//! A retired segment is never deleted before it has been archived.
+//!
+//! Checkpoints (see `checkpoint`) sit beside the segments as `<path>.<lsn>.ckpt`.
+
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+const SEALED_EXT: &str = "seg";
+const RETIRED_EXT: &str = "old";
+const CHECKPOINT_EXT: &str = "ckpt";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalOptions {
+    /// Seal the active segment once it holds this many bytes; 0 keeps one unbounded file
+    pub segment_bytes: u64,
+    /// How long retired segments are kept after a rewrite
+    pub retention: Duration,
+    /// Buffer appends and make them durable with a group commit (see `commit`)
+    pub sync_commits: bool,
+    /// Report size, append latency, fsyncs and replay time to `tonledb_metrics`. The
+    /// metrics are process-wide, so this is for the one main log of a process.
+    pub metrics: bool,
+}
+
+impl Default for WalOptions {
+    fn default() -> Self {
+        Self { segment_bytes: 0, retention: Duration::ZERO, sync_commits: false, metrics: false }
+    }
+}
+
+/// Archive hook: ships a sealed segment somewhere else, off the thread that sealed it. Delivery is at least once
+/// (segments found on disk are offered again when an archiver is attached), so
+/// archiving the same segment twice must be harmless. A failed segment is retried
+/// on the next rotation or `Wal::archive_pending`.
+pub trait SegmentArchiver: Send + Sync {
+    /// Archive segment number `seq`, currently stored at `segment`
+    fn archive(&mut self, seq: u64, segment: &Path) -> anyhow::Result<()>;
+}
+
+/// Copies segments into a directory (e.g. a mounted network volume) as `<seq>.seg`
+pub struct CopyArchiver {
+    dir: PathBuf,
+}
+
+impl CopyArchiver {
+    /// Archive into `dir`, created if missing
+    pub fn new(dir: &str) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        Ok(Self { dir: PathBuf::from(dir) })
+    }
+}
+
+impl SegmentArchiver for CopyArchiver {
+    fn archive(&mut self, seq: u64, segment: &Path) -> anyhow::Result<()> {
+        // Copied under a temporary name so the archive never holds a partial segment
+        let dest = self.dir.join(format!("{:08}.{}", seq, SEALED_EXT));
+        let mut tmp = dest.clone().into_os_string();
+        tmp.push(".part");
+        std::fs::copy(segment, &tmp)?;
+        std::fs::File::open(&tmp)?.sync_all()?;
+        std::fs::rename(&tmp, &dest)?;
+        Ok(())
+    }
+}
+
+/// A `SegmentArchiver` and the segments it has yet to archive, worked off by a
+/// background thread that a rotation wakes. The thread ends with the log.
+pub(crate) struct ArchiveWorker {
+    archiver: Arc<Mutex<Box<dyn SegmentArchiver>>>,
+    /// Segments not yet archived, oldest first
+    pending: Arc<Mutex<Vec<u64>>>,
+    log: PathBuf,
+    retention: Duration,
+    wake: Sender<()>,
+}
+
+impl ArchiveWorker {
+    /// Start archiving `pending`, then every segment `push`ed, for the log at `log`
+    pub(crate) fn start(archiver: Box<dyn SegmentArchiver>, pending: Vec<u64>, log: &Path, retention: Duration) -> anyhow::Result<Self> {
+        let (wake, woken) = mpsc::channel::<()>();
+        let worker = Self { archiver: Arc::new(Mutex::new(archiver)), pending: Arc::new(Mutex::new(pending)), log: log.to_path_buf(), retention, wake };
+        let (archiver, pending, log) = (worker.archiver.clone(), worker.pending.clone(), worker.log.clone());
+        std::thread::Builder::new().name("wal-archiver".into()).spawn(move || {
+            while woken.recv().is_ok() {
+                // One pass covers every wake-up queued meanwhile
+                while woken.try_recv().is_ok() {}
+                // Whatever fails stays pending for the next wake-up
+                let _ = archive_pending(&archiver, &pending, &log, retention);
+            }
+        })?;
+        worker.wake();
+        Ok(worker)
+    }
+
+    /// Queue sealed segment `seq` and wake the thread
+    pub(crate) fn push(&self, seq: u64) {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner()).push(seq);
+        self.wake();
+    }
+
+    pub(crate) fn wake(&self) {
+        let _ = self.wake.send(());
+    }
+
+    /// Archive the pending segments on the calling thread, after any pass already running
+    pub(crate) fn run(&self) -> anyhow::Result<usize> {
+        archive_pending(&self.archiver, &self.pending, &self.log, self.retention)
+    }
+
+    pub(crate) fn pending(&self) -> Vec<u64> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+/// Archive `pending` oldest first, stopping at the first failure, then prune what that
+/// freed. Returns how many were archived. The archiver's lock keeps passes apart.
+fn archive_pending(archiver: &Mutex<Box<dyn SegmentArchiver>>, pending: &Mutex<Vec<u64>>, log: &Path, retention: Duration) -> anyhow::Result<usize> {
+    let mut archiver = archiver.lock().unwrap_or_else(|e| e.into_inner());
+    let mut done = 0;
+    loop {
+        // Not locked while archiving, so a rotation can queue the next segment meanwhile
+        let Some(seq) = pending.lock().unwrap_or_else(|e| e.into_inner()).first().copied() else { break };
+        let sealed = segment_path(log, seq, SegmentState::Sealed);
+        let seg = if sealed.exists() { sealed } else { segment_path(log, seq, SegmentState::Retired) };
+        archiver.archive(seq, &seg)?;
+        pending.lock().unwrap_or_else(|e| e.into_inner()).retain(|s| *s != seq);
+        done += 1;
+    }
+    let pending = pending.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    prune(log, retention, &pending)?;
+    Ok(done)
+}
+
+/// Delete retired segments of the log at `log` that are past `retention` and not `pending` archiving
+pub(crate) fn prune(log: &Path, retention: Duration, pending: &[u64]) -> anyhow::Result<()> {
+    for (seq, state, seg) in list_segments(log)? {
+        if state == SegmentState::Retired && !pending.contains(&seq) && older_than(&seg, retention) {
+            match std::fs::remove_file(&seg) {
+                // Pruned by the log and the archiver thread at once
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                res => res?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// State of a segment file beside the active log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SegmentState {
+    Sealed,
+    Retired,
+    /// A checkpoint, numbered by the last segment it covers
+    Checkpoint,
+}
+
+pub(crate) fn segment_path(log: &Path, seq: u64, state: SegmentState) -> PathBuf {
+    let ext = match state {
+        SegmentState::Sealed => SEALED_EXT,
+        SegmentState::Retired => RETIRED_EXT,
+        SegmentState::Checkpoint => CHECKPOINT_EXT,
+    };
+    let mut name = log.as_os_str().to_owned();
+    name.push(format!(".{:08}.{}", seq, ext));
+    PathBuf::from(name)
+}
+
+/// Segments and checkpoints of the log at `log`, in sequence order
+pub(crate) fn list_segments(log: &Path) -> anyhow::Result<Vec<(u64, SegmentState, PathBuf)>> {
+    let dir = match log.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d,
+        _ => Path::new("."),
+    };
+    let Some(base) = log.file_name().map(|n| n.to_string_lossy().into_owned()) else { return Ok(Vec::new()) };
+    let mut out = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let Some(rest) = name.strip_prefix(&base).and_then(|r| r.strip_prefix('.')) else { continue };
+        let Some((seq, ext)) = rest.split_once('.') else { continue };
+        let state = match ext {
+            SEALED_EXT => SegmentState::Sealed,
+            RETIRED_EXT => SegmentState::Retired,
+            CHECKPOINT_EXT => SegmentState::Checkpoint,
+            _ => continue,
+        };
+        let Ok(seq) = seq.parse::<u64>() else { continue };
+        out.push((seq, state, segment_path(log, seq, state)));
+    }
+    out.sort_by_key(|(seq, _, _)| *seq);
+    Ok(out)
+}
+
+/// Whether `path` was last modified more than `retention` ago
+pub(crate) fn older_than(path: &Path, retention: Duration) -> bool {
+    retention.is_zero()
+        || std::fs::metadata(path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| SystemTime::now().duration_since(t).ok())
+            .is_some_and(|age| age >= retention)
+}
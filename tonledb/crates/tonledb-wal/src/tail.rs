@@ -0,0 +1,195 @@
+//! Following the log.
+//!
+//! A `WalTail` reads records as they are appended, for replication and change-data
+//! capture. It reads the files directly rather than through the `Wal`, so it can
+//! run in another thread or process without holding up writers, and only sees
+//! records once they are written out (with `WalOptions::sync_commits`, once their
+//! group commit has started).
+//!
+//! Positions are `Lsn`s: a segment number and a byte offset in it. The active file
+//! is segment `n` until it is sealed under that number, so a position stays valid
+//! across rotations. Once a `Wal::rewrite` has replaced the log, the tail returns its
+//! `RecordType::Reset`: everything before it is superseded and a consumer should
+//! resynchronise (e.g. from a snapshot) rather than apply what follows as changes.
+//! Positions in segments already pruned fail with an error.
+//!
+//! The `RecordType::Time` stamps in the log are not returned; each record carries
+//! the time of the stamp before it instead.
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::segment::{list_segments, segment_path, SegmentState};
+use crate::{is_last_record, parse_at, reset_seq, stamp_time, CorruptRecord, Record, Wal, FILE_HEADER_LEN, RECORD_HEADER_LEN};
+
+/// Position in the log
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Lsn {
+    pub segment: u64,
+    pub offset: u64,
+}
+
+/// A record read by a `WalTail`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalRecord {
+    /// Where the record starts
+    pub lsn: Lsn,
+    /// Where the record after it starts, to resume from
+    pub next: Lsn,
+    /// When it was appended (epoch ms); `None` for records from before stamping was added
+    pub time: Option<u64>,
+    pub record: Record,
+}
+
+impl Wal {
+    /// Follow this log from `from` (see `WalTail::open`)
+    pub fn tail(&self, from: Lsn) -> WalTail {
+        WalTail::open(&self.path, from)
+    }
+
+    /// Position just past the last record written out
+    pub fn end_lsn(&self) -> Lsn {
+        Lsn { segment: self.next_seq, offset: self.active_bytes - self.buffered.len() as u64 }
+    }
+}
+
+/// Reader following the log at a path. As an iterator it returns the records
+/// written so far and then `None`; calling `next` again later picks up what was
+/// appended since. `recv` waits for the next record instead.
+pub struct WalTail {
+    log: PathBuf,
+    pos: Lsn,
+    ready: VecDeque<WalRecord>,
+    /// Time of the last stamp before `pos`
+    time: Option<u64>,
+    /// Whether `time` is known; not until the segment has been read from its start
+    timed: bool,
+}
+
+impl WalTail {
+    /// Follow the log at `log` from `from`, the `next` of the last record already
+    /// consumed. `Lsn::default()` starts at the oldest segment still on disk.
+    pub fn open(log: &Path, from: Lsn) -> Self {
+        let timed = from.offset <= FILE_HEADER_LEN as u64;
+        Self { log: log.to_path_buf(), pos: from, ready: VecDeque::new(), time: None, timed }
+    }
+
+    /// Where the next record will be read from
+    pub fn position(&self) -> Lsn {
+        self.ready.front().map_or(self.pos, |r| r.lsn)
+    }
+
+    /// The next record, polling every `poll` until one is appended
+    pub fn recv(&mut self, poll: Duration) -> anyhow::Result<WalRecord> {
+        loop {
+            if let Some(rec) = self.next() {
+                return rec;
+            }
+            std::thread::sleep(poll);
+        }
+    }
+
+    /// The next record, polling every `poll` until one is appended, without blocking the runtime
+    #[cfg(feature = "async")]
+    pub async fn recv_async(&mut self, poll: Duration) -> anyhow::Result<WalRecord> {
+        loop {
+            if let Some(rec) = self.next() {
+                return rec;
+            }
+            tokio::time::sleep(poll).await;
+        }
+    }
+
+    /// Read what the segment at the current position holds past it, moving on
+    /// through sealed segments until something is found or the active one is reached
+    fn fill(&mut self) -> anyhow::Result<()> {
+        loop {
+            let Some((buf, sealed)) = self.read_segment()? else { return Ok(()) };
+            // An active file so new that its header is not written yet
+            if !sealed && buf.len() < FILE_HEADER_LEN { return Ok(()); }
+            let seg = self.pos.segment;
+            // The stamp in effect at the position is found by reading the segment from its start
+            let start = if self.timed { self.pos.offset as usize } else { FILE_HEADER_LEN };
+            let (records, end) = match parse_at(&buf, start) {
+                Ok(parsed) => parsed,
+                Err(e) => match e.downcast_ref::<CorruptRecord>() {
+                    // The record being written when the file was read
+                    Some(c) if !sealed && is_last_record(&buf, c.offset) => parse_at(&buf[..c.offset as usize], start)?,
+                    _ => return Err(e),
+                },
+            };
+            for (at, record) in records {
+                if let Some(t) = stamp_time(&record) {
+                    self.time = Some(t);
+                    continue;
+                }
+                if (at as u64) < self.pos.offset { continue; }
+                let next = Lsn { segment: seg, offset: (at + RECORD_HEADER_LEN + record.payload.len()) as u64 };
+                self.ready.push_back(WalRecord { lsn: Lsn { segment: seg, offset: at as u64 }, next, time: self.time, record });
+            }
+            self.pos = Lsn { segment: seg, offset: end as u64 };
+            self.timed = true;
+            if !self.ready.is_empty() || !sealed {
+                return Ok(());
+            }
+            self.pos = Lsn { segment: seg + 1, offset: 0 };
+        }
+    }
+
+    /// Contents of the segment at the current position, and whether it is sealed
+    /// (complete). `None` if the position is past the end of the log.
+    fn read_segment(&mut self) -> anyhow::Result<Option<(Vec<u8>, bool)>> {
+        if let Some(buf) = self.read_sealed()? {
+            return Ok(Some((buf, true)));
+        }
+        let segments = list_segments(&self.log)?;
+        let buf = match std::fs::read(&self.log) {
+            Ok(buf) => buf,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        // Sealed while it was being read, so what was read may be the next segment
+        if let Some(buf) = self.read_sealed()? {
+            return Ok(Some((buf, true)));
+        }
+        let listed = segments.last().map_or(1, |(seq, _, _)| seq + 1);
+        let records = parse_at(&buf, 0).map(|(recs, _)| recs.into_iter().map(|(_, r)| r).collect::<Vec<_>>()).unwrap_or_default();
+        let active = listed.max(reset_seq(&records).unwrap_or(0));
+        if self.pos.segment == 0 {
+            // Start at the oldest segment still on disk
+            let first = segments.iter().find(|(_, state, _)| *state != SegmentState::Checkpoint).map_or(active, |(seq, _, _)| *seq);
+            self.pos = Lsn { segment: first, offset: 0 };
+            return self.read_segment();
+        }
+        match self.pos.segment.cmp(&active) {
+            std::cmp::Ordering::Equal => Ok(Some((buf, false))),
+            std::cmp::Ordering::Greater => Ok(None),
+            std::cmp::Ordering::Less => anyhow::bail!("WAL segment {} of {} is no longer retained", self.pos.segment, self.log.display()),
+        }
+    }
+
+    fn read_sealed(&self) -> anyhow::Result<Option<Vec<u8>>> {
+        for state in [SegmentState::Sealed, SegmentState::Retired] {
+            match std::fs::read(segment_path(&self.log, self.pos.segment, state)) {
+                Ok(buf) => return Ok(Some(buf)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Iterator for WalTail {
+    type Item = anyhow::Result<WalRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.ready.is_empty() {
+            if let Err(e) = self.fill() {
+                return Some(Err(e));
+            }
+        }
+        self.ready.pop_front().map(Ok)
+    }
+}
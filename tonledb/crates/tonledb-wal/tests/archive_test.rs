@@ -0,0 +1,54 @@
+//! Tests for the time-stamped WAL archive
+
+use tonledb_wal::{read_archive, ArchiveCursor, Record, RecordType, Wal, WalArchive};
+
+fn path(name: &str) -> String {
+    let p = std::env::temp_dir().join(format!("tonledb-wal-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&p);
+    let _ = std::fs::remove_file(&p);
+    p.to_string_lossy().into_owned()
+}
+
+#[test]
+fn test_appends_are_archived_across_segments_and_rewrites() {
+    let wal_path = path("archived.wal");
+    let dir = path("archive");
+    let mut wal = Wal::open(&wal_path).unwrap();
+    wal.set_archive(WalArchive::open(&dir, 16).unwrap());
+    for i in 0..5 {
+        wal.append(format!("kv\tk\t{}", i).as_bytes()).unwrap();
+    }
+    wal.rewrite(&[Record::new(RecordType::Raw, b"kv\tk\t4".to_vec())]).unwrap();
+    wal.append(b"kv\tk\t5").unwrap();
+    assert!(std::fs::read_dir(&dir).unwrap().count() > 1);
+
+    let (recs, cursor) = read_archive(&dir, &ArchiveCursor::default(), u64::MAX).unwrap();
+    let payloads: Vec<Vec<u8>> = recs.iter().map(|r| r.record.payload.clone()).collect();
+    let want: Vec<Vec<u8>> = (0..6).map(|i| format!("kv\tk\t{}", i).into_bytes()).collect();
+    assert_eq!(payloads, want);
+    assert!(recs.windows(2).all(|w| w[0].at_ms <= w[1].at_ms));
+
+    // Reading on from the cursor only returns what was appended since
+    wal.append(b"kv\tk\t6").unwrap();
+    let (more, _) = read_archive(&dir, &cursor, u64::MAX).unwrap();
+    assert_eq!(more.len(), 1);
+    assert_eq!(more[0].record.payload, b"kv\tk\t6".to_vec());
+    let _ = std::fs::remove_dir_all(&dir);
+    let _ = std::fs::remove_file(&wal_path);
+}
+
+#[test]
+fn test_records_newer_than_the_bound_wait_for_a_later_read() {
+    let dir = path("bounded");
+    let mut archive = WalArchive::open(&dir, 1 << 20).unwrap();
+    archive.append(100, RecordType::Put, b"a").unwrap();
+    archive.append(200, RecordType::Put, b"b").unwrap();
+    archive.append(300, RecordType::Delete, b"c").unwrap();
+
+    let (recs, cursor) = read_archive(&dir, &ArchiveCursor::default(), 200).unwrap();
+    assert_eq!(recs.iter().map(|r| r.at_ms).collect::<Vec<_>>(), vec![100, 200]);
+    let (recs, _) = read_archive(&dir, &cursor, 1000).unwrap();
+    assert_eq!(recs.iter().map(|r| r.record.clone()).collect::<Vec<_>>(), vec![Record::new(RecordType::Delete, b"c".to_vec())]);
+    assert!(read_archive(&path("missing"), &ArchiveCursor::default(), 1000).unwrap().0.is_empty());
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,88 @@
+//! Tests for replaying the WAL from a checkpoint
+
+use std::path::{Path, PathBuf};
+use tonledb_wal::{write_checkpoint, Record, RecordType, Wal, WalOptions};
+
+fn dir(name: &str) -> PathBuf {
+    let d = std::env::temp_dir().join(format!("tonledb-walckpt-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&d);
+    std::fs::create_dir_all(&d).unwrap();
+    d
+}
+
+fn count(dir: &Path, ext: &str) -> usize {
+    std::fs::read_dir(dir).unwrap().filter(|e| e.as_ref().unwrap().file_name().to_string_lossy().ends_with(ext)).count()
+}
+
+fn raw(s: &str) -> Record {
+    Record::new(RecordType::Raw, s.as_bytes().to_vec())
+}
+
+#[test]
+fn test_replay_starts_from_the_checkpoint() {
+    let d = dir("replay");
+    let p = d.join("db.wal").to_string_lossy().into_owned();
+    let mut wal = Wal::open_with(&p, WalOptions { segment_bytes: 64, ..Default::default() }).unwrap();
+    for i in 0..20 {
+        wal.append(format!("record-{:02}", i).as_bytes()).unwrap();
+    }
+    let lsn = wal.begin_checkpoint().unwrap().unwrap();
+    // Appends made while the checkpoint is written belong after it
+    wal.append(b"after").unwrap();
+    write_checkpoint(wal.path(), lsn, &[raw("state")]).unwrap();
+    wal.complete_checkpoint(lsn).unwrap();
+    assert_eq!(count(&d, ".seg"), 0);
+    assert_eq!(count(&d, ".ckpt"), 1);
+    drop(wal);
+
+    let mut wal = Wal::open_with(&p, WalOptions { segment_bytes: 64, ..Default::default() }).unwrap();
+    assert_eq!(wal.replay().unwrap(), vec![b"state".to_vec(), b"after".to_vec()]);
+
+    // A newer checkpoint replaces the older one
+    let lsn2 = wal.begin_checkpoint().unwrap().unwrap();
+    assert!(lsn2 > lsn);
+    write_checkpoint(wal.path(), lsn2, &[raw("state2")]).unwrap();
+    wal.complete_checkpoint(lsn2).unwrap();
+    assert_eq!(count(&d, ".ckpt"), 1);
+    assert_eq!(wal.replay().unwrap(), vec![b"state2".to_vec()]);
+
+    // Nothing appended since, so nothing to checkpoint
+    assert_eq!(wal.begin_checkpoint().unwrap(), None);
+    let _ = std::fs::remove_dir_all(&d);
+}
+
+#[test]
+fn test_interrupted_checkpoint_is_ignored() {
+    let d = dir("crash");
+    let p = d.join("db.wal").to_string_lossy().into_owned();
+    let mut wal = Wal::open(&p).unwrap();
+    wal.append(b"a").unwrap();
+    let lsn = wal.begin_checkpoint().unwrap().unwrap();
+    wal.append(b"b").unwrap();
+    // Crash before the checkpoint is written: the sealed segment is still replayed
+    assert_eq!(wal.replay().unwrap(), vec![b"a".to_vec(), b"b".to_vec()]);
+
+    // Crash after it is written but before completion: the segment is skipped
+    write_checkpoint(wal.path(), lsn, &[raw("a")]).unwrap();
+    assert_eq!(Wal::open(&p).unwrap().replay().unwrap(), vec![b"a".to_vec(), b"b".to_vec()]);
+    let _ = std::fs::remove_dir_all(&d);
+}
+
+#[test]
+fn test_rewrite_supersedes_checkpoints() {
+    let d = dir("rewrite");
+    let p = d.join("db.wal").to_string_lossy().into_owned();
+    let mut wal = Wal::open(&p).unwrap();
+    wal.append(b"old").unwrap();
+    let lsn = wal.begin_checkpoint().unwrap().unwrap();
+    write_checkpoint(wal.path(), lsn, &[raw("old")]).unwrap();
+    wal.complete_checkpoint(lsn).unwrap();
+    wal.rewrite(&[raw("new")]).unwrap();
+    assert_eq!(count(&d, ".ckpt"), 0);
+    assert_eq!(wal.replay().unwrap(), vec![b"new".to_vec()]);
+
+    // A checkpoint left behind by a crash during the rewrite is not replayed
+    write_checkpoint(wal.path(), lsn, &[raw("old")]).unwrap();
+    assert_eq!(Wal::open(&p).unwrap().replay().unwrap(), vec![b"new".to_vec()]);
+    let _ = std::fs::remove_dir_all(&d);
+}
@@ -0,0 +1,129 @@
+//! Tests for WAL record framing and checksums
+
+use std::io::Write;
+use tonledb_wal::{CorruptRecord, Record, RecordType, Wal, FORMAT_VERSION};
+
+fn path(name: &str) -> String {
+    let p = std::env::temp_dir().join(format!("tonledb-wal-{}-{}.wal", name, std::process::id()));
+    let _ = std::fs::remove_file(&p);
+    p.to_string_lossy().into_owned()
+}
+
+#[test]
+fn test_round_trip() {
+    let p = path("roundtrip");
+    let mut wal = Wal::open(&p).unwrap();
+    wal.append(b"kv\ta\t1").unwrap();
+    wal.append(b"kv\tb\t2").unwrap();
+    assert_eq!(wal.replay().unwrap(), vec![b"kv\ta\t1".to_vec(), b"kv\tb\t2".to_vec()]);
+    let _ = std::fs::remove_file(&p);
+}
+
+#[test]
+fn test_flipped_byte_is_reported() {
+    let p = path("flipped");
+    let mut wal = Wal::open(&p).unwrap();
+    wal.append(b"kv\ta\t1").unwrap();
+    wal.append(b"kv\tb\t2").unwrap();
+    let tail = std::fs::metadata(&p).unwrap().len() as usize;
+    // Not the last record, so not a torn write
+    wal.append(b"kv\tc\t3").unwrap();
+    drop(wal);
+    let mut bytes = std::fs::read(&p).unwrap();
+    bytes[tail - 2] = b'9';
+    std::fs::write(&p, &bytes).unwrap();
+
+    let err = Wal::open(&p).unwrap().replay().unwrap_err();
+    // Every append is preceded by its time stamp
+    assert_eq!(err.downcast::<CorruptRecord>().unwrap().index, 3);
+    let _ = std::fs::remove_file(&p);
+}
+
+#[test]
+fn test_last_record_failing_its_checksum_is_cut_off() {
+    let p = path("torn-crc");
+    let mut wal = Wal::open(&p).unwrap();
+    wal.append(b"kv\ta\t1").unwrap();
+    let good = std::fs::metadata(&p).unwrap().len();
+    wal.append(b"kv\tb\t2").unwrap();
+    drop(wal);
+    let mut bytes = std::fs::read(&p).unwrap();
+    let last = bytes.len() - 2;
+    bytes[last] = b'9';
+    std::fs::write(&p, &bytes).unwrap();
+
+    let mut wal = Wal::open(&p).unwrap();
+    assert_eq!(std::fs::metadata(&p).unwrap().len(), good);
+    wal.append(b"kv\tc\t3").unwrap();
+    assert_eq!(wal.replay().unwrap(), vec![b"kv\ta\t1".to_vec(), b"kv\tc\t3".to_vec()]);
+    let _ = std::fs::remove_file(&p);
+}
+
+#[test]
+fn test_record_cut_short_before_the_end_is_reported() {
+    let p = path("cut-short");
+    let mut wal = Wal::open(&p).unwrap();
+    wal.append(b"kv\ta\t1").unwrap();
+    // Past the time stamp of the next append
+    let damaged = std::fs::metadata(&p).unwrap().len() as usize + 17;
+    wal.append(b"kv\tb\t2").unwrap();
+    wal.append(b"kv\tc\t3").unwrap();
+    drop(wal);
+    let mut bytes = std::fs::read(&p).unwrap();
+    bytes[damaged..damaged + 4].copy_from_slice(&0x00ff_0000u32.to_be_bytes());
+    std::fs::write(&p, &bytes).unwrap();
+
+    // A length running past the end, with later appends behind it, is not a torn write
+    let err = Wal::open(&p).unwrap().replay().unwrap_err();
+    let corrupt = err.downcast::<CorruptRecord>().unwrap();
+    assert_eq!((corrupt.index, corrupt.offset), (3, damaged as u64));
+    assert_eq!(std::fs::read(&p).unwrap(), bytes);
+    let _ = std::fs::remove_file(&p);
+}
+
+#[test]
+fn test_torn_tail_and_legacy_records() {
+    let p = path("torn");
+    // A record from before checksums, then a write cut short by a crash
+    std::fs::write(&p, b"kv\told\tv\n").unwrap();
+    let mut wal = Wal::open(&p).unwrap();
+    wal.append(b"kv\ta\t1").unwrap();
+    drop(wal);
+    std::fs::OpenOptions::new().append(true).open(&p).unwrap().write_all(b"\x01deadbeefkv\tb").unwrap();
+
+    let recs = Wal::open(&p).unwrap().replay().unwrap();
+    assert_eq!(recs, vec![b"kv\told\tv".to_vec(), b"kv\ta\t1".to_vec()]);
+    let _ = std::fs::remove_file(&p);
+}
+
+#[test]
+fn test_newlines_and_tabs_in_payloads_round_trip() {
+    let p = path("binary");
+    let payload = b"kv\tkey\twith\nnewline\tand\ttabs\n".to_vec();
+    let mut wal = Wal::open(&p).unwrap();
+    wal.append_record(RecordType::Put, &payload).unwrap();
+    wal.append_record(RecordType::Delete, b"\n").unwrap();
+    drop(wal);
+    let recs = Wal::open(&p).unwrap().replay_records().unwrap();
+    assert_eq!(recs, vec![Record::new(RecordType::Put, payload), Record::new(RecordType::Delete, b"\n".to_vec())]);
+    let _ = std::fs::remove_file(&p);
+}
+
+#[test]
+fn test_legacy_file_is_converted_on_open() {
+    let p = path("convert");
+    std::fs::write(&p, b"kv\ta\t1\n\x018a2df158kv\tb\t2\n").unwrap();
+    let recs = Wal::open(&p).unwrap().replay_records().unwrap();
+    assert!(recs.iter().all(|r| r.kind == RecordType::Raw));
+    assert_eq!(recs[0].payload, b"kv\ta\t1".to_vec());
+    assert!(std::fs::read(&p).unwrap().starts_with(b"TLDBWAL\0"));
+    let _ = std::fs::remove_file(&p);
+}
+
+#[test]
+fn test_newer_format_version_is_refused() {
+    let p = path("future");
+    std::fs::write(&p, [&b"TLDBWAL\0"[..], &(FORMAT_VERSION + 1).to_be_bytes()].concat()).unwrap();
+    assert!(Wal::open(&p).is_err());
+    let _ = std::fs::remove_file(&p);
+}
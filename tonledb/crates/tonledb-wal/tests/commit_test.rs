@@ -0,0 +1,71 @@
+//! Tests for group commit of buffered WAL appends
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier, Mutex};
+use tonledb_wal::{Wal, WalOptions};
+
+fn path(name: &str) -> String {
+    let p = std::env::temp_dir().join(format!("tonledb-walcommit-{}-{}.wal", name, std::process::id()));
+    let _ = std::fs::remove_file(&p);
+    p.to_string_lossy().into_owned()
+}
+
+fn sync_opts() -> WalOptions {
+    WalOptions { sync_commits: true, ..Default::default() }
+}
+
+#[test]
+fn test_appends_are_buffered_until_committed() {
+    let p = path("buffered");
+    let mut wal = Wal::open_with(&p, sync_opts()).unwrap();
+    wal.append(b"one").unwrap();
+    let ticket = wal.commit_ticket().unwrap();
+    // Only the file header is on disk so far
+    assert_eq!(std::fs::metadata(&p).unwrap().len(), 10);
+
+    let queue = wal.commit_queue();
+    queue.commit(ticket, || wal.take_batch()).unwrap();
+    assert!(std::fs::metadata(&p).unwrap().len() > 10);
+    // Already durable: no second batch is taken
+    queue.commit(ticket, || panic!("nothing left to commit")).unwrap();
+    assert_eq!(Wal::open(&p).unwrap().replay().unwrap(), vec![b"one".to_vec()]);
+    assert_eq!(Wal::open(&p).unwrap().commit_ticket(), None);
+    let _ = std::fs::remove_file(&p);
+}
+
+#[test]
+fn test_concurrent_writers_share_commits() {
+    let p = path("concurrent");
+    let wal = Arc::new(Mutex::new(Wal::open_with(&p, sync_opts()).unwrap()));
+    let queue = wal.lock().unwrap().commit_queue();
+    let batches = Arc::new(AtomicUsize::new(0));
+    let appended = Arc::new(Barrier::new(8));
+    let threads: Vec<_> = (0..8)
+        .map(|t| {
+            let (wal, queue, batches, appended) = (wal.clone(), queue.clone(), batches.clone(), appended.clone());
+            std::thread::spawn(move || {
+                for i in 0..50 {
+                    let ticket = {
+                        let mut w = wal.lock().unwrap();
+                        w.append(format!("{}-{}", t, i).as_bytes()).unwrap();
+                        w.commit_ticket().unwrap()
+                    };
+                    // Every writer of the round has appended before any commits
+                    appended.wait();
+                    queue.commit(ticket, || {
+                        batches.fetch_add(1, Ordering::Relaxed);
+                        wal.lock().unwrap().take_batch()
+                    }).unwrap();
+                }
+            })
+        })
+        .collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+    drop(wal);
+    assert_eq!(Wal::open(&p).unwrap().replay().unwrap().len(), 400);
+    // The first writer of each round to commit writes and syncs the whole round
+    assert_eq!(batches.load(Ordering::Relaxed), 50);
+    let _ = std::fs::remove_file(&p);
+}
@@ -0,0 +1,63 @@
+//! Tests for incremental WAL exports
+
+use std::path::PathBuf;
+use tonledb_wal::{read_export, Record, RecordType, Wal, WalOptions};
+
+fn dir(name: &str) -> PathBuf {
+    let d = std::env::temp_dir().join(format!("tonledb-walexport-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&d);
+    std::fs::create_dir_all(&d).unwrap();
+    d
+}
+
+fn put(s: &str) -> Record {
+    Record::new(RecordType::Put, s.as_bytes().to_vec())
+}
+
+#[test]
+fn test_exports_chain_from_where_the_last_one_ended() {
+    let d = dir("chain");
+    let p = d.join("db.wal").to_string_lossy().into_owned();
+    let mut wal = Wal::open_with(&p, WalOptions { segment_bytes: 64, ..Default::default() }).unwrap();
+    wal.append_record(RecordType::Put, b"before").unwrap();
+    let start = wal.end_lsn();
+    for i in 0..10 {
+        wal.append_record(RecordType::Put, format!("k{}", i).as_bytes()).unwrap();
+    }
+    wal.append_txn(1, &[put("a"), put("b")]).unwrap();
+
+    let first = d.join("1.wlx");
+    let end = wal.export_since(start, &first).unwrap();
+    assert_eq!(end, wal.end_lsn());
+    let export = read_export(&first).unwrap();
+    assert_eq!((export.from, export.to), (start, end));
+    let kinds: Vec<RecordType> = export.records.iter().map(|r| r.record.kind).collect();
+    assert_eq!(kinds.len(), 14);
+    assert_eq!(export.records[0].record, put("k0"));
+    assert_eq!(kinds[10..], [RecordType::TxnBegin, RecordType::Put, RecordType::Put, RecordType::TxnCommit]);
+    assert!(export.records.iter().all(|r| r.at_ms > 0));
+
+    // Nothing new: an empty export of the same end
+    let second = d.join("2.wlx");
+    assert_eq!(wal.export_since(end, &second).unwrap(), end);
+    assert!(read_export(&second).unwrap().records.is_empty());
+    wal.append_record(RecordType::Put, b"after").unwrap();
+    let third = d.join("3.wlx");
+    wal.export_since(end, &third).unwrap();
+    assert_eq!(read_export(&third).unwrap().records.iter().map(|r| r.record.clone()).collect::<Vec<_>>(), vec![put("after")]);
+    let _ = std::fs::remove_dir_all(&d);
+}
+
+#[test]
+fn test_truncated_export_is_rejected() {
+    let d = dir("truncated");
+    let p = d.join("db.wal").to_string_lossy().into_owned();
+    let mut wal = Wal::open(&p).unwrap();
+    wal.append_record(RecordType::Put, b"a").unwrap();
+    let out = d.join("x.wlx");
+    wal.export_since(Default::default(), &out).unwrap();
+    let bytes = std::fs::read(&out).unwrap();
+    std::fs::write(&out, &bytes[..bytes.len() - 1]).unwrap();
+    assert!(read_export(&out).is_err());
+    let _ = std::fs::remove_dir_all(&d);
+}
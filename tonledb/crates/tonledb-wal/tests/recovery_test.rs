@@ -0,0 +1,73 @@
+//! Tests for point-in-time recovery
+
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tonledb_wal::{write_checkpoint, Lsn, RecoveryTarget, Record, RecordType, Wal, WalOptions};
+
+fn dir(name: &str) -> PathBuf {
+    let d = std::env::temp_dir().join(format!("tonledb-walpitr-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&d);
+    std::fs::create_dir_all(&d).unwrap();
+    d
+}
+
+fn raw(s: &str) -> Record {
+    Record::new(RecordType::Raw, s.as_bytes().to_vec())
+}
+
+/// Current time, between appends made just before and just after
+fn pause() -> u64 {
+    std::thread::sleep(Duration::from_millis(5));
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+    std::thread::sleep(Duration::from_millis(5));
+    now
+}
+
+#[test]
+fn test_replay_stops_at_a_time_or_lsn() {
+    let d = dir("target");
+    let p = d.join("db.wal").to_string_lossy().into_owned();
+    let mut wal = Wal::open_with(&p, WalOptions { segment_bytes: 64, ..Default::default() }).unwrap();
+    wal.append(b"a").unwrap();
+    wal.append(b"b").unwrap();
+    let t = pause();
+    wal.append(b"c").unwrap();
+    wal.append(b"d").unwrap();
+
+    assert_eq!(wal.replay_to(RecoveryTarget::Time(t)).unwrap(), vec![raw("a"), raw("b")]);
+    assert_eq!(wal.replay_to(RecoveryTarget::Time(u64::MAX)).unwrap(), wal.replay_records().unwrap());
+
+    let recs: Vec<_> = wal.tail(Lsn::default()).map(|r| r.unwrap()).collect();
+    assert!(recs[..2].iter().all(|r| r.time.unwrap() <= t) && recs[2..].iter().all(|r| r.time.unwrap() > t));
+    assert_eq!(wal.replay_to(RecoveryTarget::Lsn(recs[2].next)).unwrap(), vec![raw("a"), raw("b"), raw("c")]);
+    let _ = std::fs::remove_dir_all(&d);
+}
+
+#[test]
+fn test_target_before_the_checkpoint_needs_retired_segments() {
+    let d = dir("retired");
+    let p = d.join("db.wal").to_string_lossy().into_owned();
+    let opts = WalOptions { retention: Duration::from_secs(3600), ..Default::default() };
+    let mut wal = Wal::open_with(&p, opts).unwrap();
+    wal.append(b"a").unwrap();
+    let t = pause();
+    wal.append(b"b").unwrap();
+    let lsn = wal.begin_checkpoint().unwrap().unwrap();
+    write_checkpoint(wal.path(), lsn, &[raw("a"), raw("b")]).unwrap();
+    wal.complete_checkpoint(lsn).unwrap();
+    wal.append(b"c").unwrap();
+
+    // The checkpoint is newer than the target, so replay starts from the retired segment
+    assert_eq!(wal.replay_to(RecoveryTarget::Time(t)).unwrap(), vec![raw("a")]);
+    assert_eq!(wal.replay_to(RecoveryTarget::Time(u64::MAX)).unwrap(), vec![raw("a"), raw("b"), raw("c")]);
+    drop(wal);
+
+    // Once the retired segment is pruned that history is gone
+    let mut wal = Wal::open(&p).unwrap();
+    wal.append(b"d").unwrap();
+    let lsn = wal.begin_checkpoint().unwrap().unwrap();
+    write_checkpoint(wal.path(), lsn, &[raw("a"), raw("b"), raw("c"), raw("d")]).unwrap();
+    wal.complete_checkpoint(lsn).unwrap();
+    assert!(wal.replay_to(RecoveryTarget::Time(t)).is_err());
+    let _ = std::fs::remove_dir_all(&d);
+}
@@ -0,0 +1,144 @@
+//! Tests for WAL segment rotation, retention and archiving
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tonledb_wal::{CopyArchiver, Record, RecordType, SegmentArchiver, Wal, WalOptions};
+
+fn dir(name: &str) -> PathBuf {
+    let d = std::env::temp_dir().join(format!("tonledb-walseg-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&d);
+    std::fs::create_dir_all(&d).unwrap();
+    d
+}
+
+fn files(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(dir).unwrap().map(|e| e.unwrap().file_name().to_string_lossy().into_owned()).collect();
+    names.sort();
+    names
+}
+
+fn opts(segment_bytes: u64) -> WalOptions {
+    WalOptions { segment_bytes, ..Default::default() }
+}
+
+#[test]
+fn test_segments_rotate_and_replay_in_order() {
+    let d = dir("rotate");
+    let p = d.join("db.wal").to_string_lossy().into_owned();
+    let mut wal = Wal::open_with(&p, opts(64)).unwrap();
+    let payloads: Vec<Vec<u8>> = (0..20).map(|i| format!("record-{:02}", i).into_bytes()).collect();
+    for rec in &payloads {
+        wal.append(rec).unwrap();
+    }
+    drop(wal);
+
+    let names = files(&d);
+    assert!(names.iter().filter(|n| n.ends_with(".seg")).count() >= 3, "{:?}", names);
+    // No segment grows much past the limit
+    for n in names.iter().filter(|n| n.ends_with(".seg")) {
+        assert!(std::fs::metadata(d.join(n)).unwrap().len() < 64 + 32);
+    }
+    assert_eq!(Wal::open_with(&p, opts(64)).unwrap().replay().unwrap(), payloads);
+    let _ = std::fs::remove_dir_all(&d);
+}
+
+#[test]
+fn test_rewrite_retires_segments_and_retention_keeps_them() {
+    let d = dir("retire");
+    let p = d.join("db.wal").to_string_lossy().into_owned();
+    let keep = WalOptions { segment_bytes: 64, retention: Duration::from_secs(3600), ..Default::default() };
+    let mut wal = Wal::open_with(&p, keep).unwrap();
+    for i in 0..20 {
+        wal.append(format!("record-{:02}", i).as_bytes()).unwrap();
+    }
+    wal.rewrite(&[Record::new(RecordType::Raw, b"snapshot".to_vec())]).unwrap();
+    assert_eq!(wal.replay().unwrap(), vec![b"snapshot".to_vec()]);
+    let names = files(&d);
+    assert!(!names.iter().any(|n| n.ends_with(".seg")));
+    assert!(names.iter().any(|n| n.ends_with(".old")));
+    drop(wal);
+
+    // Without retention they are deleted on the next rewrite
+    let mut wal = Wal::open_with(&p, opts(64)).unwrap();
+    wal.rewrite(&[Record::new(RecordType::Raw, b"snapshot".to_vec())]).unwrap();
+    assert_eq!(files(&d), vec!["db.wal".to_string()]);
+    let _ = std::fs::remove_dir_all(&d);
+}
+
+/// Archives into a directory, failing while `broken` is set
+struct Flaky {
+    inner: CopyArchiver,
+    broken: Arc<Mutex<bool>>,
+}
+
+impl SegmentArchiver for Flaky {
+    fn archive(&mut self, seq: u64, segment: &Path) -> anyhow::Result<()> {
+        if *self.broken.lock().unwrap() {
+            anyhow::bail!("archive unreachable");
+        }
+        self.inner.archive(seq, segment)
+    }
+}
+
+#[test]
+fn test_unarchived_segments_are_kept_until_archived() {
+    let d = dir("archive");
+    let archive = d.join("archive");
+    let p = d.join("db.wal").to_string_lossy().into_owned();
+    let broken = Arc::new(Mutex::new(true));
+    let mut wal = Wal::open_with(&p, opts(64)).unwrap();
+    let archiver = Flaky { inner: CopyArchiver::new(&archive.to_string_lossy()).unwrap(), broken: broken.clone() };
+    wal.set_segment_archiver(Box::new(archiver)).unwrap();
+    for i in 0..20 {
+        wal.append(format!("record-{:02}", i).as_bytes()).unwrap();
+    }
+    assert!(files(&archive).is_empty());
+
+    // Retired but not archived yet, so the zero retention does not delete them
+    wal.rewrite(&[]).unwrap();
+    let retired = files(&d).into_iter().filter(|n| n.ends_with(".old")).count();
+    assert!(retired >= 3);
+
+    // The archiver thread, woken by the rewrite, may get to some of them first
+    *broken.lock().unwrap() = false;
+    wal.archive_pending().unwrap();
+    assert_eq!(files(&archive).len(), retired);
+    assert!(!files(&d).iter().any(|n| n.ends_with(".old")));
+    let _ = std::fs::remove_dir_all(&d);
+}
+
+/// Archives into a directory once `gate` is closed
+struct Gated {
+    inner: CopyArchiver,
+    gate: Mutex<std::sync::mpsc::Receiver<()>>,
+}
+
+impl SegmentArchiver for Gated {
+    fn archive(&mut self, seq: u64, segment: &Path) -> anyhow::Result<()> {
+        let _ = self.gate.lock().unwrap().recv();
+        self.inner.archive(seq, segment)
+    }
+}
+
+#[test]
+fn test_stalled_archiver_does_not_hold_up_appends() {
+    let d = dir("stalled");
+    let archive = d.join("archive");
+    let p = d.join("db.wal").to_string_lossy().into_owned();
+    let (release, gate) = std::sync::mpsc::channel();
+    let mut wal = Wal::open_with(&p, opts(64)).unwrap();
+    wal.set_segment_archiver(Box::new(Gated { inner: CopyArchiver::new(&archive.to_string_lossy()).unwrap(), gate: Mutex::new(gate) })).unwrap();
+    for i in 0..20 {
+        wal.append(format!("record-{:02}", i).as_bytes()).unwrap();
+    }
+    assert_eq!(wal.replay().unwrap().len(), 20);
+    assert!(files(&archive).is_empty());
+
+    drop(release);
+    wal.archive_pending().unwrap();
+    let sealed = files(&d).into_iter().filter(|n| n.ends_with(".seg")).count();
+    assert!(sealed >= 3);
+    assert_eq!(files(&archive).len(), sealed);
+    let _ = std::fs::remove_dir_all(&d);
+}
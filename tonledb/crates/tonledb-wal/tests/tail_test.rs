@@ -0,0 +1,83 @@
+//! Tests for following the WAL as it grows
+
+use std::path::PathBuf;
+use std::time::Duration;
+use tonledb_wal::{Lsn, Record, RecordType, Wal, WalOptions, WalTail};
+
+fn dir(name: &str) -> PathBuf {
+    let d = std::env::temp_dir().join(format!("tonledb-waltail-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&d);
+    std::fs::create_dir_all(&d).unwrap();
+    d
+}
+
+fn payloads(tail: &mut WalTail) -> Vec<Vec<u8>> {
+    tail.map(|r| r.unwrap().record.payload).collect()
+}
+
+#[test]
+fn test_tail_follows_appends_across_segments() {
+    let d = dir("follow");
+    let p = d.join("db.wal").to_string_lossy().into_owned();
+    let mut wal = Wal::open_with(&p, WalOptions { segment_bytes: 64, ..Default::default() }).unwrap();
+    let mut tail = wal.tail(Lsn::default());
+    assert!(tail.next().is_none());
+
+    let all: Vec<Vec<u8>> = (0..20).map(|i| format!("record-{:02}", i).into_bytes()).collect();
+    for rec in &all[..5] {
+        wal.append(rec).unwrap();
+    }
+    assert_eq!(payloads(&mut tail), all[..5].to_vec());
+    // Picks up where it stopped, through the rotations in between
+    for rec in &all[5..] {
+        wal.append(rec).unwrap();
+    }
+    assert_eq!(payloads(&mut tail), all[5..].to_vec());
+    assert_eq!(tail.position(), wal.end_lsn());
+
+    // A new reader resumes from the position after the last record it consumed
+    let tenth = wal.tail(Lsn::default()).nth(9).unwrap().unwrap();
+    assert_eq!(payloads(&mut WalTail::open(&d.join("db.wal"), tenth.next)), all[10..].to_vec());
+    let _ = std::fs::remove_dir_all(&d);
+}
+
+#[test]
+fn test_tail_sees_reset_after_rewrite() {
+    let d = dir("reset");
+    let p = d.join("db.wal").to_string_lossy().into_owned();
+    let keep = WalOptions { retention: Duration::from_secs(3600), ..Default::default() };
+    let mut wal = Wal::open_with(&p, keep).unwrap();
+    wal.append(b"a").unwrap();
+    let mut tail = wal.tail(Lsn::default());
+    assert_eq!(payloads(&mut tail), vec![b"a".to_vec()]);
+
+    wal.rewrite(&[Record::new(RecordType::Raw, b"snapshot".to_vec())]).unwrap();
+    wal.append(b"b").unwrap();
+    let kinds: Vec<RecordType> = tail.by_ref().map(|r| r.unwrap().record.kind).collect();
+    assert_eq!(kinds, vec![RecordType::Reset, RecordType::Raw, RecordType::Raw]);
+
+    // Without retention the old segment is gone, which a reader still in it is told
+    let p2 = d.join("bare.wal").to_string_lossy().into_owned();
+    let mut wal = Wal::open(&p2).unwrap();
+    wal.append(b"a").unwrap();
+    let mut tail = wal.tail(Lsn::default());
+    tail.next().unwrap().unwrap();
+    wal.rewrite(&[]).unwrap();
+    assert!(tail.next().unwrap().is_err());
+    let _ = std::fs::remove_dir_all(&d);
+}
+
+#[test]
+fn test_recv_waits_for_the_next_append() {
+    let d = dir("recv");
+    let p = d.join("db.wal").to_string_lossy().into_owned();
+    let mut wal = Wal::open(&p).unwrap();
+    let mut tail = wal.tail(wal.end_lsn());
+    let writer = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(50));
+        wal.append(b"late").unwrap();
+    });
+    assert_eq!(tail.recv(Duration::from_millis(5)).unwrap().record.payload, b"late".to_vec());
+    writer.join().unwrap();
+    let _ = std::fs::remove_dir_all(&d);
+}
@@ -0,0 +1,47 @@
+//! Tests for transactions in the WAL
+
+use tonledb_wal::{Record, RecordType, Wal};
+
+fn path(name: &str) -> String {
+    let p = std::env::temp_dir().join(format!("tonledb-wal-{}-{}.wal", name, std::process::id()));
+    let _ = std::fs::remove_file(&p);
+    p.to_string_lossy().into_owned()
+}
+
+fn put(s: &str) -> Record {
+    Record::new(RecordType::Put, s.as_bytes().to_vec())
+}
+
+#[test]
+fn test_committed_transaction_replays_without_markers() {
+    let p = path("txn-ok");
+    let mut wal = Wal::open(&p).unwrap();
+    wal.append_record(RecordType::Put, b"before").unwrap();
+    wal.append_txn(7, &[put("a"), Record::new(RecordType::Delete, b"b".to_vec())]).unwrap();
+    drop(wal);
+    let recs = Wal::open(&p).unwrap().replay_records().unwrap();
+    assert_eq!(recs, vec![put("before"), put("a"), Record::new(RecordType::Delete, b"b".to_vec())]);
+    let _ = std::fs::remove_file(&p);
+}
+
+#[test]
+fn test_torn_transaction_is_dropped_and_cut_off() {
+    let p = path("txn-torn");
+    let mut wal = Wal::open(&p).unwrap();
+    wal.append_record(RecordType::Put, b"before").unwrap();
+    let len_before = std::fs::metadata(&p).unwrap().len();
+    wal.append_txn(7, &[put("a"), put("b")]).unwrap();
+    drop(wal);
+
+    // Crash after the first record of the transaction reached the disk
+    let full = std::fs::read(&p).unwrap();
+    let cut = len_before as usize + (9 + 8) + (9 + 8) + (9 + 1) + 3;
+    std::fs::write(&p, &full[..cut]).unwrap();
+    let mut wal = Wal::open(&p).unwrap();
+    assert_eq!(std::fs::metadata(&p).unwrap().len(), len_before);
+
+    // Later appends are not swallowed by the unfinished transaction
+    wal.append_record(RecordType::Put, b"after").unwrap();
+    assert_eq!(wal.replay_records().unwrap(), vec![put("before"), put("after")]);
+    let _ = std::fs::remove_file(&p);
+}
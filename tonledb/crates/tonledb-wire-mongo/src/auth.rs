@@ -0,0 +1,44 @@
+//! Who may run what
+//!
+//! With a `PasswordStore` in the `MongoOptions`, a connection logs in with SASL
+//! PLAIN before anything but the handshake: `saslStart` with mechanism `PLAIN`
+//! and the payload `\0<user>\0<password>`, which drivers send for
+//! `authMechanism=PLAIN&authSource=$external`. PLAIN carries the password as it
+//! is, so it is only taken over TLS (`MongoOptions::tls`); on a plain connection
+//! `saslStart` is refused before the password is looked at.
+//! Reads need `Read`; commands that write documents, collections or indexes need
+//! `Write`.
+
+/// What a user may do, each level including the ones before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Access {
+    Read,
+    Write,
+    Admin,
+}
+
+/// Checks the passwords of clients logging in
+pub trait PasswordStore: Send + Sync {
+    /// What `user` may do if `password` is theirs; `None` when it isn't
+    fn verify(&self, user: &str, password: &str) -> Option<Access>;
+}
+
+/// The access command `name` needs; `None` for the handshake and login commands,
+/// which run before logging in
+pub(crate) fn needed(name: &str) -> Option<Access> {
+    match name {
+        "hello" | "isMaster" | "ismaster" | "ping" | "buildInfo" | "buildinfo" | "saslStart" | "saslContinue" | "endSessions" | "connectionStatus" => None,
+        "create" | "drop" | "insert" | "update" | "delete" | "createIndexes" | "dropIndexes" => Some(Access::Write),
+        _ => Some(Access::Read),
+    }
+}
+
+/// User and password of a SASL PLAIN message (`[authzid] \0 user \0 password`)
+pub(crate) fn plain(payload: &[u8]) -> Option<(&str, &str)> {
+    let mut parts = payload.split(|&b| b == 0);
+    let (_authzid, user, password) = (parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some() {
+        return None;
+    }
+    Some((std::str::from_utf8(user).ok()?, std::str::from_utf8(password).ok()?))
+}
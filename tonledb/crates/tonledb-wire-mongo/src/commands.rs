@@ -0,0 +1,921 @@
+//! Database commands, run against the document store.
+//!
+//! A command is a document whose first key names it. CRUD maps onto
+//! `tonledb_nosql_doc`: `find` and `count` onto `query` (indexed when the filter
+//! allows), `insert` onto `insert` (under the document's own `_id` when it has
+//! one), `update` onto the update operators of `update` or a replacement, and
+//! `delete` onto `delete`. `aggregate` runs the stages the store knows (`$match`,
+//! `$lookup`, `$skip`, `$limit`) through `aggregate`, and `$sort`, `$project`,
+//! `$count` and a counting `$group` here. Indexes are single-field, named
+//! `<field>_1`.
+//!
+//! Results come back through cursors: the first batch in the reply, the rest held
+//! by the `Session` for `getMore`, at most `MAX_CURSORS` of them at once. An
+//! unsorted `find` reads only what each batch needs; a sorted one keeps the
+//! first `skip + limit` documents while it reads, and fails past `MAX_SORT_DOCS`.
+//! Update operators are applied to each matched document as it is when written
+//! (see `docs::update_ops`), so concurrent updates don't lose each other.
+//!
+//! With a `PasswordStore` the session has to log in first, and each command
+//! needs the access `auth::needed` names. Handshake and admin commands (`hello`,
+//! `buildInfo`, `listDatabases`, ...) answer with what drivers and Compass expect
+//! of a standalone server.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use bson::spec::BinarySubtype;
+use bson::{doc, Bson, Document};
+use serde_json::{Map, Value as Json};
+use tonledb_core::{numbers, Db, DbError, Storage};
+use tonledb_nosql_doc::aggregate::{self, Pipeline};
+use tonledb_nosql_doc::capped::{self, Cap};
+use tonledb_nosql_doc::filter::{self, Filter};
+use tonledb_nosql_doc::{self as docs, index, update, Paging};
+use crate::auth::{self, Access, PasswordStore};
+use crate::convert::{doc_to_json, to_bson, to_document};
+use crate::protocol::MAX_MESSAGE_BYTES;
+
+/// The one database listed; every database name reaches the same collections
+pub const DATABASE: &str = "tonledb";
+/// Largest document accepted, as `hello` announces it
+pub const MAX_BSON_OBJECT_SIZE: i32 = 16 * 1024 * 1024;
+/// Wire version of MongoDB 6.0, the newest whose commands are answered
+const MAX_WIRE_VERSION: i32 = 17;
+/// Documents in a first batch when the client doesn't ask for a size
+const DEFAULT_BATCH: usize = 101;
+/// A batch stops once its documents take this many bytes
+const BATCH_BYTES: usize = 8 << 20;
+/// Cursors a session can have open at once
+pub const MAX_CURSORS: usize = 100;
+/// Most documents a sorted `find` holds while sorting: every match without a
+/// `limit`, `skip + limit` with one
+pub const MAX_SORT_DOCS: usize = 100_000;
+/// Documents a `find` reads from the store at a time once past its first batch
+const FIND_PAGE: usize = 1000;
+
+/// A failed command, answered as `{ok: 0, errmsg, code, codeName}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandError {
+    pub code: i32,
+    pub code_name: &'static str,
+    pub message: String,
+}
+
+impl CommandError {
+    pub fn new(code: i32, code_name: &'static str, message: impl Into<String>) -> Self {
+        Self { code, code_name, message: message.into() }
+    }
+
+    fn bad_value(message: impl Into<String>) -> Self {
+        Self::new(2, "BadValue", message)
+    }
+
+    pub fn to_document(&self) -> Document {
+        doc! { "ok": 0.0, "errmsg": self.message.clone(), "code": self.code, "codeName": self.code_name }
+    }
+
+    /// Entry of a write reply's `writeErrors` for the `index`th write
+    fn write_error(&self, index: usize) -> Document {
+        doc! { "index": index as i32, "code": self.code, "codeName": self.code_name, "errmsg": self.message.clone() }
+    }
+}
+
+impl From<DbError> for CommandError {
+    fn from(e: DbError) -> Self {
+        let (code, code_name) = match &e {
+            DbError::Conflict(_) | DbError::UniqueViolation(_) => (11000, "DuplicateKey"),
+            DbError::Invalid(_) => (2, "BadValue"),
+            DbError::NotFound(_) => (26, "NamespaceNotFound"),
+            DbError::DeadlineExceeded(_) => (50, "MaxTimeMSExpired"),
+            _ => (1, "InternalError"),
+        };
+        Self::new(code, code_name, e.to_string())
+    }
+}
+
+type CommandResult<T = Document> = Result<T, CommandError>;
+
+/// What a connection is logged in as, and the cursors it has open
+pub struct Session {
+    cursors: HashMap<i64, Cursor>,
+    last_cursor: i64,
+    passwords: Option<Arc<dyn PasswordStore>>,
+    /// `None` until logged in
+    access: Option<Access>,
+    /// Whether the connection is inside TLS, which PLAIN needs
+    secure: bool,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new(None, false)
+    }
+}
+
+/// Documents of a result not sent yet
+struct Cursor {
+    ns: String,
+    docs: std::vec::IntoIter<Json>,
+    /// Where an unsorted `find` reads on once `docs` runs out
+    rest: Option<FindRest>,
+}
+
+/// The part of an unsorted `find` not read yet
+struct FindRest {
+    coll: String,
+    filter: Filter,
+    /// Id of the last document read
+    after: String,
+    /// Documents still to return; `None` for every match
+    limit: Option<usize>,
+    projection: Option<Json>,
+}
+
+impl Cursor {
+    fn exhausted(&self) -> bool {
+        self.docs.as_slice().is_empty() && self.rest.is_none()
+    }
+
+    /// Read up to `size` more documents of an unsorted `find` into `docs`
+    fn read_on(&mut self, storage: &dyn Storage, size: usize) -> CommandResult<()> {
+        let Some(rest) = self.rest.take() else { return Ok(()) };
+        let size = size.clamp(1, FIND_PAGE);
+        let paging = Paging { after: Some(rest.after.clone()), skip: 0, limit: Some(rest.limit.map_or(size, |l| l.min(size))) };
+        let page = docs::query_page(storage, &rest.coll, &rest.filter, true, &paging)?;
+        let limit = rest.limit.map(|l| l - page.docs.len());
+        self.docs = projected(page.docs, rest.projection.as_ref())?.into_iter();
+        if let Some(after) = page.next.filter(|_| limit != Some(0)) {
+            self.rest = Some(FindRest { after, limit, ..rest });
+        }
+        Ok(())
+    }
+}
+
+impl Session {
+    /// A session that logs in against `passwords`, if `secure`; without them it may
+    /// run anything
+    pub fn new(passwords: Option<Arc<dyn PasswordStore>>, secure: bool) -> Self {
+        let access = passwords.is_none().then_some(Access::Admin);
+        Self { cursors: HashMap::new(), last_cursor: 0, passwords, access, secure }
+    }
+
+    /// Whether the session has logged in, or needs no login
+    pub fn logged_in(&self) -> bool {
+        self.access.is_some()
+    }
+
+    /// Run `command`; a failure is answered with its error document
+    pub fn run(&mut self, db: &Db, command: &Document) -> Document {
+        self.dispatch(db, command).unwrap_or_else(|e| e.to_document())
+    }
+
+    fn dispatch(&mut self, db: &Db, cmd: &Document) -> CommandResult {
+        let name = cmd.keys().next().map(String::as_str).unwrap_or("");
+        let database = cmd.get_str("$db").unwrap_or("admin");
+        let storage = &*db.storage;
+        match (auth::needed(name), self.access) {
+            (Some(_), None) => return Err(CommandError::new(13, "Unauthorized", format!("command {} requires authentication", name))),
+            (Some(needed), Some(access)) if access < needed => {
+                return Err(CommandError::new(13, "Unauthorized", format!("not authorized to run {}", name)));
+            }
+            _ => {}
+        }
+        match name {
+            "hello" | "isMaster" | "ismaster" => Ok(hello(name != "hello")),
+            "ping" | "endSessions" | "killAllSessions" => Ok(doc! { "ok": 1.0 }),
+            "buildInfo" | "buildinfo" => Ok(doc! {
+                "version": crate::SERVER_VERSION,
+                "gitVersion": "tonledb",
+                "versionArray": [6, 0, 0, 0],
+                "bits": 64,
+                "maxBsonObjectSize": MAX_BSON_OBJECT_SIZE,
+                "ok": 1.0,
+            }),
+            "connectionStatus" => Ok(doc! {
+                "authInfo": { "authenticatedUsers": [], "authenticatedUserRoles": [] },
+                "ok": 1.0,
+            }),
+            "saslStart" => self.sasl_start(cmd),
+            // PLAIN is done in one step
+            "saslContinue" => Err(CommandError::new(17, "ProtocolError", "no SASL conversation is in progress")),
+            "getLastError" => Ok(doc! { "n": 0, "err": Bson::Null, "ok": 1.0 }),
+            "listDatabases" => Ok(doc! {
+                "databases": [{ "name": DATABASE, "sizeOnDisk": 0i64, "empty": false }],
+                "totalSize": 0i64,
+                "ok": 1.0,
+            }),
+            "listCollections" => {
+                let filter = filter_of(cmd, "filter")?;
+                let name_only = flag(cmd, "nameOnly");
+                let infos = docs::list_collections(storage)?
+                    .into_iter()
+                    .map(|name| serde_json::json!({ "name": name, "type": "collection", "options": {}, "info": { "readOnly": false } }))
+                    .filter(|info| filter.matches(info))
+                    .map(|info| if name_only { serde_json::json!({ "name": info["name"], "type": "collection" }) } else { info })
+                    .collect();
+                self.cursor(format!("{}.$cmd.listCollections", database), infos, None, cursor_batch_size(cmd)?, false)
+            }
+            "create" => {
+                let coll = collection(cmd, "create")?;
+                if flag(cmd, "capped") {
+                    let cap = Cap { max_docs: count_of(cmd, "max")?.map(|n| n as u64), max_bytes: count_of(cmd, "size")?.map(|n| n as u64) };
+                    capped::create_capped_collection(storage, coll, cap)?;
+                } else {
+                    docs::create_collection(storage, coll)?;
+                }
+                Ok(doc! { "ok": 1.0 })
+            }
+            "drop" => {
+                let coll = collection(cmd, "drop")?;
+                docs::drop_collection(storage, coll)?;
+                Ok(doc! { "ns": format!("{}.{}", database, coll), "ok": 1.0 })
+            }
+            "insert" => insert(storage, cmd),
+            "update" => update_docs(storage, cmd),
+            "delete" => delete_docs(storage, cmd),
+            "find" => self.find(storage, database, cmd),
+            "getMore" => self.get_more(storage, cmd),
+            "killCursors" => {
+                let ids: Vec<i64> = cmd.get_array("cursors").map(|ids| ids.iter().filter_map(number).collect()).unwrap_or_default();
+                let (killed, not_found): (Vec<i64>, Vec<i64>) = ids.into_iter().partition(|id| self.cursors.remove(id).is_some());
+                Ok(doc! { "cursorsKilled": killed, "cursorsNotFound": not_found, "cursorsAlive": [], "cursorsUnknown": [], "ok": 1.0 })
+            }
+            "aggregate" => {
+                let Ok(coll) = cmd.get_str("aggregate") else {
+                    return Err(CommandError::bad_value("aggregate needs a collection; database aggregations are not supported"));
+                };
+                check_name(coll)?;
+                let stages = cmd.get_array("pipeline").map_err(|_| CommandError::bad_value("aggregate needs a pipeline array"))?;
+                let results = run_pipeline(storage, coll, stages)?;
+                self.cursor(format!("{}.{}", database, coll), results, None, cursor_batch_size(cmd)?, false)
+            }
+            "count" => {
+                let coll = collection(cmd, "count")?;
+                let n = docs::count(storage, coll, &filter_of(cmd, "query")?)?.saturating_sub(count_of(cmd, "skip")?.unwrap_or(0));
+                let n = match count_of(cmd, "limit")? {
+                    Some(limit) if limit > 0 => n.min(limit),
+                    _ => n,
+                };
+                Ok(doc! { "n": n as i64, "ok": 1.0 })
+            }
+            "distinct" => {
+                let coll = collection(cmd, "distinct")?;
+                let key = cmd.get_str("key").map_err(|_| CommandError::bad_value("distinct needs a key"))?;
+                let values: Vec<Bson> = docs::distinct(storage, coll, key, &filter_of(cmd, "query")?)?.iter().map(to_bson).collect();
+                Ok(doc! { "values": values, "ok": 1.0 })
+            }
+            "createIndexes" => create_indexes(storage, cmd),
+            "listIndexes" => {
+                let coll = collection(cmd, "listIndexes")?;
+                let mut specs = vec![serde_json::json!({ "v": 2, "key": { "_id": 1 }, "name": "_id_" })];
+                for field in index::indexed_fields(storage, coll)? {
+                    let key = Map::from_iter([(field.clone(), Json::from(1))]);
+                    let mut spec = serde_json::json!({ "v": 2, "key": key, "name": format!("{}_1", field) });
+                    if index::is_unique(storage, coll, &field)? {
+                        spec["unique"] = Json::Bool(true);
+                    }
+                    specs.push(spec);
+                }
+                self.cursor(format!("{}.{}", database, coll), specs, None, cursor_batch_size(cmd)?, false)
+            }
+            "dropIndexes" => drop_indexes(storage, cmd),
+            other => Err(CommandError::new(59, "CommandNotFound", format!("no such command: '{}'", other))),
+        }
+    }
+
+    /// Log in with SASL PLAIN
+    fn sasl_start(&mut self, cmd: &Document) -> CommandResult {
+        let Some(passwords) = &self.passwords else {
+            return Err(CommandError::new(334, "MechanismUnavailable", "authentication is not enabled"));
+        };
+        if cmd.get_str("mechanism") != Ok("PLAIN") {
+            return Err(CommandError::new(334, "MechanismUnavailable", "only the PLAIN mechanism is supported"));
+        }
+        if !self.secure {
+            return Err(CommandError::new(334, "MechanismUnavailable", "PLAIN sends the password as it is, so it is only accepted over TLS"));
+        }
+        let payload = match cmd.get("payload") {
+            Some(Bson::Binary(b)) => b.bytes.as_slice(),
+            _ => return Err(CommandError::bad_value("saslStart needs a binary payload")),
+        };
+        let access = auth::plain(payload).and_then(|(user, password)| passwords.verify(user, password));
+        let Some(access) = access else {
+            return Err(CommandError::new(18, "AuthenticationFailed", "Authentication failed."));
+        };
+        self.access = Some(access);
+        let payload = bson::Binary { subtype: BinarySubtype::Generic, bytes: Vec::new() };
+        Ok(doc! { "conversationId": 1, "done": true, "payload": payload, "ok": 1.0 })
+    }
+
+    fn find(&mut self, storage: &dyn Storage, database: &str, cmd: &Document) -> CommandResult {
+        let coll = collection(cmd, "find")?;
+        let filter = filter_of(cmd, "filter")?;
+        let skip = count_of(cmd, "skip")?.unwrap_or(0);
+        let limit = count_of(cmd, "limit")?.filter(|&n| n > 0);
+        let projection = cmd.get_document("projection").ok().filter(|p| !p.is_empty()).map(doc_to_json);
+        let batch_size = count_of(cmd, "batchSize")?;
+        let (results, rest) = match cmd.get_document("sort").ok().filter(|s| !s.is_empty()) {
+            Some(sort) => (sorted(storage, coll, &filter, &sort_keys(&doc_to_json(sort))?, skip, limit)?, None),
+            None => {
+                // Only the first batch is read now; `getMore` reads on from there
+                let first = batch_size.unwrap_or(DEFAULT_BATCH).max(1);
+                let paging = Paging { after: None, skip, limit: Some(limit.map_or(first, |l| l.min(first))) };
+                let page = docs::query_page(storage, coll, &filter, true, &paging)?;
+                let limit = limit.map(|l| l - page.docs.len());
+                let rest = page.next.filter(|_| limit != Some(0)).map(|after| FindRest {
+                    coll: coll.to_string(),
+                    filter,
+                    after,
+                    limit,
+                    projection: projection.clone(),
+                });
+                (page.docs, rest)
+            }
+        };
+        let results = projected(results, projection.as_ref())?;
+        self.cursor(format!("{}.{}", database, coll), results, rest, batch_size, flag(cmd, "singleBatch"))
+    }
+
+    fn get_more(&mut self, storage: &dyn Storage, cmd: &Document) -> CommandResult {
+        let id = cmd.get("getMore").and_then(number).ok_or_else(|| CommandError::bad_value("getMore needs a cursor id"))?;
+        let batch_size = count_of(cmd, "batchSize")?;
+        let Some(cursor) = self.cursors.get_mut(&id) else {
+            return Err(CommandError::new(43, "CursorNotFound", format!("cursor id {} not found", id)));
+        };
+        let size = batch_size.unwrap_or(usize::MAX);
+        if cursor.docs.as_slice().is_empty() {
+            cursor.read_on(storage, size)?;
+        }
+        let next = batch(&mut cursor.docs, size);
+        let ns = cursor.ns.clone();
+        let id = if cursor.exhausted() {
+            self.cursors.remove(&id);
+            0
+        } else {
+            id
+        };
+        Ok(doc! { "cursor": { "nextBatch": next, "id": id, "ns": ns }, "ok": 1.0 })
+    }
+
+    /// Answer with the first batch of `results`, keeping the rest (and `rest`) for
+    /// `getMore` unless `single`
+    fn cursor(&mut self, ns: String, results: Vec<Json>, rest: Option<FindRest>, batch_size: Option<usize>, single: bool) -> CommandResult {
+        let mut cursor = Cursor { ns: ns.clone(), docs: results.into_iter(), rest };
+        let first = batch(&mut cursor.docs, batch_size.unwrap_or(DEFAULT_BATCH));
+        let id = if single || cursor.exhausted() {
+            0
+        } else {
+            if self.cursors.len() >= MAX_CURSORS {
+                return Err(CommandError::new(
+                    96,
+                    "OperationFailed",
+                    format!("a connection can have at most {} open cursors; close some with killCursors", MAX_CURSORS),
+                ));
+            }
+            self.last_cursor += 1;
+            self.cursors.insert(self.last_cursor, cursor);
+            self.last_cursor
+        };
+        Ok(doc! { "cursor": { "firstBatch": first, "id": id, "ns": ns }, "ok": 1.0 })
+    }
+}
+
+fn hello(legacy: bool) -> Document {
+    let mut reply = doc! {
+        "helloOk": true,
+        "maxBsonObjectSize": MAX_BSON_OBJECT_SIZE,
+        "maxMessageSizeBytes": MAX_MESSAGE_BYTES as i32,
+        "maxWriteBatchSize": 100_000,
+        "localTime": bson::DateTime::now(),
+        "logicalSessionTimeoutMinutes": 30,
+        "minWireVersion": 0,
+        "maxWireVersion": MAX_WIRE_VERSION,
+        "readOnly": false,
+        "ok": 1.0,
+    };
+    reply.insert(if legacy { "ismaster" } else { "isWritablePrimary" }, true);
+    reply
+}
+
+/// Up to `size` documents, fewer once they take `BATCH_BYTES`
+fn batch(results: &mut std::vec::IntoIter<Json>, size: usize) -> Vec<Document> {
+    let mut out = Vec::new();
+    let mut bytes = 0;
+    while out.len() < size && bytes < BATCH_BYTES {
+        let Some(doc) = results.next() else { break };
+        let doc = to_document(&doc);
+        let mut encoded = Vec::new();
+        bytes += doc.to_writer(&mut encoded).map_or(0, |_| encoded.len());
+        out.push(doc);
+    }
+    out
+}
+
+/// The documents `filter` matches in `keys` order, past `skip` and up to `limit`.
+/// The collection is read a page at a time, keeping only the first `skip + limit`
+/// so far.
+fn sorted(storage: &dyn Storage, coll: &str, filter: &Filter, keys: &[(String, bool)], skip: usize, limit: Option<usize>) -> CommandResult<Vec<Json>> {
+    let keep = limit.map_or(usize::MAX, |l| l.saturating_add(skip));
+    let mut kept = Vec::new();
+    let mut after = None;
+    loop {
+        let page = docs::query_page(storage, coll, filter, true, &Paging { after, skip: 0, limit: Some(FIND_PAGE) })?;
+        kept.extend(page.docs);
+        if kept.len() > keep {
+            sort_docs(&mut kept, keys);
+            kept.truncate(keep);
+        }
+        if kept.len() > MAX_SORT_DOCS {
+            return Err(CommandError::new(
+                292,
+                "QueryExceededMemoryLimitNoDiskUseAllowed",
+                format!("a sort can hold at most {} documents; add a limit", MAX_SORT_DOCS),
+            ));
+        }
+        match page.next {
+            Some(next) => after = Some(next),
+            None => break,
+        }
+    }
+    sort_docs(&mut kept, keys);
+    Ok(kept.into_iter().skip(skip).collect())
+}
+
+/// `docs` through the projection `spec`, if any
+fn projected(docs: Vec<Json>, spec: Option<&Json>) -> CommandResult<Vec<Json>> {
+    match spec {
+        None => Ok(docs),
+        Some(spec) => docs.iter().map(|d| project(d, spec)).collect(),
+    }
+}
+
+/// Collection named by the command's `key`
+fn collection<'a>(cmd: &'a Document, key: &str) -> CommandResult<&'a str> {
+    let name = cmd.get_str(key).map_err(|_| CommandError::bad_value(format!("{} needs a collection name", key)))?;
+    check_name(name)?;
+    Ok(name)
+}
+
+/// Collection names become part of storage keys, so they can't hold a `/`
+fn check_name(name: &str) -> CommandResult<()> {
+    if name.is_empty() || name.contains('/') || name.contains('\0') {
+        return Err(CommandError::new(73, "InvalidNamespace", format!("invalid collection name {:?}", name)));
+    }
+    Ok(())
+}
+
+/// The filter at `key`; matching everything when absent
+fn filter_of(cmd: &Document, key: &str) -> CommandResult<Filter> {
+    match cmd.get(key) {
+        None | Some(Bson::Null) => Ok(Filter::all()),
+        Some(Bson::Document(d)) => Ok(Filter::parse(&doc_to_json(d))?),
+        Some(_) => Err(CommandError::bad_value(format!("{} must be a document", key))),
+    }
+}
+
+/// A whole number, whichever numeric type the driver sent it as
+fn number(value: &Bson) -> Option<i64> {
+    match value {
+        Bson::Int32(n) => Some(*n as i64),
+        Bson::Int64(n) => Some(*n),
+        Bson::Double(f) if f.fract() == 0.0 => Some(*f as i64),
+        _ => None,
+    }
+}
+
+/// The non-negative number at `key`, if any
+fn count_of(cmd: &Document, key: &str) -> CommandResult<Option<usize>> {
+    match cmd.get(key) {
+        None | Some(Bson::Null) => Ok(None),
+        Some(v) => number(v)
+            .and_then(|n| usize::try_from(n).ok())
+            .map(Some)
+            .ok_or_else(|| CommandError::bad_value(format!("{} must be a non-negative integer", key))),
+    }
+}
+
+fn flag(cmd: &Document, key: &str) -> bool {
+    match cmd.get(key) {
+        Some(Bson::Boolean(b)) => *b,
+        Some(v) => number(v).is_some_and(|n| n != 0),
+        None => false,
+    }
+}
+
+/// `cursor.batchSize` of commands that take a `cursor` option
+fn cursor_batch_size(cmd: &Document) -> CommandResult<Option<usize>> {
+    match cmd.get_document("cursor") {
+        Ok(options) => count_of(options, "batchSize"),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Store id of a document read from a collection
+fn doc_id(doc: &Json) -> CommandResult<String> {
+    doc.get("_id")
+        .and_then(Json::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| CommandError::new(1, "InternalError", "document without a string _id"))
+}
+
+fn insert(storage: &dyn Storage, cmd: &Document) -> CommandResult {
+    let coll = collection(cmd, "insert")?;
+    let documents = cmd.get_array("documents").map_err(|_| CommandError::bad_value("insert needs documents"))?;
+    let ordered = cmd.get_bool("ordered").unwrap_or(true);
+    let mut n = 0;
+    let mut errors = Vec::new();
+    for (i, d) in documents.iter().enumerate() {
+        let res = match d {
+            Bson::Document(d) => insert_one(storage, coll, doc_to_json(d)),
+            _ => Err(CommandError::bad_value("documents to insert must be objects")),
+        };
+        match res {
+            Ok(_) => n += 1,
+            Err(e) => {
+                errors.push(e.write_error(i));
+                if ordered {
+                    break;
+                }
+            }
+        }
+    }
+    Ok(write_reply(n, errors))
+}
+
+/// Insert `doc` under its `_id`, or a generated one; returns the id
+fn insert_one(storage: &dyn Storage, coll: &str, doc: Json) -> CommandResult<String> {
+    match doc.get("_id") {
+        None => Ok(docs::insert(storage, coll, doc)?),
+        Some(Json::String(id)) => {
+            let id = id.clone();
+            docs::insert_with_id(storage, coll, &id, doc)?;
+            Ok(id)
+        }
+        Some(_) => Err(CommandError::bad_value("_id must be a string or an ObjectId")),
+    }
+}
+
+fn write_reply(n: usize, errors: Vec<Document>) -> Document {
+    let mut reply = doc! { "n": n as i32, "ok": 1.0 };
+    if !errors.is_empty() {
+        reply.insert("writeErrors", errors);
+    }
+    reply
+}
+
+fn update_docs(storage: &dyn Storage, cmd: &Document) -> CommandResult {
+    let coll = collection(cmd, "update")?;
+    let updates = cmd.get_array("updates").map_err(|_| CommandError::bad_value("update needs updates"))?;
+    let ordered = cmd.get_bool("ordered").unwrap_or(true);
+    let (mut n, mut modified) = (0, 0);
+    let mut upserted = Vec::new();
+    let mut errors = Vec::new();
+    for (i, u) in updates.iter().enumerate() {
+        let res = match u {
+            Bson::Document(u) => update_one(storage, coll, u),
+            _ => Err(CommandError::bad_value("updates must be objects")),
+        };
+        match res {
+            Ok(Updated { matched, modified: m, upserted: None }) => {
+                n += matched;
+                modified += m;
+            }
+            Ok(Updated { upserted: Some(id), .. }) => {
+                n += 1;
+                upserted.push(doc! { "index": i as i32, "_id": id });
+            }
+            Err(e) => {
+                errors.push(e.write_error(i));
+                if ordered {
+                    break;
+                }
+            }
+        }
+    }
+    let mut reply = write_reply(n, errors);
+    reply.insert("nModified", modified as i32);
+    if !upserted.is_empty() {
+        reply.insert("upserted", upserted);
+    }
+    Ok(reply)
+}
+
+/// Outcome of one update statement
+struct Updated {
+    matched: usize,
+    modified: usize,
+    /// Id of the document an upsert inserted
+    upserted: Option<String>,
+}
+
+fn update_one(storage: &dyn Storage, coll: &str, spec: &Document) -> CommandResult<Updated> {
+    let query = match spec.get("q") {
+        Some(Bson::Document(q)) => doc_to_json(q),
+        _ => return Err(CommandError::bad_value("an update needs a q document")),
+    };
+    let change = match spec.get("u") {
+        Some(Bson::Document(u)) => doc_to_json(u),
+        Some(Bson::Array(_)) => return Err(CommandError::bad_value("pipeline updates are not supported")),
+        _ => return Err(CommandError::bad_value("an update needs a u document")),
+    };
+    let operators = change.as_object().and_then(|o| o.keys().next()).is_some_and(|k| k.starts_with('$'));
+    let multi = flag(spec, "multi");
+    if multi && !operators {
+        return Err(CommandError::bad_value("multi updates take update operators, not a replacement"));
+    }
+    let paging = Paging { limit: (!multi).then_some(1), ..Default::default() };
+    let matched = docs::query_page(storage, coll, &Filter::parse(&query)?, true, &paging)?.docs;
+    if matched.is_empty() {
+        if !flag(spec, "upsert") {
+            return Ok(Updated { matched: 0, modified: 0, upserted: None });
+        }
+        // The upserted document starts from the query's equality fields
+        let equalities: Map<String, Json> = query
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter(|(k, v)| !k.starts_with('$') && !v.as_object().is_some_and(|o| o.keys().any(|k| k.starts_with('$'))))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        let mut seed = Json::Object(Map::new());
+        update::apply(&mut seed, &serde_json::json!({ "$set": equalities }))?;
+        let id = insert_one(storage, coll, changed(seed, &change, operators)?)?;
+        return Ok(Updated { matched: 0, modified: 0, upserted: Some(id) });
+    }
+    let mut modified = 0;
+    for old in &matched {
+        // Checked against the document as read; operators are then applied to it as
+        // it is when written, so a concurrent update of it isn't lost
+        let new = changed(old.clone(), &change, operators)?;
+        if new == *old {
+            continue;
+        }
+        let id = doc_id(old)?;
+        let written = if operators { docs::update_ops(storage, coll, &id, &change, false)? } else { docs::replace(storage, coll, &id, new)? };
+        if written {
+            modified += 1;
+        }
+    }
+    Ok(Updated { matched: matched.len(), modified, upserted: None })
+}
+
+/// `doc` after the update operators or replacement `change`; its `_id` can't change
+fn changed(doc: Json, change: &Json, operators: bool) -> CommandResult<Json> {
+    let id = doc.get("_id").cloned();
+    let new = if operators {
+        let mut new = doc;
+        update::apply(&mut new, change)?;
+        new
+    } else {
+        let mut new = change.clone();
+        if let (Some(id), Some(obj)) = (&id, new.as_object_mut()) {
+            obj.entry("_id").or_insert_with(|| id.clone());
+        }
+        new
+    };
+    if id.is_some() && new.get("_id") != id.as_ref() {
+        return Err(CommandError::new(66, "ImmutableField", "the _id of a document can't change"));
+    }
+    Ok(new)
+}
+
+fn delete_docs(storage: &dyn Storage, cmd: &Document) -> CommandResult {
+    let coll = collection(cmd, "delete")?;
+    let deletes = cmd.get_array("deletes").map_err(|_| CommandError::bad_value("delete needs deletes"))?;
+    let ordered = cmd.get_bool("ordered").unwrap_or(true);
+    let mut n = 0;
+    let mut errors = Vec::new();
+    for (i, d) in deletes.iter().enumerate() {
+        let res = match d {
+            Bson::Document(d) => delete_matching(storage, coll, d),
+            _ => Err(CommandError::bad_value("deletes must be objects")),
+        };
+        match res {
+            Ok(removed) => n += removed,
+            Err(e) => {
+                errors.push(e.write_error(i));
+                if ordered {
+                    break;
+                }
+            }
+        }
+    }
+    Ok(write_reply(n, errors))
+}
+
+/// Delete the documents `spec.q` matches: all of them with `limit: 0`, the
+/// first with `limit: 1`
+fn delete_matching(storage: &dyn Storage, coll: &str, spec: &Document) -> CommandResult<usize> {
+    let filter = match spec.get("q") {
+        Some(Bson::Document(_)) => filter_of(spec, "q")?,
+        _ => return Err(CommandError::bad_value("a delete needs a q document")),
+    };
+    let paging = Paging { limit: (count_of(spec, "limit")? == Some(1)).then_some(1), ..Default::default() };
+    let mut n = 0;
+    for doc in docs::query_page(storage, coll, &filter, true, &paging)?.docs {
+        if docs::delete(storage, coll, &doc_id(&doc)?)? {
+            n += 1;
+        }
+    }
+    Ok(n)
+}
+
+fn create_indexes(storage: &dyn Storage, cmd: &Document) -> CommandResult {
+    let coll = collection(cmd, "createIndexes")?;
+    let specs = cmd.get_array("indexes").map_err(|_| CommandError::bad_value("createIndexes needs indexes"))?;
+    let before = index::indexed_fields(storage, coll)?.len() + 1;
+    for spec in specs {
+        let key = match spec {
+            Bson::Document(spec) => spec.get_document("key").ok(),
+            _ => None,
+        };
+        let key = key.ok_or_else(|| CommandError::bad_value("each index needs a key document"))?;
+        let mut fields = key.iter();
+        let field = match (fields.next(), fields.next()) {
+            (Some((field, dir)), None) if number(dir).is_some_and(|d| d == 1 || d == -1) => field,
+            _ => return Err(CommandError::new(67, "CannotCreateIndex", "only single-field ascending or descending indexes are supported")),
+        };
+        // Documents are stored by _id already
+        if field == "_id" {
+            continue;
+        }
+        let unique = spec.as_document().is_some_and(|s| s.get_bool("unique").unwrap_or(false));
+        if unique {
+            index::create_unique_index(storage, coll, field)?;
+        } else {
+            index::create_index(storage, coll, field)?;
+        }
+    }
+    let after = index::indexed_fields(storage, coll)?.len() + 1;
+    Ok(doc! { "numIndexesBefore": before as i32, "numIndexesAfter": after as i32, "createdCollectionAutomatically": false, "ok": 1.0 })
+}
+
+fn drop_indexes(storage: &dyn Storage, cmd: &Document) -> CommandResult {
+    let coll = collection(cmd, "dropIndexes")?;
+    let fields = index::indexed_fields(storage, coll)?;
+    let doomed: Vec<&String> = match cmd.get("index") {
+        Some(Bson::String(name)) if name == "*" => fields.iter().collect(),
+        Some(Bson::String(name)) => fields.iter().filter(|f| [format!("{}_1", f), format!("{}_-1", f)].contains(name)).collect(),
+        Some(Bson::Document(key)) => fields.iter().filter(|f| key.len() == 1 && key.contains_key(f.as_str())).collect(),
+        _ => return Err(CommandError::bad_value("dropIndexes needs an index name or key")),
+    };
+    if doomed.is_empty() && !matches!(cmd.get("index"), Some(Bson::String(name)) if name == "*") {
+        return Err(CommandError::new(27, "IndexNotFound", format!("index not found in {}", coll)));
+    }
+    for field in doomed {
+        index::drop_index(storage, coll, field)?;
+    }
+    Ok(doc! { "nIndexesWas": fields.len() as i32 + 1, "ok": 1.0 })
+}
+
+/// Run an aggregation pipeline. Runs of the stages the store knows go through
+/// `aggregate`, the first against the collection itself; the others are applied
+/// here.
+fn run_pipeline(storage: &dyn Storage, coll: &str, stages: &[Bson]) -> CommandResult<Vec<Json>> {
+    let mut results: Option<Vec<Json>> = None;
+    let mut pending = Vec::new();
+    for stage in stages {
+        let stage = match stage {
+            Bson::Document(d) if d.len() == 1 => doc_to_json(d),
+            _ => return Err(CommandError::bad_value("each pipeline stage must be an object with exactly one key")),
+        };
+        let (name, arg) = stage.as_object().and_then(|o| o.iter().next()).expect("one key");
+        if matches!(name.as_str(), "$match" | "$lookup" | "$skip" | "$limit") {
+            pending.push(stage.clone());
+            continue;
+        }
+        let mut current = run_pending(storage, coll, results.take(), &mut pending)?;
+        match name.as_str() {
+            "$sort" => sort_docs(&mut current, &sort_keys(arg)?),
+            "$project" => current = current.iter().map(|d| project(d, arg)).collect::<CommandResult<_>>()?,
+            "$count" => {
+                let field = arg
+                    .as_str()
+                    .filter(|f| !f.is_empty() && !f.starts_with('$') && !f.contains('.'))
+                    .ok_or_else(|| CommandError::bad_value("$count needs a field name"))?;
+                if !current.is_empty() {
+                    current = vec![Json::Object(Map::from_iter([(field.to_string(), current.len().into())]))];
+                }
+            }
+            "$group" => current = count_group(arg, current.len())?,
+            other => return Err(CommandError::new(40324, "Location40324", format!("unrecognized pipeline stage name: {}", other))),
+        }
+        results = Some(current);
+    }
+    run_pending(storage, coll, results, &mut pending)
+}
+
+/// Run the `pending` store stages over `results`, or over the collection when
+/// nothing has run yet
+fn run_pending(storage: &dyn Storage, coll: &str, results: Option<Vec<Json>>, pending: &mut Vec<Json>) -> CommandResult<Vec<Json>> {
+    let pipeline = Pipeline::parse(&Json::Array(std::mem::take(pending)))?;
+    Ok(match results {
+        None => aggregate::aggregate(storage, coll, &pipeline)?,
+        Some(results) => pipeline.run(storage, results)?,
+    })
+}
+
+/// `{$group: {_id: <constant>, <field>: {$sum: 1}}}`, which drivers send for
+/// `countDocuments`: one document counting the input, none for no input
+fn count_group(arg: &Json, n: usize) -> CommandResult<Vec<Json>> {
+    let unsupported = || CommandError::bad_value("only $group {_id: <constant>, <field>: {$sum: 1}} is supported");
+    let spec = arg.as_object().filter(|o| o.len() == 2).ok_or_else(unsupported)?;
+    let id = spec.get("_id").filter(|id| !id.as_str().is_some_and(|s| s.starts_with('$'))).ok_or_else(unsupported)?;
+    let (field, _) = spec
+        .iter()
+        .find(|(k, v)| *k != "_id" && **v == serde_json::json!({ "$sum": 1 }))
+        .ok_or_else(unsupported)?;
+    if n == 0 {
+        return Ok(Vec::new());
+    }
+    Ok(vec![Json::Object(Map::from_iter([("_id".to_string(), id.clone()), (field.clone(), n.into())]))])
+}
+
+/// Fields of a sort specification, each ascending (`true`) or descending
+fn sort_keys(spec: &Json) -> CommandResult<Vec<(String, bool)>> {
+    let spec = spec.as_object().ok_or_else(|| CommandError::bad_value("sort must be a document"))?;
+    spec.iter()
+        .map(|(field, dir)| match dir.as_f64() {
+            Some(1.0) => Ok((field.clone(), true)),
+            Some(-1.0) => Ok((field.clone(), false)),
+            _ => Err(CommandError::bad_value(format!("sort of {} must be 1 or -1", field))),
+        })
+        .collect()
+}
+
+fn sort_docs(docs: &mut [Json], keys: &[(String, bool)]) {
+    docs.sort_by(|a, b| {
+        keys.iter()
+            .map(|(field, asc)| {
+                let o = compare(filter::lookup(a, field), filter::lookup(b, field));
+                if *asc { o } else { o.reverse() }
+            })
+            .find(|o| o.is_ne())
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+}
+
+/// MongoDB's sort order: missing and null, then numbers, strings, objects,
+/// arrays and booleans
+fn compare(a: Option<&Json>, b: Option<&Json>) -> std::cmp::Ordering {
+    fn rank(v: Option<&Json>) -> u8 {
+        match v {
+            None | Some(Json::Null) => 0,
+            Some(Json::Number(_)) => 1,
+            Some(Json::String(_)) => 2,
+            Some(Json::Object(_)) => 3,
+            Some(Json::Array(_)) => 4,
+            Some(Json::Bool(_)) => 5,
+        }
+    }
+    match (a, b) {
+        (Some(Json::Number(x)), Some(Json::Number(y))) => numbers::cmp_numbers(x, y).unwrap_or(std::cmp::Ordering::Equal),
+        (Some(Json::String(x)), Some(Json::String(y))) => x.cmp(y),
+        (Some(Json::Bool(x)), Some(Json::Bool(y))) => x.cmp(y),
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+/// `doc` with the fields a projection includes, or without those it excludes
+fn project(doc: &Json, spec: &Json) -> CommandResult<Json> {
+    let spec = spec.as_object().ok_or_else(|| CommandError::bad_value("projection must be a document"))?;
+    let mut with_id = true;
+    let mut inclusive = None;
+    let mut fields = Vec::new();
+    for (field, v) in spec {
+        let on = match v {
+            Json::Bool(b) => *b,
+            Json::Number(n) => n.as_f64() != Some(0.0),
+            _ => return Err(CommandError::bad_value(format!("projection of {} must be 0 or 1", field))),
+        };
+        if field == "_id" {
+            with_id = on;
+            continue;
+        }
+        if inclusive.is_some_and(|i| i != on) {
+            return Err(CommandError::bad_value("a projection can't mix inclusion and exclusion"));
+        }
+        inclusive = Some(on);
+        fields.push(field.as_str());
+    }
+    let mut out;
+    if inclusive == Some(true) {
+        let mut sets: Map<String, Json> = fields
+            .iter()
+            .filter_map(|f| filter::lookup(doc, f).map(|v| (f.to_string(), v.clone())))
+            .collect();
+        if let Some(id) = doc.get("_id").filter(|_| with_id) {
+            sets.insert("_id".into(), id.clone());
+        }
+        out = Json::Object(Map::new());
+        update::apply(&mut out, &serde_json::json!({ "$set": sets }))?;
+    } else {
+        let mut unsets: Map<String, Json> = fields.iter().map(|f| (f.to_string(), Json::Null)).collect();
+        if !with_id {
+            unsets.insert("_id".into(), Json::Null);
+        }
+        out = doc.clone();
+        update::apply(&mut out, &serde_json::json!({ "$unset": unsets }))?;
+    }
+    Ok(out)
+}
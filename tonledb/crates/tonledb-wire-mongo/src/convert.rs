@@ -0,0 +1,62 @@
+//! Conversion between BSON and the JSON documents the store keeps.
+//!
+//! ObjectIds become their hex string, so the `_id` a driver generates is the
+//! document's id in the store. Integers and doubles become JSON numbers, and JSON
+//! integers go back as Int32 when they fit and Int64 otherwise. Other BSON types
+//! (dates, binary, decimals, ...) are kept in their relaxed Extended JSON form,
+//! e.g. `{"$date": "2024-01-01T00:00:00Z"}`, and turn back into BSON on the way
+//! out.
+
+use bson::{Bson, Document};
+use serde_json::{Number, Value as Json};
+
+pub fn to_json(value: &Bson) -> Json {
+    match value {
+        Bson::Double(f) => Number::from_f64(*f).map(Json::Number).unwrap_or_else(|| value.clone().into_relaxed_extjson()),
+        Bson::String(s) => Json::String(s.clone()),
+        Bson::Boolean(b) => Json::Bool(*b),
+        Bson::Null => Json::Null,
+        Bson::Int32(n) => (*n).into(),
+        Bson::Int64(n) => (*n).into(),
+        Bson::ObjectId(id) => Json::String(id.to_hex()),
+        Bson::Array(items) => Json::Array(items.iter().map(to_json).collect()),
+        Bson::Document(doc) => doc_to_json(doc),
+        other => other.clone().into_relaxed_extjson(),
+    }
+}
+
+pub fn doc_to_json(doc: &Document) -> Json {
+    Json::Object(doc.iter().map(|(k, v)| (k.clone(), to_json(v))).collect())
+}
+
+pub fn to_bson(value: &Json) -> Bson {
+    match value {
+        Json::Null => Bson::Null,
+        Json::Bool(b) => Bson::Boolean(*b),
+        Json::Number(n) => match n.as_i64() {
+            Some(i) => i32::try_from(i).map_or(Bson::Int64(i), Bson::Int32),
+            None => Bson::Double(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        Json::String(s) => Bson::String(s.clone()),
+        Json::Array(items) => Bson::Array(items.iter().map(to_bson).collect()),
+        Json::Object(map) => {
+            // A value `to_json` kept as Extended JSON
+            if map.len() == 1 && map.keys().all(|k| k.starts_with('$')) {
+                if let Ok(v) = Bson::try_from(value.clone()) {
+                    if !matches!(v, Bson::Document(_)) {
+                        return v;
+                    }
+                }
+            }
+            Bson::Document(map.iter().map(|(k, v)| (k.clone(), to_bson(v))).collect())
+        }
+    }
+}
+
+/// A stored document as BSON
+pub fn to_document(value: &Json) -> Document {
+    match to_bson(value) {
+        Bson::Document(doc) => doc,
+        other => bson::doc! { "value": other },
+    }
+}
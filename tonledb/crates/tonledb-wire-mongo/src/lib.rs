@@ -0,0 +1,107 @@
+//! MongoDB wire protocol compatibility for TonleDB
+//!
+//! Speaks OP_MSG, so MongoDB drivers, `mongosh` and Compass can work with the
+//! document collections of a `Db`: every command (`find`, `insert`, `update`,
+//! `delete`, `aggregate`, ...) is run against `tonledb_nosql_doc` and answered
+//! with one reply (see `commands`), documents converted between BSON and JSON on
+//! the way (see `convert`). OP_QUERY is answered for commands only, which is how
+//! older drivers send their first `isMaster`.
+//!
+//! Every database name reaches the same collections. With a `PasswordStore` in
+//! the `MongoOptions`, clients log in with SASL PLAIN and each command is checked
+//! against what they may do (see `auth`); until then a message can be at most
+//! `MAX_LOGIN_MESSAGE_BYTES` long. With a TLS config every connection is TLS from
+//! its first byte, as drivers expect with `tls=true`; PLAIN logins are only taken
+//! over TLS.
+
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio_rustls::{rustls::ServerConfig, TlsAcceptor};
+use tonledb_core::Db;
+use crate::auth::PasswordStore;
+use crate::commands::{CommandError, Session};
+use crate::protocol::{Request, MAX_LOGIN_MESSAGE_BYTES, MAX_MESSAGE_BYTES};
+
+pub mod auth;
+pub mod commands;
+pub mod convert;
+pub mod protocol;
+
+/// Reported by `buildInfo`; drivers check it for features
+pub const SERVER_VERSION: &str = "6.0.0";
+
+/// How the server lets clients in
+#[derive(Clone, Default)]
+pub struct MongoOptions {
+    /// Checks passwords; `None` lets every client in without one
+    pub passwords: Option<Arc<dyn PasswordStore>>,
+    /// Serves every connection over TLS with this config; `None` serves plain TCP,
+    /// where logging in is refused
+    pub tls: Option<Arc<ServerConfig>>,
+}
+
+/// Handle a MongoDB client connection
+pub async fn handle_mongo_connection<S>(stream: S, db: Arc<Db>, options: MongoOptions) -> Result<(), anyhow::Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    match options.tls.clone() {
+        Some(tls) => serve(TlsAcceptor::from(tls).accept(stream).await?, db, options, true).await,
+        None => serve(stream, db, options, false).await,
+    }
+}
+
+/// Run the commands of a client; `secure` is whether it is inside TLS
+async fn serve<S>(mut stream: S, db: Arc<Db>, options: MongoOptions, secure: bool) -> Result<(), anyhow::Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut session = Session::new(options.passwords, secure);
+    let mut buf = Vec::new();
+    loop {
+        let max_len = if session.logged_in() { MAX_MESSAGE_BYTES } else { MAX_LOGIN_MESSAGE_BYTES };
+        let Some(request) = protocol::read_request(&mut stream, max_len).await? else { break };
+        buf.clear();
+        match request {
+            Request::Msg { request_id, more_to_come, command } => {
+                let reply = session.run(&db, &command);
+                if more_to_come {
+                    continue;
+                }
+                protocol::op_msg(&mut buf, request_id, &reply)?;
+            }
+            Request::Query { request_id, collection, query } => {
+                let reply = if collection.ends_with(".$cmd") {
+                    // Older drivers wrap the command when they add read preferences
+                    let command = query.get_document("$query").unwrap_or(&query);
+                    session.run(&db, command)
+                } else {
+                    CommandError::new(352, "UnsupportedOpQueryCommand", "OP_QUERY is only supported for commands").to_document()
+                };
+                protocol::op_reply(&mut buf, request_id, &reply)?;
+            }
+        }
+        stream.write_all(&buf).await?;
+        stream.flush().await?;
+    }
+    Ok(())
+}
+
+/// Start a MongoDB wire protocol server
+pub async fn start_mongo_server(db: Arc<Db>, bind_addr: &str, options: MongoOptions) -> Result<(), anyhow::Error> {
+    let listener = TcpListener::bind(bind_addr).await?;
+    println!("MongoDB wire protocol server listening on {}", bind_addr);
+
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        println!("New MongoDB client connected from {}", addr);
+
+        let (db_clone, options) = (db.clone(), options.clone());
+        tokio::spawn(async move {
+            if let Err(e) = handle_mongo_connection(stream, db_clone, options).await {
+                eprintln!("Error handling MongoDB connection: {}", e);
+            }
+        });
+    }
+}
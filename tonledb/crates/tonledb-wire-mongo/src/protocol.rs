@@ -0,0 +1,154 @@
+//! MongoDB wire protocol framing: reading requests and encoding replies.
+//!
+//! Every message starts with a 16-byte little-endian header: its length, its
+//! request id, the id of the request it answers and its opcode. Drivers send
+//! commands as OP_MSG; OP_QUERY only still carries the legacy `isMaster`
+//! handshake, which is answered with OP_REPLY.
+
+use std::sync::atomic::{AtomicI32, Ordering};
+use bson::{Bson, Document};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+pub const OP_REPLY: i32 = 1;
+pub const OP_QUERY: i32 = 2004;
+pub const OP_MSG: i32 = 2013;
+/// Longest message accepted, as `hello` announces it
+pub const MAX_MESSAGE_BYTES: usize = 48_000_000;
+/// Longest message accepted before the client has logged in
+pub const MAX_LOGIN_MESSAGE_BYTES: usize = 64 << 10;
+/// OP_MSG flag: a CRC-32C checksum follows the sections
+const CHECKSUM_PRESENT: u32 = 1;
+/// OP_MSG flag: the sender expects no reply
+const MORE_TO_COME: u32 = 1 << 1;
+
+/// Request ids of the messages the server sends
+static NEXT_REQUEST_ID: AtomicI32 = AtomicI32::new(1);
+
+/// A message from the client
+#[derive(Debug, Clone, PartialEq)]
+pub enum Request {
+    /// OP_MSG: the command document, with each document sequence section added
+    /// to it as an array under its identifier
+    Msg { request_id: i32, more_to_come: bool, command: Document },
+    /// OP_QUERY on `collection` (`<db>.$cmd` for a command)
+    Query { request_id: i32, collection: String, query: Document },
+}
+
+/// Read the next request, of at most `max_len` bytes; `None` when the client has
+/// closed the connection
+pub async fn read_request<R: AsyncRead + Unpin>(r: &mut R, max_len: usize) -> anyhow::Result<Option<Request>> {
+    let len = match r.read_i32_le().await {
+        Ok(len) => len,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if len < 16 || len as usize > max_len {
+        anyhow::bail!("bad message length {}", len);
+    }
+    let request_id = r.read_i32_le().await?;
+    let _response_to = r.read_i32_le().await?;
+    let op_code = r.read_i32_le().await?;
+    let mut body = vec![0; len as usize - 16];
+    r.read_exact(&mut body).await?;
+    let mut body = body.as_slice();
+    match op_code {
+        OP_MSG => {
+            let flags = u32::from_le_bytes(take(&mut body, 4)?.try_into()?);
+            if flags & CHECKSUM_PRESENT != 0 {
+                body = &body[..body.len().saturating_sub(4)];
+            }
+            let mut command = None;
+            let mut sequences = Vec::new();
+            while !body.is_empty() {
+                match take(&mut body, 1)?[0] {
+                    0 => command = Some(document(&mut body)?),
+                    1 => {
+                        let size = i32::from_le_bytes(take(&mut body, 4)?.try_into()?);
+                        let mut section = take(&mut body, usize::try_from(size)?.saturating_sub(4))?;
+                        let name = cstring(&mut section)?;
+                        let mut docs = Vec::new();
+                        while !section.is_empty() {
+                            docs.push(Bson::Document(document(&mut section)?));
+                        }
+                        sequences.push((name, docs));
+                    }
+                    kind => anyhow::bail!("unknown OP_MSG section kind {}", kind),
+                }
+            }
+            let mut command = command.ok_or_else(|| anyhow::anyhow!("OP_MSG without a body section"))?;
+            for (name, docs) in sequences {
+                command.insert(name, docs);
+            }
+            Ok(Some(Request::Msg { request_id, more_to_come: flags & MORE_TO_COME != 0, command }))
+        }
+        OP_QUERY => {
+            let _flags = take(&mut body, 4)?;
+            let collection = cstring(&mut body)?;
+            let _skip_and_return = take(&mut body, 8)?;
+            let query = document(&mut body)?;
+            Ok(Some(Request::Query { request_id, collection, query }))
+        }
+        op_code => anyhow::bail!("unsupported opcode {}", op_code),
+    }
+}
+
+/// Split the first `n` bytes off `buf`
+fn take<'a>(buf: &mut &'a [u8], n: usize) -> anyhow::Result<&'a [u8]> {
+    if buf.len() < n {
+        anyhow::bail!("truncated message");
+    }
+    let (head, rest) = buf.split_at(n);
+    *buf = rest;
+    Ok(head)
+}
+
+fn cstring(buf: &mut &[u8]) -> anyhow::Result<String> {
+    let end = buf.iter().position(|&b| b == 0).ok_or_else(|| anyhow::anyhow!("unterminated string"))?;
+    let s = String::from_utf8(buf[..end].to_vec())?;
+    *buf = &buf[end + 1..];
+    Ok(s)
+}
+
+fn document(buf: &mut &[u8]) -> anyhow::Result<Document> {
+    let len = buf.get(..4).ok_or_else(|| anyhow::anyhow!("truncated message"))?;
+    let len = i32::from_le_bytes(len.try_into()?);
+    let mut bytes = take(buf, usize::try_from(len)?)?;
+    Ok(Document::from_reader(&mut bytes)?)
+}
+
+/// Append an OP_MSG answering request `response_to` with `body`
+pub fn op_msg(buf: &mut Vec<u8>, response_to: i32, body: &Document) -> anyhow::Result<()> {
+    let start = header(buf, response_to, OP_MSG);
+    buf.extend_from_slice(&0u32.to_le_bytes());
+    buf.push(0);
+    body.to_writer(&mut *buf)?;
+    finish(buf, start);
+    Ok(())
+}
+
+/// Append an OP_REPLY answering OP_QUERY `response_to` with the one document `doc`
+pub fn op_reply(buf: &mut Vec<u8>, response_to: i32, doc: &Document) -> anyhow::Result<()> {
+    let start = header(buf, response_to, OP_REPLY);
+    buf.extend_from_slice(&0i32.to_le_bytes()); // responseFlags
+    buf.extend_from_slice(&0i64.to_le_bytes()); // cursorID
+    buf.extend_from_slice(&0i32.to_le_bytes()); // startingFrom
+    buf.extend_from_slice(&1i32.to_le_bytes()); // numberReturned
+    doc.to_writer(&mut *buf)?;
+    finish(buf, start);
+    Ok(())
+}
+
+/// Start a message, its length left for `finish`; returns where it starts
+fn header(buf: &mut Vec<u8>, response_to: i32, op_code: i32) -> usize {
+    let start = buf.len();
+    buf.extend_from_slice(&0i32.to_le_bytes());
+    buf.extend_from_slice(&NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed).to_le_bytes());
+    buf.extend_from_slice(&response_to.to_le_bytes());
+    buf.extend_from_slice(&op_code.to_le_bytes());
+    start
+}
+
+fn finish(buf: &mut [u8], start: usize) {
+    let len = (buf.len() - start) as i32;
+    buf[start..start + 4].copy_from_slice(&len.to_le_bytes());
+}
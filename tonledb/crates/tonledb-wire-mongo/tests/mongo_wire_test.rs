@@ -0,0 +1,316 @@
+//! Tests for the MongoDB wire protocol: framing, the handshake, logging in, CRUD
+//! commands, cursors and aggregation
+
+use std::sync::Arc;
+use bson::spec::BinarySubtype;
+use bson::{doc, oid::ObjectId, Binary, Bson, Document};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream};
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::{self, Certificate, PrivateKey, RootCertStore, ServerName};
+use tokio_rustls::TlsConnector;
+use tonledb_core::Db;
+use tonledb_storage::InMemoryStore;
+use tonledb_wire_mongo::auth::{Access, PasswordStore};
+use tonledb_wire_mongo::commands::{Session, MAX_CURSORS};
+use tonledb_wire_mongo::{handle_mongo_connection, MongoOptions};
+
+fn db() -> Db {
+    Db::new(Arc::new(InMemoryStore::new(1000)))
+}
+
+fn connect() -> DuplexStream {
+    connect_with(MongoOptions::default())
+}
+
+fn connect_with(options: MongoOptions) -> DuplexStream {
+    let (client, server) = tokio::io::duplex(1 << 16);
+    tokio::spawn(handle_mongo_connection(server, Arc::new(db()), options));
+    client
+}
+
+/// A client over TLS of a server that checks passwords, with a fresh certificate
+/// for `localhost`
+async fn connect_tls() -> TlsStream<DuplexStream> {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+    let der = cert.serialize_der().unwrap();
+    let server = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(vec![Certificate(der.clone())], PrivateKey(cert.serialize_private_key_der()))
+        .unwrap();
+    let mut roots = RootCertStore::empty();
+    roots.add(&Certificate(der)).unwrap();
+    let client = rustls::ClientConfig::builder().with_safe_defaults().with_root_certificates(roots).with_no_client_auth();
+    let stream = connect_with(MongoOptions { passwords: Some(Arc::new(Users)), tls: Some(Arc::new(server)) });
+    TlsConnector::from(Arc::new(client)).connect(ServerName::try_from("localhost").unwrap(), stream).await.unwrap()
+}
+
+/// "reader" and "writer", each with the password "pw"
+struct Users;
+
+impl PasswordStore for Users {
+    fn verify(&self, user: &str, password: &str) -> Option<Access> {
+        match (user, password) {
+            ("reader", "pw") => Some(Access::Read),
+            ("writer", "pw") => Some(Access::Write),
+            _ => None,
+        }
+    }
+}
+
+fn login(user: &str, password: &str) -> Document {
+    let payload = Binary { subtype: BinarySubtype::Generic, bytes: format!("\0{}\0{}", user, password).into_bytes() };
+    doc! { "saslStart": 1, "mechanism": "PLAIN", "payload": payload, "$db": "$external" }
+}
+
+async fn send<S: AsyncWrite + Unpin>(client: &mut S, op_code: i32, body: &[u8]) {
+    let mut msg = ((body.len() + 16) as i32).to_le_bytes().to_vec();
+    msg.extend_from_slice(&7i32.to_le_bytes());
+    msg.extend_from_slice(&0i32.to_le_bytes());
+    msg.extend_from_slice(&op_code.to_le_bytes());
+    msg.extend_from_slice(body);
+    client.write_all(&msg).await.unwrap();
+}
+
+/// Opcode and body of the next reply, checking it answers request 7
+async fn receive<S: AsyncRead + Unpin>(client: &mut S) -> (i32, Vec<u8>) {
+    let len = client.read_i32_le().await.unwrap() as usize;
+    let _request_id = client.read_i32_le().await.unwrap();
+    assert_eq!(client.read_i32_le().await.unwrap(), 7);
+    let op_code = client.read_i32_le().await.unwrap();
+    let mut body = vec![0; len - 16];
+    client.read_exact(&mut body).await.unwrap();
+    (op_code, body)
+}
+
+fn encode(doc: &Document) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    doc.to_writer(&mut bytes).unwrap();
+    bytes
+}
+
+/// Send `command` as an OP_MSG, with `sequence` as a document sequence section
+async fn command<S: AsyncRead + AsyncWrite + Unpin>(client: &mut S, command: Document, sequence: Option<(&str, Vec<Document>)>) -> Document {
+    let mut body = 0u32.to_le_bytes().to_vec();
+    body.push(0);
+    body.extend(encode(&command));
+    if let Some((name, docs)) = sequence {
+        let mut section = format!("{}\0", name).into_bytes();
+        for doc in &docs {
+            section.extend(encode(doc));
+        }
+        body.push(1);
+        body.extend(((section.len() + 4) as i32).to_le_bytes());
+        body.extend(section);
+    }
+    send(client, 2013, &body).await;
+    let (op_code, body) = receive(client).await;
+    assert_eq!(op_code, 2013);
+    assert_eq!(&body[..5], &[0, 0, 0, 0, 0]);
+    Document::from_reader(&mut &body[5..]).unwrap()
+}
+
+fn ok(reply: &Document) -> bool {
+    matches!(reply.get("ok"), Some(Bson::Double(ok)) if *ok == 1.0)
+}
+
+fn batch<'a>(reply: &'a Document, name: &str) -> Vec<&'a Document> {
+    reply.get_document("cursor").unwrap().get_array(name).unwrap().iter().map(|d| d.as_document().unwrap()).collect()
+}
+
+#[tokio::test]
+async fn test_handshake() {
+    let mut client = connect();
+    // Older drivers start with isMaster over OP_QUERY
+    let mut body = 0i32.to_le_bytes().to_vec();
+    body.extend_from_slice(b"admin.$cmd\0");
+    body.extend_from_slice(&0i32.to_le_bytes());
+    body.extend_from_slice(&(-1i32).to_le_bytes());
+    body.extend(encode(&doc! { "isMaster": 1, "client": { "driver": { "name": "test" } } }));
+    send(&mut client, 2004, &body).await;
+    let (op_code, body) = receive(&mut client).await;
+    assert_eq!(op_code, 1);
+    assert_eq!(i32::from_le_bytes(body[16..20].try_into().unwrap()), 1);
+    let reply = Document::from_reader(&mut &body[20..]).unwrap();
+    assert_eq!(reply.get_bool("ismaster"), Ok(true));
+    assert!(reply.get_i32("maxWireVersion").unwrap() >= 6);
+
+    let reply = command(&mut client, doc! { "hello": 1, "$db": "admin" }, None).await;
+    assert_eq!(reply.get_bool("isWritablePrimary"), Ok(true));
+    assert!(ok(&command(&mut client, doc! { "ping": 1, "$db": "admin" }, None).await));
+    let reply = command(&mut client, doc! { "noSuchCommand": 1, "$db": "admin" }, None).await;
+    assert_eq!((reply.get_i32("code"), reply.get_str("codeName")), (Ok(59), Ok("CommandNotFound")));
+}
+
+#[tokio::test]
+async fn test_insert_document_sequence_and_find() {
+    let mut client = connect();
+    let oid = ObjectId::new();
+    let docs = vec![doc! { "_id": oid, "name": "ann", "age": 31 }, doc! { "name": "bob", "age": 25 }];
+    let reply = command(&mut client, doc! { "insert": "users", "ordered": true, "$db": "test" }, Some(("documents", docs))).await;
+    assert!(ok(&reply));
+    assert_eq!(reply.get_i32("n"), Ok(2));
+
+    // The ObjectId is the document's id, read back as its hex string
+    let reply = command(&mut client, doc! { "find": "users", "filter": { "_id": oid }, "$db": "test" }, None).await;
+    let found = batch(&reply, "firstBatch");
+    assert_eq!(found.len(), 1);
+    assert_eq!((found[0].get_str("_id"), found[0].get_str("name")), (Ok(oid.to_hex().as_str()), Ok("ann")));
+
+    let reply = command(&mut client, doc! { "insert": "users", "documents": [{ "_id": oid }], "$db": "test" }, None).await;
+    assert_eq!(reply.get_i32("n"), Ok(0));
+    let errors = reply.get_array("writeErrors").unwrap();
+    assert_eq!(errors[0].as_document().unwrap().get_i32("code"), Ok(11000));
+}
+
+#[test]
+fn test_find_update_delete() {
+    let db = db();
+    let mut session = Session::default();
+    let docs: Vec<Bson> = (1..=5).map(|n| Bson::Document(doc! { "_id": format!("u{}", n), "n": n, "even": n % 2 == 0 })).collect();
+    assert!(ok(&session.run(&db, &doc! { "insert": "nums", "documents": docs })));
+
+    let reply = session.run(&db, &doc! { "find": "nums", "filter": { "n": { "$gte": 2 } }, "sort": { "n": -1 }, "skip": 1, "limit": 2, "projection": { "n": 1 } });
+    assert_eq!(batch(&reply, "firstBatch"), [&doc! { "_id": "u4", "n": 4 }, &doc! { "_id": "u3", "n": 3 }]);
+
+    let reply = session.run(&db, &doc! { "update": "nums", "updates": [{ "q": { "even": true }, "u": { "$inc": { "n": 10 } }, "multi": true }] });
+    assert_eq!((reply.get_i32("n"), reply.get_i32("nModified")), (Ok(2), Ok(2)));
+    let reply = session.run(&db, &doc! { "update": "nums", "updates": [{ "q": { "_id": "u9" }, "u": { "$set": { "n": 9 } }, "upsert": true }] });
+    assert_eq!(reply.get_array("upserted").unwrap()[0].as_document().unwrap().get_str("_id"), Ok("u9"));
+    let reply = session.run(&db, &doc! { "update": "nums", "updates": [{ "q": { "_id": "u1" }, "u": { "_id": "other" } }] });
+    assert_eq!(reply.get_array("writeErrors").unwrap()[0].as_document().unwrap().get_i32("code"), Ok(66));
+
+    let reply = session.run(&db, &doc! { "delete": "nums", "deletes": [{ "q": { "n": { "$gt": 10 } }, "limit": 0 }] });
+    assert_eq!(reply.get_i32("n"), Ok(2));
+    let reply = session.run(&db, &doc! { "count": "nums", "query": {} });
+    assert_eq!(reply.get_i64("n"), Ok(4));
+    let reply = session.run(&db, &doc! { "distinct": "nums", "key": "even", "query": {} });
+    assert_eq!(reply.get_array("values").unwrap(), &vec![Bson::Boolean(false)]);
+
+    let reply = session.run(&db, &doc! { "find": "nums", "filter": { "n": { "$where": "x" } } });
+    assert_eq!(reply.get_i32("code"), Ok(2));
+}
+
+#[test]
+fn test_cursors_and_aggregate() {
+    let db = db();
+    let mut session = Session::default();
+    let docs: Vec<Bson> = (0..5).map(|n| Bson::Document(doc! { "n": n, "group": if n < 3 { "a" } else { "b" } })).collect();
+    session.run(&db, &doc! { "insert": "items", "documents": docs });
+
+    let reply = session.run(&db, &doc! { "find": "items", "sort": { "n": 1 }, "batchSize": 2 });
+    assert_eq!(batch(&reply, "firstBatch").len(), 2);
+    let id = reply.get_document("cursor").unwrap().get_i64("id").unwrap();
+    assert_ne!(id, 0);
+    let reply = session.run(&db, &doc! { "getMore": id, "collection": "items", "batchSize": 2 });
+    assert_eq!(batch(&reply, "nextBatch").iter().map(|d| d.get_i32("n").unwrap()).collect::<Vec<_>>(), [2, 3]);
+    let reply = session.run(&db, &doc! { "getMore": id, "collection": "items" });
+    assert_eq!(batch(&reply, "nextBatch").len(), 1);
+    assert_eq!(reply.get_document("cursor").unwrap().get_i64("id"), Ok(0));
+    let reply = session.run(&db, &doc! { "getMore": id, "collection": "items" });
+    assert_eq!(reply.get_i32("code"), Ok(43));
+
+    let pipeline = doc! { "aggregate": "items", "pipeline": [{ "$match": { "group": "a" } }, { "$sort": { "n": -1 } }, { "$limit": 2 }, { "$project": { "_id": 0, "n": 1 } }], "cursor": {} };
+    let reply = session.run(&db, &pipeline);
+    assert_eq!(batch(&reply, "firstBatch"), [&doc! { "n": 2 }, &doc! { "n": 1 }]);
+    // What drivers send for countDocuments
+    let pipeline = doc! { "aggregate": "items", "pipeline": [{ "$match": { "group": "b" } }, { "$group": { "_id": 1, "n": { "$sum": 1 } } }], "cursor": {} };
+    assert_eq!(batch(&session.run(&db, &pipeline), "firstBatch"), [&doc! { "_id": 1, "n": 2 }]);
+    let pipeline = doc! { "aggregate": "items", "pipeline": [{ "$count": "total" }], "cursor": {} };
+    assert_eq!(batch(&session.run(&db, &pipeline), "firstBatch"), [&doc! { "total": 5 }]);
+
+    let reply = session.run(&db, &doc! { "listCollections": 1, "filter": { "name": "items" }, "nameOnly": true });
+    assert_eq!(batch(&reply, "firstBatch"), [&doc! { "name": "items", "type": "collection" }]);
+    assert!(ok(&session.run(&db, &doc! { "createIndexes": "items", "indexes": [{ "key": { "group": 1 }, "name": "group_1" }] })));
+    let names: Vec<String> = batch(&session.run(&db, &doc! { "listIndexes": "items" }), "firstBatch").iter().map(|d| d.get_str("name").unwrap().to_string()).collect();
+    assert_eq!(names, ["_id_", "group_1"]);
+}
+
+#[tokio::test]
+async fn test_login_and_access() {
+    let mut client = connect_tls().await;
+    // The handshake runs before logging in; nothing else does
+    assert!(ok(&command(&mut client, doc! { "hello": 1, "$db": "admin" }, None).await));
+    let reply = command(&mut client, doc! { "find": "users", "$db": "test" }, None).await;
+    assert_eq!((reply.get_i32("code"), reply.get_str("codeName")), (Ok(13), Ok("Unauthorized")));
+    let reply = command(&mut client, login("reader", "wrong"), None).await;
+    assert_eq!(reply.get_i32("code"), Ok(18));
+
+    let reply = command(&mut client, login("reader", "pw"), None).await;
+    assert_eq!(reply.get_bool("done"), Ok(true));
+    assert!(ok(&command(&mut client, doc! { "find": "users", "$db": "test" }, None).await));
+    for write in [doc! { "insert": "users", "documents": [{ "n": 1 }], "$db": "test" }, doc! { "drop": "users", "$db": "test" }] {
+        assert_eq!(command(&mut client, write, None).await.get_i32("code"), Ok(13));
+    }
+    let reply = command(&mut client, login("writer", "pw"), None).await;
+    assert!(ok(&reply));
+    let reply = command(&mut client, doc! { "insert": "users", "documents": [{ "n": 1 }], "$db": "test" }, None).await;
+    assert_eq!(reply.get_i32("n"), Ok(1));
+
+    // Without TLS, PLAIN is refused even with the right password
+    let mut client = connect_with(MongoOptions { passwords: Some(Arc::new(Users)), ..Default::default() });
+    let reply = command(&mut client, login("writer", "pw"), None).await;
+    assert_eq!((reply.get_i32("code"), reply.get_str("codeName")), (Ok(334), Ok("MechanismUnavailable")));
+    let reply = command(&mut client, doc! { "find": "users", "$db": "test" }, None).await;
+    assert_eq!(reply.get_i32("code"), Ok(13));
+
+    // Before logging in, the header of a long message ends the connection
+    let mut client = connect_with(MongoOptions { passwords: Some(Arc::new(Users)), ..Default::default() });
+    let mut header = (100i32 << 10).to_le_bytes().to_vec();
+    header.extend([7, 0, 0, 0, 0, 0, 0, 0, 0xdd, 0x07, 0, 0]);
+    client.write_all(&header).await.unwrap();
+    assert_eq!(client.read_u8().await.map_err(|e| e.kind()), Err(std::io::ErrorKind::UnexpectedEof));
+}
+
+#[test]
+fn test_concurrent_updates_all_count() {
+    let db = Arc::new(db());
+    Session::default().run(&db, &doc! { "insert": "counters", "documents": [{ "_id": "c", "n": 0 }] });
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let db = db.clone();
+            std::thread::spawn(move || {
+                let mut session = Session::default();
+                for _ in 0..50 {
+                    let reply = session.run(&db, &doc! { "update": "counters", "updates": [{ "q": { "_id": "c" }, "u": { "$inc": { "n": 1 } } }] });
+                    assert_eq!(reply.get_i32("nModified"), Ok(1));
+                }
+            })
+        })
+        .collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+    let reply = Session::default().run(&db, &doc! { "find": "counters" });
+    assert_eq!(batch(&reply, "firstBatch")[0].get_i32("n"), Ok(200));
+}
+
+#[test]
+fn test_find_reads_on_demand_and_cursors_are_capped() {
+    let db = db();
+    let mut session = Session::default();
+    let docs: Vec<Bson> = (0..2500).map(|n| Bson::Document(doc! { "_id": format!("d{:04}", n), "n": n })).collect();
+    assert!(ok(&session.run(&db, &doc! { "insert": "items", "documents": docs })));
+
+    // A sorted find with a limit keeps only what it returns, across pages of the collection
+    let reply = session.run(&db, &doc! { "find": "items", "sort": { "n": -1 }, "skip": 1, "limit": 3 });
+    let found: Vec<i32> = batch(&reply, "firstBatch").iter().map(|d| d.get_i32("n").unwrap()).collect();
+    assert_eq!(found, [2498, 2497, 2496]);
+
+    // An unsorted find reads later batches when they are asked for
+    let reply = session.run(&db, &doc! { "find": "items", "filter": { "n": { "$gte": 2490 } }, "batchSize": 2 });
+    let id = reply.get_document("cursor").unwrap().get_i64("id").unwrap();
+    session.run(&db, &doc! { "insert": "items", "documents": [{ "_id": "d9999", "n": 9999 }] });
+    let reply = session.run(&db, &doc! { "getMore": id, "collection": "items" });
+    assert_eq!(batch(&reply, "nextBatch").len(), 9);
+    assert_eq!(reply.get_document("cursor").unwrap().get_i64("id"), Ok(0));
+
+    for _ in 0..MAX_CURSORS {
+        assert!(ok(&session.run(&db, &doc! { "find": "items", "batchSize": 1 })));
+    }
+    let reply = session.run(&db, &doc! { "find": "items", "batchSize": 1 });
+    assert_eq!(reply.get_i32("code"), Ok(96));
+    // Closing one makes room
+    assert!(ok(&session.run(&db, &doc! { "killCursors": "items", "cursors": [2i64] })));
+    assert!(ok(&session.run(&db, &doc! { "find": "items", "batchSize": 1 })));
+}
@@ -0,0 +1,158 @@
+//! Password authentication at connection time.
+//!
+//! The plugin is chosen per user from what the `PasswordStore` keeps for them:
+//! `mysql_native_password` for a stored native hash (`*` and 40 hex digits, as
+//! `mysql.user.authentication_string` holds it), and otherwise
+//! `mysql_clear_password`, the password checked with `PasswordStore::verify`. A
+//! client that answered the handshake with another plugin is asked to switch.
+//! Cleartext is only taken over TLS: on a plain connection, a user without a
+//! native hash is refused before the password is asked for. Most connectors also
+//! only send it once allowed to (e.g. `enable_cleartext_plugin`).
+//!
+//! Once in, what a user may do is `PasswordStore::access`: statements run by
+//! `tonledb_sql` need `Write`, as SQL over HTTP does.
+
+use rand::Rng;
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use crate::protocol::{self, HandshakeResponse};
+
+pub const NATIVE_PASSWORD: &str = "mysql_native_password";
+pub const CLEAR_PASSWORD: &str = "mysql_clear_password";
+
+/// Where the server checks passwords
+pub trait PasswordStore: Send + Sync {
+    /// Whether `password` is the password of `user`
+    fn verify(&self, user: &str, password: &str) -> bool;
+
+    /// What `user` may do once logged in
+    fn access(&self, user: &str) -> Access;
+
+    /// The `mysql_native_password` hash stored for `user`, if any
+    fn native_password(&self, _user: &str) -> Option<String> {
+        None
+    }
+}
+
+/// What a logged-in user may do, least first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Access {
+    Read,
+    Write,
+    Admin,
+}
+
+/// The `mysql_native_password` hash of `password`, in the form `PasswordStore`
+/// gives it: `*` and the upper-case hex of SHA1(SHA1(password))
+pub fn native_password_hash(password: &str) -> String {
+    let stage2 = Sha1::digest(Sha1::digest(password.as_bytes()));
+    format!("*{}", stage2.iter().map(|b| format!("{:02X}", b)).collect::<String>())
+}
+
+/// A fresh 20-byte challenge for the handshake. The bytes are printable, as
+/// MySQL's are, since some clients treat the scramble as a C string.
+pub fn scramble() -> [u8; 20] {
+    let mut rng = rand::thread_rng();
+    std::array::from_fn(|_| rng.gen_range(0x21..0x7f))
+}
+
+/// Check the password of the client that sent `response` to the handshake carrying
+/// `scramble`, sending OK or an error; `false` if it failed or went away. `seq`
+/// is the sequence id of the server's next packet; `secure` is whether the
+/// connection is inside TLS.
+pub(crate) async fn authenticate<S>(
+    stream: &mut S,
+    seq: &mut u8,
+    response: &HandshakeResponse,
+    scramble: &[u8; 20],
+    store: &dyn PasswordStore,
+    secure: bool,
+) -> anyhow::Result<bool>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // Clients without plugin auth scramble with the native method
+    let plugin = response.auth_plugin.as_deref().unwrap_or(NATIVE_PASSWORD);
+    let user = response.user.as_str();
+    let accepted = match store.native_password(user) {
+        Some(hash) => {
+            let Some(stage2) = parse_hash(&hash) else { anyhow::bail!("malformed native password hash stored for {}", user) };
+            let reply = match plugin {
+                NATIVE_PASSWORD => Some(response.auth_response.clone()),
+                _ => switch(stream, seq, NATIVE_PASSWORD, &[&scramble[..], &[0u8][..]].concat()).await?,
+            };
+            match (reply, stage2) {
+                (Some(reply), None) => reply.is_empty(),
+                (Some(reply), Some(stage2)) => check_scramble(&reply, scramble, &stage2),
+                (None, _) => return Ok(false),
+            }
+        }
+        None if !secure => {
+            let mut buf = Vec::new();
+            let message = format!("Access denied for user '{}' (its password can only be sent over TLS)", user);
+            protocol::err(&mut buf, seq, 1045, "28000", &message);
+            stream.write_all(&buf).await?;
+            stream.flush().await?;
+            return Ok(false);
+        }
+        None => {
+            let reply = match plugin {
+                CLEAR_PASSWORD => Some(response.auth_response.clone()),
+                _ => switch(stream, seq, CLEAR_PASSWORD, &[]).await?,
+            };
+            match reply {
+                Some(p) => store.verify(user, &String::from_utf8_lossy(p.strip_suffix(&[0]).unwrap_or(&p))),
+                None => return Ok(false),
+            }
+        }
+    };
+    let mut buf = Vec::new();
+    if accepted {
+        protocol::ok(&mut buf, seq, 0, protocol::SERVER_STATUS_AUTOCOMMIT);
+    } else {
+        protocol::err(&mut buf, seq, 1045, "28000", &format!("Access denied for user '{}' (using password: YES)", user));
+    }
+    stream.write_all(&buf).await?;
+    stream.flush().await?;
+    Ok(accepted)
+}
+
+/// Ask the client to switch to `plugin`; its answer, `None` if it went away
+async fn switch<S>(stream: &mut S, seq: &mut u8, plugin: &str, data: &[u8]) -> anyhow::Result<Option<Vec<u8>>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut buf = Vec::new();
+    protocol::auth_switch_request(&mut buf, seq, plugin, data);
+    stream.write_all(&buf).await?;
+    stream.flush().await?;
+    Ok(protocol::read_packet(stream, protocol::MAX_LOGIN_PACKET_BYTES).await?.map(|(client_seq, payload)| {
+        *seq = client_seq.wrapping_add(1);
+        payload
+    }))
+}
+
+/// SHA1(SHA1(password)) from a stored hash; `Some(None)` for the empty hash of
+/// an empty password, `None` if the hash is malformed
+fn parse_hash(hash: &str) -> Option<Option<[u8; 20]>> {
+    if hash.is_empty() {
+        return Some(None);
+    }
+    let hex = hash.strip_prefix('*').filter(|h| h.len() == 40)?;
+    let mut stage2 = [0u8; 20];
+    for (i, b) in stage2.iter_mut().enumerate() {
+        *b = u8::from_str_radix(hex.get(2 * i..2 * i + 2)?, 16).ok()?;
+    }
+    Some(Some(stage2))
+}
+
+/// Whether `reply` is SHA1(password) XOR SHA1(scramble + SHA1(SHA1(password))),
+/// which is checked by undoing the XOR and hashing the result again
+fn check_scramble(reply: &[u8], scramble: &[u8; 20], stage2: &[u8; 20]) -> bool {
+    if reply.len() != 20 {
+        return false;
+    }
+    let mask = Sha1::new().chain_update(scramble).chain_update(stage2).finalize();
+    let stage1: Vec<u8> = reply.iter().zip(mask.iter()).map(|(r, m)| r ^ m).collect();
+    Sha1::digest(stage1).as_slice() == stage2
+}
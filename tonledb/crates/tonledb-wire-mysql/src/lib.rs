@@ -0,0 +1,268 @@
+//! MySQL wire protocol compatibility for TonleDB
+//!
+//! Speaks the MySQL client/server protocol (4.1 and later), so applications on
+//! MySQL connectors and the `mysql` client can run SQL against a `Db`: the
+//! statement of each COM_QUERY is run with `tonledb_sql::execute_sql` and answered
+//! with a text result set (see `results`). The statements connectors send around
+//! their queries (`SET`, `SHOW VARIABLES`, `SELECT @@...`, `USE`, `BEGIN`) are
+//! answered from the connection's state (see `session`). With a `PasswordStore`
+//! in the `MysqlOptions`, clients log in with a password first, and only users
+//! with `Write` access run statements through `tonledb_sql` (see `auth`). Until
+//! then a packet can be at most `MAX_LOGIN_PACKET_BYTES` long. With a TLS config
+//! the handshake offers `CLIENT_SSL`, and a client that asks for it carries on
+//! inside TLS.
+//!
+//! Prepared statements (COM_STMT_*) are not supported, so connectors have to
+//! prepare on the client side, as most do unless told otherwise (e.g. Connector/J
+//! without `useServerPrepStmts`). Neither are several statements in one query.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use sqlparser::ast::Statement;
+use sqlparser::dialect::MySqlDialect;
+use sqlparser::keywords::Keyword;
+use sqlparser::parser::{Parser, ParserError};
+use sqlparser::tokenizer::{Token, Tokenizer};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio_rustls::{rustls::ServerConfig, TlsAcceptor};
+use tonledb_core::Db;
+use crate::auth::{Access, PasswordStore};
+use crate::protocol::{Column, MAX_LOGIN_PACKET_BYTES, MAX_PACKET_BYTES};
+use crate::results::MysqlError;
+use crate::session::{Reply, Session};
+
+pub mod auth;
+pub mod protocol;
+pub mod results;
+pub mod session;
+
+/// Reported in the handshake and as `@@version`; connectors check it for features
+pub const SERVER_VERSION: &str = "8.0.36-TonleDB";
+
+/// Ids of the connections, as `CONNECTION_ID()` gives them
+static NEXT_CONNECTION_ID: AtomicU32 = AtomicU32::new(1);
+
+/// How the server lets clients in
+#[derive(Clone, Default)]
+pub struct MysqlOptions {
+    /// Checks passwords; `None` lets every client in without one
+    pub passwords: Option<Arc<dyn PasswordStore>>,
+    /// Offers TLS with this config; `None` doesn't
+    pub tls: Option<Arc<ServerConfig>>,
+}
+
+/// Handle a MySQL client connection
+pub async fn handle_mysql_connection<S>(mut stream: S, db: Arc<Db>, options: Arc<MysqlOptions>) -> Result<(), anyhow::Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let connection_id = NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed);
+    let scramble = auth::scramble();
+    let mut buf = Vec::new();
+    let mut seq = 0;
+    let capabilities = match options.tls {
+        Some(_) => protocol::SERVER_CAPABILITIES | protocol::CLIENT_SSL,
+        None => protocol::SERVER_CAPABILITIES,
+    };
+    protocol::handshake(&mut buf, &mut seq, connection_id, &scramble, auth::NATIVE_PASSWORD, capabilities);
+    send(&mut stream, &mut buf).await?;
+
+    let Some(first) = protocol::read_packet(&mut stream, MAX_LOGIN_PACKET_BYTES).await? else { return Ok(()) };
+    if let (Some(tls), true) = (&options.tls, protocol::is_ssl_request(&first.1)) {
+        // The handshake response comes again, inside TLS
+        let mut stream = TlsAcceptor::from(tls.clone()).accept(stream).await?;
+        let Some(first) = protocol::read_packet(&mut stream, MAX_LOGIN_PACKET_BYTES).await? else { return Ok(()) };
+        return serve(stream, db, &options, connection_id, &scramble, first, true).await;
+    }
+    serve(stream, db, &options, connection_id, &scramble, first, false).await
+}
+
+/// Log in the client whose handshake response is `first` (sequence id and
+/// payload), then run its commands; `secure` is whether it is inside TLS
+async fn serve<S>(
+    mut stream: S,
+    db: Arc<Db>,
+    options: &MysqlOptions,
+    connection_id: u32,
+    scramble: &[u8; 20],
+    first: (u8, Vec<u8>),
+    secure: bool,
+) -> Result<(), anyhow::Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (client_seq, payload) = first;
+    let mut buf = Vec::new();
+    let mut seq = client_seq.wrapping_add(1);
+    let response = match protocol::parse_handshake_response(&payload) {
+        Ok(response) => response,
+        Err(e) => {
+            protocol::err(&mut buf, &mut seq, 1043, "08S01", &format!("Bad handshake: {}", e));
+            return send(&mut stream, &mut buf).await;
+        }
+    };
+    let access = match &options.passwords {
+        Some(store) => {
+            if !auth::authenticate(&mut stream, &mut seq, &response, scramble, store.as_ref(), secure).await? {
+                return Ok(());
+            }
+            store.access(&response.user)
+        }
+        None => {
+            protocol::ok(&mut buf, &mut seq, 0, protocol::SERVER_STATUS_AUTOCOMMIT);
+            send(&mut stream, &mut buf).await?;
+            Access::Admin
+        }
+    };
+
+    let mut session = Session::new(&response.user, connection_id, response.database);
+    session.access = access;
+    while let Some((client_seq, payload)) = protocol::read_packet(&mut stream, MAX_PACKET_BYTES).await? {
+        let mut seq = client_seq.wrapping_add(1);
+        let Some((&command, body)) = payload.split_first() else { anyhow::bail!("empty command packet") };
+        match command {
+            protocol::COM_QUIT => break,
+            protocol::COM_QUERY => match query(&db, &mut session, &String::from_utf8_lossy(body)) {
+                Ok(Reply::Ok { affected_rows }) => {
+                    protocol::ok_with_warnings(&mut buf, &mut seq, affected_rows, session.status(), session.warning_count())
+                }
+                Ok(Reply::Rows(result)) => {
+                    let schema = session.database.as_deref().unwrap_or_default();
+                    protocol::result_set(&mut buf, &mut seq, schema, &result.columns, &result.rows, session.status());
+                }
+                Err(e) => protocol::err(&mut buf, &mut seq, e.code, e.sqlstate, &e.message),
+            },
+            protocol::COM_INIT_DB => {
+                session.database = Some(String::from_utf8_lossy(body).into_owned());
+                protocol::ok(&mut buf, &mut seq, 0, session.status());
+            }
+            protocol::COM_PING => protocol::ok(&mut buf, &mut seq, 0, session.status()),
+            protocol::COM_RESET_CONNECTION => {
+                session.reset();
+                protocol::ok(&mut buf, &mut seq, 0, session.status());
+            }
+            protocol::COM_FIELD_LIST => field_list(&db, &session, body, &mut buf, &mut seq),
+            // Never answered, and there is no statement they could be about
+            protocol::COM_STMT_CLOSE | protocol::COM_STMT_SEND_LONG_DATA => continue,
+            protocol::COM_STMT_PREPARE | protocol::COM_STMT_EXECUTE | protocol::COM_STMT_RESET => {
+                protocol::err(&mut buf, &mut seq, 1295, "HY000", "This command is not supported in the prepared statement protocol yet");
+            }
+            _ => protocol::err(&mut buf, &mut seq, 1047, "08S01", "Unknown command"),
+        }
+        send(&mut stream, &mut buf).await?;
+    }
+    Ok(())
+}
+
+async fn send<S: AsyncWrite + Unpin>(stream: &mut S, buf: &mut Vec<u8>) -> anyhow::Result<()> {
+    stream.write_all(buf).await?;
+    stream.flush().await?;
+    buf.clear();
+    Ok(())
+}
+
+/// Run the statement of a COM_QUERY
+fn query(db: &Db, session: &mut Session, sql: &str) -> Result<Reply, MysqlError> {
+    let statements = parse(sql).map_err(|e| MysqlError::new(1064, "42000", e.to_string()))?;
+    let stmt = match statements.as_slice() {
+        [] => return Err(MysqlError::new(1065, "42000", "Query was empty")),
+        [stmt] => stmt,
+        _ => return Err(MysqlError::unsupported("several statements in one query")),
+    };
+    session.start(stmt);
+    if session::is_session_statement(stmt) {
+        return session.answer(db, stmt);
+    }
+    // As over HTTP, SQL needs write access
+    if session.access < Access::Write {
+        return Err(MysqlError::new(1142, "42000", format!("command denied to user '{}'", session.user)));
+    }
+    let value = tonledb_sql::execute_sql(db, &translate(sql))?;
+    Ok(Reply::Rows(results::result_set(db, stmt, value)))
+}
+
+/// The statements of `sql`. The parser doesn't take `SET SESSION TRANSACTION ...`,
+/// which Connector/J sends to change the isolation level, so a query string that
+/// fails to parse and starts that way is tried again without the `SESSION`.
+fn parse(sql: &str) -> Result<Vec<Statement>, ParserError> {
+    Parser::parse_sql(&MySqlDialect {}, sql).or_else(|e| {
+        let words: Vec<&str> = sql.split_whitespace().take(3).collect();
+        match words.as_slice() {
+            [set, scope, transaction]
+                if set.eq_ignore_ascii_case("SET")
+                    && (scope.eq_ignore_ascii_case("SESSION") || scope.eq_ignore_ascii_case("GLOBAL"))
+                    && transaction.eq_ignore_ascii_case("TRANSACTION") =>
+            {
+                let rest = sql.trim_start()[set.len()..].trim_start()[scope.len()..].to_string();
+                Parser::parse_sql(&MySqlDialect {}, &format!("SET{}", rest)).map_err(|_| e)
+            }
+            _ => Err(e),
+        }
+    })
+}
+
+/// `sql` as `tonledb_sql` reads it, which is as generic SQL: backquoted names that
+/// need no quotes lose them (the catalog knows tables by their bare names), and
+/// strings are single-quoted, MySQL's backslash escapes resolved
+fn translate(sql: &str) -> String {
+    let Ok(tokens) = Tokenizer::new(&MySqlDialect {}, sql).tokenize() else { return sql.to_string() };
+    let mut out = String::with_capacity(sql.len());
+    for token in tokens {
+        match token {
+            Token::Word(w) if w.quote_style == Some('`') && is_plain_name(&w.value) => out.push_str(&w.value),
+            Token::SingleQuotedString(s) | Token::DoubleQuotedString(s) => {
+                out.push('\'');
+                out.push_str(&s.replace('\'', "''"));
+                out.push('\'');
+            }
+            other => out.push_str(&other.to_string()),
+        }
+    }
+    out
+}
+
+/// Whether `name` reads the same unquoted: letters, digits and `_`, and no keyword
+fn is_plain_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && matches!(Token::make_word(name, None), Token::Word(w) if w.keyword == Keyword::NoKeyword)
+}
+
+/// COM_FIELD_LIST: the column definitions of a table, then EOF
+fn field_list(db: &Db, session: &Session, body: &[u8], buf: &mut Vec<u8>, seq: &mut u8) {
+    let mut body = body;
+    let table = protocol::cstring(&mut body).unwrap_or_default();
+    let schema = session.database.as_deref().unwrap_or(session::DATABASE);
+    match db.catalog.read().tables.get(&table) {
+        Some(t) => {
+            for c in &t.columns {
+                let column = Column { name: c.name.clone(), column_type: results::column_type(&c.data_type) };
+                protocol::column_definition(buf, seq, schema, &table, &column);
+            }
+            protocol::eof(buf, seq, session.status());
+        }
+        None => protocol::err(buf, seq, 1146, "42S02", &format!("Table '{}.{}' doesn't exist", schema, table)),
+    }
+}
+
+/// Start a MySQL wire protocol server
+pub async fn start_mysql_server(db: Arc<Db>, bind_addr: &str, options: MysqlOptions) -> Result<(), anyhow::Error> {
+    let options = Arc::new(options);
+    let listener = TcpListener::bind(bind_addr).await?;
+    println!("MySQL wire protocol server listening on {}", bind_addr);
+
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        println!("New MySQL client connected from {}", addr);
+
+        let db_clone = db.clone();
+        let options = options.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_mysql_connection(stream, db_clone, options).await {
+                eprintln!("Error handling MySQL connection: {}", e);
+            }
+        });
+    }
+}
@@ -0,0 +1,362 @@
+//! MySQL client/server protocol framing: reading packets and encoding replies.
+//!
+//! Every packet is a 3-byte little-endian payload length, a sequence id and the
+//! payload. A payload of 16 MiB - 1 bytes or more is split over several packets,
+//! the last one shorter. The sequence id starts at 0 with each command (and with
+//! the handshake) and counts every packet of the exchange, in both directions.
+//! Result sets go in the text protocol, ended by EOF packets: the server doesn't
+//! offer CLIENT_DEPRECATE_EOF.
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Longest payload of one packet
+const MAX_PAYLOAD: usize = 0xff_ffff;
+/// Longest payload accepted, split or not; `max_allowed_packet` reports it
+pub const MAX_PACKET_BYTES: usize = 64 << 20;
+/// Longest payload accepted before the client has logged in
+pub const MAX_LOGIN_PACKET_BYTES: usize = 64 << 10;
+
+pub const CLIENT_LONG_PASSWORD: u32 = 1;
+pub const CLIENT_FOUND_ROWS: u32 = 1 << 1;
+pub const CLIENT_LONG_FLAG: u32 = 1 << 2;
+pub const CLIENT_CONNECT_WITH_DB: u32 = 1 << 3;
+pub const CLIENT_PROTOCOL_41: u32 = 1 << 9;
+pub const CLIENT_SSL: u32 = 1 << 11;
+pub const CLIENT_TRANSACTIONS: u32 = 1 << 13;
+pub const CLIENT_SECURE_CONNECTION: u32 = 1 << 15;
+pub const CLIENT_PLUGIN_AUTH: u32 = 1 << 19;
+pub const CLIENT_CONNECT_ATTRS: u32 = 1 << 20;
+pub const CLIENT_PLUGIN_AUTH_LENENC_CLIENT_DATA: u32 = 1 << 21;
+
+/// Capabilities offered in the handshake; `CLIENT_SSL` is added when the server
+/// has a TLS config
+pub const SERVER_CAPABILITIES: u32 = CLIENT_LONG_PASSWORD
+    | CLIENT_FOUND_ROWS
+    | CLIENT_LONG_FLAG
+    | CLIENT_CONNECT_WITH_DB
+    | CLIENT_PROTOCOL_41
+    | CLIENT_TRANSACTIONS
+    | CLIENT_SECURE_CONNECTION
+    | CLIENT_PLUGIN_AUTH
+    | CLIENT_CONNECT_ATTRS
+    | CLIENT_PLUGIN_AUTH_LENENC_CLIENT_DATA;
+
+/// Status flag: a transaction is open
+pub const SERVER_STATUS_IN_TRANS: u16 = 1;
+/// Status flag: statements commit on their own
+pub const SERVER_STATUS_AUTOCOMMIT: u16 = 1 << 1;
+
+/// `utf8mb4_general_ci`, the character set of text columns and the connection
+pub const UTF8MB4_GENERAL_CI: u8 = 45;
+/// `binary`, the character set of numeric columns
+const BINARY_CHARSET: u16 = 63;
+
+pub const COM_QUIT: u8 = 0x01;
+pub const COM_INIT_DB: u8 = 0x02;
+pub const COM_QUERY: u8 = 0x03;
+pub const COM_FIELD_LIST: u8 = 0x04;
+pub const COM_PING: u8 = 0x0e;
+pub const COM_STMT_PREPARE: u8 = 0x16;
+pub const COM_STMT_EXECUTE: u8 = 0x17;
+pub const COM_STMT_SEND_LONG_DATA: u8 = 0x18;
+pub const COM_STMT_CLOSE: u8 = 0x19;
+pub const COM_STMT_RESET: u8 = 0x1a;
+pub const COM_RESET_CONNECTION: u8 = 0x1f;
+
+pub const TYPE_TINY: u8 = 0x01;
+pub const TYPE_DOUBLE: u8 = 0x05;
+pub const TYPE_NULL: u8 = 0x06;
+pub const TYPE_LONGLONG: u8 = 0x08;
+pub const TYPE_JSON: u8 = 0xf5;
+pub const TYPE_NEWDECIMAL: u8 = 0xf6;
+pub const TYPE_VAR_STRING: u8 = 0xfd;
+
+/// Column flag: the value is compared as bytes, set on numeric columns
+const BINARY_FLAG: u16 = 1 << 7;
+
+/// A column of a result set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Column {
+    pub name: String,
+    /// One of the `TYPE_` constants
+    pub column_type: u8,
+}
+
+/// What a client sends in reply to the handshake
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandshakeResponse {
+    pub capabilities: u32,
+    pub user: String,
+    /// Scrambled (or, for `mysql_clear_password`, plain) password
+    pub auth_response: Vec<u8>,
+    /// Database to start in
+    pub database: Option<String>,
+    /// Plugin `auth_response` was made with
+    pub auth_plugin: Option<String>,
+}
+
+/// Read the next packet, joining a payload split over several, of at most
+/// `max_len` bytes; its sequence id (the last one's) and payload, or `None` when
+/// the client has closed the connection
+pub async fn read_packet<R: AsyncRead + Unpin>(r: &mut R, max_len: usize) -> anyhow::Result<Option<(u8, Vec<u8>)>> {
+    let mut payload = Vec::new();
+    loop {
+        let mut header = [0u8; 4];
+        match r.read_exact(&mut header).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof && payload.is_empty() => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let len = u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize;
+        if payload.len() + len > max_len {
+            anyhow::bail!("packet longer than {} bytes", max_len);
+        }
+        let start = payload.len();
+        payload.resize(start + len, 0);
+        r.read_exact(&mut payload[start..]).await?;
+        if len < MAX_PAYLOAD {
+            return Ok(Some((header[3], payload)));
+        }
+    }
+}
+
+/// Append a packet with the payload `f` writes, numbered `seq`, which is advanced
+/// past it (and past every packet a long payload is split into)
+fn packet(buf: &mut Vec<u8>, seq: &mut u8, f: impl FnOnce(&mut Vec<u8>)) {
+    let mut payload = Vec::new();
+    f(&mut payload);
+    let mut rest = payload.as_slice();
+    loop {
+        let n = rest.len().min(MAX_PAYLOAD);
+        buf.extend_from_slice(&(n as u32).to_le_bytes()[..3]);
+        buf.push(*seq);
+        *seq = seq.wrapping_add(1);
+        buf.extend_from_slice(&rest[..n]);
+        rest = &rest[n..];
+        if n < MAX_PAYLOAD {
+            break;
+        }
+    }
+}
+
+fn put_cstr(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(s.as_bytes());
+    buf.push(0);
+}
+
+/// Length-encoded integer
+fn put_lenenc_int(buf: &mut Vec<u8>, n: u64) {
+    match n {
+        0..=250 => buf.push(n as u8),
+        251..=0xffff => {
+            buf.push(0xfc);
+            buf.extend_from_slice(&(n as u16).to_le_bytes());
+        }
+        0x1_0000..=0xff_ffff => {
+            buf.push(0xfd);
+            buf.extend_from_slice(&(n as u32).to_le_bytes()[..3]);
+        }
+        _ => {
+            buf.push(0xfe);
+            buf.extend_from_slice(&n.to_le_bytes());
+        }
+    }
+}
+
+fn put_lenenc_str(buf: &mut Vec<u8>, s: &[u8]) {
+    put_lenenc_int(buf, s.len() as u64);
+    buf.extend_from_slice(s);
+}
+
+/// The server's greeting, packet 0 of the connection: HandshakeV10 offering
+/// `capabilities` and `auth_plugin` with the 20-byte `scramble`
+pub fn handshake(buf: &mut Vec<u8>, seq: &mut u8, connection_id: u32, scramble: &[u8; 20], auth_plugin: &str, capabilities: u32) {
+    packet(buf, seq, |b| {
+        b.push(10); // protocol version
+        put_cstr(b, crate::SERVER_VERSION);
+        b.extend_from_slice(&connection_id.to_le_bytes());
+        b.extend_from_slice(&scramble[..8]);
+        b.push(0);
+        b.extend_from_slice(&(capabilities as u16).to_le_bytes());
+        b.push(UTF8MB4_GENERAL_CI);
+        b.extend_from_slice(&SERVER_STATUS_AUTOCOMMIT.to_le_bytes());
+        b.extend_from_slice(&((capabilities >> 16) as u16).to_le_bytes());
+        b.push(scramble.len() as u8 + 1);
+        b.extend_from_slice(&[0; 10]);
+        b.extend_from_slice(&scramble[8..]);
+        b.push(0);
+        put_cstr(b, auth_plugin);
+    });
+}
+
+/// Whether a reply to the handshake is an SSL request: the start of a
+/// HandshakeResponse41 with `CLIENT_SSL`, after which the client starts TLS
+pub fn is_ssl_request(payload: &[u8]) -> bool {
+    payload.len() == 32 && u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]) & CLIENT_SSL != 0
+}
+
+/// Parse a HandshakeResponse41. Fails on the pre-4.1 response, which no client
+/// still in use sends, and on an SSL request (see `is_ssl_request`).
+pub fn parse_handshake_response(payload: &[u8]) -> anyhow::Result<HandshakeResponse> {
+    let mut buf = payload;
+    let capabilities = u32::from_le_bytes(take(&mut buf, 4)?.try_into()?);
+    if capabilities & CLIENT_PROTOCOL_41 == 0 {
+        anyhow::bail!("client does not speak protocol 4.1");
+    }
+    let _max_packet_size = take(&mut buf, 4)?;
+    let _charset = take(&mut buf, 1)?;
+    let _filler = take(&mut buf, 23)?;
+    if buf.is_empty() && capabilities & CLIENT_SSL != 0 {
+        anyhow::bail!("client asked for SSL, which is not offered");
+    }
+    let user = cstring(&mut buf)?;
+    let auth_response = if capabilities & CLIENT_PLUGIN_AUTH_LENENC_CLIENT_DATA != 0 {
+        let len = lenenc_int(&mut buf)?;
+        take(&mut buf, usize::try_from(len)?)?.to_vec()
+    } else if capabilities & CLIENT_SECURE_CONNECTION != 0 {
+        let len = take(&mut buf, 1)?[0];
+        take(&mut buf, len as usize)?.to_vec()
+    } else {
+        cstring(&mut buf)?.into_bytes()
+    };
+    let database = match capabilities & CLIENT_CONNECT_WITH_DB != 0 && !buf.is_empty() {
+        true => Some(cstring(&mut buf)?).filter(|db| !db.is_empty()),
+        false => None,
+    };
+    let auth_plugin = match capabilities & CLIENT_PLUGIN_AUTH != 0 && !buf.is_empty() {
+        true => Some(cstring(&mut buf)?),
+        false => None,
+    };
+    // Connection attributes (client name, version, ...) are not kept
+    Ok(HandshakeResponse { capabilities, user, auth_response, database, auth_plugin })
+}
+
+/// Split the first `n` bytes off `buf`
+fn take<'a>(buf: &mut &'a [u8], n: usize) -> anyhow::Result<&'a [u8]> {
+    if buf.len() < n {
+        anyhow::bail!("truncated packet");
+    }
+    let (head, rest) = buf.split_at(n);
+    *buf = rest;
+    Ok(head)
+}
+
+/// A NUL-terminated string, or the rest of `buf` if it has no NUL
+pub(crate) fn cstring(buf: &mut &[u8]) -> anyhow::Result<String> {
+    let end = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    let s = String::from_utf8(buf[..end].to_vec())?;
+    *buf = &buf[(end + 1).min(buf.len())..];
+    Ok(s)
+}
+
+fn lenenc_int(buf: &mut &[u8]) -> anyhow::Result<u64> {
+    let width = match take(buf, 1)?[0] {
+        n @ 0..=250 => return Ok(n as u64),
+        0xfc => 2,
+        0xfd => 3,
+        0xfe => 8,
+        b => anyhow::bail!("bad length-encoded integer {:#x}", b),
+    };
+    let mut bytes = [0u8; 8];
+    bytes[..width].copy_from_slice(take(buf, width)?);
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Success without a result set
+pub fn ok(buf: &mut Vec<u8>, seq: &mut u8, affected_rows: u64, status: u16) {
+    ok_with_warnings(buf, seq, affected_rows, status, 0);
+}
+
+/// OK that tells the client `SHOW WARNINGS` has `warnings` for it
+pub fn ok_with_warnings(buf: &mut Vec<u8>, seq: &mut u8, affected_rows: u64, status: u16, warnings: u16) {
+    packet(buf, seq, |b| {
+        b.push(0x00);
+        put_lenenc_int(b, affected_rows);
+        put_lenenc_int(b, 0); // last insert id
+        b.extend_from_slice(&status.to_le_bytes());
+        b.extend_from_slice(&warnings.to_le_bytes());
+    });
+}
+
+/// An error with its MySQL error `code` and SQLSTATE
+pub fn err(buf: &mut Vec<u8>, seq: &mut u8, code: u16, sqlstate: &str, message: &str) {
+    packet(buf, seq, |b| {
+        b.push(0xff);
+        b.extend_from_slice(&code.to_le_bytes());
+        b.push(b'#');
+        b.extend_from_slice(sqlstate.as_bytes());
+        b.extend_from_slice(message.as_bytes());
+    });
+}
+
+/// End of the column definitions or of the rows of a result set
+pub fn eof(buf: &mut Vec<u8>, seq: &mut u8, status: u16) {
+    packet(buf, seq, |b| {
+        b.push(0xfe);
+        b.extend_from_slice(&0u16.to_le_bytes()); // warnings
+        b.extend_from_slice(&status.to_le_bytes());
+    });
+}
+
+/// Ask the client to authenticate again with `plugin`, given `data`
+pub fn auth_switch_request(buf: &mut Vec<u8>, seq: &mut u8, plugin: &str, data: &[u8]) {
+    packet(buf, seq, |b| {
+        b.push(0xfe);
+        put_cstr(b, plugin);
+        b.extend_from_slice(data);
+    });
+}
+
+/// A ColumnDefinition41 for `column` of `table` in `schema`
+pub fn column_definition(buf: &mut Vec<u8>, seq: &mut u8, schema: &str, table: &str, column: &Column) {
+    let (charset, length, flags, decimals) = match column.column_type {
+        TYPE_TINY => (BINARY_CHARSET, 1, BINARY_FLAG, 0),
+        TYPE_LONGLONG => (BINARY_CHARSET, 20, BINARY_FLAG, 0),
+        TYPE_DOUBLE => (BINARY_CHARSET, 22, BINARY_FLAG, 0x1f),
+        TYPE_NEWDECIMAL => (BINARY_CHARSET, 67, BINARY_FLAG, 0x1f),
+        TYPE_NULL => (BINARY_CHARSET, 0, BINARY_FLAG, 0),
+        TYPE_JSON => (UTF8MB4_GENERAL_CI as u16, u32::MAX, 0, 0),
+        // Four bytes a character, as for a VARCHAR(65535)
+        _ => (UTF8MB4_GENERAL_CI as u16, 4 * 0xffff, 0, 0),
+    };
+    packet(buf, seq, |b| {
+        put_lenenc_str(b, b"def");
+        put_lenenc_str(b, schema.as_bytes());
+        put_lenenc_str(b, table.as_bytes()); // table
+        put_lenenc_str(b, table.as_bytes()); // original table
+        put_lenenc_str(b, column.name.as_bytes());
+        put_lenenc_str(b, column.name.as_bytes()); // original name
+        b.push(0x0c); // length of the fixed-length fields
+        b.extend_from_slice(&charset.to_le_bytes());
+        b.extend_from_slice(&length.to_le_bytes());
+        b.push(column.column_type);
+        b.extend_from_slice(&flags.to_le_bytes());
+        b.push(decimals);
+        b.extend_from_slice(&[0, 0]);
+    });
+}
+
+/// One row in the text protocol; `None` cells are NULL
+pub fn text_row<T: AsRef<[u8]>>(buf: &mut Vec<u8>, seq: &mut u8, cells: &[Option<T>]) {
+    packet(buf, seq, |b| {
+        for cell in cells {
+            match cell {
+                Some(v) => put_lenenc_str(b, v.as_ref()),
+                None => b.push(0xfb),
+            }
+        }
+    });
+}
+
+/// A whole result set: the column count, the column definitions, then the rows
+pub fn result_set(buf: &mut Vec<u8>, seq: &mut u8, schema: &str, columns: &[Column], rows: &[Vec<Option<String>>], status: u16) {
+    packet(buf, seq, |b| put_lenenc_int(b, columns.len() as u64));
+    for column in columns {
+        column_definition(buf, seq, schema, "", column);
+    }
+    eof(buf, seq, status);
+    for row in rows {
+        text_row(buf, seq, row);
+    }
+    eof(buf, seq, status);
+}
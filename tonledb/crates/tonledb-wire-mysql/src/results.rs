@@ -0,0 +1,171 @@
+//! Turning what `tonledb_sql::execute_sql` returns into MySQL result sets.
+//!
+//! A `SELECT` from a table takes its column names and types from the catalog, so
+//! even an empty result describes its columns. Other results (collections,
+//! `EXPLAIN`, `ANALYZE`) get the keys of their rows as columns, in the order they
+//! are first seen, typed by the values under them. Values go in the text protocol,
+//! booleans as `1` and `0` the way MySQL stores them.
+
+use serde_json::Value as Json;
+use sqlparser::ast::{Expr, ObjectName, SelectItem, SetExpr, Statement};
+use tonledb_core::{DataType, Db, DbError, TableSchema};
+use crate::protocol::{Column, TYPE_DOUBLE, TYPE_JSON, TYPE_LONGLONG, TYPE_NEWDECIMAL, TYPE_TINY, TYPE_VAR_STRING};
+
+/// Rows of one statement, ready to send
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResultSet {
+    pub columns: Vec<Column>,
+    pub rows: Vec<Vec<Option<String>>>,
+}
+
+pub(crate) fn column(name: &str, column_type: u8) -> Column {
+    Column { name: name.to_string(), column_type }
+}
+
+/// MySQL type of a catalog column type
+pub fn column_type(data_type: &DataType) -> u8 {
+    match data_type {
+        DataType::Integer => TYPE_LONGLONG,
+        DataType::Float => TYPE_DOUBLE,
+        DataType::Text => TYPE_VAR_STRING,
+        DataType::Boolean => TYPE_TINY,
+        DataType::Json => TYPE_JSON,
+        DataType::Decimal => TYPE_NEWDECIMAL,
+    }
+}
+
+/// MySQL's name for a catalog column type, as `DESCRIBE` shows it
+pub fn type_name(data_type: &DataType) -> &'static str {
+    match data_type {
+        DataType::Integer => "bigint",
+        DataType::Float => "double",
+        DataType::Text => "text",
+        DataType::Boolean => "tinyint(1)",
+        DataType::Json => "json",
+        DataType::Decimal => "decimal(65,30)",
+    }
+}
+
+/// The result set of `stmt`, which returned `value`
+pub fn result_set(db: &Db, stmt: &Statement, value: Json) -> ResultSet {
+    let rows = match value {
+        Json::Array(rows) => rows,
+        Json::Null => Vec::new(),
+        Json::Object(_) => vec![value],
+        other => vec![serde_json::json!({ "result": other })],
+    };
+    let mut columns = catalog_columns(db, stmt).unwrap_or_default();
+    // Keys the catalog doesn't know of, e.g. every key of a collection's documents
+    for row in &rows {
+        let Some(obj) = row.as_object() else { continue };
+        for key in obj.keys() {
+            if !columns.iter().any(|c| c.name == *key) {
+                columns.push(column(key, common_type(rows.iter().filter_map(|r| r.get(key)))));
+            }
+        }
+    }
+    let cells = rows.iter().map(|row| columns.iter().map(|c| text_value(c.column_type, row.get(&c.name))).collect()).collect();
+    ResultSet { columns, rows: cells }
+}
+
+/// The catalog table `name` names, its quotes dropped
+pub(crate) fn table(db: &Db, name: &ObjectName) -> Option<TableSchema> {
+    let name = name.0.iter().map(|i| i.value.as_str()).collect::<Vec<_>>().join(".");
+    db.catalog.read().tables.get(&name).cloned()
+}
+
+/// Columns of a `SELECT` from a catalog table, if `stmt` is one whose projection
+/// names columns (or `*`)
+fn catalog_columns(db: &Db, stmt: &Statement) -> Option<Vec<Column>> {
+    let Statement::Query(query) = stmt else { return None };
+    let SetExpr::Select(select) = &*query.body else { return None };
+    let [from] = select.from.as_slice() else { return None };
+    let sqlparser::ast::TableFactor::Table { name, .. } = &from.relation else { return None };
+    let table = table(db, name)?;
+    let type_of = |name: &str| table.columns.iter().find(|c| c.name == name).map(|c| column_type(&c.data_type));
+    let mut columns = Vec::new();
+    for item in &select.projection {
+        match item {
+            SelectItem::Wildcard(_) => columns.extend(table.columns.iter().map(|c| column(&c.name, column_type(&c.data_type)))),
+            SelectItem::UnnamedExpr(Expr::Identifier(ident)) => columns.push(column(&ident.value, type_of(&ident.value)?)),
+            SelectItem::ExprWithAlias { expr: Expr::Identifier(ident), alias } => columns.push(column(&alias.value, type_of(&ident.value)?)),
+            _ => return None,
+        }
+    }
+    Some(columns)
+}
+
+/// Type of a column of `values`: the one type they share, text if they differ
+pub(crate) fn common_type<'a>(values: impl IntoIterator<Item = &'a Json>) -> u8 {
+    let mut found = None;
+    for v in values {
+        let this = match v {
+            Json::Null => continue,
+            Json::Bool(_) => TYPE_TINY,
+            Json::Number(n) if n.is_i64() || n.is_u64() => TYPE_LONGLONG,
+            Json::Number(_) => TYPE_NEWDECIMAL,
+            Json::String(_) => TYPE_VAR_STRING,
+            Json::Array(_) | Json::Object(_) => TYPE_JSON,
+        };
+        found = match found {
+            None => Some(this),
+            Some(TYPE_LONGLONG) if this == TYPE_NEWDECIMAL => Some(TYPE_NEWDECIMAL),
+            Some(TYPE_NEWDECIMAL) if this == TYPE_LONGLONG => Some(TYPE_NEWDECIMAL),
+            Some(seen) if seen == this => Some(seen),
+            Some(_) => return TYPE_VAR_STRING,
+        };
+    }
+    found.unwrap_or(TYPE_VAR_STRING)
+}
+
+/// Text form of `v` in a column of `column_type`; `None` for NULL
+pub fn text_value(column_type: u8, v: Option<&Json>) -> Option<String> {
+    match v? {
+        Json::Null => None,
+        v if column_type == TYPE_JSON => Some(v.to_string()),
+        Json::Bool(b) => Some(if *b { "1" } else { "0" }.to_string()),
+        Json::String(s) => Some(s.clone()),
+        other => Some(other.to_string()),
+    }
+}
+
+/// An error to send to the client, with its MySQL error code and SQLSTATE
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MysqlError {
+    pub code: u16,
+    pub sqlstate: &'static str,
+    pub message: String,
+}
+
+impl MysqlError {
+    pub fn new(code: u16, sqlstate: &'static str, message: impl Into<String>) -> Self {
+        Self { code, sqlstate, message: message.into() }
+    }
+
+    /// ER_NOT_SUPPORTED_YET
+    pub fn unsupported(what: impl std::fmt::Display) -> Self {
+        Self::new(1235, "42000", format!("This version of TonleDB doesn't yet support '{}'", what))
+    }
+}
+
+impl From<DbError> for MysqlError {
+    fn from(e: DbError) -> Self {
+        let (code, sqlstate) = error_code(&e);
+        Self::new(code, sqlstate, e.to_string())
+    }
+}
+
+/// MySQL error code and SQLSTATE of an error
+pub fn error_code(e: &DbError) -> (u16, &'static str) {
+    match e {
+        // ER_PARSE_ERROR: the engine rejects what it can't run
+        DbError::Invalid(_) => (1064, "42000"),
+        DbError::NotFound(_) => (1146, "42S02"),
+        DbError::Conflict(_) => (1213, "40001"),
+        DbError::ResourceExhausted(_) => (1041, "HY000"),
+        DbError::DeadlineExceeded(_) => (3024, "HY000"),
+        DbError::UniqueViolation(_) => (1062, "23000"),
+        DbError::NotNullViolation(_) => (1048, "23000"),
+        DbError::Storage(_) | DbError::Corruption(_) => (1105, "HY000"),
+    }
+}
@@ -0,0 +1,533 @@
+//! Per-connection state, and answers to the statements connectors send around
+//! their queries.
+//!
+//! Drivers and the `mysql` client set variables (`SET NAMES`, `SET autocommit`),
+//! read them back (`SELECT @@version_comment`, `SHOW VARIABLES`), look at the
+//! schema (`SHOW TABLES`, `DESCRIBE`) and wrap work in transactions. Those
+//! statements, and any `SELECT` without `FROM`, are answered here instead of by
+//! `tonledb_sql`. Variables set with `SET` last for the connection. Every database
+//! name reaches the same tables, reported as `tonledb`. Transactions are accepted
+//! so that connectors which open them can run, but each statement takes effect on
+//! its own: nothing is held back for `COMMIT`, nor undone by `ROLLBACK`. `BEGIN`
+//! and `ROLLBACK` say so with a warning, which `SHOW WARNINGS` reads until the
+//! next statement.
+
+use std::collections::HashMap;
+use serde_json::{json, Value as Json};
+use sqlparser::ast::{
+    BinaryOperator, Expr, FunctionArguments, Ident, ObjectName, OneOrManyWithParens, Query, SelectItem, SetExpr,
+    ShowStatementFilter, Statement, TableFactor, UnaryOperator, Value,
+};
+use tonledb_core::{ColumnConstraint, Db};
+use crate::auth::Access;
+use crate::protocol::{self, Column};
+use crate::results::{self, MysqlError, ResultSet};
+
+/// Name the database is reported under
+pub const DATABASE: &str = "tonledb";
+
+/// System variables connectors read, with the values they start out with
+const SYSTEM_VARIABLES: &[(&str, &str)] = &[
+    ("auto_increment_increment", "1"),
+    ("character_set_client", "utf8mb4"),
+    ("character_set_connection", "utf8mb4"),
+    ("character_set_database", "utf8mb4"),
+    ("character_set_results", "utf8mb4"),
+    ("character_set_server", "utf8mb4"),
+    ("character_set_system", "utf8mb3"),
+    ("collation_connection", "utf8mb4_general_ci"),
+    ("collation_database", "utf8mb4_general_ci"),
+    ("collation_server", "utf8mb4_general_ci"),
+    ("init_connect", ""),
+    ("interactive_timeout", "28800"),
+    ("license", "MIT"),
+    ("lower_case_table_names", "0"),
+    ("max_allowed_packet", "67108864"),
+    ("net_buffer_length", "16384"),
+    ("net_write_timeout", "60"),
+    ("performance_schema", "0"),
+    ("query_cache_size", "0"),
+    ("query_cache_type", "OFF"),
+    ("sql_mode", "ONLY_FULL_GROUP_BY,STRICT_TRANS_TABLES,NO_ZERO_IN_DATE,NO_ZERO_DATE,ERROR_FOR_DIVISION_BY_ZERO,NO_ENGINE_SUBSTITUTION"),
+    ("system_time_zone", "UTC"),
+    ("time_zone", "SYSTEM"),
+    ("transaction_isolation", "REPEATABLE-READ"),
+    ("transaction_read_only", "0"),
+    ("tx_isolation", "REPEATABLE-READ"),
+    ("tx_read_only", "0"),
+    ("version", crate::SERVER_VERSION),
+    ("version_comment", "TonleDB"),
+    ("wait_timeout", "28800"),
+];
+
+/// Collations `SHOW COLLATION` lists: name, character set and id
+const COLLATIONS: &[(&str, &str, u8)] = &[
+    ("utf8mb4_general_ci", "utf8mb4", protocol::UTF8MB4_GENERAL_CI),
+    ("utf8mb4_bin", "utf8mb4", 46),
+    ("binary", "binary", 63),
+];
+
+/// What a statement sends back
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    Ok { affected_rows: u64 },
+    Rows(ResultSet),
+}
+
+/// State of one connection
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub user: String,
+    pub connection_id: u32,
+    /// Database chosen with `USE` or at connect
+    pub database: Option<String>,
+    pub autocommit: bool,
+    pub in_transaction: bool,
+    /// What the user may do
+    pub access: Access,
+    /// Warnings of the last statement: code and message
+    warnings: Vec<(u16, String)>,
+    /// System variables set with `SET`, by lower-case name
+    variables: HashMap<String, Json>,
+    /// User variables (`@name`), by lower-case name
+    user_variables: HashMap<String, Json>,
+}
+
+impl Session {
+    pub fn new(user: &str, connection_id: u32, database: Option<String>) -> Self {
+        Self {
+            user: user.to_string(),
+            connection_id,
+            database,
+            autocommit: true,
+            in_transaction: false,
+            access: Access::Admin,
+            warnings: Vec::new(),
+            variables: HashMap::new(),
+            user_variables: HashMap::new(),
+        }
+    }
+
+    /// Status flags for OK and EOF packets
+    pub fn status(&self) -> u16 {
+        let mut status = 0;
+        if self.autocommit {
+            status |= protocol::SERVER_STATUS_AUTOCOMMIT;
+        }
+        if self.in_transaction {
+            status |= protocol::SERVER_STATUS_IN_TRANS;
+        }
+        status
+    }
+
+    /// Back to the state of a new connection, keeping the user, their access and
+    /// the database
+    pub fn reset(&mut self) {
+        let (user, database, access) = (std::mem::take(&mut self.user), self.database.take(), self.access);
+        *self = Self::new(&user, self.connection_id, database);
+        self.access = access;
+    }
+
+    /// Warnings for the OK packet of the last statement
+    pub fn warning_count(&self) -> u16 {
+        self.warnings.len().min(u16::MAX as usize) as u16
+    }
+
+    /// Called before running `stmt`: the warnings of the last statement are
+    /// dropped, unless `stmt` is the `SHOW WARNINGS` reading them
+    pub fn start(&mut self, stmt: &Statement) {
+        let show_warnings = matches!(stmt, Statement::ShowVariable { variable }
+            if variable.len() == 1 && variable[0].value.eq_ignore_ascii_case("WARNINGS"));
+        if !show_warnings {
+            self.warnings.clear();
+        }
+    }
+
+    /// Run a statement `is_session_statement` accepts
+    pub fn answer(&mut self, db: &Db, stmt: &Statement) -> Result<Reply, MysqlError> {
+        let ok = Ok(Reply::Ok { affected_rows: 0 });
+        match stmt {
+            Statement::SetVariable { variables: OneOrManyWithParens::One(name), value, .. } => {
+                let [value] = value.as_slice() else { return Err(MysqlError::unsupported(stmt)) };
+                self.set(name, value)?;
+                ok
+            }
+            Statement::SetNames { charset_name, .. } => {
+                for name in ["character_set_client", "character_set_connection", "character_set_results"] {
+                    self.variables.insert(name.to_string(), json!(charset_name));
+                }
+                ok
+            }
+            Statement::SetNamesDefault {} => {
+                for name in ["character_set_client", "character_set_connection", "character_set_results"] {
+                    self.variables.remove(name);
+                }
+                ok
+            }
+            // Isolation levels and access modes: there is nothing to isolate
+            Statement::SetTransaction { .. } => ok,
+            Statement::Use { db_name } => {
+                self.database = Some(db_name.value.clone());
+                ok
+            }
+            Statement::StartTransaction { .. } => {
+                self.in_transaction = true;
+                self.warnings.push((1105, "TonleDB does not hold statements back for COMMIT; each one takes effect on its own".into()));
+                ok
+            }
+            Statement::Commit { .. } => {
+                self.in_transaction = false;
+                ok
+            }
+            Statement::Rollback { .. } => {
+                self.in_transaction = false;
+                // MySQL's warning for tables that can't roll back
+                self.warnings.push((1196, "Some non-transactional changed tables couldn't be rolled back".into()));
+                ok
+            }
+            Statement::ShowVariables { filter, .. } => {
+                let mut names: Vec<&str> = SYSTEM_VARIABLES.iter().map(|(n, _)| *n).chain(["autocommit"]).collect();
+                names.sort_unstable();
+                let rows = names.into_iter().map(|n| vec![json!(n), json!(text(&self.variable(n).unwrap_or_default()))]).collect();
+                self.rows(&["Variable_name", "Value"], rows, filter.as_ref())
+            }
+            // Nothing keeps counters
+            Statement::ShowStatus { filter, .. } => self.rows(&["Variable_name", "Value"], Vec::new(), filter.as_ref()),
+            Statement::ShowTables { full, filter, .. } => {
+                let mut names: Vec<String> = db.catalog.read().tables.keys().cloned().collect();
+                names.sort_unstable();
+                let column = format!("Tables_in_{}", self.database.as_deref().unwrap_or(DATABASE));
+                match full {
+                    true => self.rows(&[column.as_str(), "Table_type"], names.into_iter().map(|n| vec![json!(n), json!("BASE TABLE")]).collect(), filter.as_ref()),
+                    false => self.rows(&[column.as_str()], names.into_iter().map(|n| vec![json!(n)]).collect(), filter.as_ref()),
+                }
+            }
+            Statement::ShowColumns { table_name, filter, .. } => self.describe(db, table_name, filter.as_ref()),
+            Statement::ExplainTable { table_name, .. } => self.describe(db, table_name, None),
+            Statement::ShowCollation { filter } => {
+                let rows = COLLATIONS
+                    .iter()
+                    .map(|(name, charset, id)| {
+                        let default = if *id == protocol::UTF8MB4_GENERAL_CI || *id == 63 { "Yes" } else { "" };
+                        vec![json!(name), json!(charset), json!(id), json!(default), json!("Yes"), json!(1)]
+                    })
+                    .collect();
+                self.rows(&["Collation", "Charset", "Id", "Default", "Compiled", "Sortlen"], rows, filter.as_ref())
+            }
+            Statement::ShowVariable { variable } => match variable.first().map(|i| i.value.to_ascii_uppercase()).as_deref() {
+                Some("DATABASES" | "SCHEMAS") if variable.len() == 1 => self.rows(&["Database"], vec![vec![json!(DATABASE)]], None),
+                Some("WARNINGS") if variable.len() == 1 => {
+                    let rows = self.warnings.iter().map(|(code, message)| vec![json!("Warning"), json!(code), json!(message)]).collect();
+                    self.rows(&["Level", "Code", "Message"], rows, None)
+                }
+                // Errors are only sent as ERR packets
+                Some("ERRORS") if variable.len() == 1 => self.rows(&["Level", "Code", "Message"], Vec::new(), None),
+                _ => Err(MysqlError::unsupported(stmt)),
+            },
+            Statement::Query(query) => self.select(query),
+            _ => Err(MysqlError::unsupported(stmt)),
+        }
+    }
+
+    /// `SET name = value`, for a system or user variable
+    fn set(&mut self, name: &ObjectName, value: &Expr) -> Result<(), MysqlError> {
+        // Bare words are strings here: `SET sql_mode = ANSI`
+        let value = self.eval(value, &|name: &str| Some(json!(name)))?;
+        let (name, system) = variable_name(&name.0).ok_or_else(|| MysqlError::unsupported(format!("SET {}", name)))?;
+        if !system {
+            self.user_variables.insert(name, value);
+            return Ok(());
+        }
+        if self.variable(&name).is_none() {
+            return Err(unknown_variable(&name));
+        }
+        if name == "autocommit" {
+            self.autocommit = truthy(&value);
+            // Turning it on ends the open transaction
+            self.in_transaction &= !self.autocommit;
+        } else {
+            self.variables.insert(name, value);
+        }
+        Ok(())
+    }
+
+    /// Current value of system variable `name`, given in lower case
+    fn variable(&self, name: &str) -> Option<Json> {
+        if name == "autocommit" {
+            return Some(json!(self.autocommit as i64));
+        }
+        if let Some(v) = self.variables.get(name) {
+            return Some(v.clone());
+        }
+        let (_, v) = SYSTEM_VARIABLES.iter().find(|(n, _)| *n == name)?;
+        Some(v.parse::<i64>().map_or_else(|_| json!(v), Json::from))
+    }
+
+    /// `SHOW COLUMNS` and `DESCRIBE`
+    fn describe(&self, db: &Db, table_name: &ObjectName, filter: Option<&ShowStatementFilter>) -> Result<Reply, MysqlError> {
+        let Some(table) = results::table(db, table_name) else {
+            let name = format!("{}.{}", self.database.as_deref().unwrap_or(DATABASE), table_name);
+            return Err(MysqlError::new(1146, "42S02", format!("Table '{}' doesn't exist", name)));
+        };
+        let rows = table
+            .columns
+            .iter()
+            .map(|c| {
+                let primary = table.pk.as_deref() == Some(c.name.as_str()) || c.constraints.contains(&ColumnConstraint::PrimaryKey);
+                let not_null = primary || c.constraints.contains(&ColumnConstraint::NotNull);
+                let key = match (primary, c.constraints.contains(&ColumnConstraint::Unique)) {
+                    (true, _) => "PRI",
+                    (false, true) => "UNI",
+                    (false, false) => "",
+                };
+                vec![json!(c.name), json!(results::type_name(&c.data_type)), json!(if not_null { "NO" } else { "YES" }), json!(key), Json::Null, json!("")]
+            })
+            .collect();
+        self.rows(&["Field", "Type", "Null", "Key", "Default", "Extra"], rows, filter)
+    }
+
+    /// A `SELECT` without `FROM` (or from `DUAL`): one row of its expressions
+    fn select(&self, query: &Query) -> Result<Reply, MysqlError> {
+        let SetExpr::Select(select) = &*query.body else { return Err(MysqlError::unsupported(query)) };
+        let mut names = Vec::new();
+        let mut row = Vec::new();
+        for item in &select.projection {
+            let (name, expr) = match item {
+                SelectItem::UnnamedExpr(expr) => (expr.to_string(), expr),
+                SelectItem::ExprWithAlias { expr, alias } => (alias.value.clone(), expr),
+                _ => return Err(MysqlError::new(1096, "HY000", "No tables used")),
+            };
+            row.push(self.eval(expr, &|_: &str| None)?);
+            names.push(name);
+        }
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        let rows = match &query.limit {
+            Some(Expr::Value(Value::Number(n, _))) if n == "0" => Vec::new(),
+            _ => vec![row],
+        };
+        self.rows(&names, rows, None)
+    }
+
+    /// A result set of `rows` under `names`, keeping those `filter` matches: by the
+    /// first column for `LIKE`, by any for `WHERE`
+    fn rows(&self, names: &[&str], rows: Vec<Vec<Json>>, filter: Option<&ShowStatementFilter>) -> Result<Reply, MysqlError> {
+        let mut kept = Vec::new();
+        for row in rows {
+            let keep = match filter {
+                None => true,
+                Some(ShowStatementFilter::Like(pattern) | ShowStatementFilter::ILike(pattern)) => like(&text(&row[0]), pattern),
+                Some(ShowStatementFilter::Where(expr)) => {
+                    let column = |name: &str| names.iter().position(|n| n.eq_ignore_ascii_case(name)).map(|i| row[i].clone());
+                    truthy(&self.eval(expr, &column)?)
+                }
+            };
+            if keep {
+                kept.push(row);
+            }
+        }
+        let columns: Vec<Column> = names.iter().enumerate().map(|(i, n)| results::column(n, results::common_type(kept.iter().map(|r| &r[i])))).collect();
+        let rows = kept.iter().map(|row| columns.iter().zip(row).map(|(c, v)| results::text_value(c.column_type, Some(v))).collect()).collect();
+        Ok(Reply::Rows(ResultSet { columns, rows }))
+    }
+
+    /// Value of `expr`, with `column` giving the value of a bare name
+    fn eval(&self, expr: &Expr, column: &dyn Fn(&str) -> Option<Json>) -> Result<Json, MysqlError> {
+        Ok(match expr {
+            Expr::Value(value) => match value {
+                Value::Number(n, _) => serde_json::from_str(n).unwrap_or_else(|_| json!(n)),
+                Value::SingleQuotedString(s) | Value::DoubleQuotedString(s) => json!(s),
+                Value::Boolean(b) => json!(*b as i64),
+                Value::Null => Json::Null,
+                _ => return Err(MysqlError::unsupported(expr)),
+            },
+            Expr::Identifier(ident) if ident.value.starts_with('@') => self.variable_value(std::slice::from_ref(ident))?,
+            Expr::CompoundIdentifier(idents) if idents[0].value.starts_with('@') => self.variable_value(idents)?,
+            Expr::Identifier(ident) => column(&ident.value).ok_or_else(|| unknown_column(&ident.value))?,
+            Expr::CompoundIdentifier(idents) => return Err(unknown_column(&ObjectName(idents.clone()).to_string())),
+            Expr::Nested(inner) => self.eval(inner, column)?,
+            Expr::UnaryOp { op, expr: inner } => {
+                let v = self.eval(inner, column)?;
+                match op {
+                    UnaryOperator::Plus => v,
+                    UnaryOperator::Minus => match v.as_i64() {
+                        Some(i) => json!(-i),
+                        None => v.as_f64().map_or(Json::Null, |f| json!(-f)),
+                    },
+                    UnaryOperator::Not => json!(!truthy(&v) as i64),
+                    _ => return Err(MysqlError::unsupported(expr)),
+                }
+            }
+            Expr::BinaryOp { left, op, right } => {
+                let (l, r) = (self.eval(left, column)?, self.eval(right, column)?);
+                match op {
+                    BinaryOperator::And => json!((truthy(&l) && truthy(&r)) as i64),
+                    BinaryOperator::Or => json!((truthy(&l) || truthy(&r)) as i64),
+                    BinaryOperator::Eq => equal(&l, &r).map_or(Json::Null, |eq| json!(eq as i64)),
+                    BinaryOperator::NotEq => equal(&l, &r).map_or(Json::Null, |eq| json!(!eq as i64)),
+                    _ => return Err(MysqlError::unsupported(expr)),
+                }
+            }
+            Expr::Like { negated, expr: inner, pattern, .. } | Expr::ILike { negated, expr: inner, pattern, .. } => {
+                let (v, p) = (self.eval(inner, column)?, self.eval(pattern, column)?);
+                if v.is_null() || p.is_null() {
+                    return Ok(Json::Null);
+                }
+                json!((like(&text(&v), &text(&p)) != *negated) as i64)
+            }
+            Expr::InList { expr: inner, list, negated } => {
+                let v = self.eval(inner, column)?;
+                let mut found = false;
+                for item in list {
+                    found |= equal(&v, &self.eval(item, column)?) == Some(true);
+                }
+                json!((found != *negated) as i64)
+            }
+            Expr::IsNull(inner) => json!(self.eval(inner, column)?.is_null() as i64),
+            Expr::IsNotNull(inner) => json!(!self.eval(inner, column)?.is_null() as i64),
+            Expr::Function(f) => {
+                if !matches!(&f.args, FunctionArguments::None) && !matches!(&f.args, FunctionArguments::List(l) if l.args.is_empty()) {
+                    return Err(MysqlError::unsupported(expr));
+                }
+                let name = f.name.0.last().map(|i| i.value.to_ascii_lowercase()).unwrap_or_default();
+                match name.as_str() {
+                    "version" => json!(crate::SERVER_VERSION),
+                    "database" | "schema" => self.database.as_ref().map_or(Json::Null, |d| json!(d)),
+                    "user" | "current_user" | "session_user" | "system_user" => json!(format!("{}@%", self.user)),
+                    "connection_id" => json!(self.connection_id),
+                    "last_insert_id" => json!(0),
+                    _ => return Err(MysqlError::new(1305, "42000", format!("FUNCTION {} does not exist", f.name))),
+                }
+            }
+            _ => return Err(MysqlError::unsupported(expr)),
+        })
+    }
+
+    /// Value of `@name` or `@@[session.]name`
+    fn variable_value(&self, idents: &[Ident]) -> Result<Json, MysqlError> {
+        let display = ObjectName(idents.to_vec()).to_string();
+        match variable_name(idents) {
+            Some((name, true)) => self.variable(&name).ok_or_else(|| unknown_variable(&name)),
+            Some((name, false)) => Ok(self.user_variables.get(&name).cloned().unwrap_or(Json::Null)),
+            None => Err(unknown_variable(&display)),
+        }
+    }
+}
+
+/// Whether `stmt` is answered by `Session::answer` rather than run by `tonledb_sql`
+pub fn is_session_statement(stmt: &Statement) -> bool {
+    match stmt {
+        Statement::SetVariable { .. }
+        | Statement::SetNames { .. }
+        | Statement::SetNamesDefault {}
+        | Statement::SetTransaction { .. }
+        | Statement::Use { .. }
+        | Statement::StartTransaction { .. }
+        | Statement::Commit { .. }
+        | Statement::Rollback { .. }
+        | Statement::ShowVariables { .. }
+        | Statement::ShowStatus { .. }
+        | Statement::ShowTables { .. }
+        | Statement::ShowColumns { .. }
+        | Statement::ShowCollation { .. }
+        | Statement::ShowVariable { .. }
+        | Statement::ExplainTable { .. } => true,
+        Statement::Query(query) => match &*query.body {
+            SetExpr::Select(select) => match select.from.as_slice() {
+                [] => true,
+                [from] => from.joins.is_empty() && matches!(&from.relation, TableFactor::Table { name, .. } if name.to_string().eq_ignore_ascii_case("dual")),
+                _ => false,
+            },
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Lower-case name of the variable `idents` names, and whether it is a system
+/// variable: `@@name`, `@@session.name` (or `global`, `local`) and a bare `name`
+/// are, `@name` is a user variable
+fn variable_name(idents: &[Ident]) -> Option<(String, bool)> {
+    let lower = |i: &Ident| i.value.to_ascii_lowercase();
+    match idents {
+        [one] => match one.value.strip_prefix("@@") {
+            Some(name) => Some((name.to_ascii_lowercase(), true)),
+            None => match one.value.strip_prefix('@') {
+                Some(name) => Some((name.to_ascii_lowercase(), false)),
+                None => Some((lower(one), true)),
+            },
+        },
+        [scope, name] if ["@@session", "@@global", "@@local"].contains(&lower(scope).as_str()) => Some((lower(name), true)),
+        _ => None,
+    }
+}
+
+fn unknown_variable(name: &str) -> MysqlError {
+    MysqlError::new(1193, "HY000", format!("Unknown system variable '{}'", name))
+}
+
+fn unknown_column(name: &str) -> MysqlError {
+    MysqlError::new(1054, "42S22", format!("Unknown column '{}' in 'field list'", name))
+}
+
+/// Text form of a value, as comparisons and patterns see it
+fn text(v: &Json) -> String {
+    match v {
+        Json::String(s) => s.clone(),
+        Json::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+/// MySQL truth: non-zero numbers, strings holding one, and `ON`
+fn truthy(v: &Json) -> bool {
+    match v {
+        Json::Number(n) => n.as_f64().is_some_and(|f| f != 0.0),
+        Json::Bool(b) => *b,
+        Json::String(s) => s.trim().parse::<f64>().is_ok_and(|f| f != 0.0) || s.eq_ignore_ascii_case("on"),
+        _ => false,
+    }
+}
+
+/// `l = r`, numbers by value and text case-insensitively as the default collation
+/// compares it; `None` if either is NULL
+fn equal(l: &Json, r: &Json) -> Option<bool> {
+    match (l, r) {
+        (Json::Null, _) | (_, Json::Null) => None,
+        (Json::Number(a), Json::Number(b)) => Some(a.as_f64() == b.as_f64()),
+        _ => Some(text(l).to_lowercase() == text(r).to_lowercase()),
+    }
+}
+
+/// Whether `s` matches the LIKE `pattern` (`%`, `_` and `\` escapes),
+/// case-insensitively
+pub(crate) fn like(s: &str, pattern: &str) -> bool {
+    let s: Vec<char> = s.to_lowercase().chars().collect();
+    let p: Vec<char> = pattern.to_lowercase().chars().collect();
+    // Where the last `%` was seen in the pattern, and how much of `s` it has taken
+    let (mut si, mut pi, mut star) = (0, 0, None);
+    while si < s.len() {
+        match p.get(pi) {
+            Some('%') => {
+                star = Some((pi, si));
+                pi += 1;
+            }
+            Some('\\') if p.get(pi + 1) == Some(&s[si]) => {
+                si += 1;
+                pi += 2;
+            }
+            Some(c) if *c == '_' || *c == s[si] => {
+                si += 1;
+                pi += 1;
+            }
+            _ => match star {
+                Some((star_pi, star_si)) => {
+                    star = Some((star_pi, star_si + 1));
+                    pi = star_pi + 1;
+                    si = star_si + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    p[pi..].iter().all(|c| *c == '%')
+}
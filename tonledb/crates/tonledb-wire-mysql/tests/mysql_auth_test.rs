@@ -0,0 +1,175 @@
+//! Tests for password authentication: mysql_native_password, cleartext over TLS,
+//! the switch between plugins and what users may run
+
+use std::sync::Arc;
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream};
+use tokio_rustls::rustls::{self, Certificate, PrivateKey, RootCertStore, ServerName};
+use tokio_rustls::TlsConnector;
+use tonledb_core::Db;
+use tonledb_storage::InMemoryStore;
+use tonledb_wire_mysql::auth::{self, Access, PasswordStore};
+use tonledb_wire_mysql::{handle_mysql_connection, MysqlOptions};
+
+/// Every user's password is `secret`; `native` and `reader` have its hash
+/// stored, and `reader` may only read
+struct Users;
+
+impl PasswordStore for Users {
+    fn verify(&self, _user: &str, password: &str) -> bool {
+        password == "secret"
+    }
+
+    fn access(&self, user: &str) -> Access {
+        if user == "reader" { Access::Read } else { Access::Write }
+    }
+
+    fn native_password(&self, user: &str) -> Option<String> {
+        (user == "native" || user == "reader").then(|| auth::native_password_hash("secret"))
+    }
+}
+
+async fn read_packet<S: AsyncRead + Unpin>(client: &mut S) -> (u8, Vec<u8>) {
+    let mut header = [0u8; 4];
+    client.read_exact(&mut header).await.unwrap();
+    let mut payload = vec![0; u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize];
+    client.read_exact(&mut payload).await.unwrap();
+    (header[3], payload)
+}
+
+async fn write_packet<S: AsyncWrite + Unpin>(client: &mut S, seq: u8, payload: &[u8]) {
+    client.write_all(&(payload.len() as u32).to_le_bytes()[..3]).await.unwrap();
+    client.write_all(&[seq]).await.unwrap();
+    client.write_all(payload).await.unwrap();
+    client.flush().await.unwrap();
+}
+
+/// What mysql_native_password sends: SHA1(password) XOR SHA1(scramble + SHA1(SHA1(password)))
+fn scramble_password(password: &str, scramble: &[u8]) -> Vec<u8> {
+    let stage1 = Sha1::digest(password.as_bytes());
+    let mask = Sha1::new().chain_update(scramble).chain_update(Sha1::digest(stage1)).finalize();
+    stage1.iter().zip(mask.iter()).map(|(a, b)| a ^ b).collect()
+}
+
+/// CLIENT_PROTOCOL_41 | CLIENT_SECURE_CONNECTION | CLIENT_PLUGIN_AUTH
+const CAPABILITIES: u32 = 0x200 | 0x8000 | 0x80000;
+const CLIENT_SSL: u32 = 0x800;
+
+/// Connect and read the handshake; the client, the scramble and the capabilities offered
+async fn connect(options: MysqlOptions) -> (DuplexStream, Vec<u8>, u32) {
+    let (mut client, server) = tokio::io::duplex(1 << 16);
+    tokio::spawn(handle_mysql_connection(server, Arc::new(Db::new(Arc::new(InMemoryStore::new(1000)))), Arc::new(options)));
+    let (_, handshake) = read_packet(&mut client).await;
+    let version_end = handshake.iter().position(|b| *b == 0).unwrap();
+    let rest = &handshake[version_end + 1..];
+    let scramble = [&rest[4..12], &rest[31..43]].concat();
+    assert_eq!(&rest[44..rest.len() - 1], b"mysql_native_password");
+    let capabilities = u16::from_le_bytes([rest[13], rest[14]]) as u32 | (u16::from_le_bytes([rest[18], rest[19]]) as u32) << 16;
+    (client, scramble, capabilities)
+}
+
+/// Answer the handshake as `user` with `plugin`, as packet `seq`
+async fn respond<S: AsyncWrite + Unpin>(client: &mut S, seq: u8, scramble: &[u8], user: &str, plugin: &str, password: Option<&str>) {
+    let mut response = CAPABILITIES.to_le_bytes().to_vec();
+    response.extend_from_slice(&(1u32 << 24).to_le_bytes());
+    response.push(45);
+    response.extend_from_slice(&[0; 23]);
+    response.extend_from_slice(format!("{}\0", user).as_bytes());
+    let auth = password.map(|p| scramble_password(p, scramble)).unwrap_or_default();
+    response.push(auth.len() as u8);
+    response.extend_from_slice(&auth);
+    response.extend_from_slice(format!("{}\0", plugin).as_bytes());
+    write_packet(client, seq, &response).await;
+}
+
+/// Connect, read the handshake and answer it as `user` with `plugin`; the
+/// scramble from the handshake
+async fn start(user: &str, plugin: &str, password: Option<&str>) -> (DuplexStream, Vec<u8>) {
+    let (mut client, scramble, _) = connect(MysqlOptions { passwords: Some(Arc::new(Users)), ..Default::default() }).await;
+    respond(&mut client, 1, &scramble, user, plugin, password).await;
+    (client, scramble)
+}
+
+/// A server config with a fresh certificate for `localhost`, and a client config
+/// that trusts it
+fn tls_configs() -> (rustls::ServerConfig, rustls::ClientConfig) {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+    let der = cert.serialize_der().unwrap();
+    let server = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(vec![Certificate(der.clone())], PrivateKey(cert.serialize_private_key_der()))
+        .unwrap();
+    let mut roots = RootCertStore::empty();
+    roots.add(&Certificate(der)).unwrap();
+    (server, rustls::ClientConfig::builder().with_safe_defaults().with_root_certificates(roots).with_no_client_auth())
+}
+
+#[tokio::test]
+async fn test_native_password() {
+    let (mut client, _) = start("native", "mysql_native_password", Some("secret")).await;
+    assert_eq!(read_packet(&mut client).await, (2, vec![0, 0, 0, 2, 0, 0, 0]));
+
+    let (mut client, _) = start("native", "mysql_native_password", Some("wrong")).await;
+    let (seq, err) = read_packet(&mut client).await;
+    assert_eq!((seq, err[0], u16::from_le_bytes([err[1], err[2]])), (2, 0xff, 1045));
+    assert_eq!(&err[3..9], b"#28000");
+}
+
+#[tokio::test]
+async fn test_switch_to_native_password() {
+    // MySQL 8 clients answer with caching_sha2_password unless told otherwise
+    let (mut client, scramble) = start("native", "caching_sha2_password", None).await;
+    let (seq, switch) = read_packet(&mut client).await;
+    assert_eq!((seq, switch[0]), (2, 0xfe));
+    assert_eq!(switch[1..], [&b"mysql_native_password\0"[..], &scramble[..], &[0u8][..]].concat()[..]);
+    write_packet(&mut client, 3, &scramble_password("secret", &scramble)).await;
+    assert_eq!(read_packet(&mut client).await, (4, vec![0, 0, 0, 2, 0, 0, 0]));
+}
+
+#[tokio::test]
+async fn test_cleartext_password_needs_tls() {
+    // Over a plain connection the password isn't asked for
+    let (mut client, _) = start("plain", "mysql_native_password", Some("secret")).await;
+    let (seq, err) = read_packet(&mut client).await;
+    assert_eq!((seq, err[0], u16::from_le_bytes([err[1], err[2]])), (2, 0xff, 1045));
+
+    let (server_config, client_config) = tls_configs();
+    for (password, accepted) in [("secret", true), ("wrong", false)] {
+        let options = MysqlOptions { passwords: Some(Arc::new(Users)), tls: Some(Arc::new(server_config.clone())) };
+        let (mut client, scramble, capabilities) = connect(options).await;
+        assert_ne!(capabilities & CLIENT_SSL, 0);
+        let mut ssl_request = (CAPABILITIES | CLIENT_SSL).to_le_bytes().to_vec();
+        ssl_request.extend_from_slice(&(1u32 << 24).to_le_bytes());
+        ssl_request.push(45);
+        ssl_request.extend_from_slice(&[0; 23]);
+        write_packet(&mut client, 1, &ssl_request).await;
+        let connector = TlsConnector::from(Arc::new(client_config.clone()));
+        let mut tls = connector.connect(ServerName::try_from("localhost").unwrap(), client).await.unwrap();
+        respond(&mut tls, 2, &scramble, "plain", "mysql_native_password", Some(password)).await;
+        let (seq, switch) = read_packet(&mut tls).await;
+        assert_eq!((seq, &switch[..]), (3, &b"\xfemysql_clear_password\0"[..]));
+        write_packet(&mut tls, 4, format!("{}\0", password).as_bytes()).await;
+        let (seq, reply) = read_packet(&mut tls).await;
+        assert_eq!((seq, reply[0] == 0), (5, accepted));
+    }
+}
+
+#[tokio::test]
+async fn test_sql_needs_write_access() {
+    let (mut client, _) = start("reader", "mysql_native_password", Some("secret")).await;
+    assert_eq!(read_packet(&mut client).await.1[0], 0);
+    write_packet(&mut client, 0, b"\x03SELECT id FROM orders").await;
+    let (_, err) = read_packet(&mut client).await;
+    assert_eq!((err[0], u16::from_le_bytes([err[1], err[2]])), (0xff, 1142));
+    // What connectors send on connect is still answered
+    write_packet(&mut client, 0, b"\x03SET NAMES utf8mb4").await;
+    assert_eq!(read_packet(&mut client).await.1[0], 0);
+}
+
+#[tokio::test]
+async fn test_long_packet_before_login_closes_the_connection() {
+    let (mut client, _, _) = connect(MysqlOptions { passwords: Some(Arc::new(Users)), ..Default::default() }).await;
+    client.write_all(&[0, 0, 2, 1]).await.unwrap();
+    assert_eq!(client.read(&mut [0; 1]).await.unwrap(), 0);
+}
@@ -0,0 +1,206 @@
+//! Tests for the MySQL wire protocol: the handshake, text result sets, the
+//! statements connectors send on connect, and errors
+
+use std::sync::Arc;
+use serde_json::json;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tonledb_core::{DataType, Db};
+use tonledb_sql::fixture;
+use tonledb_storage::InMemoryStore;
+use tonledb_wire_mysql::handle_mysql_connection;
+
+fn orders_db() -> Arc<Db> {
+    let db = Db::new(Arc::new(InMemoryStore::new(1000)));
+    fixture::create_table(&db, "orders", &[("id", DataType::Integer), ("customer", DataType::Text), ("paid", DataType::Boolean)], Some("id"));
+    fixture::insert_rows(&db, "orders", &[json!({"id": 1, "customer": "ann", "paid": true}), json!({"id": 2, "paid": false})]).unwrap();
+    Arc::new(db)
+}
+
+async fn read_packet(client: &mut DuplexStream) -> (u8, Vec<u8>) {
+    let mut header = [0u8; 4];
+    client.read_exact(&mut header).await.unwrap();
+    let mut payload = vec![0; u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize];
+    client.read_exact(&mut payload).await.unwrap();
+    (header[3], payload)
+}
+
+async fn write_packet(client: &mut DuplexStream, seq: u8, payload: &[u8]) {
+    client.write_all(&(payload.len() as u32).to_le_bytes()[..3]).await.unwrap();
+    client.write_all(&[seq]).await.unwrap();
+    client.write_all(payload).await.unwrap();
+}
+
+/// A client logged in as `ann`, with no password asked for
+async fn connect(db: Arc<Db>) -> DuplexStream {
+    let (mut client, server) = tokio::io::duplex(1 << 16);
+    tokio::spawn(handle_mysql_connection(server, db, Default::default()));
+    let (seq, handshake) = read_packet(&mut client).await;
+    assert_eq!((seq, handshake[0]), (0, 10));
+    assert!(handshake[1..].starts_with(b"8.0."));
+    // CLIENT_PROTOCOL_41 | CLIENT_SECURE_CONNECTION | CLIENT_PLUGIN_AUTH
+    let mut response = (0x200u32 | 0x8000 | 0x80000).to_le_bytes().to_vec();
+    response.extend_from_slice(&(1u32 << 24).to_le_bytes());
+    response.push(45);
+    response.extend_from_slice(&[0; 23]);
+    response.extend_from_slice(b"ann\0\0mysql_native_password\0");
+    write_packet(&mut client, 1, &response).await;
+    let (seq, ok) = read_packet(&mut client).await;
+    assert_eq!((seq, ok[0]), (2, 0x00));
+    client
+}
+
+#[derive(Debug, PartialEq)]
+enum Reply {
+    /// Affected rows and status flags
+    Ok(u64, u16),
+    /// Error code and message
+    Err(u16, String),
+    /// Columns (name and type) and rows
+    Rows(Vec<(String, u8)>, Vec<Vec<Option<String>>>),
+}
+
+fn lenenc_str(buf: &mut &[u8]) -> Option<String> {
+    let (len, rest) = match buf[0] {
+        0xfb => {
+            *buf = &buf[1..];
+            return None;
+        }
+        0xfc => (u16::from_le_bytes([buf[1], buf[2]]) as usize, &buf[3..]),
+        n => (n as usize, &buf[1..]),
+    };
+    let s = String::from_utf8(rest[..len].to_vec()).unwrap();
+    *buf = &rest[len..];
+    Some(s)
+}
+
+async fn reply(client: &mut DuplexStream) -> Reply {
+    let (_, first) = read_packet(client).await;
+    match first[0] {
+        0x00 => Reply::Ok(first[1] as u64, u16::from_le_bytes([first[3], first[4]])),
+        0xff => Reply::Err(u16::from_le_bytes([first[1], first[2]]), String::from_utf8(first[9..].to_vec()).unwrap()),
+        count => {
+            let mut columns = Vec::new();
+            for _ in 0..count {
+                let (_, def) = read_packet(client).await;
+                let mut rest = def.as_slice();
+                let fields: Vec<_> = (0..6).map(|_| lenenc_str(&mut rest)).collect();
+                columns.push((fields[4].clone().unwrap(), rest[7]));
+            }
+            assert_eq!(read_packet(client).await.1[0], 0xfe);
+            let mut rows = Vec::new();
+            loop {
+                let (_, row) = read_packet(client).await;
+                if row[0] == 0xfe && row.len() < 9 {
+                    return Reply::Rows(columns, rows);
+                }
+                let mut rest = row.as_slice();
+                rows.push((0..count).map(|_| lenenc_str(&mut rest)).collect());
+            }
+        }
+    }
+}
+
+async fn command(client: &mut DuplexStream, command: u8, body: &[u8]) -> Reply {
+    write_packet(client, 0, &[&[command][..], body].concat()).await;
+    reply(client).await
+}
+
+async fn query(client: &mut DuplexStream, sql: &str) -> Reply {
+    command(client, 0x03, sql.as_bytes()).await
+}
+
+fn cells(row: &[&str]) -> Vec<Option<String>> {
+    row.iter().map(|c| Some(c.to_string())).collect()
+}
+
+#[tokio::test]
+async fn test_select_sends_columns_and_rows() {
+    let mut client = connect(orders_db()).await;
+    let reply = query(&mut client, "SELECT * FROM orders").await;
+    let expected = vec![
+        vec![Some("1".into()), Some("ann".into()), Some("1".into())],
+        vec![Some("2".into()), None, Some("0".into())],
+    ];
+    assert_eq!(reply, Reply::Rows(vec![("id".into(), 0x08), ("customer".into(), 0xfd), ("paid".into(), 0x01)], expected));
+
+    // Backquoted names and double-quoted strings, as MySQL reads them
+    let reply = query(&mut client, "SELECT `id` FROM `orders` WHERE customer = \"ann\"").await;
+    assert_eq!(reply, Reply::Rows(vec![("id".into(), 0x08)], vec![cells(&["1"])]));
+    // An empty result still describes its columns
+    let reply = query(&mut client, "SELECT customer FROM orders WHERE id = 9").await;
+    assert_eq!(reply, Reply::Rows(vec![("customer".into(), 0xfd)], vec![]));
+}
+
+#[tokio::test]
+async fn test_connector_statements() {
+    let mut client = connect(orders_db()).await;
+    assert_eq!(query(&mut client, "SET NAMES utf8mb4").await, Reply::Ok(0, 2));
+    let reply = query(&mut client, "SELECT @@version_comment LIMIT 1").await;
+    assert_eq!(reply, Reply::Rows(vec![("@@version_comment".into(), 0xfd)], vec![cells(&["TonleDB"])]));
+    let reply = query(&mut client, "/* connector */ SELECT @@session.auto_increment_increment AS auto_increment_increment, @@max_allowed_packet").await;
+    assert_eq!(reply, Reply::Rows(vec![("auto_increment_increment".into(), 0x08), ("@@max_allowed_packet".into(), 0x08)], vec![cells(&["1", "67108864"])]));
+
+    // Variables and transactions are kept per connection
+    assert_eq!(query(&mut client, "SET autocommit = 0").await, Reply::Ok(0, 0));
+    assert_eq!(query(&mut client, "BEGIN").await, Reply::Ok(0, 1));
+    let Reply::Rows(_, rows) = query(&mut client, "SHOW WARNINGS").await else { panic!() };
+    assert_eq!(rows[0][1].as_deref(), Some("1105"));
+    assert_eq!(query(&mut client, "COMMIT").await, Reply::Ok(0, 0));
+    // Nothing is undone, and the warning says so until the next statement
+    assert_eq!(query(&mut client, "ROLLBACK").await, Reply::Ok(0, 0));
+    let Reply::Rows(_, rows) = query(&mut client, "SHOW WARNINGS").await else { panic!() };
+    assert_eq!(rows, vec![cells(&["Warning", "1196", "Some non-transactional changed tables couldn't be rolled back"])]);
+    assert_eq!(query(&mut client, "SET SESSION TRANSACTION ISOLATION LEVEL READ COMMITTED").await, Reply::Ok(0, 0));
+    assert!(matches!(query(&mut client, "SHOW WARNINGS").await, Reply::Rows(_, rows) if rows.is_empty()));
+    query(&mut client, "SET @limit = 'ten'").await;
+    query(&mut client, "USE shop").await;
+    let reply = query(&mut client, "SELECT @@autocommit, @limit, @unset, DATABASE()").await;
+    let Reply::Rows(_, rows) = reply else { panic!("{:?}", reply) };
+    assert_eq!(rows, vec![vec![Some("0".into()), Some("ten".into()), None, Some("shop".into())]]);
+    assert!(matches!(query(&mut client, "SET @@no_such_variable = 1").await, Reply::Err(1193, _)));
+
+    let reply = query(&mut client, "SHOW VARIABLES WHERE Variable_name = 'sql_mode' OR Variable_name LIKE 'version_c%'").await;
+    let Reply::Rows(_, rows) = reply else { panic!("{:?}", reply) };
+    assert_eq!(rows.iter().map(|r| r[0].clone().unwrap()).collect::<Vec<_>>(), ["sql_mode", "version_comment"]);
+}
+
+#[tokio::test]
+async fn test_show_tables_and_describe() {
+    let mut client = connect(orders_db()).await;
+    let reply = query(&mut client, "SHOW FULL TABLES LIKE 'ord%'").await;
+    assert_eq!(reply, Reply::Rows(vec![("Tables_in_tonledb".into(), 0xfd), ("Table_type".into(), 0xfd)], vec![cells(&["orders", "BASE TABLE"])]));
+    let Reply::Rows(columns, rows) = query(&mut client, "DESCRIBE orders").await else { panic!() };
+    assert_eq!(columns[0].0, "Field");
+    assert_eq!(rows[0][..4], cells(&["id", "bigint", "NO", "PRI"])[..]);
+    assert_eq!(rows[2][..4], cells(&["paid", "tinyint(1)", "YES", ""])[..]);
+    assert!(matches!(query(&mut client, "SHOW COLUMNS FROM missing").await, Reply::Err(1146, _)));
+
+    // COM_FIELD_LIST sends the definitions alone
+    write_packet(&mut client, 0, b"\x04orders\0").await;
+    for name in ["id", "customer", "paid"] {
+        let (_, def) = read_packet(&mut client).await;
+        let mut rest = def.as_slice();
+        assert_eq!((0..5).map(|_| lenenc_str(&mut rest)).last().unwrap().as_deref(), Some(name));
+    }
+    assert_eq!(read_packet(&mut client).await.1[0], 0xfe);
+}
+
+#[tokio::test]
+async fn test_errors_and_other_commands() {
+    let mut client = connect(orders_db()).await;
+    let Reply::Err(code, message) = query(&mut client, "SELEC 1").await else { panic!() };
+    assert_eq!(code, 1064);
+    assert!(message.contains("SELEC"));
+    assert!(matches!(query(&mut client, "DELETE FROM orders").await, Reply::Err(1064, _)));
+    assert!(matches!(query(&mut client, "SELECT 1; SELECT 2").await, Reply::Err(1235, _)));
+    assert!(matches!(query(&mut client, "SELECT nope()").await, Reply::Err(1305, _)));
+
+    assert_eq!(command(&mut client, 0x0e, b"").await, Reply::Ok(0, 2));
+    assert_eq!(command(&mut client, 0x02, b"shop").await, Reply::Ok(0, 2));
+    assert!(matches!(command(&mut client, 0x16, b"SELECT ?").await, Reply::Err(1295, _)));
+    assert!(matches!(command(&mut client, 0x55, b"").await, Reply::Err(1047, _)));
+    // The connection is still usable, and COM_QUIT closes it
+    assert_eq!(query(&mut client, "SELECT 1 + 0 AS one").await, Reply::Err(1235, "This version of TonleDB doesn't yet support '1 + 0'".into()));
+    write_packet(&mut client, 0, &[0x01]).await;
+    assert_eq!(client.read(&mut [0; 1]).await.unwrap(), 0);
+}
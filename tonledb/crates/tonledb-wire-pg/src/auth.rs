@@ -0,0 +1,233 @@
+//! Password authentication at startup.
+//!
+//! The method is chosen per user from what the `PasswordStore` keeps for them, as
+//! Postgres does: SCRAM-SHA-256 for a stored `SCRAM-SHA-256$…` verifier, MD5 for a
+//! stored `md5…` hash (both in the form `pg_authid.rolpassword` holds), and
+//! otherwise a cleartext password checked with `PasswordStore::verify`. Cleartext
+//! is only as safe as the connection it crosses.
+
+use base64::{engine::general_purpose::STANDARD as B64, Engine as _};
+use hmac::{Hmac, Mac};
+use md5::Md5;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use crate::protocol::{self, FrontendMessage};
+
+const SCRAM_SHA_256: &str = "SCRAM-SHA-256";
+/// Iterations of a verifier made with `scram_verifier`, Postgres' default
+pub const SCRAM_ITERATIONS: u32 = 4096;
+
+/// Where the server checks passwords
+pub trait PasswordStore: Send + Sync {
+    /// Whether `password` is the password of `user`
+    fn verify(&self, user: &str, password: &str) -> bool;
+
+    /// What `user` may do once logged in
+    fn access(&self, user: &str) -> Access;
+
+    /// The Postgres password stored for `user` (`SCRAM-SHA-256$…` or `md5…`), if any
+    fn stored_password(&self, _user: &str) -> Option<String> {
+        None
+    }
+}
+
+/// What a logged-in user may do, least first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Access {
+    Read,
+    Write,
+    Admin,
+}
+
+/// Ask `user` for a password and check it, sending AuthenticationOk or an error;
+/// `false` if the client failed or went away
+pub(crate) async fn authenticate<S>(stream: &mut S, user: &str, store: &dyn PasswordStore) -> anyhow::Result<bool>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let stored = store.stored_password(user);
+    let accepted = match stored.as_deref() {
+        Some(s) if s.starts_with(SCRAM_SHA_256) => match ScramVerifier::parse(s) {
+            Some(verifier) => scram(stream, &verifier).await?,
+            None => anyhow::bail!("malformed SCRAM verifier stored for {}", user),
+        },
+        Some(s) if s.starts_with("md5") => {
+            let mut salt = [0u8; 4];
+            rand::thread_rng().fill_bytes(&mut salt);
+            let mut buf = Vec::new();
+            protocol::authentication_md5_password(&mut buf, salt);
+            send(stream, &buf).await?;
+            let expected = format!("md5{}", hex(&Md5::digest([&s.as_bytes()[3..], &salt].concat())));
+            password(stream).await?.is_some_and(|p| cstr(&p) == expected.as_bytes())
+        }
+        _ => {
+            let mut buf = Vec::new();
+            protocol::authentication_cleartext_password(&mut buf);
+            send(stream, &buf).await?;
+            match password(stream).await? {
+                Some(p) => store.verify(user, &String::from_utf8_lossy(cstr(&p))),
+                None => false,
+            }
+        }
+    };
+    let mut buf = Vec::new();
+    if accepted {
+        protocol::authentication_ok(&mut buf);
+    } else {
+        protocol::error_response(&mut buf, "28P01", &format!("password authentication failed for user \"{}\"", user));
+    }
+    send(stream, &buf).await?;
+    Ok(accepted)
+}
+
+async fn send<S: AsyncWrite + Unpin>(stream: &mut S, buf: &[u8]) -> anyhow::Result<()> {
+    stream.write_all(buf).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Body of the next PasswordMessage (or SASL response); `None` for anything else
+async fn password<S: AsyncRead + Unpin>(stream: &mut S) -> anyhow::Result<Option<Vec<u8>>> {
+    Ok(match protocol::read_auth_message(stream).await? {
+        Some(FrontendMessage::Password(body)) => Some(body),
+        _ => None,
+    })
+}
+
+/// A PasswordMessage body without its NUL terminator
+fn cstr(body: &[u8]) -> &[u8] {
+    body.strip_suffix(&[0]).unwrap_or(body)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes any key length");
+    mac.update(data);
+    bytes32(&mac.finalize().into_bytes())
+}
+
+fn bytes32(digest: &[u8]) -> [u8; 32] {
+    let mut out = [0u8; 32];
+    out.copy_from_slice(digest);
+    out
+}
+
+/// A SCRAM-SHA-256 verifier: what the server keeps instead of the password
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScramVerifier {
+    pub iterations: u32,
+    pub salt: Vec<u8>,
+    pub stored_key: [u8; 32],
+    pub server_key: [u8; 32],
+}
+
+impl ScramVerifier {
+    /// Parse `SCRAM-SHA-256$<iterations>:<salt>$<StoredKey>:<ServerKey>`
+    pub fn parse(s: &str) -> Option<Self> {
+        let rest = s.strip_prefix("SCRAM-SHA-256$")?;
+        let (params, keys) = rest.split_once('$')?;
+        let (iterations, salt) = params.split_once(':')?;
+        let (stored_key, server_key) = keys.split_once(':')?;
+        Some(Self {
+            iterations: iterations.parse().ok()?,
+            salt: B64.decode(salt).ok()?,
+            stored_key: B64.decode(stored_key).ok()?.try_into().ok()?,
+            server_key: B64.decode(server_key).ok()?.try_into().ok()?,
+        })
+    }
+}
+
+impl std::fmt::Display for ScramVerifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}${}:{}${}:{}", SCRAM_SHA_256, self.iterations, B64.encode(&self.salt), B64.encode(self.stored_key), B64.encode(self.server_key))
+    }
+}
+
+/// PBKDF2-HMAC-SHA-256 of `password`, the `Hi` of RFC 5802
+pub fn salted_password(password: &str, salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mut u = hmac(password.as_bytes(), &[salt, &1u32.to_be_bytes()].concat());
+    let mut out = u;
+    for _ in 1..iterations {
+        u = hmac(password.as_bytes(), &u);
+        out.iter_mut().zip(&u).for_each(|(o, b)| *o ^= b);
+    }
+    out
+}
+
+/// The verifier of `password` with a fresh salt, for a token file's `pg_password`
+pub fn scram_verifier(password: &str) -> ScramVerifier {
+    let mut salt = vec![0u8; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let salted = salted_password(password, &salt, SCRAM_ITERATIONS);
+    let client_key = hmac(&salted, b"Client Key");
+    ScramVerifier {
+        iterations: SCRAM_ITERATIONS,
+        salt,
+        stored_key: bytes32(&Sha256::digest(client_key)),
+        server_key: hmac(&salted, b"Server Key"),
+    }
+}
+
+/// `key=value` attribute `name` of a SCRAM message
+fn attribute(message: &str, name: char) -> Option<&str> {
+    message.split(',').find_map(|a| a.strip_prefix(name)?.strip_prefix('='))
+}
+
+/// SCRAM-SHA-256 exchange (RFC 5802, 7677) without channel binding
+async fn scram<S>(stream: &mut S, verifier: &ScramVerifier) -> anyhow::Result<bool>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut buf = Vec::new();
+    protocol::authentication_sasl(&mut buf, &[SCRAM_SHA_256]);
+    send(stream, &buf).await?;
+
+    // SASLInitialResponse: mechanism, then the client-first-message
+    let Some(initial) = password(stream).await? else { return Ok(false) };
+    let Some(mechanism_end) = initial.iter().position(|b| *b == 0) else { return Ok(false) };
+    if initial[..mechanism_end] != *SCRAM_SHA_256.as_bytes() || initial.len() < mechanism_end + 5 {
+        return Ok(false);
+    }
+    let client_first = String::from_utf8_lossy(&initial[mechanism_end + 5..]).into_owned();
+    // gs2 header `n,,` or `y,,`: the client doesn't bind to the channel
+    let mut parts = client_first.splitn(3, ',');
+    let (Some(cbind @ ("n" | "y")), Some(_authzid), Some(client_first_bare)) = (parts.next(), parts.next(), parts.next()) else { return Ok(false) };
+    let gs2_header = format!("{},,", cbind);
+    let Some(client_nonce) = attribute(client_first_bare, 'r') else { return Ok(false) };
+
+    let mut server_nonce = [0u8; 18];
+    rand::thread_rng().fill_bytes(&mut server_nonce);
+    let nonce = format!("{}{}", client_nonce, B64.encode(server_nonce));
+    let server_first = format!("r={},s={},i={}", nonce, B64.encode(&verifier.salt), verifier.iterations);
+    let mut buf = Vec::new();
+    protocol::authentication_sasl_continue(&mut buf, server_first.as_bytes());
+    send(stream, &buf).await?;
+
+    let Some(client_final) = password(stream).await? else { return Ok(false) };
+    let client_final = String::from_utf8_lossy(&client_final).into_owned();
+    let Some((without_proof, proof)) = client_final.rsplit_once(",p=") else { return Ok(false) };
+    if attribute(without_proof, 'c') != Some(B64.encode(&gs2_header).as_str()) || attribute(without_proof, 'r') != Some(nonce.as_str()) {
+        return Ok(false);
+    }
+    let Ok(proof) = B64.decode(proof) else { return Ok(false) };
+    if proof.len() != 32 {
+        return Ok(false);
+    }
+    let auth_message = format!("{},{},{}", client_first_bare, server_first, without_proof);
+    let client_signature = hmac(&verifier.stored_key, auth_message.as_bytes());
+    let client_key: Vec<u8> = proof.iter().zip(client_signature).map(|(p, s)| p ^ s).collect();
+    let stored_key = bytes32(&Sha256::digest(&client_key));
+    // Compared in full, so the time taken doesn't tell how much matched
+    if stored_key.iter().zip(verifier.stored_key).fold(0, |acc, (a, b)| acc | (a ^ b)) != 0 {
+        return Ok(false);
+    }
+    let server_signature = hmac(&verifier.server_key, auth_message.as_bytes());
+    let mut buf = Vec::new();
+    protocol::authentication_sasl_final(&mut buf, format!("v={}", B64.encode(server_signature)).as_bytes());
+    send(stream, &buf).await?;
+    Ok(true)
+}
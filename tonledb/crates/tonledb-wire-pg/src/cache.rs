@@ -0,0 +1,167 @@
+//! Parsed statements and their plans, cached by their SQL text.
+//!
+//! The server keeps an LRU of parsed query strings shared by its connections
+//! (`PgOptions::statement_cache`), and each connection keeps the ones it used last
+//! in front of it, so a query string sent again, or prepared again by a driver that
+//! prepares on every call, is parsed once. Bind puts parameters into a copy of the
+//! parsed statement rather than into its text (see `extended`), so running a
+//! prepared statement doesn't parse it either. Query strings longer than
+//! `MAX_CACHED_QUERY_LEN` are parsed every time rather than kept.
+//!
+//! A parsed statement doesn't depend on the tables, so entries never go stale.
+//! Beside each statement the entry keeps its plan (see `tonledb_sql::Plan`), made
+//! on its first run and again whenever the catalog has changed since; the access
+//! path is still chosen on each run, from the statistics at the time.
+
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use clru::CLruCache;
+use parking_lot::Mutex;
+use sqlparser::ast::Statement;
+use sqlparser::{dialect::PostgreSqlDialect, parser::Parser};
+use tonledb_core::{CacheStats, Db};
+use tonledb_sql::Plan;
+use crate::results::PgError;
+use crate::session;
+
+/// Query strings the shared cache holds by default
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+/// Query strings each connection holds in front of the shared cache
+const CONNECTION_CAPACITY: usize = 64;
+
+/// Longest query string kept, in bytes, so that clients can't fill the caches
+/// with huge ones
+pub const MAX_CACHED_QUERY_LEN: usize = 8 << 10;
+
+/// The statements of a query string
+pub type Parsed = Arc<ParsedQuery>;
+
+/// The statements of a query string, and the plans of those that have run
+#[derive(Debug)]
+pub struct ParsedQuery {
+    pub statements: Vec<Statement>,
+    /// One per statement, made at the catalog version it holds for
+    plans: Mutex<Vec<Option<Arc<Plan>>>>,
+}
+
+impl ParsedQuery {
+    fn new(statements: Vec<Statement>) -> Self {
+        let plans = Mutex::new(vec![None; statements.len()]);
+        Self { statements, plans }
+    }
+
+    /// The plan of statement `i`: the one kept from an earlier run, unless the
+    /// catalog has changed since
+    pub fn plan(&self, db: &Db, i: usize) -> Arc<Plan> {
+        let version = db.catalog_version();
+        if let Some(plan) = self.plans.lock()[i].as_ref().filter(|p| p.catalog_version() == version) {
+            return plan.clone();
+        }
+        let plan = Arc::new(tonledb_sql::plan_statement(db, &self.statements[i]));
+        self.plans.lock()[i] = Some(plan.clone());
+        plan
+    }
+}
+
+/// Parsed query strings shared by a server's connections
+#[derive(Debug)]
+pub struct StatementCache {
+    /// `None` when caching is off
+    entries: Option<Mutex<CLruCache<String, Parsed>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl Default for StatementCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl StatementCache {
+    /// A cache of up to `capacity` query strings; 0 turns caching off, connections'
+    /// own caches included
+    pub fn new(capacity: usize) -> Self {
+        let entries = NonZeroUsize::new(capacity).map(|cap| Mutex::new(CLruCache::new(cap)));
+        Self { entries, hits: AtomicU64::new(0), misses: AtomicU64::new(0) }
+    }
+
+    fn get(&self, sql: &str) -> Option<Parsed> {
+        let parsed = self.entries.as_ref()?.lock().get(sql).cloned();
+        let counter = if parsed.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        parsed
+    }
+
+    fn insert(&self, sql: &str, parsed: &Parsed) {
+        if let Some(entries) = &self.entries {
+            entries.lock().put(sql.to_string(), parsed.clone());
+        }
+    }
+
+    /// Lookups of the shared cache, which connections make when their own misses
+    pub fn stats(&self) -> CacheStats {
+        let (capacity, entries) = match &self.entries {
+            Some(e) => {
+                let e = e.lock();
+                (e.capacity(), e.len())
+            }
+            None => (0, 0),
+        };
+        CacheStats { capacity, entries, hits: self.hits.load(Ordering::Relaxed), misses: self.misses.load(Ordering::Relaxed) }
+    }
+}
+
+/// The statements one connection parsed last, in front of the shared cache
+#[derive(Debug)]
+pub(crate) struct ConnectionCache {
+    shared: Arc<StatementCache>,
+    recent: Option<CLruCache<String, Parsed>>,
+}
+
+impl ConnectionCache {
+    pub(crate) fn new(shared: Arc<StatementCache>) -> Self {
+        let recent = shared.entries.is_some().then(|| CLruCache::new(NonZeroUsize::new(CONNECTION_CAPACITY).expect("non-zero")));
+        Self { shared, recent }
+    }
+
+    /// The statements of `sql`: from this connection's cache, the shared one, or
+    /// parsed when in neither
+    pub(crate) fn parse(&mut self, sql: &str) -> Result<Parsed, PgError> {
+        let Some(recent) = self.recent.as_mut().filter(|_| sql.len() <= MAX_CACHED_QUERY_LEN) else {
+            return parse(sql).map(|statements| Arc::new(ParsedQuery::new(statements)));
+        };
+        if let Some(parsed) = recent.get(sql) {
+            return Ok(parsed.clone());
+        }
+        let parsed = match self.shared.get(sql) {
+            Some(parsed) => parsed,
+            None => {
+                let parsed = Arc::new(ParsedQuery::new(parse(sql)?));
+                self.shared.insert(sql, &parsed);
+                parsed
+            }
+        };
+        recent.put(sql.to_string(), parsed.clone());
+        Ok(parsed)
+    }
+}
+
+/// The statements of `sql`. The parser wants `COPY ... FROM STDIN` followed by `;`
+/// (it reads inline rows after it), which clients leave off, so a query string that
+/// fails to parse without one is tried again with one. It doesn't read `RESET`
+/// either, which is tried again as `SET ... TO DEFAULT`.
+fn parse(sql: &str) -> Result<Vec<Statement>, PgError> {
+    Parser::parse_sql(&PostgreSqlDialect {}, sql)
+        .or_else(|e| match sql.trim_end().ends_with(';') {
+            true => Err(e),
+            false => Parser::parse_sql(&PostgreSqlDialect {}, &format!("{};", sql)).map_err(|_| e),
+        })
+        .or_else(|e| match session::reset_as_set(sql) {
+            Some(set) => Parser::parse_sql(&PostgreSqlDialect {}, &set).map_err(|_| e),
+            None => Err(e),
+        })
+        .map_err(|e| PgError::new("42601", e.to_string()))
+}
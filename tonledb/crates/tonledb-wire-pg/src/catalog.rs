@@ -0,0 +1,1112 @@
+//! Answers to the catalog queries `psql`, GUI tools and ORMs send to find out about
+//! the server and its tables.
+//!
+//! `SHOW`, and a `SELECT` that reads `pg_catalog` or `information_schema`
+//! relations, calls one of Postgres' catalog functions (`version()`,
+//! `current_setting()`, `format_type()`, ...) or reads no relation at all
+//! (`SELECT 1`), are answered here instead of by `tonledb_sql`. The relations are
+//! built from the catalog when asked for: every table is in schema `public`, owned
+//! by the `tonledb` role, with an OID hashed from its name, so it stays the same as
+//! other tables come and go. Queries on them may filter, join (inner, left and
+//! cross), use subqueries, the usual operators and those catalog functions, sort,
+//! count and limit; GROUP BY and window functions aren't supported. Settings (`SHOW`, `current_setting()`,
+//! `pg_settings`) are the asking session's.
+
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::ops::ControlFlow;
+use regex::RegexBuilder;
+use serde_json::{json, Value as Json};
+use sqlparser::ast::{
+    BinaryOperator, DataType as SqlType, Distinct, Expr, Function, FunctionArg, FunctionArgExpr, FunctionArguments, GroupByExpr, Ident,
+    JoinConstraint, JoinOperator, ObjectName, Query, SelectItem, SetExpr, Statement, TableFactor, TableWithJoins, UnaryOperator, Value,
+    visit_expressions, visit_relations,
+};
+use tonledb_core::{ColumnConstraint, Db, TableSchema};
+use crate::results::{self, PgError, ResultSet};
+use crate::session::{unrecognized_setting, SessionState};
+
+/// Settings reported in ParameterStatus at startup, which SHOW also gives
+pub(crate) const REPORTED_SETTINGS: &[(&str, &str)] = &[
+    ("server_version", crate::SERVER_VERSION),
+    ("server_encoding", "UTF8"),
+    ("client_encoding", "UTF8"),
+    ("DateStyle", "ISO, MDY"),
+    ("TimeZone", "UTC"),
+    ("integer_datetimes", "on"),
+    ("standard_conforming_strings", "on"),
+    ("application_name", ""),
+];
+
+/// Settings only read with SHOW or `current_setting()`
+pub(crate) const OTHER_SETTINGS: &[(&str, &str)] = &[
+    ("server_version_num", "140000"),
+    ("search_path", "\"$user\", public"),
+    ("transaction_isolation", "read committed"),
+    ("default_transaction_isolation", "read committed"),
+    ("transaction_read_only", "off"),
+    ("default_transaction_read_only", "off"),
+    ("extra_float_digits", "1"),
+    ("max_identifier_length", "63"),
+    ("lc_collate", "C"),
+    ("lc_ctype", "C"),
+];
+
+const PG_CATALOG_OID: i64 = 11;
+const PUBLIC_OID: i64 = 2200;
+const INFORMATION_SCHEMA_OID: i64 = 13000;
+/// The role owning everything, with the OID of Postgres' bootstrap superuser
+const OWNER_OID: i64 = 10;
+const OWNER: &str = "tonledb";
+const DATABASE_OID: i64 = 16383;
+const DATABASE: &str = "tonledb";
+/// Lowest OID of a table or index; lower ones are Postgres' own
+const FIRST_OID: i64 = 16384;
+const HEAP_AM_OID: i64 = 2;
+const BTREE_AM_OID: i64 = 403;
+/// Collation of text types
+const DEFAULT_COLLATION_OID: i64 = 100;
+
+/// Types in `pg_type`: OID, name, length, category, OID of the array type, and the
+/// name `format_type()` gives it
+const TYPES: &[(i64, &str, i64, &str, i64, &str)] = &[
+    (16, "bool", 1, "B", 1000, "boolean"),
+    (17, "bytea", -1, "U", 1001, "bytea"),
+    (18, "char", 1, "S", 1002, "\"char\""),
+    (19, "name", 64, "S", 1003, "name"),
+    (20, "int8", 8, "N", 1016, "bigint"),
+    (21, "int2", 2, "N", 1005, "smallint"),
+    (23, "int4", 4, "N", 1007, "integer"),
+    (25, "text", -1, "S", 1009, "text"),
+    (26, "oid", 4, "N", 1028, "oid"),
+    (114, "json", -1, "U", 199, "json"),
+    (700, "float4", 4, "N", 1021, "real"),
+    (701, "float8", 8, "N", 1022, "double precision"),
+    (1042, "bpchar", -1, "S", 1014, "character"),
+    (1043, "varchar", -1, "S", 1015, "character varying"),
+    (1082, "date", 4, "D", 1182, "date"),
+    (1114, "timestamp", 8, "D", 1115, "timestamp without time zone"),
+    (1184, "timestamptz", 8, "D", 1185, "timestamp with time zone"),
+    (1700, "numeric", -1, "N", 1231, "numeric"),
+    (2950, "uuid", 16, "U", 2951, "uuid"),
+    (3802, "jsonb", -1, "U", 3807, "jsonb"),
+];
+
+/// Functions only answered here
+const CATALOG_FUNCTIONS: &[&str] = &[
+    "version", "current_database", "current_catalog", "current_schema", "current_user", "session_user", "current_role",
+    "pg_backend_pid", "current_setting", "pg_encoding_to_char", "pg_table_is_visible", "pg_type_is_visible", "has_table_privilege",
+    "has_schema_privilege", "has_database_privilege", "pg_get_userbyid", "format_type", "pg_get_indexdef", "pg_get_expr",
+    "obj_description", "col_description", "shobj_description",
+];
+
+/// Whether `stmt` is answered here rather than by `tonledb_sql`
+pub fn is_catalog_query(stmt: &Statement) -> bool {
+    match stmt {
+        Statement::ShowVariable { .. } => true,
+        Statement::Query(query) => reads_catalog(query),
+        _ => false,
+    }
+}
+
+/// The result of catalog query `stmt`, run in `session`
+pub fn answer(db: &Db, session: &SessionState, stmt: &Statement) -> Result<ResultSet, PgError> {
+    match stmt {
+        Statement::ShowVariable { variable } => show(session, variable),
+        Statement::Query(query) => {
+            let rows = Catalog::new(db, session).query(query, None)?;
+            let fields: Vec<_> = rows
+                .columns
+                .iter()
+                .enumerate()
+                .map(|(i, name)| results::field(name, results::common_oid(rows.rows.iter().map(|r| &r[i]))))
+                .collect();
+            let cells = rows.rows.iter().map(|row| row.iter().zip(&fields).map(|(v, f)| results::text_value(f.type_oid, Some(v))).collect()).collect();
+            Ok(ResultSet { fields, rows: cells, tag: format!("SELECT {}", rows.rows.len()) })
+        }
+        _ => Err(PgError::new("42601", "not a catalog query")),
+    }
+}
+
+fn show(session: &SessionState, variable: &[Ident]) -> Result<ResultSet, PgError> {
+    let name = variable.iter().map(|i| i.value.to_lowercase()).collect::<Vec<_>>().join(" ");
+    if name == "all" {
+        let rows = session.settings().iter().map(|(n, v)| vec![Some(n.clone()), Some(v.clone())]).collect();
+        let fields = vec![results::field("name", results::TEXT_OID), results::field("setting", results::TEXT_OID)];
+        return Ok(ResultSet { fields, rows, tag: "SHOW".into() });
+    }
+    let name = match name.as_str() {
+        "transaction isolation level" => "transaction_isolation".to_string(),
+        "time zone" => "TimeZone".to_string(),
+        // SHOW reads the parts of a custom setting (`app.tenant`) as separate words
+        _ => variable.iter().map(|i| i.value.as_str()).collect::<Vec<_>>().join("."),
+    };
+    let (name, value) = session.setting(&name).ok_or_else(|| unrecognized_setting(&name))?;
+    Ok(ResultSet { fields: vec![results::field(name, results::TEXT_OID)], rows: vec![vec![Some(value.to_string())]], tag: "SHOW".into() })
+}
+
+fn unsupported(what: impl std::fmt::Display) -> PgError {
+    PgError::new("0A000", format!("{} is not supported in catalog queries", what))
+}
+
+/// An identifier as Postgres reads it: folded to lower case unless quoted
+fn fold(ident: &Ident) -> String {
+    match ident.quote_style {
+        Some(_) => ident.value.clone(),
+        None => ident.value.to_lowercase(),
+    }
+}
+
+/// Schema and name of `name` if it is a catalog relation
+fn catalog_name(name: &ObjectName) -> Option<(&'static str, String)> {
+    let parts: Vec<String> = name.0.iter().map(fold).collect();
+    let (schema, relation) = match parts.as_slice() {
+        [relation] if relation.starts_with("pg_") => ("pg_catalog", relation),
+        [schema, relation] | [_, schema, relation] => (schema.as_str(), relation),
+        _ => return None,
+    };
+    match schema {
+        "pg_catalog" => Some(("pg_catalog", relation.clone())),
+        "information_schema" => Some(("information_schema", relation.clone())),
+        _ => None,
+    }
+}
+
+/// OID of the table or index named `name`: an FNV-1a hash of the name, so it doesn't
+/// depend on what else exists, moved on past any OID already `taken`
+fn stable_oid(name: &str, taken: &mut HashSet<i64>) -> i64 {
+    let hash = name.bytes().fold(0xcbf2_9ce4_8422_2325u64, |h, b| (h ^ b as u64).wrapping_mul(0x0100_0000_01b3));
+    let span = u32::MAX as i64 - FIRST_OID;
+    let mut oid = FIRST_OID + (hash % span as u64) as i64;
+    while !taken.insert(oid) {
+        oid = FIRST_OID + (oid - FIRST_OID + 1) % span;
+    }
+    oid
+}
+
+/// Whether `query` reads a catalog relation, calls a catalog function, or reads no
+/// relation at all, which `tonledb_sql` can't answer
+fn reads_catalog(query: &Query) -> bool {
+    if !matches!(&*query.body, SetExpr::Select(_)) {
+        return false;
+    }
+    let mut relations = 0;
+    let catalog = visit_relations(query, |name| {
+        relations += 1;
+        match catalog_name(name) {
+            Some(_) => ControlFlow::Break(()),
+            None => ControlFlow::Continue(()),
+        }
+    });
+    let calls = visit_expressions(query, |expr| match expr {
+        Expr::Function(f) if f.name.0.last().is_some_and(|n| CATALOG_FUNCTIONS.contains(&fold(n).as_str())) => ControlFlow::Break(()),
+        _ => ControlFlow::Continue(()),
+    });
+    catalog.is_break() || calls.is_break() || relations == 0
+}
+
+/// Rows with a name for each column, and the relation (by name or alias) each
+/// column comes from
+#[derive(Debug, Clone, Default)]
+struct Rows {
+    qualifiers: Vec<String>,
+    columns: Vec<String>,
+    rows: Vec<Vec<Json>>,
+}
+
+impl Rows {
+    fn new(columns: &[&str], rows: Vec<Vec<Json>>) -> Self {
+        Self { qualifiers: vec![String::new(); columns.len()], columns: columns.iter().map(|c| c.to_string()).collect(), rows }
+    }
+}
+
+/// A row being evaluated, within the row of the query around it for a correlated
+/// subquery
+#[derive(Clone, Copy)]
+struct Frame<'f> {
+    qualifiers: &'f [String],
+    columns: &'f [String],
+    row: &'f [Json],
+    outer: Option<&'f Frame<'f>>,
+}
+
+impl Frame<'_> {
+    /// The value of column `name` (of relation `qualifier`, if given)
+    fn lookup(&self, qualifier: Option<&str>, name: &str) -> Result<Option<Json>, PgError> {
+        let mut found = None;
+        for (i, column) in self.columns.iter().enumerate() {
+            if column == name && qualifier.iter().all(|q| self.qualifiers[i] == *q) {
+                if found.is_some() {
+                    return Err(PgError::new("42702", format!("column reference \"{}\" is ambiguous", name)));
+                }
+                found = Some(self.row[i].clone());
+            }
+        }
+        match (found, self.outer) {
+            (Some(v), _) => Ok(Some(v)),
+            (None, Some(outer)) => outer.lookup(qualifier, name),
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+/// A single-column index: the primary key, or one made with CREATE INDEX
+struct Index {
+    oid: i64,
+    name: String,
+    /// Position of the table among `Catalog::tables`
+    table: usize,
+    /// Position of the column in the table
+    column: usize,
+    unique: bool,
+    primary: bool,
+}
+
+/// The tables, as of when a query started, and the session asking about them
+struct Catalog<'a> {
+    session: &'a SessionState,
+    tables: Vec<TableSchema>,
+    /// OID of each of `tables`
+    table_oids: Vec<i64>,
+    indexes: Vec<Index>,
+}
+
+impl<'a> Catalog<'a> {
+    fn new(db: &Db, session: &'a SessionState) -> Self {
+        let catalog = db.catalog.read();
+        let tables: Vec<TableSchema> = catalog.tables.values().cloned().collect();
+        let mut taken = HashSet::new();
+        let table_oids = tables.iter().map(|t| stable_oid(&t.name, &mut taken)).collect();
+        let mut indexes = Vec::new();
+        for (i, table) in tables.iter().enumerate() {
+            if let Some(column) = table.columns.iter().position(|c| Some(&c.name) == table.pk.as_ref()) {
+                let name = format!("{}_pkey", table.name);
+                indexes.push(Index { oid: stable_oid(&name, &mut taken), name, table: i, column, unique: true, primary: true });
+            }
+        }
+        let mut defs: Vec<_> = catalog.indexes.values().collect();
+        defs.sort_by(|a, b| (&a.table, &a.column).cmp(&(&b.table, &b.column)));
+        for def in defs {
+            let Some(i) = tables.iter().position(|t| t.name == def.table) else { continue };
+            let table = &tables[i];
+            let Some(column) = table.columns.iter().position(|c| c.name == def.column) else { continue };
+            if table.pk.as_ref() == Some(&def.column) {
+                continue;
+            }
+            let name = format!("{}_{}_idx", table.name, def.column);
+            indexes.push(Index { oid: stable_oid(&name, &mut taken), name, table: i, column, unique: def.is_unique, primary: false });
+        }
+        Self { session, tables, table_oids, indexes }
+    }
+
+    fn table_oid(&self, i: usize) -> i64 {
+        self.table_oids[i]
+    }
+
+    /// OID of the table named `name`, as in `'name'::regclass`
+    fn regclass(&self, name: &str) -> Option<i64> {
+        let name = name.strip_prefix("public.").unwrap_or(name).trim_matches('"');
+        if let Some(i) = self.tables.iter().position(|t| t.name == name) {
+            return Some(self.table_oid(i));
+        }
+        self.indexes.iter().find(|x| x.name == name).map(|x| x.oid)
+    }
+
+    fn relation(&self, name: &ObjectName) -> Result<Rows, PgError> {
+        let missing = || PgError::new("42P01", format!("relation \"{}\" does not exist", name));
+        let (schema, relation) = catalog_name(name).ok_or_else(missing)?;
+        Ok(match (schema, relation.as_str()) {
+            ("pg_catalog", "pg_namespace") => Rows::new(
+                &["oid", "nspname", "nspowner", "nspacl"],
+                [(PG_CATALOG_OID, "pg_catalog"), (PUBLIC_OID, "public"), (INFORMATION_SCHEMA_OID, "information_schema")]
+                    .iter()
+                    .map(|(oid, name)| vec![json!(oid), json!(name), json!(OWNER_OID), Json::Null])
+                    .collect(),
+            ),
+            ("pg_catalog", "pg_class") => self.pg_class(),
+            ("pg_catalog", "pg_attribute") => self.pg_attribute(),
+            ("pg_catalog", "pg_index") => self.pg_index(),
+            ("pg_catalog", "pg_type") => pg_type(),
+            ("pg_catalog", "pg_database") => Rows::new(
+                &["oid", "datname", "datdba", "encoding", "datcollate", "datctype", "datistemplate", "datallowconn", "datconnlimit", "datacl"],
+                vec![vec![json!(DATABASE_OID), json!(DATABASE), json!(OWNER_OID), json!(6), json!("C"), json!("C"), json!(false), json!(true), json!(-1), Json::Null]],
+            ),
+            ("pg_catalog", "pg_roles") | ("pg_catalog", "pg_user") => Rows::new(
+                &["oid", "rolname", "rolsuper", "rolinherit", "rolcreaterole", "rolcreatedb", "rolcanlogin", "rolreplication", "rolconnlimit", "rolvaliduntil", "rolbypassrls"],
+                vec![vec![json!(OWNER_OID), json!(OWNER), json!(true), json!(true), json!(true), json!(true), json!(true), json!(false), json!(-1), Json::Null, json!(true)]],
+            ),
+            ("pg_catalog", "pg_settings") => Rows::new(
+                &["name", "setting"],
+                self.session.settings().iter().map(|(n, v)| vec![json!(n), json!(v)]).collect(),
+            ),
+            // Nothing has defaults or comments
+            ("pg_catalog", "pg_attrdef") => Rows::new(&["oid", "adrelid", "adnum", "adbin"], Vec::new()),
+            ("pg_catalog", "pg_description") => Rows::new(&["objoid", "classoid", "objsubid", "description"], Vec::new()),
+            ("information_schema", "schemata") => Rows::new(
+                &["catalog_name", "schema_name", "schema_owner"],
+                ["pg_catalog", "public", "information_schema"].iter().map(|s| vec![json!(DATABASE), json!(s), json!(OWNER)]).collect(),
+            ),
+            ("information_schema", "tables") => Rows::new(
+                &["table_catalog", "table_schema", "table_name", "table_type", "is_insertable_into", "is_typed"],
+                self.tables.iter().map(|t| vec![json!(DATABASE), json!("public"), json!(t.name), json!("BASE TABLE"), json!("YES"), json!("NO")]).collect(),
+            ),
+            ("information_schema", "columns") => self.information_schema_columns(),
+            _ => return Err(missing()),
+        })
+    }
+
+    fn pg_class(&self) -> Rows {
+        let row = |oid: i64, name: &str, am: i64, kind: &str, natts: usize, has_index: bool| {
+            vec![
+                json!(oid), json!(name), json!(PUBLIC_OID), json!(0), json!(OWNER_OID), json!(am), json!(oid), json!(0),
+                json!(-1.0), json!(has_index), json!(false), json!("p"), json!(kind), json!(natts), json!(0), json!(false),
+                json!(false), json!(false), json!(false), json!(false), json!(true), json!("d"), json!(false), Json::Null, Json::Null,
+            ]
+        };
+        let mut rows: Vec<_> = self.tables.iter().enumerate()
+            .map(|(i, t)| row(self.table_oid(i), &t.name, HEAP_AM_OID, "r", t.columns.len(), self.indexes.iter().any(|x| x.table == i)))
+            .collect();
+        rows.extend(self.indexes.iter().map(|x| row(x.oid, &x.name, BTREE_AM_OID, "i", 1, false)));
+        Rows::new(
+            &[
+                "oid", "relname", "relnamespace", "reltype", "relowner", "relam", "relfilenode", "reltablespace",
+                "reltuples", "relhasindex", "relisshared", "relpersistence", "relkind", "relnatts", "relchecks", "relhasrules",
+                "relhastriggers", "relhassubclass", "relrowsecurity", "relforcerowsecurity", "relispopulated", "relreplident", "relispartition", "relacl", "reloptions",
+            ],
+            rows,
+        )
+    }
+
+    fn pg_attribute(&self) -> Rows {
+        let row = |relid: i64, name: &str, type_oid: i64, num: usize, not_null: bool| {
+            let len = TYPES.iter().find(|t| t.0 == type_oid).map_or(-1, |t| t.2);
+            let collation = if type_oid == i64::from(results::TEXT_OID) { DEFAULT_COLLATION_OID } else { 0 };
+            vec![
+                json!(relid), json!(name), json!(type_oid), json!(len), json!(num), json!(-1), json!(0), json!(not_null),
+                json!(false), json!(""), json!(""), json!(false), json!(true), json!(collation),
+            ]
+        };
+        let mut rows = Vec::new();
+        for (i, table) in self.tables.iter().enumerate() {
+            for (n, column) in table.columns.iter().enumerate() {
+                rows.push(row(self.table_oid(i), &column.name, column_type(table, n), n + 1, not_null(table, n)));
+            }
+        }
+        for index in &self.indexes {
+            let table = &self.tables[index.table];
+            rows.push(row(index.oid, &table.columns[index.column].name, column_type(table, index.column), 1, false));
+        }
+        Rows::new(
+            &[
+                "attrelid", "attname", "atttypid", "attlen", "attnum", "atttypmod", "attndims", "attnotnull",
+                "atthasdef", "attidentity", "attgenerated", "attisdropped", "attislocal", "attcollation",
+            ],
+            rows,
+        )
+    }
+
+    fn pg_index(&self) -> Rows {
+        Rows::new(
+            &["indexrelid", "indrelid", "indnatts", "indnkeyatts", "indisunique", "indisprimary", "indisexclusion", "indimmediate", "indisclustered", "indisvalid", "indisready", "indislive", "indkey", "indexprs", "indpred"],
+            self.indexes
+                .iter()
+                .map(|x| {
+                    vec![
+                        json!(x.oid), json!(self.table_oid(x.table)), json!(1), json!(1), json!(x.unique), json!(x.primary), json!(false), json!(true),
+                        json!(false), json!(true), json!(true), json!(true), json!((x.column + 1).to_string()), Json::Null, Json::Null,
+                    ]
+                })
+                .collect(),
+        )
+    }
+
+    fn information_schema_columns(&self) -> Rows {
+        let mut rows = Vec::new();
+        for table in &self.tables {
+            for (n, column) in table.columns.iter().enumerate() {
+                let type_oid = column_type(table, n);
+                let (_, udt_name, _, _, _, data_type) = TYPES.iter().find(|t| t.0 == type_oid).expect("column types are in TYPES");
+                let (precision, scale) = match type_oid {
+                    20 => (json!(64), json!(0)),
+                    701 => (json!(53), Json::Null),
+                    _ => (Json::Null, Json::Null),
+                };
+                rows.push(vec![
+                    json!(DATABASE), json!("public"), json!(table.name), json!(column.name), json!(n + 1), Json::Null,
+                    json!(if not_null(table, n) { "NO" } else { "YES" }), json!(data_type), precision, scale,
+                    json!(DATABASE), json!("pg_catalog"), json!(udt_name), json!("NO"), json!("NEVER"), json!("YES"),
+                ]);
+            }
+        }
+        Rows::new(
+            &[
+                "table_catalog", "table_schema", "table_name", "column_name", "ordinal_position", "column_default",
+                "is_nullable", "data_type", "numeric_precision", "numeric_scale",
+                "udt_catalog", "udt_schema", "udt_name", "is_identity", "is_generated", "is_updatable",
+            ],
+            rows,
+        )
+    }
+
+    fn query(&self, query: &Query, outer: Option<&Frame>) -> Result<Rows, PgError> {
+        if query.with.is_some() {
+            return Err(unsupported("WITH"));
+        }
+        let SetExpr::Select(select) = &*query.body else { return Err(unsupported("UNION, INTERSECT and EXCEPT")) };
+        if !matches!(&select.group_by, GroupByExpr::Expressions(e) if e.is_empty()) || select.having.is_some() {
+            return Err(unsupported("GROUP BY"));
+        }
+        let mut source = self.from(&select.from, outer)?;
+        let mut kept = Vec::new();
+        for row in std::mem::take(&mut source.rows) {
+            let frame = Frame { qualifiers: &source.qualifiers, columns: &source.columns, row: &row, outer };
+            if let Some(selection) = &select.selection {
+                if self.eval(selection, &frame)? != Json::Bool(true) {
+                    continue;
+                }
+            }
+            kept.push(row);
+        }
+
+        let mut columns = Vec::new();
+        for item in &select.projection {
+            match item {
+                SelectItem::Wildcard(_) => columns.extend(source.columns.iter().cloned()),
+                SelectItem::QualifiedWildcard(name, _) => {
+                    let qualifier = name.0.last().map(fold).unwrap_or_default();
+                    columns.extend(source.columns.iter().zip(&source.qualifiers).filter(|(_, q)| **q == qualifier).map(|(c, _)| c.clone()));
+                }
+                SelectItem::UnnamedExpr(expr) => columns.push(column_name(expr)),
+                SelectItem::ExprWithAlias { alias, .. } => columns.push(fold(alias)),
+            }
+        }
+
+        // Each output row, with its ORDER BY keys
+        let mut out: Vec<(Vec<Json>, Vec<Json>)> = Vec::new();
+        if select.projection.iter().any(|item| matches!(item_expr(item), Some(Expr::Function(f)) if is_count(f))) {
+            out.push((self.aggregate(&select.projection, &source, &kept, outer)?, Vec::new()));
+        } else {
+            for row in &kept {
+                let frame = Frame { qualifiers: &source.qualifiers, columns: &source.columns, row, outer };
+                let values = self.project(&select.projection, &frame)?;
+                let mut keys = Vec::new();
+                for order in &query.order_by {
+                    keys.push(match &order.expr {
+                        Expr::Value(Value::Number(n, _)) => {
+                            let position = n.parse::<usize>().ok().filter(|p| (1..=values.len()).contains(p));
+                            let position = position.ok_or_else(|| PgError::new("42P10", format!("ORDER BY position {} is not in select list", n)))?;
+                            values[position - 1].clone()
+                        }
+                        Expr::Identifier(ident) if columns.contains(&fold(ident)) => {
+                            values[columns.iter().position(|c| *c == fold(ident)).expect("checked above")].clone()
+                        }
+                        expr => self.eval(expr, &frame)?,
+                    });
+                }
+                out.push((values, keys));
+            }
+        }
+
+        match &select.distinct {
+            None => {}
+            Some(Distinct::Distinct) => {
+                let mut seen = Vec::new();
+                out.retain(|(values, _)| {
+                    let new = !seen.contains(values);
+                    if new {
+                        seen.push(values.clone());
+                    }
+                    new
+                });
+            }
+            Some(Distinct::On(_)) => return Err(unsupported("DISTINCT ON")),
+        }
+        out.sort_by(|(_, a), (_, b)| {
+            for (i, order) in query.order_by.iter().enumerate() {
+                let asc = order.asc.unwrap_or(true);
+                let nulls_first = order.nulls_first.unwrap_or(!asc);
+                let ordering = match (a[i].is_null(), b[i].is_null()) {
+                    (true, true) => Ordering::Equal,
+                    (true, false) if nulls_first => Ordering::Less,
+                    (true, false) => Ordering::Greater,
+                    (false, true) if nulls_first => Ordering::Greater,
+                    (false, true) => Ordering::Less,
+                    (false, false) if asc => compare(&a[i], &b[i]).unwrap_or(Ordering::Equal),
+                    (false, false) => compare(&b[i], &a[i]).unwrap_or(Ordering::Equal),
+                };
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            Ordering::Equal
+        });
+        let offset = match &query.offset {
+            Some(offset) => self.count_of(&offset.value, "OFFSET")?.unwrap_or(0),
+            None => 0,
+        };
+        let limit = match &query.limit {
+            Some(limit) => self.count_of(limit, "LIMIT")?.unwrap_or(usize::MAX),
+            None => usize::MAX,
+        };
+        let rows = out.into_iter().skip(offset).take(limit).map(|(values, _)| values).collect();
+        Ok(Rows { qualifiers: vec![String::new(); columns.len()], columns, rows })
+    }
+
+    /// A LIMIT or OFFSET; `None` for NULL, which sets no limit
+    fn count_of(&self, expr: &Expr, clause: &str) -> Result<Option<usize>, PgError> {
+        let empty = Frame { qualifiers: &[], columns: &[], row: &[], outer: None };
+        match self.eval(expr, &empty)? {
+            Json::Null => Ok(None),
+            v => v.as_u64().map(|n| Some(n as usize)).ok_or_else(|| PgError::new("2201W", format!("{} must not be negative", clause))),
+        }
+    }
+
+    fn from(&self, from: &[TableWithJoins], outer: Option<&Frame>) -> Result<Rows, PgError> {
+        // One row of no columns, so a SELECT without FROM gives one row
+        let mut rows = Rows { rows: vec![Vec::new()], ..Rows::default() };
+        for table in from {
+            rows = self.join(rows, self.table_factor(&table.relation)?, None, false, outer)?;
+            for join in &table.joins {
+                let right = self.table_factor(&join.relation)?;
+                let (constraint, left_outer) = match &join.join_operator {
+                    JoinOperator::Inner(c) => (c, false),
+                    JoinOperator::LeftOuter(c) => (c, true),
+                    JoinOperator::CrossJoin => (&JoinConstraint::None, false),
+                    _ => return Err(unsupported("RIGHT, FULL, SEMI and ANTI JOIN")),
+                };
+                let on = match constraint {
+                    JoinConstraint::On(expr) => Some(expr),
+                    JoinConstraint::None => None,
+                    JoinConstraint::Using(_) | JoinConstraint::Natural => return Err(unsupported("JOIN USING and NATURAL JOIN")),
+                };
+                rows = self.join(rows, right, on, left_outer, outer)?;
+            }
+        }
+        Ok(rows)
+    }
+
+    fn table_factor(&self, factor: &TableFactor) -> Result<Rows, PgError> {
+        let (mut rows, qualifier) = match factor {
+            TableFactor::Table { name, alias, .. } => {
+                let qualifier = match alias {
+                    Some(alias) => fold(&alias.name),
+                    None => name.0.last().map(fold).unwrap_or_default(),
+                };
+                (self.relation(name)?, qualifier)
+            }
+            TableFactor::Derived { subquery, alias, .. } => (self.query(subquery, None)?, alias.as_ref().map(|a| fold(&a.name)).unwrap_or_default()),
+            other => return Err(unsupported(format!("FROM {}", other))),
+        };
+        rows.qualifiers = vec![qualifier; rows.columns.len()];
+        Ok(rows)
+    }
+
+    /// Rows of `left` joined to those of `right` that `on` holds for; with
+    /// `left_outer`, left rows that match none are kept with NULLs
+    fn join(&self, left: Rows, right: Rows, on: Option<&Expr>, left_outer: bool, outer: Option<&Frame>) -> Result<Rows, PgError> {
+        let qualifiers: Vec<String> = left.qualifiers.into_iter().chain(right.qualifiers).collect();
+        let columns: Vec<String> = left.columns.into_iter().chain(right.columns.iter().cloned()).collect();
+        let mut rows = Vec::new();
+        for l in &left.rows {
+            let mut matched = false;
+            for r in &right.rows {
+                let row: Vec<Json> = l.iter().chain(r).cloned().collect();
+                if let Some(on) = on {
+                    let frame = Frame { qualifiers: &qualifiers, columns: &columns, row: &row, outer };
+                    if self.eval(on, &frame)? != Json::Bool(true) {
+                        continue;
+                    }
+                }
+                matched = true;
+                rows.push(row);
+            }
+            if left_outer && !matched {
+                rows.push(l.iter().cloned().chain(std::iter::repeat_n(Json::Null, right.columns.len())).collect());
+            }
+        }
+        Ok(Rows { qualifiers, columns, rows })
+    }
+
+    fn project(&self, items: &[SelectItem], frame: &Frame) -> Result<Vec<Json>, PgError> {
+        let mut values = Vec::new();
+        for item in items {
+            match item {
+                SelectItem::Wildcard(_) => values.extend(frame.row.iter().cloned()),
+                SelectItem::QualifiedWildcard(name, _) => {
+                    let qualifier = name.0.last().map(fold).unwrap_or_default();
+                    values.extend(frame.row.iter().zip(frame.qualifiers).filter(|(_, q)| **q == qualifier).map(|(v, _)| v.clone()));
+                }
+                SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } => values.push(self.eval(expr, frame)?),
+            }
+        }
+        Ok(values)
+    }
+
+    /// The one row of a SELECT of `count()`s; other expressions can't read the
+    /// rows' columns, as there is no GROUP BY
+    fn aggregate(&self, items: &[SelectItem], source: &Rows, rows: &[Vec<Json>], outer: Option<&Frame>) -> Result<Vec<Json>, PgError> {
+        let nulls = vec![Json::Null; source.columns.len()];
+        let first = Frame { qualifiers: &source.qualifiers, columns: &source.columns, row: rows.first().unwrap_or(&nulls), outer };
+        let mut values = Vec::new();
+        for item in items {
+            let value = match item_expr(item) {
+                Some(Expr::Function(f)) if is_count(f) => {
+                    let FunctionArguments::List(list) = &f.args else { return Err(unsupported(f)) };
+                    match list.args.as_slice() {
+                        [FunctionArg::Unnamed(FunctionArgExpr::Wildcard)] => json!(rows.len()),
+                        [FunctionArg::Unnamed(FunctionArgExpr::Expr(expr))] => {
+                            let mut n = 0;
+                            for row in rows {
+                                let frame = Frame { row, ..first };
+                                if !self.eval(expr, &frame)?.is_null() {
+                                    n += 1;
+                                }
+                            }
+                            json!(n)
+                        }
+                        _ => return Err(unsupported(f)),
+                    }
+                }
+                Some(expr) => match ungrouped_column(expr, source) {
+                    Some(column) => {
+                        return Err(PgError::new("42803", format!("column \"{}\" must appear in the GROUP BY clause or be used in an aggregate function", column)))
+                    }
+                    None => self.eval(expr, &first)?,
+                },
+                None => return Err(unsupported("* with count()")),
+            };
+            values.push(value);
+        }
+        Ok(values)
+    }
+
+    fn eval(&self, expr: &Expr, frame: &Frame) -> Result<Json, PgError> {
+        let missing = |name: &str| PgError::new("42703", format!("column \"{}\" does not exist", name));
+        Ok(match expr {
+            Expr::Identifier(ident) => {
+                let name = fold(ident);
+                match frame.lookup(None, &name)? {
+                    Some(v) => v,
+                    // Called without parentheses
+                    None if name == "current_schema" => json!("public"),
+                    None => return Err(missing(&name)),
+                }
+            }
+            Expr::CompoundIdentifier(idents) => {
+                let [.., qualifier, name] = idents.as_slice() else { return Err(unsupported(expr)) };
+                frame.lookup(Some(&fold(qualifier)), &fold(name))?.ok_or_else(|| missing(&format!("{}.{}", fold(qualifier), fold(name))))?
+            }
+            Expr::Value(value) => literal(value)?,
+            Expr::Nested(e) | Expr::Collate { expr: e, .. } => self.eval(e, frame)?,
+            Expr::IsNull(e) => json!(self.eval(e, frame)?.is_null()),
+            Expr::IsNotNull(e) => json!(!self.eval(e, frame)?.is_null()),
+            Expr::UnaryOp { op: UnaryOperator::Not, expr } => match self.eval(expr, frame)? {
+                Json::Bool(b) => json!(!b),
+                _ => Json::Null,
+            },
+            Expr::UnaryOp { op: UnaryOperator::Minus, expr } => match self.eval(expr, frame)? {
+                Json::Number(n) if n.is_i64() => json!(-n.as_i64().expect("checked")),
+                Json::Number(n) => json!(-n.as_f64().unwrap_or_default()),
+                _ => Json::Null,
+            },
+            Expr::BinaryOp { left, op, right } => self.binary(left, op, right, frame)?,
+            Expr::InList { expr, list, negated } => {
+                let v = self.eval(expr, frame)?;
+                let mut values = Vec::new();
+                for e in list {
+                    values.push(self.eval(e, frame)?);
+                }
+                in_values(&v, &values, *negated)
+            }
+            Expr::InSubquery { expr, subquery, negated } => {
+                let v = self.eval(expr, frame)?;
+                let values: Vec<Json> = self.query(subquery, Some(frame))?.rows.into_iter().filter_map(|r| r.into_iter().next()).collect();
+                in_values(&v, &values, *negated)
+            }
+            Expr::Between { expr, negated, low, high } => {
+                let v = self.eval(expr, frame)?;
+                match (compare(&v, &self.eval(low, frame)?), compare(&v, &self.eval(high, frame)?)) {
+                    (Some(l), Some(h)) => json!((l != Ordering::Less && h != Ordering::Greater) != *negated),
+                    _ => Json::Null,
+                }
+            }
+            Expr::Like { negated, expr, pattern, escape_char } => self.like(expr, pattern, escape_char.as_deref(), *negated, false, frame)?,
+            Expr::ILike { negated, expr, pattern, escape_char } => self.like(expr, pattern, escape_char.as_deref(), *negated, true, frame)?,
+            Expr::Case { operand, conditions, results, else_result } => {
+                let operand = match operand {
+                    Some(e) => Some(self.eval(e, frame)?),
+                    None => None,
+                };
+                for (condition, result) in conditions.iter().zip(results) {
+                    let v = self.eval(condition, frame)?;
+                    let hit = match &operand {
+                        Some(o) => compare(o, &v) == Some(Ordering::Equal),
+                        None => v == Json::Bool(true),
+                    };
+                    if hit {
+                        return self.eval(result, frame);
+                    }
+                }
+                match else_result {
+                    Some(e) => self.eval(e, frame)?,
+                    None => Json::Null,
+                }
+            }
+            Expr::Cast { expr, data_type, .. } => self.cast(self.eval(expr, frame)?, data_type)?,
+            Expr::Function(f) => self.function(f, frame)?,
+            Expr::Exists { subquery, negated } => json!(self.query(subquery, Some(frame))?.rows.is_empty() == *negated),
+            Expr::Subquery(query) => {
+                let rows = self.query(query, Some(frame))?;
+                if rows.rows.len() > 1 {
+                    return Err(PgError::new("21000", "more than one row returned by a subquery used as an expression"));
+                }
+                rows.rows.into_iter().next().and_then(|r| r.into_iter().next()).unwrap_or(Json::Null)
+            }
+            other => return Err(unsupported(other)),
+        })
+    }
+
+    fn binary(&self, left: &Expr, op: &BinaryOperator, right: &Expr, frame: &Frame) -> Result<Json, PgError> {
+        // `OPERATOR(pg_catalog.~)`, as psql writes it, is `~`
+        let op = match op {
+            BinaryOperator::PGCustomBinaryOperator(parts) => parts.last().cloned().unwrap_or_default(),
+            op => op.to_string(),
+        };
+        let (l, r) = (self.eval(left, frame)?, self.eval(right, frame)?);
+        Ok(match op.as_str() {
+            "AND" => match (&l, &r) {
+                (Json::Bool(false), _) | (_, Json::Bool(false)) => json!(false),
+                (Json::Bool(true), Json::Bool(true)) => json!(true),
+                _ => Json::Null,
+            },
+            "OR" => match (&l, &r) {
+                (Json::Bool(true), _) | (_, Json::Bool(true)) => json!(true),
+                (Json::Bool(false), Json::Bool(false)) => json!(false),
+                _ => Json::Null,
+            },
+            "=" | "<>" | "<" | "<=" | ">" | ">=" => match compare(&l, &r) {
+                Some(ordering) => json!(match op.as_str() {
+                    "=" => ordering == Ordering::Equal,
+                    "<>" => ordering != Ordering::Equal,
+                    "<" => ordering == Ordering::Less,
+                    "<=" => ordering != Ordering::Greater,
+                    ">" => ordering == Ordering::Greater,
+                    _ => ordering != Ordering::Less,
+                }),
+                None => Json::Null,
+            },
+            "~" | "~*" | "!~" | "!~*" => match (text(&l), text(&r)) {
+                (Some(s), Some(pattern)) => {
+                    let regex = RegexBuilder::new(&pattern)
+                        .case_insensitive(op.contains('*'))
+                        .build()
+                        .map_err(|e| PgError::new("2201B", format!("invalid regular expression: {}", e)))?;
+                    json!(regex.is_match(&s) != op.starts_with('!'))
+                }
+                _ => Json::Null,
+            },
+            "||" => match (text(&l), text(&r)) {
+                (Some(a), Some(b)) => json!(a + &b),
+                _ => Json::Null,
+            },
+            "+" | "-" | "*" | "/" | "%" => arithmetic(&op, &l, &r)?,
+            other => return Err(unsupported(format!("operator {}", other))),
+        })
+    }
+
+    fn like(&self, expr: &Expr, pattern: &Expr, escape: Option<&str>, negated: bool, ignore_case: bool, frame: &Frame) -> Result<Json, PgError> {
+        let (Some(s), Some(pattern)) = (text(&self.eval(expr, frame)?), text(&self.eval(pattern, frame)?)) else { return Ok(Json::Null) };
+        let escape = escape.and_then(|e| e.chars().next()).unwrap_or('\\');
+        let mut regex = String::from("(?s)^");
+        let mut chars = pattern.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                c if c == escape => regex.push_str(&regex::escape(&chars.next().map(String::from).unwrap_or_default())),
+                '%' => regex.push_str(".*"),
+                '_' => regex.push('.'),
+                c => regex.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        regex.push('$');
+        let regex = RegexBuilder::new(&regex).case_insensitive(ignore_case).build().map_err(|e| PgError::new("22025", e.to_string()))?;
+        Ok(json!(regex.is_match(&s) != negated))
+    }
+
+    fn cast(&self, v: Json, data_type: &SqlType) -> Result<Json, PgError> {
+        if v.is_null() {
+            return Ok(v);
+        }
+        let type_name = match data_type {
+            SqlType::Custom(name, _) => name.0.last().map(fold).unwrap_or_default(),
+            _ => String::new(),
+        };
+        Ok(match data_type {
+            SqlType::Regclass => match &v {
+                Json::String(name) => json!(self.regclass(name).ok_or_else(|| PgError::new("42P01", format!("relation \"{}\" does not exist", name)))?),
+                _ => v,
+            },
+            SqlType::Int(_) | SqlType::Integer(_) | SqlType::BigInt(_) | SqlType::SmallInt(_) | SqlType::Int2(_) | SqlType::Int4(_) | SqlType::Int8(_) => {
+                let invalid = || PgError::new("22P02", format!("invalid input syntax for type integer: \"{}\"", text(&v).unwrap_or_default()));
+                json!(number(&v).filter(|n| n.fract() == 0.0).ok_or_else(invalid)? as i64)
+            }
+            SqlType::Custom(..) if matches!(type_name.as_str(), "oid" | "regproc" | "regnamespace" | "regrole") => match number(&v) {
+                Some(n) => json!(n as i64),
+                None => v,
+            },
+            SqlType::Text | SqlType::Varchar(_) | SqlType::Char(_) => json!(text(&v)),
+            SqlType::Custom(..) if type_name == "name" => json!(text(&v)),
+            SqlType::Boolean | SqlType::Bool => match &v {
+                Json::Bool(_) => v,
+                other => json!(text(other).as_deref().and_then(results::parse_bool).ok_or_else(|| PgError::new("22P02", "invalid input syntax for type boolean"))?),
+            },
+            _ => v,
+        })
+    }
+
+    fn function(&self, f: &Function, frame: &Frame) -> Result<Json, PgError> {
+        let name = f.name.0.last().map(fold).unwrap_or_default();
+        let mut args = Vec::new();
+        match &f.args {
+            FunctionArguments::None => {}
+            FunctionArguments::List(list) => {
+                for arg in &list.args {
+                    match arg {
+                        FunctionArg::Unnamed(FunctionArgExpr::Expr(e)) => args.push(self.eval(e, frame)?),
+                        _ => return Err(unsupported(f)),
+                    }
+                }
+            }
+            FunctionArguments::Subquery(_) => return Err(unsupported(f)),
+        }
+        let arg = |i: usize| args.get(i).cloned().unwrap_or(Json::Null);
+        Ok(match name.as_str() {
+            "version" => json!(format!("PostgreSQL {}", crate::SERVER_VERSION)),
+            "current_database" | "current_catalog" => json!(DATABASE),
+            "current_schema" => json!("public"),
+            "current_user" | "session_user" | "user" | "current_role" => json!(self.session.user),
+            "pg_backend_pid" => json!(self.session.process_id),
+            "current_setting" => {
+                let name = text(&arg(0)).unwrap_or_default();
+                // current_setting(name, true) answers NULL for an unknown setting
+                match self.session.setting(&name) {
+                    Some((_, value)) => json!(value),
+                    None if arg(1) == json!(true) => Json::Null,
+                    None => return Err(unrecognized_setting(&name)),
+                }
+            }
+            "pg_encoding_to_char" => json!("UTF8"),
+            "pg_table_is_visible" | "pg_type_is_visible" | "has_table_privilege" | "has_schema_privilege" | "has_database_privilege" => json!(true),
+            "pg_get_userbyid" => match number(&arg(0)) {
+                Some(oid) if oid as i64 == OWNER_OID => json!(OWNER),
+                Some(oid) => json!(format!("unknown (OID={})", oid)),
+                None => Json::Null,
+            },
+            "format_type" => match number(&arg(0)) {
+                Some(oid) => json!(format_type(oid as i64)),
+                None => Json::Null,
+            },
+            "pg_get_indexdef" => match number(&arg(0)).and_then(|oid| self.indexes.iter().find(|x| x.oid == oid as i64)) {
+                Some(x) => {
+                    let table = &self.tables[x.table];
+                    let unique = if x.unique { "UNIQUE " } else { "" };
+                    json!(format!("CREATE {}INDEX {} ON public.{} USING btree ({})", unique, x.name, table.name, table.columns[x.column].name))
+                }
+                None => Json::Null,
+            },
+            "pg_get_expr" | "obj_description" | "col_description" | "shobj_description" => Json::Null,
+            "coalesce" => args.into_iter().find(|v| !v.is_null()).unwrap_or(Json::Null),
+            "lower" => json!(text(&arg(0)).map(|s| s.to_lowercase())),
+            "upper" => json!(text(&arg(0)).map(|s| s.to_uppercase())),
+            "count" => return Err(PgError::new("42803", "aggregate functions are not allowed here")),
+            other => return Err(PgError::new("42883", format!("function {}() does not exist", other))),
+        })
+    }
+}
+
+fn pg_type() -> Rows {
+    let mut rows = Vec::new();
+    for (oid, name, len, category, array, _) in TYPES {
+        let by_value = (1..=8).contains(len);
+        let collation = if *category == "S" && *name != "char" && *name != "name" { DEFAULT_COLLATION_OID } else { 0 };
+        rows.push(vec![
+            json!(oid), json!(name), json!(PG_CATALOG_OID), json!(OWNER_OID), json!(len), json!(by_value), json!("b"), json!(category),
+            json!(true), json!(","), json!(0), json!(0), json!(array), json!(0), json!(-1), json!(false), json!(0), json!(collation),
+        ]);
+        rows.push(vec![
+            json!(array), json!(format!("_{}", name)), json!(PG_CATALOG_OID), json!(OWNER_OID), json!(-1), json!(false), json!("b"), json!("A"),
+            json!(true), json!(","), json!(0), json!(oid), json!(0), json!(0), json!(-1), json!(false), json!(0), json!(collation),
+        ]);
+    }
+    Rows::new(
+        &[
+            "oid", "typname", "typnamespace", "typowner", "typlen", "typbyval", "typtype", "typcategory",
+            "typisdefined", "typdelim", "typrelid", "typelem", "typarray", "typbasetype", "typtypmod", "typnotnull", "typndims", "typcollation",
+        ],
+        rows,
+    )
+}
+
+/// Whether column `i` of `table` can't hold NULL
+fn not_null(table: &TableSchema, i: usize) -> bool {
+    let column = &table.columns[i];
+    table.pk.as_ref() == Some(&column.name) || column.constraints.iter().any(|c| matches!(c, ColumnConstraint::NotNull | ColumnConstraint::PrimaryKey))
+}
+
+/// Type OID of column `i` of `table`
+fn column_type(table: &TableSchema, i: usize) -> i64 {
+    i64::from(results::type_oid(&table.columns[i].data_type))
+}
+
+/// The name of type `oid` as SQL writes it, as `format_type()` gives it
+fn format_type(oid: i64) -> String {
+    for (base, _, _, _, array, name) in TYPES {
+        if *base == oid {
+            return name.to_string();
+        }
+        if *array == oid {
+            return format!("{}[]", name);
+        }
+    }
+    "???".into()
+}
+
+/// Output column name of an unaliased expression, as Postgres picks it
+fn column_name(expr: &Expr) -> String {
+    match expr {
+        Expr::Identifier(ident) => fold(ident),
+        Expr::CompoundIdentifier(idents) => idents.last().map(fold).unwrap_or_default(),
+        Expr::Function(f) => f.name.0.last().map(fold).unwrap_or_default(),
+        Expr::Nested(e) | Expr::Collate { expr: e, .. } => column_name(e),
+        Expr::Cast { expr, data_type, .. } => match column_name(expr).as_str() {
+            "?column?" => data_type.to_string().to_lowercase(),
+            name => name.to_string(),
+        },
+        Expr::Case { .. } => "case".into(),
+        Expr::Exists { .. } => "exists".into(),
+        _ => "?column?".into(),
+    }
+}
+
+fn item_expr(item: &SelectItem) -> Option<&Expr> {
+    match item {
+        SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } => Some(expr),
+        SelectItem::Wildcard(_) | SelectItem::QualifiedWildcard(..) => None,
+    }
+}
+
+/// A column of `source` that `expr` reads, which without GROUP BY has no one value
+/// beside an aggregate
+fn ungrouped_column(expr: &Expr, source: &Rows) -> Option<String> {
+    let found = visit_expressions(expr, |e| {
+        let name = match e {
+            Expr::Identifier(ident) => fold(ident),
+            Expr::CompoundIdentifier(parts) => parts.last().map(fold).unwrap_or_default(),
+            _ => return ControlFlow::Continue(()),
+        };
+        match source.columns.contains(&name) {
+            true => ControlFlow::Break(name),
+            false => ControlFlow::Continue(()),
+        }
+    });
+    found.break_value()
+}
+
+/// `l op r` for one of + - * / %: exact on integers as Postgres' bigint is, and in
+/// double precision otherwise
+fn arithmetic(op: &str, l: &Json, r: &Json) -> Result<Json, PgError> {
+    if l.is_null() || r.is_null() {
+        return Ok(Json::Null);
+    }
+    let divides = matches!(op, "/" | "%");
+    // A string is read as a number, as with `c.oid + '1'`
+    let integer = |v: &Json| match v {
+        Json::String(s) => s.trim().parse::<i64>().ok(),
+        v => v.as_i64(),
+    };
+    if let (Some(a), Some(b)) = (integer(l), integer(r)) {
+        if divides && b == 0 {
+            return Err(PgError::new("22012", "division by zero"));
+        }
+        let value = match op {
+            "+" => a.checked_add(b),
+            "-" => a.checked_sub(b),
+            "*" => a.checked_mul(b),
+            "/" => a.checked_div(b),
+            _ => a.checked_rem(b),
+        };
+        return value.map(|v| json!(v)).ok_or_else(|| PgError::new("22003", "bigint out of range"));
+    }
+    let (Some(a), Some(b)) = (number(l), number(r)) else { return Ok(Json::Null) };
+    if divides && b == 0.0 {
+        return Err(PgError::new("22012", "division by zero"));
+    }
+    Ok(json!(match op {
+        "+" => a + b,
+        "-" => a - b,
+        "*" => a * b,
+        "/" => a / b,
+        _ => a % b,
+    }))
+}
+
+fn is_count(f: &Function) -> bool {
+    f.name.0.last().map(fold).as_deref() == Some("count")
+}
+
+fn literal(value: &Value) -> Result<Json, PgError> {
+    Ok(match value {
+        Value::Number(n, _) => match n.parse::<i64>() {
+            Ok(i) => json!(i),
+            Err(_) => serde_json::from_str(n).map_err(|_| PgError::new("22P02", format!("invalid number {}", n)))?,
+        },
+        Value::SingleQuotedString(s) | Value::EscapedStringLiteral(s) => json!(s),
+        Value::DollarQuotedString(s) => json!(s.value),
+        Value::Boolean(b) => json!(b),
+        Value::Null => Json::Null,
+        Value::Placeholder(p) => return Err(PgError::new("42P02", format!("there is no parameter {}", p))),
+        other => return Err(unsupported(other)),
+    })
+}
+
+/// Text form of a value; `None` for NULL
+fn text(v: &Json) -> Option<String> {
+    results::text_value(results::TEXT_OID, Some(v))
+}
+
+fn number(v: &Json) -> Option<f64> {
+    match v {
+        Json::Number(n) => n.as_f64(),
+        Json::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// How `a` compares to `b`, reading a string as a number or boolean when the other
+/// side is one (as with `c.oid = '16384'`); `None` if either is NULL
+fn compare(a: &Json, b: &Json) -> Option<Ordering> {
+    match (a, b) {
+        (Json::Null, _) | (_, Json::Null) => None,
+        (Json::Number(_), Json::Number(_) | Json::String(_)) | (Json::String(_), Json::Number(_)) => match (number(a), number(b)) {
+            (Some(x), Some(y)) => x.partial_cmp(&y),
+            _ => Some(text(a).cmp(&text(b))),
+        },
+        (Json::Bool(x), Json::Bool(y)) => Some(x.cmp(y)),
+        (Json::Bool(x), Json::String(s)) => results::parse_bool(s).map(|y| x.cmp(&y)),
+        (Json::String(s), Json::Bool(y)) => results::parse_bool(s).map(|x| x.cmp(y)),
+        _ => Some(text(a).cmp(&text(b))),
+    }
+}
+
+/// `v [NOT] IN (values)`, NULL when no value matches but one is NULL
+fn in_values(v: &Json, values: &[Json], negated: bool) -> Json {
+    if v.is_null() {
+        return Json::Null;
+    }
+    if values.iter().any(|x| compare(v, x) == Some(Ordering::Equal)) {
+        return json!(!negated);
+    }
+    if values.iter().any(Json::is_null) {
+        return Json::Null;
+    }
+    json!(negated)
+}
@@ -0,0 +1,133 @@
+//! The connections a server has open.
+//!
+//! Every client that gets past its startup packet registers here for as long as
+//! it stays connected, and is given the process id it is known by
+//! (`pg_backend_pid()`). A registry made with a limit refuses clients once that
+//! many are connected, which the server answers with Postgres' "too many clients"
+//! error before any authentication.
+//!
+//! Each connection also gets a random secret key, sent to the client in
+//! BackendKeyData. A CancelRequest giving a connection's process id and key
+//! cancels the statement it is running (see `Registration::statement`); one that
+//! gets either wrong is ignored, as is one for a connection running nothing.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use parking_lot::Mutex;
+use tonledb_core::deadline::Canceller;
+
+/// One open connection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Connection {
+    pub process_id: u32,
+    pub user: String,
+    pub application_name: String,
+}
+
+/// A registered connection and what cancels it
+#[derive(Debug)]
+struct Entry {
+    connection: Connection,
+    secret_key: i32,
+    /// Cancels the statement running, or the last one that ran
+    statement: Canceller,
+}
+
+/// Open connections, up to a limit
+#[derive(Debug)]
+pub struct ConnectionRegistry {
+    max_connections: Option<usize>,
+    next_process_id: AtomicU32,
+    open: Mutex<BTreeMap<u32, Entry>>,
+}
+
+impl Default for ConnectionRegistry {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl ConnectionRegistry {
+    /// A registry taking up to `max_connections` at once; `None` for no limit
+    pub fn new(max_connections: Option<usize>) -> Self {
+        Self { max_connections, next_process_id: AtomicU32::new(1), open: Mutex::new(BTreeMap::new()) }
+    }
+
+    pub fn max_connections(&self) -> Option<usize> {
+        self.max_connections
+    }
+
+    /// Register a connection of `user`; `None` when the registry is full. The
+    /// connection stays registered until the `Registration` is dropped.
+    pub fn register(self: &Arc<Self>, user: &str, application_name: &str) -> Option<Registration> {
+        let mut open = self.open.lock();
+        if self.max_connections.is_some_and(|max| open.len() >= max) {
+            return None;
+        }
+        let process_id = self.next_process_id.fetch_add(1, Ordering::Relaxed);
+        let connection = Connection { process_id, user: user.to_string(), application_name: application_name.to_string() };
+        let secret_key = rand::random();
+        open.insert(process_id, Entry { connection, secret_key, statement: Canceller::new() });
+        Some(Registration { registry: self.clone(), process_id, secret_key })
+    }
+
+    /// The open connections, oldest first
+    pub fn connections(&self) -> Vec<Connection> {
+        self.open.lock().values().map(|e| e.connection.clone()).collect()
+    }
+
+    /// Cancel the statement connection `process_id` is running, if `secret_key` is
+    /// its key; whether it was
+    pub fn cancel(&self, process_id: u32, secret_key: i32) -> bool {
+        match self.open.lock().get(&process_id) {
+            Some(entry) if entry.secret_key == secret_key => {
+                entry.statement.cancel();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.open.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A registered connection; dropping it frees its place
+#[derive(Debug)]
+pub struct Registration {
+    registry: Arc<ConnectionRegistry>,
+    process_id: u32,
+    secret_key: i32,
+}
+
+impl Registration {
+    pub fn process_id(&self) -> u32 {
+        self.process_id
+    }
+
+    pub fn secret_key(&self) -> i32 {
+        self.secret_key
+    }
+
+    /// A canceller for the statement about to run, which a CancelRequest for this
+    /// connection cancels until the next statement starts
+    pub fn statement(&self) -> Canceller {
+        let canceller = Canceller::new();
+        if let Some(entry) = self.registry.open.lock().get_mut(&self.process_id) {
+            entry.statement = canceller.clone();
+        }
+        canceller
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.registry.open.lock().remove(&self.process_id);
+    }
+}
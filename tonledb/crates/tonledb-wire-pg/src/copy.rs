@@ -0,0 +1,515 @@
+//! `COPY ... FROM STDIN` and `COPY ... TO STDOUT`, in text or CSV format.
+//!
+//! COPY FROM loads the rows a client streams in CopyData messages into a table with
+//! `tonledb_sql::bulk::BulkInsert`, so they are committed a batch at a time and
+//! a COPY that fails part way keeps the batches committed before the failure. COPY
+//! TO streams a table from one snapshot, or the rows of a query, a chunk of
+//! CopyData at a time. Binary format, and files or programs on the server, aren't
+//! supported. Both check the request's deadline (`tonledb_core::deadline`) a row
+//! at a time, so a cancelled COPY stops.
+
+use serde_json::Value as Json;
+use sqlparser::ast::{CopyLegacyCsvOption, CopyLegacyOption, CopyOption, CopySource, CopyTarget, Ident, Statement};
+use tonledb_core::{deadline, Column, DataType, Db, TableSchema};
+use tonledb_sql::bulk::BulkInsert;
+use tonledb_sql::rows;
+use crate::protocol;
+use crate::results::{self, PgError};
+
+/// CopyData appended to the send buffer before it goes out
+const CHUNK_BYTES: usize = 64 << 10;
+
+type Rows = Box<dyn Iterator<Item = Result<Vec<Option<String>>, PgError>> + Send>;
+
+/// What a COPY statement leaves the connection doing
+pub(crate) enum Copying<'a> {
+    /// Reading CopyData from the client
+    In(Box<CopyIn<'a>>),
+    /// Sending CopyData to the client
+    Out(CopyOut),
+}
+
+/// Start the COPY `stmt`, appending CopyInResponse or CopyOutResponse to `buf`
+pub(crate) fn start<'a>(db: &'a Db, stmt: &Statement, buf: &mut Vec<u8>) -> Result<Copying<'a>, PgError> {
+    let Statement::Copy { source, to, target, options, legacy_options, values } = stmt else {
+        return Err(PgError::new("42601", "not a COPY statement"));
+    };
+    let format = Format::from_options(options, legacy_options)?;
+    match (to, target) {
+        (true, CopyTarget::Stdout) => copy_out(db, source, format, buf).map(Copying::Out),
+        (false, CopyTarget::Stdin) => {
+            if !values.is_empty() {
+                return Err(PgError::new("0A000", "COPY FROM STDIN takes its rows in CopyData messages, not in the query string"));
+            }
+            let CopySource::Table { table_name, columns } = source else {
+                return Err(PgError::new("42601", "COPY FROM needs a table"));
+            };
+            let table = table_schema(db, &table_name.to_string())?;
+            let columns = target_columns(&table, columns)?;
+            protocol::copy_in_response(buf, columns.len());
+            Ok(Copying::In(Box::new(CopyIn { bulk: BulkInsert::new(db, table), columns, format, pending: Vec::new(), records: 0, ended: false, failed: None })))
+        }
+        _ => Err(PgError::new("0A000", "COPY only reads from STDIN and writes to STDOUT")),
+    }
+}
+
+/// The table `stmt` loads, if it is a COPY FROM
+pub(crate) fn loaded_table(stmt: &Statement) -> Option<String> {
+    match stmt {
+        Statement::Copy { source: CopySource::Table { table_name, .. }, to: false, .. } => Some(table_name.to_string()),
+        _ => None,
+    }
+}
+
+fn table_schema(db: &Db, name: &str) -> Result<TableSchema, PgError> {
+    db.catalog.read().tables.get(name).cloned().ok_or_else(|| PgError::new("42P01", format!("relation \"{}\" does not exist", name)))
+}
+
+/// The columns a COPY names, or all of the table's
+fn target_columns(table: &TableSchema, names: &[Ident]) -> Result<Vec<Column>, PgError> {
+    if names.is_empty() {
+        return Ok(table.columns.clone());
+    }
+    names
+        .iter()
+        .map(|n| {
+            table.columns.iter().find(|c| c.name == n.value).cloned().ok_or_else(|| {
+                PgError::new("42703", format!("column \"{}\" of relation \"{}\" does not exist", n.value, table.name))
+            })
+        })
+        .collect()
+}
+
+fn copy_out(db: &Db, source: &CopySource, format: Format, buf: &mut Vec<u8>) -> Result<CopyOut, PgError> {
+    let (names, rows): (Vec<String>, Rows) = match source {
+        CopySource::Table { table_name, columns } => {
+            let table = table_schema(db, &table_name.to_string())?;
+            let columns = target_columns(&table, columns)?;
+            let scan = db.storage.snapshot()?.scan_prefix(&rows::data_space(), rows::table_prefix(&table.name).as_bytes())?;
+            let names = columns.iter().map(|c| c.name.clone()).collect();
+            let rows = scan.map(move |(_, v)| -> Result<Vec<Option<String>>, PgError> {
+                let row: Json = serde_json::from_slice(&v).map_err(|e| PgError::new("XX001", format!("row of table {}: {}", table.name, e)))?;
+                Ok(columns.iter().map(|c| results::text_value(results::type_oid(&c.data_type), row.get(&c.name))).collect())
+            });
+            (names, Box::new(rows))
+        }
+        CopySource::Query(query) => {
+            let stmt = Statement::Query(query.clone());
+            let value = tonledb_sql::execute_sql(db, &stmt.to_string())?;
+            let result = results::result_set(db, &stmt, value);
+            (result.fields.iter().map(|f| f.name.clone()).collect(), Box::new(result.rows.into_iter().map(Ok)))
+        }
+    };
+    protocol::copy_out_response(buf, names.len());
+    if format.header {
+        let mut line = Vec::new();
+        format.write_row(&names.into_iter().map(Some).collect::<Vec<_>>(), &mut line);
+        protocol::copy_data(buf, &line);
+    }
+    Ok(CopyOut { rows, format, sent: 0 })
+}
+
+/// Rows of a COPY TO still to send
+pub(crate) struct CopyOut {
+    rows: Rows,
+    format: Format,
+    sent: u64,
+}
+
+impl CopyOut {
+    /// Append a CopyData for each of the next rows to `buf`, until it holds about
+    /// `CHUNK_BYTES`; after the last row, CopyDone and CommandComplete. Returns
+    /// whether rows are left.
+    pub(crate) fn next_chunk(&mut self, buf: &mut Vec<u8>) -> Result<bool, PgError> {
+        let mut line = Vec::new();
+        while buf.len() < CHUNK_BYTES {
+            let Some(row) = self.rows.next() else {
+                protocol::copy_done(buf);
+                protocol::command_complete(buf, &format!("COPY {}", self.sent));
+                return Ok(false);
+            };
+            deadline::check()?;
+            line.clear();
+            self.format.write_row(&row?, &mut line);
+            protocol::copy_data(buf, &line);
+            self.sent += 1;
+        }
+        Ok(true)
+    }
+}
+
+/// A COPY FROM under way
+pub(crate) struct CopyIn<'a> {
+    bulk: BulkInsert<'a>,
+    columns: Vec<Column>,
+    format: Format,
+    /// Received bytes not yet making up a whole record
+    pending: Vec<u8>,
+    /// Records read so far, counting a header
+    records: u64,
+    /// Set by the `\.` end-of-data line; anything after it is ignored
+    ended: bool,
+    /// The first error; data after it is ignored and it is reported at CopyDone
+    failed: Option<PgError>,
+}
+
+impl CopyIn<'_> {
+    /// The table being loaded
+    pub(crate) fn table(&self) -> &str {
+        &self.bulk.table().name
+    }
+
+    /// Take the bytes of a CopyData message
+    pub(crate) fn data(&mut self, data: &[u8]) {
+        if self.failed.is_some() || self.ended {
+            return;
+        }
+        self.pending.extend_from_slice(data);
+        self.read_records(false);
+    }
+
+    /// The client has sent everything: commit the rows and append CommandComplete,
+    /// or the error that stopped the COPY, to `buf`; whether the COPY succeeded
+    pub(crate) fn done(mut self, buf: &mut Vec<u8>) -> bool {
+        self.read_records(true);
+        let result = match self.failed {
+            Some(e) => Err(e),
+            None => self.bulk.finish().map_err(PgError::from),
+        };
+        match &result {
+            Ok(n) => protocol::command_complete(buf, &format!("COPY {}", n)),
+            Err(e) => protocol::error_response(buf, e.code, &e.message),
+        }
+        result.is_ok()
+    }
+
+    /// The client gave up with `reason`; rows not yet committed are dropped
+    pub(crate) fn fail(self, reason: &str, buf: &mut Vec<u8>) {
+        protocol::error_response(buf, "57014", &format!("COPY from stdin failed: {}", reason));
+    }
+
+    /// Load every whole record in `pending`, and at the end of the data whatever is
+    /// left after the last newline too
+    fn read_records(&mut self, at_end: bool) {
+        let mut start = 0;
+        while !self.ended && self.failed.is_none() {
+            let rest = &self.pending[start..];
+            let (record, next) = match self.format.record_end(rest) {
+                Some(end) => (&rest[..end], start + end + 1),
+                None if at_end && !rest.is_empty() => (rest, self.pending.len()),
+                None => break,
+            };
+            let line = std::str::from_utf8(record).map(str::to_string);
+            start = next;
+            let result = line.map_err(|_| PgError::new("22021", "invalid byte sequence for encoding \"UTF8\"")).and_then(|l| self.record(&l));
+            if let Err(e) = result {
+                let context = format!("{} (COPY {}, line {})", e.message, self.bulk.table().name, self.records);
+                self.failed = Some(PgError::new(e.code, context));
+            }
+        }
+        self.pending.drain(..start);
+    }
+
+    fn record(&mut self, line: &str) -> Result<(), PgError> {
+        self.records += 1;
+        deadline::check()?;
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line == "\\." {
+            self.ended = true;
+            return Ok(());
+        }
+        if self.records == 1 && self.format.header {
+            return Ok(());
+        }
+        let cells = self.format.split(line);
+        if cells.len() < self.columns.len() {
+            return Err(PgError::new("22P04", format!("missing data for column \"{}\"", self.columns[cells.len()].name)));
+        }
+        if cells.len() > self.columns.len() {
+            return Err(PgError::new("22P04", "extra data after last expected column"));
+        }
+        let mut row = serde_json::Map::new();
+        for (column, cell) in self.columns.iter().zip(cells) {
+            let value = match cell {
+                Some(text) => parse_value(column, &text)?,
+                None => Json::Null,
+            };
+            row.insert(column.name.clone(), value);
+        }
+        self.bulk.push(&Json::Object(row)).map_err(PgError::from)
+    }
+}
+
+/// The value of a column of `column`'s type written as `text`
+fn parse_value(column: &Column, text: &str) -> Result<Json, PgError> {
+    let bad = |type_name: &str| PgError::new("22P02", format!("invalid input syntax for type {}: \"{}\"", type_name, text));
+    Ok(match column.data_type {
+        DataType::Integer => Json::from(text.trim().parse::<i64>().map_err(|_| bad("bigint"))?),
+        DataType::Float => text.trim().parse::<f64>().ok().and_then(serde_json::Number::from_f64).map(Json::Number).ok_or_else(|| bad("double precision"))?,
+        DataType::Decimal => Json::Number(serde_json::from_str(text.trim()).map_err(|_| bad("numeric"))?),
+        DataType::Boolean => Json::Bool(results::parse_bool(text).ok_or_else(|| bad("boolean"))?),
+        DataType::Text => Json::String(text.to_string()),
+        DataType::Json => serde_json::from_str(text).map_err(|_| bad("json"))?,
+    })
+}
+
+fn unsupported(what: &str) -> PgError {
+    PgError::new("0A000", format!("COPY {} is not supported", what))
+}
+
+/// How rows are written as lines of text
+#[derive(Debug, Clone)]
+struct Format {
+    csv: bool,
+    delimiter: u8,
+    /// How NULL is written
+    null: String,
+    header: bool,
+    quote: u8,
+    escape: u8,
+}
+
+impl Format {
+    fn from_options(options: &[CopyOption], legacy: &[CopyLegacyOption]) -> Result<Self, PgError> {
+        let mut csv = false;
+        let (mut delimiter, mut null, mut header, mut quote, mut escape) = (None, None, false, None, None);
+        for option in options {
+            match option {
+                CopyOption::Format(name) => match name.value.to_ascii_lowercase().as_str() {
+                    "text" => csv = false,
+                    "csv" => csv = true,
+                    "binary" => return Err(unsupported("in binary format")),
+                    other => return Err(PgError::new("22023", format!("COPY format \"{}\" not recognized", other))),
+                },
+                CopyOption::Delimiter(c) => delimiter = Some(*c),
+                CopyOption::Null(s) => null = Some(s.clone()),
+                CopyOption::Header(h) => header = *h,
+                CopyOption::Quote(c) => quote = Some(*c),
+                CopyOption::Escape(c) => escape = Some(*c),
+                CopyOption::Encoding(e) if e.eq_ignore_ascii_case("utf8") || e.eq_ignore_ascii_case("utf-8") => {}
+                CopyOption::Encoding(_) => return Err(unsupported("in an encoding other than UTF8")),
+                // Only a hint for Postgres' own storage
+                CopyOption::Freeze(_) => {}
+                CopyOption::ForceQuote(_) | CopyOption::ForceNotNull(_) | CopyOption::ForceNull(_) => return Err(unsupported("with FORCE options")),
+            }
+        }
+        for option in legacy {
+            match option {
+                CopyLegacyOption::Binary => return Err(unsupported("in binary format")),
+                CopyLegacyOption::Delimiter(c) => delimiter = Some(*c),
+                CopyLegacyOption::Null(s) => null = Some(s.clone()),
+                CopyLegacyOption::Csv(csv_options) => {
+                    csv = true;
+                    for o in csv_options {
+                        match o {
+                            CopyLegacyCsvOption::Header => header = true,
+                            CopyLegacyCsvOption::Quote(c) => quote = Some(*c),
+                            CopyLegacyCsvOption::Escape(c) => escape = Some(*c),
+                            CopyLegacyCsvOption::ForceQuote(_) | CopyLegacyCsvOption::ForceNotNull(_) => return Err(unsupported("with FORCE options")),
+                        }
+                    }
+                }
+            }
+        }
+        let byte = |c: char, what: &str| -> Result<u8, PgError> {
+            match u8::try_from(c) {
+                Ok(b) if b.is_ascii() && b != b'\n' && b != b'\r' => Ok(b),
+                _ => Err(PgError::new("22023", format!("COPY {} must be a single one-byte character", what))),
+            }
+        };
+        let delimiter = byte(delimiter.unwrap_or(if csv { ',' } else { '\t' }), "delimiter")?;
+        if !csv && delimiter == b'\\' {
+            return Err(PgError::new("22023", "COPY delimiter cannot be backslash"));
+        }
+        let quote = byte(quote.unwrap_or('"'), "quote")?;
+        let escape = match escape {
+            Some(c) => byte(c, "escape")?,
+            None => quote,
+        };
+        let null = null.unwrap_or_else(|| if csv { String::new() } else { "\\N".into() });
+        Ok(Self { csv, delimiter, null, header, quote, escape })
+    }
+
+    /// `cells` as one line, newline included
+    fn write_row(&self, cells: &[Option<String>], out: &mut Vec<u8>) {
+        for (i, cell) in cells.iter().enumerate() {
+            if i > 0 {
+                out.push(self.delimiter);
+            }
+            match cell {
+                None => out.extend_from_slice(self.null.as_bytes()),
+                Some(v) if self.csv => self.write_csv_field(v, out),
+                Some(v) => self.write_text_field(v, out),
+            }
+        }
+        out.push(b'\n');
+    }
+
+    fn write_text_field(&self, v: &str, out: &mut Vec<u8>) {
+        for b in v.bytes() {
+            match b {
+                b'\\' => out.extend_from_slice(b"\\\\"),
+                b'\n' => out.extend_from_slice(b"\\n"),
+                b'\r' => out.extend_from_slice(b"\\r"),
+                b'\t' => out.extend_from_slice(b"\\t"),
+                b if b == self.delimiter => out.extend_from_slice(&[b'\\', b]),
+                b => out.push(b),
+            }
+        }
+    }
+
+    /// Quoted if it could otherwise be mistaken for NULL or split
+    fn write_csv_field(&self, v: &str, out: &mut Vec<u8>) {
+        let special = |b: u8| b == self.delimiter || b == self.quote || b == b'\n' || b == b'\r';
+        if *v != self.null && !v.bytes().any(special) {
+            out.extend_from_slice(v.as_bytes());
+            return;
+        }
+        out.push(self.quote);
+        for b in v.bytes() {
+            if b == self.quote || b == self.escape {
+                out.push(self.escape);
+            }
+            out.push(b);
+        }
+        out.push(self.quote);
+    }
+
+    /// Index of the newline ending the first record of `data`, if all of it is there;
+    /// a CSV record goes on past newlines inside quotes
+    fn record_end(&self, data: &[u8]) -> Option<usize> {
+        if !self.csv {
+            return data.iter().position(|b| *b == b'\n');
+        }
+        let mut in_quotes = false;
+        let mut i = 0;
+        while i < data.len() {
+            let b = data[i];
+            if in_quotes {
+                if b == self.escape && self.escape != self.quote {
+                    i += 1;
+                } else if b == self.quote {
+                    if self.escape == self.quote && data.get(i + 1) == Some(&self.quote) {
+                        i += 1;
+                    } else {
+                        in_quotes = false;
+                    }
+                }
+            } else if b == self.quote {
+                in_quotes = true;
+            } else if b == b'\n' {
+                return Some(i);
+            }
+            i += 1;
+        }
+        None
+    }
+
+    /// Cells of a record; `None` for NULL
+    fn split(&self, line: &str) -> Vec<Option<String>> {
+        if self.csv {
+            self.split_csv(line)
+        } else {
+            self.split_text(line)
+        }
+    }
+
+    fn split_text(&self, line: &str) -> Vec<Option<String>> {
+        let delimiter = self.delimiter as char;
+        let mut raw = vec![String::new()];
+        let mut chars = line.chars();
+        while let Some(c) = chars.next() {
+            let field = raw.last_mut().expect("never empty");
+            if c == '\\' {
+                field.push(c);
+                field.extend(chars.next());
+            } else if c == delimiter {
+                raw.push(String::new());
+            } else {
+                field.push(c);
+            }
+        }
+        raw.into_iter().map(|f| if f == self.null { None } else { Some(unescape_text(&f)) }).collect()
+    }
+
+    fn split_csv(&self, line: &str) -> Vec<Option<String>> {
+        let (delimiter, quote, escape) = (self.delimiter as char, self.quote as char, self.escape as char);
+        let mut cells = Vec::new();
+        let mut field = String::new();
+        // Whether any of the field was quoted: a quoted empty string isn't NULL
+        let mut quoted = false;
+        let mut in_quotes = false;
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            if in_quotes {
+                if c == escape && chars.peek().is_some_and(|n| *n == quote || *n == escape) {
+                    field.extend(chars.next());
+                } else if c == quote {
+                    in_quotes = false;
+                } else {
+                    field.push(c);
+                }
+            } else if c == quote {
+                in_quotes = true;
+                quoted = true;
+            } else if c == delimiter {
+                cells.push(if !quoted && field == self.null { None } else { Some(std::mem::take(&mut field)) });
+                field.clear();
+                quoted = false;
+            } else {
+                field.push(c);
+            }
+        }
+        cells.push(if !quoted && field == self.null { None } else { Some(field) });
+        cells
+    }
+}
+
+/// A text-format field with its backslash escapes undone
+fn unescape_text(raw: &str) -> String {
+    let mut out = String::with_capacity(raw.len());
+    let mut chars = raw.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('b') => out.push('\u{8}'),
+            Some('f') => out.push('\u{c}'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('v') => out.push('\u{b}'),
+            Some(d @ '0'..='7') => {
+                let mut code = d.to_digit(8).expect("octal digit");
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(digit) => {
+                            code = code * 8 + digit;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                out.extend(char::from_u32(code));
+            }
+            Some('x') if chars.peek().is_some_and(|c| c.is_ascii_hexdigit()) => {
+                let mut code = 0;
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(16)) {
+                        Some(digit) => {
+                            code = code * 16 + digit;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                out.extend(char::from_u32(code));
+            }
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
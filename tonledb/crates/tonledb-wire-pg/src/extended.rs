@@ -0,0 +1,472 @@
+//! The extended query protocol: statements prepared with Parse, bound to their
+//! parameters with Bind into portals, described, and run with Execute.
+//!
+//! Parse takes the parsed statement from the statement cache (see `cache`), and
+//! Bind replaces each `$n` placeholder in a copy of it with its parameter as a
+//! literal. A parameter's declared type decides the literal: a number or boolean
+//! for numeric and boolean types, a string for anything else; a parameter of
+//! unspecified type is a number if it reads as one. Binary parameters of the
+//! integer, float and boolean types are decoded; other binary parameters are taken
+//! as UTF-8 text.
+//!
+//! Describing a prepared statement doesn't run it: its columns come from the catalog
+//! (see `results::described_fields`). A portal runs its statement the first time it
+//! is described or executed and keeps the rows, so an Execute with a row limit
+//! resumes where the last one stopped.
+//! Results are text, except that `int8`, `float8`, `bool`, `text` and `json` columns
+//! are sent in binary when the client asks for it.
+//!
+//! Session statements (`SET`, `BEGIN`, `COMMIT`, ...) change the `SessionState`
+//! when executed; describing them gives no columns and doesn't run them.
+
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::sync::Arc;
+use sqlparser::ast::{visit_expressions_mut, DiscardObject, Expr, Statement, UnaryOperator, Value};
+use tonledb_core::Db;
+use crate::cache::{ConnectionCache, Parsed, ParsedQuery, StatementCache};
+use crate::catalog;
+use crate::protocol::{self, FieldDescription};
+use crate::results::{self, PgError, ResultSet, BOOL_OID, FLOAT8_OID, INT8_OID, JSON_OID, NUMERIC_OID, TEXT_OID};
+use crate::session::{self, SessionState};
+
+const INT2_OID: u32 = 21;
+const INT4_OID: u32 = 23;
+const FLOAT4_OID: u32 = 700;
+
+/// A statement prepared with Parse
+#[derive(Debug, Clone)]
+pub struct Prepared {
+    pub query: String,
+    /// One per parameter; 0 where the client left the type unspecified
+    pub param_types: Vec<u32>,
+    /// No statements for a query string holding none
+    parsed: Parsed,
+}
+
+impl Prepared {
+    fn statement(&self) -> Option<&Statement> {
+        self.parsed.statements.first()
+    }
+}
+
+/// A prepared statement bound to its parameters
+#[derive(Debug)]
+struct Portal {
+    statement: Option<Statement>,
+    /// What `statement` was bound from, which keeps its plan
+    parsed: Parsed,
+    result_formats: Vec<i16>,
+    /// Set once the statement has run
+    result: Option<ResultSet>,
+    /// Rows of `result` already sent
+    sent: usize,
+}
+
+/// Settings, prepared statements and portals of one connection
+#[derive(Debug)]
+pub struct Session {
+    pub state: SessionState,
+    statements: HashMap<String, Prepared>,
+    portals: HashMap<String, Portal>,
+    cache: ConnectionCache,
+}
+
+impl Session {
+    /// A session parsing through `cache`, the server's statement cache
+    pub fn new(state: SessionState, cache: Arc<StatementCache>) -> Self {
+        Self { state, statements: HashMap::new(), portals: HashMap::new(), cache: ConnectionCache::new(cache) }
+    }
+
+    /// The statements of query string `sql`, parsed or from the statement cache
+    pub(crate) fn statements_of(&mut self, sql: &str) -> Result<Parsed, PgError> {
+        self.cache.parse(sql)
+    }
+
+    /// Prepare `query` as statement `name`; the unnamed statement (`""`) is replaced
+    pub fn parse(&mut self, name: String, query: String, param_types: Vec<u32>, buf: &mut Vec<u8>) -> Result<(), PgError> {
+        if !name.is_empty() && self.statements.contains_key(&name) {
+            return Err(PgError::new("42P05", format!("prepared statement \"{}\" already exists", name)));
+        }
+        let parsed = self.statements_of(&query)?;
+        if parsed.statements.len() > 1 {
+            return Err(PgError::new("42601", "cannot insert multiple commands into a prepared statement"));
+        }
+        let mut param_types = param_types;
+        param_types.resize(param_types.len().max(placeholder_count(&query)), 0);
+        self.statements.insert(name, Prepared { query, param_types, parsed });
+        protocol::parse_complete(buf);
+        Ok(())
+    }
+
+    /// Bind `params` to `statement` as portal `portal`; the unnamed portal is replaced
+    pub fn bind(
+        &mut self,
+        portal: String,
+        statement: &str,
+        param_formats: &[i16],
+        params: Vec<Option<Vec<u8>>>,
+        result_formats: Vec<i16>,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), PgError> {
+        if !portal.is_empty() && self.portals.contains_key(&portal) {
+            return Err(PgError::new("42P03", format!("portal \"{}\" already exists", portal)));
+        }
+        let prepared = self.statement(statement)?;
+        if params.len() != prepared.param_types.len() {
+            return Err(PgError::new(
+                "08P01",
+                format!("bind message supplies {} parameters, but prepared statement \"{}\" requires {}", params.len(), statement, prepared.param_types.len()),
+            ));
+        }
+        let mut literals = Vec::with_capacity(params.len());
+        for (i, (param, oid)) in params.iter().zip(&prepared.param_types).enumerate() {
+            let binary = format_of(param_formats, i) == 1;
+            let text = param.as_deref().map(|raw| if binary { decode_binary(*oid, raw) } else { utf8(raw) }).transpose()?;
+            literals.push(literal(*oid, text.as_deref())?);
+        }
+        let statement = prepared.statement().map(|s| bind_parameters(s, &literals));
+        let parsed = prepared.parsed.clone();
+        self.portals.insert(portal, Portal { statement, parsed, result_formats, result: None, sent: 0 });
+        protocol::bind_complete(buf);
+        Ok(())
+    }
+
+    /// Describe statement (`b'S'`) or portal (`b'P'`) `name`
+    pub fn describe(&mut self, db: &Db, kind: u8, name: &str, buf: &mut Vec<u8>) -> Result<(), PgError> {
+        match kind {
+            b'S' => {
+                let prepared = self.statement(name)?;
+                let types: Vec<u32> = prepared.param_types.iter().map(|t| if *t == 0 { TEXT_OID } else { *t }).collect();
+                protocol::parameter_description(buf, &types);
+                let Some(statement) = prepared.statement().filter(|s| !session::is_session_statement(s)) else {
+                    protocol::no_data(buf);
+                    return Ok(());
+                };
+                self.state.check_runnable(statement)?;
+                let fields = if catalog::is_catalog_query(statement) {
+                    // Answered from the catalog alone, so asking it costs no more than planning
+                    let nulls = vec![Expr::Value(Value::Null); prepared.param_types.len()];
+                    Some(catalog::answer(db, &self.state, &bind_parameters(statement, &nulls))?.fields)
+                } else {
+                    results::described_fields(db, statement)
+                };
+                match fields {
+                    Some(fields) => protocol::row_description(buf, &fields),
+                    None => protocol::no_data(buf),
+                }
+            }
+            b'P' => {
+                let portal = portal(&mut self.portals, name)?;
+                if portal.statement.as_ref().is_some_and(session::is_session_statement) {
+                    protocol::no_data(buf);
+                    return Ok(());
+                }
+                run_portal(db, &self.state, portal)?;
+                match &portal.result {
+                    Some(result) => protocol::row_description(buf, &with_formats(&result.fields, &portal.result_formats)),
+                    None => protocol::no_data(buf),
+                }
+            }
+            other => return Err(PgError::new("08P01", format!("invalid DESCRIBE message subtype {}", other as char))),
+        }
+        Ok(())
+    }
+
+    /// Send up to `max_rows` more rows of `portal` (all of them if 0)
+    pub fn execute(&mut self, db: &Db, portal: &str, max_rows: i32, buf: &mut Vec<u8>) -> Result<(), PgError> {
+        let portal = self::portal(&mut self.portals, portal)?;
+        if let Some(statement) = &portal.statement {
+            self.state.check_runnable(statement)?;
+            if session::is_session_statement(statement) {
+                let statement = statement.clone();
+                let tag = self.session_statement(&statement, buf)?;
+                protocol::command_complete(buf, &tag);
+                return Ok(());
+            }
+        }
+        run_portal(db, &self.state, portal)?;
+        let Some(result) = &portal.result else {
+            protocol::empty_query_response(buf);
+            return Ok(());
+        };
+        let fields = with_formats(&result.fields, &portal.result_formats);
+        let remaining = result.rows.len() - portal.sent;
+        let end = portal.sent + if max_rows > 0 { remaining.min(max_rows as usize) } else { remaining };
+        for row in &result.rows[portal.sent..end] {
+            protocol::data_row(buf, &encode_row(&fields, row)?);
+        }
+        if end < result.rows.len() {
+            protocol::portal_suspended(buf);
+        } else {
+            protocol::command_complete(buf, &result.tag);
+        }
+        portal.sent = end;
+        Ok(())
+    }
+
+    /// Close statement (`b'S'`) or portal (`b'P'`) `name`; closing one that doesn't
+    /// exist is not an error
+    pub fn close(&mut self, kind: u8, name: &str, buf: &mut Vec<u8>) -> Result<(), PgError> {
+        match kind {
+            b'S' => {
+                self.statements.remove(name);
+            }
+            b'P' => {
+                self.portals.remove(name);
+            }
+            other => return Err(PgError::new("08P01", format!("invalid CLOSE message subtype {}", other as char))),
+        }
+        protocol::close_complete(buf);
+        Ok(())
+    }
+
+    /// Run `stmt`, a statement of `session::is_session_statement`; its command tag.
+    /// `DISCARD ALL` also closes every prepared statement and portal.
+    pub fn session_statement(&mut self, stmt: &Statement, buf: &mut Vec<u8>) -> Result<String, PgError> {
+        let tag = self.state.answer(stmt, buf)?;
+        if let Statement::Discard { object_type: DiscardObject::ALL } = stmt {
+            self.statements.clear();
+            self.portals.clear();
+        }
+        Ok(tag)
+    }
+
+    fn statement(&self, name: &str) -> Result<&Prepared, PgError> {
+        self.statements.get(name).ok_or_else(|| PgError::new("26000", format!("prepared statement \"{}\" does not exist", name)))
+    }
+
+}
+
+fn portal<'a>(portals: &'a mut HashMap<String, Portal>, name: &str) -> Result<&'a mut Portal, PgError> {
+    portals.get_mut(name).ok_or_else(|| PgError::new("34000", format!("portal \"{}\" does not exist", name)))
+}
+
+/// Run the statement of `portal` unless it has run or there is none
+fn run_portal(db: &Db, state: &SessionState, portal: &mut Portal) -> Result<(), PgError> {
+    if portal.result.is_none() {
+        if let Some(statement) = &portal.statement {
+            portal.result = Some(run(db, state, statement, &portal.parsed)?);
+        }
+    }
+    Ok(())
+}
+
+/// Run `statement`, its parameters bound, with the plan `parsed` keeps for it
+fn run(db: &Db, state: &SessionState, statement: &Statement, parsed: &ParsedQuery) -> Result<ResultSet, PgError> {
+    if catalog::is_catalog_query(statement) {
+        return catalog::answer(db, state, statement);
+    }
+    state.check_access(statement)?;
+    state.check_writes(statement)?;
+    let value = tonledb_sql::execute_planned(db, statement, &parsed.plan(db, 0))?;
+    Ok(results::result_set(db, statement, value))
+}
+
+/// `statement` with each `$n` placeholder replaced by `literals[n - 1]`;
+/// placeholders past the end are left as they are
+fn bind_parameters(statement: &Statement, literals: &[Expr]) -> Statement {
+    let mut bound = statement.clone();
+    if !literals.is_empty() {
+        let _ = visit_expressions_mut(&mut bound, |expr| {
+            if let Expr::Value(Value::Placeholder(p)) = expr {
+                let literal = p.strip_prefix('$').and_then(|n| n.parse::<usize>().ok()).and_then(|n| n.checked_sub(1)).and_then(|i| literals.get(i));
+                if let Some(literal) = literal {
+                    *expr = literal.clone();
+                }
+            }
+            ControlFlow::<()>::Continue(())
+        });
+    }
+    bound
+}
+
+/// Format code `i` of a list that holds none (all text), one (for all) or one each
+fn format_of(formats: &[i16], i: usize) -> i16 {
+    match formats {
+        [] => 0,
+        [one] => *one,
+        many => many.get(i).copied().unwrap_or(0),
+    }
+}
+
+/// `fields` in the formats asked for; binary only where it is supported (see the
+/// module docs)
+fn with_formats(fields: &[FieldDescription], formats: &[i16]) -> Vec<FieldDescription> {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, f)| {
+            let binary = format_of(formats, i) == 1 && matches!(f.type_oid, INT8_OID | FLOAT8_OID | BOOL_OID | TEXT_OID | JSON_OID);
+            FieldDescription { format: i16::from(binary), ..f.clone() }
+        })
+        .collect()
+}
+
+/// Cells of a text-format `row` in the formats of `fields`
+fn encode_row(fields: &[FieldDescription], row: &[Option<String>]) -> Result<Vec<Option<Vec<u8>>>, PgError> {
+    fields
+        .iter()
+        .zip(row)
+        .map(|(f, cell)| {
+            let Some(text) = cell else { return Ok(None) };
+            if f.format == 0 {
+                return Ok(Some(text.clone().into_bytes()));
+            }
+            let bad = || PgError::new("22P03", format!("column {} holds {}, which has no binary form as type {}", f.name, text, f.type_oid));
+            Ok(Some(match f.type_oid {
+                INT8_OID => text.parse::<i64>().map_err(|_| bad())?.to_be_bytes().to_vec(),
+                FLOAT8_OID => text.parse::<f64>().map_err(|_| bad())?.to_be_bytes().to_vec(),
+                BOOL_OID => vec![u8::from(text == "t")],
+                _ => text.clone().into_bytes(),
+            }))
+        })
+        .collect()
+}
+
+fn utf8(raw: &[u8]) -> Result<String, PgError> {
+    String::from_utf8(raw.to_vec()).map_err(|_| PgError::new("22021", "parameter is not valid UTF-8"))
+}
+
+/// Text of a binary parameter of type `oid`
+fn decode_binary(oid: u32, raw: &[u8]) -> Result<String, PgError> {
+    let bad = || PgError::new("22P03", format!("incorrect binary data format for a parameter of type {}", oid));
+    Ok(match oid {
+        BOOL_OID => match raw {
+            [b] => (*b != 0).to_string(),
+            _ => return Err(bad()),
+        },
+        INT2_OID => i16::from_be_bytes(raw.try_into().map_err(|_| bad())?).to_string(),
+        INT4_OID => i32::from_be_bytes(raw.try_into().map_err(|_| bad())?).to_string(),
+        INT8_OID => i64::from_be_bytes(raw.try_into().map_err(|_| bad())?).to_string(),
+        FLOAT4_OID => f32::from_be_bytes(raw.try_into().map_err(|_| bad())?).to_string(),
+        FLOAT8_OID => f64::from_be_bytes(raw.try_into().map_err(|_| bad())?).to_string(),
+        _ => utf8(raw)?,
+    })
+}
+
+fn is_number(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_digit() || "+-.eE".contains(c)) && s.parse::<f64>().is_ok()
+}
+
+/// A parameter of type `oid` as a literal (see the module docs)
+fn literal(oid: u32, text: Option<&str>) -> Result<Expr, PgError> {
+    let Some(text) = text else { return Ok(Expr::Value(Value::Null)) };
+    Ok(match oid {
+        BOOL_OID => match results::parse_bool(text) {
+            Some(b) => Expr::Value(Value::Boolean(b)),
+            None => return Err(PgError::new("22P02", format!("invalid input syntax for type boolean: \"{}\"", text))),
+        },
+        INT2_OID | INT4_OID | INT8_OID | FLOAT4_OID | FLOAT8_OID | NUMERIC_OID => {
+            if !is_number(text.trim()) {
+                return Err(PgError::new("22P02", format!("invalid input syntax for a number: \"{}\"", text)));
+            }
+            number(text.trim())
+        }
+        0 if is_number(text) => number(text),
+        _ => Expr::Value(Value::SingleQuotedString(text.to_string())),
+    })
+}
+
+/// Number `text` as the parser reads it: a sign is an operator on the digits
+fn number(text: &str) -> Expr {
+    let digits = |n: &str| Box::new(Expr::Value(Value::Number(n.to_string(), false)));
+    match (text.strip_prefix('-'), text.strip_prefix('+')) {
+        (Some(n), _) => Expr::UnaryOp { op: UnaryOperator::Minus, expr: digits(n) },
+        (_, Some(n)) => Expr::UnaryOp { op: UnaryOperator::Plus, expr: digits(n) },
+        _ => *digits(text),
+    }
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$'
+}
+
+/// Walk `query`, copying it to the result but replacing each `$n` placeholder with
+/// `replace(n)`; strings, quoted identifiers, dollar quotes and comments are copied
+/// as they are
+fn rewrite(query: &str, mut replace: impl FnMut(usize) -> String) -> String {
+    let chars: Vec<char> = query.chars().collect();
+    let mut out = String::with_capacity(query.len());
+    let mut i = 0;
+    // Copy through the first occurrence of `end` at or after `from`, or to the end
+    let copy_until = |out: &mut String, from: usize, end: &[char]| -> usize {
+        let mut j = from;
+        while j < chars.len() && !chars[j..].starts_with(end) {
+            j += 1;
+        }
+        let stop = (j + end.len()).min(chars.len());
+        out.extend(&chars[from..stop]);
+        stop
+    };
+    while i < chars.len() {
+        let c = chars[i];
+        let prev = if i > 0 { Some(chars[i - 1]) } else { None };
+        match c {
+            '\'' | '"' => {
+                // Doubled quotes stay inside; E'' strings also escape with backslashes
+                let escapes = c == '\'' && matches!(prev, Some('e' | 'E')) && !(i >= 2 && is_ident_char(chars[i - 2]));
+                out.push(c);
+                i += 1;
+                while i < chars.len() {
+                    out.push(chars[i]);
+                    if escapes && chars[i] == '\\' && i + 1 < chars.len() {
+                        out.push(chars[i + 1]);
+                        i += 2;
+                        continue;
+                    }
+                    if chars[i] == c {
+                        if chars.get(i + 1) == Some(&c) {
+                            out.push(c);
+                            i += 2;
+                            continue;
+                        }
+                        i += 1;
+                        break;
+                    }
+                    i += 1;
+                }
+            }
+            '-' if chars.get(i + 1) == Some(&'-') => i = copy_until(&mut out, i, &['\n']),
+            '/' if chars.get(i + 1) == Some(&'*') => {
+                out.push_str("/*");
+                i = copy_until(&mut out, i + 2, &['*', '/']);
+            }
+            '$' if !prev.is_some_and(is_ident_char) => {
+                let digits = chars[i + 1..].iter().take_while(|c| c.is_ascii_digit()).count();
+                if digits > 0 {
+                    let n: usize = chars[i + 1..i + 1 + digits].iter().collect::<String>().parse().unwrap_or(0);
+                    out.push_str(&replace(n));
+                    i += 1 + digits;
+                    continue;
+                }
+                // A dollar quote: `$tag$ ... $tag$`
+                let tag_len = chars[i + 1..].iter().take_while(|c| c.is_alphanumeric() || **c == '_').count();
+                if chars.get(i + 1 + tag_len) == Some(&'$') {
+                    let tag: Vec<char> = chars[i..i + tag_len + 2].to_vec();
+                    out.extend(&tag);
+                    i = copy_until(&mut out, i + tag.len(), &tag);
+                } else {
+                    out.push(c);
+                    i += 1;
+                }
+            }
+            _ => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+/// Highest `$n` placeholder in `query`
+pub fn placeholder_count(query: &str) -> usize {
+    let mut highest = 0;
+    rewrite(query, |n| {
+        highest = highest.max(n);
+        String::new()
+    });
+    highest
+}
+
@@ -1,158 +1,440 @@
 //! Postgres wire protocol compatibility for TonleDB
+//!
+//! Speaks the simple query protocol of Postgres v3, so `psql` and drivers can run
+//! SQL against a `Db`: every statement of a query string is run with
+//! `tonledb_sql::execute_planned` and answered with a RowDescription, a DataRow per
+//! row and CommandComplete (see `results`), and the query with ReadyForQuery.
+//! The extended protocol (Parse, Bind, Describe, Execute, Close, Sync) runs
+//! prepared statements with bound parameters (see `extended`). With a
+//! `PasswordStore` in the `PgOptions`, clients log in with a password first (see
+//! `auth`). Catalog queries tools send on connect (`version()`, `pg_class`,
+//! `information_schema.columns`, `SHOW`) are answered from the table catalog (see
+//! `catalog`). `COPY ... FROM STDIN` and `COPY ... TO STDOUT` stream rows in and
+//! out of a table (see `copy`). Statements that write, COPY FROM included, run
+//! only in a read-write transaction outside a block (see `session`) and need a
+//! user the store gives write access; COPY FROM and the statements refused are
+//! recorded in the `AuditLog` if there is one. With a TLS config, an SSLRequest is
+//! accepted and the connection carries on inside TLS; without one it is declined
+//! and the client carries on in plain text, unless the server requires TLS.
+//!
+//! Each connection keeps its own settings and transaction state (see `session`)
+//! and is registered in the server's `ConnectionRegistry` (see `connections`),
+//! which may cap how many are open at once. With an authentication timeout, a
+//! client that hasn't logged in that long after connecting is disconnected, so
+//! clients that never answer can't hold every slot; with an idle timeout, a
+//! connection the client leaves waiting that long is closed. A CancelRequest with
+//! the key from BackendKeyData stops the statement the connection is running at
+//! its next deadline check (see `tonledb_core::deadline`), failing it with 57014.
+//! Statements run with `block_in_place` on a multi-threaded runtime, so the
+//! worker's other connections, CancelRequests included, move to another thread
+//! while one runs. Parsed query strings and their plans are cached per connection
+//! and across connections (see `cache`).
 
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use sqlparser::ast::Statement;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpListener;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::runtime::RuntimeFlavor;
+use tokio::time::Instant;
+use tokio_rustls::{rustls::ServerConfig, TlsAcceptor};
+use tonledb_core::deadline::{self, Canceller};
 use tonledb_core::Db;
-use std::sync::Arc;
+use crate::auth::{Access, PasswordStore};
+use crate::cache::StatementCache;
+use crate::connections::ConnectionRegistry;
+use crate::copy::{CopyIn, Copying};
+use crate::extended::Session;
+use crate::protocol::{FrontendMessage, StartupPacket, TransactionStatus};
+use crate::results::PgError;
+use crate::session::SessionState;
+
+pub mod auth;
+pub mod cache;
+pub mod catalog;
+pub mod connections;
+mod copy;
+pub mod extended;
+pub mod protocol;
+pub mod results;
+pub mod session;
 
-/// PostgreSQL wire protocol message types
-#[derive(Debug)]
-pub enum PgMessage {
-    StartupMessage {
-        version: i32,
-        parameters: Vec<(String, String)>,
-    },
-    Query {
-        query: String,
-    },
-    Terminate,
+/// Reported as `server_version`; drivers check it for features
+pub const SERVER_VERSION: &str = "14.0 (TonleDB)";
+
+/// How the server lets clients in
+#[derive(Clone, Default)]
+pub struct PgOptions {
+    /// Checks passwords; `None` lets every client in without one
+    pub passwords: Option<Arc<dyn PasswordStore>>,
+    /// Accepts SSLRequest with this config; `None` declines it
+    pub tls: Option<Arc<ServerConfig>>,
+    /// Refuse clients that start without TLS
+    pub require_tls: bool,
+    /// The open connections, and how many may be open at once
+    pub connections: Arc<ConnectionRegistry>,
+    /// Disconnect a client that hasn't logged in this long after connecting;
+    /// `None` waits for ever
+    pub authentication_timeout: Option<Duration>,
+    /// Close a connection the client leaves idle this long; `None` never does
+    pub idle_timeout: Option<Duration>,
+    /// Settings every session starts with, such as `search_path`; see
+    /// `session::check_settings`
+    pub settings: Vec<(String, String)>,
+    /// Parsed query strings, shared by the connections
+    pub statement_cache: Arc<StatementCache>,
+    /// Where COPY FROM and refused statements are recorded; `None` records nothing
+    pub audit: Option<Arc<dyn AuditLog>>,
 }
 
-/// Parse a PostgreSQL wire protocol message
-pub async fn parse_pg_message(stream: &mut tokio::net::TcpStream) -> Result<PgMessage, anyhow::Error> {
-    // Read message type
-    let mut type_byte = [0u8; 1];
-    stream.read_exact(&mut type_byte).await?;
-    
-    match type_byte[0] {
-        b'Q' => {
-            // Query message
-            let mut len_bytes = [0u8; 4];
-            stream.read_exact(&mut len_bytes).await?;
-            let len = i32::from_be_bytes(len_bytes) as usize;
-            
-            let mut query_bytes = vec![0u8; len - 4];
-            stream.read_exact(&mut query_bytes).await?;
-            
-            let query = String::from_utf8(query_bytes)?;
-            Ok(PgMessage::Query { query })
-        }
-        b'X' => {
-            // Terminate message
-            let mut len_bytes = [0u8; 4];
-            stream.read_exact(&mut len_bytes).await?;
-            Ok(PgMessage::Terminate)
-        }
-        _ => {
-            // For other messages, just read the length and skip
-            let mut len_bytes = [0u8; 4];
-            stream.read_exact(&mut len_bytes).await?;
-            let len = i32::from_be_bytes(len_bytes) as usize;
-            
-            let mut skip_bytes = vec![0u8; len - 4];
-            stream.read_exact(&mut skip_bytes).await?;
-            
-            // Return an error for unsupported message types instead of recursing
-            Err(anyhow::anyhow!("Unsupported message type: {}", type_byte[0]))
-        }
+/// The server's audit trail
+pub trait AuditLog: Send + Sync {
+    /// `user` did `action` to `resource`, with `result` ("ok", "error" or "forbidden")
+    fn record(&self, user: &str, action: &str, resource: &str, result: &str);
+}
+
+/// Record a COPY FROM into `table` by `user`, if the server keeps an audit trail
+fn audit_copy(options: &PgOptions, user: &str, table: &str, result: &str) {
+    if let Some(audit) = &options.audit {
+        audit.record(user, "COPY", &format!("pg:{}", table), result);
     }
 }
 
-/// Send a PostgreSQL response message
-pub async fn send_pg_response(stream: &mut tokio::net::TcpStream, message: &str) -> Result<(), anyhow::Error> {
-    // Send a simple response
-    let response = format!("{}\0", message);
-    let len = (response.len() + 4) as i32;
-    
-    stream.write_all(&[b'T']).await?; // RowDescription message type
-    stream.write_all(&len.to_be_bytes()).await?;
-    stream.write_all(response.as_bytes()).await?;
-    
-    Ok(())
+/// Record that `result`, of a statement `user` ran, refused it for want of access
+fn audit_refusal(options: &PgOptions, user: &str, result: &Result<(), PgError>) {
+    if let (Some(audit), Err(PgError { code: "42501", .. })) = (&options.audit, result) {
+        audit.record(user, "SQL", "pg:query", "forbidden");
+    }
+}
+
+/// `step` of logging in, failing once `login_by` has passed
+async fn before<T>(login_by: Option<Instant>, step: impl Future<Output = anyhow::Result<T>>) -> anyhow::Result<T> {
+    match login_by {
+        Some(at) => tokio::time::timeout_at(at, step).await.map_err(|_| anyhow::anyhow!("canceling authentication due to timeout"))?,
+        None => step.await,
+    }
+}
+
+/// Run `f`, a statement's work, so that `canceller` stops it; on a multi-threaded
+/// runtime the worker hands its other tasks on first
+fn run<T>(canceller: &Canceller, f: impl FnOnce() -> T) -> T {
+    match tokio::runtime::Handle::try_current().map(|h| h.runtime_flavor()) {
+        Ok(RuntimeFlavor::MultiThread) => tokio::task::block_in_place(|| deadline::cancellable(canceller, f)),
+        _ => deadline::cancellable(canceller, f),
+    }
 }
 
 /// Handle a PostgreSQL client connection
-pub async fn handle_pg_connection(mut stream: tokio::net::TcpStream, db: Arc<Db>) -> Result<(), anyhow::Error> {
-    // First handle the startup message
-    match parse_startup_message(&mut stream).await {
-        Ok(_) => {
-            // Send authentication ok message
-            stream.write_all(&[b'R']).await?; // Authentication message type
-            stream.write_all(&8i32.to_be_bytes()).await?; // Length
-            stream.write_all(&0i32.to_be_bytes()).await?; // Success
+pub async fn handle_pg_connection<S>(mut stream: S, db: Arc<Db>, options: Arc<PgOptions>) -> Result<(), anyhow::Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let login_by = options.authentication_timeout.map(|t| Instant::now() + t);
+    let startup = loop {
+        match before(login_by, protocol::read_startup(&mut stream)).await? {
+            StartupPacket::SslRequest if options.tls.is_some() => {
+                stream.write_all(b"S").await?;
+                stream.flush().await?;
+                let acceptor = TlsAcceptor::from(options.tls.clone().expect("checked above"));
+                let mut stream = before(login_by, async { Ok(acceptor.accept(stream).await?) }).await?;
+                let startup = before(login_by, protocol::read_startup(&mut stream)).await?;
+                return serve(stream, db, &options, startup, login_by).await;
+            }
+            StartupPacket::SslRequest | StartupPacket::GssEncRequest => {
+                stream.write_all(b"N").await?;
+                stream.flush().await?;
+            }
+            startup => break startup,
         }
-        Err(e) => {
-            eprintln!("Error parsing startup message: {}", e);
-            return Err(e);
+    };
+    if options.require_tls && matches!(startup, StartupPacket::Startup { .. }) {
+        let mut buf = Vec::new();
+        protocol::error_response(&mut buf, "28000", "connection requires SSL");
+        stream.write_all(&buf).await?;
+        stream.flush().await?;
+        return Ok(());
+    }
+    serve(stream, db, &options, startup, login_by).await
+}
+
+/// Serve a connection from its startup packet on; it must have logged in by `login_by`
+async fn serve<S>(mut stream: S, db: Arc<Db>, options: &PgOptions, startup: StartupPacket, login_by: Option<Instant>) -> Result<(), anyhow::Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let parameters = match startup {
+        StartupPacket::Startup { version, parameters } if version == protocol::PROTOCOL_VERSION => parameters,
+        StartupPacket::Startup { version, .. } => {
+            let mut buf = Vec::new();
+            protocol::error_response(&mut buf, "0A000", &format!("unsupported protocol version {}.{}", version >> 16, version & 0xffff));
+            stream.write_all(&buf).await?;
+            stream.flush().await?;
+            return Ok(());
+        }
+        StartupPacket::SslRequest | StartupPacket::GssEncRequest => anyhow::bail!("encryption requested twice"),
+        // Answered by closing the connection, whether or not it cancelled anything
+        StartupPacket::CancelRequest { process_id, secret_key } => {
+            options.connections.cancel(process_id as u32, secret_key);
+            return Ok(());
         }
+    };
+
+    let user = parameters.iter().find(|(k, _)| k == "user").map(|(_, v)| v.as_str()).unwrap_or("");
+    let mut buf = Vec::new();
+    if user.is_empty() {
+        protocol::error_response(&mut buf, "28000", "no PostgreSQL user name specified in startup packet");
+        stream.write_all(&buf).await?;
+        stream.flush().await?;
+        return Ok(());
     }
-    
+    let application_name = parameters.iter().find(|(k, _)| k == "application_name").map(|(_, v)| v.as_str()).unwrap_or("");
+    let Some(registration) = options.connections.register(user, application_name) else {
+        protocol::fatal_response(&mut buf, "53300", "sorry, too many clients already");
+        stream.write_all(&buf).await?;
+        stream.flush().await?;
+        return Ok(());
+    };
+    let access = match &options.passwords {
+        Some(store) => {
+            if !before(login_by, auth::authenticate(&mut stream, user, store.as_ref())).await? {
+                return Ok(());
+            }
+            store.access(user)
+        }
+        None => {
+            protocol::authentication_ok(&mut buf);
+            Access::Admin
+        }
+    };
+    let mut state = SessionState::new(user, registration.process_id(), &options.settings);
+    state.access = access;
+    state.apply_startup(&parameters);
+    state.report_changes(&mut buf);
+    protocol::backend_key_data(&mut buf, registration.process_id(), registration.secret_key());
+    protocol::ready_for_query(&mut buf, TransactionStatus::Idle);
+    stream.write_all(&buf).await?;
+    stream.flush().await?;
+
+    let mut session = Session::new(state, options.statement_cache.clone());
+    let mut buf = Vec::new();
+    // After an extended-protocol message fails, the rest up to Sync are discarded
+    let mut skipping = false;
+    // Set while a COPY FROM STDIN reads CopyData
+    let mut copy_in: Option<Box<CopyIn>> = None;
+    // Cancels the statement last started, COPY included
+    let mut canceller = Canceller::new();
     loop {
-        let message = parse_pg_message(&mut stream).await;
-        match message {
-            Ok(PgMessage::Query { query }) => {
-                // Execute the query using TonleDB's SQL engine
-                match tonledb_sql::execute_sql(&db, &query) {
-                    Ok(result) => {
-                        let result_str = serde_json::to_string(&result)?;
-                        send_pg_response(&mut stream, &result_str).await?;
+        let read = protocol::read_message(&mut stream);
+        let message = match options.idle_timeout {
+            Some(idle) => match tokio::time::timeout(idle, read).await {
+                Ok(message) => message?,
+                Err(_) => {
+                    let (code, text) = match session.state.transaction_status() {
+                        TransactionStatus::Idle => ("57P05", "terminating connection due to idle-session timeout"),
+                        _ => ("25P03", "terminating connection due to idle-in-transaction timeout"),
+                    };
+                    protocol::fatal_response(&mut buf, code, text);
+                    stream.write_all(&buf).await?;
+                    stream.flush().await?;
+                    return Ok(());
+                }
+            },
+            None => read.await?,
+        };
+        let Some(message) = message else { break };
+        if let Some(copy) = &mut copy_in {
+            match message {
+                FrontendMessage::CopyData(data) => run(&canceller, || copy.data(&data)),
+                FrontendMessage::Flush | FrontendMessage::Sync => {}
+                FrontendMessage::Terminate => break,
+                other => {
+                    let copy = copy_in.take().expect("in a COPY");
+                    let table = copy.table().to_string();
+                    let done = match other {
+                        FrontendMessage::CopyDone => run(&canceller, || copy.done(&mut buf)),
+                        FrontendMessage::CopyFail(reason) => {
+                            copy.fail(&reason, &mut buf);
+                            false
+                        }
+                        _ => {
+                            protocol::error_response(&mut buf, "08P01", "unexpected message during COPY FROM STDIN");
+                            false
+                        }
+                    };
+                    audit_copy(options, &session.state.user, &table, if done { "ok" } else { "error" });
+                    if !done {
+                        session.state.fail();
                     }
-                    Err(e) => {
-                        send_pg_response(&mut stream, &format!("Error: {}", e)).await?;
+                    protocol::ready_for_query(&mut buf, session.state.transaction_status());
+                    stream.write_all(&buf).await?;
+                    stream.flush().await?;
+                    buf.clear();
+                }
+            }
+            continue;
+        }
+        // Extended-protocol replies are held until Sync or Flush
+        let reply = matches!(message, FrontendMessage::Query(_) | FrontendMessage::Sync | FrontendMessage::Flush | FrontendMessage::Unsupported(_));
+        let result = match message {
+            FrontendMessage::Terminate => break,
+            FrontendMessage::Sync => {
+                skipping = false;
+                session.state.report_changes(&mut buf);
+                protocol::ready_for_query(&mut buf, session.state.transaction_status());
+                Ok(())
+            }
+            _ if skipping => continue,
+            FrontendMessage::Query(sql) => {
+                canceller = registration.statement();
+                match run(&canceller, || simple_query(&db, options, &mut session, &sql, &mut buf)) {
+                    Some(Copying::In(copy)) => copy_in = Some(copy),
+                    Some(Copying::Out(mut copy)) => loop {
+                        let more = run(&canceller, || copy.next_chunk(&mut buf)).unwrap_or_else(|e| {
+                            protocol::error_response(&mut buf, e.code, &e.message);
+                            session.state.fail();
+                            false
+                        });
+                        if !more {
+                            protocol::ready_for_query(&mut buf, session.state.transaction_status());
+                            break;
+                        }
+                        stream.write_all(&buf).await?;
+                        buf.clear();
+                    },
+                    None => {
+                        session.state.report_changes(&mut buf);
+                        protocol::ready_for_query(&mut buf, session.state.transaction_status());
                     }
                 }
+                Ok(())
+            }
+            FrontendMessage::Parse { name, query, param_types } => session.parse(name, query, param_types, &mut buf),
+            FrontendMessage::Bind { portal, statement, param_formats, params, result_formats } => {
+                session.bind(portal, &statement, &param_formats, params, result_formats, &mut buf)
             }
-            Ok(PgMessage::StartupMessage { .. }) => {
-                // Ignore additional startup messages
-                continue;
+            FrontendMessage::Describe { kind, name } => {
+                canceller = registration.statement();
+                let result = run(&canceller, || session.describe(&db, kind, &name, &mut buf));
+                audit_refusal(options, &session.state.user, &result);
+                result
             }
-            Ok(PgMessage::Terminate) => {
-                break;
+            FrontendMessage::Execute { portal, max_rows } => {
+                canceller = registration.statement();
+                let result = run(&canceller, || session.execute(&db, &portal, max_rows, &mut buf));
+                audit_refusal(options, &session.state.user, &result);
+                result
             }
-            Err(e) => {
-                eprintln!("Error parsing PG message: {}", e);
-                break;
+            FrontendMessage::Close { kind, name } => session.close(kind, &name, &mut buf),
+            FrontendMessage::Flush => Ok(()),
+            FrontendMessage::Password(_) => Err(PgError::new("08P01", "unexpected password message")),
+            // Left over from a COPY that already failed
+            FrontendMessage::CopyData(_) | FrontendMessage::CopyDone | FrontendMessage::CopyFail(_) => continue,
+            FrontendMessage::Unsupported(tag) => {
+                protocol::error_response(&mut buf, "0A000", &format!("unsupported message type {}", tag as char));
+                session.state.fail();
+                protocol::ready_for_query(&mut buf, session.state.transaction_status());
+                Ok(())
             }
+        };
+        if let Err(e) = result {
+            protocol::error_response(&mut buf, e.code, &e.message);
+            session.state.fail();
+            skipping = true;
+        }
+        if reply || skipping {
+            stream.write_all(&buf).await?;
+            stream.flush().await?;
+            buf.clear();
         }
     }
-    
     Ok(())
 }
 
-/// Parse PostgreSQL startup message
-async fn parse_startup_message(stream: &mut tokio::net::TcpStream) -> Result<PgMessage, anyhow::Error> {
-    let mut len_bytes = [0u8; 4];
-    stream.read_exact(&mut len_bytes).await?;
-    let len = i32::from_be_bytes(len_bytes) as usize;
-    
-    let mut version_bytes = [0u8; 4];
-    stream.read_exact(&mut version_bytes).await?;
-    let version = i32::from_be_bytes(version_bytes);
-    
-    // For simplicity, we'll just skip the rest of the startup message
-    let mut skip_bytes = vec![0u8; len - 8];
-    stream.read_exact(&mut skip_bytes).await?;
-    
-    Ok(PgMessage::StartupMessage {
-        version,
-        parameters: vec![],
-    })
+/// Run each statement of `sql` in `session`, appending its results to `buf`; stops
+/// at the first statement that fails. A COPY, which must be the last statement, is
+/// left under way for the connection to carry on with. Statements that write, COPY
+/// FROM among them, need write access.
+fn simple_query<'a>(db: &'a Db, options: &PgOptions, session: &mut Session, sql: &str, buf: &mut Vec<u8>) -> Option<Copying<'a>> {
+    let parsed = match session.statements_of(sql) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            protocol::error_response(buf, e.code, &e.message);
+            session.state.fail();
+            return None;
+        }
+    };
+    let statements = &parsed.statements;
+    if statements.is_empty() {
+        protocol::empty_query_response(buf);
+        return None;
+    }
+    for (i, stmt) in statements.iter().enumerate() {
+        let result = if let Err(e) = session.state.check_runnable(stmt) {
+            Err(e)
+        } else if let Statement::Copy { .. } = stmt {
+            let loaded = copy::loaded_table(stmt);
+            let started = match (i + 1 == statements.len(), &loaded) {
+                (_, Some(table)) if session.state.access < Access::Write => {
+                    audit_copy(options, &session.state.user, table, "forbidden");
+                    Err(PgError::new("42501", format!("permission denied for table {}", table)))
+                }
+                (true, Some(_)) => session.state.check_writes(stmt).and_then(|()| copy::start(db, stmt, buf)),
+                (true, None) => copy::start(db, stmt, buf),
+                (false, _) => Err(PgError::new("0A000", "COPY must be the last statement of a query string")),
+            };
+            match started {
+                Ok(copying) => return Some(copying),
+                Err(e) => Err(e),
+            }
+        } else if session::is_session_statement(stmt) {
+            session.session_statement(stmt, buf).map(|tag| {
+                protocol::command_complete(buf, &tag);
+            })
+        } else {
+            let allowed = session.state.check_access(stmt);
+            audit_refusal(options, &session.state.user, &allowed);
+            let result = allowed.and_then(|()| session.state.check_writes(stmt)).and_then(|()| {
+                if catalog::is_catalog_query(stmt) {
+                    catalog::answer(db, &session.state, stmt)
+                } else {
+                    let plan = parsed.plan(db, i);
+                    tonledb_sql::execute_planned(db, stmt, &plan).map(|value| results::result_set(db, stmt, value)).map_err(PgError::from)
+                }
+            });
+            result.map(|result| {
+                protocol::row_description(buf, &result.fields);
+                for row in &result.rows {
+                    protocol::data_row(buf, row);
+                }
+                protocol::command_complete(buf, &result.tag);
+            })
+        };
+        if let Err(e) = result {
+            protocol::error_response(buf, e.code, &e.message);
+            session.state.fail();
+            return None;
+        }
+    }
+    None
 }
 
 /// Start a PostgreSQL wire protocol server
-pub async fn start_pg_server(db: Arc<Db>, bind_addr: &str) -> Result<(), anyhow::Error> {
+pub async fn start_pg_server(db: Arc<Db>, bind_addr: &str, options: PgOptions) -> Result<(), anyhow::Error> {
+    let options = Arc::new(options);
     let listener = TcpListener::bind(bind_addr).await?;
     println!("PostgreSQL wire protocol server listening on {}", bind_addr);
-    
+
     loop {
         let (stream, addr) = listener.accept().await?;
         println!("New PostgreSQL client connected from {}", addr);
-        
+
         let db_clone = db.clone();
+        let options = options.clone();
         tokio::spawn(async move {
-            if let Err(e) = handle_pg_connection(stream, db_clone).await {
+            if let Err(e) = handle_pg_connection(stream, db_clone, options).await {
                 eprintln!("Error handling PostgreSQL connection: {}", e);
             }
         });
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,411 @@
+//! Postgres wire protocol (v3) framing: reading frontend messages and encoding
+//! backend ones.
+//!
+//! Backend messages are appended to a buffer, so a whole response (row
+//! description, rows, completion, ready) goes out in one write.
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Protocol version 3.0, the only one spoken
+pub const PROTOCOL_VERSION: i32 = 196608;
+const SSL_REQUEST: i32 = 80877103;
+const GSSENC_REQUEST: i32 = 80877104;
+const CANCEL_REQUEST: i32 = 80877102;
+/// Longest message accepted from a client
+const MAX_MESSAGE_LEN: usize = 64 << 20;
+/// Longest startup packet accepted, as in Postgres
+const MAX_STARTUP_LEN: usize = 10_000;
+/// Longest message accepted while a client authenticates, before anyone knows who it is
+const MAX_AUTH_MESSAGE_LEN: usize = 64 << 10;
+
+/// First packet of a connection, which has no type byte
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StartupPacket {
+    Startup { version: i32, parameters: Vec<(String, String)> },
+    SslRequest,
+    GssEncRequest,
+    CancelRequest { process_id: i32, secret_key: i32 },
+}
+
+/// Message from the client after startup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrontendMessage {
+    Query(String),
+    /// Prepare `query` as statement `name`, with the parameter types the client
+    /// declares (0 leaves one unspecified)
+    Parse { name: String, query: String, param_types: Vec<u32> },
+    /// Bind parameters to `statement`, creating `portal`
+    Bind { portal: String, statement: String, param_formats: Vec<i16>, params: Vec<Option<Vec<u8>>>, result_formats: Vec<i16> },
+    /// Describe statement (`b'S'`) or portal (`b'P'`) `name`
+    Describe { kind: u8, name: String },
+    /// Run `portal`, sending at most `max_rows` rows (0: all of them)
+    Execute { portal: String, max_rows: i32 },
+    /// Close statement (`b'S'`) or portal (`b'P'`) `name`
+    Close { kind: u8, name: String },
+    Sync,
+    Flush,
+    /// Rows of a COPY FROM STDIN
+    CopyData(Vec<u8>),
+    CopyDone,
+    /// The client abandons a COPY FROM STDIN, giving a reason
+    CopyFail(String),
+    /// PasswordMessage, SASLInitialResponse or SASLResponse: which one depends on
+    /// the authentication under way, so the body is left as it is
+    Password(Vec<u8>),
+    Terminate,
+    /// Any other message: its type byte, body skipped
+    Unsupported(u8),
+}
+
+/// Transaction status sent with ReadyForQuery
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionStatus {
+    Idle,
+    InTransaction,
+    Failed,
+}
+
+/// One column of a RowDescription
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldDescription {
+    pub name: String,
+    pub type_oid: u32,
+    /// Size of the type in bytes; -1 for variable-length types
+    pub type_len: i16,
+    /// 0 for text, 1 for binary
+    pub format: i16,
+}
+
+/// Body length of the message being read, which may be at most `max` long
+async fn read_len<R: AsyncRead + Unpin>(r: &mut R, max: usize) -> anyhow::Result<usize> {
+    let len = r.read_i32().await?;
+    if len < 4 || len as usize > max {
+        anyhow::bail!("bad message length {}", len);
+    }
+    Ok(len as usize - 4)
+}
+
+/// Read the packet a connection starts with
+pub async fn read_startup<R: AsyncRead + Unpin>(r: &mut R) -> anyhow::Result<StartupPacket> {
+    let len = read_len(r, MAX_STARTUP_LEN).await?;
+    if len < 4 {
+        anyhow::bail!("startup packet too short");
+    }
+    let mut body = vec![0u8; len];
+    r.read_exact(&mut body).await?;
+    let code = i32::from_be_bytes(body[..4].try_into().expect("4 bytes"));
+    let rest = &body[4..];
+    Ok(match code {
+        SSL_REQUEST => StartupPacket::SslRequest,
+        GSSENC_REQUEST => StartupPacket::GssEncRequest,
+        CANCEL_REQUEST if rest.len() == 8 => StartupPacket::CancelRequest {
+            process_id: i32::from_be_bytes(rest[..4].try_into().expect("4 bytes")),
+            secret_key: i32::from_be_bytes(rest[4..].try_into().expect("4 bytes")),
+        },
+        version => {
+            let mut fields = rest.split(|b| *b == 0).map(|s| String::from_utf8_lossy(s).into_owned());
+            let mut parameters = Vec::new();
+            while let (Some(name), Some(value)) = (fields.next(), fields.next()) {
+                if name.is_empty() {
+                    break;
+                }
+                parameters.push((name, value));
+            }
+            StartupPacket::Startup { version, parameters }
+        }
+    })
+}
+
+/// Read the next message; `None` once the client has closed the connection
+pub async fn read_message<R: AsyncRead + Unpin>(r: &mut R) -> anyhow::Result<Option<FrontendMessage>> {
+    read_message_within(r, MAX_MESSAGE_LEN).await
+}
+
+/// `read_message` for a client still authenticating, which gets far less room
+pub async fn read_auth_message<R: AsyncRead + Unpin>(r: &mut R) -> anyhow::Result<Option<FrontendMessage>> {
+    read_message_within(r, MAX_AUTH_MESSAGE_LEN).await
+}
+
+async fn read_message_within<R: AsyncRead + Unpin>(r: &mut R, max: usize) -> anyhow::Result<Option<FrontendMessage>> {
+    let tag = match r.read_u8().await {
+        Ok(tag) => tag,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let mut body = vec![0u8; read_len(r, max).await?];
+    r.read_exact(&mut body).await?;
+    let mut body = Body(&body);
+    Ok(Some(match tag {
+        b'Q' => FrontendMessage::Query(body.cstr()?),
+        b'P' => {
+            let name = body.cstr()?;
+            let query = body.cstr()?;
+            let param_types = (0..body.i16()?).map(|_| body.i32().map(|t| t as u32)).collect::<anyhow::Result<_>>()?;
+            FrontendMessage::Parse { name, query, param_types }
+        }
+        b'B' => {
+            let portal = body.cstr()?;
+            let statement = body.cstr()?;
+            let param_formats = (0..body.i16()?).map(|_| body.i16()).collect::<anyhow::Result<_>>()?;
+            let params = (0..body.i16()?)
+                .map(|_| match body.i32()? {
+                    -1 => Ok(None),
+                    len if len < 0 => anyhow::bail!("bad parameter length {}", len),
+                    len => Ok(Some(body.bytes(len as usize)?.to_vec())),
+                })
+                .collect::<anyhow::Result<_>>()?;
+            let result_formats = (0..body.i16()?).map(|_| body.i16()).collect::<anyhow::Result<_>>()?;
+            FrontendMessage::Bind { portal, statement, param_formats, params, result_formats }
+        }
+        b'D' => FrontendMessage::Describe { kind: body.u8()?, name: body.cstr()? },
+        b'E' => FrontendMessage::Execute { portal: body.cstr()?, max_rows: body.i32()? },
+        b'C' => FrontendMessage::Close { kind: body.u8()?, name: body.cstr()? },
+        b'S' => FrontendMessage::Sync,
+        b'H' => FrontendMessage::Flush,
+        b'p' => FrontendMessage::Password(body.0.to_vec()),
+        b'd' => FrontendMessage::CopyData(body.0.to_vec()),
+        b'c' => FrontendMessage::CopyDone,
+        b'f' => FrontendMessage::CopyFail(body.cstr()?),
+        b'X' => FrontendMessage::Terminate,
+        other => FrontendMessage::Unsupported(other),
+    }))
+}
+
+/// Reads the fields of a message body in order
+struct Body<'a>(&'a [u8]);
+
+impl<'a> Body<'a> {
+    fn bytes(&mut self, n: usize) -> anyhow::Result<&'a [u8]> {
+        if self.0.len() < n {
+            anyhow::bail!("message ends early");
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn i16(&mut self) -> anyhow::Result<i16> {
+        Ok(i16::from_be_bytes(self.bytes(2)?.try_into().expect("2 bytes")))
+    }
+
+    fn i32(&mut self) -> anyhow::Result<i32> {
+        Ok(i32::from_be_bytes(self.bytes(4)?.try_into().expect("4 bytes")))
+    }
+
+    /// A NUL-terminated string
+    fn cstr(&mut self) -> anyhow::Result<String> {
+        let end = self.0.iter().position(|b| *b == 0).ok_or_else(|| anyhow::anyhow!("unterminated string"))?;
+        let s = String::from_utf8(self.bytes(end)?.to_vec())?;
+        self.bytes(1)?;
+        Ok(s)
+    }
+}
+
+/// Append a message of type `tag` with the body `body` writes
+fn message(buf: &mut Vec<u8>, tag: u8, body: impl FnOnce(&mut Vec<u8>)) {
+    buf.push(tag);
+    let at = buf.len();
+    buf.extend_from_slice(&[0; 4]);
+    body(buf);
+    let len = (buf.len() - at) as i32;
+    buf[at..at + 4].copy_from_slice(&len.to_be_bytes());
+}
+
+fn put_cstr(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(s.as_bytes());
+    buf.push(0);
+}
+
+/// An Authentication message: request `code`, then its data
+fn authentication(buf: &mut Vec<u8>, code: i32, data: &[u8]) {
+    message(buf, b'R', |b| {
+        b.extend_from_slice(&code.to_be_bytes());
+        b.extend_from_slice(data);
+    });
+}
+
+pub fn authentication_ok(buf: &mut Vec<u8>) {
+    authentication(buf, 0, &[]);
+}
+
+pub fn authentication_cleartext_password(buf: &mut Vec<u8>) {
+    authentication(buf, 3, &[]);
+}
+
+pub fn authentication_md5_password(buf: &mut Vec<u8>, salt: [u8; 4]) {
+    authentication(buf, 5, &salt);
+}
+
+/// Start of a SASL exchange, offering `mechanisms`
+pub fn authentication_sasl(buf: &mut Vec<u8>, mechanisms: &[&str]) {
+    let mut data = Vec::new();
+    for m in mechanisms {
+        put_cstr(&mut data, m);
+    }
+    data.push(0);
+    authentication(buf, 10, &data);
+}
+
+pub fn authentication_sasl_continue(buf: &mut Vec<u8>, data: &[u8]) {
+    authentication(buf, 11, data);
+}
+
+pub fn authentication_sasl_final(buf: &mut Vec<u8>, data: &[u8]) {
+    authentication(buf, 12, data);
+}
+
+pub fn parameter_status(buf: &mut Vec<u8>, name: &str, value: &str) {
+    message(buf, b'S', |b| {
+        put_cstr(b, name);
+        put_cstr(b, value);
+    });
+}
+
+/// The process id and secret key a CancelRequest for this connection must give
+pub fn backend_key_data(buf: &mut Vec<u8>, process_id: u32, secret_key: i32) {
+    message(buf, b'K', |b| {
+        b.extend_from_slice(&process_id.to_be_bytes());
+        b.extend_from_slice(&secret_key.to_be_bytes());
+    });
+}
+
+pub fn ready_for_query(buf: &mut Vec<u8>, status: TransactionStatus) {
+    let status = match status {
+        TransactionStatus::Idle => b'I',
+        TransactionStatus::InTransaction => b'T',
+        TransactionStatus::Failed => b'E',
+    };
+    message(buf, b'Z', |b| b.push(status));
+}
+
+/// Columns of the rows that follow
+pub fn row_description(buf: &mut Vec<u8>, fields: &[FieldDescription]) {
+    message(buf, b'T', |b| {
+        b.extend_from_slice(&(fields.len() as i16).to_be_bytes());
+        for f in fields {
+            put_cstr(b, &f.name);
+            b.extend_from_slice(&0i32.to_be_bytes()); // table OID
+            b.extend_from_slice(&0i16.to_be_bytes()); // column number
+            b.extend_from_slice(&f.type_oid.to_be_bytes());
+            b.extend_from_slice(&f.type_len.to_be_bytes());
+            b.extend_from_slice(&(-1i32).to_be_bytes()); // type modifier
+            b.extend_from_slice(&f.format.to_be_bytes());
+        }
+    });
+}
+
+/// One row; `None` cells are NULL
+pub fn data_row<T: AsRef<[u8]>>(buf: &mut Vec<u8>, cells: &[Option<T>]) {
+    message(buf, b'D', |b| {
+        b.extend_from_slice(&(cells.len() as i16).to_be_bytes());
+        for cell in cells {
+            match cell {
+                Some(v) => {
+                    let v = v.as_ref();
+                    b.extend_from_slice(&(v.len() as i32).to_be_bytes());
+                    b.extend_from_slice(v);
+                }
+                None => b.extend_from_slice(&(-1i32).to_be_bytes()),
+            }
+        }
+    });
+}
+
+/// End of a statement's results, e.g. `SELECT 3`
+pub fn command_complete(buf: &mut Vec<u8>, tag: &str) {
+    message(buf, b'C', |b| put_cstr(b, tag));
+}
+
+/// Reply to a query string holding no statement
+pub fn empty_query_response(buf: &mut Vec<u8>) {
+    message(buf, b'I', |_| {});
+}
+
+pub fn parse_complete(buf: &mut Vec<u8>) {
+    message(buf, b'1', |_| {});
+}
+
+pub fn bind_complete(buf: &mut Vec<u8>) {
+    message(buf, b'2', |_| {});
+}
+
+pub fn close_complete(buf: &mut Vec<u8>) {
+    message(buf, b'3', |_| {});
+}
+
+/// Reply to Describe for a statement or portal that returns no rows
+pub fn no_data(buf: &mut Vec<u8>) {
+    message(buf, b'n', |_| {});
+}
+
+/// Execute stopped at its row limit; the portal has more rows
+pub fn portal_suspended(buf: &mut Vec<u8>) {
+    message(buf, b's', |_| {});
+}
+
+/// Types of a prepared statement's parameters
+pub fn parameter_description(buf: &mut Vec<u8>, type_oids: &[u32]) {
+    message(buf, b't', |b| {
+        b.extend_from_slice(&(type_oids.len() as i16).to_be_bytes());
+        for oid in type_oids {
+            b.extend_from_slice(&oid.to_be_bytes());
+        }
+    });
+}
+
+/// Start of a COPY FROM STDIN of `columns` columns, in text
+pub fn copy_in_response(buf: &mut Vec<u8>, columns: usize) {
+    copy_response(buf, b'G', columns);
+}
+
+/// Start of a COPY TO STDOUT of `columns` columns, in text
+pub fn copy_out_response(buf: &mut Vec<u8>, columns: usize) {
+    copy_response(buf, b'H', columns);
+}
+
+fn copy_response(buf: &mut Vec<u8>, tag: u8, columns: usize) {
+    message(buf, tag, |b| {
+        b.push(0);
+        b.extend_from_slice(&(columns as i16).to_be_bytes());
+        for _ in 0..columns {
+            b.extend_from_slice(&0i16.to_be_bytes());
+        }
+    });
+}
+
+pub fn copy_data(buf: &mut Vec<u8>, data: &[u8]) {
+    message(buf, b'd', |b| b.extend_from_slice(data));
+}
+
+pub fn copy_done(buf: &mut Vec<u8>) {
+    message(buf, b'c', |_| {});
+}
+
+/// An error with its SQLSTATE `code`
+pub fn error_response(buf: &mut Vec<u8>, code: &str, text: &str) {
+    report(buf, b'E', "ERROR", code, text);
+}
+
+/// An error that ends the connection
+pub fn fatal_response(buf: &mut Vec<u8>, code: &str, text: &str) {
+    report(buf, b'E', "FATAL", code, text);
+}
+
+/// A warning, which doesn't stop the statement
+pub fn notice_response(buf: &mut Vec<u8>, code: &str, text: &str) {
+    report(buf, b'N', "WARNING", code, text);
+}
+
+fn report(buf: &mut Vec<u8>, tag: u8, severity: &str, code: &str, text: &str) {
+    message(buf, tag, |b| {
+        for (field, value) in [(b'S', severity), (b'V', severity), (b'C', code), (b'M', text)] {
+            b.push(field);
+            put_cstr(b, value);
+        }
+        b.push(0);
+    });
+}
@@ -0,0 +1,188 @@
+//! Turning what `tonledb_sql::execute_sql` returns into Postgres result sets.
+//!
+//! Results take their columns from `described_fields`, so even an empty result
+//! describes them: a `SELECT` from a table from the catalog, one from a collection
+//! from its inferred schema, and `EXPLAIN` and `ANALYZE` the fields they answer with.
+//! Keys of the rows that these don't name are added as columns, in the order they
+//! are first seen, typed by the values under them. Values are in text format here;
+//! `extended` sends some types in binary when a client asks.
+
+use serde_json::Value as Json;
+use sqlparser::ast::{Expr, SelectItem, SetExpr, Statement};
+use tonledb_core::{DataType, Db, DbError};
+use crate::protocol::FieldDescription;
+
+pub const BOOL_OID: u32 = 16;
+pub const INT8_OID: u32 = 20;
+pub const TEXT_OID: u32 = 25;
+pub const JSON_OID: u32 = 114;
+pub const FLOAT8_OID: u32 = 701;
+pub const NUMERIC_OID: u32 = 1700;
+
+/// Rows of one statement, ready to send
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResultSet {
+    pub fields: Vec<FieldDescription>,
+    pub rows: Vec<Vec<Option<String>>>,
+    /// CommandComplete tag, e.g. `SELECT 2`
+    pub tag: String,
+}
+
+pub(crate) fn field(name: &str, type_oid: u32) -> FieldDescription {
+    let type_len = match type_oid {
+        BOOL_OID => 1,
+        INT8_OID | FLOAT8_OID => 8,
+        _ => -1,
+    };
+    FieldDescription { name: name.to_string(), type_oid, type_len, format: 0 }
+}
+
+/// Postgres type of a catalog column type
+pub fn type_oid(data_type: &DataType) -> u32 {
+    match data_type {
+        DataType::Integer => INT8_OID,
+        DataType::Float => FLOAT8_OID,
+        DataType::Text => TEXT_OID,
+        DataType::Boolean => BOOL_OID,
+        DataType::Json => JSON_OID,
+        DataType::Decimal => NUMERIC_OID,
+    }
+}
+
+/// The result set of `stmt`, which returned `value`
+pub fn result_set(db: &Db, stmt: &Statement, value: Json) -> ResultSet {
+    let rows = match value {
+        Json::Array(rows) => rows,
+        Json::Null => Vec::new(),
+        Json::Object(_) => vec![value],
+        other => vec![serde_json::json!({ "result": other })],
+    };
+    let mut fields = described_fields(db, stmt).unwrap_or_default();
+    // Keys the catalog doesn't know of, e.g. those written since a collection's schema was inferred
+    for row in &rows {
+        let Some(obj) = row.as_object() else { continue };
+        for key in obj.keys() {
+            if !fields.iter().any(|f| f.name == *key) {
+                fields.push(field(key, common_oid(rows.iter().filter_map(|r| r.get(key)))));
+            }
+        }
+    }
+    let cells = rows.iter().map(|row| fields.iter().map(|f| text_value(f.type_oid, row.get(&f.name))).collect()).collect();
+    ResultSet { fields, rows: cells, tag: command_tag(stmt, rows.len()) }
+}
+
+/// Columns `stmt` returns, worked out without running it (see
+/// `tonledb_sql::result_columns`); `None` if it returns no rows or can't be planned
+pub(crate) fn described_fields(db: &Db, stmt: &Statement) -> Option<Vec<FieldDescription>> {
+    catalog_fields(db, stmt).or_else(|| {
+        let columns = tonledb_sql::result_columns(db, stmt).ok()??;
+        Some(columns.iter().map(|c| field(&c.name, type_oid(&c.data_type))).collect())
+    })
+}
+
+/// Columns of a `SELECT` from a catalog table, if `stmt` is one whose projection
+/// names columns (or `*`)
+pub(crate) fn catalog_fields(db: &Db, stmt: &Statement) -> Option<Vec<FieldDescription>> {
+    let Statement::Query(query) = stmt else { return None };
+    let SetExpr::Select(select) = &*query.body else { return None };
+    let [from] = select.from.as_slice() else { return None };
+    let table = db.catalog.read().tables.get(&from.relation.to_string()).cloned()?;
+    let column_oid = |name: &str| table.columns.iter().find(|c| c.name == name).map(|c| type_oid(&c.data_type));
+    let mut fields = Vec::new();
+    for item in &select.projection {
+        match item {
+            SelectItem::Wildcard(_) => fields.extend(table.columns.iter().map(|c| field(&c.name, type_oid(&c.data_type)))),
+            SelectItem::UnnamedExpr(Expr::Identifier(ident)) => fields.push(field(&ident.value, column_oid(&ident.value)?)),
+            SelectItem::ExprWithAlias { expr: Expr::Identifier(ident), alias } => fields.push(field(&alias.value, column_oid(&ident.value)?)),
+            _ => return None,
+        }
+    }
+    Some(fields)
+}
+
+/// Type of a column of `values`: the one type they share, text if they differ
+pub(crate) fn common_oid<'a>(values: impl IntoIterator<Item = &'a Json>) -> u32 {
+    let mut oid = None;
+    for v in values {
+        let this = match v {
+            Json::Null => continue,
+            Json::Bool(_) => BOOL_OID,
+            Json::Number(n) if n.is_i64() || n.is_u64() => INT8_OID,
+            Json::Number(_) => NUMERIC_OID,
+            Json::String(_) => TEXT_OID,
+            Json::Array(_) | Json::Object(_) => JSON_OID,
+        };
+        oid = match oid {
+            None => Some(this),
+            Some(INT8_OID) if this == NUMERIC_OID => Some(NUMERIC_OID),
+            Some(NUMERIC_OID) if this == INT8_OID => Some(NUMERIC_OID),
+            Some(seen) if seen == this => Some(seen),
+            Some(_) => return TEXT_OID,
+        };
+    }
+    oid.unwrap_or(TEXT_OID)
+}
+
+/// Text format of `v` in a column of type `oid`; `None` for NULL
+pub fn text_value(oid: u32, v: Option<&Json>) -> Option<String> {
+    match v? {
+        Json::Null => None,
+        v if oid == JSON_OID => Some(v.to_string()),
+        Json::Bool(b) => Some(if *b { "t" } else { "f" }.to_string()),
+        Json::String(s) => Some(s.clone()),
+        other => Some(other.to_string()),
+    }
+}
+
+/// A boolean in any spelling Postgres accepts for one
+pub fn parse_bool(s: &str) -> Option<bool> {
+    match s.trim().to_ascii_lowercase().as_str() {
+        "t" | "true" | "y" | "yes" | "on" | "1" => Some(true),
+        "f" | "false" | "n" | "no" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// CommandComplete tag of `stmt` after it returned `rows` rows
+fn command_tag(stmt: &Statement, rows: usize) -> String {
+    match stmt {
+        Statement::Query(_) => format!("SELECT {}", rows),
+        Statement::Explain { .. } => "EXPLAIN".into(),
+        Statement::Analyze { .. } => "ANALYZE".into(),
+        _ => stmt.to_string().split_whitespace().next().unwrap_or_default().to_uppercase(),
+    }
+}
+
+/// An error to send to the client, with its SQLSTATE
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PgError {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl PgError {
+    pub fn new(code: &'static str, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+}
+
+impl From<DbError> for PgError {
+    fn from(e: DbError) -> Self {
+        Self::new(sqlstate(&e), e.to_string())
+    }
+}
+
+/// SQLSTATE of an error
+pub fn sqlstate(e: &DbError) -> &'static str {
+    match e {
+        // syntax_error_or_access_rule_violation: the engine rejects what it can't run
+        DbError::Invalid(_) => "42601",
+        DbError::NotFound(_) => "42P01",
+        DbError::Conflict(_) => "40001",
+        DbError::ResourceExhausted(_) => "53000",
+        DbError::DeadlineExceeded(_) => "57014",
+        DbError::UniqueViolation(_) => "23505",
+        DbError::NotNullViolation(_) => "23502",
+        DbError::Storage(_) | DbError::Corruption(_) => "XX000",
+    }
+}
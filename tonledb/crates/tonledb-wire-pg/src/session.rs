@@ -0,0 +1,508 @@
+//! Per-connection state: the settings `SET` changes and `SHOW` reads, and the
+//! transaction block the connection is in.
+//!
+//! A connection starts with the built-in settings (see `catalog`), over them the
+//! server's defaults from `PgOptions::settings` (say, a `search_path`), and over
+//! those the settings of its startup packet, including `-c name=value` in its
+//! `options`; startup settings it doesn't know are ignored. `SET`, `SET LOCAL`,
+//! `RESET` and `DISCARD ALL` change them, and the ones reported in
+//! ParameterStatus at startup are reported again whenever they change. Names with
+//! a dot (`app.tenant`) are custom settings, which take any value; `server_version`
+//! and the like can't be set, and `client_encoding` only to UTF8.
+//!
+//! `BEGIN` opens a transaction block and `COMMIT` or `ROLLBACK` closes it, which
+//! ReadyForQuery reports. A statement that fails inside a block fails the block:
+//! everything but `COMMIT` and `ROLLBACK` is refused until it ends, and `COMMIT`
+//! then rolls back. Rolling back undoes the block's `SET`s, and `SET LOCAL` lasts
+//! until the block ends. The engine runs and applies each statement on its own,
+//! though, so nothing a block did could be rolled back but its settings: statements
+//! that write (`COPY FROM` among them) are refused inside a block, which holds only
+//! reads, and so `ROLLBACK` never leaves writes behind. They are refused as well
+//! while `transaction_read_only` (or, outside a block,
+//! `default_transaction_read_only`) is on. Isolation levels are recorded for
+//! `SHOW` without changing how statements run.
+//!
+//! A user the `PasswordStore` gives read access only may run queries, `SHOW` and
+//! `EXPLAIN`, but nothing that writes (`check_access`).
+
+use sqlparser::ast::{DiscardObject, Expr, ObjectName, OneOrManyWithParens, Statement, TransactionAccessMode, TransactionMode, UnaryOperator, Value};
+use crate::auth::Access;
+use crate::catalog::{OTHER_SETTINGS, REPORTED_SETTINGS};
+use crate::protocol::{self, TransactionStatus};
+use crate::results::PgError;
+
+/// Built-in settings that can't be changed
+const READ_ONLY: &[&str] = &[
+    "server_version",
+    "server_version_num",
+    "server_encoding",
+    "integer_datetimes",
+    "max_identifier_length",
+    "lc_collate",
+    "lc_ctype",
+];
+
+/// Startup packet entries that aren't settings
+const NOT_SETTINGS: &[&str] = &["user", "database", "replication", "options"];
+
+/// Settings and transaction state of one connection
+#[derive(Debug, Clone)]
+pub struct SessionState {
+    /// Who logged in
+    pub user: String,
+    /// What `pg_backend_pid()` gives
+    pub process_id: u32,
+    /// What the user may do; everything unless a `PasswordStore` says otherwise
+    pub access: Access,
+    /// Every setting, built-in ones under their canonical names
+    settings: Vec<(String, String)>,
+    /// Settings as the session started, which RESET goes back to
+    defaults: Vec<(String, String)>,
+    transaction: TransactionStatus,
+    /// Settings as the transaction block began, restored on rollback
+    saved: Vec<(String, String)>,
+    /// Values settings changed with SET LOCAL had before, restored when the block ends
+    local: Vec<(String, String)>,
+    /// Reported settings as last sent in ParameterStatus
+    reported: Vec<(String, String)>,
+}
+
+/// Position of setting `name` in `settings`; setting names ignore case
+fn position(settings: &[(String, String)], name: &str) -> Option<usize> {
+    settings.iter().position(|(n, _)| n.eq_ignore_ascii_case(name))
+}
+
+impl SessionState {
+    /// A session of `user`, starting with `defaults` over the built-in settings
+    pub fn new(user: &str, process_id: u32, defaults: &[(String, String)]) -> Self {
+        let settings = REPORTED_SETTINGS.iter().chain(OTHER_SETTINGS).map(|(n, v)| (n.to_string(), v.to_string())).collect();
+        let mut session = Self {
+            user: user.to_string(),
+            process_id,
+            access: Access::Admin,
+            settings,
+            defaults: Vec::new(),
+            transaction: TransactionStatus::Idle,
+            saved: Vec::new(),
+            local: Vec::new(),
+            reported: Vec::new(),
+        };
+        for (name, value) in defaults {
+            // Checked when the server starts, see `check_settings`
+            let _ = session.set(name, value);
+        }
+        session.defaults = session.settings.clone();
+        session
+    }
+
+    /// Apply the settings of a startup packet, which become what RESET goes back to
+    pub fn apply_startup(&mut self, parameters: &[(String, String)]) {
+        for (name, value) in parameters {
+            if name == "options" {
+                for (name, value) in command_line_settings(value) {
+                    let _ = self.set(&name, &value);
+                }
+            } else if !NOT_SETTINGS.contains(&name.as_str()) {
+                let _ = self.set(name, value);
+            }
+        }
+        self.defaults = self.settings.clone();
+    }
+
+    /// The canonical name and value of setting `name`
+    pub fn setting(&self, name: &str) -> Option<(&str, &str)> {
+        position(&self.settings, name).map(|i| (self.settings[i].0.as_str(), self.settings[i].1.as_str()))
+    }
+
+    /// Every setting, as `SHOW ALL` lists them
+    pub fn settings(&self) -> &[(String, String)] {
+        &self.settings
+    }
+
+    pub fn transaction_status(&self) -> TransactionStatus {
+        self.transaction
+    }
+
+    /// Set `name` to `value` for the rest of the session
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), PgError> {
+        self.assign(name, value)?;
+        // A session-wide SET outlasts an earlier SET LOCAL of the block
+        if let Some(i) = position(&self.local, name) {
+            self.local.remove(i);
+        }
+        Ok(())
+    }
+
+    /// Set `name` to `value` until the transaction block ends
+    fn set_local(&mut self, name: &str, value: &str) -> Result<(), PgError> {
+        let before = self.setting(name).map(|(n, v)| (n.to_string(), v.to_string()));
+        self.assign(name, value)?;
+        if let Some(before) = before.filter(|(n, _)| position(&self.local, n).is_none()) {
+            self.local.push(before);
+        }
+        Ok(())
+    }
+
+    fn assign(&mut self, name: &str, value: &str) -> Result<(), PgError> {
+        let value = checked_value(name, value)?;
+        match position(&self.settings, name) {
+            Some(i) => self.settings[i].1 = value,
+            None if name.contains('.') => self.settings.push((name.to_lowercase(), value)),
+            None => return Err(unrecognized_setting(name)),
+        }
+        Ok(())
+    }
+
+    /// Put `name` back to its value when the session started
+    pub fn reset(&mut self, name: &str) -> Result<(), PgError> {
+        if READ_ONLY.iter().any(|n| n.eq_ignore_ascii_case(name)) {
+            return Err(read_only_setting(name));
+        }
+        match (position(&self.settings, name), position(&self.defaults, name)) {
+            (Some(i), Some(d)) => self.settings[i].1 = self.defaults[d].1.clone(),
+            (Some(i), None) => {
+                self.settings.remove(i);
+            }
+            (None, _) if name.contains('.') => {}
+            (None, _) => return Err(unrecognized_setting(name)),
+        }
+        if let Some(i) = position(&self.local, name) {
+            self.local.remove(i);
+        }
+        Ok(())
+    }
+
+    /// Put every setting back to its value when the session started
+    pub fn reset_all(&mut self) {
+        self.settings = self.defaults.clone();
+        self.local.clear();
+    }
+
+    /// Refuse `stmt` while the transaction block has failed, unless it ends the block
+    pub fn check_runnable(&self, stmt: &Statement) -> Result<(), PgError> {
+        match (self.transaction, stmt) {
+            (TransactionStatus::Failed, Statement::Commit { .. } | Statement::Rollback { .. }) => Ok(()),
+            (TransactionStatus::Failed, _) => {
+                Err(PgError::new("25P02", "current transaction is aborted, commands ignored until end of transaction block"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Refuse `stmt` if it writes and the user may only read
+    pub fn check_access(&self, stmt: &Statement) -> Result<(), PgError> {
+        if self.access < Access::Write && !is_read_only(stmt) {
+            return Err(PgError::new("42501", format!("permission denied for user {}: read-only access", self.user)));
+        }
+        Ok(())
+    }
+
+    /// Refuse `stmt`, unless it only reads, inside a transaction block, whose
+    /// rollback couldn't undo it, or in a read-only transaction
+    pub fn check_writes(&self, stmt: &Statement) -> Result<(), PgError> {
+        if is_read_only(stmt) {
+            return Ok(());
+        }
+        let command = match stmt {
+            Statement::Copy { .. } => "COPY FROM".to_string(),
+            _ => stmt.to_string().split_whitespace().next().unwrap_or_default().to_uppercase(),
+        };
+        let on = |name| self.setting(name).is_some_and(|(_, v)| v == "on");
+        let idle = self.transaction == TransactionStatus::Idle;
+        if on("transaction_read_only") || (idle && on("default_transaction_read_only")) {
+            return Err(PgError::new("25006", format!("cannot execute {} in a read-only transaction", command)));
+        }
+        if !idle {
+            return Err(PgError::new("0A000", format!("{} is not supported inside a transaction block", command)));
+        }
+        Ok(())
+    }
+
+    /// A statement failed: inside a transaction block, the block fails
+    pub fn fail(&mut self) {
+        if self.transaction == TransactionStatus::InTransaction {
+            self.transaction = TransactionStatus::Failed;
+        }
+    }
+
+    /// Run `stmt`, a statement of `is_session_statement`, appending its warnings to
+    /// `buf`; its command tag
+    pub fn answer(&mut self, stmt: &Statement, buf: &mut Vec<u8>) -> Result<String, PgError> {
+        match stmt {
+            Statement::SetVariable { local, variables: OneOrManyWithParens::One(name), value, .. } => {
+                let name = setting_name(name);
+                match set_value(value)? {
+                    None if name.eq_ignore_ascii_case("all") => self.reset_all(),
+                    None => self.reset(&name)?,
+                    Some(value) if *local => self.answer_set_local(&name, &value, buf)?,
+                    Some(value) => self.set(&name, &value)?,
+                }
+                Ok("SET".into())
+            }
+            Statement::SetVariable { .. } => Err(PgError::new("42601", "SET takes one setting at a time")),
+            Statement::SetTimeZone { local, value } => {
+                let zone = match value {
+                    // SET TIME ZONE LOCAL is SET TIME ZONE DEFAULT
+                    Expr::Identifier(ident) if ident.quote_style.is_none() && ident.value.eq_ignore_ascii_case("local") => None,
+                    _ => set_value(std::slice::from_ref(value))?,
+                };
+                match zone {
+                    None => self.reset("TimeZone")?,
+                    Some(zone) if *local => self.answer_set_local("TimeZone", &zone, buf)?,
+                    Some(zone) => self.set("TimeZone", &zone)?,
+                }
+                Ok("SET".into())
+            }
+            Statement::SetTransaction { modes, session: true, .. } => {
+                for (name, value) in transaction_settings(modes) {
+                    self.set(&format!("default_{}", name), &value)?;
+                    self.set(name, &value)?;
+                }
+                Ok("SET".into())
+            }
+            Statement::SetTransaction { modes, .. } => {
+                if self.transaction == TransactionStatus::Idle {
+                    protocol::notice_response(buf, "25P01", "SET TRANSACTION can only be used in transaction blocks");
+                } else {
+                    for (name, value) in transaction_settings(modes) {
+                        self.set_local(name, &value)?;
+                    }
+                }
+                Ok("SET".into())
+            }
+            Statement::StartTransaction { modes, begin, .. } => {
+                if self.transaction == TransactionStatus::Idle {
+                    self.transaction = TransactionStatus::InTransaction;
+                    self.saved = self.settings.clone();
+                    for (name, value) in transaction_settings(modes) {
+                        self.set_local(name, &value)?;
+                    }
+                } else {
+                    protocol::notice_response(buf, "25001", "there is already a transaction in progress");
+                }
+                Ok(if *begin { "BEGIN" } else { "START TRANSACTION" }.into())
+            }
+            Statement::Commit { chain } => {
+                let tag = match self.transaction {
+                    TransactionStatus::Idle => {
+                        protocol::notice_response(buf, "25P01", "there is no transaction in progress");
+                        "COMMIT"
+                    }
+                    TransactionStatus::InTransaction => {
+                        self.end(true);
+                        "COMMIT"
+                    }
+                    TransactionStatus::Failed => {
+                        self.end(false);
+                        "ROLLBACK"
+                    }
+                };
+                self.chain(*chain);
+                Ok(tag.into())
+            }
+            Statement::Rollback { savepoint: Some(_), .. } => Err(PgError::new("0A000", "savepoints are not supported")),
+            Statement::Rollback { chain, .. } => {
+                if self.transaction == TransactionStatus::Idle {
+                    protocol::notice_response(buf, "25P01", "there is no transaction in progress");
+                } else {
+                    self.end(false);
+                }
+                self.chain(*chain);
+                Ok("ROLLBACK".into())
+            }
+            Statement::Discard { object_type } => {
+                if self.transaction != TransactionStatus::Idle {
+                    return Err(PgError::new("25001", format!("DISCARD {} cannot run inside a transaction block", object_type)));
+                }
+                if matches!(object_type, DiscardObject::ALL) {
+                    self.reset_all();
+                }
+                Ok(format!("DISCARD {}", object_type))
+            }
+            _ => Err(PgError::new("42601", "not a session statement")),
+        }
+    }
+
+    /// SET LOCAL, which only warns outside a transaction block
+    fn answer_set_local(&mut self, name: &str, value: &str, buf: &mut Vec<u8>) -> Result<(), PgError> {
+        if self.transaction == TransactionStatus::Idle {
+            checked_value(name, value)?;
+            protocol::notice_response(buf, "25P01", "SET LOCAL can only be used in transaction blocks");
+            return Ok(());
+        }
+        self.set_local(name, value)
+    }
+
+    /// Close the transaction block, keeping its settings or not
+    fn end(&mut self, commit: bool) {
+        let saved = std::mem::take(&mut self.saved);
+        let local = std::mem::take(&mut self.local);
+        if commit {
+            for (name, value) in local {
+                if let Some(i) = position(&self.settings, &name) {
+                    self.settings[i].1 = value;
+                }
+            }
+        } else {
+            self.settings = saved;
+        }
+        self.transaction = TransactionStatus::Idle;
+    }
+
+    /// `AND CHAIN`: open the next block straight away
+    fn chain(&mut self, chain: bool) {
+        if chain {
+            self.transaction = TransactionStatus::InTransaction;
+            self.saved = self.settings.clone();
+        }
+    }
+
+    /// Append a ParameterStatus for each reported setting that changed since the
+    /// last call (all of them the first time)
+    pub fn report_changes(&mut self, buf: &mut Vec<u8>) {
+        for (name, _) in REPORTED_SETTINGS {
+            let value = self.setting(name).map(|(_, v)| v).unwrap_or_default();
+            match position(&self.reported, name) {
+                Some(i) if self.reported[i].1 == value => {}
+                Some(i) => {
+                    protocol::parameter_status(buf, name, value);
+                    self.reported[i].1 = value.to_string();
+                }
+                None => {
+                    protocol::parameter_status(buf, name, value);
+                    self.reported.push((name.to_string(), value.to_string()));
+                }
+            }
+        }
+    }
+}
+
+/// Whether `stmt` only reads: a query, `SHOW`, or `EXPLAIN` of one (plain
+/// `EXPLAIN` of anything, as it doesn't run the statement)
+pub fn is_read_only(stmt: &Statement) -> bool {
+    match stmt {
+        Statement::Explain { analyze: true, statement, .. } => is_read_only(statement),
+        Statement::Query(_)
+        | Statement::Explain { .. }
+        | Statement::ExplainTable { .. }
+        | Statement::ShowVariable { .. }
+        | Statement::ShowVariables { .. }
+        | Statement::ShowTables { .. }
+        | Statement::ShowColumns { .. }
+        | Statement::ShowCreate { .. } => true,
+        _ => false,
+    }
+}
+
+/// Whether `stmt` changes the session rather than running in the engine
+pub fn is_session_statement(stmt: &Statement) -> bool {
+    matches!(
+        stmt,
+        Statement::SetVariable { hivevar: false, .. }
+            | Statement::SetTimeZone { .. }
+            | Statement::SetTransaction { snapshot: None, .. }
+            | Statement::StartTransaction { .. }
+            | Statement::Commit { .. }
+            | Statement::Rollback { .. }
+            | Statement::Discard { .. }
+    )
+}
+
+/// Check that setting `name` may be set to `value`; the value to keep
+fn checked_value(name: &str, value: &str) -> Result<String, PgError> {
+    if READ_ONLY.iter().any(|n| n.eq_ignore_ascii_case(name)) {
+        return Err(read_only_setting(name));
+    }
+    if name.eq_ignore_ascii_case("client_encoding") {
+        let encoding = value.to_uppercase().replace(['-', '_'], "");
+        if encoding != "UTF8" && encoding != "UNICODE" {
+            return Err(PgError::new("22023", format!("invalid value for parameter \"client_encoding\": \"{}\"", value)));
+        }
+        return Ok("UTF8".into());
+    }
+    Ok(value.to_string())
+}
+
+/// Check settings for `PgOptions::settings`, which every session starts with
+pub fn check_settings(settings: &[(String, String)]) -> Result<(), PgError> {
+    let mut session = SessionState::new("", 0, &[]);
+    settings.iter().try_for_each(|(name, value)| session.set(name, value))
+}
+
+/// A query string that is a lone `RESET name`, `RESET ALL` or `RESET TIME ZONE`
+/// as the `SET ... TO DEFAULT` it means, which the parser does read
+pub(crate) fn reset_as_set(sql: &str) -> Option<String> {
+    let sql = sql.trim().trim_end_matches(';');
+    let (keyword, rest) = sql.split_once(char::is_whitespace)?;
+    let name = rest.trim();
+    if !keyword.eq_ignore_ascii_case("reset") || name.is_empty() || name.contains(';') {
+        return None;
+    }
+    match name.split_whitespace().collect::<Vec<_>>().as_slice() {
+        [time, zone] if time.eq_ignore_ascii_case("time") && zone.eq_ignore_ascii_case("zone") => Some("SET TIME ZONE DEFAULT".into()),
+        [name] => Some(format!("SET {} TO DEFAULT", name)),
+        _ => None,
+    }
+}
+
+fn setting_name(name: &ObjectName) -> String {
+    name.0.iter().map(|i| i.value.as_str()).collect::<Vec<_>>().join(".")
+}
+
+/// The value a SET gives, its items joined with commas; `None` for `DEFAULT`
+fn set_value(values: &[Expr]) -> Result<Option<String>, PgError> {
+    if let [Expr::Identifier(ident)] = values {
+        if ident.quote_style.is_none() && ident.value.eq_ignore_ascii_case("default") {
+            return Ok(None);
+        }
+    }
+    let mut items = Vec::with_capacity(values.len());
+    for value in values {
+        items.push(match value {
+            Expr::Value(Value::SingleQuotedString(s) | Value::EscapedStringLiteral(s) | Value::DollarQuotedString(sqlparser::ast::DollarQuotedString { value: s, .. })) => s.clone(),
+            Expr::Value(Value::Number(n, _)) => n.clone(),
+            Expr::Value(Value::Boolean(b)) => if *b { "on" } else { "off" }.to_string(),
+            Expr::UnaryOp { op: UnaryOperator::Minus, expr } if matches!(**expr, Expr::Value(Value::Number(..))) => format!("-{}", expr),
+            Expr::Identifier(ident) if ident.quote_style.is_none() => ident.value.clone(),
+            Expr::Identifier(_) | Expr::CompoundIdentifier(_) => value.to_string(),
+            other => return Err(PgError::new("22023", format!("invalid value for a setting: {}", other))),
+        });
+    }
+    Ok(Some(items.join(", ")))
+}
+
+/// Settings a list of transaction modes gives
+fn transaction_settings(modes: &[TransactionMode]) -> Vec<(&'static str, String)> {
+    modes
+        .iter()
+        .map(|mode| match mode {
+            TransactionMode::IsolationLevel(level) => ("transaction_isolation", level.to_string().to_lowercase()),
+            TransactionMode::AccessMode(TransactionAccessMode::ReadOnly) => ("transaction_read_only", "on".into()),
+            TransactionMode::AccessMode(TransactionAccessMode::ReadWrite) => ("transaction_read_only", "off".into()),
+        })
+        .collect()
+}
+
+/// Settings in the `options` of a startup packet: `-c name=value` or `--name=value`
+fn command_line_settings(options: &str) -> Vec<(String, String)> {
+    let mut settings = Vec::new();
+    let mut words = options.split_whitespace();
+    while let Some(word) = words.next() {
+        let setting = match word {
+            "-c" => words.next(),
+            _ => word.strip_prefix("--").or_else(|| word.strip_prefix("-c")),
+        };
+        if let Some((name, value)) = setting.and_then(|s| s.split_once('=')) {
+            settings.push((name.replace('-', "_"), value.to_string()));
+        }
+    }
+    settings
+}
+
+pub(crate) fn unrecognized_setting(name: &str) -> PgError {
+    PgError::new("42704", format!("unrecognized configuration parameter \"{}\"", name))
+}
+
+fn read_only_setting(name: &str) -> PgError {
+    PgError::new("55P02", format!("parameter \"{}\" cannot be changed", name))
+}
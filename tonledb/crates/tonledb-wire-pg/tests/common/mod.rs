@@ -0,0 +1,99 @@
+//! Helpers shared by the Postgres wire tests: a server task to talk to, framing
+//! messages both ways, and the `orders` table most of them query
+
+// Each test file uses its own share of these
+#![allow(dead_code)]
+
+use std::sync::Arc;
+use serde_json::json;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tonledb_core::{DataType, Db};
+use tonledb_sql::fixture;
+use tonledb_storage::InMemoryStore;
+use tonledb_wire_pg::{handle_pg_connection, PgOptions};
+
+/// `orders` with ids 1 (ann, paid) and 2 (no customer, unpaid), keyed by id
+pub fn orders_db() -> Arc<Db> {
+    let db = Db::new(Arc::new(InMemoryStore::new(1000)));
+    fixture::create_table(&db, "orders", &[("id", DataType::Integer), ("customer", DataType::Text), ("paid", DataType::Boolean)], Some("id"));
+    fixture::insert_rows(&db, "orders", &[json!({"id": 1, "customer": "ann", "paid": true}), json!({"id": 2, "paid": false})]).unwrap();
+    Arc::new(db)
+}
+
+/// A client of a server task on `db`
+pub fn serve(db: Arc<Db>, options: Arc<PgOptions>) -> DuplexStream {
+    let (client, server) = tokio::io::duplex(1 << 16);
+    tokio::spawn(handle_pg_connection(server, db, options));
+    client
+}
+
+/// Send a startup packet with `parameters`
+pub async fn send_startup(client: &mut DuplexStream, parameters: &[(&str, &str)]) {
+    let mut startup = 196608i32.to_be_bytes().to_vec();
+    for (name, value) in parameters {
+        startup.extend_from_slice(format!("{}\0{}\0", name, value).as_bytes());
+    }
+    startup.push(0);
+    client.write_all(&((startup.len() + 4) as i32).to_be_bytes()).await.unwrap();
+    client.write_all(&startup).await.unwrap();
+}
+
+/// A client of a server task on `db`, logged in as ann
+pub async fn connect(db: Arc<Db>, options: Arc<PgOptions>) -> DuplexStream {
+    let mut client = serve(db, options);
+    send_startup(&mut client, &[("user", "ann")]).await;
+    read_until_ready(&mut client).await;
+    client
+}
+
+/// The next message from the server
+pub async fn read_message(client: &mut DuplexStream) -> (u8, Vec<u8>) {
+    let tag = client.read_u8().await.unwrap();
+    let len = client.read_i32().await.unwrap() as usize;
+    let mut body = vec![0; len - 4];
+    client.read_exact(&mut body).await.unwrap();
+    (tag, body)
+}
+
+/// Messages up to and including the next ReadyForQuery
+pub async fn read_until_ready(client: &mut DuplexStream) -> Vec<(u8, Vec<u8>)> {
+    let mut messages = Vec::new();
+    loop {
+        let message = read_message(client).await;
+        let ready = message.0 == b'Z';
+        messages.push(message);
+        if ready {
+            return messages;
+        }
+    }
+}
+
+pub async fn send(client: &mut DuplexStream, tag: u8, body: &[u8]) {
+    client.write_all(&[tag]).await.unwrap();
+    client.write_all(&((body.len() + 4) as i32).to_be_bytes()).await.unwrap();
+    client.write_all(body).await.unwrap();
+}
+
+/// Run `sql` as a simple query; its answers up to ReadyForQuery
+pub async fn query(client: &mut DuplexStream, sql: &str) -> Vec<(u8, Vec<u8>)> {
+    send(client, b'Q', &[sql.as_bytes(), b"\0"].concat()).await;
+    read_until_ready(client).await
+}
+
+/// Field `field` of an ErrorResponse or NoticeResponse body
+pub fn error_field(body: &[u8], field: u8) -> String {
+    let mut at = 0;
+    while body[at] != 0 {
+        let end = at + 1 + body[at + 1..].iter().position(|b| *b == 0).unwrap();
+        if body[at] == field {
+            return String::from_utf8(body[at + 1..end].to_vec()).unwrap();
+        }
+        at = end + 1;
+    }
+    panic!("no field {}", field as char)
+}
+
+/// SQLSTATE of the first ErrorResponse
+pub fn error_code(messages: &[(u8, Vec<u8>)]) -> Option<String> {
+    messages.iter().find(|(tag, _)| *tag == b'E').map(|(_, body)| error_field(body, b'C'))
+}
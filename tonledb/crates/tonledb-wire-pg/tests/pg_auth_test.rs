@@ -0,0 +1,269 @@
+//! Tests for password authentication: cleartext, MD5 and SCRAM-SHA-256, and what
+//! a user's access allows
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use base64::{engine::general_purpose::STANDARD as B64, Engine as _};
+use hmac::{Hmac, Mac};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tonledb_core::{DataType, Db};
+use tonledb_sql::fixture;
+use tonledb_storage::InMemoryStore;
+use tonledb_wire_pg::auth::{self, Access, PasswordStore};
+use tonledb_wire_pg::connections::ConnectionRegistry;
+use tonledb_wire_pg::{AuditLog, PgOptions};
+
+mod common;
+use common::{read_message, send};
+
+/// Every user's password is `secret`; `md5user` and `scramuser` have it stored.
+/// `reader` may only read.
+struct Users {
+    scram: String,
+}
+
+impl PasswordStore for Users {
+    fn verify(&self, _user: &str, password: &str) -> bool {
+        password == "secret"
+    }
+
+    fn access(&self, user: &str) -> Access {
+        match user {
+            "reader" => Access::Read,
+            _ => Access::Admin,
+        }
+    }
+
+    fn stored_password(&self, user: &str) -> Option<String> {
+        match user {
+            "md5user" => Some(format!("md5{}", hex(&Md5::digest(b"secretmd5user")))),
+            "scramuser" => Some(self.scram.clone()),
+            _ => None,
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Audit records as `user action resource result`
+#[derive(Default)]
+struct Trail(Mutex<Vec<String>>);
+
+impl AuditLog for Trail {
+    fn record(&self, user: &str, action: &str, resource: &str, result: &str) {
+        self.0.lock().unwrap().push(format!("{} {} {} {}", user, action, resource, result));
+    }
+}
+
+/// A client that has sent its startup packet as `user`
+async fn start(user: &str) -> DuplexStream {
+    start_on(user, Arc::new(Db::new(Arc::new(InMemoryStore::new(1000)))), None).await
+}
+
+/// A client of `db` that has sent its startup packet as `user`
+async fn start_on(user: &str, db: Arc<Db>, audit: Option<Arc<dyn AuditLog>>) -> DuplexStream {
+    let users = Users { scram: auth::scram_verifier("secret").to_string() };
+    let options = PgOptions { passwords: Some(Arc::new(users)), audit, ..Default::default() };
+    let mut client = common::serve(db, Arc::new(options));
+    common::send_startup(&mut client, &[("user", user)]).await;
+    client
+}
+
+/// Authentication request code and data
+fn request(message: &(u8, Vec<u8>)) -> (i32, &[u8]) {
+    assert_eq!(message.0, b'R');
+    (i32::from_be_bytes(message.1[..4].try_into().unwrap()), &message.1[4..])
+}
+
+/// Read past AuthenticationOk to ReadyForQuery
+async fn expect_logged_in(client: &mut DuplexStream) {
+    assert_eq!(request(&read_message(client).await).0, 0);
+    while read_message(client).await.0 != b'Z' {}
+}
+
+#[tokio::test]
+async fn test_cleartext_password() {
+    let mut client = start("ann").await;
+    assert_eq!(request(&read_message(&mut client).await).0, 3);
+    send(&mut client, b'p', b"secret\0").await;
+    expect_logged_in(&mut client).await;
+
+    let mut client = start("ann").await;
+    read_message(&mut client).await;
+    send(&mut client, b'p', b"wrong\0").await;
+    let (tag, body) = read_message(&mut client).await;
+    assert_eq!(tag, b'E');
+    assert!(body.windows(6).any(|w| w == b"C28P01"));
+}
+
+#[tokio::test]
+async fn test_large_messages_before_login_close_the_connection() {
+    // A password message claiming 1 MiB is refused before any of it is read
+    let mut client = start("ann").await;
+    assert_eq!(request(&read_message(&mut client).await).0, 3);
+    client.write_all(b"p").await.unwrap();
+    client.write_all(&(1i32 << 20).to_be_bytes()).await.unwrap();
+    assert_eq!(client.read_to_end(&mut Vec::new()).await.unwrap(), 0);
+
+    // So is a startup packet longer than Postgres allows
+    let mut client = common::serve(Arc::new(Db::new(Arc::new(InMemoryStore::new(1000)))), Default::default());
+    client.write_all(&20_000i32.to_be_bytes()).await.unwrap();
+    assert_eq!(client.read_to_end(&mut Vec::new()).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_copy_from_needs_write_access() {
+    let db = Arc::new(Db::new(Arc::new(InMemoryStore::new(1000))));
+    fixture::create_table(&db, "t", &[("id", DataType::Integer)], None);
+    let trail = Arc::new(Trail::default());
+
+    let mut client = start_on("reader", db.clone(), Some(trail.clone())).await;
+    read_message(&mut client).await;
+    send(&mut client, b'p', b"secret\0").await;
+    expect_logged_in(&mut client).await;
+    send(&mut client, b'Q', b"COPY t FROM STDIN\0").await;
+    let (tag, body) = read_message(&mut client).await;
+    assert_eq!(tag, b'E');
+    assert!(body.windows(6).any(|w| w == b"C42501"));
+    assert_eq!(read_message(&mut client).await.0, b'Z');
+    // Reading is still allowed
+    send(&mut client, b'Q', b"COPY t TO STDOUT\0").await;
+    assert_eq!(read_message(&mut client).await.0, b'H');
+
+    let mut client = start_on("ann", db.clone(), Some(trail.clone())).await;
+    read_message(&mut client).await;
+    send(&mut client, b'p', b"secret\0").await;
+    expect_logged_in(&mut client).await;
+    send(&mut client, b'Q', b"COPY t FROM STDIN\0").await;
+    assert_eq!(read_message(&mut client).await.0, b'G');
+    send(&mut client, b'd', b"1\n").await;
+    send(&mut client, b'c', b"").await;
+    let (tag, body) = read_message(&mut client).await;
+    assert_eq!((tag, body.as_slice()), (b'C', b"COPY 1\0".as_slice()));
+    assert_eq!(read_message(&mut client).await.0, b'Z');
+
+    assert_eq!(*trail.0.lock().unwrap(), ["reader COPY pg:t forbidden", "ann COPY pg:t ok"]);
+}
+
+#[tokio::test]
+async fn test_reader_refused_writes() {
+    let db = Arc::new(Db::new(Arc::new(InMemoryStore::new(1000))));
+    fixture::create_table(&db, "t", &[("id", DataType::Integer)], None);
+    let trail = Arc::new(Trail::default());
+    let mut client = start_on("reader", db.clone(), Some(trail.clone())).await;
+    read_message(&mut client).await;
+    send(&mut client, b'p', b"secret\0").await;
+    expect_logged_in(&mut client).await;
+
+    send(&mut client, b'Q', b"INSERT INTO t (id) VALUES (1)\0").await;
+    let (tag, body) = read_message(&mut client).await;
+    assert_eq!(tag, b'E');
+    assert!(body.windows(6).any(|w| w == b"C42501"));
+    assert_eq!(read_message(&mut client).await.0, b'Z');
+    // Prepared, it is refused when it would run
+    send(&mut client, b'P', b"\0INSERT INTO t (id) VALUES (2)\0\0\0").await;
+    send(&mut client, b'B', b"\0\0\0\0\0\0\0\0").await;
+    send(&mut client, b'E', b"\0\0\0\0\0").await;
+    send(&mut client, b'S', b"").await;
+    assert_eq!(read_message(&mut client).await.0, b'1');
+    assert_eq!(read_message(&mut client).await.0, b'2');
+    let (tag, body) = read_message(&mut client).await;
+    assert_eq!(tag, b'E');
+    assert!(body.windows(6).any(|w| w == b"C42501"));
+    assert_eq!(read_message(&mut client).await.0, b'Z');
+    // Reading is still allowed
+    send(&mut client, b'Q', b"SELECT id FROM t\0").await;
+    assert_eq!(read_message(&mut client).await.0, b'T');
+    while read_message(&mut client).await.0 != b'Z' {}
+
+    assert!(tonledb_sql::execute_sql(&db, "SELECT id FROM t").unwrap().as_array().unwrap().is_empty());
+    assert_eq!(*trail.0.lock().unwrap(), ["reader SQL pg:query forbidden", "reader SQL pg:query forbidden"]);
+}
+
+#[tokio::test]
+async fn test_md5_password() {
+    let mut client = start("md5user").await;
+    let message = read_message(&mut client).await;
+    let (code, salt) = request(&message);
+    assert_eq!(code, 5);
+    let inner = hex(&Md5::digest(b"secretmd5user"));
+    let answer = format!("md5{}\0", hex(&Md5::digest([inner.as_bytes(), salt].concat())));
+    send(&mut client, b'p', answer.as_bytes()).await;
+    expect_logged_in(&mut client).await;
+}
+
+#[tokio::test]
+async fn test_scram_sha_256() {
+    let mut client = start("scramuser").await;
+    let message = read_message(&mut client).await;
+    assert_eq!(request(&message), (10, b"SCRAM-SHA-256\0\0".as_slice()));
+
+    let client_first_bare = "n=,r=clientnonce";
+    let mut initial = b"SCRAM-SHA-256\0".to_vec();
+    initial.extend_from_slice(&((client_first_bare.len() + 3) as i32).to_be_bytes());
+    initial.extend_from_slice(format!("n,,{}", client_first_bare).as_bytes());
+    send(&mut client, b'p', &initial).await;
+
+    let message = read_message(&mut client).await;
+    let (code, data) = request(&message);
+    assert_eq!(code, 11);
+    let server_first = String::from_utf8(data.to_vec()).unwrap();
+    let attr = |name: &str| server_first.split(',').find_map(|a| a.strip_prefix(name)).unwrap().to_string();
+    let nonce = attr("r=");
+    assert!(nonce.starts_with("clientnonce"));
+    let salted = auth::salted_password("secret", &B64.decode(attr("s=")).unwrap(), attr("i=").parse().unwrap());
+
+    let without_proof = format!("c=biws,r={}", nonce);
+    let auth_message = format!("{},{},{}", client_first_bare, server_first, without_proof);
+    let client_key = hmac(&salted, b"Client Key");
+    let signature = hmac(&Sha256::digest(&client_key), auth_message.as_bytes());
+    let proof: Vec<u8> = client_key.iter().zip(signature).map(|(k, s)| k ^ s).collect();
+    send(&mut client, b'p', format!("{},p={}", without_proof, B64.encode(proof)).as_bytes()).await;
+
+    let message = read_message(&mut client).await;
+    let (code, data) = request(&message);
+    assert_eq!(code, 12);
+    let server_signature = hmac(&hmac(&salted, b"Server Key"), auth_message.as_bytes());
+    assert_eq!(data, format!("v={}", B64.encode(server_signature)).as_bytes());
+    expect_logged_in(&mut client).await;
+}
+
+#[test]
+fn test_scram_verifier_round_trips() {
+    let verifier = auth::scram_verifier("secret");
+    assert!(verifier.to_string().starts_with("SCRAM-SHA-256$4096:"));
+    assert_eq!(auth::ScramVerifier::parse(&verifier.to_string()), Some(verifier));
+}
+
+#[tokio::test]
+async fn test_client_that_never_logs_in_is_disconnected() {
+    let users = Users { scram: auth::scram_verifier("secret").to_string() };
+    let options = Arc::new(PgOptions {
+        passwords: Some(Arc::new(users)),
+        connections: Arc::new(ConnectionRegistry::new(Some(1))),
+        authentication_timeout: Some(Duration::from_millis(50)),
+        ..Default::default()
+    });
+    let mut client = common::serve(Arc::new(Db::new(Arc::new(InMemoryStore::new(1000)))), options.clone());
+    common::send_startup(&mut client, &[("user", "ann")]).await;
+    assert_eq!(read_message(&mut client).await.0, b'R');
+
+    // Never answering the password request, it's dropped and its slot freed
+    assert!(client.read_u8().await.is_err());
+    assert!(options.connections.is_empty());
+
+    // So is one that never finishes its startup packet
+    let mut client = common::serve(Arc::new(Db::new(Arc::new(InMemoryStore::new(1000)))), options);
+    client.write_all(&100i32.to_be_bytes()).await.unwrap();
+    assert!(client.read_u8().await.is_err());
+}
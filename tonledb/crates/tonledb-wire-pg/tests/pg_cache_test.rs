@@ -0,0 +1,110 @@
+//! Tests for the statement cache: parsed query strings shared by connections, and
+//! prepared statements bound again without parsing
+
+use std::sync::Arc;
+use serde_json::json;
+use tokio::io::DuplexStream;
+use tonledb_sql::fixture;
+use tonledb_wire_pg::cache::{StatementCache, MAX_CACHED_QUERY_LEN};
+use tonledb_wire_pg::PgOptions;
+
+mod common;
+use common::{connect, orders_db, query, read_until_ready, send};
+
+/// Bind text `params` to the unnamed portal of `statement`, execute it and sync
+async fn bind_and_execute(client: &mut DuplexStream, statement: &str, params: &[&str]) -> Vec<(u8, Vec<u8>)> {
+    let mut body = [b"\0".as_slice(), statement.as_bytes(), b"\0\0\0"].concat();
+    body.extend_from_slice(&(params.len() as i16).to_be_bytes());
+    for p in params {
+        body.extend_from_slice(&(p.len() as i32).to_be_bytes());
+        body.extend_from_slice(p.as_bytes());
+    }
+    body.extend_from_slice(&0i16.to_be_bytes());
+    send(client, b'B', &body).await;
+    send(client, b'E', &[b"\0".as_slice(), &0i32.to_be_bytes()].concat()).await;
+    send(client, b'S', &[]).await;
+    read_until_ready(client).await
+}
+
+/// The first cell of each DataRow
+fn first_cells(messages: &[(u8, Vec<u8>)]) -> Vec<Option<String>> {
+    messages
+        .iter()
+        .filter(|(tag, _)| *tag == b'D')
+        .map(|(_, body)| {
+            let len = i32::from_be_bytes(body[2..6].try_into().unwrap());
+            (len >= 0).then(|| String::from_utf8(body[6..6 + len as usize].to_vec()).unwrap())
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_connections_share_parsed_statements() {
+    let db = orders_db();
+    let options = Arc::new(PgOptions::default());
+    let sql = "SELECT customer FROM orders WHERE id = 1";
+    let mut first = connect(db.clone(), options.clone()).await;
+    assert_eq!(first_cells(&query(&mut first, sql).await), [Some("ann".to_string())]);
+    let parsed = options.statement_cache.stats();
+    assert_eq!((parsed.entries, parsed.hits, parsed.misses), (1, 0, 1));
+
+    // Sent again on the same connection, it doesn't reach the shared cache
+    assert_eq!(first_cells(&query(&mut first, sql).await), [Some("ann".to_string())]);
+    assert_eq!(options.statement_cache.stats().misses, 1);
+    assert_eq!(options.statement_cache.stats().hits, 0);
+
+    // Another connection finds it there, for a query or a prepared statement
+    let mut second = connect(db.clone(), options.clone()).await;
+    assert_eq!(first_cells(&query(&mut second, sql).await), [Some("ann".to_string())]);
+    let mut third = connect(db.clone(), options.clone()).await;
+    send(&mut third, b'P', &[b"\0".as_slice(), sql.as_bytes(), b"\0\0\0"].concat()).await;
+    assert_eq!(first_cells(&bind_and_execute(&mut third, "", &[]).await), [Some("ann".to_string())]);
+    let parsed = options.statement_cache.stats();
+    assert_eq!((parsed.entries, parsed.hits, parsed.misses), (1, 2, 1));
+}
+
+#[tokio::test]
+async fn test_prepared_statement_binds_each_execution() {
+    let db = orders_db();
+    fixture::insert_rows(&db, "orders", &[json!({"id": 3, "customer": "o'neil"})]).unwrap();
+    let options = Arc::new(PgOptions::default());
+    let mut client = connect(db.clone(), options.clone()).await;
+    send(&mut client, b'P', b"by_id\0SELECT customer FROM orders WHERE id = $1\0\0\0").await;
+    send(&mut client, b'P', b"by_customer\0SELECT id FROM orders WHERE customer = $1\0\0\0").await;
+    send(&mut client, b'S', &[]).await;
+    read_until_ready(&mut client).await;
+
+    assert_eq!(first_cells(&bind_and_execute(&mut client, "by_id", &["1"]).await), [Some("ann".to_string())]);
+    assert_eq!(first_cells(&bind_and_execute(&mut client, "by_id", &["3"]).await), [Some("o'neil".to_string())]);
+    assert_eq!(first_cells(&bind_and_execute(&mut client, "by_id", &["4"]).await), Vec::<Option<String>>::new());
+    // A string parameter is a literal, quotes and all
+    assert_eq!(first_cells(&bind_and_execute(&mut client, "by_customer", &["o'neil"]).await), [Some("3".to_string())]);
+    assert_eq!(first_cells(&bind_and_execute(&mut client, "by_customer", &["ann' OR '1' = '1"]).await), Vec::<Option<String>>::new());
+    // Parsed once each, however often they ran
+    assert_eq!(options.statement_cache.stats().misses, 2);
+}
+
+#[tokio::test]
+async fn test_cache_turned_off() {
+    let db = orders_db();
+    let options = Arc::new(PgOptions { statement_cache: Arc::new(StatementCache::new(0)), ..Default::default() });
+    let mut client = connect(db.clone(), options.clone()).await;
+    for _ in 0..2 {
+        assert_eq!(first_cells(&query(&mut client, "SELECT id FROM orders WHERE customer = 'ann'").await), [Some("1".to_string())]);
+    }
+    let parsed = options.statement_cache.stats();
+    assert_eq!((parsed.capacity, parsed.entries, parsed.hits, parsed.misses), (0, 0, 0, 0));
+}
+
+#[tokio::test]
+async fn test_long_query_strings_not_cached() {
+    let db = orders_db();
+    let options = Arc::new(PgOptions::default());
+    let mut client = connect(db.clone(), options.clone()).await;
+    let sql = format!("SELECT customer FROM orders WHERE id = 1 /* {} */", "x".repeat(MAX_CACHED_QUERY_LEN));
+    for _ in 0..2 {
+        assert_eq!(first_cells(&query(&mut client, &sql).await), [Some("ann".to_string())]);
+    }
+    let parsed = options.statement_cache.stats();
+    assert_eq!((parsed.entries, parsed.hits, parsed.misses), (0, 0, 0));
+}
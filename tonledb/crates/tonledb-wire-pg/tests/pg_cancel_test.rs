@@ -0,0 +1,83 @@
+//! Tests for cancelling a running statement with a CancelRequest
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use serde_json::json;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tonledb_core::{DataType, Db, Result, Space, Storage};
+use tonledb_sql::fixture;
+use tonledb_storage::InMemoryStore;
+use tonledb_wire_pg::PgOptions;
+
+mod common;
+use common::{error_code, query, read_until_ready, send};
+
+/// Storage whose table scans take 5 ms a row
+struct SlowStore(InMemoryStore);
+
+impl Storage for SlowStore {
+    fn get(&self, space: &Space, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.0.get(space, key)
+    }
+
+    fn put(&self, space: &Space, key: Vec<u8>, val: Vec<u8>) -> Result<()> {
+        self.0.put(space, key, val)
+    }
+
+    fn del(&self, space: &Space, key: &[u8]) -> Result<()> {
+        self.0.del(space, key)
+    }
+
+    fn scan_prefix(&self, space: &Space, prefix: &[u8]) -> Result<Box<dyn Iterator<Item = (Vec<u8>, Vec<u8>)> + Send>> {
+        let rows = self.0.scan_prefix(space, prefix)?;
+        match prefix.starts_with(b"tbl/") {
+            true => Ok(Box::new(rows.inspect(|_| std::thread::sleep(Duration::from_millis(5))))),
+            false => Ok(rows),
+        }
+    }
+}
+
+/// 2000 rows, which take 10 s to scan
+fn slow_db() -> Arc<Db> {
+    let db = Db::new(Arc::new(SlowStore(InMemoryStore::new(100_000))));
+    fixture::create_table(&db, "events", &[("id", DataType::Integer)], Some("id"));
+    fixture::insert_rows(&db, "events", &(0..2000).map(|id| json!({"id": id})).collect::<Vec<_>>()).unwrap();
+    Arc::new(db)
+}
+
+// One worker, which the running statement hands the CancelRequest on from
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn test_cancel_request_stops_the_running_statement() {
+    let db = slow_db();
+    let options = Arc::new(PgOptions::default());
+    let mut client = common::serve(db.clone(), options.clone());
+    common::send_startup(&mut client, &[("user", "ann")]).await;
+    let messages = read_until_ready(&mut client).await;
+    let (_, key) = messages.iter().find(|(tag, _)| *tag == b'K').expect("BackendKeyData");
+    let (process_id, secret_key) = (u32::from_be_bytes(key[..4].try_into().unwrap()), i32::from_be_bytes(key[4..].try_into().unwrap()));
+    assert_eq!(options.connections.connections()[0].process_id, process_id);
+
+    let started = Instant::now();
+    send(&mut client, b'Q', b"SELECT * FROM events\0").await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // A wrong key cancels nothing
+    assert!(!options.connections.cancel(process_id, secret_key.wrapping_add(1)));
+    let mut canceller = common::serve(db.clone(), options.clone());
+    let mut cancel = 16i32.to_be_bytes().to_vec();
+    cancel.extend_from_slice(&80877102i32.to_be_bytes());
+    cancel.extend_from_slice(&process_id.to_be_bytes());
+    cancel.extend_from_slice(&secret_key.to_be_bytes());
+    canceller.write_all(&cancel).await.unwrap();
+    // The server closes the cancel connection without a reply
+    assert!(canceller.read_u8().await.is_err());
+
+    let cancelled = read_until_ready(&mut client).await;
+    assert_eq!(error_code(&cancelled).as_deref(), Some("57014"));
+    assert!(started.elapsed() < Duration::from_secs(5), "took {:?}", started.elapsed());
+
+    // The next statement runs as usual
+    let next = query(&mut client, "SELECT 1").await;
+    assert_eq!(error_code(&next), None);
+    assert!(next.iter().any(|(tag, _)| *tag == b'D'));
+}
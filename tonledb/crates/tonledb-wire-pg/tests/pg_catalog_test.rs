@@ -0,0 +1,120 @@
+//! Tests for the emulated pg_catalog and information_schema queries
+
+use std::sync::Arc;
+use sqlparser::{dialect::PostgreSqlDialect, parser::Parser};
+use tonledb_core::{DataType, Db};
+use tonledb_sql::fixture;
+use tonledb_storage::InMemoryStore;
+use tonledb_wire_pg::catalog;
+use tonledb_wire_pg::results::ResultSet;
+use tonledb_wire_pg::session::SessionState;
+
+fn db() -> Db {
+    let db = Db::new(Arc::new(InMemoryStore::new(1000)));
+    fixture::create_table(&db, "orders", &[("id", DataType::Integer), ("customer", DataType::Text), ("paid", DataType::Boolean)], Some("id"));
+    fixture::create_table(&db, "items", &[("sku", DataType::Text), ("price", DataType::Float)], None);
+    db
+}
+
+fn answer(db: &Db, sql: &str) -> ResultSet {
+    let stmt = Parser::parse_sql(&PostgreSqlDialect {}, sql).unwrap().remove(0);
+    assert!(catalog::is_catalog_query(&stmt), "{}", sql);
+    catalog::answer(db, &SessionState::new("ann", 1, &[]), &stmt).unwrap()
+}
+
+fn error(db: &Db, sql: &str) -> String {
+    let stmt = Parser::parse_sql(&PostgreSqlDialect {}, sql).unwrap().remove(0);
+    catalog::answer(db, &SessionState::new("ann", 1, &[]), &stmt).unwrap_err().code.to_string()
+}
+
+fn routed(sql: &str) -> bool {
+    catalog::is_catalog_query(&Parser::parse_sql(&PostgreSqlDialect {}, sql).unwrap().remove(0))
+}
+
+fn rows(result: &ResultSet) -> Vec<Vec<&str>> {
+    result.rows.iter().map(|row| row.iter().map(|c| c.as_deref().unwrap_or("NULL")).collect()).collect()
+}
+
+#[test]
+fn test_connect_queries() {
+    let db = db();
+    let result = answer(&db, "SELECT version(), current_user, pg_catalog.current_database(), current_schema");
+    let names: Vec<&str> = result.fields.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(names, ["version", "current_user", "current_database", "current_schema"]);
+    assert_eq!(rows(&result), [["PostgreSQL 14.0 (TonleDB)", "ann", "tonledb", "public"]]);
+    assert_eq!(rows(&answer(&db, "SELECT 1")), [["1"]]);
+    assert_eq!(rows(&answer(&db, "SELECT 2*3, 7 / 2, 7 % 3, -4 + 1")), [["6", "3", "1", "-3"]]);
+    assert_eq!(error(&db, "SELECT 1 / 0"), "22012");
+
+    assert_eq!(rows(&answer(&db, "SHOW standard_conforming_strings")), [["on"]]);
+    let result = answer(&db, "SHOW TRANSACTION ISOLATION LEVEL");
+    assert_eq!((result.fields[0].name.as_str(), result.tag.as_str()), ("transaction_isolation", "SHOW"));
+    assert_eq!(rows(&result), [["read committed"]]);
+    let stmt = Parser::parse_sql(&PostgreSqlDialect {}, "SHOW no_such_setting").unwrap().remove(0);
+    assert_eq!(catalog::answer(&db, &SessionState::new("ann", 1, &[]), &stmt).unwrap_err().code, "42704");
+
+    // Queries on tables are left to the engine, unless they ask the catalog too
+    assert!(!routed("SELECT * FROM orders"));
+    assert!(!routed("SELECT id FROM orders WHERE id = 2*3"));
+    assert!(!routed("SELECT (SELECT count(*) FROM orders)"));
+    assert!(routed("SELECT relname FROM pg_catalog.pg_class WHERE relname IN (SELECT 'orders')"));
+    assert!(routed("SELECT id FROM orders WHERE current_setting('app.tenant') = 'x'"));
+}
+
+#[test]
+fn test_psql_list_tables() {
+    // What psql sends for \dt
+    let result = answer(
+        &db(),
+        "SELECT n.nspname as \"Schema\", c.relname as \"Name\", \
+         CASE c.relkind WHEN 'r' THEN 'table' WHEN 'v' THEN 'view' WHEN 'i' THEN 'index' END as \"Type\", \
+         pg_catalog.pg_get_userbyid(c.relowner) as \"Owner\" \
+         FROM pg_catalog.pg_class c LEFT JOIN pg_catalog.pg_namespace n ON n.oid = c.relnamespace \
+         WHERE c.relkind IN ('r','p','') AND n.nspname <> 'pg_catalog' AND n.nspname !~ '^pg_toast' \
+         AND n.nspname <> 'information_schema' AND pg_catalog.pg_table_is_visible(c.oid) ORDER BY 1,2;",
+    );
+    let names: Vec<&str> = result.fields.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(names, ["Schema", "Name", "Type", "Owner"]);
+    assert_eq!(rows(&result), [["public", "items", "table", "tonledb"], ["public", "orders", "table", "tonledb"]]);
+    assert_eq!(result.tag, "SELECT 2");
+}
+
+#[test]
+fn test_describe_table_columns() {
+    let db = db();
+    // psql's \d orders finds the table's OID, then its columns
+    let result = answer(
+        &db,
+        "SELECT c.oid, n.nspname, c.relname FROM pg_catalog.pg_class c LEFT JOIN pg_catalog.pg_namespace n ON n.oid = c.relnamespace \
+         WHERE c.relname OPERATOR(pg_catalog.~) '^(orders)$' COLLATE pg_catalog.default AND pg_catalog.pg_table_is_visible(c.oid) ORDER BY 2, 3",
+    );
+    let oid = result.rows[0][0].clone().unwrap();
+    let result = answer(
+        &db,
+        &format!(
+            "SELECT a.attname, pg_catalog.format_type(a.atttypid, a.atttypmod), a.attnotnull, \
+             (SELECT count(*) FROM pg_catalog.pg_index i WHERE i.indrelid = a.attrelid AND i.indisprimary) AS pk \
+             FROM pg_catalog.pg_attribute a WHERE a.attrelid = '{}' AND a.attnum > 0 AND NOT a.attisdropped ORDER BY a.attnum",
+            oid
+        ),
+    );
+    assert_eq!(rows(&result), [["id", "bigint", "t", "1"], ["customer", "text", "f", "1"], ["paid", "boolean", "f", "1"]]);
+    assert_eq!(answer(&db, "SELECT 'orders'::regclass::oid").rows[0][0], Some(oid.clone()));
+    let pkey = answer(&db, "SELECT 'orders_pkey'::regclass::oid").rows[0][0].clone();
+
+    let result = answer(
+        &db,
+        "SELECT column_name, data_type, is_nullable FROM information_schema.columns \
+         WHERE table_schema = 'public' AND table_name = 'items' ORDER BY ordinal_position DESC LIMIT 1",
+    );
+    assert_eq!(rows(&result), [["price", "double precision", "YES"]]);
+    let result = answer(&db, "SELECT count(*) FROM information_schema.tables WHERE table_name LIKE 'ord%'");
+    assert_eq!(rows(&result), [["1"]]);
+    // A column beside count() needs a GROUP BY, which isn't supported
+    assert_eq!(error(&db, "SELECT table_name, count(*) FROM information_schema.tables"), "42803");
+
+    // OIDs don't move as other tables come and go
+    fixture::create_table(&db, "aardvarks", &[("a", DataType::Integer)], Some("a"));
+    assert_eq!(answer(&db, "SELECT 'orders'::regclass::oid").rows[0][0], Some(oid.clone()));
+    assert_eq!(answer(&db, "SELECT 'orders_pkey'::regclass::oid").rows[0][0], pkey);
+}
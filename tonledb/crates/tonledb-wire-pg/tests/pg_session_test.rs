@@ -0,0 +1,182 @@
+//! Tests for per-connection sessions: settings, transaction blocks, the connection
+//! limit and the idle timeout
+
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, DuplexStream};
+use tonledb_wire_pg::connections::ConnectionRegistry;
+use tonledb_wire_pg::PgOptions;
+
+mod common;
+use common::{error_code, error_field, orders_db, query, read_message, read_until_ready};
+
+/// A client of a server task on `orders`, having sent a startup packet with `parameters`
+async fn start(options: &Arc<PgOptions>, parameters: &[(&str, &str)]) -> DuplexStream {
+    let mut client = common::serve(orders_db(), options.clone());
+    let parameters: Vec<_> = [("user", "ann")].iter().chain(parameters).copied().collect();
+    common::send_startup(&mut client, &parameters).await;
+    client
+}
+
+/// The first value of a SHOW
+async fn show(client: &mut DuplexStream, name: &str) -> String {
+    let messages = query(client, &format!("SHOW {}", name)).await;
+    let (_, row) = messages.iter().find(|(tag, _)| *tag == b'D').unwrap_or_else(|| panic!("no row for {}: {:?}", name, messages));
+    let len = i32::from_be_bytes(row[2..6].try_into().unwrap()) as usize;
+    String::from_utf8(row[6..6 + len].to_vec()).unwrap()
+}
+
+/// Name and value of each ParameterStatus
+fn parameter_statuses(messages: &[(u8, Vec<u8>)]) -> Vec<(String, String)> {
+    messages
+        .iter()
+        .filter(|(tag, _)| *tag == b'S')
+        .map(|(_, body)| {
+            let mut parts = body.split(|b| *b == 0).map(|p| String::from_utf8(p.to_vec()).unwrap());
+            (parts.next().unwrap(), parts.next().unwrap())
+        })
+        .collect()
+}
+
+/// The transaction status of the ReadyForQuery ending `messages`
+fn status(messages: &[(u8, Vec<u8>)]) -> u8 {
+    let (tag, body) = messages.last().unwrap();
+    assert_eq!(*tag, b'Z');
+    body[0]
+}
+
+#[tokio::test]
+async fn test_set_show_and_reset() {
+    let settings = vec![("search_path".to_string(), "app, public".to_string())];
+    let options = Arc::new(PgOptions { settings, ..Default::default() });
+    let mut client = start(&options, &[("application_name", "psql"), ("options", "-c extra_float_digits=3")]).await;
+    let startup = read_until_ready(&mut client).await;
+    let reported = parameter_statuses(&startup);
+    assert!(reported.contains(&("application_name".into(), "psql".into())), "{:?}", reported);
+    assert_eq!(show(&mut client, "search_path").await, "app, public");
+    assert_eq!(show(&mut client, "extra_float_digits").await, "3");
+
+    let set = query(&mut client, "SET search_path TO other").await;
+    assert!(set.iter().any(|(tag, body)| *tag == b'C' && body == b"SET\0"));
+    assert!(parameter_statuses(&set).is_empty());
+    assert_eq!(show(&mut client, "search_path").await, "other");
+
+    // Reported settings are reported again when they change
+    let set = query(&mut client, "SET application_name = 'etl'").await;
+    assert_eq!(parameter_statuses(&set), [("application_name".to_string(), "etl".to_string())]);
+
+    query(&mut client, "RESET search_path").await;
+    assert_eq!(show(&mut client, "search_path").await, "app, public");
+    let reset = query(&mut client, "RESET ALL").await;
+    assert_eq!(parameter_statuses(&reset), [("application_name".to_string(), "psql".to_string())]);
+
+    query(&mut client, "SET app.tenant = 'acme'").await;
+    assert_eq!(show(&mut client, "app.tenant").await, "acme");
+    assert_eq!(error_code(&query(&mut client, "SET server_version = '9'").await).as_deref(), Some("55P02"));
+    assert_eq!(error_code(&query(&mut client, "SET no_such_setting = 1").await).as_deref(), Some("42704"));
+    assert_eq!(error_code(&query(&mut client, "SET client_encoding = 'LATIN1'").await).as_deref(), Some("22023"));
+}
+
+#[tokio::test]
+async fn test_transaction_blocks() {
+    let options = Arc::new(PgOptions::default());
+    let mut client = start(&options, &[]).await;
+    read_until_ready(&mut client).await;
+    let search_path = show(&mut client, "search_path").await;
+
+    let begin = query(&mut client, "BEGIN").await;
+    assert!(begin.iter().any(|(tag, body)| *tag == b'C' && body == b"BEGIN\0"));
+    assert_eq!(status(&begin), b'T');
+
+    // A failed statement fails the block, which then only ends
+    query(&mut client, "SET search_path TO elsewhere").await;
+    let failed = query(&mut client, "SELECT * FROM a, b").await;
+    assert_eq!(status(&failed), b'E');
+    let refused = query(&mut client, "SELECT 1").await;
+    assert_eq!(error_code(&refused).as_deref(), Some("25P02"));
+    assert_eq!(status(&refused), b'E');
+    let commit = query(&mut client, "COMMIT").await;
+    assert!(commit.iter().any(|(tag, body)| *tag == b'C' && body == b"ROLLBACK\0"));
+    assert_eq!(status(&commit), b'I');
+    assert_eq!(show(&mut client, "search_path").await, search_path);
+
+    // SET LOCAL lasts until the block ends; SET outlasts a commit
+    query(&mut client, "BEGIN ISOLATION LEVEL SERIALIZABLE").await;
+    assert_eq!(show(&mut client, "transaction_isolation").await, "serializable");
+    query(&mut client, "SET LOCAL search_path TO scratch").await;
+    query(&mut client, "SET TimeZone TO 'Asia/Phnom_Penh'").await;
+    assert_eq!(show(&mut client, "search_path").await, "scratch");
+    assert_eq!(status(&query(&mut client, "COMMIT").await), b'I');
+    assert_eq!(show(&mut client, "search_path").await, search_path);
+    assert_eq!(show(&mut client, "transaction_isolation").await, "read committed");
+    assert_eq!(show(&mut client, "TIME ZONE").await, "Asia/Phnom_Penh");
+
+    // Outside a block, COMMIT only warns
+    let commit = query(&mut client, "COMMIT").await;
+    assert!(commit.iter().any(|(tag, body)| *tag == b'N' && error_field(body, b'C') == "25P01"));
+    assert_eq!(error_code(&query(&mut client, "BEGIN; DISCARD ALL").await).as_deref(), Some("25001"));
+    assert_eq!(status(&query(&mut client, "ROLLBACK").await), b'I');
+}
+
+#[tokio::test]
+async fn test_max_connections() {
+    let options = Arc::new(PgOptions { connections: Arc::new(ConnectionRegistry::new(Some(1))), ..Default::default() });
+    let mut first = start(&options, &[]).await;
+    read_until_ready(&mut first).await;
+    let pid = options.connections.connections()[0].process_id;
+    let backend_pid = query(&mut first, "SELECT pg_backend_pid()").await;
+    let (_, row) = backend_pid.iter().find(|(tag, _)| *tag == b'D').unwrap();
+    assert_eq!(&row[6..], pid.to_string().as_bytes());
+
+    let mut second = start(&options, &[]).await;
+    let (tag, body) = read_message(&mut second).await;
+    assert_eq!(tag, b'E');
+    assert_eq!((error_field(&body, b'S'), error_field(&body, b'C')), ("FATAL".to_string(), "53300".to_string()));
+
+    // The place is freed once the first client goes
+    drop(first);
+    while !options.connections.is_empty() {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    let mut third = start(&options, &[]).await;
+    assert_eq!(status(&read_until_ready(&mut third).await), b'I');
+}
+
+#[tokio::test]
+async fn test_idle_timeout() {
+    let options = Arc::new(PgOptions { idle_timeout: Some(Duration::from_millis(50)), ..Default::default() });
+    let mut client = start(&options, &[]).await;
+    read_until_ready(&mut client).await;
+    query(&mut client, "BEGIN").await;
+    let (tag, body) = read_message(&mut client).await;
+    assert_eq!((tag, error_field(&body, b'C')), (b'E', "25P03".to_string()));
+    assert!(client.read_u8().await.is_err());
+}
+
+#[tokio::test]
+async fn test_writes_only_outside_blocks_and_read_only_transactions() {
+    let options = Arc::new(PgOptions::default());
+    let mut client = start(&options, &[]).await;
+    read_until_ready(&mut client).await;
+    // Their rows couldn't be rolled back with the block
+    let copy = query(&mut client, "BEGIN; COPY orders FROM STDIN").await;
+    assert_eq!((error_code(&copy).as_deref(), status(&copy)), (Some("0A000"), b'E'));
+    query(&mut client, "ROLLBACK").await;
+    let insert = query(&mut client, "BEGIN; INSERT INTO orders (id) VALUES (1)").await;
+    assert_eq!((error_code(&insert).as_deref(), status(&insert)), (Some("0A000"), b'E'));
+    query(&mut client, "ROLLBACK").await;
+    // Reads are fine in a block, and writes outside one
+    let select = query(&mut client, "BEGIN; SELECT id FROM orders; COMMIT").await;
+    assert_eq!((error_code(&select), status(&select)), (None, b'I'));
+    assert_eq!(error_code(&query(&mut client, "BEGIN; ANALYZE TABLE orders").await).as_deref(), Some("0A000"));
+    query(&mut client, "ROLLBACK").await;
+    assert_eq!(error_code(&query(&mut client, "ANALYZE TABLE orders").await), None);
+
+    let copy = query(&mut client, "BEGIN READ ONLY; COPY orders FROM STDIN").await;
+    assert_eq!(error_code(&copy).as_deref(), Some("25006"));
+    query(&mut client, "ROLLBACK").await;
+
+    query(&mut client, "SET default_transaction_read_only = on").await;
+    assert_eq!(error_code(&query(&mut client, "COPY orders FROM STDIN").await).as_deref(), Some("25006"));
+    assert_eq!(error_code(&query(&mut client, "DELETE FROM orders").await).as_deref(), Some("25006"));
+}
@@ -0,0 +1,75 @@
+//! Tests for SSLRequest negotiation and TLS connections
+
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream};
+use tokio_rustls::rustls::{self, Certificate, PrivateKey, RootCertStore, ServerName};
+use tokio_rustls::TlsConnector;
+use tonledb_core::Db;
+use tonledb_storage::InMemoryStore;
+use tonledb_wire_pg::{handle_pg_connection, PgOptions};
+
+const SSL_REQUEST: [u8; 8] = [0, 0, 0, 8, 0x04, 0xd2, 0x16, 0x2f];
+
+fn serve(options: PgOptions) -> DuplexStream {
+    let (client, server) = tokio::io::duplex(1 << 16);
+    tokio::spawn(handle_pg_connection(server, Arc::new(Db::new(Arc::new(InMemoryStore::new(1000)))), Arc::new(options)));
+    client
+}
+
+async fn send_startup<S: AsyncWrite + Unpin>(client: &mut S) {
+    let mut startup = 196608i32.to_be_bytes().to_vec();
+    startup.extend_from_slice(b"user\0ann\0\0");
+    client.write_all(&((startup.len() + 4) as i32).to_be_bytes()).await.unwrap();
+    client.write_all(&startup).await.unwrap();
+    client.flush().await.unwrap();
+}
+
+/// Type bytes of the messages up to ReadyForQuery
+async fn read_until_ready<S: AsyncRead + Unpin>(client: &mut S) -> Vec<u8> {
+    let mut tags = Vec::new();
+    loop {
+        let tag = client.read_u8().await.unwrap();
+        let len = client.read_i32().await.unwrap() as usize;
+        let mut body = vec![0; len - 4];
+        client.read_exact(&mut body).await.unwrap();
+        tags.push(tag);
+        if tag == b'Z' {
+            return tags;
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_ssl_request_upgrades_to_tls() {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+    let der = cert.serialize_der().unwrap();
+    let server_config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(vec![Certificate(der.clone())], PrivateKey(cert.serialize_private_key_der()))
+        .unwrap();
+    let mut client = serve(PgOptions { tls: Some(Arc::new(server_config)), ..Default::default() });
+
+    client.write_all(&SSL_REQUEST).await.unwrap();
+    assert_eq!(client.read_u8().await.unwrap(), b'S');
+    let mut roots = RootCertStore::empty();
+    roots.add(&Certificate(der)).unwrap();
+    let client_config = rustls::ClientConfig::builder().with_safe_defaults().with_root_certificates(roots).with_no_client_auth();
+    let mut tls = TlsConnector::from(Arc::new(client_config)).connect(ServerName::try_from("localhost").unwrap(), client).await.unwrap();
+    send_startup(&mut tls).await;
+    assert_eq!(read_until_ready(&mut tls).await.first(), Some(&b'R'));
+}
+
+#[tokio::test]
+async fn test_plain_connection_refused_when_tls_required() {
+    let mut client = serve(PgOptions { require_tls: true, ..Default::default() });
+    // Without a TLS config the request is declined, and the plain startup refused
+    client.write_all(&SSL_REQUEST).await.unwrap();
+    assert_eq!(client.read_u8().await.unwrap(), b'N');
+    send_startup(&mut client).await;
+    assert_eq!(client.read_u8().await.unwrap(), b'E');
+    let len = client.read_i32().await.unwrap() as usize;
+    let mut body = vec![0; len - 4];
+    client.read_exact(&mut body).await.unwrap();
+    assert!(body.windows(6).any(|w| w == b"C28000"));
+}
@@ -0,0 +1,293 @@
+//! Tests for the Postgres wire protocol: startup, simple-query result sets, the
+//! extended query protocol and COPY
+
+use std::sync::Arc;
+use serde_json::json;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tonledb_core::{Db, Space, Storage};
+use tonledb_sql::bulk::BULK_BATCH_ROWS;
+
+mod common;
+use common::{orders_db, query, read_message, read_until_ready, send};
+
+/// A client connected to a server task on `db`, past startup
+async fn connect(db: Arc<Db>) -> DuplexStream {
+    let mut client = common::serve(db, Default::default());
+    // SSL is declined
+    client.write_all(&[0, 0, 0, 8, 0x04, 0xd2, 0x16, 0x2f]).await.unwrap();
+    assert_eq!(client.read_u8().await.unwrap(), b'N');
+    common::send_startup(&mut client, &[("user", "ann")]).await;
+    let messages = read_until_ready(&mut client).await;
+    assert_eq!(messages[0], (b'R', 0i32.to_be_bytes().to_vec()));
+    assert!(messages.iter().any(|(tag, body)| *tag == b'S' && body.starts_with(b"server_version\0")));
+    client
+}
+
+/// Column names and type OIDs of a RowDescription body
+fn columns(body: &[u8]) -> Vec<(String, u32)> {
+    let count = i16::from_be_bytes([body[0], body[1]]) as usize;
+    let mut at = 2;
+    (0..count)
+        .map(|_| {
+            let end = at + body[at..].iter().position(|b| *b == 0).unwrap();
+            let name = String::from_utf8(body[at..end].to_vec()).unwrap();
+            let oid = u32::from_be_bytes(body[end + 7..end + 11].try_into().unwrap());
+            at = end + 19;
+            (name, oid)
+        })
+        .collect()
+}
+
+/// Cells of a DataRow body
+fn cells(body: &[u8]) -> Vec<Option<String>> {
+    let count = i16::from_be_bytes([body[0], body[1]]) as usize;
+    let mut at = 2;
+    (0..count)
+        .map(|_| {
+            let len = i32::from_be_bytes(body[at..at + 4].try_into().unwrap());
+            at += 4;
+            if len < 0 {
+                return None;
+            }
+            let cell = String::from_utf8(body[at..at + len as usize].to_vec()).unwrap();
+            at += len as usize;
+            Some(cell)
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_select_sends_row_description_and_rows() {
+    let mut client = connect(orders_db()).await;
+    let messages = query(&mut client, "SELECT * FROM orders").await;
+    let tags: Vec<u8> = messages.iter().map(|(tag, _)| *tag).collect();
+    assert_eq!(tags, b"TDDCZ".to_vec());
+    assert_eq!(columns(&messages[0].1), vec![("id".into(), 20), ("customer".into(), 25), ("paid".into(), 16)]);
+    assert_eq!(cells(&messages[1].1), vec![Some("1".into()), Some("ann".into()), Some("t".into())]);
+    assert_eq!(cells(&messages[2].1), vec![Some("2".into()), None, Some("f".into())]);
+    assert_eq!(messages[3].1, b"SELECT 2\0".to_vec());
+    assert_eq!(messages[4].1, b"I".to_vec());
+
+    // An empty result still describes its columns
+    let messages = query(&mut client, "SELECT customer FROM orders WHERE id = 9").await;
+    assert_eq!(columns(&messages[0].1), vec![("customer".into(), 25)]);
+    assert_eq!(messages[1], (b'C', b"SELECT 0\0".to_vec()));
+}
+
+#[tokio::test]
+async fn test_errors_and_empty_queries() {
+    let mut client = connect(orders_db()).await;
+    let messages = query(&mut client, "DELETE FROM orders").await;
+    assert_eq!(messages[0].0, b'E');
+    assert!(messages[0].1.windows(6).any(|w| w == b"C42601"));
+    assert_eq!(messages[1].0, b'Z');
+
+    let messages = query(&mut client, "  ").await;
+    assert_eq!(messages.iter().map(|(tag, _)| *tag).collect::<Vec<_>>(), b"IZ".to_vec());
+
+    // Several statements each get their results
+    let messages = query(&mut client, "SELECT id FROM orders WHERE id = 1; SELECT id FROM orders WHERE id = 2").await;
+    assert_eq!(messages.iter().map(|(tag, _)| *tag).collect::<Vec<_>>(), b"TDCTDCZ".to_vec());
+}
+
+async fn parse(client: &mut DuplexStream, name: &str, sql: &str) {
+    send(client, b'P', &[name.as_bytes(), b"\0", sql.as_bytes(), b"\0\0\0"].concat()).await;
+}
+
+/// Bind text `params` to `statement`, with every result column in `result_format`
+async fn bind(client: &mut DuplexStream, portal: &str, statement: &str, params: &[&str], result_format: i16) {
+    let mut body = [portal.as_bytes(), b"\0", statement.as_bytes(), b"\0\0\0"].concat();
+    body.extend_from_slice(&(params.len() as i16).to_be_bytes());
+    for p in params {
+        body.extend_from_slice(&(p.len() as i32).to_be_bytes());
+        body.extend_from_slice(p.as_bytes());
+    }
+    body.extend_from_slice(&1i16.to_be_bytes());
+    body.extend_from_slice(&result_format.to_be_bytes());
+    send(client, b'B', &body).await;
+}
+
+async fn execute(client: &mut DuplexStream, portal: &str, max_rows: i32) {
+    send(client, b'E', &[portal.as_bytes(), b"\0", &max_rows.to_be_bytes()].concat()).await;
+}
+
+fn tags(messages: &[(u8, Vec<u8>)]) -> Vec<u8> {
+    messages.iter().map(|(tag, _)| *tag).collect()
+}
+
+#[tokio::test]
+async fn test_extended_query_binds_parameters() {
+    let mut client = connect(orders_db()).await;
+    parse(&mut client, "", "SELECT customer FROM orders WHERE id = $1").await;
+    send(&mut client, b'D', b"S\0").await;
+    bind(&mut client, "", "", &["1"], 0).await;
+    send(&mut client, b'D', b"P\0").await;
+    execute(&mut client, "", 0).await;
+    send(&mut client, b'S', &[]).await;
+    let messages = read_until_ready(&mut client).await;
+    assert_eq!(tags(&messages), b"1tT2TDCZ".to_vec());
+    // The one parameter, unspecified, is described as text
+    assert_eq!(messages[1].1, [1i16.to_be_bytes().as_slice(), &25u32.to_be_bytes()].concat());
+    assert_eq!(columns(&messages[2].1), vec![("customer".into(), 25)]);
+    assert_eq!(cells(&messages[5].1), vec![Some("ann".into())]);
+    assert_eq!(messages[6].1, b"SELECT 1\0".to_vec());
+}
+
+#[tokio::test]
+async fn test_describe_statement_does_not_run_it() {
+    let db = orders_db();
+    // Running anything over `orders` now fails on the damaged row
+    db.storage.put(&Space("data".into()), b"tbl/orders/9".to_vec(), b"not json".to_vec()).unwrap();
+    tonledb_nosql_doc::create_collection(&*db.storage, "visits").unwrap();
+    tonledb_nosql_doc::insert(&*db.storage, "visits", json!({"page": "home", "ms": 1.5})).unwrap();
+    let mut client = connect(db).await;
+    parse(&mut client, "", "SELECT id, customer AS who FROM orders WHERE paid = $1").await;
+    send(&mut client, b'D', b"S\0").await;
+    parse(&mut client, "visits", "SELECT * FROM visits WHERE page = $1").await;
+    send(&mut client, b'D', b"Svisits\0").await;
+    parse(&mut client, "plan", "EXPLAIN SELECT * FROM visits").await;
+    send(&mut client, b'D', b"Splan\0").await;
+    send(&mut client, b'S', &[]).await;
+    let messages = read_until_ready(&mut client).await;
+    assert_eq!(tags(&messages), b"1tT1tT1tTZ".to_vec());
+    assert_eq!(columns(&messages[2].1), vec![("id".into(), 20), ("who".into(), 25)]);
+    let visits = columns(&messages[5].1);
+    assert!(visits.contains(&("page".into(), 25)) && visits.contains(&("ms".into(), 701)), "{:?}", visits);
+    assert_eq!(columns(&messages[8].1), vec![("plan".into(), 114), ("estimate".into(), 114), ("cache".into(), 114)]);
+}
+
+#[tokio::test]
+async fn test_execute_with_row_limit_suspends_portal() {
+    let mut client = connect(orders_db()).await;
+    parse(&mut client, "ids", "SELECT id FROM orders").await;
+    bind(&mut client, "p", "ids", &[], 1).await;
+    execute(&mut client, "p", 1).await;
+    execute(&mut client, "p", 1).await;
+    send(&mut client, b'S', &[]).await;
+    let messages = read_until_ready(&mut client).await;
+    assert_eq!(tags(&messages), b"12DsDCZ".to_vec());
+    // Binary int8
+    assert_eq!(messages[2].1, [1i16.to_be_bytes().as_slice(), &8i32.to_be_bytes(), &1i64.to_be_bytes()].concat());
+    assert_eq!(messages[4].1, [1i16.to_be_bytes().as_slice(), &8i32.to_be_bytes(), &2i64.to_be_bytes()].concat());
+}
+
+#[tokio::test]
+async fn test_extended_error_skips_to_sync() {
+    let mut client = connect(orders_db()).await;
+    bind(&mut client, "", "missing", &[], 0).await;
+    execute(&mut client, "", 0).await;
+    send(&mut client, b'S', &[]).await;
+    let messages = read_until_ready(&mut client).await;
+    assert_eq!(tags(&messages), b"EZ".to_vec());
+    assert!(messages[0].1.windows(6).any(|w| w == b"C26000"));
+
+    // The connection carries on after Sync
+    let messages = query(&mut client, "SELECT id FROM orders WHERE id = 2").await;
+    assert_eq!(tags(&messages), b"TDCZ".to_vec());
+}
+
+/// Send `sql` and expect a CopyInResponse for `columns` text columns
+async fn copy_from_stdin(client: &mut DuplexStream, sql: &str, columns: i16) {
+    send(client, b'Q', &[sql.as_bytes(), b"\0"].concat()).await;
+    let (tag, body) = read_message(client).await;
+    assert_eq!(tag, b'G');
+    assert_eq!(body[..3], [[0].as_slice(), &columns.to_be_bytes()].concat());
+}
+
+#[tokio::test]
+async fn test_copy_from_stdin_loads_rows() {
+    let db = orders_db();
+    let mut client = connect(db.clone()).await;
+    copy_from_stdin(&mut client, "COPY orders FROM STDIN", 3).await;
+    // Records may be split across CopyData messages
+    send(&mut client, b'd', b"3\tbob\tt\n4\t\\N").await;
+    send(&mut client, b'd', b"\tf\n").await;
+    send(&mut client, b'c', &[]).await;
+    let messages = read_until_ready(&mut client).await;
+    assert_eq!(messages[0], (b'C', b"COPY 2\0".to_vec()));
+
+    let messages = query(&mut client, "SELECT * FROM orders WHERE id = 4").await;
+    assert_eq!(cells(&messages[1].1), vec![Some("4".into()), None, Some("f".into())]);
+
+    copy_from_stdin(&mut client, "COPY orders (id, customer) FROM STDIN WITH (FORMAT csv, HEADER)", 2).await;
+    send(&mut client, b'd', b"id,customer\n5,\"cy, \"\"jr\"\"\"\n6,\"\"\n").await;
+    send(&mut client, b'c', &[]).await;
+    assert_eq!(read_until_ready(&mut client).await[0], (b'C', b"COPY 2\0".to_vec()));
+    let messages = query(&mut client, "SELECT customer FROM orders WHERE id = 5").await;
+    assert_eq!(cells(&messages[1].1), vec![Some("cy, \"jr\"".into())]);
+    // A quoted empty string is not NULL
+    let messages = query(&mut client, "SELECT customer FROM orders WHERE id = 6").await;
+    assert_eq!(cells(&messages[1].1), vec![Some("".into())]);
+}
+
+#[tokio::test]
+async fn test_copy_from_stdin_errors() {
+    let mut client = connect(orders_db()).await;
+    copy_from_stdin(&mut client, "COPY orders FROM STDIN", 3).await;
+    send(&mut client, b'd', b"7\tdee\tmaybe\n").await;
+    send(&mut client, b'c', &[]).await;
+    let messages = read_until_ready(&mut client).await;
+    assert_eq!(tags(&messages), b"EZ".to_vec());
+    assert!(messages[0].1.windows(6).any(|w| w == b"C22P02"));
+    assert!(String::from_utf8_lossy(&messages[0].1).contains("(COPY orders, line 1)"));
+
+    // A primary key already stored, given twice, or NULL fails the COPY
+    for (data, code) in [(b"1\tann\tt\n".as_slice(), b"C23505"), (b"9\tfay\tt\n9\tgus\tf\n", b"C23505"), (b"\\N\thal\tt\n", b"C23502")] {
+        copy_from_stdin(&mut client, "COPY orders FROM STDIN", 3).await;
+        send(&mut client, b'd', data).await;
+        send(&mut client, b'c', &[]).await;
+        let messages = read_until_ready(&mut client).await;
+        assert!(messages[0].1.windows(6).any(|w| w == code));
+    }
+    let messages = query(&mut client, "SELECT customer FROM orders WHERE id = 1").await;
+    assert_eq!(cells(&messages[1].1), vec![Some("ann".into())]);
+    let messages = query(&mut client, "SELECT id FROM orders WHERE id = 9").await;
+    assert_eq!(tags(&messages), b"TCZ".to_vec());
+
+    // Rows of the batch under way when the client abandons a COPY are not kept
+    copy_from_stdin(&mut client, "COPY orders FROM STDIN", 3).await;
+    send(&mut client, b'd', b"8\teve\tt\n").await;
+    send(&mut client, b'f', b"cancelled\0").await;
+    let messages = read_until_ready(&mut client).await;
+    assert!(messages[0].1.windows(6).any(|w| w == b"C57014"));
+    let messages = query(&mut client, "SELECT id FROM orders WHERE id = 8").await;
+    assert_eq!(tags(&messages), b"TCZ".to_vec());
+}
+
+#[tokio::test]
+async fn test_copy_from_stdin_commits_a_batch_at_a_time() {
+    let mut client = connect(orders_db()).await;
+    copy_from_stdin(&mut client, "COPY orders FROM STDIN", 3).await;
+    let first = 100;
+    let rows: String = (first..first + BULK_BATCH_ROWS + 10).map(|id| format!("{}\tx\tt\n", id)).collect();
+    send(&mut client, b'd', rows.as_bytes()).await;
+    // Fails in the second batch
+    send(&mut client, b'd', b"1\tann\tt\n").await;
+    send(&mut client, b'c', &[]).await;
+    let messages = read_until_ready(&mut client).await;
+    assert!(messages[0].1.windows(6).any(|w| w == b"C23505"));
+
+    // The first batch was committed before the failure, the second was not
+    for (id, found) in [(first, true), (first + BULK_BATCH_ROWS - 1, true), (first + BULK_BATCH_ROWS, false)] {
+        let messages = query(&mut client, &format!("SELECT id FROM orders WHERE id = {}", id)).await;
+        assert_eq!(tags(&messages), if found { b"TDCZ".to_vec() } else { b"TCZ".to_vec() });
+    }
+}
+
+#[tokio::test]
+async fn test_copy_to_stdout() {
+    let mut client = connect(orders_db()).await;
+    let messages = query(&mut client, "COPY orders TO STDOUT").await;
+    assert_eq!(tags(&messages), b"HddcCZ".to_vec());
+    assert_eq!(messages[1].1, b"1\tann\tt\n".to_vec());
+    assert_eq!(messages[2].1, b"2\t\\N\tf\n".to_vec());
+    assert_eq!(messages[4].1, b"COPY 2\0".to_vec());
+
+    let messages = query(&mut client, "COPY (SELECT id, customer FROM orders WHERE id = 1) TO STDOUT WITH (FORMAT csv, HEADER)").await;
+    assert_eq!(tags(&messages), b"HddcCZ".to_vec());
+    assert_eq!(messages[1].1, b"id,customer\n".to_vec());
+    assert_eq!(messages[2].1, b"1,ann\n".to_vec());
+
+    let messages = query(&mut client, "COPY orders TO '/tmp/orders'").await;
+    assert!(messages[0].1.windows(6).any(|w| w == b"C0A000"));
+}